@@ -0,0 +1,58 @@
+//! Times writing a synthetic 10,000-file tree serially and with a worker pool.
+//!
+//! Run with `cargo bench --bench write_tree`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use cs_01::modules::files::{TreeNode, WriteOptions, write_files_from_tree};
+
+const DIRS: usize = 100;
+const FILES_PER_DIR: usize = 100;
+const RUNS: usize = 3;
+
+fn synthetic_tree() -> TreeNode {
+    let children = (0..DIRS)
+        .map(|d| {
+            let files = (0..FILES_PER_DIR)
+                .map(|f| (format!("file{}", f), TreeNode::file(vec![b'x'; 256 + f])))
+                .collect();
+            (format!("dir{}", d), TreeNode::dir(files))
+        })
+        .collect::<HashMap<_, _>>();
+    TreeNode::dir(children)
+}
+
+/// The best of `RUNS` writes into fresh directories.
+fn time_write(tree: &TreeNode, parallelism: usize) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let dir = tempfile::tempdir().unwrap();
+            let opts = WriteOptions {
+                parallelism,
+                ..Default::default()
+            };
+            let start = Instant::now();
+            write_files_from_tree(tree, &dir.path().join("tree"), &opts).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let tree = synthetic_tree();
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+    let serial = time_write(&tree, 1);
+    println!("write_tree/10k files, 1 thread:   {:>8.1?}", serial);
+    for parallelism in [2, 4, threads.max(8)] {
+        let parallel = time_write(&tree, parallelism);
+        println!(
+            "write_tree/10k files, {} threads: {:>8.1?} ({:.1}x)",
+            parallelism,
+            parallel,
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::modules::{
+    constants::is_repository_dir_name,
+    files::is_embedded_repository,
+    hash::ObjectKind,
+    ignore::IgnoreMatcher,
+    index::{Index, IndexEntry, IndexTime, index_mtime, working_content, working_mode},
+    lockfile::Lockfile,
+    odb::Odb,
+};
+use crate::repo::Repository;
+
+/// Which files `add` considers besides the ones it finds under its paths.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddOptions {
+    /// Stage ignored files that are named explicitly (`-f`).
+    pub force: bool,
+    /// Only touch tracked files, staging changes and deletions but adding nothing new (`-u`).
+    pub update: bool,
+    /// Stage deletions of tracked files as well as new and changed files (`-A`).
+    pub all: bool,
+}
+
+/// What `add` changed in the index, in path order.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AddOutcome {
+    /// Paths whose entry was added or changed.
+    pub added: Vec<String>,
+    /// Tracked paths whose deletion was staged.
+    pub removed: Vec<String>,
+    /// Directories holding a repository of their own, left out whole.
+    pub embedded: Vec<String>,
+}
+
+/// The files `add` found, checked before anything is staged.
+#[derive(Default)]
+struct Plan {
+    files: BTreeSet<String>,
+    deleted: BTreeSet<String>,
+    /// Explicitly named files held back by ignore rules.
+    ignored: Vec<String>,
+    embedded: BTreeSet<String>,
+}
+
+/// Stages the files under `paths` (relative to `cwd`): each is stored as a blob and its
+/// index entry added or updated, recursing into directories.
+///
+/// Untracked files matched by ignore rules (see `IgnoreMatcher`) are skipped, or refused
+/// when named directly unless `options.force` is set; the other paths are still staged.
+/// Deleted tracked files are only unstaged with `update` or `all`, which also default to
+/// the whole working tree.
+///
+/// Directories holding their own `.CS01` or `.git` are embedded repositories: nothing in
+/// them is staged, and they're listed in the outcome to warn about. Metadata directories,
+/// ours or git's, are never entered.
+///
+/// Note: A file whose stat data matches its entry isn't read again, and when nothing
+/// changed the index isn't rewritten at all.
+pub fn add(
+    repo: &Repository,
+    cwd: &Path,
+    paths: &[String],
+    options: &AddOptions,
+) -> Result<AddOutcome> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let mut specs = paths
+        .iter()
+        .map(|path| Ok((path.as_str(), repo.work_tree_path(cwd, Path::new(path))?)))
+        .collect::<Result<Vec<_>>>()?;
+    if specs.is_empty() {
+        if !options.update && !options.all {
+            bail!("Nothing specified, nothing added.\nhint: Maybe you wanted to say 'cs01 add .'?");
+        }
+        specs.push((".", String::new()));
+    }
+
+    let odb = repo.odb()?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let matcher = IgnoreMatcher::for_repo(repo)?;
+    let excluded = |relative: &str, is_dir: bool| matcher.is_ignored(relative, is_dir);
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    let index_mtime = index_mtime(&index_path);
+
+    // Note: Every path is checked before anything is staged, so a mistyped one stages nothing.
+    let mut plan = Plan::default();
+    for (given, relative) in &specs {
+        if relative
+            .split('/')
+            .any(|part| is_repository_dir_name(part.as_ref()))
+        {
+            bail!("'{}' is inside a repository's metadata directory", given);
+        }
+        if let Some(embedded) = relative
+            .match_indices('/')
+            .map(|(i, _)| &relative[..i])
+            .find(|dir| is_embedded_repository(&work_tree.join(dir)))
+        {
+            bail!(
+                "'{}' is inside the embedded repository '{}'",
+                given,
+                embedded
+            );
+        }
+        let full = work_tree.join(relative);
+        let metadata = match fs::symlink_metadata(&full) {
+            Ok(metadata) => Some(metadata),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+                ) =>
+            {
+                None
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", full)),
+        };
+
+        match metadata {
+            Some(metadata)
+                if metadata.is_dir() && !relative.is_empty() && is_embedded_repository(&full) =>
+            {
+                if options.update {
+                    continue;
+                }
+                plan.embedded.insert(relative.clone());
+            }
+            Some(metadata) if metadata.is_dir() => {
+                collect_dir(&full, relative, &index, options, &excluded, &mut plan)?;
+            }
+            Some(_) => {
+                if index.get(relative).is_none() {
+                    if options.update {
+                        continue;
+                    }
+                    if excluded(relative, false) && !options.force {
+                        plan.ignored.push(relative.clone());
+                        continue;
+                    }
+                }
+                plan.files.insert(relative.clone());
+            }
+            None if !is_tracked(&index, relative) => {
+                bail!("pathspec '{}' did not match any files", given);
+            }
+            None => {}
+        }
+
+        if options.update || options.all {
+            plan.deleted.extend(
+                index
+                    .entries()
+                    .iter()
+                    .map(|entry| &entry.path)
+                    .filter(|path| is_under(path, relative) && !is_file(&work_tree.join(path)))
+                    .cloned(),
+            );
+        }
+    }
+
+    let mut outcome = AddOutcome {
+        embedded: plan.embedded.into_iter().collect(),
+        ..AddOutcome::default()
+    };
+    for path in &plan.files {
+        if stage_file(&mut index, &odb, work_tree, path, filemode, index_mtime)? {
+            outcome.added.push(path.clone());
+        }
+    }
+    for path in &plan.deleted {
+        if index.remove(path) {
+            outcome.removed.push(path.clone());
+        }
+    }
+    if !outcome.added.is_empty() || !outcome.removed.is_empty() {
+        index.write_locked(lock)?;
+    }
+
+    if !plan.ignored.is_empty() {
+        bail!(
+            "The following paths are ignored by one of your ignore files:\n{}\nhint: Use -f if you really want to add them.",
+            plan.ignored.join("\n")
+        );
+    }
+    Ok(outcome)
+}
+
+/// Collects the files under the directory `dir` (at `relative` in the working tree).
+///
+/// Note: Ignored directories are only entered when they hold tracked files. Metadata
+/// directories and embedded repositories are never entered.
+fn collect_dir(
+    dir: &Path,
+    relative: &str,
+    index: &Index,
+    options: &AddOptions,
+    excluded: &dyn Fn(&str, bool) -> bool,
+    plan: &mut Plan,
+) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", dir))?;
+        let name = entry.file_name();
+        if is_repository_dir_name(&name) {
+            continue;
+        }
+        let Some(name) = name.to_str() else {
+            bail!("path {:?} is not valid UTF-8", entry.path());
+        };
+        let child = if relative.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", relative, name)
+        };
+
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("Failed to read {:?}", entry.path()))?;
+        if file_type.is_dir() && is_embedded_repository(&entry.path()) {
+            if !options.update && !excluded(&child, true) {
+                plan.embedded.insert(child);
+            }
+        } else if file_type.is_dir() {
+            if !excluded(&child, true) || is_tracked(index, &child) {
+                collect_dir(&entry.path(), &child, index, options, excluded, plan)?;
+            }
+        } else if index.get(&child).is_some() || !(options.update || excluded(&child, false)) {
+            plan.files.insert(child);
+        }
+    }
+    Ok(())
+}
+
+/// Stores the file at `path` as a blob and stages it; returns whether its entry changed.
+fn stage_file(
+    index: &mut Index,
+    odb: &Odb,
+    work_tree: &Path,
+    path: &str,
+    filemode: bool,
+    index_mtime: Option<IndexTime>,
+) -> Result<bool> {
+    let full = work_tree.join(path);
+    let metadata =
+        fs::symlink_metadata(&full).with_context(|| format!("Failed to read {:?}", full))?;
+    let existing = index.get(path);
+    let mode = working_mode(&metadata, filemode, existing);
+
+    if let (Some(entry), Some(index_mtime)) = (existing, index_mtime)
+        && entry.mode == mode
+        && entry.stat_matches(&metadata)
+        && !entry.is_racy(index_mtime)
+    {
+        return Ok(false);
+    }
+
+    let content = working_content(&full, mode)?;
+    let oid = odb.write(ObjectKind::Blob, &content)?;
+    let mut entry = IndexEntry::new(path, mode, oid);
+    entry.set_stat(&metadata);
+    if existing == Some(&entry) {
+        return Ok(false);
+    }
+    index.add_entry(entry)?;
+    Ok(true)
+}
+
+/// Whether `path` is tracked or is a directory holding tracked files.
+fn is_tracked(index: &Index, path: &str) -> bool {
+    index
+        .entries()
+        .iter()
+        .any(|entry| is_under(&entry.path, path))
+}
+
+/// Whether `path` is `dir` itself or inside it; everything is under the top (`""`).
+pub fn is_under(path: &str, dir: &str) -> bool {
+    dir.is_empty()
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Whether something other than a directory exists at `path`.
+fn is_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir())
+}
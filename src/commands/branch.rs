@@ -0,0 +1,173 @@
+use anyhow::{Context, Result, bail};
+
+use crate::commands::{
+    symbolic_ref::short_name,
+    update_ref::{delete_ref, update_ref},
+};
+use crate::modules::{
+    hash::{ObjectId, ObjectKind},
+    refs::{HeadState, list_refs, read_ref, resolve_ref, validate_ref_name},
+    revwalk::{ahead_behind, is_ancestor},
+};
+use crate::repo::Repository;
+
+/// A branch as `branch` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// The name without `refs/heads/`.
+    pub name: String,
+    pub id: ObjectId,
+    /// Whether HEAD is on it.
+    pub current: bool,
+}
+
+/// How a branch compares to the branch it tracks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracking {
+    /// The tracked branch as git shows it, e.g. `origin/main`.
+    pub upstream: String,
+    /// The commits only the branch has and those only the upstream has; `None` when
+    /// the upstream no longer exists.
+    pub counts: Option<(usize, usize)>,
+}
+
+/// What the branch `name` tracks, from `branch.<name>.remote` and `branch.<name>.merge`
+/// as `clone` sets them; `None` when it tracks nothing or is unborn.
+///
+/// Note: A remote's branches are looked up as `refs/remotes/<remote>/<branch>`, the
+/// only mapping `clone` sets up; the remote `.` stands for this repository.
+pub fn tracking(repo: &Repository, name: &str) -> Result<Option<Tracking>> {
+    let config = repo.config()?;
+    let (Some(remote), Some(merge)) = (
+        config.get_string(&format!("branch.{}.remote", name))?,
+        config.get_string(&format!("branch.{}.merge", name))?,
+    ) else {
+        return Ok(None);
+    };
+    let upstream = match remote {
+        "." => merge.to_string(),
+        _ => format!(
+            "refs/remotes/{}/{}",
+            remote,
+            merge.strip_prefix("refs/heads/").unwrap_or(merge)
+        ),
+    };
+    let Some(ours) = resolve_ref(repo.repo_dir(), &format!("refs/heads/{}", name))?.oid else {
+        return Ok(None);
+    };
+    let counts = match resolve_ref(repo.repo_dir(), &upstream)?.oid {
+        Some(theirs) => {
+            let ours = ObjectId::from_hex(&ours)?;
+            let theirs = ObjectId::from_hex(&theirs)?;
+            Some(ahead_behind(&repo.odb()?, &ours, &theirs)?)
+        }
+        None => None,
+    };
+    Ok(Some(Tracking {
+        upstream: short_name(&upstream).to_string(),
+        counts,
+    }))
+}
+
+/// Every branch, sorted by name. An unborn current branch isn't listed, as in git.
+pub fn list_branches(repo: &Repository) -> Result<Vec<Branch>> {
+    let current = match repo.head_ref()? {
+        Some(HeadState::Branch(name)) => Some(name),
+        _ => None,
+    };
+    list_refs(repo.repo_dir(), "refs/heads/")?
+        .into_iter()
+        .map(|(name, oid)| {
+            let name = name["refs/heads/".len()..].to_string();
+            let id = ObjectId::from_hex(&oid)
+                .with_context(|| format!("reference refs/heads/{} is broken", name))?;
+            Ok(Branch {
+                current: current.as_ref() == Some(&name),
+                name,
+                id,
+            })
+        })
+        .collect()
+}
+
+/// Creates the branch `name` at the commit `start` names (HEAD by default) and returns
+/// that commit. An existing branch is never overwritten.
+pub fn create_branch(repo: &Repository, name: &str, start: Option<&str>) -> Result<ObjectId> {
+    let full = check_new_branch(repo, name)?;
+    let start = start.unwrap_or("HEAD");
+    let id = match start {
+        "HEAD" => match repo.head_commit()? {
+            Some(id) => id,
+            None => bail!("not a valid object name: '{}'", head_name(repo)?),
+        },
+        _ => repo.resolve_revision(start)?,
+    };
+    let odb = repo.odb()?;
+    if !odb.contains(&id) || odb.read(&id)?.0 != ObjectKind::Commit {
+        bail!("not a valid branch point: '{}'", start);
+    }
+
+    let message = format!("branch: Created from {}", start);
+    update_ref(repo, &full, &id.to_hex(), Some(""), Some(&message))?;
+    Ok(id)
+}
+
+/// Deletes the branch `name` and returns the commit it pointed at.
+///
+/// Note: Unless `force` is set, the branch must be merged into HEAD, so no commit is
+/// lost with it. The current branch can't be deleted either way.
+pub fn delete_branch(repo: &Repository, name: &str, force: bool) -> Result<ObjectId> {
+    let full = branch_ref(name)?;
+    let Some(branch) = list_branches(repo)?
+        .into_iter()
+        .find(|branch| branch.name == name)
+    else {
+        bail!("branch '{}' not found", name);
+    };
+    if branch.current {
+        bail!("cannot delete branch '{}' used by the current HEAD", name);
+    }
+    if !force {
+        let merged = match repo.head_commit()? {
+            Some(head) => is_ancestor(&repo.odb()?, &branch.id, &head)?,
+            None => false,
+        };
+        if !merged {
+            bail!(
+                "the branch '{}' is not fully merged\nhint: If you are sure you want to delete it, run 'cs01 branch -D {}'",
+                name,
+                name
+            );
+        }
+    }
+
+    delete_ref(repo, &full, Some(&branch.id.to_hex()))?;
+    Ok(branch.id)
+}
+
+/// `refs/heads/<name>`, once `name` is checked to be a usable name no branch has yet.
+pub fn check_new_branch(repo: &Repository, name: &str) -> Result<String> {
+    let full = branch_ref(name)?;
+    if read_ref(repo.repo_dir(), &full)?.is_some() {
+        bail!("a branch named '{}' already exists", name);
+    }
+    Ok(full)
+}
+
+/// `refs/heads/<name>`, once `name` is checked to be a usable branch name.
+fn branch_ref(name: &str) -> Result<String> {
+    if name == "HEAD" || name.starts_with('-') {
+        bail!("'{}' is not a valid branch name", name);
+    }
+    let full = format!("refs/heads/{}", name);
+    validate_ref_name(&full).with_context(|| format!("'{}' is not a valid branch name", name))?;
+    Ok(full)
+}
+
+/// The branch HEAD is on, for messages about an unborn HEAD.
+fn head_name(repo: &Repository) -> Result<String> {
+    Ok(match repo.head_ref()? {
+        Some(HeadState::Branch(name) | HeadState::OtherRef(name)) => name,
+        _ => "HEAD".to_string(),
+    })
+}
@@ -0,0 +1,29 @@
+use anyhow::{Result, bail};
+
+use crate::modules::{
+    hash::{ObjectId, ObjectKind},
+    objects::tree::Tree,
+    odb::Odb,
+};
+
+/// Reads the object `id`, failing with its name when it isn't stored.
+pub fn read(odb: &Odb, id: &ObjectId) -> Result<(ObjectKind, Vec<u8>)> {
+    if !odb.contains(id) {
+        bail!("Not a valid object name {}", id);
+    }
+    odb.read(id)
+}
+
+/// The bytes `cat-file -p` prints for an object: blobs, commits and tags as stored,
+/// trees one entry per line.
+pub fn pretty(kind: ObjectKind, data: Vec<u8>) -> Result<Vec<u8>> {
+    match kind {
+        ObjectKind::Tree => Ok(Tree::parse(&data)?
+            .entries()
+            .iter()
+            .map(|entry| format!("{}\n", entry))
+            .collect::<String>()
+            .into_bytes()),
+        ObjectKind::Blob | ObjectKind::Commit | ObjectKind::Tag => Ok(data),
+    }
+}
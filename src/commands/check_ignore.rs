@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::modules::{
+    ignore::{IgnoreMatcher, Pattern},
+    index::Index,
+};
+use crate::repo::Repository;
+
+/// Why one path given to `check_ignore` is or isn't ignored.
+#[derive(Debug, Clone)]
+pub struct IgnoreCheck {
+    /// The path as given.
+    pub path: String,
+    /// The pattern that decides it, which may be a negation; `None` when no pattern
+    /// matches or the path is tracked.
+    pub pattern: Option<Pattern>,
+    /// Where `pattern` was read from, relative to the top of the working tree when
+    /// it's inside it.
+    pub source: String,
+}
+
+impl IgnoreCheck {
+    /// Whether the path is ignored: a pattern matched and it isn't a negation.
+    pub fn is_ignored(&self) -> bool {
+        self.pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Looks up the ignore rule that applies to each of `paths` (relative to `cwd`), in the
+/// order given.
+///
+/// Note: As in git, tracked files are never reported, since ignore rules only keep
+/// untracked files out. A path names a directory if it ends with `/` or is one on disk.
+pub fn check_ignore(repo: &Repository, cwd: &Path, paths: &[String]) -> Result<Vec<IgnoreCheck>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let matcher = IgnoreMatcher::for_repo(repo)?;
+    let index = Index::read(&repo.index_path())?;
+
+    paths
+        .iter()
+        .map(|given| {
+            let relative = repo.work_tree_path(cwd, Path::new(given))?;
+            let is_dir = given.ends_with('/')
+                || fs::symlink_metadata(work_tree.join(&relative)).is_ok_and(|m| m.is_dir());
+            let pattern = match index.get(&relative) {
+                _ if relative.is_empty() => None,
+                Some(_) => None,
+                None => matcher.matching_pattern(&relative, is_dir),
+            };
+            let source = pattern.as_ref().map_or_else(String::new, |pattern| {
+                let source = pattern
+                    .source
+                    .strip_prefix(work_tree)
+                    .unwrap_or(&pattern.source);
+                source.to_string_lossy().into_owned()
+            });
+            Ok(IgnoreCheck {
+                path: given.clone(),
+                pattern,
+                source,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commands::{config, update_ref::logged_names, update_ref::reflog_entry};
+use crate::modules::{
+    checkout::{Files, check_out},
+    config::{ConfigKey, Identity},
+    constants::is_metadata_dir_name,
+    files::{find_metadata_dir, looks_like_repo_dir},
+    hash::{ObjectFormat, ObjectId},
+    lockfile::Lockfile,
+    objects::{commit::Commit, tree::Tree},
+    refs::{
+        self, ExpectedOld, HeadState, append_reflog, head_state, list_refs, write_detached_ref,
+        write_symbolic_ref,
+    },
+    vfs::RealFs,
+};
+use crate::repo::{InitOptions, Repository};
+
+/// The name the repository cloned from is known by in the clone.
+pub const ORIGIN: &str = "origin";
+
+/// How `clone` sets up the new repository.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CloneOptions {
+    /// Create a bare repository, with the source's branches as its own (`--bare`).
+    pub bare: bool,
+    /// Copy the object files rather than hardlinking them (`--no-hardlinks`).
+    pub no_hardlinks: bool,
+}
+
+/// What `clone` created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloneOutcome {
+    pub repo: Repository,
+    /// The branch HEAD points at; `None` when the source's HEAD was detached.
+    pub branch: Option<String>,
+    /// The commit checked out; `None` when the source had no commit on its HEAD.
+    pub head: Option<ObjectId>,
+    /// Whether the source had no references at all.
+    pub empty: bool,
+}
+
+/// Clones the repository at `source` (a working tree or a bare repository) into
+/// `destination`, which must not exist yet or be an empty directory.
+///
+/// The object store is hardlinked, or copied when linking isn't possible or
+/// `options.no_hardlinks` is set. The source's branches become `refs/remotes/origin/*`
+/// (its own branches for a bare clone) and its tags are copied as they are; `remote.origin`
+/// is set up in the config, and HEAD points at the branch the source's HEAD is on, which
+/// is created and checked out. An empty source leaves that branch unborn.
+///
+/// Note: A destination created here is removed again if the clone fails.
+pub fn clone(source: &Path, destination: &Path, options: CloneOptions) -> Result<CloneOutcome> {
+    let source = open_source(source)?;
+    let existed = destination.exists();
+    if existed
+        && fs::read_dir(destination)
+            .with_context(|| format!("Failed to read {:?}", destination))?
+            .next()
+            .is_some()
+    {
+        bail!(
+            "destination path '{}' already exists and is not an empty directory",
+            destination.display()
+        );
+    }
+
+    let result = clone_into(&source, destination, options);
+    if result.is_err() {
+        remove_partial_clone(destination, existed);
+    }
+    result
+}
+
+/// The directory `clone` creates when none is given: the last component of the source,
+/// without its metadata directory, e.g. `proj` for `../proj` or `../proj/.CS01`.
+pub fn default_destination(source: &Path) -> Result<PathBuf> {
+    let source = std::path::absolute(source)
+        .with_context(|| format!("Failed to resolve {}", source.display()))?;
+    let mut components: Vec<_> = source
+        .components()
+        .filter(|component| component.as_os_str() != ".")
+        .collect();
+    if components
+        .last()
+        .is_some_and(|last| is_metadata_dir_name(last.as_os_str()))
+    {
+        components.pop();
+    }
+    match components.last().map(|last| last.as_os_str()) {
+        Some(name) if name != ".." && name != "/" => Ok(PathBuf::from(name)),
+        _ => bail!(
+            "cannot guess a directory name from '{}'; please specify a directory",
+            source.display()
+        ),
+    }
+}
+
+/// The repository at `path`: its metadata directory if it's a working tree, or `path`
+/// itself if it's bare.
+fn open_source(path: &Path) -> Result<Repository> {
+    if let Some(repo_dir) = find_metadata_dir(&RealFs, path)
+        && looks_like_repo_dir(&repo_dir)
+    {
+        return Repository::open(&repo_dir);
+    }
+    if looks_like_repo_dir(path) {
+        return Repository::open(path);
+    }
+    bail!("repository '{}' does not exist", path.display())
+}
+
+fn clone_into(
+    source: &Repository,
+    destination: &Path,
+    options: CloneOptions,
+) -> Result<CloneOutcome> {
+    let source_config = source.config()?;
+    let object_format = source_config
+        .get_string("extensions.objectformat")?
+        .map(ObjectFormat::parse)
+        .transpose()?;
+    let head = head_state(source.repo_dir())?;
+    let branch = match &head {
+        Some(HeadState::Branch(name)) => Some(name.clone()),
+        _ => None,
+    };
+
+    let repo = Repository::init(
+        destination,
+        InitOptions {
+            bare: options.bare,
+            initial_branch: branch.clone(),
+            object_format,
+            ..InitOptions::default()
+        },
+    )?;
+    copy_objects(
+        &source.repo_dir().join("objects"),
+        &repo.repo_dir().join("objects"),
+        !options.no_hardlinks,
+    )?;
+
+    let url = std::path::absolute(source.work_tree().unwrap_or(source.repo_dir()))
+        .with_context(|| format!("Failed to resolve {}", source.repo_dir().display()))?;
+    let url = url.to_string_lossy();
+    let config_path = repo.config_path();
+    let set = |key: &str, value: &str| config::set(&config_path, &ConfigKey::parse(key)?, value);
+    set("remote.origin.url", &url)?;
+    if !options.bare {
+        set(
+            "remote.origin.fetch",
+            &format!("+refs/heads/*:refs/remotes/{}/*", ORIGIN),
+        )?;
+    }
+
+    // Note: A clone shouldn't fail for lack of an identity, which git would make up;
+    // without one the refs are written but not logged.
+    let identity = repo.config()?.committer().ok();
+    let message = format!("clone: from {}", url);
+    let branches = list_refs(source.repo_dir(), "refs/heads/")?;
+    let tags = list_refs(source.repo_dir(), "refs/tags/")?;
+    let empty = branches.is_empty() && tags.is_empty();
+    for (name, oid) in &branches {
+        let short = name.trim_start_matches("refs/heads/");
+        let copy = if options.bare {
+            name.clone()
+        } else {
+            format!("refs/remotes/{}/{}", ORIGIN, short)
+        };
+        create_ref(&repo, &copy, oid, identity.as_ref(), &message)?;
+    }
+    for (name, oid) in &tags {
+        create_ref(&repo, name, oid, identity.as_ref(), &message)?;
+    }
+
+    // Note: The local branch and HEAD follow the source's HEAD; a branch it names that
+    // has no commit yet stays unborn here too.
+    let head_id = match &head {
+        Some(HeadState::Branch(name)) => {
+            let full = format!("refs/heads/{}", name);
+            let found = branches.iter().find(|(branch, _)| *branch == full);
+            if let Some((_, oid)) = found
+                && !options.bare
+            {
+                write_symbolic_ref(
+                    repo.repo_dir(),
+                    &format!("refs/remotes/{}/HEAD", ORIGIN),
+                    &format!("refs/remotes/{}/{}", ORIGIN, name),
+                )?;
+                create_ref(&repo, &full, oid, identity.as_ref(), &message)?;
+                set(&format!("branch.{}.remote", name), ORIGIN)?;
+                set(&format!("branch.{}.merge", name), &full)?;
+            }
+            found.map(|(_, oid)| ObjectId::from_hex(oid)).transpose()?
+        }
+        Some(HeadState::Detached(oid)) => {
+            let oid = ObjectId::from_hex(oid)?;
+            write_detached_ref(repo.repo_dir(), "HEAD", &oid)?;
+            Some(oid)
+        }
+        Some(HeadState::OtherRef(_)) | None => None,
+    };
+
+    if let Some(id) = &head_id
+        && !options.bare
+    {
+        let odb = repo.odb()?;
+        let files = Tree::flatten(&odb, &Commit::read(&odb, id)?.tree)?;
+        let index_path = repo.index_path();
+        let lock = Lockfile::acquire(&index_path)?;
+        let mut index = repo.read_index()?;
+        check_out(&repo, &mut index, &Files::new(), &files)?;
+        index.write_locked(lock)?;
+    }
+
+    Ok(CloneOutcome {
+        repo,
+        branch,
+        head: head_id,
+        empty,
+    })
+}
+
+/// Removes what a failed clone left in `destination`, best effort: the directory itself
+/// if the clone created it, only its contents otherwise.
+fn remove_partial_clone(destination: &Path, existed: bool) {
+    if !existed {
+        let _ = fs::remove_dir_all(destination);
+        return;
+    }
+    for entry in fs::read_dir(destination).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let _ = match entry.file_type() {
+            Ok(kind) if kind.is_dir() => fs::remove_dir_all(&path),
+            _ => fs::remove_file(&path),
+        };
+    }
+}
+
+/// Creates the reference `name` at `oid` in `repo`, logged as `message` when there's an
+/// identity to log it with.
+fn create_ref(
+    repo: &Repository,
+    name: &str,
+    oid: &str,
+    identity: Option<&Identity>,
+    message: &str,
+) -> Result<()> {
+    let id = ObjectId::from_hex(oid).with_context(|| format!("{} is broken", name))?;
+    refs::update_ref(repo.repo_dir(), name, &id, &ExpectedOld::Missing)?;
+    if let Some(identity) = identity {
+        let entry = reflog_entry(identity, None, Some(oid), Some(message));
+        for logged in logged_names(repo, name)? {
+            append_reflog(repo.repo_dir(), &logged, &entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Puts every file under `from` at the same place under `to`, hardlinking it when `link`
+/// is set and that works (the same filesystem), and copying it otherwise. Files already
+/// in `to` are kept.
+///
+/// Note: Object files are never written to once stored, so the two repositories can
+/// safely share them.
+fn copy_objects(from: &Path, to: &Path, link: bool) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create dir {:?}", to))?;
+    for entry in fs::read_dir(from).with_context(|| format!("Failed to read {:?}", from))? {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", from))?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("Failed to read {:?}", source))?;
+        if file_type.is_dir() {
+            copy_objects(&source, &target, link)?;
+        } else if target.exists() || (link && fs::hard_link(&source, &target).is_ok()) {
+            continue;
+        } else {
+            fs::copy(&source, &target)
+                .with_context(|| format!("Failed to copy {:?} to {:?}", source, target))?;
+        }
+    }
+    Ok(())
+}
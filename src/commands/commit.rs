@@ -0,0 +1,248 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+
+use crate::commands::{
+    merge::{MERGE_MSG, clear_merge_state, merge_head},
+    status::{self, Status},
+    update_ref::update_ref,
+};
+use crate::modules::{
+    editor,
+    hash::{ObjectId, ObjectKind},
+    hooks::run_hook,
+    index::Index,
+    lockfile::Lockfile,
+    objects::commit::Commit,
+    refs::HeadState,
+};
+use crate::repo::Repository;
+
+/// The file the message is edited in, kept in the metadata directory as in git.
+const COMMIT_EDITMSG: &str = "COMMIT_EDITMSG";
+
+/// How `commit` treats an unchanged tree or an empty message, and whether it runs hooks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommitOptions {
+    /// Record a commit even when its tree is the same as its parent's (`--allow-empty`).
+    pub allow_empty: bool,
+    /// Record a commit whose message is empty instead of aborting (`--allow-empty-message`).
+    pub allow_empty_message: bool,
+    /// Skip the `pre-commit` and `commit-msg` hooks (`--no-verify`).
+    pub no_verify: bool,
+}
+
+/// The commit `commit` recorded and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitOutcome {
+    pub id: ObjectId,
+    /// What HEAD pointed at, and so which branch moved (if any).
+    pub head: Option<HeadState>,
+    /// Whether this is the first commit on its branch, with no parent.
+    pub root: bool,
+    /// The first line of the message.
+    pub summary: String,
+}
+
+/// Records the index of `repo` as a commit on top of HEAD and moves HEAD's branch to it.
+///
+/// The parent is the commit HEAD resolves to; on an unborn branch the commit is a root
+/// commit and the branch is created. The message is cleaned up as git does: trailing
+/// whitespace and surplus blank lines go, and without `message` the editor is opened on
+/// `COMMIT_EDITMSG`, whose `#` lines are dropped. An empty message aborts the commit
+/// unless `options.allow_empty_message` is set.
+///
+/// Unless `options.no_verify` is set, the `pre-commit` hook runs first and the
+/// `commit-msg` hook then gets the path of `COMMIT_EDITMSG`, which it may rewrite; either
+/// failing aborts the commit.
+///
+/// While a conflicted merge is waiting (`MERGE_HEAD` exists), the commit concludes it:
+/// the merged commit becomes a second parent, the commit is recorded even if the tree
+/// didn't change, and the editor starts from `MERGE_MSG`.
+///
+/// Note: The branch is moved only if it still holds the parent, and the move goes into
+/// the reflogs like any `update-ref`.
+pub fn commit(
+    repo: &Repository,
+    message: Option<&str>,
+    options: &CommitOptions,
+) -> Result<CommitOutcome> {
+    if repo.work_tree().is_none() {
+        bail!("this operation must be run in a work tree");
+    }
+    if !options.no_verify {
+        run_hook(repo, "pre-commit", &[], None)?;
+    }
+    let odb = repo.odb()?;
+    let index_path = repo.index_path();
+    // Note: The index stays locked until the commit is recorded, so nothing is staged
+    // halfway through.
+    let _lock = Lockfile::acquire(&index_path)?;
+    let index = Index::read(&index_path)?;
+    let tree = index.write_tree(&odb)?;
+
+    let parent = repo.head_commit()?;
+    let merged = merge_head(repo)?;
+    let parent_tree = match &parent {
+        Some(id) => Some(Commit::read(&odb, id)?.tree),
+        None => None,
+    };
+    let unchanged = match parent_tree {
+        Some(parent_tree) => parent_tree == tree,
+        None => index.is_empty(),
+    };
+    if unchanged && merged.is_none() && !options.allow_empty {
+        bail!("nothing to commit (use \"cs01 add\" to stage changes, or --allow-empty)");
+    }
+
+    let message_path = repo.repo_dir().join(COMMIT_EDITMSG);
+    let strip_comments = match message {
+        Some(message) => {
+            fs::write(&message_path, message)
+                .with_context(|| format!("Failed to write {:?}", message_path))?;
+            false
+        }
+        None => {
+            edit_message(repo, &message_path)?;
+            true
+        }
+    };
+    if !options.no_verify {
+        run_hook(repo, "commit-msg", &[&message_path.to_string_lossy()], None)?;
+    }
+    let text = fs::read_to_string(&message_path)
+        .with_context(|| format!("Failed to read {:?}", message_path))?;
+    let message = clean_message(&text, strip_comments);
+    if message.is_empty() && !options.allow_empty_message {
+        bail!("Aborting commit due to empty commit message.");
+    }
+
+    let config = repo.config()?;
+    let commit = Commit {
+        tree,
+        parents: parent.into_iter().chain(merged).collect(),
+        author: config.author()?,
+        committer: config.committer()?,
+        extra_headers: Vec::new(),
+        message,
+    };
+    let id = odb.write(ObjectKind::Commit, &commit.serialize())?;
+
+    let reason = match (parent, merged) {
+        (_, Some(_)) => format!("commit (merge): {}", commit.summary()),
+        (Some(_), None) => format!("commit: {}", commit.summary()),
+        (None, None) => format!("commit (initial): {}", commit.summary()),
+    };
+    let old = parent.map(|id| id.to_hex()).unwrap_or_default();
+    update_ref(repo, "HEAD", &id.to_hex(), Some(&old), Some(&reason))?;
+    clear_merge_state(repo)?;
+
+    Ok(CommitOutcome {
+        id,
+        head: repo.head_ref()?,
+        root: parent.is_none(),
+        summary: commit.summary().to_string(),
+    })
+}
+
+/// Opens the editor on `path`, filled with a commented summary of what will be
+/// committed (after the prepared `MERGE_MSG` of a merge), leaving what the user wrote
+/// there.
+fn edit_message(repo: &Repository, path: &Path) -> Result<()> {
+    let mut template = message_template(&status::status(repo)?);
+    let merge_message = repo.repo_dir().join(MERGE_MSG);
+    if merge_message.is_file() {
+        let prepared = fs::read_to_string(&merge_message)
+            .with_context(|| format!("Failed to read {:?}", merge_message))?;
+        template = prepared + &template;
+    }
+    fs::write(path, template).with_context(|| format!("Failed to write {:?}", path))?;
+    editor::launch(path)
+}
+
+/// The text the editor starts with: an empty line for the message, then the status as
+/// `#` comments.
+fn message_template(report: &Status) -> String {
+    let mut lines = vec![
+        String::new(),
+        "# Please enter the commit message for your changes. Lines starting".to_string(),
+        "# with '#' will be ignored, and an empty message aborts the commit.".to_string(),
+        "#".to_string(),
+    ];
+    match &report.head {
+        Some(HeadState::Branch(name)) => lines.push(format!("# On branch {}", name)),
+        Some(HeadState::OtherRef(name)) => lines.push(format!("# On {}", name)),
+        _ => lines.push("# Not currently on any branch.".to_string()),
+    }
+    if report.head_commit.is_none() {
+        lines.extend(["#".to_string(), "# Initial commit".to_string()]);
+    }
+
+    let sections = [
+        ("Changes to be committed:", &report.staged),
+        ("Changes not staged for commit:", &report.unstaged),
+    ];
+    for (title, changes) in sections {
+        if changes.is_empty() {
+            continue;
+        }
+        lines.extend(["#".to_string(), format!("# {}", title)]);
+        for (path, change) in changes {
+            lines.push(format!("#\t{:<12}{}", format!("{}:", change.label()), path));
+        }
+    }
+    if !report.untracked.is_empty() {
+        lines.extend(["#".to_string(), "# Untracked files:".to_string()]);
+        lines.extend(report.untracked.iter().map(|path| format!("#\t{}", path)));
+    }
+    lines.push("#".to_string());
+    lines.join("\n") + "\n"
+}
+
+/// `text` as git records a message: without trailing whitespace, leading and trailing
+/// blank lines or runs of blank lines, and with `#` lines dropped if `strip_comments`.
+/// Empty when nothing is left.
+pub fn clean_message(text: &str, strip_comments: bool) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    if lines.last() == Some(&"") {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_message() {
+        let cases = [
+            ("Subject", false, "Subject\n"),
+            (
+                "\n\n  Subject  \n\n\n\nBody\t\n\n",
+                false,
+                "  Subject\n\nBody\n",
+            ),
+            ("Subject\n# comment\n\n#\nBody\n", true, "Subject\n\nBody\n"),
+            ("Subject\n# kept\n", false, "Subject\n# kept\n"),
+            ("\n# only comments\n#\n", true, ""),
+            ("  \n\t\n", false, ""),
+        ];
+        for (text, strip_comments, expected) in cases {
+            assert_eq!(clean_message(text, strip_comments), expected, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{Result, bail};
+
+use crate::modules::{
+    hash::{ObjectId, ObjectKind},
+    objects::commit::Commit,
+    odb::Odb,
+    output::Output,
+};
+use crate::repo::Repository;
+
+/// Creates a commit of `tree` following `parents` and returns its id; no ref is touched.
+///
+/// Note: Every object is checked before anything is written, so a typo in an id
+/// never leaves a dangling commit behind.
+pub fn commit_tree(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    message: String,
+) -> Result<ObjectId> {
+    let odb = repo.odb()?;
+    let tree = expect_object(repo, &odb, tree, ObjectKind::Tree)?;
+    let mut unique = Vec::new();
+    for parent in parents {
+        let id = expect_object(repo, &odb, parent, ObjectKind::Commit)?;
+        // Like git, a parent named twice is recorded once.
+        if unique.contains(&id) {
+            Output::default().warn(&format!("warning: duplicate parent {} ignored", id));
+        } else {
+            unique.push(id);
+        }
+    }
+
+    let config = repo.config()?;
+    let commit = Commit {
+        tree,
+        parents: unique,
+        author: config.author()?,
+        committer: config.committer()?,
+        extra_headers: Vec::new(),
+        message,
+    };
+    odb.write(ObjectKind::Commit, &commit.serialize())
+}
+
+/// The message for `-m` options: like git, each is its own paragraph, ending in a newline.
+pub fn join_messages(messages: &[String]) -> String {
+    messages
+        .iter()
+        .map(|message| format!("{}\n", message.trim_end_matches('\n')))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn expect_object(
+    repo: &Repository,
+    odb: &Odb,
+    name: &str,
+    expected: ObjectKind,
+) -> Result<ObjectId> {
+    let id = repo.resolve_revision(name)?;
+    if !odb.contains(&id) {
+        bail!("Not a valid object name {}", name);
+    }
+    let (kind, _) = odb.read(&id)?;
+    if kind != expected {
+        bail!("{} is a {}, not a {}", name, kind, expected);
+    }
+    Ok(id)
+}
@@ -0,0 +1,175 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+
+use crate::modules::{
+    config::{
+        ConfigDocument, ConfigEntry, ConfigKey, ConfigScope, global_config_write_path,
+        load_cascaded_entries, parse_section_name, system_config_path,
+    },
+    editor,
+    lockfile::Lockfile,
+};
+use crate::repo::{NotARepository, Repository};
+
+/// Finds the repository containing the current directory, if any.
+fn current_repo() -> Result<Option<Repository>> {
+    Repository::discover(Path::new("."))
+}
+
+/// Finds the config file of the repository containing the current directory.
+pub fn repo_config_path() -> Result<PathBuf> {
+    Ok(Repository::current()?.config_path())
+}
+
+/// The file a write goes to: the repository's config unless a scope says otherwise.
+pub fn write_file(scope: Option<ConfigScope>) -> Result<PathBuf> {
+    match scope {
+        None | Some(ConfigScope::Local) => repo_config_path(),
+        Some(ConfigScope::Global) => global_config_write_path(),
+        Some(ConfigScope::System) => Ok(system_config_path()),
+        Some(ConfigScope::Command) => bail!("environment overrides cannot be written"),
+    }
+}
+
+fn load(path: &Path) -> Result<ConfigDocument> {
+    if !path.is_file() {
+        return Ok(ConfigDocument::default());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    ConfigDocument::parse(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Applies `change` to the document at `path` and writes it back.
+///
+/// Note: The file is locked before it is read, so two concurrent edits can't
+/// interleave their read-modify-write cycles and lose one another's change.
+fn edit(path: &Path, change: impl FnOnce(&mut ConfigDocument) -> Result<()>) -> Result<()> {
+    create_parent_dir(path)?;
+    let mut lock = Lockfile::acquire(path)?;
+    let mut document = load(path)?;
+    change(&mut document)?;
+    lock.write(document.to_string().as_bytes())?;
+    lock.commit()
+}
+
+/// Reads every setting a `config` read sees, tagged with its scope, in precedence order.
+///
+/// Without a scope this is the whole cascade, including the `CS01_CONFIG_COUNT` overrides;
+/// the repository's config is included only when run inside one, so `cs01 config --list`
+/// also works elsewhere. `includeIf` conditions always match against that repository.
+pub fn read_entries(scope: Option<ConfigScope>) -> Result<Vec<(ConfigScope, ConfigEntry)>> {
+    let repo = current_repo()?;
+    if scope == Some(ConfigScope::Local) && repo.is_none() {
+        return Err(NotARepository::in_current_dir().into());
+    }
+
+    let mut entries = load_cascaded_entries(repo.as_ref().map(Repository::repo_dir))?;
+    entries.retain(|(entry_scope, _)| scope.is_none_or(|scope| scope == *entry_scope));
+    Ok(entries)
+}
+
+/// Returns the entry holding the value of `key`, or `None` when it isn't set.
+///
+/// Note: For a multi-valued key this is the last value, like `git config <key>`,
+/// which also makes the highest-precedence scope win.
+pub fn get<'a>(
+    entries: &'a [(ConfigScope, ConfigEntry)],
+    key: &ConfigKey,
+) -> Option<&'a (ConfigScope, ConfigEntry)> {
+    get_all(entries, key).pop()
+}
+
+/// Returns every entry of `key` in precedence order; empty when it isn't set.
+pub fn get_all<'a>(
+    entries: &'a [(ConfigScope, ConfigEntry)],
+    key: &ConfigKey,
+) -> Vec<&'a (ConfigScope, ConfigEntry)> {
+    entries
+        .iter()
+        .filter(|(_, entry)| {
+            entry.section == key.section
+                && entry.subsection == key.subsection
+                && entry.name == key.name
+        })
+        .collect()
+}
+
+/// Note: The global and system files are created on first write, like git does.
+fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create dir {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// Opens `path` in the user's editor, for `cs01 config --edit`.
+///
+/// Note: The editor works on the locked copy (`<path>.lock`), which replaces `path`
+/// only if the editor exits successfully and the result still parses. Otherwise
+/// `path` is left exactly as it was.
+pub fn edit_in_editor(path: &Path) -> Result<()> {
+    create_parent_dir(path)?;
+    let mut lock = Lockfile::acquire(path)?;
+    if path.is_file() {
+        let original =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        lock.write(&original)?;
+    }
+
+    editor::launch(lock.lock_path())?;
+
+    let edited = std::fs::read_to_string(lock.lock_path())
+        .with_context(|| format!("Failed to read {}", lock.lock_path().display()))?;
+    if let Err(e) = ConfigDocument::parse(&edited) {
+        bail!(
+            "{}: {}; the config file was left unchanged",
+            path.display(),
+            e
+        );
+    }
+    lock.commit()
+}
+
+/// Sets `key` to `value` in `path`, creating the file and section if needed.
+///
+/// Note: Edits go through `ConfigDocument`, so comments and every other line survive.
+pub fn set(path: &Path, key: &ConfigKey, value: &str) -> Result<()> {
+    edit(path, |document| {
+        document.set(&key.section, &key.subsection, &key.name, value)
+    })
+}
+
+/// Adds `value` to `key` without replacing existing values, making it multi-valued.
+pub fn add(path: &Path, key: &ConfigKey, value: &str) -> Result<()> {
+    edit(path, |document| {
+        document.add(&key.section, &key.subsection, &key.name, value)
+    })
+}
+
+/// Removes `key`, dropping its section header when nothing else remains in it.
+pub fn unset(path: &Path, key: &ConfigKey) -> Result<()> {
+    edit(path, |document| {
+        document.unset(&key.section, &key.subsection, &key.name)
+    })
+}
+
+/// Renames the section `old` (e.g. `remote.origin`) to `new` in `path`, keys and comments included.
+pub fn rename_section(path: &Path, old: &str, new: &str) -> Result<()> {
+    let (section, subsection) = parse_section_name(old)?;
+    let (new_section, new_subsection) = parse_section_name(new)?;
+    edit(path, |document| {
+        document.rename_section((&section, &subsection), (&new_section, &new_subsection))
+    })
+}
+
+/// Removes the section `name` and every line under it from `path`.
+pub fn remove_section(path: &Path, name: &str) -> Result<()> {
+    let (section, subsection) = parse_section_name(name)?;
+    edit(path, |document| {
+        document.remove_section(&section, &subsection)
+    })
+}
@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::modules::{
+    checkout::Files,
+    diff::{FilePatch, FileVersion, file_patches, max_changed_lines},
+    hash::{ObjectId, ObjectKind, hash_object},
+    index::{Index, IndexEntry, index_mtime, is_unmodified, working_content, working_mode},
+    objects::{
+        commit::Commit,
+        tree::{EntryMode, Tree},
+    },
+    odb::Odb,
+};
+use crate::repo::Repository;
+
+/// What `diff` compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison<'a> {
+    /// The index against the working tree.
+    WorkTree,
+    /// A commit (HEAD when `None`) against the index (`--staged`).
+    Staged(Option<&'a str>),
+    /// A commit against the working tree.
+    WorkTreeAgainst(&'a str),
+    /// One commit against another.
+    Commits(&'a str, &'a str),
+}
+
+/// The patches for every path that differs in `comparison`, sorted by path, with
+/// `context` unchanged lines around each change.
+///
+/// Note: Only tracked files are compared with the working tree, and files whose stat
+/// data matches the index aren't read. Unmerged paths are left out. A file with more
+/// changed lines than `diff.maxChangedLines` is only said to be too large to diff.
+pub fn diff(repo: &Repository, comparison: Comparison, context: usize) -> Result<Vec<FilePatch>> {
+    let odb = repo.odb()?;
+    let max_changes = max_changed_lines(&repo.config()?, "diff.maxChangedLines")?;
+    let commit_files = |revision: Option<&str>| -> Result<Files> {
+        let id = match revision {
+            Some(revision) => Some(repo.resolve_revision(revision)?),
+            None => repo.head_commit()?,
+        };
+        match id {
+            Some(id) => Tree::flatten(&odb, &Commit::read(&odb, &id)?.tree),
+            None => Ok(Files::new()),
+        }
+    };
+    let index_files = || -> Result<Files> {
+        let index = Index::read(&repo.index_path())?;
+        Ok(index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage() == 0)
+            .map(|entry| (entry.path.clone(), (entry.mode, entry.oid)))
+            .collect())
+    };
+
+    let (old, new) = match comparison {
+        Comparison::WorkTree => return work_tree_patches(repo, &odb, context, max_changes),
+        Comparison::Staged(revision) => (commit_files(revision)?, index_files()?),
+        Comparison::WorkTreeAgainst(revision) => {
+            let old = commit_files(Some(revision))?;
+            return commit_to_work_tree_patches(
+                repo,
+                &odb,
+                &old,
+                &index_files()?,
+                context,
+                max_changes,
+            );
+        }
+        Comparison::Commits(from, to) => (commit_files(Some(from))?, commit_files(Some(to))?),
+    };
+
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut patches = Vec::new();
+    for path in paths {
+        let (before, after) = (old.get(path), new.get(path));
+        if before == after {
+            continue;
+        }
+        let before = before
+            .map(|(mode, oid)| read_version(&odb, *mode, oid))
+            .transpose()?;
+        let after = after
+            .map(|(mode, oid)| read_version(&odb, *mode, oid))
+            .transpose()?;
+        patches.extend(file_patches(
+            path,
+            before.as_ref(),
+            after.as_ref(),
+            context,
+            max_changes,
+        ));
+    }
+    Ok(patches)
+}
+
+/// The patches from the index to the working tree.
+fn work_tree_patches(
+    repo: &Repository,
+    odb: &Odb,
+    context: usize,
+    max_changes: usize,
+) -> Result<Vec<FilePatch>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_path = repo.index_path();
+    let index = Index::read(&index_path)?;
+    let index_mtime = index_mtime(&index_path);
+
+    let mut patches = Vec::new();
+    for entry in index.entries().iter().filter(|entry| entry.stage() == 0) {
+        let missing = fs::symlink_metadata(work_tree.join(&entry.path)).is_err();
+        if !missing && is_unmodified(work_tree, entry, filemode, index_mtime)? {
+            continue;
+        }
+        let staged = (entry.mode, entry.oid);
+        let before = read_version(odb, entry.mode, &entry.oid)?;
+        let after = read_work_tree_file(work_tree, &entry.path, filemode, Some(&staged))?;
+        patches.extend(file_patches(
+            &entry.path,
+            Some(&before),
+            after.as_ref(),
+            context,
+            max_changes,
+        ));
+    }
+    Ok(patches)
+}
+
+/// The patches from the files of a commit, `old`, to the working tree, whose tracked
+/// files are those of `old` and `index`.
+fn commit_to_work_tree_patches(
+    repo: &Repository,
+    odb: &Odb,
+    old: &Files,
+    index: &Files,
+    context: usize,
+    max_changes: usize,
+) -> Result<Vec<FilePatch>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+
+    let paths: BTreeSet<&String> = old.keys().chain(index.keys()).collect();
+    let mut patches = Vec::new();
+    for path in paths {
+        let recorded = old.get(path).or(index.get(path));
+        let before = old
+            .get(path)
+            .map(|(mode, oid)| read_version(odb, *mode, oid))
+            .transpose()?;
+        let after = read_work_tree_file(work_tree, path, filemode, recorded)?;
+        patches.extend(file_patches(
+            path,
+            before.as_ref(),
+            after.as_ref(),
+            context,
+            max_changes,
+        ));
+    }
+    Ok(patches)
+}
+
+/// The stored version of a path: its blob, or for a submodule the line git shows.
+fn read_version(odb: &Odb, mode: EntryMode, oid: &ObjectId) -> Result<FileVersion> {
+    let data = match mode {
+        EntryMode::Commit => format!("Subproject commit {}\n", oid).into_bytes(),
+        _ => {
+            let (kind, data) = odb.read(oid)?;
+            if kind != ObjectKind::Blob {
+                anyhow::bail!("object {} is a {}, not a blob", oid, kind);
+            }
+            data
+        }
+    };
+    Ok(FileVersion {
+        mode,
+        oid: *oid,
+        data,
+    })
+}
+
+/// The working tree version of `path`, or `None` if there's no file there; `recorded`
+/// is how it was last recorded, to keep its mode when `filemode` is off.
+fn read_work_tree_file(
+    work_tree: &Path,
+    path: &str,
+    filemode: bool,
+    recorded: Option<&(EntryMode, ObjectId)>,
+) -> Result<Option<FileVersion>> {
+    let full = work_tree.join(path);
+    let Ok(metadata) = fs::symlink_metadata(&full) else {
+        return Ok(None);
+    };
+    // Note: A submodule's checkout isn't looked into, and a directory where a file
+    // was means the file is gone.
+    if metadata.is_dir() {
+        return Ok(match recorded {
+            Some((EntryMode::Commit, oid)) => Some(FileVersion {
+                mode: EntryMode::Commit,
+                oid: *oid,
+                data: format!("Subproject commit {}\n", oid).into_bytes(),
+            }),
+            _ => None,
+        });
+    }
+    let staged = recorded.map(|(mode, oid)| IndexEntry::new(path, *mode, *oid));
+    let mode = working_mode(&metadata, filemode, staged.as_ref());
+    let data = working_content(&full, mode)?;
+    Ok(Some(FileVersion {
+        mode,
+        oid: hash_object(ObjectKind::Blob, &data),
+        data,
+    }))
+}
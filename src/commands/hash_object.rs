@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::modules::{
+    hash::{ObjectId, ObjectKind, hash_object},
+    odb::Odb,
+};
+use crate::repo::Repository;
+
+/// The object database of the repository containing the current directory.
+pub fn current_odb() -> Result<Odb> {
+    Repository::current()?.odb()
+}
+
+/// The id `data` gets as an object of type `kind`; with an `odb`, the object is also stored.
+///
+/// Note: Like git, plain hashing needs no repository, so it works anywhere.
+pub fn hash(data: &[u8], kind: ObjectKind, odb: Option<&Odb>) -> Result<ObjectId> {
+    match odb {
+        Some(odb) => odb.write(kind, data),
+        None => Ok(hash_object(kind, data)),
+    }
+}
+
+/// Reads the content to hash: a file, or standard input for `None`.
+pub fn read_input(path: Option<&Path>) -> Result<Vec<u8>> {
+    match path {
+        Some(path) => {
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+        }
+        None => {
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut data)
+                .context("Failed to read standard input")?;
+            Ok(data)
+        }
+    }
+}
@@ -1,104 +1,556 @@
 use anyhow::{Context, Result};
-use colored::*;
+use std::path::{Path, PathBuf};
 
 use crate::modules::{
-    files::{WriteOptions, cs01_path, write_files_from_tree},
-    repo_structure::build_repo_tree,
+    config::{Config, last_value, parse_bool, str_to_obj},
+    constants::METADATA_DIR,
+    files::{
+        DiscoveredRepo, ErrorMode, FsCaps, PlannedOp, Progress, ReadOptions, TreeNode,
+        WriteOptions, discover, find_foreign_vcs, find_metadata_dir, looks_like_repo_dir,
+        probe_fs_capabilities, read_tree_from_dir, repo_dir_from_env, set_hidden, set_mode,
+        write_files_from_tree, write_files_transaction, write_tree_staged,
+    },
+    hash::ObjectFormat,
+    ignore::IgnoreMatcher,
+    output::{Output, ProgressLine},
+    perms::SharedMode,
+    refs::{head_branch, is_bootstrap_ref, is_unborn, validate_ref_name, write_symbolic_ref},
+    repo_structure::build_metadata_tree,
+    tree::{MergePolicy, merge_trees},
+    vfs::RealFs,
 };
 
-pub fn init(bare: bool, initial_branch: &str, path: &str) -> Result<()> {
-    let root_path = if path == "." {
+/// How `init` should create the repository; the defaults match a plain `cs01 init`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitOptions {
+    /// Create a bare repository (no working tree).
+    pub bare: bool,
+    /// The first branch; `None` falls back to `resolve_initial_branch`'s defaults.
+    pub initial_branch: Option<String>,
+    pub shared: SharedMode,
+    /// The object hash; `None` means SHA-1 for a new repository and "keep" on re-init.
+    pub object_format: Option<ObjectFormat>,
+    /// Proceed inside another CS01 repository or another VCS's checkout.
+    pub force: bool,
+    /// A directory whose contents are copied into the metadata directory (hooks, info/exclude, ...).
+    pub template: Option<PathBuf>,
+    /// Work out what would be created, without touching the disk; see `InitOutcome::plan`.
+    pub dry_run: bool,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        Self {
+            bare: false,
+            initial_branch: None,
+            shared: SharedMode::Umask,
+            object_format: None,
+            force: false,
+            template: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// What `init` did, for callers to report or act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitOutcome {
+    /// The metadata directory (`.CS01`, or the repository itself when bare).
+    pub repo_dir: PathBuf,
+    /// The working tree root; `None` for bare repositories.
+    pub work_tree: Option<PathBuf>,
+    /// True when an existing repository was repaired rather than created.
+    pub reinitialized: bool,
+    pub bare: bool,
+    /// The branch HEAD points at.
+    pub initial_branch: String,
+    /// True when re-init moved an unborn HEAD to a different branch.
+    pub retargeted_head: bool,
+    /// For a dry run, every step init would take; `None` when it actually ran.
+    pub plan: Option<Vec<PlannedOp>>,
+}
+
+/// Lays the files of the `--template` directory over the built-in metadata tree.
+///
+/// Note: Template files win over the built-in ones (e.g. a custom `description` or hooks),
+/// except `HEAD` and `config`, which init must write itself. Like git, a missing template
+/// directory only produces a warning.
+fn apply_template(tree: TreeNode, template: &Path, out: &Output) -> Result<TreeNode> {
+    if !template.is_dir() {
+        out.warn(&format!(
+            "warning: templates not found in {}",
+            template.display()
+        ));
+        return Ok(tree);
+    }
+
+    let mut template_tree = read_tree_from_dir(template, &ReadOptions::default())
+        .with_context(|| format!("Failed to read template {}", template.display()))?;
+    if let TreeNode::Directory {
+        entries: children, ..
+    } = &mut template_tree
+    {
+        children.remove("HEAD");
+        children.remove("config");
+    }
+
+    merge_trees(tree, template_tree, MergePolicy::OverlayWins)
+        .with_context(|| format!("Failed to apply template {}", template.display()))
+}
+
+/// Widens the modes files in `tree` bring along (hooks, template files) to `shared`;
+/// `WriteOptions::file_perms` only reaches the files without one.
+fn share_explicit_modes(tree: &mut TreeNode, shared: SharedMode) {
+    match tree {
+        TreeNode::File {
+            mode: Some(mode), ..
+        } => *mode = shared.adjust_mode(*mode),
+        TreeNode::Directory {
+            entries: children, ..
+        } => {
+            for child in children.values_mut() {
+                share_explicit_modes(child, shared);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Built-in name of the first branch when nothing else is configured.
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// Decides the name of the initial branch.
+///
+/// Precedence (highest first), mirroring Git:
+/// 1. The `--initial-branch` flag.
+/// 2. The `CS01_INITIAL_BRANCH` environment variable.
+/// 3. `init.defaultBranch` from the system or per-user config files.
+/// 4. The built-in default, "main".
+pub fn resolve_initial_branch(cli_value: Option<&str>) -> Result<String> {
+    if let Some(branch) = cli_value {
+        return Ok(branch.to_string());
+    }
+
+    if let Ok(branch) = std::env::var("CS01_INITIAL_BRANCH")
+        && !branch.is_empty()
+    {
+        return Ok(branch);
+    }
+
+    if let Some(branch) = Config::load_cascaded(None)?.get_string("init.defaultbranch")? {
+        return Ok(branch.to_string());
+    }
+
+    Ok(DEFAULT_BRANCH.to_string())
+}
+
+/// Creates a repository at `path` (the working tree, or the repository itself when bare),
+/// or repairs the one already there. Warnings go through `out`.
+///
+/// Library users should prefer `Repository::init`, which wraps this.
+pub fn init(path: &Path, options: &InitOptions, out: &Output) -> Result<InitOutcome> {
+    let InitOptions {
+        bare,
+        ref initial_branch,
+        shared,
+        object_format,
+        force,
+        ref template,
+        dry_run,
+    } = *options;
+    let branch_is_explicit = initial_branch.is_some();
+    let initial_branch = &resolve_initial_branch(initial_branch.as_deref())?;
+
+    // Critical: The branch name becomes a file path under refs/heads/, so it must be
+    // validated before anything is written to disk.
+    validate_ref_name(initial_branch)
+        .with_context(|| format!("invalid initial branch name: '{}'", initial_branch))?;
+
+    let root_path = if path == Path::new(".") {
         std::env::current_dir()?
     } else {
-        std::path::PathBuf::from(path)
+        path.to_path_buf()
     };
 
-    if !root_path.exists() {
+    // Note: In a dry run the target may not exist yet; the plan's first directory covers it.
+    if !root_path.exists() && !dry_run {
         std::fs::create_dir_all(&root_path).context("Failed to create target directory")?;
     }
+    let mut plan = Vec::new();
 
-    let repo_dir = if bare {
-        root_path.clone()
-    } else {
-        root_path.join(".CS01")
+    // Note: Like GIT_DIR, CS01_DIR lets the caller put the metadata somewhere other
+    // than `<work tree>/.CS01`. The path argument still names the working tree.
+    let env_repo_dir = repo_dir_from_env();
+
+    let repo_dir = match &env_repo_dir {
+        Some(dir) => dir.clone(),
+        None if bare => root_path.clone(),
+        None => root_path.join(METADATA_DIR),
     };
 
+    // Note: On a case-sensitive filesystem a `.cs01` left by another tool is a separate
+    // entry; creating `.CS01` next to it would give the tree two repositories.
+    if !bare
+        && env_repo_dir.is_none()
+        && let Some(existing) = find_metadata_dir(&RealFs, &root_path)
+        && existing.file_name() != Some(METADATA_DIR.as_ref())
+    {
+        anyhow::bail!(
+            "Refusing to create {} in {}: it already contains {} (rename it to {} to use it)",
+            METADATA_DIR,
+            root_path.display(),
+            existing.display(),
+            METADATA_DIR
+        );
+    }
+
     // Tough Topic: Re-initialization
     // We must detect if a repo already exists to avoid overwriting critical data (like objects/HEAD),
     // but we SHOULD allow running 'init' to repair missing files (like config).
-    let is_reinit = if bare {
-        repo_dir.join("HEAD").exists() || repo_dir.join("objects").exists()
+    let is_reinit = if env_repo_dir.is_some() {
+        looks_like_repo_dir(&repo_dir)
+    } else if bare {
+        // Critical: A bare repository shares its directory with whatever else is there,
+        // so `init --bare` on top of a standard repository would scatter metadata next
+        // to its working files.
+        if find_metadata_dir(&RealFs, &root_path).is_some_and(|dir| dir.is_dir()) {
+            anyhow::bail!(
+                "Refusing to create a bare repository in {}: it already contains a standard repository (.CS01)",
+                root_path.display()
+            );
+        }
+        is_existing_bare_repo(&repo_dir)?
     } else {
         repo_dir.exists()
     };
 
     // Critical: Nested Repository Protection
-    // We explicitly forbid creating a repository *inside* another repository (unless it's a re-init of the same repo).
-    // This prevents confusing state where inner commands might accidentally affect the outer repo.
-    // Critical: Nested Repository Protection
-    // We explicitly forbid creating a repository *inside* another repository (unless it's a re-init of the same repo).
-    // This prevents confusing state where inner commands might accidentally affect the outer repo.
-    if !is_reinit && let Some(existing_root) = cs01_path(None, Some(&root_path)) {
-        let existing_root = existing_root.canonicalize()?;
-        let target_root = root_path.canonicalize()?;
-
-        if existing_root != target_root {
-            println!(
-                "{}",
-                format!(
+    // Creating a repository inside another one (other than re-initializing it) leaves
+    // inner commands liable to act on the outer repository.
+    // An explicit CS01_DIR is a deliberate choice of location, so it is exempt.
+    if !is_reinit
+        && env_repo_dir.is_none()
+        && let Some(existing) = discover(Some(&root_path))?
+    {
+        let existing_root = existing.root().canonicalize()?;
+        let target_root = resolve_target(&root_path)?;
+
+        // Note: An outer repo that ignores the target (in `info/exclude`, say) has already
+        // signalled that the directory is meant to be managed separately (vendoring).
+        if existing_root != target_root && !is_excluded_by(&existing, &existing_root, &target_root)?
+        {
+            if force {
+                out.warn(&format!(
+                    "Warning: Creating a nested repository inside the existing one at {}.",
+                    existing_root.display()
+                ));
+            } else {
+                out.warn(&format!(
                     "Warning: You are attempting to initialize a repository inside an existing one at {}.",
                     existing_root.display()
-                )
-                .yellow()
+                ));
+                anyhow::bail!(
+                    "Refusing to create nested repository inside {} (use --force to create it anyway)",
+                    existing_root.display()
+                );
+            }
+        }
+    }
+
+    // Note: Foreign VCS Detection
+    // A `.CS01` next to `.git` works, but tools and people get confused about which VCS
+    // owns the files. Same-directory is only worth a warning; nesting inside a Git
+    // checkout further up is almost always a mistake, so it needs `--force`.
+    if !is_reinit && let Some(foreign) = find_foreign_vcs(&root_path) {
+        let foreign_root = foreign.root.canonicalize()?;
+        let target_root = resolve_target(&root_path)?;
+
+        if foreign_root == target_root {
+            out.warn(&format!(
+                "Warning: {} is already a {} repository. CS01 will keep its own metadata in .CS01 alongside it.",
+                target_root.display(),
+                foreign.name
+            ));
+        } else if force {
+            out.warn(&format!(
+                "Warning: Creating a CS01 repository inside the {} repository at {}.",
+                foreign.name,
+                foreign_root.display()
+            ));
+        } else {
+            anyhow::bail!(
+                "Refusing to create a repository inside the {} repository at {} (use --force to override)",
+                foreign.name,
+                foreign_root.display()
             );
+        }
+    }
+
+    // Critical: The object format decides how every object is named, so it cannot change
+    // after the fact. Re-init only checks that an explicitly requested format matches.
+    if is_reinit && let Some(requested) = object_format {
+        let existing = existing_object_format(&repo_dir)?;
+        if existing != requested {
             anyhow::bail!(
-                "Refusing to create nested repository inside {}",
-                existing_root.display()
+                "attempt to reinitialize repository with different hash (existing: {}, requested: {})",
+                existing,
+                requested
             );
         }
     }
 
+    // Tough Topic: Re-init with a different initial branch
+    // Existing files are never overwritten, so HEAD would keep pointing at the old branch.
+    // Like git, we retarget HEAD only while the current branch has no commits yet;
+    // once history exists, moving HEAD would silently "lose" it from the user's view.
+    let mut retargeted = false;
+    if is_reinit
+        && branch_is_explicit
+        && let Some(current) = head_branch(&repo_dir)?
+        && current != *initial_branch
+    {
+        if is_unborn(&repo_dir, &current)? {
+            if dry_run {
+                plan.extend(retarget_plan(&repo_dir, &current, initial_branch));
+            } else {
+                retarget_unborn_head(&repo_dir, &current, initial_branch)?;
+            }
+            retargeted = true;
+        } else {
+            out.warn(&format!(
+                "warning: re-init: ignored --initial-branch={}",
+                initial_branch
+            ));
+        }
+    }
+
+    // Note: Re-init repairs a bootstrap ref left by older versions, which made the
+    // current branch a symbolic ref to itself; without it the branch is plainly unborn.
+    if is_reinit
+        && !retargeted
+        && let Some(current) = head_branch(&repo_dir)?
+        && is_bootstrap_ref(&repo_dir, &current)
+    {
+        let bootstrap = repo_dir.join("refs/heads").join(&current);
+        if dry_run {
+            plan.push(PlannedOp::RemoveFile(bootstrap));
+        } else {
+            std::fs::remove_file(&bootstrap)
+                .with_context(|| format!("Failed to remove {}", bootstrap.display()))?;
+        }
+    }
+
+    if cfg!(not(unix)) && shared != SharedMode::Umask {
+        out.warn(
+            "Note: --shared is not supported on this platform; permissions were left unchanged.",
+        );
+    }
+
     // Build the repository structure (config, HEAD, etc.)
-    let tree_to_write = build_repo_tree(bare, initial_branch)?;
+    let object_format = object_format.unwrap_or_default();
+    // Note: The staging directory for a fresh init is a sibling of repo_dir, so probing
+    // here (or at the nearest existing ancestor) sees the same filesystem.
+    // A dry run must not create the probe's scratch files, so it assumes the platform's defaults.
+    let fs_caps = if dry_run {
+        FsCaps::default()
+    } else {
+        probe_fs_capabilities(&repo_dir)
+    };
+    let hide_dot_files = hide_dot_files_setting(&repo_dir, is_reinit)?;
+    let tree_to_write = build_metadata_tree(
+        bare,
+        initial_branch,
+        shared,
+        object_format,
+        fs_caps,
+        hide_dot_files,
+    )?;
+    let mut tree_to_write = match template {
+        Some(template) => apply_template(tree_to_write, template, out)?,
+        None => tree_to_write,
+    };
+    share_explicit_modes(&mut tree_to_write, shared);
 
+    // Note: A large template can take a while to write; small trees finish before the line shows.
+    let progress_line = ProgressLine::new("Writing files", out);
+    let report_progress =
+        |progress: Progress| progress_line.update(progress.files_done, progress.files_total);
     let opts = WriteOptions {
-        dir_perms: 0o755,
+        dir_perms: shared.dir_perms(),
+        file_perms: shared.file_perms(),
         overwrite: false,
-        dry_run: false,
+        dry_run,
+        atomic: true,
+        symlinks: fs_caps.symlinks,
+        progress: Some(&report_progress),
+        // Note: A repository missing any of its files is unusable, so init stops at the first failure.
+        on_error: ErrorMode::Abort,
+        vfs: &RealFs,
+        parallelism: 1,
     };
 
-    // Note: write_files_from_tree helps us implement safe re-init because
-    // `overwrite: false` ensures we don't blow away existing HEAD/refs.
-    write_files_from_tree(&tree_to_write, &root_path, &opts)?;
+    if dry_run {
+        plan.extend(write_files_from_tree(&tree_to_write, &repo_dir, &opts)?);
+        return Ok(InitOutcome {
+            work_tree: if bare { None } else { Some(root_path) },
+            repo_dir,
+            reinitialized: is_reinit,
+            bare,
+            initial_branch: initial_branch.clone(),
+            retargeted_head: retargeted,
+            plan: Some(plan),
+        });
+    }
 
-    let repo_type = if bare { "bare" } else { "standard" };
+    // Note: A bare repository's top-level directory is its metadata directory, so other
+    // members of the group must be able to create files (lock files, new refs) in it as well.
+    if bare
+        && let Some(mode) = shared.dir_perms()
+        && repo_dir.is_dir()
+    {
+        set_mode(&repo_dir, mode)?;
+    }
 
-    let folder_note = if bare {
-        "".to_string()
+    // Note: A fresh metadata directory is staged next to its final location and renamed
+    // into place, so a failed init never leaves a half-built repository that the next
+    // run would mistake for an existing one. When the directory already exists (re-init,
+    // or bare init into the target directory), `overwrite: false` keeps existing HEAD/refs
+    // and the transaction removes whatever this run added if a write fails.
+    if repo_dir.exists() {
+        write_files_transaction(&tree_to_write, &repo_dir, &opts)?;
     } else {
-        " (with .CS01 directory)"
-            .truecolor(128, 128, 128)
-            .to_string() // gray
-    };
+        write_tree_staged(&tree_to_write, &repo_dir, &opts)?;
+    }
 
-    let (action, state) = if is_reinit {
-        ("Reinitialized", "existing")
-    } else {
-        ("Initialized", "empty")
-    };
+    // Note: Hiding is cosmetic (and a no-op outside Windows), so it must never fail init.
+    // A bare repository's directory is the user's own choice of name; only dot-directories are hidden.
+    let is_dot_dir = repo_dir
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    if hide_dot_files
+        && is_dot_dir
+        && let Err(e) = set_hidden(&repo_dir)
+    {
+        out.warn(&format!("warning: {:#}", e));
+    }
 
-    let display_path = root_path.canonicalize().unwrap_or(root_path);
+    let work_tree = root_path.canonicalize().unwrap_or(root_path);
+    let repo_dir = repo_dir.canonicalize().unwrap_or(repo_dir);
 
-    let message = format!(
-        "{} {} {} CS01 repository in {}{}",
-        action,
-        state,
-        repo_type,
-        display_path.display(),
-        folder_note
-    );
+    Ok(InitOutcome {
+        work_tree: if bare { None } else { Some(work_tree) },
+        repo_dir,
+        reinitialized: is_reinit,
+        bare,
+        initial_branch: initial_branch.clone(),
+        retargeted_head: retargeted,
+        plan: None,
+    })
+}
 
-    println!("{}", message.green());
+/// `path` resolved for comparing against other repositories' roots.
+///
+/// Note: Only a dry run gets here with a target that doesn't exist yet; its absolute
+/// path is the best available answer, and a missing directory can't be another root anyway.
+fn resolve_target(path: &Path) -> Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(_) if !path.exists() => Ok(std::path::absolute(path)?),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Decides whether `dir` already holds a bare repository.
+///
+/// The config is authoritative: a parseable `config` with `core.bare = true` means yes,
+/// even when HEAD has gone missing. Only when the config is absent or unreadable do we
+/// fall back to structure, requiring both `objects/` and `refs/` so that a stray file
+/// named `HEAD` doesn't make an arbitrary directory look like a repository.
+fn is_existing_bare_repo(dir: &Path) -> Result<bool> {
+    let config = std::fs::read_to_string(dir.join("config"))
+        .ok()
+        .and_then(|content| str_to_obj(&content).ok());
+
+    if let Some(config) = config
+        && config.get("core").is_some()
+    {
+        return match last_value(&config["core"][""]["bare"]).and_then(parse_bool) {
+            Some(true) => Ok(true),
+            _ => anyhow::bail!(
+                "Refusing to reinitialize {} as bare: its config says it is not a bare repository",
+                dir.display()
+            ),
+        };
+    }
+
+    Ok(dir.join("objects").is_dir() && dir.join("refs").is_dir())
+}
+
+/// The steps `retarget_unborn_head` would take, for a dry run.
+fn retarget_plan(repo_dir: &Path, old_branch: &str, new_branch: &str) -> Vec<PlannedOp> {
+    let mut plan = vec![PlannedOp::WriteFile {
+        path: repo_dir.join("HEAD"),
+        bytes: format!("ref: refs/heads/{}\n", new_branch).len(),
+    }];
+    if is_bootstrap_ref(repo_dir, old_branch) {
+        plan.push(PlannedOp::RemoveFile(
+            repo_dir.join("refs/heads").join(old_branch),
+        ));
+    }
+    plan
+}
+
+/// Points HEAD at the unborn `new_branch`, removing the bootstrap ref file older
+/// versions left for the abandoned branch.
+fn retarget_unborn_head(repo_dir: &Path, old_branch: &str, new_branch: &str) -> Result<()> {
+    write_symbolic_ref(repo_dir, "HEAD", &format!("refs/heads/{}", new_branch))
+        .context("Failed to update HEAD")?;
+
+    let old_ref = repo_dir.join("refs/heads").join(old_branch);
+    if is_bootstrap_ref(repo_dir, old_branch) {
+        std::fs::remove_file(&old_ref)
+            .with_context(|| format!("Failed to remove {}", old_ref.display()))?;
+    }
 
     Ok(())
 }
+
+/// Checks whether the directory `target` is ignored by `outer`, through its `info/exclude`
+/// or a `.cs01ignore`; its root (canonicalized, like `target`) is `outer_root`.
+fn is_excluded_by(outer: &DiscoveredRepo, outer_root: &Path, target: &Path) -> Result<bool> {
+    let Ok(relative) = target.strip_prefix(outer_root) else {
+        return Ok(false);
+    };
+    let mut matcher = IgnoreMatcher::new(outer_root);
+    matcher.add_file(&outer.repo_dir.join("info/exclude"))?;
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    Ok(matcher.is_ignored(&relative, true))
+}
+
+/// Whether the metadata directory should be hidden (`core.hidedotfiles`, default true).
+///
+/// Note: On re-init the repository's own config wins, so a user who turned hiding off
+/// for one repository does not get the directory hidden again.
+fn hide_dot_files_setting(repo_dir: &Path, is_reinit: bool) -> Result<bool> {
+    let config = Config::load_cascaded(is_reinit.then_some(repo_dir))?;
+    Ok(config.get_bool("core.hidedotfiles")?.unwrap_or(true))
+}
+
+/// Reads `extensions.objectformat` from an existing repository's config.
+///
+/// A missing config or setting means SHA-1, which is the format of every repository
+/// created before the setting existed.
+fn existing_object_format(repo_dir: &Path) -> Result<ObjectFormat> {
+    let config_path = repo_dir.join("config");
+    if !config_path.is_file() {
+        return Ok(ObjectFormat::Sha1);
+    }
+
+    let config = str_to_obj(&std::fs::read_to_string(&config_path)?)
+        .with_context(|| format!("Failed to parse {}", config_path.display()))?;
+
+    match last_value(&config["extensions"][""]["objectformat"]) {
+        Some(name) => ObjectFormat::parse(name),
+        None => Ok(ObjectFormat::Sha1),
+    }
+}
@@ -0,0 +1,535 @@
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::modules::{
+    color::ColorSpec,
+    config::Config,
+    hash::ObjectId,
+    ignore::wildmatch_name,
+    objects::{commit::Commit, tag},
+    refs::{HeadState, list_refs},
+    revwalk::RevWalk,
+};
+use crate::repo::Repository;
+
+/// Whether `log` shows the references pointing at each commit, and how it names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecorateMode {
+    /// Without their `refs/heads/`, `refs/tags/` or `refs/remotes/` prefix.
+    Short,
+    /// By their full names.
+    Full,
+    /// Short names when stdout is a terminal, none otherwise.
+    Auto,
+    No,
+}
+
+impl DecorateMode {
+    /// Parses `--decorate=<mode>` or the `log.decorate` setting, which may also be a
+    /// boolean.
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "short" | "true" | "yes" | "on" | "1" => Ok(DecorateMode::Short),
+            "full" => Ok(DecorateMode::Full),
+            "auto" => Ok(DecorateMode::Auto),
+            "no" | "false" | "off" | "0" => Ok(DecorateMode::No),
+            _ => bail!("invalid --decorate option: {}", value),
+        }
+    }
+
+    /// What `Auto` comes down to; the other modes stay as they are.
+    pub fn resolve(self, terminal: bool) -> Self {
+        match self {
+            DecorateMode::Auto if terminal => DecorateMode::Short,
+            DecorateMode::Auto => DecorateMode::No,
+            mode => mode,
+        }
+    }
+}
+
+/// A name shown next to a commit by `log --decorate`, holding the reference's full name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoration {
+    /// HEAD, with the branch it's on when that branch is shown too.
+    Head(Option<String>),
+    Branch(String),
+    RemoteBranch(String),
+    Tag(String),
+    Stash,
+    /// Any other reference, e.g. `refs/notes/commits`.
+    Other(String),
+}
+
+impl Decoration {
+    fn from_ref(name: String) -> Self {
+        if name.starts_with("refs/heads/") {
+            Decoration::Branch(name)
+        } else if name.starts_with("refs/remotes/") {
+            Decoration::RemoteBranch(name)
+        } else if name.starts_with("refs/tags/") {
+            Decoration::Tag(name)
+        } else if name == "refs/stash" {
+            Decoration::Stash
+        } else {
+            Decoration::Other(name)
+        }
+    }
+
+    /// The name as `log` shows it, e.g. `tag: v1`, or `tag: refs/tags/v1` when `full`.
+    ///
+    /// Note: Only branches, remote branches and tags are shortened, like git does, so
+    /// the stash stays `refs/stash`.
+    pub fn label(&self, full: bool) -> String {
+        let short = |name: &str| match full {
+            true => name.to_string(),
+            false => shorten(name).to_string(),
+        };
+        match self {
+            Decoration::Head(Some(branch)) => format!("HEAD -> {}", short(branch)),
+            Decoration::Head(None) => "HEAD".to_string(),
+            Decoration::Branch(name) | Decoration::RemoteBranch(name) => short(name),
+            Decoration::Tag(name) => format!("tag: {}", short(name)),
+            Decoration::Stash => "refs/stash".to_string(),
+            Decoration::Other(name) => name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Decoration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label(false))
+    }
+}
+
+/// `name` without the prefix of its kind of reference.
+fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Which references `log` decorates commits with: `--decorate-refs` and
+/// `--decorate-refs-exclude` patterns, matched against full names (and `HEAD`).
+///
+/// Note: As in git, a pattern with glob characters must match the whole name (`*`
+/// crossing `/`), and any other pattern is a prefix ending at a `/`, so `refs/tags`
+/// matches `refs/tags/v1` but `main` matches nothing. Exclusions win. Without any
+/// patterns, only HEAD, branches, remote branches, tags and the stash are shown.
+#[derive(Debug, Clone, Default)]
+pub struct DecorationFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+const DEFAULT_DECORATIONS: [&str; 5] = [
+    "HEAD",
+    "refs/heads/",
+    "refs/remotes/",
+    "refs/tags/",
+    "refs/stash",
+];
+
+impl DecorationFilter {
+    pub fn allows(&self, name: &str) -> bool {
+        let matches = |pattern: &String| matches_ref_pattern(pattern, name);
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return DEFAULT_DECORATIONS
+                .iter()
+                .any(|prefix| matches_ref_pattern(prefix, name));
+        }
+        self.include.is_empty() || self.include.iter().any(matches)
+    }
+}
+
+fn matches_ref_pattern(pattern: &str, name: &str) -> bool {
+    if pattern.contains(['*', '?', '[', '\\']) {
+        return wildmatch_name(pattern.as_bytes(), name.as_bytes());
+    }
+    match name.strip_prefix(pattern) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || pattern.ends_with('/'),
+        None => false,
+    }
+}
+
+/// The commits reachable from `start` (HEAD when `None`), newest first, at most
+/// `max_count` of them.
+///
+/// Note: `start` is any revision `rev-parse` accepts, and must name a commit or an
+/// annotated tag of one.
+pub fn log(
+    repo: &Repository,
+    start: Option<&str>,
+    max_count: Option<usize>,
+) -> Result<Vec<(ObjectId, Commit)>> {
+    let odb = repo.odb()?;
+    let start = match start {
+        Some(revision) => tag::peel(&odb, &repo.resolve_revision(revision)?)?,
+        None => match repo.head_commit()? {
+            Some(id) => id,
+            None => match repo.head_ref()? {
+                Some(HeadState::Branch(name)) => {
+                    bail!(
+                        "your current branch '{}' does not have any commits yet",
+                        name
+                    )
+                }
+                _ => bail!("HEAD does not point at a commit"),
+            },
+        },
+    };
+
+    let mut walk = RevWalk::new(&odb);
+    walk.push(start)?;
+    walk.take(max_count.unwrap_or(usize::MAX)).collect()
+}
+
+/// The names of the commits the references `filter` allows point at, with annotated
+/// tags peeled to their commit, in the order git shows them: HEAD (with its branch,
+/// which isn't repeated) first, then the other references in reverse order of their
+/// full names.
+///
+/// Note: References to missing objects are left out, as git does.
+pub fn decorations(
+    repo: &Repository,
+    filter: &DecorationFilter,
+) -> Result<HashMap<ObjectId, Vec<Decoration>>> {
+    let odb = repo.odb()?;
+    let mut decorations: HashMap<ObjectId, Vec<Decoration>> = HashMap::new();
+    let current = match repo.head_ref()? {
+        Some(HeadState::Branch(name)) => Some(format!("refs/heads/{}", name)),
+        _ => None,
+    };
+    let head = repo.head_commit()?.filter(|_| filter.allows("HEAD"));
+    let mut head_branch = None;
+    for (name, oid) in list_refs(repo.repo_dir(), "refs/")?.into_iter().rev() {
+        if !filter.allows(&name) {
+            continue;
+        }
+        let oid = ObjectId::from_hex(&oid).with_context(|| format!("{} is broken", name))?;
+        if !odb.contains(&oid) {
+            continue;
+        }
+        if head.is_some() && current.as_ref() == Some(&name) {
+            head_branch = Some(name);
+            continue;
+        }
+        let oid = tag::peel(&odb, &oid)?;
+        decorations
+            .entry(oid)
+            .or_default()
+            .push(Decoration::from_ref(name));
+    }
+    if let Some(head) = head {
+        decorations
+            .entry(head)
+            .or_default()
+            .insert(0, Decoration::Head(head_branch));
+    }
+    Ok(decorations)
+}
+
+/// The colors of `log --decorate`, from `color.decorate.<slot>` where the slot is
+/// `HEAD`, `branch`, `remoteBranch`, `tag` or `stash`; other references are plain.
+#[derive(Debug, Clone)]
+pub struct DecorationColors {
+    head: ColorSpec,
+    branch: ColorSpec,
+    remote_branch: ColorSpec,
+    tag: ColorSpec,
+    stash: ColorSpec,
+}
+
+impl DecorationColors {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let slot = |name: &str, default: &str| -> Result<ColorSpec> {
+            let key = format!("color.decorate.{}", name);
+            let value = config.get_string(&key)?.unwrap_or(default);
+            ColorSpec::parse(value)
+                .map_err(|_| anyhow::anyhow!("bad config value {} for '{}'", value, key))
+        };
+        Ok(DecorationColors {
+            head: slot("HEAD", "bold cyan")?,
+            branch: slot("branch", "bold green")?,
+            remote_branch: slot("remoteBranch", "bold red")?,
+            tag: slot("tag", "bold yellow")?,
+            stash: slot("stash", "bold magenta")?,
+        })
+    }
+
+    /// ` (HEAD -> main, tag: v1)` in color, or nothing without `names`.
+    pub fn paint(&self, names: &[Decoration], full: bool) -> String {
+        if names.is_empty() {
+            return String::new();
+        }
+        let names: Vec<String> = names
+            .iter()
+            .map(|name| match name {
+                Decoration::Head(Some(branch)) => format!(
+                    "{}{}",
+                    self.head.paint("HEAD -> "),
+                    self.branch
+                        .paint(&Decoration::Branch(branch.clone()).label(full))
+                ),
+                Decoration::Head(None) => self.head.paint("HEAD").to_string(),
+                Decoration::Branch(_) => self.branch.paint(&name.label(full)).to_string(),
+                Decoration::RemoteBranch(_) => {
+                    self.remote_branch.paint(&name.label(full)).to_string()
+                }
+                Decoration::Tag(_) => self.tag.paint(&name.label(full)).to_string(),
+                Decoration::Stash => self.stash.paint(&name.label(full)).to_string(),
+                Decoration::Other(_) => name.label(full),
+            })
+            .collect();
+        format!(
+            " {}{}{}",
+            "(".yellow(),
+            names.join(&", ".yellow().to_string()),
+            ")".yellow()
+        )
+    }
+}
+
+/// How `log` shows each commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pretty {
+    /// The default: a `commit` header, author, date and the indented message.
+    Medium,
+    /// The id and subject on one line, the id abbreviated with `abbrev`.
+    Oneline { abbrev: bool },
+    /// A template with `%` placeholders; entries are separated by newlines with
+    /// `format:` and terminated by one with `tformat:`.
+    Format { template: String, terminated: bool },
+}
+
+impl Pretty {
+    /// Parses `--pretty=<format>` or `--format=<format>`: `medium`, `oneline`,
+    /// `format:<template>`, `tformat:<template>`, or a bare template with a `%` in it.
+    pub fn parse(value: &str) -> Result<Self> {
+        if let Some(template) = value.strip_prefix("format:") {
+            return Ok(Pretty::Format {
+                template: template.to_string(),
+                terminated: false,
+            });
+        }
+        if let Some(template) = value.strip_prefix("tformat:") {
+            return Ok(Pretty::Format {
+                template: template.to_string(),
+                terminated: true,
+            });
+        }
+        match value {
+            "medium" => Ok(Pretty::Medium),
+            "oneline" => Ok(Pretty::Oneline { abbrev: false }),
+            _ if value.contains('%') => Ok(Pretty::Format {
+                template: value.to_string(),
+                terminated: true,
+            }),
+            _ => bail!("invalid --pretty format: {}", value),
+        }
+    }
+
+    /// Whether the format shows decorations even without `--decorate`.
+    pub fn uses_decorations(&self) -> bool {
+        match self {
+            Pretty::Format { template, .. } => template.contains("%d") || template.contains("%D"),
+            _ => false,
+        }
+    }
+}
+
+/// The subject of a commit message, as `%s` and `--oneline` show it: its first
+/// paragraph, with the lines joined by spaces.
+pub fn subject(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything after the subject paragraph, as `%b` shows it.
+fn body(message: &str) -> String {
+    let mut lines = message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .skip_while(|line| !line.trim().is_empty())
+        .skip_while(|line| line.trim().is_empty())
+        .peekable();
+    let mut body = String::new();
+    while let Some(line) = lines.next() {
+        body.push_str(line);
+        body.push('\n');
+        if lines.peek().is_none() {
+            break;
+        }
+    }
+    body
+}
+
+/// Expands the placeholders of a `--format` template for one commit.
+///
+/// Note: Supported are `%H`/`%h` (commit), `%T`/`%t` (tree), `%P`/`%p` (parents),
+/// `%an`, `%ae`, `%ad`, `%at` and their `%c` counterparts for the committer, `%s`,
+/// `%b`, `%B`, `%d`, `%D`, `%n`, `%xNN` (a byte in hex) and `%%`. Anything else is copied as is, as git does
+/// with placeholders it doesn't know. Decorations are never colored here.
+pub fn expand_format(
+    template: &str,
+    id: &ObjectId,
+    commit: &Commit,
+    names: &[Decoration],
+    full: bool,
+) -> String {
+    let labels = || {
+        names
+            .iter()
+            .map(|name| name.label(full))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let ids = |ids: &[ObjectId], len: usize| {
+        ids.iter()
+            .map(|id| id.short(len))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('%') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let mut chars = rest[1..].chars();
+        let (expansion, len) = match (chars.next(), chars.next()) {
+            (Some('H'), _) => (id.to_hex(), 2),
+            (Some('h'), _) => (id.short(7), 2),
+            (Some('T'), _) => (commit.tree.to_hex(), 2),
+            (Some('t'), _) => (commit.tree.short(7), 2),
+            (Some('P'), _) => (ids(&commit.parents, 40), 2),
+            (Some('p'), _) => (ids(&commit.parents, 7), 2),
+            (Some(role @ ('a' | 'c')), Some(field @ ('n' | 'e' | 'd' | 't'))) => {
+                let identity = match role {
+                    'a' => &commit.author,
+                    _ => &commit.committer,
+                };
+                let value = match field {
+                    'n' => identity.name.clone(),
+                    'e' => identity.email.clone(),
+                    'd' => identity.when.to_log_date(),
+                    _ => identity.when.seconds.to_string(),
+                };
+                (value, 3)
+            }
+            (Some('s'), _) => (subject(&commit.message), 2),
+            (Some('b'), _) => (body(&commit.message), 2),
+            (Some('B'), _) => (commit.message.clone(), 2),
+            (Some('d'), _) if names.is_empty() => (String::new(), 2),
+            (Some('d'), _) => (format!(" ({})", labels()), 2),
+            (Some('D'), _) => (labels(), 2),
+            (Some('n'), _) => ("\n".to_string(), 2),
+            (Some('x'), _) => match rest.get(2..).and_then(hex_byte) {
+                Some(byte) => (char::from(byte).to_string(), 4),
+                None => ("%".to_string(), 1),
+            },
+            (Some('%'), _) => ("%".to_string(), 2),
+            _ => ("%".to_string(), 1),
+        };
+        out.push_str(&expansion);
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The ASCII character written as two hex digits at the start of `text`.
+fn hex_byte(text: &str) -> Option<u8> {
+    text.get(..2)
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        .filter(u8::is_ascii)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoration_filter() {
+        let default = DecorationFilter::default();
+        for name in ["HEAD", "refs/heads/main", "refs/tags/v1", "refs/stash"] {
+            assert!(default.allows(name), "{}", name);
+        }
+        assert!(!default.allows("refs/notes/commits"));
+        assert!(!default.allows("refs/stashes"));
+
+        let filter = DecorationFilter {
+            include: vec!["refs/tags".to_string(), "refs/remotes/*".to_string()],
+            exclude: vec!["refs/tags/v1".to_string()],
+        };
+        assert!(filter.allows("refs/tags/v2"));
+        assert!(filter.allows("refs/remotes/origin/main"));
+        assert!(!filter.allows("refs/tags/v1"));
+        assert!(!filter.allows("refs/tags-old/v1"));
+        assert!(!filter.allows("HEAD"));
+
+        // Excluding alone lifts the default set
+        let filter = DecorationFilter {
+            include: Vec::new(),
+            exclude: vec!["refs/remotes/*".to_string()],
+        };
+        assert!(filter.allows("refs/notes/commits"));
+        assert!(!filter.allows("refs/remotes/origin/main"));
+    }
+
+    #[test]
+    fn test_decoration_labels() {
+        let head = Decoration::Head(Some("refs/heads/main".to_string()));
+        assert_eq!(head.label(false), "HEAD -> main");
+        assert_eq!(head.label(true), "HEAD -> refs/heads/main");
+        let tag = Decoration::from_ref("refs/tags/v1".to_string());
+        assert_eq!(tag.label(false), "tag: v1");
+        assert_eq!(tag.label(true), "tag: refs/tags/v1");
+        let remote = Decoration::from_ref("refs/remotes/origin/main".to_string());
+        assert_eq!(remote.to_string(), "origin/main");
+        assert_eq!(
+            Decoration::from_ref("refs/stash".to_string()),
+            Decoration::Stash
+        );
+        assert_eq!(Decoration::Stash.label(false), "refs/stash");
+    }
+
+    #[test]
+    fn test_subject_and_body() {
+        let message = "First line\nstill the subject\n\nBody one.\n\nBody two.\n";
+        assert_eq!(subject(message), "First line still the subject");
+        assert_eq!(body(message), "Body one.\n\nBody two.\n");
+        assert_eq!(body("Only a subject\n"), "");
+    }
+
+    #[test]
+    fn test_pretty_parse() {
+        assert_eq!(Pretty::parse("medium").unwrap(), Pretty::Medium);
+        assert_eq!(
+            Pretty::parse("%h %s").unwrap(),
+            Pretty::Format {
+                template: "%h %s".to_string(),
+                terminated: true
+            }
+        );
+        assert_eq!(
+            Pretty::parse("format:%h").unwrap(),
+            Pretty::Format {
+                template: "%h".to_string(),
+                terminated: false
+            }
+        );
+        assert!(Pretty::parse("fancy").is_err());
+        assert!(Pretty::parse("tformat:%d").unwrap().uses_decorations());
+    }
+}
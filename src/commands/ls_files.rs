@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::modules::{
+    constants::is_repository_dir_name,
+    files::is_embedded_repository,
+    ignore::IgnoreMatcher,
+    index::{Index, IndexEntry, index_mtime, is_unmodified},
+};
+use crate::repo::Repository;
+
+/// Which files `ls_files` lists.
+#[derive(Debug, Clone, Default)]
+pub struct LsFilesOptions {
+    /// Every index entry (`--cached`); the default when nothing else is asked for.
+    pub cached: bool,
+    /// Untracked files in the working tree (`--others`).
+    pub others: bool,
+    /// Tracked files whose working tree copy differs from the index, or is gone
+    /// (`--modified`).
+    pub modified: bool,
+    /// Tracked files missing from the working tree (`--deleted`).
+    pub deleted: bool,
+    /// Leave out untracked files matched by the usual ignore rules (`--exclude-standard`).
+    pub exclude_standard: bool,
+    /// Leave out untracked files matched by the patterns in these files (`--exclude-from`).
+    pub exclude_from: Vec<PathBuf>,
+    /// Only list paths inside this directory (from the top of the working tree, empty
+    /// for everything), as git does when run from a subdirectory.
+    pub under: String,
+}
+
+/// Why a path is listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listing {
+    Cached,
+    Other,
+    Deleted,
+    Modified,
+}
+
+impl Listing {
+    /// The listing's name in JSON output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Listing::Cached => "cached",
+            Listing::Other => "other",
+            Listing::Deleted => "deleted",
+            Listing::Modified => "modified",
+        }
+    }
+}
+
+/// One line of `ls_files` output.
+#[derive(Debug, Clone)]
+pub struct Listed {
+    pub path: String,
+    pub listing: Listing,
+    /// The index entry behind the line; `None` for untracked files.
+    pub entry: Option<IndexEntry>,
+}
+
+/// Lists the files of `repo` that `options` asks for, by `/`-separated path from the
+/// top of the working tree.
+///
+/// Untracked files come first, sorted; then each index entry in index order, once per
+/// listing that applies to it, as git orders them.
+///
+/// Note: Without `exclude_standard` or `exclude_from`, ignored files count as
+/// untracked like any other.
+pub fn ls_files(repo: &Repository, options: &LsFilesOptions) -> Result<Vec<Listed>> {
+    let index_path = repo.index_path();
+    let index = Index::read(&index_path)?;
+    let cached = options.cached || !(options.others || options.modified || options.deleted);
+
+    let mut listed = Vec::new();
+    if options.others {
+        let work_tree = repo
+            .work_tree()
+            .context("this operation must be run in a work tree")?;
+        let mut matcher = if options.exclude_standard {
+            IgnoreMatcher::for_repo(repo)?
+        } else {
+            IgnoreMatcher::without_directory_files(work_tree)
+        };
+        for path in &options.exclude_from {
+            matcher.add_file(path)?;
+        }
+        let tracked: HashSet<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+        let mut others = Vec::new();
+        collect_others(work_tree, "", &tracked, &matcher, &mut others)?;
+        others.retain(|path| is_under(path, &options.under));
+        others.sort();
+        listed.extend(others.into_iter().map(|path| Listed {
+            path,
+            listing: Listing::Other,
+            entry: None,
+        }));
+    }
+
+    let work_tree = if options.modified || options.deleted {
+        Some(
+            repo.work_tree()
+                .context("this operation must be run in a work tree")?,
+        )
+    } else {
+        None
+    };
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_mtime = index_mtime(&index_path);
+    for entry in index.entries() {
+        if !is_under(&entry.path, &options.under) {
+            continue;
+        }
+        let mut listings = Vec::new();
+        if cached {
+            listings.push(Listing::Cached);
+        }
+        if let Some(work_tree) = work_tree {
+            let gone = fs::symlink_metadata(work_tree.join(&entry.path)).is_err();
+            if options.deleted && gone {
+                listings.push(Listing::Deleted);
+            }
+            // Note: Like git, a deleted file is modified too.
+            if options.modified
+                && (gone || !is_unmodified(work_tree, entry, filemode, index_mtime)?)
+            {
+                listings.push(Listing::Modified);
+            }
+        }
+        listed.extend(listings.into_iter().map(|listing| Listed {
+            path: entry.path.clone(),
+            listing,
+            entry: Some(entry.clone()),
+        }));
+    }
+    Ok(listed)
+}
+
+/// Whether `path` is inside the directory `dir`, both from the top; everything is
+/// inside the top itself (`""`).
+fn is_under(path: &str, dir: &str) -> bool {
+    dir.is_empty()
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Collects the untracked files under `dir` (at `relative`) that `matcher` doesn't
+/// ignore, skipping ignored directories whole; an embedded repository is listed as
+/// `dir/`, as in git.
+fn collect_others(
+    dir: &Path,
+    relative: &str,
+    tracked: &HashSet<&str>,
+    matcher: &IgnoreMatcher,
+    found: &mut Vec<String>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", dir))?;
+        let name = entry.file_name();
+        if is_repository_dir_name(&name) {
+            continue;
+        }
+        let name = name.to_string_lossy();
+        let child = if relative.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        let is_dir = entry
+            .file_type()
+            .with_context(|| format!("Failed to read {:?}", entry.path()))?
+            .is_dir();
+        // Note: A tracked directory is a submodule, whose files aren't ours to list.
+        if is_dir {
+            if tracked.contains(child.as_str()) || matcher.is_ignored(&child, true) {
+                continue;
+            }
+            if is_embedded_repository(&entry.path()) {
+                found.push(format!("{}/", child));
+            } else {
+                collect_others(&entry.path(), &child, tracked, matcher, found)?;
+            }
+        } else if !tracked.contains(child.as_str()) && !matcher.is_ignored(&child, false) {
+            found.push(child);
+        }
+    }
+    Ok(())
+}
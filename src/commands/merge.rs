@@ -0,0 +1,265 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::fs;
+
+use crate::commands::update_ref::update_ref;
+use crate::modules::{
+    checkout::{Files, Operation, WouldOverwrite, check_out},
+    diff::max_changed_lines,
+    hash::{ObjectId, ObjectKind},
+    index::{Index, IndexEntry},
+    lockfile::Lockfile,
+    merge::{Conflict, merge_trees},
+    objects::{commit::Commit, tree::Tree},
+    refs::{RefValue, read_ref, write_detached_ref},
+    revision::full_ref_name,
+    revwalk::{is_ancestor, merge_base},
+};
+use crate::repo::Repository;
+
+/// The commit being merged while a conflicted merge waits to be committed.
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
+/// The message the commit concluding a conflicted merge starts from.
+pub const MERGE_MSG: &str = "MERGE_MSG";
+
+/// When `merge` may just move the branch instead of recording a merge commit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FastForward {
+    /// Whenever HEAD is an ancestor of the merged commit.
+    #[default]
+    Allowed,
+    /// Never; a merge commit is always recorded (`--no-ff`).
+    Never,
+    /// Only; anything else is refused (`--ff-only`).
+    Only,
+}
+
+/// What `merge` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The merged commit was already part of HEAD's history.
+    UpToDate,
+    /// The branch moved forward from `from` to `to`.
+    FastForward {
+        from: Option<ObjectId>,
+        to: ObjectId,
+    },
+    /// A merge commit was recorded.
+    Merged {
+        id: ObjectId,
+        /// The paths whose content was merged line by line.
+        auto_merged: Vec<String>,
+    },
+    /// The merge stopped with conflicts for the user to resolve and commit.
+    Conflicted {
+        auto_merged: Vec<String>,
+        conflicts: Vec<Conflict>,
+    },
+}
+
+/// Merges the commit `revision` names into HEAD.
+///
+/// If HEAD is an ancestor of it, HEAD's branch is fast-forwarded and the working tree
+/// checked out as `switch` does, keeping local changes that don't get in the way.
+/// Otherwise the two histories are merged against their merge base with `merge_trees`,
+/// which needs an index matching HEAD; a clean result is recorded as a commit with both
+/// parents. Conflicts are left in the working tree with markers and in the index as
+/// stages 1-3, with `MERGE_HEAD` and `MERGE_MSG` written for `commit` to finish the job.
+///
+/// Note: HEAD's previous commit is saved in `ORIG_HEAD`, and moves go into the reflogs
+/// as `merge <revision>: ...`.
+pub fn merge(repo: &Repository, revision: &str, fast_forward: FastForward) -> Result<MergeOutcome> {
+    if repo.work_tree().is_none() {
+        bail!("this operation must be run in a work tree");
+    }
+    if merge_head(repo)?.is_some() {
+        bail!(
+            "You have not concluded your merge (MERGE_HEAD exists).\nPlease, commit your changes before you merge."
+        );
+    }
+    let odb = repo.odb()?;
+    let theirs = repo.resolve_revision(revision)?;
+    let their_commit = Commit::read(&odb, &theirs)?;
+    let head = repo.head_commit()?;
+
+    let fast_forwards = match head {
+        Some(head) if is_ancestor(&odb, &theirs, &head)? => return Ok(MergeOutcome::UpToDate),
+        Some(head) => is_ancestor(&odb, &head, &theirs)?,
+        None if fast_forward == FastForward::Never => {
+            bail!("Non-fast-forward commit does not make sense into an empty head")
+        }
+        None => true,
+    };
+    let head_files = match head {
+        Some(head) => Tree::flatten(&odb, &Commit::read(&odb, &head)?.tree)?,
+        None => Files::new(),
+    };
+    let their_files = Tree::flatten(&odb, &their_commit.tree)?;
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    let old = head.map(|id| id.to_hex()).unwrap_or_default();
+
+    if fast_forwards && fast_forward != FastForward::Never {
+        check_out_for_merge(repo, &mut index, &head_files, &their_files)?;
+        index.write_locked(lock)?;
+        if let Some(head) = &head {
+            write_detached_ref(repo.repo_dir(), "ORIG_HEAD", head)?;
+        }
+        let reason = format!("merge {}: Fast-forward", revision);
+        update_ref(repo, "HEAD", &theirs.to_hex(), Some(&old), Some(&reason))?;
+        return Ok(MergeOutcome::FastForward {
+            from: head,
+            to: theirs,
+        });
+    }
+    if fast_forward == FastForward::Only {
+        bail!("Not possible to fast-forward, aborting.");
+    }
+    let Some(head) = head else {
+        unreachable!("an unborn HEAD always fast-forwards");
+    };
+    let Some(base) = merge_base(&odb, &head, &theirs)? else {
+        bail!("refusing to merge unrelated histories");
+    };
+
+    // Note: As in git, the merge result is staged over HEAD's tree, so nothing else may
+    // be staged.
+    let staged: Files = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage() == 0)
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.oid)))
+        .collect();
+    let unmerged = index.entries().iter().filter(|entry| entry.stage() != 0);
+    let dirty: BTreeSet<String> = head_files
+        .keys()
+        .chain(staged.keys())
+        .filter(|path| head_files.get(*path) != staged.get(*path))
+        .cloned()
+        .chain(unmerged.map(|entry| entry.path.clone()))
+        .collect();
+    if !dirty.is_empty() {
+        return Err(WouldOverwrite {
+            changed: dirty.into_iter().collect(),
+            untracked: Vec::new(),
+            operation: Operation::Merge,
+        }
+        .into());
+    }
+
+    let base_files = Tree::flatten(&odb, &Commit::read(&odb, &base)?.tree)?;
+    let merged = merge_trees(
+        &odb,
+        &base_files,
+        &head_files,
+        &their_files,
+        ("HEAD", revision),
+        max_changed_lines(&repo.config()?, "merge.maxChangedLines")?,
+    )?;
+    check_out_for_merge(repo, &mut index, &head_files, &merged.files)?;
+    for conflict in &merged.conflicts {
+        index.remove(&conflict.path);
+        let stages = [conflict.base, conflict.ours, conflict.theirs];
+        for (stage, version) in (1..).zip(stages) {
+            if let Some((mode, oid)) = version {
+                index.add_entry(
+                    IndexEntry::new(conflict.path.as_str(), mode, oid).with_stage(stage),
+                )?;
+            }
+        }
+    }
+    index.write_locked(lock)?;
+    write_detached_ref(repo.repo_dir(), "ORIG_HEAD", &head)?;
+
+    let message = merge_message(repo, revision);
+    if !merged.conflicts.is_empty() {
+        let mut text = format!("{}\n# Conflicts:\n", message);
+        for conflict in &merged.conflicts {
+            text.push_str(&format!("#\t{}\n", conflict.path));
+        }
+        let message_path = repo.repo_dir().join(MERGE_MSG);
+        fs::write(&message_path, text)
+            .with_context(|| format!("Failed to write {:?}", message_path))?;
+        write_detached_ref(repo.repo_dir(), MERGE_HEAD, &theirs)?;
+        return Ok(MergeOutcome::Conflicted {
+            auto_merged: merged.auto_merged,
+            conflicts: merged.conflicts,
+        });
+    }
+
+    let config = repo.config()?;
+    let commit = Commit {
+        tree: index.write_tree(&odb)?,
+        parents: vec![head, theirs],
+        author: config.author()?,
+        committer: config.committer()?,
+        extra_headers: Vec::new(),
+        message,
+    };
+    let id = odb.write(ObjectKind::Commit, &commit.serialize())?;
+    let reason = format!("merge {}: Merge made by a three-way merge.", revision);
+    update_ref(repo, "HEAD", &id.to_hex(), Some(&old), Some(&reason))?;
+    Ok(MergeOutcome::Merged {
+        id,
+        auto_merged: merged.auto_merged,
+    })
+}
+
+/// The commit a conflicted merge is waiting to merge, if one is.
+pub fn merge_head(repo: &Repository) -> Result<Option<ObjectId>> {
+    match read_ref(repo.repo_dir(), MERGE_HEAD)? {
+        Some(RefValue::Direct(oid)) => Ok(Some(
+            ObjectId::from_hex(&oid).with_context(|| format!("{} is broken", MERGE_HEAD))?,
+        )),
+        Some(RefValue::Symbolic(_)) => bail!("{} is broken", MERGE_HEAD),
+        None => Ok(None),
+    }
+}
+
+/// Forgets a merge in progress, once it's committed or reset away.
+pub fn clear_merge_state(repo: &Repository) -> Result<()> {
+    for name in [MERGE_HEAD, MERGE_MSG] {
+        let path = repo.repo_dir().join(name);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove {:?}", path));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks out `new` over `old` as `check_out` does, wording a refusal for `merge`.
+pub fn check_out_for_merge(
+    repo: &Repository,
+    index: &mut Index,
+    old: &Files,
+    new: &Files,
+) -> Result<()> {
+    check_out(repo, index, old, new).map_err(|e| match e.downcast::<WouldOverwrite>() {
+        Ok(refused) => WouldOverwrite {
+            operation: Operation::Merge,
+            ..refused
+        }
+        .into(),
+        Err(e) => e,
+    })
+}
+
+/// The message of a merge commit, naming what was merged as git does.
+fn merge_message(repo: &Repository, revision: &str) -> String {
+    let full = full_ref_name(repo.repo_dir(), revision).unwrap_or_default();
+    let what = if full.starts_with("refs/heads/") {
+        "branch"
+    } else if full.starts_with("refs/tags/") {
+        "tag"
+    } else if full.starts_with("refs/remotes/") {
+        "remote-tracking branch"
+    } else {
+        "commit"
+    };
+    format!("Merge {} '{}'\n", what, revision)
+}
@@ -1 +1,26 @@
+pub mod add;
+pub mod branch;
+pub mod cat_file;
+pub mod check_ignore;
+pub mod clone;
+pub mod commit;
+pub mod commit_tree;
+pub mod config;
+pub mod diff;
+pub mod hash_object;
 pub mod init;
+pub mod log;
+pub mod ls_files;
+pub mod merge;
+pub mod mv;
+pub mod pack_refs;
+pub mod reflog;
+pub mod reset;
+pub mod rev_parse;
+pub mod rm;
+pub mod stash;
+pub mod status;
+pub mod switch;
+pub mod symbolic_ref;
+pub mod update_ref;
+pub mod write_tree;
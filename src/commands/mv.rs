@@ -0,0 +1,119 @@
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::commands::add::is_under;
+use crate::modules::{constants::is_metadata_dir_name, index::IndexEntry, lockfile::Lockfile};
+use crate::repo::Repository;
+
+/// Renames each of `sources` (relative to `cwd`) to `destination`, or into it when it
+/// is a directory, on disk and in the index; returns the renames as `(from, to)`
+/// working tree paths.
+///
+/// A source is a tracked file or a directory holding tracked files, whose entries all
+/// move with it. An existing destination is only replaced when `force` is set, and
+/// never when it's a directory.
+///
+/// Note: Every rename is checked before anything moves, so one bad source moves
+/// nothing.
+pub fn mv(
+    repo: &Repository,
+    cwd: &Path,
+    sources: &[String],
+    destination: &str,
+    force: bool,
+) -> Result<Vec<(String, String)>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let destination = repo.work_tree_path(cwd, Path::new(destination))?;
+    let into_dir = destination.is_empty()
+        || fs::symlink_metadata(work_tree.join(&destination)).is_ok_and(|m| m.is_dir());
+    if sources.len() > 1 && !into_dir {
+        bail!("destination '{}' is not a directory", destination);
+    }
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+
+    let mut renames = Vec::new();
+    let mut targets = HashSet::new();
+    for given in sources {
+        let source = repo.work_tree_path(cwd, Path::new(given))?;
+        let name = source.rsplit('/').next().unwrap_or_default();
+        let target = match (into_dir, destination.is_empty()) {
+            (true, true) => name.to_string(),
+            (true, false) => format!("{}/{}", destination, name),
+            (false, _) => destination.clone(),
+        };
+        let fail = |problem: &str| {
+            anyhow::anyhow!("{}, source={}, destination={}", problem, source, target)
+        };
+
+        if source.is_empty()
+            || source
+                .split('/')
+                .any(|part| is_metadata_dir_name(part.as_ref()))
+        {
+            return Err(fail("bad source"));
+        }
+        let Ok(metadata) = fs::symlink_metadata(work_tree.join(&source)) else {
+            return Err(fail("bad source"));
+        };
+        let entries: Vec<&IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|entry| is_under(&entry.path, &source))
+            .collect();
+        if entries.is_empty() {
+            return Err(fail("not under version control"));
+        }
+        if entries.iter().any(|entry| entry.stage() != 0) {
+            return Err(fail("conflicted"));
+        }
+        if metadata.is_dir() && is_under(&target, &source) {
+            return Err(fail("can not move directory into itself"));
+        }
+        match fs::symlink_metadata(work_tree.join(&target)) {
+            Ok(existing) if existing.is_dir() || !force => {
+                return Err(fail("destination exists"));
+            }
+            _ => {}
+        }
+        let parent = target.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        if !work_tree.join(parent).is_dir() {
+            return Err(fail("destination directory does not exist"));
+        }
+        if !targets.insert(target.clone()) {
+            return Err(fail("multiple sources for the same target"));
+        }
+        renames.push((source, target));
+    }
+
+    for (source, target) in &renames {
+        let (from, to) = (work_tree.join(source), work_tree.join(target));
+        fs::rename(&from, &to).with_context(|| format!("renaming '{}' failed", source))?;
+
+        let moved: Vec<IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|entry| is_under(&entry.path, source))
+            .cloned()
+            .collect();
+        index.remove(target);
+        for entry in moved {
+            index.remove(&entry.path);
+            let path = format!("{}{}", target, &entry.path[source.len()..]);
+            let full = work_tree.join(&path);
+            let metadata = fs::symlink_metadata(&full)
+                .with_context(|| format!("Failed to read {:?}", full))?;
+            let mut renamed = IndexEntry::new(path, entry.mode, entry.oid);
+            renamed.set_stat(&metadata);
+            index.add_entry(renamed)?;
+        }
+    }
+    index.write_locked(lock)?;
+    Ok(renames)
+}
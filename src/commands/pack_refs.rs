@@ -0,0 +1,29 @@
+use anyhow::Result;
+
+use crate::modules::{
+    files::{STALE_TEMP_AGE, remove_stale_temp_files},
+    hash::ObjectId,
+    objects::tag,
+    refs,
+};
+use crate::repo::Repository;
+
+/// Packs the loose references of `repo` into `packed-refs`, every one with `all` and
+/// otherwise only tags, and returns the names packed.
+///
+/// Note: Annotated tags get their peeled line, so readers of `packed-refs` don't need
+/// the object store to find the commit a tag is for. As the one maintenance step so
+/// far, this also clears out temporary files that interrupted writes left in the
+/// metadata and object directories more than a day ago.
+pub fn pack_refs(repo: &Repository, all: bool) -> Result<Vec<String>> {
+    let odb = repo.odb()?;
+    remove_stale_temp_files(repo.repo_dir(), STALE_TEMP_AGE)?;
+    if !odb.objects_dir().starts_with(repo.repo_dir()) {
+        remove_stale_temp_files(odb.objects_dir(), STALE_TEMP_AGE)?;
+    }
+    refs::pack_refs(repo.repo_dir(), all, |oid| {
+        let id = ObjectId::from_hex(oid)?;
+        let peeled = tag::peel(&odb, &id)?;
+        Ok((peeled != id).then(|| peeled.to_hex()))
+    })
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+
+use crate::modules::{
+    refs::{ReflogEntry, read_reflog, resolve_ref},
+    revision::{BadRevision, full_ref_name},
+};
+use crate::repo::Repository;
+
+/// The reflog of the reference `name` (HEAD by default) stands for, newest entry first,
+/// so that entry `n` is what `name@{n}` resolves to.
+///
+/// Note: `name` is looked up like a revision, so `main` reads `logs/refs/heads/main`. A
+/// reference without a log has no entries; one that doesn't exist is a `BadRevision`.
+pub fn reflog(repo: &Repository, name: Option<&str>) -> Result<Vec<ReflogEntry>> {
+    let full = match name.unwrap_or("HEAD") {
+        "@" => "HEAD".to_string(),
+        "" => resolve_ref(repo.repo_dir(), "HEAD")?.name,
+        name => full_ref_name(repo.repo_dir(), name)
+            .ok_or_else(|| BadRevision::Unknown(name.to_string()))?,
+    };
+    let mut entries = read_reflog(repo.repo_dir(), &full)?;
+    entries.reverse();
+    Ok(entries)
+}
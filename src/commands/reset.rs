@@ -0,0 +1,170 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::commands::{add::is_under, merge::clear_merge_state, update_ref::update_ref};
+use crate::modules::{
+    checkout::{Files, force_check_out},
+    hash::ObjectId,
+    index::{Index, IndexEntry},
+    lockfile::Lockfile,
+    objects::{commit::Commit, tree::Tree},
+    refs::write_detached_ref,
+};
+use crate::repo::Repository;
+
+/// How much `reset` moves along with the branch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Only the branch (`--soft`).
+    Soft,
+    /// The branch and the index (`--mixed`).
+    #[default]
+    Mixed,
+    /// The branch, the index and the working tree (`--hard`).
+    Hard,
+}
+
+/// Where `reset` left HEAD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResetOutcome {
+    pub id: ObjectId,
+    /// The summary line of that commit.
+    pub summary: String,
+    /// The working tree files `--hard` wrote or deleted; empty otherwise.
+    pub updated: Vec<String>,
+}
+
+/// Points HEAD's branch (or a detached HEAD) at the commit `revision` names, then
+/// resets the index, and with `ResetMode::Hard` the working tree, to its tree.
+///
+/// The commit HEAD was at is saved in `ORIG_HEAD` first, and the move goes into the
+/// reflogs as `reset: moving to <revision>`. A mixed reset keeps the stat data of index
+/// entries that don't change, so unchanged files aren't read again. A merge in progress
+/// is forgotten.
+///
+/// Note: A hard reset overwrites local changes without asking; that is what it's for.
+pub fn reset(repo: &Repository, revision: &str, mode: ResetMode) -> Result<ResetOutcome> {
+    let id = repo.resolve_revision(revision)?;
+    let odb = repo.odb()?;
+    let commit = Commit::read(&odb, &id)?;
+    let files = Tree::flatten(&odb, &commit.tree)?;
+
+    // Note: As in `switch`, the branch only moves once the index and working tree are
+    // in place.
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    let updated = match mode {
+        ResetMode::Soft => {
+            if index.entries().iter().any(|entry| entry.stage() != 0) {
+                bail!("Cannot do a soft reset in the middle of a merge.");
+            }
+            Vec::new()
+        }
+        ResetMode::Mixed => {
+            index = index_from_files(&index, &files)?;
+            Vec::new()
+        }
+        ResetMode::Hard => {
+            if repo.work_tree().is_none() {
+                bail!("this operation must be run in a work tree");
+            }
+            force_check_out(repo, &mut index, &files)?
+        }
+    };
+
+    index.write_locked(lock)?;
+
+    let head = repo.head_commit()?;
+    if let Some(head) = &head {
+        write_detached_ref(repo.repo_dir(), "ORIG_HEAD", head)?;
+    }
+    let old = head.map(|id| id.to_hex()).unwrap_or_default();
+    let reason = format!("reset: moving to {}", revision);
+    update_ref(repo, "HEAD", &id.to_hex(), Some(&old), Some(&reason))?;
+    clear_merge_state(repo)?;
+
+    Ok(ResetOutcome {
+        id,
+        summary: commit.summary().to_string(),
+        updated,
+    })
+}
+
+/// Copies the entries of `paths` (relative to `cwd`) in the commit `revision` names, HEAD
+/// by default, back into the index, unstaging their changes, and returns the paths whose
+/// entries changed, sorted. Paths the commit doesn't have are removed from the index.
+///
+/// Note: Neither HEAD nor the working tree changes.
+pub fn reset_paths(
+    repo: &Repository,
+    revision: Option<&str>,
+    cwd: &Path,
+    paths: &[String],
+) -> Result<Vec<String>> {
+    let odb = repo.odb()?;
+    let id = match revision {
+        Some(revision) => Some(repo.resolve_revision(revision)?),
+        None => repo.head_commit()?,
+    };
+    let files = match id {
+        Some(id) => Tree::flatten(&odb, &Commit::read(&odb, &id)?.tree)?,
+        None => Files::new(),
+    };
+    let relatives = paths
+        .iter()
+        .map(|given| repo.work_tree_path(cwd, Path::new(given)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    let matches = |path: &str| relatives.iter().any(|relative| is_under(path, relative));
+    let paths: BTreeSet<String> = index
+        .entries()
+        .iter()
+        .map(|entry| &entry.path)
+        .chain(files.keys())
+        .filter(|path| matches(path))
+        .cloned()
+        .collect();
+
+    let mut changed = Vec::new();
+    for path in paths {
+        let staged = index.get(&path).map(|entry| (entry.mode, entry.oid));
+        let unmerged = index
+            .entries()
+            .iter()
+            .any(|entry| entry.path == path && entry.stage() != 0);
+        let recorded = files.get(&path).copied();
+        if staged == recorded && !unmerged {
+            continue;
+        }
+        match recorded {
+            Some((mode, oid)) => index.add_entry(IndexEntry::new(path.as_str(), mode, oid))?,
+            None => {
+                index.remove(&path);
+            }
+        }
+        changed.push(path);
+    }
+    index.write_locked(lock)?;
+    Ok(changed)
+}
+
+/// An index holding exactly `files`, reusing the entries of `old` that already match so
+/// their stat data carries over.
+fn index_from_files(old: &Index, files: &Files) -> Result<Index> {
+    let mut index = Index::with_version(old.version())?;
+    for (path, (mode, oid)) in files {
+        let entry = match old.get(path) {
+            Some(entry) if (entry.mode, entry.oid) == (*mode, *oid) => entry.clone(),
+            _ => IndexEntry::new(path.as_str(), *mode, *oid),
+        };
+        index
+            .add_entry(entry)
+            .with_context(|| format!("cannot stage '{}'", path))?;
+    }
+    Ok(index)
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::repo::Repository;
+
+/// `path` as an absolute path with symlinks resolved, or as given when it can't be.
+pub fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether `dir` is inside the working tree of `repo`.
+///
+/// Note: The metadata directory is inside the working tree on disk, but like git's
+/// `.git` it doesn't count as part of it; a bare repository has no working tree at all.
+pub fn is_inside_work_tree(repo: &Repository, dir: &Path) -> bool {
+    let Some(work_tree) = repo.work_tree() else {
+        return false;
+    };
+    let dir = absolute(dir);
+    dir.starts_with(absolute(work_tree)) && !dir.starts_with(absolute(repo.repo_dir()))
+}
+
+/// Where `dir` is in the working tree, as `--show-prefix` prints it: `src/deep/` in
+/// `src/deep`, and empty at the top.
+pub fn show_prefix(repo: &Repository, dir: &Path) -> Result<String> {
+    let prefix = repo.work_tree_path(dir, Path::new("."))?;
+    Ok(if prefix.is_empty() {
+        prefix
+    } else {
+        prefix + "/"
+    })
+}
+
+/// The way up from `dir` to the top of the working tree, as `--show-cdup` prints it:
+/// `../../` in `src/deep`, and empty at the top.
+pub fn show_cdup(repo: &Repository, dir: &Path) -> Result<String> {
+    let prefix = repo.work_tree_path(dir, Path::new("."))?;
+    Ok("../".repeat(prefix.split('/').filter(|part| !part.is_empty()).count()))
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::commands::add::is_under;
+use crate::modules::{
+    checkout::remove_work_tree_file,
+    index::{Index, index_mtime, is_unmodified},
+    lockfile::Lockfile,
+    objects::{commit::Commit, tree::Tree},
+};
+use crate::repo::Repository;
+
+/// What `rm` removes besides the index entries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RmOptions {
+    /// Only unstage the files, leaving them in the working tree (`--cached`).
+    pub cached: bool,
+    /// Remove the tracked files under a named directory (`-r`).
+    pub recursive: bool,
+    /// Remove files even when that loses their changes (`-f`).
+    pub force: bool,
+}
+
+/// Unstages the tracked files under `paths` (relative to `cwd`) and deletes them from
+/// the working tree, returning them in index order.
+///
+/// Note: Unless `options.force` is set, nothing is removed if any file has changes that
+/// would be lost: staged changes (unless the file is kept with `cached`) or
+/// modifications in the working tree. Every such file is listed.
+pub fn rm(
+    repo: &Repository,
+    cwd: &Path,
+    paths: &[String],
+    options: &RmOptions,
+) -> Result<Vec<String>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    if paths.is_empty() {
+        bail!("No pathspec was given. Which files should I remove?");
+    }
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+
+    let mut removed = BTreeSet::new();
+    for given in paths {
+        let relative = repo.work_tree_path(cwd, Path::new(given))?;
+        let matched: Vec<&str> = index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .filter(|path| is_under(path, &relative))
+            .collect();
+        if matched.is_empty() {
+            bail!("pathspec '{}' did not match any files", given);
+        }
+        if !options.recursive && matched.iter().any(|path| *path != relative) {
+            bail!("not removing '{}' recursively without -r", given);
+        }
+        removed.extend(matched.into_iter().map(str::to_string));
+    }
+
+    if !options.force {
+        check_removable(repo, work_tree, &index, &removed, options.cached)?;
+    }
+    for path in &removed {
+        index.remove(path);
+        if !options.cached {
+            remove_work_tree_file(work_tree, path)?;
+        }
+    }
+    index.write_locked(lock)?;
+    Ok(removed.into_iter().collect())
+}
+
+/// Fails, listing the files, if removing `paths` would lose changes to any of them.
+fn check_removable(
+    repo: &Repository,
+    work_tree: &Path,
+    index: &Index,
+    paths: &BTreeSet<String>,
+    cached: bool,
+) -> Result<()> {
+    let odb = repo.odb()?;
+    let head_files = match repo.head_commit()? {
+        Some(id) => Tree::flatten(&odb, &Commit::read(&odb, &id)?.tree)?,
+        None => BTreeMap::new(),
+    };
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_mtime = index_mtime(&repo.index_path());
+
+    let (mut both, mut staged, mut local) = (Vec::new(), Vec::new(), Vec::new());
+    for path in paths {
+        // Note: An unmerged path has no single staged version to lose.
+        let Some(entry) = index.get(path) else {
+            continue;
+        };
+        let staged_changes = head_files.get(path) != Some(&(entry.mode, entry.oid));
+        let local_changes = !is_unmodified(work_tree, entry, filemode, index_mtime)?;
+        match (staged_changes, local_changes) {
+            (true, true) => both.push(path.as_str()),
+            (true, false) if !cached => staged.push(path.as_str()),
+            (false, true) if !cached => local.push(path.as_str()),
+            _ => {}
+        }
+    }
+
+    let sections = [
+        (
+            both,
+            "staged content different from both the file and the HEAD",
+            "(use -f to force removal)",
+        ),
+        (
+            staged,
+            "changes staged in the index",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+        (
+            local,
+            "local modifications",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+    ];
+    let mut message = Vec::new();
+    for (files, problem, hint) in sections {
+        if files.is_empty() {
+            continue;
+        }
+        let subject = if files.len() == 1 {
+            "file has"
+        } else {
+            "files have"
+        };
+        message.push(format!("the following {} {}:", subject, problem));
+        message.extend(files.iter().map(|path| format!("    {}", path)));
+        message.push(hint.to_string());
+    }
+    if !message.is_empty() {
+        bail!("{}", message.join("\n"));
+    }
+    Ok(())
+}
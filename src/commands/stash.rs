@@ -0,0 +1,343 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::fs;
+
+use crate::commands::{
+    ls_files::{LsFilesOptions, ls_files},
+    merge::{check_out_for_merge, merge_head},
+    update_ref::reflog_entry,
+};
+use crate::modules::{
+    checkout::{Files, force_check_out, remove_work_tree_file, write_file},
+    diff::max_changed_lines,
+    hash::{ObjectId, ObjectKind},
+    index::{Index, IndexEntry, index_mtime, is_unmodified, working_content, working_mode},
+    lockfile::Lockfile,
+    merge::{Conflict, merge_trees},
+    objects::{
+        commit::Commit,
+        tree::{EntryMode, Tree},
+    },
+    odb::Odb,
+    refs::{self, ExpectedOld, HeadState, append_reflog, read_reflog, write_reflog},
+};
+use crate::repo::Repository;
+
+/// The reference holding the latest stash entry; its reflog is the stack of entries.
+pub const STASH_REF: &str = "refs/stash";
+
+/// One entry of the stash stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StashEntry {
+    /// The commit holding the stashed working tree.
+    pub id: ObjectId,
+    /// What `stash list` shows, e.g. `WIP on main: 1a2b3c4 Add README`.
+    pub message: String,
+}
+
+/// What applying a stash entry did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyOutcome {
+    /// The paths whose content was merged line by line.
+    pub auto_merged: Vec<String>,
+    /// Paths left conflicted, as `merge` leaves them.
+    pub conflicts: Vec<Conflict>,
+    /// The entry `stash_pop` dropped; `None` when it was kept.
+    pub dropped: Option<ObjectId>,
+}
+
+/// Saves the local changes of `repo` as a new stash entry and resets the index and
+/// working tree to HEAD. Returns the entry's message, or `None` when there was nothing
+/// to save.
+///
+/// As in git, an entry is a commit of the working tree whose parents are HEAD and a
+/// commit of the index. With `include_untracked`, untracked files that aren't ignored go
+/// into a third, parentless commit and are removed from the working tree.
+///
+/// Note: Entries are stacked in the reflog of `refs/stash`, which is always kept.
+pub fn stash_push(
+    repo: &Repository,
+    message: Option<&str>,
+    include_untracked: bool,
+) -> Result<Option<String>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let Some(head) = repo.head_commit()? else {
+        bail!("You do not have the initial commit yet");
+    };
+    let odb = repo.odb()?;
+    let head_commit = Commit::read(&odb, &head)?;
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    if let Some(entry) = index.entries().iter().find(|entry| entry.stage() != 0) {
+        bail!("cannot stash: '{}' is unmerged", entry.path);
+    }
+    let index_tree = index.write_tree(&odb)?;
+    let work_tree_tree = work_tree_index(repo, &odb, &index)?.write_tree(&odb)?;
+    let untracked = if include_untracked {
+        let options = LsFilesOptions {
+            others: true,
+            exclude_standard: true,
+            ..LsFilesOptions::default()
+        };
+        ls_files(repo, &options)?
+            .into_iter()
+            .map(|listed| listed.path)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if index_tree == head_commit.tree && work_tree_tree == index_tree && untracked.is_empty() {
+        return Ok(None);
+    }
+
+    let branch = match repo.head_ref()? {
+        Some(HeadState::Branch(name)) => name,
+        _ => "(no branch)".to_string(),
+    };
+    let on = format!("{}: {} {}", branch, head.short(7), head_commit.summary());
+    let config = repo.config()?;
+    let (author, committer) = (config.author()?, config.committer()?);
+    let write_commit = |tree: ObjectId, parents: Vec<ObjectId>, message: String| {
+        let commit = Commit {
+            tree,
+            parents,
+            author: author.clone(),
+            committer: committer.clone(),
+            extra_headers: Vec::new(),
+            message: message + "\n",
+        };
+        odb.write(ObjectKind::Commit, &commit.serialize())
+    };
+
+    let index_commit = write_commit(index_tree, vec![head], format!("index on {}", on))?;
+    let mut parents = vec![head, index_commit];
+    if !untracked.is_empty() {
+        let mut files = Index::default();
+        for path in &untracked {
+            let full = work_tree.join(path);
+            let metadata = fs::symlink_metadata(&full)
+                .with_context(|| format!("Failed to read {:?}", full))?;
+            let mode = working_mode(&metadata, true, None);
+            let oid = odb.write(ObjectKind::Blob, &working_content(&full, mode)?)?;
+            files.add_entry(IndexEntry::new(path.as_str(), mode, oid))?;
+        }
+        let tree = files.write_tree(&odb)?;
+        parents.push(write_commit(
+            tree,
+            Vec::new(),
+            format!("untracked files on {}", on),
+        )?);
+    }
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", on),
+    };
+    let id = write_commit(work_tree_tree, parents, message.clone())?;
+
+    let old = refs::update_ref(repo.repo_dir(), STASH_REF, &id, &ExpectedOld::Any)?;
+    let entry = reflog_entry(
+        &committer,
+        old.as_deref(),
+        Some(&id.to_hex()),
+        Some(&message),
+    );
+    append_reflog(repo.repo_dir(), STASH_REF, &entry)?;
+
+    let head_files = Tree::flatten(&odb, &head_commit.tree)?;
+    force_check_out(repo, &mut index, &head_files)?;
+    index.write_locked(lock)?;
+    for path in &untracked {
+        remove_work_tree_file(work_tree, path)?;
+    }
+    Ok(Some(message))
+}
+
+/// The stash entries, newest first, so that entry `n` is `stash@{n}`.
+pub fn stash_list(repo: &Repository) -> Result<Vec<StashEntry>> {
+    let mut entries = read_reflog(repo.repo_dir(), STASH_REF)?;
+    entries.reverse();
+    entries
+        .into_iter()
+        .map(|entry| {
+            Ok(StashEntry {
+                id: ObjectId::from_hex(&entry.new_oid)
+                    .with_context(|| format!("the reflog of {} is broken", STASH_REF))?,
+                message: entry.message,
+            })
+        })
+        .collect()
+}
+
+/// Applies the changes of stash entry `n` to the working tree, keeping the entry.
+///
+/// The entry's working tree is merged into the index's files against the commit it was
+/// made on, as `merge` would, with conflicts between `Updated upstream` and `Stashed
+/// changes` left in the working tree and the index. Changes come back unstaged, except
+/// new files, which stay added; untracked files saved with the entry come back as they
+/// were.
+///
+/// Note: Like a merge, this refuses to overwrite local changes, and stops before
+/// changing anything if an untracked file to restore is already there.
+pub fn stash_apply(repo: &Repository, n: usize) -> Result<ApplyOutcome> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    if merge_head(repo)?.is_some() {
+        bail!("Cannot apply a stash in the middle of a merge");
+    }
+    let odb = repo.odb()?;
+    let id = stash_id(repo, n)?;
+    let stash = Commit::read(&odb, &id)?;
+    let [base, _, untracked @ ..] = stash.parents.as_slice() else {
+        bail!("'{}' is not a stash-like commit", id);
+    };
+    let base_files = Tree::flatten(&odb, &Commit::read(&odb, base)?.tree)?;
+    let stash_files = Tree::flatten(&odb, &stash.tree)?;
+    let untracked_files = match untracked.first() {
+        Some(id) => Tree::flatten(&odb, &Commit::read(&odb, id)?.tree)?,
+        None => Files::new(),
+    };
+
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    if let Some(entry) = index.entries().iter().find(|entry| entry.stage() != 0) {
+        bail!("cannot apply a stash: '{}' is unmerged", entry.path);
+    }
+    let in_the_way: Vec<&String> = untracked_files
+        .keys()
+        .filter(|path| fs::symlink_metadata(work_tree.join(path)).is_ok())
+        .collect();
+    if let Some(path) = in_the_way.first() {
+        bail!(
+            "{} already exists, no checkout\ncould not restore untracked files from stash",
+            path
+        );
+    }
+
+    let previous = index.clone();
+    let ours: Files = previous
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.oid)))
+        .collect();
+    let merged = merge_trees(
+        &odb,
+        &base_files,
+        &ours,
+        &stash_files,
+        ("Updated upstream", "Stashed changes"),
+        max_changed_lines(&repo.config()?, "merge.maxChangedLines")?,
+    )?;
+    check_out_for_merge(repo, &mut index, &ours, &merged.files)?;
+
+    // Note: What was staged stays staged, so the stashed changes show up as unstaged;
+    // only files new to the index keep the entry the checkout gave them.
+    let conflicted: BTreeSet<&str> = merged.conflicts.iter().map(|c| c.path.as_str()).collect();
+    for entry in previous.entries() {
+        if !conflicted.contains(entry.path.as_str()) {
+            index.add_entry(entry.clone())?;
+        }
+    }
+    for conflict in &merged.conflicts {
+        index.remove(&conflict.path);
+        let stages = [conflict.base, conflict.ours, conflict.theirs];
+        for (stage, version) in (1..).zip(stages) {
+            if let Some((mode, oid)) = version {
+                index.add_entry(
+                    IndexEntry::new(conflict.path.as_str(), mode, oid).with_stage(stage),
+                )?;
+            }
+        }
+    }
+    index.write_locked(lock)?;
+    for (path, (mode, oid)) in &untracked_files {
+        write_file(&odb, work_tree, path, *mode, oid)?;
+    }
+
+    Ok(ApplyOutcome {
+        auto_merged: merged.auto_merged,
+        conflicts: merged.conflicts,
+        dropped: None,
+    })
+}
+
+/// Applies stash entry `n` as `stash_apply` does, then drops it if that left no
+/// conflicts.
+pub fn stash_pop(repo: &Repository, n: usize) -> Result<ApplyOutcome> {
+    let mut outcome = stash_apply(repo, n)?;
+    if outcome.conflicts.is_empty() {
+        outcome.dropped = Some(stash_drop(repo, n)?);
+    }
+    Ok(outcome)
+}
+
+/// Removes stash entry `n` from the stack and returns its commit. Dropping the last
+/// entry deletes `refs/stash` and its reflog.
+pub fn stash_drop(repo: &Repository, n: usize) -> Result<ObjectId> {
+    let repo_dir = repo.repo_dir();
+    let id = stash_id(repo, n)?;
+    let mut entries = read_reflog(repo_dir, STASH_REF)?;
+    entries.remove(entries.len() - 1 - n);
+
+    let Some(top) = entries.last() else {
+        refs::delete_ref(repo_dir, STASH_REF, &ExpectedOld::Oid(id))?;
+        let log_path = repo_dir.join("logs").join(STASH_REF);
+        fs::remove_file(&log_path).with_context(|| format!("Failed to remove {:?}", log_path))?;
+        return Ok(id);
+    };
+    if n == 0 {
+        let top = ObjectId::from_hex(&top.new_oid)
+            .with_context(|| format!("the reflog of {} is broken", STASH_REF))?;
+        refs::update_ref(repo_dir, STASH_REF, &top, &ExpectedOld::Oid(id))?;
+    }
+    write_reflog(repo_dir, STASH_REF, &entries)?;
+    Ok(id)
+}
+
+/// The commit of stash entry `n`; fails if there's no such entry.
+fn stash_id(repo: &Repository, n: usize) -> Result<ObjectId> {
+    let entries = stash_list(repo)?;
+    match entries.get(n) {
+        Some(entry) => Ok(entry.id),
+        None if entries.is_empty() => bail!("No stash entries found."),
+        None => bail!("stash@{{{}}} is not a valid reference", n),
+    }
+}
+
+/// `index` with each entry's content replaced by what's in the working tree, and files
+/// missing from it left out: the tree a stash entry records.
+fn work_tree_index(repo: &Repository, odb: &Odb, index: &Index) -> Result<Index> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_mtime = index_mtime(&repo.index_path());
+
+    let mut result = Index::default();
+    for entry in index.entries() {
+        let full = work_tree.join(&entry.path);
+        // Note: A submodule's checkout isn't looked into, and a directory where a file
+        // was means the file is gone.
+        if entry.mode == EntryMode::Commit {
+            result.add_entry(entry.clone())?;
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(&full) {
+            Ok(metadata) if !metadata.is_dir() => metadata,
+            _ => continue,
+        };
+        if is_unmodified(work_tree, entry, filemode, index_mtime)? {
+            result.add_entry(entry.clone())?;
+            continue;
+        }
+        let mode = working_mode(&metadata, filemode, Some(entry));
+        let oid = odb.write(ObjectKind::Blob, &working_content(&full, mode)?)?;
+        result.add_entry(IndexEntry::new(entry.path.as_str(), mode, oid))?;
+    }
+    Ok(result)
+}
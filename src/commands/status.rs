@@ -0,0 +1,417 @@
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::commands::merge::merge_head;
+use crate::modules::{
+    constants::is_repository_dir_name,
+    files::is_embedded_repository,
+    hash::{ObjectId, ObjectKind, hash_object},
+    ignore::IgnoreMatcher,
+    index::{Index, IndexEntry, index_mtime, working_content, working_mode},
+    objects::{
+        commit::Commit,
+        tree::{EntryMode, Tree},
+    },
+    refs::HeadState,
+};
+use crate::repo::Repository;
+
+/// How a path differs between two of HEAD, the index and the working tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// A file became a symlink or the other way round.
+    TypeChanged,
+}
+
+impl Change {
+    /// The label `status` shows, as git words it.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Change::Added => "new file",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+            Change::TypeChanged => "typechange",
+        }
+    }
+
+    /// The letter short listings show, as in `status --short`: `A`, `M`, `D` or `T`.
+    pub fn code(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+            Change::TypeChanged => "typechange",
+        }
+    }
+}
+
+/// How a conflicted path stands after a merge, from which of its base, our and their
+/// versions (index stages 1-3) exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmerged {
+    BothModified,
+    BothAdded,
+    BothDeleted,
+    AddedByUs,
+    AddedByThem,
+    DeletedByUs,
+    DeletedByThem,
+}
+
+impl Unmerged {
+    /// The state of a path with the given stages, or `None` without any.
+    pub fn from_stages(base: bool, ours: bool, theirs: bool) -> Option<Self> {
+        Some(match (base, ours, theirs) {
+            (true, true, true) => Unmerged::BothModified,
+            (false, true, true) => Unmerged::BothAdded,
+            (true, false, false) => Unmerged::BothDeleted,
+            (false, true, false) => Unmerged::AddedByUs,
+            (false, false, true) => Unmerged::AddedByThem,
+            (true, false, true) => Unmerged::DeletedByUs,
+            (true, true, false) => Unmerged::DeletedByThem,
+            (false, false, false) => return None,
+        })
+    }
+
+    /// The label `status` shows, as git words it.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Unmerged::BothModified => "both modified",
+            Unmerged::BothAdded => "both added",
+            Unmerged::BothDeleted => "both deleted",
+            Unmerged::AddedByUs => "added by us",
+            Unmerged::AddedByThem => "added by them",
+            Unmerged::DeletedByUs => "deleted by us",
+            Unmerged::DeletedByThem => "deleted by them",
+        }
+    }
+
+    /// The two letters `status --short` shows, as git writes them: `UU` for both
+    /// modified, `AA` for both added, `DU` for deleted by us, and so on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Unmerged::BothModified => "UU",
+            Unmerged::BothAdded => "AA",
+            Unmerged::BothDeleted => "DD",
+            Unmerged::AddedByUs => "AU",
+            Unmerged::AddedByThem => "UA",
+            Unmerged::DeletedByUs => "DU",
+            Unmerged::DeletedByThem => "UD",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Unmerged::BothModified => "both-modified",
+            Unmerged::BothAdded => "both-added",
+            Unmerged::BothDeleted => "both-deleted",
+            Unmerged::AddedByUs => "added-by-us",
+            Unmerged::AddedByThem => "added-by-them",
+            Unmerged::DeletedByUs => "deleted-by-us",
+            Unmerged::DeletedByThem => "deleted-by-them",
+        }
+    }
+}
+
+/// The state of a working tree, as `status` reports it. Paths are `/`-separated from
+/// the top of the working tree, in index order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub head: Option<HeadState>,
+    /// The commit HEAD resolves to; `None` on an unborn branch.
+    pub head_commit: Option<ObjectId>,
+    /// Differences between HEAD's tree and the index.
+    pub staged: Vec<(String, Change)>,
+    /// Differences between the index and the working tree.
+    pub unstaged: Vec<(String, Change)>,
+    /// Paths a merge left conflicted, sorted; they're in neither list above.
+    pub unmerged: Vec<(String, Unmerged)>,
+    /// Whether a merge is waiting to be committed (`MERGE_HEAD` exists).
+    pub merging: bool,
+    /// Files neither tracked nor ignored; a directory holding only such files is
+    /// listed once, as `dir/`.
+    pub untracked: Vec<String>,
+    /// The entries of `untracked` that are embedded repositories themselves, which
+    /// `add` won't stage.
+    pub embedded: Vec<String>,
+}
+
+/// Compares HEAD's tree, the index and the working tree of `repo`.
+///
+/// Note: Files whose stat data matches their index entry aren't read; only racily
+/// clean entries and files whose stat data changed are hashed (without being stored).
+pub fn status(repo: &Repository) -> Result<Status> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let odb = repo.odb()?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_path = repo.index_path();
+    let index = Index::read(&index_path)?;
+    let staged_entries: BTreeMap<&str, &IndexEntry> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage() == 0)
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+    let mut stages: BTreeMap<&str, [bool; 3]> = BTreeMap::new();
+    for entry in index.entries().iter().filter(|entry| entry.stage() != 0) {
+        stages.entry(&entry.path).or_default()[entry.stage() as usize - 1] = true;
+    }
+    let unmerged: Vec<(String, Unmerged)> = stages
+        .iter()
+        .filter_map(|(path, [base, ours, theirs])| {
+            Some((
+                path.to_string(),
+                Unmerged::from_stages(*base, *ours, *theirs)?,
+            ))
+        })
+        .collect();
+
+    let head_commit = repo.head_commit()?;
+    let head_files = match &head_commit {
+        Some(id) => Tree::flatten(&odb, &Commit::read(&odb, id)?.tree)?,
+        None => BTreeMap::new(),
+    };
+    let mut staged = Vec::new();
+    let paths: BTreeSet<&str> = head_files
+        .keys()
+        .map(String::as_str)
+        .chain(staged_entries.keys().copied())
+        .filter(|path| !stages.contains_key(path))
+        .collect();
+    for path in paths {
+        let change = match (head_files.get(path), staged_entries.get(path)) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Deleted,
+            (Some((mode, oid)), Some(entry)) if (*mode, *oid) != (entry.mode, entry.oid) => {
+                change_between(*mode, entry.mode)
+            }
+            _ => continue,
+        };
+        staged.push((path.to_string(), change));
+    }
+
+    let index_mtime = index_mtime(&index_path);
+    let mut unstaged = Vec::new();
+    for entry in staged_entries.values() {
+        // Note: Submodules aren't looked into; their directory is all there is to see.
+        if entry.mode == EntryMode::Commit {
+            continue;
+        }
+        let full = work_tree.join(&entry.path);
+        let metadata = match fs::symlink_metadata(&full) {
+            Ok(metadata) if !metadata.is_dir() => metadata,
+            _ => {
+                unstaged.push((entry.path.clone(), Change::Deleted));
+                continue;
+            }
+        };
+        let mode = working_mode(&metadata, filemode, Some(entry));
+        if mode == entry.mode
+            && entry.stat_matches(&metadata)
+            && index_mtime.is_some_and(|index_mtime| !entry.is_racy(index_mtime))
+        {
+            continue;
+        }
+        let oid = hash_object(ObjectKind::Blob, &working_content(&full, mode)?);
+        if (mode, oid) != (entry.mode, entry.oid) {
+            unstaged.push((entry.path.clone(), change_between(entry.mode, mode)));
+        }
+    }
+
+    let matcher = IgnoreMatcher::for_repo(repo)?;
+    let excluded = |relative: &str, is_dir: bool| matcher.is_ignored(relative, is_dir);
+    let tracked_dirs: HashSet<&str> = index
+        .entries()
+        .iter()
+        .flat_map(|entry| {
+            let path = entry.path.as_str();
+            path.match_indices('/').map(move |(i, _)| &path[..i])
+        })
+        .collect();
+    let tracked = Tracked {
+        files: index.entries().iter().map(|e| e.path.as_str()).collect(),
+        dirs: tracked_dirs,
+    };
+    let mut untracked = Vec::new();
+    let mut embedded = Vec::new();
+    collect_untracked(
+        work_tree,
+        "",
+        &tracked,
+        &excluded,
+        &mut untracked,
+        &mut embedded,
+    )?;
+    untracked.sort();
+    embedded.sort();
+
+    Ok(Status {
+        head: repo.head_ref()?,
+        head_commit,
+        staged,
+        unstaged,
+        unmerged,
+        merging: merge_head(repo)?.is_some(),
+        untracked,
+        embedded,
+    })
+}
+
+/// The change from a path recorded with `old` to the same path recorded with `new`.
+fn change_between(old: EntryMode, new: EntryMode) -> Change {
+    let is_link = |mode| mode == EntryMode::Symlink;
+    if is_link(old) != is_link(new) {
+        Change::TypeChanged
+    } else {
+        Change::Modified
+    }
+}
+
+/// The paths in the index, and every directory above them.
+struct Tracked<'a> {
+    files: HashSet<&'a str>,
+    dirs: HashSet<&'a str>,
+}
+
+/// Collects the untracked, unignored paths under `dir` (at `relative`) into `found`,
+/// and those of them that are embedded repositories into `embedded` too.
+///
+/// Note: An embedded repository is listed as `dir/` without looking inside, unless the
+/// index tracks it (a submodule) or files in it.
+fn collect_untracked(
+    dir: &Path,
+    relative: &str,
+    tracked: &Tracked,
+    excluded: &dyn Fn(&str, bool) -> bool,
+    found: &mut Vec<String>,
+    embedded: &mut Vec<String>,
+) -> Result<()> {
+    for (name, path, is_dir) in read_children(dir)? {
+        let child = if relative.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        // Note: A tracked file replaced by a directory (or the other way round) is
+        // untracked in its new form.
+        if is_dir && tracked.dirs.contains(child.as_str()) {
+            collect_untracked(&path, &child, tracked, excluded, found, embedded)?;
+        } else if is_dir && is_embedded_repository(&path) {
+            if !tracked.files.contains(child.as_str()) && !excluded(&child, true) {
+                found.push(format!("{}/", child));
+                embedded.push(format!("{}/", child));
+            }
+        } else if (!is_dir && tracked.files.contains(child.as_str())) || excluded(&child, is_dir) {
+            continue;
+        } else if !is_dir {
+            found.push(child);
+        } else if has_unignored_files(&path, &child, excluded)? {
+            found.push(format!("{}/", child));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the untracked directory `dir` holds any file that isn't ignored, which is
+/// what makes git list it.
+fn has_unignored_files(
+    dir: &Path,
+    relative: &str,
+    excluded: &dyn Fn(&str, bool) -> bool,
+) -> Result<bool> {
+    for (name, path, is_dir) in read_children(dir)? {
+        let child = format!("{}/{}", relative, name);
+        if excluded(&child, is_dir) {
+            continue;
+        }
+        if !is_dir || is_embedded_repository(&path) || has_unignored_files(&path, &child, excluded)?
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The entries of `dir` other than metadata directories, ours or git's: name, path and
+/// whether it's a directory (symlinks to directories aren't).
+fn read_children(dir: &Path) -> Result<Vec<(String, std::path::PathBuf, bool)>> {
+    let mut children = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", dir))?;
+        let name = entry.file_name();
+        if is_repository_dir_name(&name) {
+            continue;
+        }
+        let Some(name) = name.to_str() else {
+            bail!("path {:?} is not valid UTF-8", entry.path());
+        };
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("Failed to read {:?}", entry.path()))?;
+        children.push((name.to_string(), entry.path(), file_type.is_dir()));
+    }
+    Ok(children)
+}
+
+/// `path` (from the top of the working tree) as seen from the directory `base`, also
+/// from the top: `src/main.rs` from `src` is `main.rs`, and `README.md` is `../README.md`.
+pub fn relative_to(path: &str, base: &str) -> String {
+    if base.is_empty() {
+        return path.to_string();
+    }
+    let parts: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let base_parts: Vec<&str> = base.split('/').collect();
+    let common = parts
+        .iter()
+        .zip(&base_parts)
+        .take_while(|(part, base_part)| part == base_part)
+        .count();
+
+    let mut relative = vec![".."; base_parts.len() - common];
+    relative.extend(&parts[common..]);
+    let mut relative = relative.join("/");
+    if relative.is_empty() {
+        relative.push('.');
+    }
+    if path.ends_with('/') {
+        relative.push('/');
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_to() {
+        let cases = [
+            ("src/main.rs", "", "src/main.rs"),
+            ("src/main.rs", "src", "main.rs"),
+            ("README.md", "src", "../README.md"),
+            ("src/lib/mod.rs", "src/bin", "../lib/mod.rs"),
+            ("docs/", "src/lib", "../../docs/"),
+            ("src/", "src", "./"),
+            ("srcx/a", "src", "../srcx/a"),
+        ];
+        for (path, base, expected) in cases {
+            assert_eq!(relative_to(path, base), expected, "{} from {}", path, base);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{Context, Result, bail};
+
+use crate::commands::{
+    branch::{check_new_branch, create_branch},
+    symbolic_ref::short_name,
+    update_ref::{logged_names, reflog_entry},
+};
+use crate::modules::{
+    checkout::{Files, check_out},
+    hash::ObjectId,
+    lockfile::Lockfile,
+    objects::{commit::Commit, tree::Tree},
+    refs::{HeadState, RefValue, append_reflog, read_ref, write_detached_ref, write_symbolic_ref},
+};
+use crate::repo::Repository;
+
+/// Where `switch` moves HEAD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// An existing branch, by name.
+    Branch(&'a str),
+    /// A branch created at `start` (HEAD by default) before switching to it (`-c`).
+    NewBranch {
+        name: &'a str,
+        start: Option<&'a str>,
+    },
+    /// The commit a revision names, with HEAD detached at it (`--detach`).
+    Detached(&'a str),
+}
+
+/// Where HEAD ended up after `switch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwitchOutcome {
+    pub head: HeadState,
+    /// The commit checked out; `None` on an unborn branch.
+    pub id: Option<ObjectId>,
+    /// The summary line of that commit.
+    pub summary: Option<String>,
+    /// Whether the branch was created for the switch.
+    pub created: bool,
+    /// Whether HEAD was already there, so nothing changed.
+    pub unchanged: bool,
+}
+
+/// Checks out `target` in the working tree and index of `repo` and points HEAD at it.
+///
+/// Files are only written where HEAD's tree and the target's differ; local changes to
+/// them stop the switch before anything is touched (see `check_out`), and other local
+/// changes are kept. The move goes into HEAD's reflog as `checkout: moving from A to B`.
+pub fn switch(repo: &Repository, target: &Target) -> Result<SwitchOutcome> {
+    if repo.work_tree().is_none() {
+        bail!("this operation must be run in a work tree");
+    }
+    let repo_dir = repo.repo_dir();
+    let current = repo.head_ref()?;
+    let head = repo.head_commit()?;
+
+    let (new_head, id) = match *target {
+        Target::Branch(name) => {
+            let full = format!("refs/heads/{}", name);
+            if current == Some(HeadState::Branch(name.to_string())) {
+                return Ok(SwitchOutcome {
+                    head: HeadState::Branch(name.to_string()),
+                    id: head,
+                    summary: None,
+                    created: false,
+                    unchanged: true,
+                });
+            }
+            let id = match read_ref(repo_dir, &full) {
+                Ok(Some(RefValue::Direct(oid))) => ObjectId::from_hex(&oid)
+                    .with_context(|| format!("reference {} is broken", full))?,
+                _ if repo.resolve_revision(name).is_ok() => bail!(
+                    "a branch is expected, got '{}'\nhint: To switch to a commit without a branch, use 'cs01 switch --detach {}'",
+                    name,
+                    name
+                ),
+                _ => bail!("invalid reference: {}", name),
+            };
+            (HeadState::Branch(name.to_string()), Some(id))
+        }
+        Target::NewBranch { name, start } => {
+            check_new_branch(repo, name)?;
+            let id = match start {
+                Some(start) => Some(repo.resolve_revision(start)?),
+                None => head,
+            };
+            (HeadState::Branch(name.to_string()), id)
+        }
+        Target::Detached(revision) => {
+            let id = repo.resolve_revision(revision)?;
+            (HeadState::Detached(id.to_hex()), Some(id))
+        }
+    };
+
+    let odb = repo.odb()?;
+    let files_of = |id: Option<ObjectId>| -> Result<(Files, Option<Commit>)> {
+        match id {
+            Some(id) => {
+                let commit = Commit::read(&odb, &id)?;
+                Ok((Tree::flatten(&odb, &commit.tree)?, Some(commit)))
+            }
+            None => Ok((Files::new(), None)),
+        }
+    };
+    let (old, _) = files_of(head)?;
+    let (new, commit) = files_of(id)?;
+
+    // Note: The index stays locked from reading it to writing it back, and HEAD only
+    // moves once both it and the working tree are in place.
+    let index_path = repo.index_path();
+    let lock = Lockfile::acquire(&index_path)?;
+    let mut index = repo.read_index()?;
+    check_out(repo, &mut index, &old, &new)?;
+    index.write_locked(lock)?;
+
+    let from = match &current {
+        Some(HeadState::Branch(name)) => name.clone(),
+        Some(HeadState::OtherRef(name)) => short_name(name).to_string(),
+        Some(HeadState::Detached(oid)) => oid.clone(),
+        None => "HEAD".to_string(),
+    };
+    let created = matches!(target, Target::NewBranch { .. });
+    if let Target::NewBranch { name, start } = *target
+        && id.is_some()
+    {
+        create_branch(repo, name, start)?;
+    }
+    let to = match &new_head {
+        HeadState::Branch(name) | HeadState::OtherRef(name) | HeadState::Detached(name) => name,
+    };
+    move_head(
+        repo,
+        &new_head,
+        head,
+        id,
+        &format!("checkout: moving from {} to {}", from, to),
+    )?;
+
+    Ok(SwitchOutcome {
+        head: new_head,
+        id,
+        summary: commit.map(|commit| commit.summary().to_string()),
+        created,
+        unchanged: false,
+    })
+}
+
+/// Points HEAD at `head` and records the move from `old` to `new` in HEAD's reflog.
+fn move_head(
+    repo: &Repository,
+    head: &HeadState,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    message: &str,
+) -> Result<()> {
+    // Note: Looked up first, so a missing identity fails before HEAD moves.
+    let identity = if logged_names(repo, "HEAD")?
+        .iter()
+        .any(|name| name == "HEAD")
+    {
+        Some(repo.config()?.committer()?)
+    } else {
+        None
+    };
+
+    match head {
+        HeadState::Branch(name) => {
+            write_symbolic_ref(repo.repo_dir(), "HEAD", &format!("refs/heads/{}", name))?
+        }
+        HeadState::OtherRef(name) => write_symbolic_ref(repo.repo_dir(), "HEAD", name)?,
+        HeadState::Detached(oid) => {
+            write_detached_ref(repo.repo_dir(), "HEAD", &ObjectId::from_hex(oid)?)?
+        }
+    }
+
+    // Note: A move to an unborn branch has no commit to record.
+    if let (Some(identity), Some(new)) = (identity, new) {
+        let old = old.map(|id| id.to_hex());
+        let entry = reflog_entry(
+            &identity,
+            old.as_deref(),
+            Some(&new.to_hex()),
+            Some(message),
+        );
+        append_reflog(repo.repo_dir(), "HEAD", &entry)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use anyhow::{Result, bail};
+
+use crate::commands::update_ref::{logged_names, reflog_entry};
+use crate::modules::refs::{
+    self, RefValue, append_reflog, check_symbolic_target, read_ref, resolve_ref, set_aside_reflog,
+    write_symbolic_ref,
+};
+use crate::repo::Repository;
+
+/// The reference `name` points at, or `None` when it isn't a symbolic ref.
+///
+/// With `recurse`, a target that is itself symbolic is followed to the last reference
+/// in the chain, as git does unless given `--no-recurse`.
+pub fn read_symbolic_ref(repo: &Repository, name: &str, recurse: bool) -> Result<Option<String>> {
+    let target = match read_ref(repo.repo_dir(), name)? {
+        Some(RefValue::Symbolic(target)) => target,
+        Some(RefValue::Direct(_)) | None => return Ok(None),
+    };
+    if !recurse {
+        return Ok(Some(target));
+    }
+    Ok(Some(resolve_ref(repo.repo_dir(), name)?.name))
+}
+
+/// Deletes the symbolic ref `name` itself, leaving the reference it points at alone,
+/// and sets its reflog aside in `logs/deleted/`.
+///
+/// Note: Like git, this refuses to delete `HEAD`, without which the repository
+/// wouldn't be recognized anymore.
+pub fn delete_symbolic_ref(repo: &Repository, name: &str) -> Result<()> {
+    if name == "HEAD" {
+        bail!("deleting '{}' is not allowed", name);
+    }
+    refs::delete_symbolic_ref(repo.repo_dir(), name)?;
+    set_aside_reflog(repo.repo_dir(), name)
+}
+
+/// Points the symbolic ref `name` at `target`, which must be under `refs/`.
+///
+/// Note: When the switch changes what `name` resolves to, the move is recorded in its
+/// reflog, as git does for e.g. `HEAD` moving between branches. An unborn side is
+/// recorded as the null id.
+pub fn set_symbolic_ref(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    message: Option<&str>,
+) -> Result<()> {
+    check_symbolic_target(name, target)?;
+    let repo_dir = repo.repo_dir();
+    let oid_of = |name: &str| {
+        resolve_ref(repo_dir, name)
+            .ok()
+            .and_then(|resolved| resolved.oid)
+    };
+    let (before, after) = (oid_of(name), oid_of(target));
+    // Note: Looked up first, so a missing identity fails before anything changes,
+    // and only when there is a move to record.
+    let logged = before != after && logged_names(repo, name)?.iter().any(|n| n == name);
+    let identity = if logged {
+        Some(repo.config()?.committer()?)
+    } else {
+        None
+    };
+
+    write_symbolic_ref(repo_dir, name, target)?;
+
+    if let Some(identity) = identity {
+        let entry = reflog_entry(&identity, before.as_deref(), after.as_deref(), message);
+        append_reflog(repo_dir, name, &entry)?;
+    }
+    Ok(())
+}
+
+/// `target` without its `refs/heads/`, `refs/tags/`, `refs/remotes/` or `refs/` prefix.
+pub fn short_name(target: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| target.strip_prefix(prefix))
+        .unwrap_or(target)
+}
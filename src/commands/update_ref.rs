@@ -0,0 +1,123 @@
+use anyhow::{Result, bail};
+
+use crate::commands::cat_file::read;
+use crate::modules::{
+    config::Identity,
+    hash::ObjectKind,
+    refs::{
+        self, ExpectedOld, ReflogEntry, append_reflog, resolve_ref, set_aside_reflog,
+        should_log_ref,
+    },
+};
+use crate::repo::Repository;
+
+/// The id reflogs record for a reference that doesn't exist.
+const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+/// Points `name` (or the branch it refers to) at the object `new`, if it currently
+/// holds `old`, and returns the name that was updated and the id it held before.
+///
+/// Note: Like git, the new object must exist, and only commits can go on branches.
+pub fn update_ref(
+    repo: &Repository,
+    name: &str,
+    new: &str,
+    old: Option<&str>,
+    message: Option<&str>,
+) -> Result<(String, Option<String>)> {
+    let new = repo.resolve_revision(new)?;
+    let expected = parse_expected(repo, old)?;
+    let target = resolve_ref(repo.repo_dir(), name)?.name;
+    let (kind, _) = read(&repo.odb()?, &new)?;
+    if target.starts_with("refs/heads/") && kind != ObjectKind::Commit {
+        bail!(
+            "trying to write non-commit object {} to branch '{}'",
+            new,
+            target
+        );
+    }
+
+    // Note: The identity is looked up before the update, so a missing one can't leave
+    // a moved ref without its reflog entry.
+    let logged = logged_names(repo, &target)?;
+    let identity = if logged.is_empty() {
+        None
+    } else {
+        Some(repo.config()?.committer()?)
+    };
+
+    let previous = refs::update_ref(repo.repo_dir(), &target, &new, &expected)?;
+    if let Some(identity) = identity {
+        let entry = reflog_entry(&identity, previous.as_deref(), Some(&new.to_hex()), message);
+        for name in &logged {
+            append_reflog(repo.repo_dir(), name, &entry)?;
+        }
+    }
+    Ok((target, previous))
+}
+
+/// Deletes `name` (or the branch it refers to) if it currently holds `old`, and returns
+/// the name that was deleted and the id it held. Its reflog is set aside in
+/// `logs/deleted/`.
+pub fn delete_ref(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+) -> Result<(String, Option<String>)> {
+    let expected = parse_expected(repo, old)?;
+    let target = resolve_ref(repo.repo_dir(), name)?.name;
+    let previous = refs::delete_ref(repo.repo_dir(), &target, &expected)?;
+    if previous.is_none() {
+        bail!("cannot delete ref '{}': it does not exist", target);
+    }
+
+    set_aside_reflog(repo.repo_dir(), &target)?;
+    Ok((target, previous))
+}
+
+/// Reads the old-value argument: absent means any value, and an empty string or the
+/// null id means the reference must not exist yet.
+pub fn parse_expected(repo: &Repository, old: Option<&str>) -> Result<ExpectedOld> {
+    match old {
+        None => Ok(ExpectedOld::Any),
+        Some(old) if old.is_empty() || old == NULL_OID => Ok(ExpectedOld::Missing),
+        Some(old) => Ok(ExpectedOld::Oid(repo.resolve_revision(old)?)),
+    }
+}
+
+/// The reflogs an update of `target` goes into: its own, and HEAD's when HEAD is on it.
+pub fn logged_names(repo: &Repository, target: &str) -> Result<Vec<String>> {
+    let log_all = repo
+        .config()?
+        .get_bool("core.logallrefupdates")?
+        .unwrap_or(!repo.is_bare());
+
+    let mut names = Vec::new();
+    if should_log_ref(repo.repo_dir(), target, log_all) {
+        names.push(target.to_string());
+    }
+    if target != "HEAD"
+        && resolve_ref(repo.repo_dir(), "HEAD").is_ok_and(|head| head.name == target)
+        && should_log_ref(repo.repo_dir(), "HEAD", log_all)
+    {
+        names.push("HEAD".to_string());
+    }
+    Ok(names)
+}
+
+/// A reflog line for a move from `old` to `new`; `None` stands for a missing reference.
+pub fn reflog_entry(
+    identity: &Identity,
+    old: Option<&str>,
+    new: Option<&str>,
+    message: Option<&str>,
+) -> ReflogEntry {
+    ReflogEntry {
+        old_oid: old.unwrap_or(NULL_OID).to_string(),
+        new_oid: new.unwrap_or(NULL_OID).to_string(),
+        identity: identity.to_string(),
+        timestamp: identity.when.seconds,
+        tz: identity.when.offset(),
+        message: message.unwrap_or_default().to_string(),
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+
+use crate::modules::{
+    files::{ReadOptions, TreeNode, read_tree_from_dir},
+    hash::ObjectId,
+    ignore::IgnoreMatcher,
+    objects::tree::Tree,
+};
+use crate::repo::Repository;
+
+/// Stores the working tree of `repo` as tree and blob objects and returns the root tree's id.
+///
+/// Note: This snapshots the working directory itself rather than the index, leaving out
+/// the metadata directory and ignored paths. With `core.filemode`
+/// off, executable bits on disk aren't trusted and every file is recorded as 100644.
+pub fn write_tree(repo: &Repository) -> Result<ObjectId> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let odb = repo.odb()?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+
+    let matcher = IgnoreMatcher::for_repo(repo)?;
+    let exclude = |relative: &std::path::Path, is_dir: bool| {
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        matcher.is_ignored(&relative, is_dir)
+    };
+    let options = ReadOptions {
+        exclude: Some(&exclude),
+        ..Default::default()
+    };
+    let mut tree = read_tree_from_dir(work_tree, &options)?;
+    if !filemode {
+        clear_modes(&mut tree);
+    }
+    Tree::from_tree_node(&tree, &odb)
+}
+
+fn clear_modes(node: &mut TreeNode) {
+    match node {
+        TreeNode::File { mode, .. } => *mode = None,
+        TreeNode::Directory { entries, mode } => {
+            *mode = None;
+            entries.values_mut().for_each(clear_modes)
+        }
+        TreeNode::Symlink(_) => {}
+    }
+}
@@ -1,10 +1,31 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use colored::*;
 use cs_01::commands;
+use cs_01::commands::log::{DecorateMode, DecorationFilter, Pretty};
+use cs_01::modules::{
+    config::{ConfigEntry, ConfigScope, ConfigType},
+    constants::METADATA_DIR,
+    diff::WhitespaceErrorsFound,
+    files::PlannedOp,
+    hash::{ObjectFormat, ObjectId, ObjectKind},
+    index::Index,
+    objects::tree::Tree,
+    output::Output,
+    perms::SharedMode,
+    refs::{HeadState, OutsideRefs, RefMismatch},
+    revision::BadRevision,
+};
+use cs_01::repo::{InitOptions, InitOutcome, NotARepository, Repository};
+use serde_json::json;
+use std::path::{Path, PathBuf};
 #[derive(Parser)]
-#[command(name = "CS01")]
+#[command(name = "CS01", version)]
 #[command(about = "\n\nCS01 Version Control System", long_about = None)]
 struct Cli {
+    /// Print results (and errors) as JSON instead of colored text
+    #[arg(long, global = true)]
+    json: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -17,29 +38,2960 @@ enum Commands {
         #[arg(long)]
         bare: bool,
 
-        /// Specify the initial branch name (defaults to "main")
-        #[arg(long, default_value = "main")]
-        initial_branch: String,
+        /// Specify the initial branch name (defaults to init.defaultBranch, or "main")
+        #[arg(long)]
+        initial_branch: Option<String>,
+
+        /// Make the repository shared among several users (umask, group, all, or an octal mode like 0660)
+        #[arg(
+            long,
+            value_name = "PERMISSIONS",
+            value_parser = SharedMode::parse,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "group"
+        )]
+        shared: Option<SharedMode>,
+
+        /// Hash algorithm used to name objects (sha1 or sha256; defaults to sha1)
+        #[arg(long, value_name = "FORMAT", value_parser = ObjectFormat::parse)]
+        object_format: Option<ObjectFormat>,
+
+        /// Proceed even when the target is inside another CS01 repository or another VCS's checkout
+        #[arg(long)]
+        force: bool,
+
+        /// Copy files from this directory into the new metadata directory (hooks, info/exclude, ...)
+        #[arg(long, value_name = "DIR")]
+        template: Option<String>,
+
+        /// Show what would be created, without touching the disk
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Only print errors and warnings; suppress the success message
+        #[arg(short, long)]
+        quiet: bool,
 
         /// Specify the directory to initialize (defaults to current directory)
         #[arg(default_value = ".")]
         path: String,
     },
+
+    /// Get and set repository options
+    Config {
+        /// The option to read or write, as section[.subsection].name (e.g. remote.origin.url)
+        #[arg(required_unless_present_any = ["list", "edit"])]
+        key: Option<String>,
+
+        /// The new value; when omitted, the current (last) value is printed
+        #[arg(conflicts_with_all = ["unset", "remove_section"])]
+        value: Option<String>,
+
+        /// Remove the option
+        #[arg(long, requires = "key")]
+        unset: bool,
+
+        /// Add a value without replacing existing ones (for multi-valued options)
+        #[arg(long, requires = "value", conflicts_with = "unset")]
+        add: bool,
+
+        /// Print every value of a multi-valued option, one per line
+        #[arg(long, conflicts_with_all = ["value", "unset"])]
+        get_all: bool,
+
+        /// List all options as name=value
+        #[arg(short, long, conflicts_with_all = ["key", "unset", "add", "get_all"])]
+        list: bool,
+
+        /// Open the config file in an editor (CS01_EDITOR, VISUAL, EDITOR, or core.editor)
+        #[arg(short, long, conflicts_with_all = ["key", "list", "unset", "add", "get_all"])]
+        edit: bool,
+
+        /// Rename a section, e.g. `--rename-section remote.origin remote.upstream`
+        #[arg(
+            long,
+            requires = "value",
+            conflicts_with_all = ["list", "edit", "unset", "add", "get_all", "value_type"]
+        )]
+        rename_section: bool,
+
+        /// Remove a section and every option in it, e.g. `--remove-section remote.origin`
+        #[arg(
+            long,
+            conflicts_with_all = ["list", "edit", "unset", "add", "get_all", "value_type", "rename_section"]
+        )]
+        remove_section: bool,
+
+        /// Interpret values as bool, int or path: reads print the canonical form, writes store it
+        #[arg(
+            long = "type",
+            value_name = "TYPE",
+            value_parser = ConfigType::parse,
+            conflicts_with_all = ["list", "edit", "unset"]
+        )]
+        value_type: Option<ConfigType>,
+
+        /// When reading, prefix each value with the scope it comes from (system, global, local, command)
+        #[arg(long, conflicts_with_all = ["value", "unset", "edit", "remove_section"])]
+        show_scope: bool,
+
+        /// When reading, prefix each value with its origin (file:<path> or command line:)
+        #[arg(long, conflicts_with_all = ["value", "unset", "edit", "remove_section"])]
+        show_origin: bool,
+
+        /// Use the per-user config file (~/.cs01config) instead of the repository's
+        #[arg(long, conflicts_with_all = ["system", "local"])]
+        global: bool,
+
+        /// Use the machine-wide config file (/etc/cs01config) instead of the repository's
+        #[arg(long, conflicts_with = "local")]
+        system: bool,
+
+        /// Only use the repository's config file (the default for writes)
+        #[arg(long)]
+        local: bool,
+    },
+
+    /// Compute the object id of file contents, and optionally store the object
+    HashObject {
+        /// The object type (blob, tree, commit or tag)
+        #[arg(short = 't', value_name = "TYPE", default_value = "blob", value_parser = ObjectKind::parse)]
+        kind: ObjectKind,
+
+        /// Write the object into the object database
+        #[arg(short)]
+        write: bool,
+
+        /// Read the content from standard input (hashed before any files)
+        #[arg(long)]
+        stdin: bool,
+
+        /// The files to hash
+        #[arg(required_unless_present = "stdin")]
+        files: Vec<PathBuf>,
+    },
+
+    /// Show the content, type or size of a stored object
+    #[command(group(clap::ArgGroup::new("query").required(true)))]
+    CatFile {
+        /// Print the content, formatted by type (trees as one entry per line)
+        #[arg(short = 'p', group = "query")]
+        pretty: bool,
+
+        /// Print the object's type
+        #[arg(short = 't', group = "query")]
+        kind: bool,
+
+        /// Print the object's size in bytes
+        #[arg(short = 's', group = "query")]
+        size: bool,
+
+        /// Print nothing; exit with status 0 if the object exists and 1 if not
+        #[arg(short = 'e', group = "query")]
+        exists: bool,
+
+        /// The object's full hex id
+        object: String,
+    },
+
+    /// Store the working tree as tree objects and print the root tree's id
+    WriteTree,
+
+    /// Create a commit object for a tree and print its id, without updating any branch
+    CommitTree {
+        /// The tree to commit, as a full hex id
+        tree: String,
+
+        /// A parent commit; repeat for a merge
+        #[arg(short = 'p', value_name = "PARENT")]
+        parents: Vec<String>,
+
+        /// The commit message; each -m is a paragraph. Read from standard input when omitted
+        #[arg(short = 'm', value_name = "MESSAGE")]
+        messages: Vec<String>,
+    },
+
+    /// Point a reference at an object, checking its old value first; or delete it
+    UpdateRef {
+        /// Delete the reference instead; the second argument is then the expected old value
+        #[arg(short = 'd')]
+        delete: bool,
+
+        /// The reason recorded in the reflog
+        #[arg(short = 'm', value_name = "REASON")]
+        message: Option<String>,
+
+        /// The reference, e.g. refs/heads/main or HEAD (which updates its branch)
+        name: String,
+
+        /// The new object id (the expected old value with -d)
+        new: Option<String>,
+
+        /// The expected old value; empty or all zeros means the reference must not exist yet
+        old: Option<String>,
+    },
+
+    /// Show which reference a symbolic ref like HEAD points at, or point it elsewhere
+    SymbolicRef {
+        /// Exit with status 1 and no message when the reference isn't symbolic
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Print the target without its refs/heads/ (or similar) prefix
+        #[arg(long)]
+        short: bool,
+
+        /// Print the target itself, even if it is a symbolic reference too
+        #[arg(long)]
+        no_recurse: bool,
+
+        /// Delete the symbolic reference, not the reference it points at
+        #[arg(short, long, conflicts_with_all = ["target", "short", "no_recurse"])]
+        delete: bool,
+
+        /// The reason recorded in the reflog
+        #[arg(short = 'm', value_name = "REASON")]
+        message: Option<String>,
+
+        /// The symbolic reference, usually HEAD
+        name: String,
+
+        /// The new target; must start with refs/
+        target: Option<String>,
+    },
+
+    /// Move loose references into the packed-refs file
+    PackRefs {
+        /// Pack every reference, not only tags
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Print the object ids revisions name, or facts about the repository
+    RevParse {
+        /// Print the path of the metadata directory
+        #[arg(long = "cs01-dir")]
+        cs01_dir: bool,
+
+        /// Print the root of the working tree
+        #[arg(long)]
+        show_toplevel: bool,
+
+        /// Print the current directory's path from the root of the working tree
+        #[arg(long)]
+        show_prefix: bool,
+
+        /// Print the path from the current directory up to the root of the working tree
+        #[arg(long)]
+        show_cdup: bool,
+
+        /// Print whether the repository is bare (true or false)
+        #[arg(long)]
+        is_bare_repository: bool,
+
+        /// Print whether the current directory is inside the working tree (true or false)
+        #[arg(long)]
+        is_inside_work_tree: bool,
+
+        /// Revisions to resolve, e.g. HEAD, main~2, v1.0^2 or an abbreviated id
+        revisions: Vec<String>,
+    },
+
+    /// Stage files for the next commit, recursing into directories
+    Add {
+        /// Also stage ignored files named on the command line
+        #[arg(short, long)]
+        force: bool,
+
+        /// Only stage changes to tracked files, including deletions (the whole tree by default)
+        #[arg(short, long, conflicts_with = "all")]
+        update: bool,
+
+        /// Stage new, changed and deleted files alike (the whole tree by default)
+        #[arg(short = 'A', long)]
+        all: bool,
+
+        /// Print each path as it is staged or unstaged
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Files or directories to stage, relative to the current directory
+        paths: Vec<String>,
+    },
+
+    /// Show staged, unstaged and untracked changes in the working tree
+    Status {
+        /// One line per path: its staged and unstaged change as two letters, then the path
+        #[arg(short, long)]
+        short: bool,
+
+        /// Like --short, but stable for scripts: no colors, and paths from the top
+        #[arg(long, conflicts_with = "short")]
+        porcelain: bool,
+    },
+
+    /// Show changes between the working tree, the index and commits as patches
+    Diff {
+        /// Compare the index with HEAD, or with the given commit
+        #[arg(long, visible_alias = "cached")]
+        staged: bool,
+
+        /// Show this many unchanged lines around each change
+        #[arg(short = 'U', long = "unified", value_name = "N", default_value_t = 3)]
+        unified: usize,
+
+        /// Report whitespace errors in added lines instead of the patches; exits with 2
+        /// if there are any
+        #[arg(long)]
+        check: bool,
+
+        /// A commit to compare the working tree (or the index) with, or two commits
+        #[arg(num_args = 0..=2, value_name = "COMMIT")]
+        commits: Vec<String>,
+    },
+
+    /// List the files in the index or the working tree
+    LsFiles {
+        /// List the files in the index (the default)
+        #[arg(short, long)]
+        cached: bool,
+
+        /// Show each index entry's mode, object id and stage
+        #[arg(short, long)]
+        stage: bool,
+
+        /// List untracked files
+        #[arg(short, long)]
+        others: bool,
+
+        /// List tracked files that differ from the index in the working tree
+        #[arg(short, long)]
+        modified: bool,
+
+        /// List tracked files missing from the working tree
+        #[arg(short, long)]
+        deleted: bool,
+
+        /// Leave out untracked files matched by the ignore rules
+        #[arg(long)]
+        exclude_standard: bool,
+
+        /// Leave out untracked files matched by the patterns in this file
+        #[arg(long, value_name = "FILE")]
+        exclude_from: Vec<PathBuf>,
+
+        /// Show paths from the top of the working tree, not the current directory
+        #[arg(long)]
+        full_name: bool,
+
+        /// End each path with a NUL byte instead of a newline
+        #[arg(short)]
+        z: bool,
+    },
+
+    /// Show which paths are ignored, and by which rule
+    CheckIgnore {
+        /// Print the file, line and pattern that decided each path, negations included
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Read the paths from standard input, one per line
+        #[arg(long, conflicts_with = "paths")]
+        stdin: bool,
+
+        /// The paths to check, relative to the current directory
+        #[arg(required_unless_present = "stdin")]
+        paths: Vec<String>,
+    },
+
+    /// Record the staged changes as a new commit on the current branch
+    Commit {
+        /// The commit message; each -m is a paragraph. Opens the editor when omitted
+        #[arg(short = 'm', long = "message", value_name = "MESSAGE")]
+        messages: Vec<String>,
+
+        /// Commit even when nothing changed since the parent commit
+        #[arg(long)]
+        allow_empty: bool,
+
+        /// Record an empty message instead of aborting
+        #[arg(long)]
+        allow_empty_message: bool,
+
+        /// Skip the pre-commit and commit-msg hooks
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+    },
+
+    /// List, create or delete branches
+    Branch {
+        /// Delete the branch, which must be merged into HEAD
+        #[arg(short = 'd', long)]
+        delete: bool,
+
+        /// Delete the branch even if it isn't merged
+        #[arg(short = 'D', conflicts_with = "delete")]
+        force_delete: bool,
+
+        /// The branch to create or delete; lists the branches when omitted
+        name: Option<String>,
+
+        /// The commit a new branch starts at (defaults to HEAD)
+        start_point: Option<String>,
+    },
+
+    /// Remove tracked files from the index and the working tree
+    Rm {
+        /// Only remove the files from the index, keeping them on disk
+        #[arg(long)]
+        cached: bool,
+
+        /// Remove the tracked files under directories that are named
+        #[arg(short)]
+        r: bool,
+
+        /// Remove files even if they have staged or unstaged changes
+        #[arg(short, long)]
+        force: bool,
+
+        /// Don't list the removed files
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Files or directories to remove, relative to the current directory
+        paths: Vec<String>,
+    },
+
+    /// Move or rename tracked files, on disk and in the index
+    Mv {
+        /// Replace an existing destination file
+        #[arg(short, long)]
+        force: bool,
+
+        /// Print each rename
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// The files or directories to move, then where to (an existing directory
+        /// takes them all)
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+    },
+
+    /// Switch the working tree, index and HEAD to another branch or commit
+    #[command(alias = "checkout")]
+    Switch {
+        /// Create the branch first, at the start point (HEAD by default)
+        #[arg(short = 'c', long, short_alias = 'b', value_name = "NEW_BRANCH")]
+        create: Option<String>,
+
+        /// Detach HEAD at the commit instead of switching to a branch
+        #[arg(long, conflicts_with = "create")]
+        detach: bool,
+
+        /// Only print errors: no summary, local changes, advice or tracking information
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// The branch to switch to, or the start point with -c or --detach
+        target: Option<String>,
+    },
+
+    /// Move the current branch to a commit, or unstage files
+    Reset {
+        /// Only move the branch, keeping the index and working tree
+        #[arg(long, group = "mode")]
+        soft: bool,
+
+        /// Move the branch and reset the index, keeping the working tree (the default)
+        #[arg(long, group = "mode")]
+        mixed: bool,
+
+        /// Move the branch and reset the index and working tree, discarding local changes
+        #[arg(long, group = "mode")]
+        hard: bool,
+
+        /// The commit to reset to (defaults to HEAD), then files to unstage
+        args: Vec<String>,
+
+        /// Files to unstage, after `--`
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Join another branch's history into the current branch
+    Merge {
+        /// Record a merge commit even when the branch could be fast-forwarded
+        #[arg(long)]
+        no_ff: bool,
+
+        /// Only fast-forward; refuse to record a merge commit
+        #[arg(long, conflicts_with = "no_ff")]
+        ff_only: bool,
+
+        /// The branch or commit to merge
+        revision: String,
+    },
+
+    /// Show the commits reachable from a revision, newest first
+    Log {
+        /// Show at most this many commits
+        #[arg(short = 'n', long = "max-count", value_name = "COUNT")]
+        max_count: Option<usize>,
+
+        /// Show the references pointing at each commit, e.g. `(HEAD -> main, tag: v1)`, by short
+        /// or full name; auto (the default, unless log.decorate is set) only on a terminal
+        #[arg(
+            long,
+            value_name = "MODE",
+            value_parser = DecorateMode::parse,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "short"
+        )]
+        decorate: Option<DecorateMode>,
+
+        /// Show no references, whatever log.decorate says
+        #[arg(long, conflicts_with = "decorate")]
+        no_decorate: bool,
+
+        /// Only show references matching this pattern, a glob or a prefix like refs/tags (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        decorate_refs: Vec<String>,
+
+        /// Never show references matching this pattern (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        decorate_refs_exclude: Vec<String>,
+
+        /// How to show each commit: medium (the default), oneline, or a template like "format:%h %s"
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            value_parser = Pretty::parse,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "medium",
+            conflicts_with_all = ["format", "oneline"]
+        )]
+        pretty: Option<Pretty>,
+
+        /// Same as --pretty=<FORMAT>; a bare template ends each commit with a newline
+        #[arg(long, value_name = "FORMAT", value_parser = Pretty::parse, conflicts_with = "oneline")]
+        format: Option<Pretty>,
+
+        /// Show each commit as its abbreviated id and subject on one line
+        #[arg(long)]
+        oneline: bool,
+
+        /// Where to start, e.g. a branch, a tag or an abbreviated id (defaults to HEAD)
+        revision: Option<String>,
+    },
+
+    /// Show where a reference has pointed, newest first
+    Reflog {
+        /// The reference, e.g. a branch (defaults to HEAD)
+        reference: Option<String>,
+    },
+
+    /// Set local changes aside and bring them back later
+    Stash {
+        #[command(subcommand)]
+        action: Option<StashAction>,
+    },
+
+    /// Copy a repository into a new directory and check out its current branch
+    Clone {
+        /// Create a bare repository, taking the source's branches as its own
+        #[arg(long)]
+        bare: bool,
+
+        /// Copy the object files instead of hardlinking them
+        #[arg(long)]
+        no_hardlinks: bool,
+
+        /// The repository to clone: a working tree or a bare repository
+        source: String,
+
+        /// Where to create the clone (defaults to the source's directory name)
+        destination: Option<String>,
+    },
+
+    /// Print every index entry with its stat data, for debugging
+    #[command(hide = true)]
+    DebugIndex,
+}
+
+#[derive(Subcommand)]
+enum StashAction {
+    /// Save local changes as a new entry and reset to HEAD (the default)
+    Push {
+        /// Describe the entry
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Stash untracked files too, removing them from the working tree
+        #[arg(short = 'u', long)]
+        include_untracked: bool,
+    },
+
+    /// List the entries, newest first
+    List,
+
+    /// Apply an entry (stash@{0} by default) and drop it unless it conflicts
+    Pop {
+        /// The entry, as stash@{N} or N
+        stash: Option<String>,
+    },
+
+    /// Apply an entry (stash@{0} by default), keeping it
+    Apply {
+        /// The entry, as stash@{N} or N
+        stash: Option<String>,
+    },
+
+    /// Remove an entry (stash@{0} by default)
+    Drop {
+        /// The entry, as stash@{N} or N
+        stash: Option<String>,
+    },
+}
+
+/// The subcommands that work outside a repository, with the cases that still need one.
+/// Every other subcommand fails with `NotARepository` before it runs; `--help` and
+/// `--version` never get that far.
+const OUTSIDE_REPOSITORY: &[(&str, &str)] = &[
+    ("init", ""),
+    ("clone", ""),
+    ("hash-object", " (without -w)"),
+    ("config", " (reads outside the repository scope)"),
+    ("help", ""),
+];
+
+/// The command line definition, with the commands that work outside a repository
+/// listed after the help.
+fn cli_command() -> clap::Command {
+    let outside: Vec<String> = OUTSIDE_REPOSITORY
+        .iter()
+        .map(|(name, note)| format!("{}{}", name, note))
+        .collect();
+    Cli::command().after_help(format!(
+        "Outside a repository, only these commands work: {}.",
+        outside.join(", ")
+    ))
+}
+
+/// Whether the subcommand `name` may run outside a repository (see `OUTSIDE_REPOSITORY`).
+fn runs_outside_repository(name: &str) -> bool {
+    OUTSIDE_REPOSITORY
+        .iter()
+        .any(|(outside, _)| *outside == name)
+}
+
+/// Lets a closed pipe end the process quietly, as it does for git.
+///
+/// Note: Rust ignores SIGPIPE, which turns `cs01 log | head -1` into a panic on the
+/// next write once `head` exits; with the default handler back the process just stops.
+#[cfg(unix)]
+fn restore_sigpipe() {
+    // SAFETY: Runs first thing in `main`, before any other thread exists.
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
 }
 
 fn main() {
-    let cli = Cli::parse();
+    #[cfg(unix)]
+    restore_sigpipe();
+
+    let matches = cli_command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Note: JSON consumers parse stdout/stderr directly, so no ANSI escapes may leak in.
+    if cli.json {
+        colored::control::set_override(false);
+    }
+
+    if let Some(name) = matches.subcommand_name()
+        && !runs_outside_repository(name)
+        && let Err(e) = Repository::current()
+    {
+        fail(&e, cli.json);
+    }
 
     let result = match &cli.command {
         Commands::Init {
             bare,
             initial_branch,
+            shared,
+            object_format,
+            force,
+            template,
+            dry_run,
+            quiet,
             path,
-        } => commands::init::init(*bare, initial_branch, path),
+        } => {
+            let out = Output::new(*quiet).with_json(cli.json);
+            let options = InitOptions {
+                bare: *bare,
+                initial_branch: initial_branch.clone(),
+                shared: shared.unwrap_or(SharedMode::Umask),
+                object_format: *object_format,
+                force: *force,
+                // Note: As in git, an empty `--template=` means no template at all.
+                template: template
+                    .as_deref()
+                    .filter(|template| !template.is_empty())
+                    .map(PathBuf::from),
+                dry_run: *dry_run,
+            };
+            Repository::init_with_output(Path::new(path), &options, &out)
+                .map(|(_, outcome)| print_init_outcome(&outcome, &out))
+        }
+        Commands::Config {
+            key,
+            value,
+            unset,
+            add,
+            get_all,
+            list,
+            edit,
+            rename_section,
+            remove_section,
+            value_type,
+            show_scope,
+            show_origin,
+            global,
+            system,
+            local,
+        } => run_config(
+            key.as_deref(),
+            value.as_deref(),
+            ConfigAction::from_flags(
+                *unset,
+                *add,
+                *get_all,
+                *list,
+                *edit,
+                *rename_section,
+                *remove_section,
+            ),
+            config_scope(*global, *system, *local),
+            *value_type,
+            Provenance {
+                scope: *show_scope,
+                origin: *show_origin,
+            },
+            cli.json,
+        ),
+        Commands::HashObject {
+            kind,
+            write,
+            stdin,
+            files,
+        } => run_hash_object(*kind, *write, *stdin, files, cli.json),
+        Commands::CatFile {
+            pretty: _,
+            kind,
+            size,
+            exists,
+            object,
+        } => run_cat_file(
+            CatFileQuery::from_flags(*kind, *size, *exists),
+            object,
+            cli.json,
+        ),
+        Commands::WriteTree => run_write_tree(cli.json),
+        Commands::CommitTree {
+            tree,
+            parents,
+            messages,
+        } => run_commit_tree(tree, parents, messages, cli.json),
+        Commands::UpdateRef {
+            delete,
+            message,
+            name,
+            new,
+            old,
+        } => run_update_ref(
+            name,
+            *delete,
+            new.as_deref(),
+            old.as_deref(),
+            message.as_deref(),
+            cli.json,
+        ),
+        Commands::SymbolicRef {
+            quiet,
+            short,
+            no_recurse,
+            delete,
+            message,
+            name,
+            target,
+        } => run_symbolic_ref(
+            name,
+            target.as_deref(),
+            message.as_deref(),
+            SymbolicRefFlags {
+                quiet: *quiet,
+                short: *short,
+                recurse: !*no_recurse,
+                delete: *delete,
+            },
+            cli.json,
+        ),
+        Commands::PackRefs { all } => run_pack_refs(*all, cli.json),
+        Commands::RevParse { .. } => {
+            let matches = matches.subcommand_matches("rev-parse").unwrap();
+            run_rev_parse(&rev_parse_queries(matches), cli.json)
+        }
+        Commands::Add {
+            force,
+            update,
+            all,
+            verbose,
+            paths,
+        } => run_add(
+            paths,
+            &commands::add::AddOptions {
+                force: *force,
+                update: *update,
+                all: *all,
+            },
+            *verbose,
+            cli.json,
+        ),
+        Commands::Status { short, porcelain } => run_status(*short, *porcelain, cli.json),
+        Commands::Diff {
+            staged,
+            unified,
+            check,
+            commits,
+        } => run_diff(*staged, *unified, *check, commits, cli.json),
+        Commands::LsFiles {
+            cached,
+            stage,
+            others,
+            modified,
+            deleted,
+            exclude_standard,
+            exclude_from,
+            full_name,
+            z,
+        } => run_ls_files(
+            commands::ls_files::LsFilesOptions {
+                cached: *cached || *stage,
+                others: *others,
+                modified: *modified,
+                deleted: *deleted,
+                exclude_standard: *exclude_standard,
+                exclude_from: exclude_from.clone(),
+                under: String::new(),
+            },
+            *stage,
+            *full_name,
+            *z,
+            cli.json,
+        ),
+        Commands::CheckIgnore {
+            verbose,
+            stdin,
+            paths,
+        } => run_check_ignore(paths, *verbose, *stdin, cli.json),
+        Commands::Commit {
+            messages,
+            allow_empty,
+            allow_empty_message,
+            no_verify,
+        } => run_commit(
+            messages,
+            &commands::commit::CommitOptions {
+                allow_empty: *allow_empty,
+                allow_empty_message: *allow_empty_message,
+                no_verify: *no_verify,
+            },
+            cli.json,
+        ),
+        Commands::Branch {
+            delete,
+            force_delete,
+            name,
+            start_point,
+        } => run_branch(
+            name.as_deref(),
+            start_point.as_deref(),
+            *delete || *force_delete,
+            *force_delete,
+            cli.json,
+        ),
+        Commands::Rm {
+            cached,
+            r,
+            force,
+            quiet,
+            paths,
+        } => run_rm(
+            paths,
+            &commands::rm::RmOptions {
+                cached: *cached,
+                recursive: *r,
+                force: *force,
+            },
+            *quiet,
+            cli.json,
+        ),
+        Commands::Mv {
+            force,
+            verbose,
+            paths,
+        } => run_mv(paths, *force, *verbose, cli.json),
+        Commands::Switch {
+            create,
+            detach,
+            quiet,
+            target,
+        } => run_switch(
+            create.as_deref(),
+            *detach,
+            target.as_deref(),
+            Output::new(*quiet).with_json(cli.json),
+        ),
+        Commands::Reset {
+            soft,
+            mixed: _,
+            hard,
+            args,
+            paths,
+        } => run_reset(*soft, *hard, args, paths, cli.json),
+        Commands::Merge {
+            no_ff,
+            ff_only,
+            revision,
+        } => run_merge(revision, *no_ff, *ff_only, cli.json),
+        Commands::Log {
+            max_count,
+            decorate,
+            no_decorate,
+            decorate_refs,
+            decorate_refs_exclude,
+            pretty,
+            format,
+            oneline,
+            revision,
+        } => run_log(
+            revision.as_deref(),
+            *max_count,
+            match no_decorate {
+                true => Some(DecorateMode::No),
+                false => *decorate,
+            },
+            &DecorationFilter {
+                include: decorate_refs.clone(),
+                exclude: decorate_refs_exclude.clone(),
+            },
+            match oneline {
+                true => Pretty::Oneline { abbrev: true },
+                false => pretty.clone().or(format.clone()).unwrap_or(Pretty::Medium),
+            },
+            cli.json,
+        ),
+        Commands::Reflog { reference } => run_reflog(reference.as_deref(), cli.json),
+        Commands::Stash { action } => run_stash(action.as_ref(), cli.json),
+        Commands::Clone {
+            bare,
+            no_hardlinks,
+            source,
+            destination,
+        } => run_clone(
+            source,
+            destination.as_deref(),
+            commands::clone::CloneOptions {
+                bare: *bare,
+                no_hardlinks: *no_hardlinks,
+            },
+            cli.json,
+        ),
+        Commands::DebugIndex => run_debug_index(cli.json),
     };
 
     if let Err(e) = result {
-        eprintln!("{}", format!("Error: {}", e).bright_red());
+        fail(&e, cli.json);
+    }
+}
+
+/// Reports `error` and exits with its status (see `exit_code`).
+fn fail(error: &anyhow::Error, json: bool) -> ! {
+    let not_a_repository = error.downcast_ref::<NotARepository>();
+    if json {
+        eprintln!("{}", json!({ "error": error.to_string() }));
+    } else if let Some(not_a_repository) = not_a_repository {
+        // As git reports it, since scripts match on it
+        eprintln!("{}", format!("fatal: {}", not_a_repository).bright_red());
+        if not_a_repository.searched && suggest_init() {
+            eprintln!("hint: use \"cs01 init\" to create one");
+        }
+    } else {
+        eprintln!("{}", format!("Error: {}", error).bright_red());
+    }
+    std::process::exit(exit_code(error));
+}
+
+/// Whether to suggest `cs01 init` after a search for a repository found none: only
+/// where one could be created, and there is something to put in it.
+fn suggest_init() -> bool {
+    let Ok(cwd) = std::env::current_dir() else {
+        return false;
+    };
+    let non_empty = std::fs::read_dir(&cwd).is_ok_and(|mut entries| entries.next().is_some());
+    non_empty && is_writable(&cwd)
+}
+
+#[cfg(unix)]
+fn is_writable(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string for the duration of the call.
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable(dir: &Path) -> bool {
+    std::fs::metadata(dir).is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+/// The exit status for a failed command: 1 in general, with distinct codes for
+/// failures scripts may want to tell apart.
+fn exit_code(error: &anyhow::Error) -> i32 {
+    if error.downcast_ref::<RefMismatch>().is_some() {
+        // Another writer moved the ref first; retrying with a fresh old value may work.
+        2
+    } else if error.downcast_ref::<WhitespaceErrorsFound>().is_some() {
+        // As `git diff --check`.
+        2
+    } else if error.downcast_ref::<OutsideRefs>().is_some() {
+        3
+    } else if error.downcast_ref::<NotARepository>().is_some() {
+        // Apart from git's 128, so scripts can tell "no repository here" from a bad
+        // revision.
+        4
+    } else if error.downcast_ref::<BadRevision>().is_some() {
+        // As in git, so scripts can test `rev-parse --verify`-style lookups.
+        128
+    } else {
+        1
+    }
+}
+
+/// Which `config` operation the flags select; clap already rejects conflicting combinations.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConfigAction {
+    GetOrSet,
+    Unset,
+    Add,
+    GetAll,
+    List,
+    Edit,
+    RenameSection,
+    RemoveSection,
+}
+
+impl ConfigAction {
+    fn from_flags(
+        unset: bool,
+        add: bool,
+        get_all: bool,
+        list: bool,
+        edit: bool,
+        rename_section: bool,
+        remove_section: bool,
+    ) -> Self {
+        if edit {
+            ConfigAction::Edit
+        } else if list {
+            ConfigAction::List
+        } else if rename_section {
+            ConfigAction::RenameSection
+        } else if remove_section {
+            ConfigAction::RemoveSection
+        } else if unset {
+            ConfigAction::Unset
+        } else if add {
+            ConfigAction::Add
+        } else if get_all {
+            ConfigAction::GetAll
+        } else {
+            ConfigAction::GetOrSet
+        }
+    }
+}
+
+/// The scope selected by `--global`/`--system`/`--local`; `None` means the default
+/// (the whole cascade for reads, the repository for writes).
+fn config_scope(global: bool, system: bool, local: bool) -> Option<ConfigScope> {
+    match (global, system, local) {
+        (true, _, _) => Some(ConfigScope::Global),
+        (_, true, _) => Some(ConfigScope::System),
+        (_, _, true) => Some(ConfigScope::Local),
+        _ => None,
+    }
+}
+
+/// Which provenance columns (`--show-scope`, `--show-origin`) a `config` read prints.
+#[derive(Clone, Copy)]
+struct Provenance {
+    scope: bool,
+    origin: bool,
+}
+
+impl Provenance {
+    fn any(&self) -> bool {
+        self.scope || self.origin
+    }
+
+    /// The tab-separated columns printed before a value, in git's order: scope, then origin.
+    fn prefix(&self, scope: ConfigScope, entry: &ConfigEntry) -> String {
+        let mut prefix = String::new();
+        if self.scope {
+            prefix.push_str(scope.name());
+            prefix.push('\t');
+        }
+        if self.origin {
+            prefix.push_str(&entry.origin.to_string());
+            prefix.push('\t');
+        }
+        prefix
+    }
+
+    /// An entry as a JSON object with the requested provenance fields.
+    fn json(&self, scope: ConfigScope, entry: &ConfigEntry) -> serde_json::Value {
+        let mut item = json!({ "key": entry.key_name(), "value": entry.value });
+        if self.scope {
+            item["scope"] = json!(scope.name());
+        }
+        if self.origin {
+            item["origin"] = json!(entry.origin.to_string());
+        }
+        item
+    }
+}
+
+fn run_config(
+    key: Option<&str>,
+    value: Option<&str>,
+    action: ConfigAction,
+    scope: Option<ConfigScope>,
+    value_type: Option<ConfigType>,
+    provenance: Provenance,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::config;
+    use cs_01::modules::config::ConfigKey;
+
+    if action == ConfigAction::List {
+        let entries = config::read_entries(scope)?;
+        if json && provenance.any() {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(entry_scope, entry)| provenance.json(*entry_scope, entry))
+                .collect();
+            println!("{}", json!(entries));
+        } else if json {
+            // Note: A multi-valued option becomes an array, mirroring the config model.
+            let mut map = serde_json::Map::new();
+            for (_, entry) in entries {
+                let name = entry.key_name();
+                let value = serde_json::Value::String(entry.value);
+                match map.get_mut(&name) {
+                    Some(serde_json::Value::Array(values)) => values.push(value),
+                    Some(existing) => *existing = json!([existing.take(), value]),
+                    None => {
+                        map.insert(name, value);
+                    }
+                }
+            }
+            println!("{}", serde_json::Value::Object(map));
+        } else {
+            for (entry_scope, entry) in &entries {
+                println!(
+                    "{}{}={}",
+                    provenance.prefix(*entry_scope, entry),
+                    entry.key_name(),
+                    entry.value
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    if action == ConfigAction::Edit {
+        return config::edit_in_editor(&config::write_file(scope)?);
+    }
+
+    // Note: For the section actions the positionals are section names, not keys.
+    match (action, key, value) {
+        (ConfigAction::RenameSection, Some(old), Some(new)) => {
+            return config::rename_section(&config::write_file(scope)?, old, new);
+        }
+        (ConfigAction::RemoveSection, Some(name), _) => {
+            return config::remove_section(&config::write_file(scope)?, name);
+        }
+        _ => {}
+    }
+
+    // Note: clap guarantees a key whenever --list and --edit are absent.
+    let key = ConfigKey::parse(key.unwrap_or_default())?;
+    let key_name = key.to_string();
+
+    // Note: With --type, values are checked and canonicalized on the way in and out,
+    // so `yes` is stored as `true` and `1k` reads back as `1024`.
+    let value = value
+        .map(|value| match value_type {
+            Some(value_type) => value_type.normalize_for_write(&key_name, value),
+            None => Ok(value.to_string()),
+        })
+        .transpose()?;
+    let value = value.as_deref();
+    let display = |entry: &ConfigEntry| match value_type {
+        Some(value_type) => value_type.canonicalize(&key_name, &entry.value),
+        None => Ok(entry.value.clone()),
+    };
+
+    match (action, value) {
+        (ConfigAction::Add, Some(value)) => config::add(&config::write_file(scope)?, &key, value),
+        (_, Some(value)) => config::set(&config::write_file(scope)?, &key, value),
+        (ConfigAction::Unset, None) => config::unset(&config::write_file(scope)?, &key),
+        (ConfigAction::GetAll, None) => {
+            let entries = config::read_entries(scope)?;
+            let found = config::get_all(&entries, &key);
+            if found.is_empty() {
+                std::process::exit(1);
+            }
+            if json && provenance.any() {
+                let mut items = Vec::new();
+                for (entry_scope, entry) in found {
+                    let mut item = provenance.json(*entry_scope, entry);
+                    item["value"] = json!(display(entry)?);
+                    items.push(item);
+                }
+                println!("{}", json!(items));
+            } else if json {
+                let values = found
+                    .iter()
+                    .map(|(_, entry)| display(entry))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                println!("{}", json!({ "key": key_name, "values": values }));
+            } else {
+                for (entry_scope, entry) in found {
+                    println!(
+                        "{}{}",
+                        provenance.prefix(*entry_scope, entry),
+                        display(entry)?
+                    );
+                }
+            }
+            Ok(())
+        }
+        (_, None) => {
+            let entries = config::read_entries(scope)?;
+            match config::get(&entries, &key) {
+                Some((entry_scope, entry)) if json => {
+                    let mut item = provenance.json(*entry_scope, entry);
+                    item["key"] = json!(key_name);
+                    item["value"] = json!(display(entry)?);
+                    println!("{}", item);
+                }
+                Some((entry_scope, entry)) => {
+                    println!(
+                        "{}{}",
+                        provenance.prefix(*entry_scope, entry),
+                        display(entry)?
+                    );
+                }
+                // Like git, a missing key is not an error worth a message, just a non-zero exit.
+                None => std::process::exit(1),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn run_hash_object(
+    kind: ObjectKind,
+    write: bool,
+    stdin: bool,
+    files: &[PathBuf],
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::hash_object;
+
+    let odb = if write {
+        Some(hash_object::current_odb()?)
+    } else {
+        None
+    };
+    let inputs = stdin
+        .then_some(None)
+        .into_iter()
+        .chain(files.iter().map(|file| Some(file.as_path())));
+
+    let mut ids = Vec::new();
+    for input in inputs {
+        let data = hash_object::read_input(input)?;
+        let id = hash_object::hash(&data, kind, odb.as_ref())?;
+        if json {
+            ids.push(id.to_hex());
+        } else {
+            println!("{}", id);
+        }
+    }
+    if json {
+        println!("{}", json!({ "type": kind.name(), "ids": ids }));
+    }
+    Ok(())
+}
+
+/// What `cat-file` prints; clap guarantees exactly one flag.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CatFileQuery {
+    Pretty,
+    Type,
+    Size,
+    Exists,
+}
+
+impl CatFileQuery {
+    fn from_flags(kind: bool, size: bool, exists: bool) -> Self {
+        if exists {
+            CatFileQuery::Exists
+        } else if kind {
+            CatFileQuery::Type
+        } else if size {
+            CatFileQuery::Size
+        } else {
+            CatFileQuery::Pretty
+        }
+    }
+}
+
+fn run_cat_file(query: CatFileQuery, object: &str, json: bool) -> anyhow::Result<()> {
+    use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+    use commands::cat_file;
+    use std::io::Write;
+
+    let repo = Repository::current()?;
+    let odb = repo.odb()?;
+    let id = repo.resolve_revision(object)?;
+
+    if query == CatFileQuery::Exists {
+        // Like git, the answer is the exit status alone.
+        if !odb.contains(&id) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let (kind, data) = cat_file::read(&odb, &id)?;
+    match (query, json) {
+        (CatFileQuery::Type, false) => println!("{}", kind),
+        (CatFileQuery::Type, true) => {
+            println!("{}", json!({ "id": id.to_hex(), "type": kind.name() }))
+        }
+        (CatFileQuery::Size, false) => println!("{}", data.len()),
+        (CatFileQuery::Size, true) => {
+            println!("{}", json!({ "id": id.to_hex(), "size": data.len() }))
+        }
+        (_, true) => {
+            let mut item = json!({ "id": id.to_hex(), "type": kind.name(), "size": data.len() });
+            if kind == ObjectKind::Tree {
+                let entries: Vec<_> = Tree::parse(&data)?
+                    .entries()
+                    .iter()
+                    .map(|entry| {
+                        json!({
+                            "mode": format!("{:06o}", entry.mode.bits()),
+                            "type": entry.mode.kind().name(),
+                            "id": entry.id.to_hex(),
+                            "name": entry.name,
+                        })
+                    })
+                    .collect();
+                item["entries"] = json!(entries);
+            } else {
+                match String::from_utf8(data) {
+                    Ok(text) => item["content"] = json!(text),
+                    Err(e) => item["base64"] = json!(BASE64.encode(e.into_bytes())),
+                }
+            }
+            println!("{}", item);
+        }
+        (_, false) => {
+            // Note: Written as-is, so a blob without a final newline doesn't gain one.
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&cat_file::pretty(kind, data)?)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn run_write_tree(json: bool) -> anyhow::Result<()> {
+    let id = commands::write_tree::write_tree(&Repository::current()?)?;
+    print_id(&id, json);
+    Ok(())
+}
+
+fn run_commit_tree(
+    tree: &str,
+    parents: &[String],
+    messages: &[String],
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::{commit_tree, hash_object};
+
+    let repo = Repository::current()?;
+    let message = if messages.is_empty() {
+        String::from_utf8(hash_object::read_input(None)?)
+            .map_err(|_| anyhow::anyhow!("the commit message is not valid UTF-8"))?
+    } else {
+        commit_tree::join_messages(messages)
+    };
+    let id = commit_tree::commit_tree(&repo, tree, parents, message)?;
+    print_id(&id, json);
+    Ok(())
+}
+
+fn run_update_ref(
+    name: &str,
+    delete: bool,
+    new: Option<&str>,
+    old: Option<&str>,
+    message: Option<&str>,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::update_ref;
+
+    let repo = Repository::current()?;
+    let (target, previous, new) = if delete {
+        if old.is_some() {
+            anyhow::bail!("usage: update-ref -d <ref> [<old-value>]");
+        }
+        let (target, previous) = update_ref::delete_ref(&repo, name, new)?;
+        (target, previous, None)
+    } else {
+        let new = new
+            .ok_or_else(|| anyhow::anyhow!("usage: update-ref <ref> <new-value> [<old-value>]"))?;
+        let (target, previous) = update_ref::update_ref(&repo, name, new, old, message)?;
+        (target, previous, Some(new.to_lowercase()))
+    };
+
+    // Note: Like git, nothing is printed on success unless JSON is asked for.
+    if json {
+        println!("{}", json!({ "ref": target, "old": previous, "new": new }));
+    }
+    Ok(())
+}
+
+fn run_pack_refs(all: bool, json: bool) -> anyhow::Result<()> {
+    let repo = Repository::current()?;
+    let packed = commands::pack_refs::pack_refs(&repo, all)?;
+    // Note: Like git, nothing is printed on success unless JSON is asked for.
+    if json {
+        println!("{}", json!({ "packed": packed }));
+    }
+    Ok(())
+}
+
+/// The flags `symbolic-ref` was given besides the reflog message.
+struct SymbolicRefFlags {
+    quiet: bool,
+    short: bool,
+    recurse: bool,
+    delete: bool,
+}
+
+fn run_symbolic_ref(
+    name: &str,
+    target: Option<&str>,
+    message: Option<&str>,
+    flags: SymbolicRefFlags,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::symbolic_ref;
+
+    let repo = Repository::current()?;
+    if flags.delete {
+        if flags.quiet && symbolic_ref::read_symbolic_ref(&repo, name, false)?.is_none() {
+            std::process::exit(1);
+        }
+        return symbolic_ref::delete_symbolic_ref(&repo, name);
+    }
+    if let Some(target) = target {
+        return symbolic_ref::set_symbolic_ref(&repo, name, target, message);
+    }
+
+    let Some(target) = symbolic_ref::read_symbolic_ref(&repo, name, flags.recurse)? else {
+        if flags.quiet {
+            std::process::exit(1);
+        }
+        anyhow::bail!("ref {} is not a symbolic ref", name);
+    };
+    let shown = if flags.short {
+        symbolic_ref::short_name(&target)
+    } else {
+        &target
+    };
+    if json {
+        println!("{}", json!({ "name": name, "target": shown }));
+    } else {
+        println!("{}", shown);
+    }
+    Ok(())
+}
+
+/// Something `rev-parse` was asked for: a repository fact or a revision.
+enum RevParseQuery {
+    Cs01Dir,
+    ShowToplevel,
+    ShowPrefix,
+    ShowCdup,
+    IsBareRepository,
+    IsInsideWorkTree,
+    Revision(String),
+}
+
+/// The queries on the `rev-parse` command line, in the order they were given, which
+/// is the order git prints the answers in.
+fn rev_parse_queries(matches: &clap::ArgMatches) -> Vec<RevParseQuery> {
+    let flags = [
+        ("cs01_dir", RevParseQuery::Cs01Dir),
+        ("show_toplevel", RevParseQuery::ShowToplevel),
+        ("show_prefix", RevParseQuery::ShowPrefix),
+        ("show_cdup", RevParseQuery::ShowCdup),
+        ("is_bare_repository", RevParseQuery::IsBareRepository),
+        ("is_inside_work_tree", RevParseQuery::IsInsideWorkTree),
+    ];
+    let mut queries: Vec<(usize, RevParseQuery)> = flags
+        .into_iter()
+        .filter(|(id, _)| matches.get_flag(id))
+        .filter_map(|(id, query)| Some((matches.index_of(id)?, query)))
+        .collect();
+    if let (Some(indices), Some(revisions)) = (
+        matches.indices_of("revisions"),
+        matches.get_many::<String>("revisions"),
+    ) {
+        queries.extend(indices.zip(revisions.cloned().map(RevParseQuery::Revision)));
+    }
+    queries.sort_by_key(|(index, _)| *index);
+    queries.into_iter().map(|(_, query)| query).collect()
+}
+
+fn run_rev_parse(queries: &[RevParseQuery], json: bool) -> anyhow::Result<()> {
+    use commands::rev_parse;
+
+    let repo = Repository::current()?;
+    // Note: Everything is resolved before printing, so a bad revision prints nothing.
+    let mut facts = serde_json::Map::new();
+    let mut ids = Vec::new();
+    let mut lines = Vec::new();
+    for query in queries {
+        let (key, value) = match query {
+            RevParseQuery::Cs01Dir => {
+                let dir = rev_parse::absolute(repo.repo_dir());
+                ("cs01_dir", json!(dir))
+            }
+            RevParseQuery::ShowToplevel => {
+                let top = repo
+                    .work_tree()
+                    .ok_or_else(|| anyhow::anyhow!("this operation must be run in a work tree"))?;
+                ("toplevel", json!(rev_parse::absolute(top)))
+            }
+            RevParseQuery::ShowPrefix => {
+                let prefix = rev_parse::show_prefix(&repo, &std::env::current_dir()?)?;
+                ("prefix", json!(prefix))
+            }
+            RevParseQuery::ShowCdup => {
+                let cdup = rev_parse::show_cdup(&repo, &std::env::current_dir()?)?;
+                ("cdup", json!(cdup))
+            }
+            RevParseQuery::IsBareRepository => ("is_bare_repository", json!(repo.is_bare())),
+            RevParseQuery::IsInsideWorkTree => {
+                let inside = rev_parse::is_inside_work_tree(&repo, &std::env::current_dir()?);
+                ("is_inside_work_tree", json!(inside))
+            }
+            RevParseQuery::Revision(revision) => {
+                let id = repo.resolve_revision(revision)?.to_hex();
+                ids.push(id.clone());
+                lines.push(id);
+                continue;
+            }
+        };
+        lines.push(match &value {
+            serde_json::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        });
+        facts.insert(key.to_string(), value);
+    }
+
+    if json {
+        facts.insert("ids".to_string(), json!(ids));
+        println!("{}", serde_json::Value::Object(facts));
+    } else {
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn run_add(
+    paths: &[String],
+    options: &commands::add::AddOptions,
+    verbose: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    let repo = Repository::current()?;
+    let outcome = commands::add::add(&repo, &std::env::current_dir()?, paths, options)?;
+    let out = Output::default().with_json(json);
+    for path in &outcome.embedded {
+        out.warn(&format!("warning: adding embedded repository: {}", path));
+    }
+    if !outcome.embedded.is_empty() {
+        out.hint(
+            "hint: These directories hold repositories of their own, so none of their files\n\
+             hint: were staged. cs01 doesn't support submodules yet; commit in the embedded\n\
+             hint: repository itself, or add its directory to .cs01ignore to silence this.",
+        );
+    }
+    if json {
+        out.json(&json!({
+            "added": outcome.added,
+            "removed": outcome.removed,
+            "embedded": outcome.embedded,
+        }));
+    } else if verbose {
+        for path in &outcome.added {
+            println!("add '{}'", path);
+        }
+        for path in &outcome.removed {
+            println!("remove '{}'", path);
+        }
+    }
+    Ok(())
+}
+
+fn run_check_ignore(
+    paths: &[String],
+    verbose: bool,
+    stdin: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    let paths = if stdin {
+        std::io::stdin()
+            .lines()
+            .collect::<std::io::Result<Vec<String>>>()?
+    } else {
+        paths.to_vec()
+    };
+    let repo = Repository::current()?;
+    let checks = commands::check_ignore::check_ignore(&repo, &std::env::current_dir()?, &paths)?;
+
+    // Note: As in git, a negation counts as a match with -v, both for what is printed
+    // and for the exit status.
+    let shown: Vec<_> = checks
+        .iter()
+        .filter(|check| check.is_ignored() || (verbose && check.pattern.is_some()))
+        .collect();
+    if json {
+        let matches: Vec<_> = shown
+            .iter()
+            .map(|check| {
+                let pattern = check.pattern.as_ref().expect("shown paths have a pattern");
+                json!({
+                    "path": check.path,
+                    "ignored": check.is_ignored(),
+                    "source": check.source,
+                    "line": pattern.line,
+                    "pattern": pattern.text,
+                })
+            })
+            .collect();
+        println!("{}", json!({ "matches": matches }));
+    } else {
+        for check in &shown {
+            match &check.pattern {
+                Some(pattern) if verbose => println!(
+                    "{}:{}:{}\t{}",
+                    check.source, pattern.line, pattern.text, check.path
+                ),
+                _ => println!("{}", check.path),
+            }
+        }
+    }
+    // Like git, no match is not an error worth a message, just a non-zero exit.
+    if shown.is_empty() {
         std::process::exit(1);
     }
+    Ok(())
+}
+
+fn run_ls_files(
+    mut options: commands::ls_files::LsFilesOptions,
+    stage: bool,
+    full_name: bool,
+    nul: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    let repo = Repository::current()?;
+    // Note: Only what's in the current directory is listed, relative to it unless
+    // `--full-name` asks for paths from the top; JSON always has those.
+    options.under = repo
+        .work_tree_path(&std::env::current_dir()?, Path::new("."))
+        .unwrap_or_default();
+    let listed = commands::ls_files::ls_files(&repo, &options)?;
+    if json {
+        let files: Vec<_> = listed
+            .iter()
+            .map(|file| {
+                let mut item = json!({ "path": file.path, "listing": file.listing.name() });
+                if let Some(entry) = &file.entry {
+                    item["mode"] = json!(format!("{:06o}", entry.mode.bits()));
+                    item["oid"] = json!(entry.oid.to_hex());
+                    item["stage"] = json!(entry.stage());
+                }
+                item
+            })
+            .collect();
+        println!("{}", json!({ "files": files }));
+        return Ok(());
+    }
+
+    let terminator = if nul { '\0' } else { '\n' };
+    let mut out = String::new();
+    for file in &listed {
+        let path = if full_name {
+            file.path.clone()
+        } else {
+            commands::status::relative_to(&file.path, &options.under)
+        };
+        match &file.entry {
+            Some(entry) if stage => out.push_str(&format!(
+                "{:06o} {} {}\t{}",
+                entry.mode.bits(),
+                entry.oid,
+                entry.stage(),
+                path
+            )),
+            _ => out.push_str(&path),
+        }
+        out.push(terminator);
+    }
+    print!("{}", out);
+    Ok(())
+}
+
+fn run_diff(
+    staged: bool,
+    context: usize,
+    check: bool,
+    commits: &[String],
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::diff::Comparison;
+    use cs_01::modules::diff::{LineKind, PatchBody};
+
+    let comparison = match (staged, commits) {
+        (false, []) => Comparison::WorkTree,
+        (true, []) => Comparison::Staged(None),
+        (true, [commit]) => Comparison::Staged(Some(commit)),
+        (false, [commit]) => Comparison::WorkTreeAgainst(commit),
+        (false, [from, to]) => Comparison::Commits(from, to),
+        (true, _) => anyhow::bail!("--staged takes at most one commit"),
+        (false, _) => unreachable!("clap allows at most two commits"),
+    };
+    let repo = Repository::current()?;
+    let patches = commands::diff::diff(&repo, comparison, context)?;
+    let out = Output::default().with_json(json);
+    for patch in &patches {
+        if let PatchBody::TooLarge(_) = patch.body {
+            out.warn(&format!(
+                "warning: too many changed lines to diff {}; raise diff.maxChangedLines to see them",
+                patch.path
+            ));
+        }
+    }
+
+    if check {
+        return check_whitespace(&patches, &out);
+    }
+
+    if out.json {
+        let files: Vec<_> = patches
+            .iter()
+            .map(|patch| {
+                let hunks: Vec<_> = match &patch.body {
+                    PatchBody::Text { hunks, .. } => hunks
+                        .iter()
+                        .map(|hunk| {
+                            let lines: Vec<String> = hunk
+                                .lines
+                                .iter()
+                                .map(|line| format!("{}{}", line.kind.marker(), line.text))
+                                .collect();
+                            json!({ "header": hunk.header(), "lines": lines })
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                json!({
+                    "path": patch.path,
+                    "header": patch.header,
+                    "binary": matches!(patch.body, PatchBody::Binary(_)),
+                    "too_large": matches!(patch.body, PatchBody::TooLarge(_)),
+                    "hunks": hunks,
+                })
+            })
+            .collect();
+        out.json(&json!({ "files": files }));
+        return Ok(());
+    }
+
+    for patch in &patches {
+        for line in &patch.header {
+            println!("{}", line.bold());
+        }
+        match &patch.body {
+            PatchBody::Empty => {}
+            PatchBody::Text {
+                old_name,
+                new_name,
+                hunks,
+            } => {
+                println!("{}", format!("--- {}", old_name).bold());
+                println!("{}", format!("+++ {}", new_name).bold());
+                for hunk in hunks {
+                    println!("{}", hunk.header().cyan());
+                    for line in &hunk.lines {
+                        let text = format!("{}{}", line.kind.marker(), line.text);
+                        match line.kind {
+                            LineKind::Context => println!("{}", text),
+                            LineKind::Removed => println!("{}", text.red()),
+                            LineKind::Added => println!("{}", text.green()),
+                        }
+                        if line.missing_newline {
+                            println!("\\ No newline at end of file");
+                        }
+                    }
+                }
+            }
+            PatchBody::Binary(line) | PatchBody::TooLarge(line) => println!("{}", line),
+        }
+    }
+    Ok(())
+}
+
+/// Prints each whitespace error in the added lines of `patches` as `path:line: problem.`
+/// followed by the line, exiting with 2 (as git does) if there were any.
+fn check_whitespace(
+    patches: &[cs_01::modules::diff::FilePatch],
+    out: &Output,
+) -> anyhow::Result<()> {
+    use cs_01::modules::diff::PatchBody;
+
+    let mut found = Vec::new();
+    for patch in patches {
+        if let PatchBody::Text { hunks, .. } = &patch.body {
+            for hunk in hunks {
+                for (number, line, errors) in hunk.whitespace_errors() {
+                    found.push((patch.path.as_str(), number, line, errors));
+                }
+            }
+        }
+    }
+
+    if out.json {
+        let errors: Vec<_> = found
+            .iter()
+            .map(|(path, number, line, errors)| {
+                let problems: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                json!({ "path": path, "line": number, "text": line.text, "problems": problems })
+            })
+            .collect();
+        out.json(&json!({ "errors": errors }));
+    } else {
+        for (path, number, line, errors) in &found {
+            let problems: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            println!("{}:{}: {}.", path, number, problems.join(", "));
+            println!("{}", format!("+{}", line.text).red());
+        }
+    }
+    if !found.is_empty() {
+        return Err(WhitespaceErrorsFound { count: found.len() }.into());
+    }
+    Ok(())
+}
+
+fn run_status(short: bool, porcelain: bool, json: bool) -> anyhow::Result<()> {
+    use commands::status::{self, Change};
+
+    let repo = Repository::current()?;
+    let report = status::status(&repo)?;
+    let branch = match &report.head {
+        Some(HeadState::Branch(name)) => Some(name.as_str()),
+        _ => None,
+    };
+
+    if json {
+        let changes = |changes: &[(String, Change)]| -> Vec<serde_json::Value> {
+            changes
+                .iter()
+                .map(|(path, change)| json!({ "path": path, "change": change.name() }))
+                .collect()
+        };
+        println!(
+            "{}",
+            json!({
+                "branch": branch,
+                "head": report.head_commit.map(|id| id.to_hex()),
+                "staged": changes(&report.staged),
+                "unstaged": changes(&report.unstaged),
+                "unmerged": report
+                    .unmerged
+                    .iter()
+                    .map(|(path, state)| json!({ "path": path, "state": state.name() }))
+                    .collect::<Vec<_>>(),
+                "merging": report.merging,
+                "untracked": report.untracked,
+                "embedded": report.embedded,
+            })
+        );
+        return Ok(());
+    }
+
+    // Paths are shown relative to the current directory, as git does.
+    let cwd = repo
+        .work_tree_path(&std::env::current_dir()?, Path::new("."))
+        .unwrap_or_default();
+    if short || porcelain {
+        // Note: Scripts get paths from the top and no colors, wherever they run.
+        let base = if porcelain { "" } else { cwd.as_str() };
+        print_short_status(&report, base, !porcelain);
+        return Ok(());
+    }
+    let show = |path: &str| status::relative_to(path, &cwd);
+    let change_line = |(path, change): &(String, Change)| {
+        format!("{:<12}{}", format!("{}:", change.label()), show(path))
+    };
+
+    match (&report.head, &report.head_commit) {
+        (Some(HeadState::Branch(name)), None) => println!("No commits yet on {}", name),
+        (Some(HeadState::Branch(name)), Some(_)) => println!("On branch {}", name),
+        (Some(HeadState::OtherRef(name)), _) => println!("On {}", name),
+        (Some(HeadState::Detached(_)), Some(id)) => println!("HEAD detached at {}", id.short(7)),
+        _ => println!("Not currently on any branch."),
+    }
+    if report.merging && !report.unmerged.is_empty() {
+        println!("\nYou have unmerged paths.");
+        println!("  (fix conflicts and run \"cs01 commit\")");
+    } else if report.merging {
+        println!("\nAll conflicts fixed but you are still merging.");
+        println!("  (use \"cs01 commit\" to conclude merge)");
+    }
+
+    if !report.staged.is_empty() {
+        println!("\nChanges to be committed:");
+        for change in &report.staged {
+            println!("\t{}", change_line(change).green());
+        }
+    }
+    if !report.unmerged.is_empty() {
+        println!("\nUnmerged paths:");
+        println!("  (use \"cs01 add <file>...\" to mark resolution)");
+        for (path, state) in &report.unmerged {
+            let line = format!("{:<17}{}", format!("{}:", state.label()), show(path));
+            println!("\t{}", line.red());
+        }
+    }
+    if !report.unstaged.is_empty() {
+        println!("\nChanges not staged for commit:");
+        println!("  (use \"cs01 add <file>...\" to update what will be committed)");
+        for change in &report.unstaged {
+            println!("\t{}", change_line(change).red());
+        }
+    }
+    if !report.untracked.is_empty() {
+        println!("\nUntracked files:");
+        println!("  (use \"cs01 add <file>...\" to include in what will be committed)");
+        for path in &report.untracked {
+            // Note: `add` skips these, so say why they stay untracked.
+            let note = if report.embedded.binary_search(path).is_ok() {
+                " (embedded repository; not staged by add)"
+            } else {
+                ""
+            };
+            println!("\t{}{}", show(path).red(), note);
+        }
+    }
+
+    // Note: Like git, there is no summary once something is staged.
+    let summary = if !report.staged.is_empty() {
+        return Ok(());
+    } else if !report.unstaged.is_empty() || !report.unmerged.is_empty() {
+        "no changes added to commit (use \"cs01 add\")"
+    } else if !report.untracked.is_empty() {
+        "nothing added to commit but untracked files present (use \"cs01 add\" to track)"
+    } else if report.head_commit.is_none() {
+        "nothing to commit (create/copy files and use \"cs01 add\" to track)"
+    } else {
+        "nothing to commit, working tree clean"
+    };
+    println!("\n{}", summary);
+    Ok(())
+}
+
+/// Prints `report` as `status --short` does, one `XY path` line per path, sorted: X is
+/// the staged change and Y the unstaged one (a space for none), a conflicted path gets
+/// its two letters (`UU`, `AA`, ...), and untracked paths follow as `??`. Paths are
+/// shown relative to `base`.
+fn print_short_status(report: &commands::status::Status, base: &str, color: bool) {
+    use std::collections::BTreeMap;
+
+    // Each path's two letters, and whether it's conflicted
+    let mut lines: BTreeMap<&str, ([char; 2], bool)> = BTreeMap::new();
+    for (path, change) in &report.staged {
+        lines.entry(path).or_insert(([' ', ' '], false)).0[0] = change.code();
+    }
+    for (path, change) in &report.unstaged {
+        lines.entry(path).or_insert(([' ', ' '], false)).0[1] = change.code();
+    }
+    for (path, state) in &report.unmerged {
+        let mut code = state.code().chars();
+        let code = [code.next().unwrap_or(' '), code.next().unwrap_or(' ')];
+        lines.insert(path, (code, true));
+    }
+
+    let show = |path: &str| commands::status::relative_to(path, base);
+    for (path, ([x, y], unmerged)) in lines {
+        let (x, y) = (x.to_string(), y.to_string());
+        match (color, unmerged) {
+            (false, _) => println!("{}{} {}", x, y, show(path)),
+            (true, true) => println!("{}{} {}", x.red(), y.red(), show(path)),
+            (true, false) => println!("{}{} {}", x.green(), y.red(), show(path)),
+        }
+    }
+    for path in &report.untracked {
+        if color {
+            println!("{} {}", "??".red(), show(path));
+        } else {
+            println!("?? {}", show(path));
+        }
+    }
+}
+
+fn run_commit(
+    messages: &[String],
+    options: &commands::commit::CommitOptions,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::{commit, commit_tree};
+
+    let repo = Repository::current()?;
+    let message = (!messages.is_empty()).then(|| commit_tree::join_messages(messages));
+    let outcome = commit::commit(&repo, message.as_deref(), options)?;
+    let branch = match &outcome.head {
+        Some(HeadState::Branch(name)) => Some(name.as_str()),
+        _ => None,
+    };
+
+    if json {
+        println!(
+            "{}",
+            json!({
+                "id": outcome.id.to_hex(),
+                "branch": branch,
+                "root": outcome.root,
+                "summary": outcome.summary,
+            })
+        );
+        return Ok(());
+    }
+    let place = match &outcome.head {
+        Some(HeadState::Branch(name) | HeadState::OtherRef(name)) => name.as_str(),
+        _ => "detached HEAD",
+    };
+    let root = if outcome.root { " (root-commit)" } else { "" };
+    println!(
+        "[{}{} {}] {}",
+        place,
+        root,
+        outcome.id.short(7),
+        outcome.summary
+    );
+    Ok(())
+}
+
+fn run_rm(
+    paths: &[String],
+    options: &commands::rm::RmOptions,
+    quiet: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    let repo = Repository::current()?;
+    let removed = commands::rm::rm(&repo, &std::env::current_dir()?, paths, options)?;
+    if json {
+        println!("{}", json!({ "removed": removed }));
+    } else if !quiet {
+        for path in &removed {
+            println!("rm '{}'", path);
+        }
+    }
+    Ok(())
+}
+
+fn run_mv(paths: &[String], force: bool, verbose: bool, json: bool) -> anyhow::Result<()> {
+    let Some((destination, sources)) = paths.split_last() else {
+        anyhow::bail!("usage: mv <source>... <destination>");
+    };
+    let repo = Repository::current()?;
+    let renames = commands::mv::mv(
+        &repo,
+        &std::env::current_dir()?,
+        sources,
+        destination,
+        force,
+    )?;
+    if json {
+        let renames: Vec<_> = renames
+            .iter()
+            .map(|(from, to)| json!({ "from": from, "to": to }))
+            .collect();
+        println!("{}", json!({ "renamed": renames }));
+    } else if verbose {
+        for (from, to) in &renames {
+            println!("Renaming {} to {}", from, to);
+        }
+    }
+    Ok(())
+}
+
+fn run_switch(
+    create: Option<&str>,
+    detach: bool,
+    target: Option<&str>,
+    out: Output,
+) -> anyhow::Result<()> {
+    use commands::switch::{self, Target};
+
+    let target = match (create, detach, target) {
+        (Some(name), _, start) => Target::NewBranch { name, start },
+        (None, true, revision) => Target::Detached(revision.unwrap_or("HEAD")),
+        (None, false, Some(name)) => Target::Branch(name),
+        (None, false, None) => anyhow::bail!("missing branch or commit argument"),
+    };
+    let repo = Repository::current()?;
+    let previous = repo.head_ref()?;
+    let outcome = switch::switch(&repo, &target)?;
+
+    if out.json {
+        let branch = match &outcome.head {
+            HeadState::Branch(name) => Some(name.as_str()),
+            _ => None,
+        };
+        out.json(&json!({
+            "branch": branch,
+            "id": outcome.id.map(|id| id.to_hex()),
+            "created": outcome.created,
+            "unchanged": outcome.unchanged,
+        }));
+        return Ok(());
+    }
+    // Note: As with git's `-q`, quiet drops the advice too, not only the summary.
+    if out.quiet {
+        return Ok(());
+    }
+    print_local_changes(&repo)?;
+    if let (Target::Detached(revision), Some(HeadState::Branch(_))) = (target, &previous)
+        && repo
+            .config()?
+            .get_bool("advice.detachedhead")?
+            .unwrap_or(true)
+    {
+        out.warn(&detached_head_advice(revision));
+    }
+    match (&outcome.head, outcome.id) {
+        (HeadState::Branch(name), _) if outcome.unchanged => {
+            out.success(&format!("Already on '{}'", name))
+        }
+        (HeadState::Branch(name), _) if outcome.created => {
+            out.success(&format!("Switched to a new branch '{}'", name))
+        }
+        (HeadState::Branch(name), _) => out.success(&format!("Switched to branch '{}'", name)),
+        (_, Some(id)) => out.success(&format!(
+            "HEAD is now at {} {}",
+            id.short(7),
+            outcome.summary.as_deref().unwrap_or_default()
+        )),
+        _ => {}
+    }
+    if let HeadState::Branch(name) = &outcome.head
+        && let Some(tracking) = commands::branch::tracking(&repo, name)?
+    {
+        out.success(&tracking_message(&tracking));
+    }
+    Ok(())
+}
+
+/// What git tells someone who just left their branch for a detached HEAD at `revision`.
+fn detached_head_advice(revision: &str) -> String {
+    format!(
+        "Note: switching to '{}'.\n\n\
+         You are in 'detached HEAD' state. You can look around, make experimental\n\
+         changes and commit them, and you can discard any commits you make in this\n\
+         state without impacting any branches by switching back to a branch.\n\n\
+         If you want to create a new branch to retain commits you create, you may\n\
+         do so (now or later) by using -c with the switch command. Example:\n\n\
+         \x20 cs01 switch -c <new-branch-name>\n\n\
+         Turn off this advice by setting config variable advice.detachedHead to false\n",
+        revision
+    )
+}
+
+/// How the current branch compares to its upstream, worded as git words it.
+fn tracking_message(tracking: &commands::branch::Tracking) -> String {
+    let upstream = &tracking.upstream;
+    let commits = |count: usize| {
+        if count == 1 {
+            "1 commit".to_string()
+        } else {
+            format!("{} commits", count)
+        }
+    };
+    match tracking.counts {
+        None => format!(
+            "Your branch is based on '{}', but the upstream is gone.",
+            upstream
+        ),
+        Some((0, 0)) => format!("Your branch is up to date with '{}'.", upstream),
+        Some((ahead, 0)) => format!(
+            "Your branch is ahead of '{}' by {}.",
+            upstream,
+            commits(ahead)
+        ),
+        Some((0, behind)) => format!(
+            "Your branch is behind '{}' by {}, and can be fast-forwarded.",
+            upstream,
+            commits(behind)
+        ),
+        Some((ahead, behind)) => format!(
+            "Your branch and '{}' have diverged,\n\
+             and have {} and {} different commits each, respectively.",
+            upstream, ahead, behind
+        ),
+    }
+}
+
+fn run_reset(
+    soft: bool,
+    hard: bool,
+    args: &[String],
+    paths: &[String],
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::reset::{self, ResetMode};
+
+    let repo = Repository::current()?;
+    // Note: Without `--`, the first argument is the commit if it names one.
+    let (commit, paths) = match (args, paths) {
+        ([], paths) => (None, paths),
+        ([commit], paths) if !paths.is_empty() => (Some(commit.as_str()), paths),
+        (_, [_, ..]) => anyhow::bail!("usage: reset [<commit>] -- <paths>..."),
+        ([first, rest @ ..], []) if repo.resolve_revision(first).is_ok() => {
+            (Some(first.as_str()), rest)
+        }
+        (args, []) => (None, args),
+    };
+
+    if paths.is_empty() {
+        let mode = match (soft, hard) {
+            (true, _) => ResetMode::Soft,
+            (_, true) => ResetMode::Hard,
+            _ => ResetMode::Mixed,
+        };
+        let outcome = reset::reset(&repo, commit.unwrap_or("HEAD"), mode)?;
+        if json {
+            println!(
+                "{}",
+                json!({
+                    "id": outcome.id.to_hex(),
+                    "summary": outcome.summary,
+                    "updated": outcome.updated,
+                })
+            );
+            return Ok(());
+        }
+        match mode {
+            ResetMode::Soft => {}
+            ResetMode::Mixed => print_unstaged(&repo)?,
+            ResetMode::Hard => {
+                if !outcome.updated.is_empty() {
+                    println!("Updated working tree files:");
+                    for path in &outcome.updated {
+                        println!("\t{}", path);
+                    }
+                }
+                println!("HEAD is now at {} {}", outcome.id.short(7), outcome.summary);
+            }
+        }
+        return Ok(());
+    }
+
+    if soft || hard {
+        let mode = if soft { "soft" } else { "hard" };
+        anyhow::bail!("Cannot do {} reset with paths.", mode);
+    }
+    let changed = reset::reset_paths(&repo, commit, &std::env::current_dir()?, paths)?;
+    if json {
+        println!("{}", json!({ "reset": changed }));
+    } else {
+        print_unstaged(&repo)?;
+    }
+    Ok(())
+}
+
+fn run_merge(revision: &str, no_ff: bool, ff_only: bool, json: bool) -> anyhow::Result<()> {
+    use commands::merge::{self, FastForward, MergeOutcome};
+
+    let fast_forward = match (no_ff, ff_only) {
+        (true, _) => FastForward::Never,
+        (_, true) => FastForward::Only,
+        _ => FastForward::Allowed,
+    };
+    let repo = Repository::current()?;
+    let outcome = merge::merge(&repo, revision, fast_forward)?;
+
+    if json {
+        let value = match &outcome {
+            MergeOutcome::UpToDate => json!({ "result": "up-to-date" }),
+            MergeOutcome::FastForward { to, .. } => {
+                json!({ "result": "fast-forward", "id": to.to_hex() })
+            }
+            MergeOutcome::Merged { id, .. } => json!({ "result": "merge", "id": id.to_hex() }),
+            MergeOutcome::Conflicted { conflicts, .. } => json!({
+                "result": "conflict",
+                "conflicts": conflicts.iter().map(|c| c.path.as_str()).collect::<Vec<_>>(),
+            }),
+        };
+        println!("{}", value);
+    } else {
+        match &outcome {
+            MergeOutcome::UpToDate => println!("Already up to date."),
+            MergeOutcome::FastForward { from, to } => {
+                if let Some(from) = from {
+                    println!("Updating {}..{}", from.short(7), to.short(7));
+                }
+                println!("Fast-forward");
+            }
+            MergeOutcome::Merged { auto_merged, .. } => {
+                for path in auto_merged {
+                    println!("Auto-merging {}", path);
+                }
+                println!("Merge made by a three-way merge.");
+            }
+            MergeOutcome::Conflicted {
+                auto_merged,
+                conflicts,
+            } => {
+                for path in auto_merged {
+                    println!("Auto-merging {}", path);
+                }
+                print_conflicts(conflicts, ("HEAD", revision));
+            }
+        }
+    }
+    if matches!(outcome, MergeOutcome::Conflicted { .. }) {
+        anyhow::bail!("Automatic merge failed; fix conflicts and then commit the result.");
+    }
+    Ok(())
+}
+
+/// Prints a `CONFLICT` line for each path a merge couldn't resolve, naming the sides
+/// by `labels` (ours, theirs) as the conflict markers do.
+fn print_conflicts(conflicts: &[cs_01::modules::merge::Conflict], labels: (&str, &str)) {
+    use cs_01::modules::merge::ConflictKind;
+
+    for conflict in conflicts {
+        let path = &conflict.path;
+        match conflict.kind {
+            ConflictKind::Content => println!("CONFLICT (content): Merge conflict in {}", path),
+            ConflictKind::AddAdd => println!("CONFLICT (add/add): Merge conflict in {}", path),
+            ConflictKind::ModifyDelete { deleted_by_us } => {
+                let (deleted, kept) = if deleted_by_us {
+                    labels
+                } else {
+                    (labels.1, labels.0)
+                };
+                println!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                    path, deleted, kept, kept, path
+                );
+            }
+        }
+    }
+}
+
+fn run_stash(action: Option<&StashAction>, json: bool) -> anyhow::Result<()> {
+    use commands::stash;
+
+    // Note: An entry is `stash@{N}`, or just `N`, as in git.
+    let entry = |stash: &Option<String>| -> anyhow::Result<usize> {
+        let Some(stash) = stash else {
+            return Ok(0);
+        };
+        let n = stash
+            .strip_prefix("stash@{")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .unwrap_or(stash);
+        n.parse()
+            .map_err(|_| anyhow::anyhow!("'{}' is not a stash reference", stash))
+    };
+    let repo = Repository::current()?;
+    let push = StashAction::Push {
+        message: None,
+        include_untracked: false,
+    };
+
+    match action.unwrap_or(&push) {
+        StashAction::Push {
+            message,
+            include_untracked,
+        } => {
+            let saved = stash::stash_push(&repo, message.as_deref(), *include_untracked)?;
+            match (json, saved) {
+                (true, saved) => println!("{}", json!({ "saved": saved })),
+                (false, Some(message)) => {
+                    println!("Saved working directory and index state {}", message)
+                }
+                (false, None) => println!("No local changes to save"),
+            }
+        }
+        StashAction::List => {
+            let entries = stash::stash_list(&repo)?;
+            if json {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .enumerate()
+                    .map(|(n, entry)| {
+                        json!({
+                            "selector": format!("stash@{{{}}}", n),
+                            "id": entry.id.to_hex(),
+                            "message": entry.message,
+                        })
+                    })
+                    .collect();
+                println!("{}", json!({ "entries": entries }));
+            } else {
+                for (n, entry) in entries.iter().enumerate() {
+                    println!("stash@{{{}}}: {}", n, entry.message);
+                }
+            }
+        }
+        StashAction::Drop { stash } => {
+            let n = entry(stash)?;
+            let id = stash::stash_drop(&repo, n)?;
+            if json {
+                println!("{}", json!({ "dropped": id.to_hex() }));
+            } else {
+                println!("Dropped refs/stash@{{{}}} ({})", n, id);
+            }
+        }
+        StashAction::Pop { stash } | StashAction::Apply { stash } => {
+            let n = entry(stash)?;
+            let outcome = match action {
+                Some(StashAction::Pop { .. }) => stash::stash_pop(&repo, n)?,
+                _ => stash::stash_apply(&repo, n)?,
+            };
+            if json {
+                println!(
+                    "{}",
+                    json!({
+                        "conflicts": outcome
+                            .conflicts
+                            .iter()
+                            .map(|c| c.path.as_str())
+                            .collect::<Vec<_>>(),
+                        "dropped": outcome.dropped.map(|id| id.to_hex()),
+                    })
+                );
+            } else {
+                for path in &outcome.auto_merged {
+                    println!("Auto-merging {}", path);
+                }
+                print_conflicts(&outcome.conflicts, ("Updated upstream", "Stashed changes"));
+                if let Some(id) = outcome.dropped {
+                    println!("Dropped refs/stash@{{{}}} ({})", n, id);
+                }
+            }
+            if !outcome.conflicts.is_empty() {
+                anyhow::bail!("The stash entry is kept in case you need it again.");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the changes left in the working tree, as git does after a mixed reset.
+fn print_unstaged(repo: &Repository) -> anyhow::Result<()> {
+    let report = commands::status::status(repo)?;
+    if !report.unstaged.is_empty() {
+        println!("Unstaged changes after reset:");
+    }
+    for (path, change) in &report.unstaged {
+        println!("{}\t{}", change.code(), path);
+    }
+    Ok(())
+}
+
+/// Lists the local changes a switch carried over, staged or not, against the new HEAD.
+fn print_local_changes(repo: &Repository) -> anyhow::Result<()> {
+    use commands::status::Change;
+    use std::collections::BTreeMap;
+
+    let report = commands::status::status(repo)?;
+    let mut changes: BTreeMap<&str, Change> = BTreeMap::new();
+    for (path, change) in &report.staged {
+        changes.insert(path, *change);
+    }
+    // Note: What the working tree did on top decides only when the file is gone
+    // there; a file added and then deleted again was never in HEAD and isn't listed.
+    for (path, change) in &report.unstaged {
+        match (changes.get(path.as_str()), change) {
+            (Some(Change::Added), Change::Deleted) => {
+                changes.remove(path.as_str());
+            }
+            (None, _) | (_, Change::Deleted) => {
+                changes.insert(path, *change);
+            }
+            _ => {}
+        }
+    }
+    for (path, change) in changes {
+        println!("{}\t{}", change.code(), path);
+    }
+    Ok(())
+}
+
+fn run_branch(
+    name: Option<&str>,
+    start_point: Option<&str>,
+    delete: bool,
+    force: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::branch;
+
+    let repo = Repository::current()?;
+    match (name, delete) {
+        (Some(name), true) => {
+            if start_point.is_some() {
+                anyhow::bail!("usage: branch -d <name>");
+            }
+            let id = branch::delete_branch(&repo, name, force)?;
+            if json {
+                println!("{}", json!({ "deleted": name, "id": id.to_hex() }));
+            } else {
+                println!("Deleted branch {} (was {}).", name, id.short(7));
+            }
+        }
+        (None, true) => anyhow::bail!("branch name required"),
+        (Some(name), false) => {
+            let id = branch::create_branch(&repo, name, start_point)?;
+            // Note: Like git, nothing is printed on success unless JSON is asked for.
+            if json {
+                println!("{}", json!({ "branch": name, "id": id.to_hex() }));
+            }
+        }
+        (None, false) => {
+            let branches = branch::list_branches(&repo)?;
+            if json {
+                let branches: Vec<_> = branches
+                    .iter()
+                    .map(|b| json!({ "name": b.name, "id": b.id.to_hex(), "current": b.current }))
+                    .collect();
+                println!("{}", json!(branches));
+                return Ok(());
+            }
+            if let Some(HeadState::Detached(oid)) = repo.head_ref()? {
+                let detached = format!("(HEAD detached at {})", &oid[..7.min(oid.len())]);
+                println!("* {}", detached.green());
+            }
+            for branch in &branches {
+                if branch.current {
+                    println!("* {}", branch.name.green());
+                } else {
+                    println!("  {}", branch.name);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_log(
+    revision: Option<&str>,
+    max_count: Option<usize>,
+    decorate: Option<DecorateMode>,
+    filter: &DecorationFilter,
+    pretty: Pretty,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::log::{DecorationColors, expand_format, subject};
+    use std::io::IsTerminal;
+
+    let out = Output::default().with_json(json);
+    let repo = Repository::current()?;
+    let config = repo.config()?;
+    let decorate = match decorate {
+        Some(mode) => mode,
+        None => match config.get_string("log.decorate")? {
+            Some(value) => DecorateMode::parse(value)
+                .map_err(|_| anyhow::anyhow!("bad config value {} for 'log.decorate'", value))?,
+            None => DecorateMode::Auto,
+        },
+    }
+    .resolve(std::io::stdout().is_terminal());
+    let full = decorate == DecorateMode::Full;
+    let show_decorations = decorate != DecorateMode::No;
+
+    let commits = commands::log::log(&repo, revision, max_count)?;
+    let decorations = match show_decorations || pretty.uses_decorations() {
+        true => commands::log::decorations(&repo, filter)?,
+        false => Default::default(),
+    };
+    let names = |id| decorations.get(id).map(Vec::as_slice).unwrap_or_default();
+    if out.json {
+        let signature = |identity: &cs_01::modules::config::Identity| {
+            json!({
+                "name": identity.name,
+                "email": identity.email,
+                "date": identity.when.to_string(),
+            })
+        };
+        let commits: Vec<_> = commits
+            .iter()
+            .map(|(id, commit)| {
+                let mut value = json!({
+                    "id": id.to_hex(),
+                    "parents": commit.parents.iter().map(|p| p.to_hex()).collect::<Vec<_>>(),
+                    "tree": commit.tree.to_hex(),
+                    "author": signature(&commit.author),
+                    "committer": signature(&commit.committer),
+                    "message": commit.message,
+                });
+                if show_decorations {
+                    let refs: Vec<String> = names(id).iter().map(|d| d.label(full)).collect();
+                    value["decorations"] = json!(refs);
+                }
+                value
+            })
+            .collect();
+        out.json(&json!(commits));
+        return Ok(());
+    }
+
+    let colors = DecorationColors::from_config(&config)?;
+    let decoration = |id| match show_decorations {
+        true => colors.paint(names(id), full),
+        false => String::new(),
+    };
+    // Note: Output isn't paged; pipe it through `less` for long histories.
+    for (i, (id, commit)) in commits.iter().enumerate() {
+        match &pretty {
+            Pretty::Format {
+                template,
+                terminated,
+            } => {
+                if i > 0 && !terminated {
+                    println!();
+                }
+                print!("{}", expand_format(template, id, commit, names(id), full));
+                if *terminated {
+                    println!();
+                }
+            }
+            Pretty::Oneline { abbrev } => {
+                let hex = match abbrev {
+                    true => id.short(7),
+                    false => id.to_hex(),
+                };
+                println!(
+                    "{}{} {}",
+                    hex.yellow(),
+                    decoration(id),
+                    subject(&commit.message)
+                );
+            }
+            Pretty::Medium => {
+                if i > 0 {
+                    println!();
+                }
+                println!("{}{}", format!("commit {}", id).yellow(), decoration(id));
+                if commit.parents.len() > 1 {
+                    let parents: Vec<String> = commit.parents.iter().map(|p| p.short(7)).collect();
+                    println!("Merge: {}", parents.join(" "));
+                }
+                println!("Author: {}", commit.author);
+                println!("Date:   {}", commit.author.when.to_log_date());
+                println!();
+                for line in commit.message.lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_reflog(reference: Option<&str>, json: bool) -> anyhow::Result<()> {
+    let repo = Repository::current()?;
+    let entries = commands::reflog::reflog(&repo, reference)?;
+    let name = reference.unwrap_or("HEAD");
+    if json {
+        let entries: Vec<_> = entries
+            .iter()
+            .enumerate()
+            .map(|(n, entry)| {
+                json!({
+                    "selector": format!("{}@{{{}}}", name, n),
+                    "old": entry.old_oid,
+                    "new": entry.new_oid,
+                    "identity": entry.identity,
+                    "timestamp": entry.timestamp,
+                    "tz": entry.tz,
+                    "message": entry.message,
+                })
+            })
+            .collect();
+        println!("{}", json!({ "entries": entries }));
+        return Ok(());
+    }
+    for (n, entry) in entries.iter().enumerate() {
+        println!(
+            "{} {}@{{{}}}: {}",
+            entry.new_oid[..7].yellow(),
+            name,
+            n,
+            entry.message
+        );
+    }
+    Ok(())
+}
+
+/// Lists the index like `git ls-files --stage --debug`.
+fn run_debug_index(json: bool) -> anyhow::Result<()> {
+    let repo = Repository::current()?;
+    let index = Index::read(&repo.index_path())?;
+    if json {
+        let entries: Vec<_> = index
+            .entries()
+            .iter()
+            .map(|entry| {
+                json!({
+                    "mode": format!("{:06o}", entry.mode.bits()),
+                    "oid": entry.oid.to_hex(),
+                    "stage": entry.stage(),
+                    "path": entry.path,
+                    "ctime": [entry.ctime.seconds, entry.ctime.nanoseconds],
+                    "mtime": [entry.mtime.seconds, entry.mtime.nanoseconds],
+                    "dev": entry.dev,
+                    "ino": entry.ino,
+                    "uid": entry.uid,
+                    "gid": entry.gid,
+                    "size": entry.size,
+                    "flags": entry.flags,
+                })
+            })
+            .collect();
+        println!("{}", json!(entries));
+        return Ok(());
+    }
+
+    for entry in index.entries() {
+        println!(
+            "{:06o} {} {}\t{}",
+            entry.mode.bits(),
+            entry.oid,
+            entry.stage(),
+            entry.path
+        );
+        println!(
+            "  ctime: {}:{}",
+            entry.ctime.seconds, entry.ctime.nanoseconds
+        );
+        println!(
+            "  mtime: {}:{}",
+            entry.mtime.seconds, entry.mtime.nanoseconds
+        );
+        println!("  dev: {}\tino: {}", entry.dev, entry.ino);
+        println!("  uid: {}\tgid: {}", entry.uid, entry.gid);
+        println!("  size: {}\tflags: {:x}", entry.size, entry.flags);
+    }
+    Ok(())
+}
+
+/// The repository containing the current directory.
+fn print_id(id: &ObjectId, json: bool) {
+    if json {
+        println!("{}", json!({ "id": id.to_hex() }));
+    } else {
+        println!("{}", id);
+    }
+}
+
+fn run_clone(
+    source: &str,
+    destination: Option<&str>,
+    options: commands::clone::CloneOptions,
+    json: bool,
+) -> anyhow::Result<()> {
+    use commands::clone;
+
+    let source = Path::new(source);
+    let destination = match destination {
+        Some(destination) => PathBuf::from(destination),
+        None => clone::default_destination(source)?,
+    };
+    if !json {
+        let what = if options.bare { "bare repository " } else { "" };
+        eprintln!("Cloning into {}'{}'...", what, destination.display());
+    }
+    let outcome = clone::clone(source, &destination, options)?;
+
+    if json {
+        println!(
+            "{}",
+            json!({
+                "path": destination,
+                "bare": options.bare,
+                "branch": outcome.branch,
+                "head": outcome.head.map(|id| id.to_hex()),
+                "empty": outcome.empty,
+            })
+        );
+    } else if outcome.empty {
+        Output::default().warn("warning: You appear to have cloned an empty repository.");
+    }
+    Ok(())
+}
+
+fn print_init_outcome(outcome: &InitOutcome, out: &Output) {
+    let display_path = outcome.work_tree.as_ref().unwrap_or(&outcome.repo_dir);
+
+    if let Some(plan) = &outcome.plan {
+        print_init_plan(outcome, plan, out);
+        return;
+    }
+
+    if out.json {
+        out.json(&json!({
+            "action": if outcome.reinitialized { "reinitialized" } else { "initialized" },
+            "bare": outcome.bare,
+            "path": display_path,
+            "initial_branch": outcome.initial_branch,
+            "repo_dir": outcome.repo_dir,
+        }));
+        return;
+    }
+
+    let repo_type = if outcome.bare { "bare" } else { "standard" };
+
+    let folder_note = match &outcome.work_tree {
+        None => "".to_string(),
+        Some(work_tree) if outcome.repo_dir != work_tree.join(METADATA_DIR) => {
+            format!(" (metadata in {})", outcome.repo_dir.display())
+                .truecolor(128, 128, 128)
+                .to_string() // gray
+        }
+        Some(_) => format!(" (with {} directory)", METADATA_DIR)
+            .truecolor(128, 128, 128)
+            .to_string(), // gray
+    };
+
+    let (action, state) = if outcome.reinitialized {
+        ("Reinitialized", "existing")
+    } else {
+        ("Initialized", "empty")
+    };
+
+    let message = format!(
+        "{} {} {} CS01 repository in {}{}",
+        action,
+        state,
+        repo_type,
+        display_path.display(),
+        folder_note
+    );
+
+    out.success(&message);
+
+    if outcome.retargeted_head {
+        out.success(&format!(
+            "HEAD now points to the unborn branch '{}'",
+            outcome.initial_branch
+        ));
+    }
+}
+
+/// Renders what `init --dry-run` would do, one step per line.
+fn print_init_plan(outcome: &InitOutcome, plan: &[PlannedOp], out: &Output) {
+    let display_path = outcome.work_tree.as_ref().unwrap_or(&outcome.repo_dir);
+
+    if out.json {
+        let steps: Vec<_> = plan
+            .iter()
+            .map(|op| match op {
+                PlannedOp::CreateDir(path) => json!({ "op": "create_dir", "path": path }),
+                PlannedOp::WriteFile { path, bytes } => {
+                    json!({ "op": "write_file", "path": path, "bytes": bytes })
+                }
+                PlannedOp::CreateSymlink { path, target } => {
+                    json!({ "op": "create_symlink", "path": path, "target": target })
+                }
+                PlannedOp::RemoveFile(path) => json!({ "op": "remove_file", "path": path }),
+                PlannedOp::Skip { path, reason } => {
+                    json!({ "op": "skip", "path": path, "reason": reason })
+                }
+            })
+            .collect();
+        out.json(&json!({
+            "action": if outcome.reinitialized { "reinitialize" } else { "initialize" },
+            "dry_run": true,
+            "bare": outcome.bare,
+            "path": display_path,
+            "initial_branch": outcome.initial_branch,
+            "repo_dir": outcome.repo_dir,
+            "plan": steps,
+        }));
+        return;
+    }
+
+    let action = if outcome.reinitialized {
+        "Would reinitialize existing"
+    } else {
+        "Would initialize empty"
+    };
+    let repo_type = if outcome.bare { "bare" } else { "standard" };
+    out.success(&format!(
+        "{} {} CS01 repository in {}:",
+        action,
+        repo_type,
+        display_path.display()
+    ));
+    if !out.quiet {
+        for op in plan {
+            println!("  {}", op);
+        }
+    }
 }
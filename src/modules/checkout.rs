@@ -0,0 +1,444 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::modules::{
+    files::{link_target, replace_with_symlink, set_mode},
+    hash::{ObjectId, ObjectKind},
+    index::{Index, IndexEntry, index_mtime, is_unmodified},
+    objects::tree::EntryMode,
+    odb::Odb,
+    vfs::{RealFs, Vfs},
+};
+use crate::repo::Repository;
+
+/// Files by `/`-separated path, as `Tree::flatten` lists a tree.
+pub type Files = BTreeMap<String, (EntryMode, ObjectId)>;
+
+/// The command a checkout is part of, which words its refusal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Operation {
+    #[default]
+    Checkout,
+    Merge,
+}
+
+/// Local work a checkout refused to overwrite; nothing was changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WouldOverwrite {
+    /// Tracked paths whose staged or working tree content would be lost.
+    pub changed: Vec<String>,
+    /// Untracked files in the way of files the checkout would write.
+    pub untracked: Vec<String>,
+    pub operation: Operation,
+}
+
+impl fmt::Display for WouldOverwrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (command, action) = match self.operation {
+            Operation::Checkout => ("checkout", "switch branches"),
+            Operation::Merge => ("merge", "merge"),
+        };
+        if !self.changed.is_empty() {
+            writeln!(
+                f,
+                "Your local changes to the following files would be overwritten by {}:",
+                command
+            )?;
+            for path in &self.changed {
+                writeln!(f, "\t{}", path)?;
+            }
+            write!(
+                f,
+                "Please commit your changes or stash them before you {}.",
+                action
+            )?;
+        }
+        if !self.untracked.is_empty() {
+            if !self.changed.is_empty() {
+                writeln!(f)?;
+            }
+            writeln!(
+                f,
+                "The following untracked working tree files would be overwritten by {}:",
+                command
+            )?;
+            for path in &self.untracked {
+                writeln!(f, "\t{}", path)?;
+            }
+            write!(f, "Please move or remove them before you {}.", action)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WouldOverwrite {}
+
+/// Moves the working tree and `index` of `repo` from the files of `old` to those of
+/// `new`, as going from one commit to another does.
+///
+/// Only paths that differ between `old` and `new` are touched, so local changes to
+/// other files carry over. Such a path is taken over only if its staged entry matches
+/// one side and the file it replaces is unmodified, and untracked files are never
+/// overwritten; otherwise the checkout fails with a `WouldOverwrite` listing every
+/// offending path, before anything is changed.
+///
+/// Note: The caller holds the index lock and writes `index` out afterwards.
+pub fn check_out(repo: &Repository, index: &mut Index, old: &Files, new: &Files) -> Result<()> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_mtime = index_mtime(&repo.index_path());
+    let unmerged: HashSet<&str> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage() != 0)
+        .map(|entry| entry.path.as_str())
+        .collect();
+
+    let mut changed = BTreeSet::new();
+    let mut untracked = BTreeSet::new();
+    let mut updates = Vec::new();
+    let paths: BTreeSet<&str> = old.keys().chain(new.keys()).map(String::as_str).collect();
+    for path in paths {
+        let (before, after) = (old.get(path).copied(), new.get(path).copied());
+        if before == after {
+            continue;
+        }
+        if unmerged.contains(path) {
+            changed.insert(path.to_string());
+            continue;
+        }
+        let staged = index.get(path);
+        let staged_value = staged.map(|entry| (entry.mode, entry.oid));
+        // Note: Already staged as it will be, e.g. by hand; the working tree file,
+        // changed or not, is kept.
+        if staged_value == after {
+            continue;
+        }
+        let clean = match staged {
+            _ if staged_value != before => false,
+            Some(entry) => is_unmodified(work_tree, entry, filemode, index_mtime)?,
+            None => true,
+        };
+        if !clean {
+            changed.insert(path.to_string());
+            continue;
+        }
+        updates.push((path, after));
+    }
+
+    // Whatever is at or above the path of a new file must be going away with `old`.
+    let removed: HashSet<&str> = updates
+        .iter()
+        .filter(|(_, after)| after.is_none())
+        .map(|(path, _)| *path)
+        .collect();
+    let mut in_the_way = Vec::new();
+    for (path, after) in &updates {
+        if after.is_none() {
+            continue;
+        }
+        let parents = path.match_indices('/').map(|(i, _)| &path[..i]);
+        for relative in parents.chain([*path]) {
+            let full = work_tree.join(relative);
+            match fs::symlink_metadata(&full) {
+                Ok(metadata) if metadata.is_dir() && relative == *path => {
+                    files_in_the_way(&full, relative, &removed, &mut in_the_way)?
+                }
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) if relative == *path && index.get(relative).is_some() => {}
+                Ok(_) if !removed.contains(relative) => in_the_way.push(relative.to_string()),
+                _ => {}
+            }
+        }
+    }
+    for path in in_the_way {
+        if index.get(&path).is_some() {
+            changed.insert(path);
+        } else {
+            untracked.insert(path);
+        }
+    }
+    if !changed.is_empty() || !untracked.is_empty() {
+        return Err(WouldOverwrite {
+            changed: changed.into_iter().collect(),
+            untracked: untracked.into_iter().collect(),
+            operation: Operation::Checkout,
+        }
+        .into());
+    }
+
+    let odb = repo.odb()?;
+    for (path, _) in updates.iter().filter(|(_, after)| after.is_none()) {
+        remove_work_tree_file(work_tree, path)?;
+        index.remove(path);
+    }
+    for (path, after) in &updates {
+        if let Some((mode, oid)) = after {
+            let entry = write_file(&odb, work_tree, path, *mode, oid)?;
+            index.add_entry(entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes the working tree and `index` of `repo` hold exactly the files of `new`, as
+/// `reset --hard` does, and returns the paths whose working tree file was written or
+/// deleted, sorted.
+///
+/// Tracked files not in `new` are deleted and every file of `new` that doesn't already
+/// hold its content is written, whatever local changes that loses; untracked files and
+/// directories in the way are removed too. Unmerged entries are dropped.
+///
+/// Note: The caller holds the index lock and writes `index` out afterwards.
+pub fn force_check_out(repo: &Repository, index: &mut Index, new: &Files) -> Result<Vec<String>> {
+    let work_tree = repo
+        .work_tree()
+        .context("this operation must be run in a work tree")?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let index_mtime = index_mtime(&repo.index_path());
+    let odb = repo.odb()?;
+
+    let mut changed = BTreeSet::new();
+    let tracked: BTreeSet<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+    for path in tracked.iter().filter(|path| !new.contains_key(**path)) {
+        if fs::symlink_metadata(work_tree.join(path)).is_ok() {
+            changed.insert(path.to_string());
+        }
+        remove_work_tree_file(work_tree, path)?;
+    }
+
+    let mut result = Index::with_version(index.version())?;
+    for (path, (mode, oid)) in new {
+        // Note: A file that already holds what `new` has is kept, whatever the index
+        // said about it, and only its stat data is refreshed.
+        let mut entry = match index.get(path) {
+            Some(entry) if (entry.mode, entry.oid) == (*mode, *oid) => entry.clone(),
+            _ => IndexEntry::new(path.as_str(), *mode, *oid),
+        };
+        if let Ok(metadata) = fs::symlink_metadata(work_tree.join(path))
+            && is_unmodified(work_tree, &entry, filemode, index_mtime)?
+        {
+            entry.set_stat(&metadata);
+            result.add_entry(entry)?;
+            continue;
+        }
+        // Note: A file where one of the path's directories goes is in the way.
+        for (i, _) in path.match_indices('/') {
+            let full = work_tree.join(&path[..i]);
+            if fs::symlink_metadata(&full).is_ok_and(|m| !m.is_dir()) {
+                fs::remove_file(&full).with_context(|| format!("Failed to remove {:?}", full))?;
+            }
+        }
+        result.add_entry(write_file(&odb, work_tree, path, *mode, oid)?)?;
+        changed.insert(path.clone());
+    }
+    *index = result;
+    Ok(changed.into_iter().collect())
+}
+
+/// Collects the files under the directory `dir` (at `relative`) that the checkout
+/// doesn't remove, and so would be lost if a file took the directory's place.
+fn files_in_the_way(
+    dir: &Path,
+    relative: &str,
+    removed: &HashSet<&str>,
+    found: &mut Vec<String>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", dir))?;
+        let child = format!("{}/{}", relative, entry.file_name().to_string_lossy());
+        let file_type = entry
+            .file_type()
+            .with_context(|| format!("Failed to read {:?}", entry.path()))?;
+        if file_type.is_dir() {
+            files_in_the_way(&entry.path(), &child, removed, found)?;
+        } else if !removed.contains(child.as_str()) {
+            found.push(child);
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the working tree file at `path`, if still there, and the directories it
+/// leaves empty.
+pub fn remove_work_tree_file(work_tree: &Path, path: &str) -> Result<()> {
+    let full = work_tree.join(path);
+    let removal = match fs::symlink_metadata(&full) {
+        // Note: A submodule's directory goes only if it's empty.
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&full).or(Ok(())),
+        Ok(_) => fs::remove_file(&full),
+        Err(_) => Ok(()),
+    };
+    removal.with_context(|| format!("Failed to remove {:?}", full))?;
+
+    let mut dir = full.parent();
+    while let Some(current) = dir {
+        if current == work_tree || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+/// Writes the blob `oid` to `path` in the working tree as a file of `mode`, and returns
+/// its index entry, with the stat data of what was written.
+pub fn write_file(
+    odb: &Odb,
+    work_tree: &Path,
+    path: &str,
+    mode: EntryMode,
+    oid: &ObjectId,
+) -> Result<IndexEntry> {
+    let full = work_tree.join(path);
+    if let Some(parent) = full.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+    }
+    // Note: A directory in the way holds nothing by now; its files were removed.
+    if mode != EntryMode::Commit && fs::symlink_metadata(&full).is_ok_and(|m| m.is_dir()) {
+        fs::remove_dir_all(&full).with_context(|| format!("Failed to remove {:?}", full))?;
+    }
+
+    match mode {
+        EntryMode::Commit => {
+            fs::create_dir_all(&full).with_context(|| format!("Failed to create {:?}", full))?
+        }
+        _ => {
+            let (kind, data) = odb.read(oid)?;
+            if kind != ObjectKind::Blob {
+                anyhow::bail!("object {} is a {}, not a blob", oid, kind);
+            }
+            if mode == EntryMode::Symlink {
+                replace_with_symlink(&link_target(&data), &full)?;
+            } else {
+                // Note: Set either way, since a replaced file passes its bits on.
+                RealFs.replace(&full, &data)?;
+                let bits = if mode == EntryMode::Executable {
+                    0o755
+                } else {
+                    0o644
+                };
+                set_mode(&full, bits)?;
+            }
+        }
+    }
+
+    let metadata =
+        fs::symlink_metadata(&full).with_context(|| format!("Failed to read {:?}", full))?;
+    let mut entry = IndexEntry::new(path, mode, *oid);
+    entry.set_stat(&metadata);
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::objects::tree::Tree;
+    use crate::repo::InitOptions;
+    use tempfile::tempdir;
+
+    fn files(odb: &Odb, list: &[(&str, EntryMode, &str)]) -> Files {
+        list.iter()
+            .map(|(path, mode, content)| {
+                let oid = odb.write(ObjectKind::Blob, content.as_bytes()).unwrap();
+                (path.to_string(), (*mode, oid))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_check_out_moves_between_trees() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path(), InitOptions::default()).unwrap();
+        let odb = repo.odb().unwrap();
+        let old = files(
+            &odb,
+            &[
+                ("a.txt", EntryMode::File, "a\n"),
+                ("gone.txt", EntryMode::File, "gone\n"),
+                ("swap/inner.txt", EntryMode::File, "inner\n"),
+            ],
+        );
+        let new = files(
+            &odb,
+            &[
+                ("a.txt", EntryMode::File, "changed\n"),
+                ("new/deep/b.txt", EntryMode::Executable, "b\n"),
+                ("swap", EntryMode::File, "now a file\n"),
+            ],
+        );
+
+        let mut index = Index::default();
+        check_out(&repo, &mut index, &Files::new(), &old).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("gone.txt")).unwrap(),
+            "gone\n"
+        );
+        let tree = index.write_tree(&odb).unwrap();
+        assert_eq!(Tree::flatten(&odb, &tree).unwrap(), old);
+
+        check_out(&repo, &mut index, &old, &new).unwrap();
+        let tree = index.write_tree(&odb).unwrap();
+        assert_eq!(Tree::flatten(&odb, &tree).unwrap(), new);
+        assert!(!dir.path().join("gone.txt").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("swap")).unwrap(),
+            "now a file\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new/deep/b.txt")).unwrap(),
+            "b\n"
+        );
+
+        // A local change to a file that differs is refused, and nothing moves
+        fs::write(dir.path().join("a.txt"), "local\n").unwrap();
+        fs::write(dir.path().join("gone.txt"), "untracked\n").unwrap();
+        let error = check_out(&repo, &mut index, &new, &old).unwrap_err();
+        let conflict = error.downcast_ref::<WouldOverwrite>().unwrap();
+        assert_eq!(conflict.changed, ["a.txt"]);
+        assert_eq!(conflict.untracked, ["gone.txt"]);
+        assert!(dir.path().join("new/deep/b.txt").exists());
+    }
+
+    #[test]
+    fn test_force_check_out_discards_local_changes() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path(), InitOptions::default()).unwrap();
+        let odb = repo.odb().unwrap();
+        let old = files(
+            &odb,
+            &[
+                ("a.txt", EntryMode::File, "a\n"),
+                ("gone.txt", EntryMode::File, "gone\n"),
+                ("same.txt", EntryMode::File, "same\n"),
+            ],
+        );
+        let new = files(
+            &odb,
+            &[
+                ("a.txt", EntryMode::File, "a\n"),
+                ("same.txt", EntryMode::File, "same\n"),
+                ("untracked/b.txt", EntryMode::File, "b\n"),
+            ],
+        );
+        let mut index = Index::default();
+        check_out(&repo, &mut index, &Files::new(), &old).unwrap();
+
+        fs::write(dir.path().join("a.txt"), "local\n").unwrap();
+        fs::write(dir.path().join("untracked"), "in the way\n").unwrap();
+        let changed = force_check_out(&repo, &mut index, &new).unwrap();
+        assert_eq!(changed, ["a.txt", "gone.txt", "untracked/b.txt"]);
+        assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "a\n");
+        assert!(!dir.path().join("gone.txt").exists());
+        let tree = index.write_tree(&odb).unwrap();
+        assert_eq!(Tree::flatten(&odb, &tree).unwrap(), new);
+
+        // Nothing left to change
+        assert!(force_check_out(&repo, &mut index, &new).unwrap().is_empty());
+    }
+}
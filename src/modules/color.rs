@@ -0,0 +1,164 @@
+use anyhow::{Result, bail};
+use colored::{Color, ColoredString, Colorize};
+
+/// A color from the config, such as `color.decorate.branch = "bold green"`: up to two
+/// colors (foreground, then background) and any number of attributes, in git's syntax.
+///
+/// Note: Colors are the eight basic names, their `bright` variants, `normal`/`default`
+/// for "unchanged" and `#rrggbb`; git's 256-color numbers aren't supported. Attributes
+/// can be negated with a `no` prefix (e.g. `nobold`), which just leaves them off.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColorSpec {
+    foreground: Option<Color>,
+    background: Option<Color>,
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Attribute {
+    Bold,
+    Dim,
+    Italic,
+    Underline,
+    Blink,
+    Reverse,
+    Strike,
+}
+
+impl ColorSpec {
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut spec = ColorSpec::default();
+        let mut colors = 0;
+        for word in value.split_whitespace() {
+            let word = word.to_ascii_lowercase();
+            if let Some(color) = parse_color(&word) {
+                match colors {
+                    0 => spec.foreground = color,
+                    1 => spec.background = color,
+                    _ => bail!("invalid color value: {}", value),
+                }
+                colors += 1;
+            } else if let Some(attribute) = parse_attribute(&word) {
+                spec.attributes.push(attribute);
+            } else {
+                // A negated attribute is accepted and left off
+                let negated = word
+                    .strip_prefix("no")
+                    .map(|rest| rest.strip_prefix('-').unwrap_or(rest));
+                if negated.and_then(parse_attribute).is_none() {
+                    bail!("invalid color value: {}", value);
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    /// `text` in this color; plain when colors are off (e.g. stdout isn't a terminal).
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut painted = text.normal();
+        if let Some(color) = self.foreground {
+            painted = painted.color(color);
+        }
+        if let Some(color) = self.background {
+            painted = painted.on_color(color);
+        }
+        for attribute in &self.attributes {
+            painted = match attribute {
+                Attribute::Bold => painted.bold(),
+                Attribute::Dim => painted.dimmed(),
+                Attribute::Italic => painted.italic(),
+                Attribute::Underline => painted.underline(),
+                Attribute::Blink => painted.blink(),
+                Attribute::Reverse => painted.reversed(),
+                Attribute::Strike => painted.strikethrough(),
+            };
+        }
+        painted
+    }
+}
+
+/// `Some(None)` for the "unchanged" colors, `None` for anything that isn't a color.
+fn parse_color(word: &str) -> Option<Option<Color>> {
+    if matches!(word, "normal" | "default") {
+        return Some(None);
+    }
+    if let Some(hex) = word.strip_prefix('#') {
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        };
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Some(Some(Color::TrueColor { r, g, b })),
+            _ => None,
+        };
+    }
+    let (bright, name) = match word.strip_prefix("bright") {
+        Some(name) => (true, name),
+        None => (false, word),
+    };
+    let color = match (name, bright) {
+        ("black", false) => Color::Black,
+        ("red", false) => Color::Red,
+        ("green", false) => Color::Green,
+        ("yellow", false) => Color::Yellow,
+        ("blue", false) => Color::Blue,
+        ("magenta", false) => Color::Magenta,
+        ("cyan", false) => Color::Cyan,
+        ("white", false) => Color::White,
+        ("black", true) => Color::BrightBlack,
+        ("red", true) => Color::BrightRed,
+        ("green", true) => Color::BrightGreen,
+        ("yellow", true) => Color::BrightYellow,
+        ("blue", true) => Color::BrightBlue,
+        ("magenta", true) => Color::BrightMagenta,
+        ("cyan", true) => Color::BrightCyan,
+        ("white", true) => Color::BrightWhite,
+        _ => return None,
+    };
+    Some(Some(color))
+}
+
+fn parse_attribute(word: &str) -> Option<Attribute> {
+    match word {
+        "bold" => Some(Attribute::Bold),
+        "dim" => Some(Attribute::Dim),
+        "italic" => Some(Attribute::Italic),
+        "ul" | "underline" => Some(Attribute::Underline),
+        "blink" => Some(Attribute::Blink),
+        "reverse" => Some(Attribute::Reverse),
+        "strike" => Some(Attribute::Strike),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color_spec() {
+        let spec = ColorSpec::parse("bold Green").unwrap();
+        assert_eq!(spec.foreground, Some(Color::Green));
+        assert_eq!(spec.attributes, [Attribute::Bold]);
+
+        let spec = ColorSpec::parse("normal brightblue ul nobold").unwrap();
+        assert_eq!(spec.foreground, None);
+        assert_eq!(spec.background, Some(Color::BrightBlue));
+        assert_eq!(spec.attributes, [Attribute::Underline]);
+
+        let spec = ColorSpec::parse("#ff8000").unwrap();
+        assert_eq!(
+            spec.foreground,
+            Some(Color::TrueColor {
+                r: 255,
+                g: 128,
+                b: 0
+            })
+        );
+        assert_eq!(ColorSpec::parse("").unwrap(), ColorSpec::default());
+
+        for value in ["purple", "red green blue", "#ff80", "bold-ish", "208"] {
+            assert!(ColorSpec::parse(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -1,5 +1,10 @@
-use anyhow::{Result, bail};
-use serde_json::Value;
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::modules::objects::commit::Timestamp;
+use crate::modules::vfs::{RealFs, Vfs};
 
 /// Converts a JSON Object into a Git-compatible INI string.
 ///
@@ -35,29 +40,35 @@ pub fn obj_to_str(config_obj: &Value) -> Result<String> {
                 )
             })?;
 
-            // Note: Git config format uses [section "subsection"] syntax.
-            // If subsection_name is empty, it formats as [section]
-            let quoted_subsection = if subsection_name.is_empty() {
-                "".to_string()
-            } else {
-                format!(" \"{}\"", subsection_name)
-            };
-
-            output.push_str(&format!("[{}{}]\n", section_name, quoted_subsection));
+            validate_section(section_name, subsection_name)?;
+            output.push_str(&section_header(section_name, subsection_name));
+            output.push('\n');
 
             for (key, val) in settings {
-                // Critical: We must handle different JSON types to match Git's string expectation.
-                // - Objects/Arrays are serialized to JSON strings.
-                // - Primitives are converted directly.
-                let string_value = if val.is_object() {
-                    serde_json::to_string(val)?
-                } else if val.is_string() {
-                    val.as_str().unwrap().to_string()
-                } else {
-                    val.to_string()
+                validate_names(section_name, subsection_name, key)?;
+
+                // Note: An array is a multi-valued key (e.g. several `remote.*.fetch`
+                // refspecs), written as one line per element, in order.
+                let values = match val.as_array() {
+                    Some(values) => values.iter().collect(),
+                    None => vec![val],
                 };
 
-                output.push_str(&format!("  {} = {}\n", key, string_value));
+                for val in values {
+                    // Critical: We must handle different JSON types to match Git's string expectation.
+                    // - Objects/Arrays are serialized to JSON strings.
+                    // - Primitives are converted directly.
+                    let string_value = if val.is_object() || val.is_array() {
+                        serde_json::to_string(val)?
+                    } else if val.is_string() {
+                        val.as_str().unwrap().to_string()
+                    } else {
+                        val.to_string()
+                    };
+
+                    output.push_str(&setting_line(key, &string_value));
+                    output.push('\n');
+                }
             }
         }
     }
@@ -65,6 +76,1387 @@ pub fn obj_to_str(config_obj: &Value) -> Result<String> {
     Ok(output)
 }
 
+/// Formats a section header line, e.g. `[remote "origin"]`.
+///
+/// Note: Git config format uses [section "subsection"] syntax.
+/// If `subsection` is empty, it formats as [section].
+fn section_header(section: &str, subsection: &str) -> String {
+    if subsection.is_empty() {
+        format!("[{}]", section)
+    } else {
+        format!(
+            "[{} \"{}\"]",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    }
+}
+
+/// Formats a setting line as written by `obj_to_str`, quoting the value if needed.
+fn setting_line(key: &str, value: &str) -> String {
+    format!("  {} = {}", key, quote_value(value))
+}
+
+/// Whether `section` is a legal section name: letters, digits, `-` and `.`.
+fn is_valid_section_name(section: &str) -> bool {
+    !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+/// Whether `key` is a legal variable name: a letter, then letters, digits and `-`.
+fn is_valid_key_name(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Checks that a section header can be written and read back unchanged.
+///
+/// Note: Subsections are written quoted, so anything goes except line breaks and NUL.
+fn validate_section(section: &str, subsection: &str) -> Result<()> {
+    if !is_valid_section_name(section) {
+        bail!(
+            "invalid section name '{}': only letters, digits, '-' and '.' are allowed",
+            section
+        );
+    }
+    if subsection.contains(['\n', '\r', '\0']) {
+        bail!(
+            "invalid subsection name {:?} in [{}]: line breaks are not allowed",
+            subsection,
+            section
+        );
+    }
+    Ok(())
+}
+
+/// Checks that a setting can be written and read back unchanged, naming the offending part.
+fn validate_names(section: &str, subsection: &str, key: &str) -> Result<()> {
+    validate_section(section, subsection)?;
+    if !is_valid_key_name(key) {
+        bail!(
+            "invalid key name '{}' in {}: must start with a letter and contain only letters, digits and '-'",
+            key,
+            section_header(section, subsection)
+        );
+    }
+    Ok(())
+}
+
+/// Quotes a value for writing when `str_to_obj` would otherwise read it back differently.
+///
+/// Note: Surrounding whitespace is trimmed and `#`/`;` start a comment when unquoted,
+/// and backslashes, quotes and control characters always need escaping.
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.trim() != value
+        || value
+            .chars()
+            .any(|c| matches!(c, '#' | ';' | '"' | '\\' | '\n' | '\t' | '\u{8}'));
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{8}' => quoted.push_str("\\b"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parses the right-hand side of a setting the way Git does.
+///
+/// Note: Double quotes may cover all or part of the value and keep whitespace and
+/// `#`/`;` literal. Unquoted trailing whitespace is dropped, internal whitespace kept.
+fn parse_value(raw: &str, line_no: usize) -> Result<String> {
+    let mut value = String::new();
+    let mut pending_space = String::new();
+    let mut in_quotes = false;
+    let mut chars = raw.trim_start().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' | ';' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            '"' => {
+                value.push_str(&std::mem::take(&mut pending_space));
+                in_quotes = !in_quotes;
+            }
+            '\\' => {
+                value.push_str(&std::mem::take(&mut pending_space));
+                value.push(match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some(other) => bail!("line {}: invalid escape sequence '\\{}'", line_no, other),
+                    None => bail!("line {}: line continuations are not supported", line_no),
+                });
+            }
+            c => {
+                value.push_str(&std::mem::take(&mut pending_space));
+                value.push(c);
+            }
+        }
+    }
+
+    if in_quotes {
+        bail!("line {}: unterminated quoted value", line_no);
+    }
+
+    Ok(value)
+}
+
+/// Parses a section header such as `core` or `remote "origin"` (without the brackets).
+fn parse_section_header(header: &str, line_no: usize) -> Result<(String, String)> {
+    let invalid = || anyhow::anyhow!("line {}: invalid section header '[{}]'", line_no, header);
+
+    let (section, subsection) = match header.split_once(' ') {
+        Some((section, rest)) => {
+            let quoted = rest
+                .trim()
+                .strip_prefix('"')
+                .and_then(|r| r.strip_suffix('"'))
+                .ok_or_else(invalid)?;
+
+            // Inside the quotes, a backslash escapes the next character (`\"` and `\\`).
+            let mut subsection = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => subsection.push(chars.next().ok_or_else(invalid)?),
+                    '"' => return Err(invalid()),
+                    c => subsection.push(c),
+                }
+            }
+            (section, subsection)
+        }
+        None => (header, String::new()),
+    };
+
+    if !is_valid_section_name(section) {
+        return Err(invalid());
+    }
+
+    Ok((section.to_lowercase(), subsection))
+}
+
+/// Splits the text after `[` at the closing `]`, skipping over a quoted subsection.
+fn split_header(header: &str) -> Option<(&str, &str)> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, c) in header.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ']' if !in_quotes => return Some((&header[..index], &header[index + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A single line of a config file, classified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ConfigLine {
+    /// A blank line or a comment.
+    Other,
+    /// A section header; `section` is lowercased, `subsection` kept as written.
+    Section { section: String, subsection: String },
+    /// A `key = value` line; `key` is lowercased, `value` unquoted and unescaped.
+    Setting { key: String, value: String },
+}
+
+fn parse_line(raw_line: &str, line_no: usize) -> Result<ConfigLine> {
+    let line = raw_line.trim();
+
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+        return Ok(ConfigLine::Other);
+    }
+
+    if let Some(header) = line.strip_prefix('[') {
+        // Note: A comment may follow the closing bracket, e.g. `[core] # defaults`.
+        // A `]` inside a quoted subsection doesn't close the header.
+        let (header, trailing) = split_header(header).ok_or_else(|| {
+            anyhow::anyhow!("line {}: invalid section header '{}'", line_no, line)
+        })?;
+        let trailing = trailing.trim();
+        if !(trailing.is_empty() || trailing.starts_with('#') || trailing.starts_with(';')) {
+            bail!(
+                "line {}: unexpected text after section header '{}'",
+                line_no,
+                line
+            );
+        }
+
+        let (section, subsection) = parse_section_header(header, line_no)?;
+        return Ok(ConfigLine::Section {
+            section,
+            subsection,
+        });
+    }
+
+    // Note: Only the first `=` separates key from value; URLs and the like may contain more.
+    // A key without any `=` is git's shorthand for a true boolean.
+    let (key, raw_value) = line.split_once('=').unwrap_or((line, "true"));
+
+    let key = key.trim();
+    if !is_valid_key_name(key) {
+        bail!("line {}: invalid key '{}'", line_no, key);
+    }
+
+    Ok(ConfigLine::Setting {
+        key: key.to_lowercase(),
+        value: parse_value(raw_value, line_no)?,
+    })
+}
+
+/// Parses a Git-style INI string back into the JSON shape consumed by `obj_to_str`.
+///
+/// Note: Section and key names are case-insensitive in Git, so they are normalized
+/// to lowercase. Subsection names are case-sensitive and kept as written.
+/// All values are returned as JSON strings, unquoted and unescaped.
+/// A key that appears more than once becomes an array of its values,
+/// and a bare key without `=` reads as `true`.
+/// Syntax errors name the offending line (1-based).
+pub fn str_to_obj(content: &str) -> Result<Value> {
+    Ok(ConfigDocument::parse(content)?.to_value())
+}
+
+/// Formats a key for messages, e.g. `remote.origin.url` or `core.bare`.
+fn key_name(section: &str, subsection: &str, key: &str) -> String {
+    if subsection.is_empty() {
+        format!("{}.{}", section, key)
+    } else {
+        format!("{}.{}.{}", section, subsection, key)
+    }
+}
+
+/// A config file kept line by line, so edits leave comments, blank lines and
+/// formatting of everything they don't touch exactly as they were.
+///
+/// `obj_to_str` generates fresh files; this type is for changing existing ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDocument {
+    /// Every line as written (without the newline), with its classification.
+    lines: Vec<(String, ConfigLine)>,
+}
+
+impl ConfigDocument {
+    /// Parses `content`, rejecting it with the offending line number if any line is malformed.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut lines = Vec::new();
+        let mut in_section = false;
+
+        for (index, raw_line) in content.lines().enumerate() {
+            let line_no = index + 1;
+            let parsed = parse_line(raw_line, line_no)?;
+
+            match parsed {
+                ConfigLine::Section { .. } => in_section = true,
+                ConfigLine::Setting { .. } if !in_section => bail!(
+                    "line {}: setting outside of any section: '{}'",
+                    line_no,
+                    raw_line.trim()
+                ),
+                _ => {}
+            }
+
+            lines.push((raw_line.to_string(), parsed));
+        }
+
+        Ok(ConfigDocument { lines })
+    }
+
+    /// Pairs every line index with the section it belongs to.
+    fn sections(&self) -> impl Iterator<Item = (usize, Option<(&str, &str)>, &ConfigLine)> {
+        let mut current = None;
+        self.lines
+            .iter()
+            .enumerate()
+            .map(move |(index, (_, line))| {
+                if let ConfigLine::Section {
+                    section,
+                    subsection,
+                } = line
+                {
+                    current = Some((section.as_str(), subsection.as_str()));
+                }
+                (index, current, line)
+            })
+    }
+
+    /// Line indices holding `section.subsection.key`, in file order.
+    fn setting_indices(&self, section: &str, subsection: &str, key: &str) -> Vec<usize> {
+        self.sections()
+            .filter(|(_, current, line)| {
+                *current == Some((section, subsection))
+                    && matches!(line, ConfigLine::Setting { key: k, .. } if k == key)
+            })
+            .map(|(index, _, _)| index)
+            .collect()
+    }
+
+    /// Every value of `section.subsection.key`, in file order.
+    pub fn get_all(&self, section: &str, subsection: &str, key: &str) -> Vec<&str> {
+        self.setting_indices(section, subsection, key)
+            .into_iter()
+            .filter_map(|index| match &self.lines[index].1 {
+                ConfigLine::Setting { value, .. } => Some(value.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every setting as `(section, subsection, key, value)`, in file order.
+    pub fn entries(&self) -> Vec<(&str, &str, &str, &str)> {
+        self.sections()
+            .filter_map(|(_, current, line)| match (current, line) {
+                (Some((section, subsection)), ConfigLine::Setting { key, value }) => {
+                    Some((section, subsection, key.as_str(), value.as_str()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Converts to the nested JSON shape used by `obj_to_str`.
+    pub fn to_value(&self) -> Value {
+        let mut root = Map::new();
+
+        for (_, current, line) in self.sections() {
+            let Some((section, subsection)) = current else {
+                continue;
+            };
+            let settings = root
+                .entry(section)
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .unwrap()
+                .entry(subsection)
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .unwrap();
+
+            let ConfigLine::Setting { key, value } = line else {
+                continue;
+            };
+            let value = Value::String(value.clone());
+
+            // Note: Repeating a key adds a value rather than replacing it, so repeated
+            // keys accumulate into an array in file order.
+            match settings.get_mut(key) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    settings.insert(key.clone(), value);
+                }
+            }
+        }
+
+        Value::Object(root)
+    }
+
+    fn insert_setting(&mut self, index: usize, key: &str, value: &str) {
+        let raw = setting_line(key, value);
+        self.lines.insert(
+            index,
+            (
+                raw,
+                ConfigLine::Setting {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                },
+            ),
+        );
+    }
+
+    /// Adds a new `key = value` line at the end of the last matching section,
+    /// appending the section first if it doesn't exist.
+    fn insert_new(&mut self, section: &str, subsection: &str, key: &str, value: &str) {
+        let mut position = None;
+        for (index, current, line) in self.sections() {
+            if current == Some((section, subsection))
+                && matches!(
+                    line,
+                    ConfigLine::Section { .. } | ConfigLine::Setting { .. }
+                )
+            {
+                position = Some(index + 1);
+            }
+        }
+
+        match position {
+            Some(index) => self.insert_setting(index, key, value),
+            None => {
+                self.lines.push((
+                    section_header(section, subsection),
+                    ConfigLine::Section {
+                        section: section.to_string(),
+                        subsection: subsection.to_string(),
+                    },
+                ));
+                let end = self.lines.len();
+                self.insert_setting(end, key, value);
+            }
+        }
+    }
+
+    /// Sets `section.subsection.key` to `value`, changing only that line.
+    ///
+    /// Note: The line keeps its indentation and key spelling; a trailing comment on it is dropped.
+    pub fn set(&mut self, section: &str, subsection: &str, key: &str, value: &str) -> Result<()> {
+        validate_names(section, subsection, key)?;
+        match self.setting_indices(section, subsection, key).as_slice() {
+            [] => self.insert_new(section, subsection, key, value),
+            [index] => {
+                let (raw, line) = &mut self.lines[*index];
+                let indent = &raw[..raw.len() - raw.trim_start().len()];
+                let written_key = raw.trim_start().split('=').next().unwrap_or(key).trim();
+                *raw = format!("{}{} = {}", indent, written_key, quote_value(value));
+                *line = ConfigLine::Setting {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                };
+            }
+            _ => bail!(
+                "cannot overwrite multiple values with a single value: {} (use --add)",
+                key_name(section, subsection, key)
+            ),
+        }
+        Ok(())
+    }
+
+    /// Adds another value to `section.subsection.key`, right after its existing values.
+    pub fn add(&mut self, section: &str, subsection: &str, key: &str, value: &str) -> Result<()> {
+        validate_names(section, subsection, key)?;
+        match self.setting_indices(section, subsection, key).last() {
+            Some(&index) => self.insert_setting(index + 1, key, value),
+            None => self.insert_new(section, subsection, key, value),
+        }
+        Ok(())
+    }
+
+    /// Removes the single line holding `section.subsection.key`.
+    ///
+    /// Note: A section header left with nothing but blank lines under it is removed too;
+    /// one that still holds comments is kept, since they presumably describe the section.
+    pub fn unset(&mut self, section: &str, subsection: &str, key: &str) -> Result<()> {
+        let index = match self.setting_indices(section, subsection, key).as_slice() {
+            [] => bail!("key not found: {}", key_name(section, subsection, key)),
+            [index] => *index,
+            _ => bail!("{} has multiple values", key_name(section, subsection, key)),
+        };
+        self.lines.remove(index);
+
+        let header = (0..index)
+            .rev()
+            .find(|&i| matches!(self.lines[i].1, ConfigLine::Section { .. }));
+        if let Some(header) = header {
+            let body_is_empty = self.lines[header + 1..]
+                .iter()
+                .take_while(|(_, line)| !matches!(line, ConfigLine::Section { .. }))
+                .all(|(raw, _)| raw.trim().is_empty());
+            if body_is_empty {
+                self.lines.remove(header);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether any `[section "subsection"]` header is present.
+    fn has_section(&self, section: &str, subsection: &str) -> bool {
+        self.lines.iter().any(|(_, line)| {
+            matches!(line, ConfigLine::Section { section: s, subsection: sub }
+                if s == section && sub == subsection)
+        })
+    }
+
+    /// Renames every `[section "subsection"]` header to the new name, keeping its contents.
+    ///
+    /// Note: Only the header lines change; their indentation and any trailing comment
+    /// (`[remote "origin"] # primary`) stay as written. Renaming onto a section that
+    /// already exists is refused, since it would silently merge the two.
+    pub fn rename_section(
+        &mut self,
+        (section, subsection): (&str, &str),
+        (new_section, new_subsection): (&str, &str),
+    ) -> Result<()> {
+        validate_section(new_section, new_subsection)?;
+        if !self.has_section(section, subsection) {
+            bail!("no such section: {}", section_header(section, subsection));
+        }
+        if (section, subsection) != (new_section, new_subsection)
+            && self.has_section(new_section, new_subsection)
+        {
+            bail!(
+                "section {} already exists",
+                section_header(new_section, new_subsection)
+            );
+        }
+
+        for (raw, line) in &mut self.lines {
+            if let ConfigLine::Section {
+                section: s,
+                subsection: sub,
+            } = line
+                && s == section
+                && sub == subsection
+            {
+                let indent = &raw[..raw.len() - raw.trim_start().len()];
+                let trailing = raw
+                    .trim_start()
+                    .strip_prefix('[')
+                    .and_then(split_header)
+                    .map_or("", |(_, trailing)| trailing);
+                *raw = format!(
+                    "{}{}{}",
+                    indent,
+                    section_header(new_section, new_subsection),
+                    trailing
+                );
+                *s = new_section.to_string();
+                *sub = new_subsection.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes every `[section "subsection"]` header along with everything under it,
+    /// up to the next header.
+    pub fn remove_section(&mut self, section: &str, subsection: &str) -> Result<()> {
+        if !self.has_section(section, subsection) {
+            bail!("no such section: {}", section_header(section, subsection));
+        }
+
+        let doomed: Vec<bool> = self
+            .sections()
+            .map(|(_, current, _)| current == Some((section, subsection)))
+            .collect();
+        let mut doomed = doomed.into_iter();
+        self.lines.retain(|_| !doomed.next().unwrap_or(false));
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (raw, _) in &self.lines {
+            writeln!(f, "{}", raw)?;
+        }
+        Ok(())
+    }
+}
+
+/// The effective value of a setting parsed by `str_to_obj`.
+///
+/// Note: For a multi-valued key the last value wins, as with Git's single-value reads.
+pub fn last_value(value: &Value) -> Option<&str> {
+    match value {
+        Value::Array(values) => values.last().and_then(Value::as_str),
+        value => value.as_str(),
+    }
+}
+
+/// Every value of a setting parsed by `str_to_obj`, in file order.
+pub fn all_values(value: &Value) -> Vec<&str> {
+    match value {
+        Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+        value => value.as_str().into_iter().collect(),
+    }
+}
+
+/// A config key split into its parts, e.g. `remote.origin.url`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigKey {
+    /// Lowercased section name (`remote`).
+    pub section: String,
+    /// Subsection name, case preserved (`origin`); empty when there is none.
+    pub subsection: String,
+    /// Lowercased variable name (`url`).
+    pub name: String,
+}
+
+impl ConfigKey {
+    /// Splits a dotted key the way Git does.
+    ///
+    /// Note: The section is everything before the first dot and the variable name
+    /// everything after the last one, so subsections may themselves contain dots
+    /// (`branch.release/1.0.merge`).
+    pub fn parse(key: &str) -> Result<Self> {
+        let (section, rest) = key
+            .split_once('.')
+            .with_context(|| format!("key does not contain a section: {}", key))?;
+        let (subsection, name) = match rest.rsplit_once('.') {
+            Some((subsection, name)) => (subsection, name),
+            None => ("", rest),
+        };
+
+        if section.is_empty()
+            || !section
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!("invalid key (bad section): {}", key);
+        }
+        if !is_valid_key_name(name) {
+            bail!("invalid key (bad variable name): {}", key);
+        }
+        if rest.contains('.') && subsection.is_empty() {
+            bail!("invalid key (empty subsection): {}", key);
+        }
+
+        Ok(ConfigKey {
+            section: section.to_lowercase(),
+            subsection: subsection.to_string(),
+            name: name.to_lowercase(),
+        })
+    }
+}
+
+/// Splits a section name as given on the command line, e.g. `remote.origin` into
+/// `("remote", "origin")` or `core` into `("core", "")`.
+///
+/// Note: Everything after the first dot is the subsection, so it may contain dots.
+pub fn parse_section_name(name: &str) -> Result<(String, String)> {
+    let (section, subsection) = name.split_once('.').unwrap_or((name, ""));
+    if name.contains('.') && subsection.is_empty() {
+        bail!("invalid section name (empty subsection): {}", name);
+    }
+    validate_section(section, subsection)?;
+    Ok((section.to_lowercase(), subsection.to_string()))
+}
+
+impl std::fmt::Display for ConfigKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.subsection.is_empty() {
+            write!(f, "{}.{}", self.section, self.name)
+        } else {
+            write!(f, "{}.{}.{}", self.section, self.subsection, self.name)
+        }
+    }
+}
+
+/// Interprets a config value as a boolean the way git does.
+///
+/// `true`/`yes`/`on` and `false`/`no`/`off` in any case, the empty string
+/// (false), or any integer (non-zero is true). Returns `None` for anything else.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        other => parse_int(other).map(|n| n != 0),
+    }
+}
+
+/// Interprets a config value as an integer, with an optional `k`, `m` or `g`
+/// suffix (case-insensitive) multiplying by 1024, 1024² or 1024³.
+/// Returns `None` when the value isn't a number or overflows.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Read-only, typed access to a parsed config file.
+///
+/// Keys are given in their dotted form (`core.bare`, `remote.origin.url`); a key that
+/// isn't set yields `Ok(None)`, one that is set but doesn't parse is an error naming it.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    value: Value,
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Self> {
+        Ok(Config {
+            value: str_to_obj(content)?,
+        })
+    }
+
+    /// Reads and parses `path` and the files it includes; a missing file is an empty config.
+    /// `repo_dir` is the repository the config is read for, if any (see `load_entries`).
+    pub fn load(path: &Path, repo_dir: Option<&Path>) -> Result<Self> {
+        Ok(Config::from_entries(load_entries(path, repo_dir)?))
+    }
+
+    fn from_entries(entries: Vec<ConfigEntry>) -> Self {
+        let mut value = Value::Object(Map::new());
+        for entry in entries {
+            let settings = &mut value[&entry.section][&entry.subsection];
+            let new_value = Value::String(entry.value);
+            match &mut settings[&entry.name] {
+                Value::Null => settings[&entry.name] = new_value,
+                Value::Array(values) => values.push(new_value),
+                existing => *existing = Value::Array(vec![existing.take(), new_value]),
+            }
+        }
+        Config { value }
+    }
+
+    /// Merges the system, global and (if given) repository config, in that order,
+    /// then layers the `CS01_CONFIG_COUNT` environment overrides on top.
+    ///
+    /// Note: Later scopes override single-valued reads because reads take the last value;
+    /// multi-valued keys therefore concatenate across scopes instead of replacing.
+    pub fn load_cascaded(repo_dir: Option<&Path>) -> Result<Self> {
+        Ok(Config::from_entries(
+            load_cascaded_entries(repo_dir)?
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect(),
+        ))
+    }
+
+    /// The raw (last) value of `key`.
+    pub fn get_string(&self, key: &str) -> Result<Option<&str>> {
+        let key = ConfigKey::parse(key)?;
+        Ok(last_value(
+            &self.value[&key.section][&key.subsection][&key.name],
+        ))
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get_string(key)?
+            .map(|value| typed_bool(key, value))
+            .transpose()
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        self.get_string(key)?
+            .map(|value| typed_int(key, value))
+            .transpose()
+    }
+
+    /// A path value, with a leading `~` expanded to the home directory.
+    pub fn get_path(&self, key: &str) -> Result<Option<PathBuf>> {
+        self.get_string(key)?
+            .map(|value| typed_path(key, value))
+            .transpose()
+    }
+
+    /// The identity recorded for changes: `CS01_AUTHOR_NAME`/`CS01_AUTHOR_EMAIL`
+    /// if set, otherwise `user.name`/`user.email`; dated `CS01_AUTHOR_DATE` or now.
+    pub fn author(&self) -> Result<Identity> {
+        self.identity_with_env(Role::Author, |name| std::env::var_os(name))
+    }
+
+    /// Who records a change, which may differ from its author (e.g. when applying a patch):
+    /// `CS01_COMMITTER_NAME`/`CS01_COMMITTER_EMAIL`/`CS01_COMMITTER_DATE`, with the same
+    /// fallbacks as `author`, then the author's name and email variables.
+    pub fn committer(&self) -> Result<Identity> {
+        self.identity_with_env(Role::Committer, |name| std::env::var_os(name))
+    }
+
+    fn identity_with_env(
+        &self,
+        role: Role,
+        var: impl Fn(&str) -> Option<OsString>,
+    ) -> Result<Identity> {
+        let env_value = |env: &str| -> Result<Option<String>> {
+            var(env)
+                .filter(|value| !value.is_empty())
+                .map(|value| {
+                    value
+                        .into_string()
+                        .map_err(|_| anyhow::anyhow!("{} is not valid UTF-8", env))
+                })
+                .transpose()
+        };
+        let lookup = |env: &str, key: &str, fallback: Option<&str>| -> Result<Option<String>> {
+            if let Some(value) = env_value(env)? {
+                return Ok(Some(value));
+            }
+            if let Some(value) = self.get_string(key)? {
+                return Ok(Some(value.to_string()));
+            }
+            fallback.map_or(Ok(None), env_value)
+        };
+
+        let [name_env, email_env, date_env] = role.env_vars();
+        let [name_fallback, email_fallback] = match role.fallback_env_vars() {
+            Some([name, email]) => [Some(name), Some(email)],
+            None => [None, None],
+        };
+        let when = match env_value(date_env)? {
+            Some(date) => Timestamp::parse(&date).with_context(|| format!("bad {}", date_env))?,
+            None => Timestamp::now(),
+        };
+        match (
+            lookup(name_env, "user.name", name_fallback)?,
+            lookup(email_env, "user.email", email_fallback)?,
+        ) {
+            (Some(name), Some(email)) => Ok(Identity { name, email, when }),
+            _ => bail!(
+                "{} identity unknown: set user.name and user.email \
+                 (e.g. cs01 config --global user.name \"Your Name\"), \
+                 or {} and {}",
+                role.name(),
+                name_env,
+                email_env
+            ),
+        }
+    }
+}
+
+/// Environment variable overriding `user.name` for the author of changes.
+pub const CS01_AUTHOR_NAME_ENV: &str = "CS01_AUTHOR_NAME";
+
+/// Environment variable overriding `user.email` for the author of changes.
+pub const CS01_AUTHOR_EMAIL_ENV: &str = "CS01_AUTHOR_EMAIL";
+
+/// Environment variable giving the author date, as `<seconds> <+hhmm>`.
+pub const CS01_AUTHOR_DATE_ENV: &str = "CS01_AUTHOR_DATE";
+
+/// Environment variable overriding `user.name` for the committer.
+pub const CS01_COMMITTER_NAME_ENV: &str = "CS01_COMMITTER_NAME";
+
+/// Environment variable overriding `user.email` for the committer.
+pub const CS01_COMMITTER_EMAIL_ENV: &str = "CS01_COMMITTER_EMAIL";
+
+/// Environment variable giving the commit date, as `<seconds> <+hhmm>`.
+pub const CS01_COMMITTER_DATE_ENV: &str = "CS01_COMMITTER_DATE";
+
+/// Which identity of a change is being looked up.
+#[derive(Clone, Copy)]
+enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn name(&self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+
+    /// The name, email and date overrides, in that order.
+    fn env_vars(&self) -> [&'static str; 3] {
+        match self {
+            Role::Author => [
+                CS01_AUTHOR_NAME_ENV,
+                CS01_AUTHOR_EMAIL_ENV,
+                CS01_AUTHOR_DATE_ENV,
+            ],
+            Role::Committer => [
+                CS01_COMMITTER_NAME_ENV,
+                CS01_COMMITTER_EMAIL_ENV,
+                CS01_COMMITTER_DATE_ENV,
+            ],
+        }
+    }
+
+    /// The name and email variables used when neither the role's own variables nor the
+    /// config give one: the author's, for the committer. Dates never fall back.
+    fn fallback_env_vars(&self) -> Option<[&'static str; 2]> {
+        match self {
+            Role::Author => None,
+            Role::Committer => Some([CS01_AUTHOR_NAME_ENV, CS01_AUTHOR_EMAIL_ENV]),
+        }
+    }
+}
+
+/// Who made a change and when, rendered as `Name <email>` like in git's reflog and commits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+    /// When the change was made, with the UTC offset to show it in.
+    pub when: Timestamp,
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} <{}>", self.name, self.email)
+    }
+}
+
+// Note: Every typed read goes through these, so `Config` accessors and `config --type`
+// can't disagree about what a value means.
+
+fn typed_bool(key: &str, value: &str) -> Result<bool> {
+    parse_bool(value).with_context(|| format!("bad boolean config value '{}' for '{}'", value, key))
+}
+
+fn typed_int(key: &str, value: &str) -> Result<i64> {
+    parse_int(value).with_context(|| format!("bad numeric config value '{}' for '{}'", value, key))
+}
+
+fn typed_path(key: &str, value: &str) -> Result<PathBuf> {
+    expand_tilde(value).with_context(|| format!("cannot expand '{}' for '{}'", value, key))
+}
+
+/// How `cs01 config --type` interprets values, like git's option of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigType {
+    Bool,
+    Int,
+    Path,
+}
+
+impl ConfigType {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "bool" => Ok(ConfigType::Bool),
+            "int" => Ok(ConfigType::Int),
+            "path" => Ok(ConfigType::Path),
+            _ => bail!(
+                "unrecognized --type argument '{}' (expected bool, int or path)",
+                name
+            ),
+        }
+    }
+
+    /// The canonical form of a stored value, as printed by reads:
+    /// `true`/`false`, a plain decimal, or a path with `~` expanded.
+    pub fn canonicalize(&self, key: &str, value: &str) -> Result<String> {
+        Ok(match self {
+            ConfigType::Bool => typed_bool(key, value)?.to_string(),
+            ConfigType::Int => typed_int(key, value)?.to_string(),
+            ConfigType::Path => typed_path(key, value)?.display().to_string(),
+        })
+    }
+
+    /// The form in which a new value is stored.
+    ///
+    /// Note: Booleans and numbers are stored canonically, but paths are kept as written,
+    /// so `~/...` keeps following the home directory.
+    pub fn normalize_for_write(&self, key: &str, value: &str) -> Result<String> {
+        match self {
+            ConfigType::Path => Ok(value.to_string()),
+            _ => self.canonicalize(key, value),
+        }
+    }
+}
+
+/// Expands a leading `~` or `~/` to the home directory; other paths are returned as written.
+fn expand_tilde(value: &str) -> Result<PathBuf> {
+    let rest = match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => rest,
+        // `~user/...` is not supported; keep the value as written.
+        _ => return Ok(PathBuf::from(value)),
+    };
+    let home = home_dir().context("HOME is not set")?;
+
+    Ok(match rest.trim_start_matches(['/', '\\']) {
+        "" => home,
+        rest => home.join(rest),
+    })
+}
+
+/// One setting as read from a file (or a file it includes), or from the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub section: String,
+    pub subsection: String,
+    pub name: String,
+    pub value: String,
+    pub origin: ConfigOrigin,
+}
+
+impl ConfigEntry {
+    /// The full `section[.subsection].name` key.
+    pub fn key_name(&self) -> String {
+        key_name(&self.section, &self.subsection, &self.name)
+    }
+}
+
+/// Where a setting was read from, as shown by `config --list --show-origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    /// A config file; for included settings, the included file.
+    File(PathBuf),
+    /// The `CS01_CONFIG_COUNT` environment overrides.
+    CommandLine,
+}
+
+impl std::fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Note: Same labels as git, which also reports `GIT_CONFIG_COUNT` values as `command line:`.
+        match self {
+            ConfigOrigin::File(path) => write!(f, "file:{}", path.display()),
+            ConfigOrigin::CommandLine => f.write_str("command line:"),
+        }
+    }
+}
+
+/// How deeply `include.path` directives may nest; deeper chains are assumed to be cycles.
+pub const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Reads every setting of `path` in effective order, splicing in files named by
+/// `include.path` at the point where they are included. A missing `path` reads as empty.
+///
+/// `repo_dir` is the metadata directory of the repository being configured, if any;
+/// `[includeIf "cs01dir:<glob>"]` sections only apply when it matches the glob.
+///
+/// Note: Only reads follow includes. Writers edit a single `ConfigDocument`, so included
+/// values are never copied into the including file.
+pub fn load_entries(path: &Path, repo_dir: Option<&Path>) -> Result<Vec<ConfigEntry>> {
+    load_entries_in(&RealFs, path, repo_dir)
+}
+
+/// Like `load_entries`, but reading `path` and its includes from `vfs`.
+pub fn load_entries_in(
+    vfs: &dyn Vfs,
+    path: &Path,
+    repo_dir: Option<&Path>,
+) -> Result<Vec<ConfigEntry>> {
+    let mut entries = Vec::new();
+    if vfs.is_file(path) {
+        collect_entries(vfs, path, repo_dir, 0, &mut entries)?;
+    }
+    Ok(entries)
+}
+
+fn collect_entries(
+    vfs: &dyn Vfs,
+    path: &Path,
+    repo_dir: Option<&Path>,
+    depth: usize,
+    entries: &mut Vec<ConfigEntry>,
+) -> Result<()> {
+    let content = vfs
+        .read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let document = ConfigDocument::parse(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    for (section, subsection, name, value) in document.entries() {
+        entries.push(ConfigEntry {
+            section: section.to_string(),
+            subsection: subsection.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+            origin: ConfigOrigin::File(path.to_path_buf()),
+        });
+
+        let included = match section {
+            "include" => subsection.is_empty(),
+            "includeif" => include_condition_matches(subsection, path, repo_dir)?,
+            _ => false,
+        };
+        if !included || name != "path" {
+            continue;
+        }
+
+        // Like git, a relative include is resolved against the including file's directory,
+        // and skipped silently when missing; a missing absolute include is a mistake worth reporting.
+        let target = expand_tilde(value).with_context(|| {
+            format!(
+                "cannot expand include.path '{}' in {}",
+                value,
+                path.display()
+            )
+        })?;
+        let target = match path.parent() {
+            Some(dir) if target.is_relative() => dir.join(target),
+            _ => target,
+        };
+        if !vfs.is_file(&target) {
+            if Path::new(value).is_absolute() || value.starts_with('~') {
+                bail!(
+                    "included config file '{}' not found (included from {})",
+                    target.display(),
+                    path.display()
+                );
+            }
+            continue;
+        }
+
+        if depth + 1 > MAX_INCLUDE_DEPTH {
+            bail!(
+                "exceeded maximum include depth ({}) while including '{}' from {}; is there a cycle?",
+                MAX_INCLUDE_DEPTH,
+                value,
+                path.display()
+            );
+        }
+        collect_entries(vfs, &target, repo_dir, depth + 1, entries)?;
+    }
+
+    Ok(())
+}
+
+/// Evaluates the condition of an `[includeIf "<condition>"]` section read from `path`.
+///
+/// Only `cs01dir:` (and its case-insensitive twin `cs01dir/i:`) is supported; like git,
+/// unknown conditions are false, and so is every condition outside a repository.
+fn include_condition_matches(
+    condition: &str,
+    path: &Path,
+    repo_dir: Option<&Path>,
+) -> Result<bool> {
+    let (pattern, ignore_case) = if let Some(pattern) = condition.strip_prefix("cs01dir:") {
+        (pattern, false)
+    } else if let Some(pattern) = condition.strip_prefix("cs01dir/i:") {
+        (pattern, true)
+    } else {
+        return Ok(false);
+    };
+    let Some(repo_dir) = repo_dir else {
+        return Ok(false);
+    };
+
+    // Git's rules: `~/` is the home directory, `./` the including file's directory,
+    // any other relative pattern may match at any depth, and a trailing `/` means
+    // "everything below".
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        format!("{}/{}", dir.display(), rest)
+    } else {
+        expand_tilde(pattern)
+            .with_context(|| format!("cannot expand includeIf condition '{}'", condition))?
+            .to_string_lossy()
+            .into_owned()
+    };
+    pattern = pattern.replace('\\', "/");
+    if !pattern.starts_with('/') && !Path::new(&pattern).is_absolute() {
+        pattern.insert_str(0, "**/");
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    // Note: Both the path as discovered and its canonical form are tried,
+    // so a pattern written against either side of a symlink matches.
+    let mut candidates = vec![repo_dir.to_path_buf()];
+    if let Ok(canonical) = repo_dir.canonicalize() {
+        candidates.push(canonical);
+    }
+
+    Ok(candidates.iter().any(|candidate| {
+        let text = candidate.to_string_lossy().replace('\\', "/");
+        if ignore_case {
+            glob_match(
+                pattern.to_lowercase().as_bytes(),
+                text.to_lowercase().as_bytes(),
+            )
+        } else {
+            glob_match(pattern.as_bytes(), text.as_bytes())
+        }
+    }))
+}
+
+/// Matches `text` against a path glob: `*` and `?` stay within one path component,
+/// `**` crosses components, and `**/` may also match no directory at all.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            if let [b'/', after @ ..] = rest
+                && glob_match(after, text)
+            {
+                return true;
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| !text[..i].contains(&b'/'))
+            .any(|i| glob_match(rest, &text[i..])),
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob_match(rest, tail)),
+        [p, rest @ ..] => matches!(text, [c, tail @ ..] if c == p && glob_match(rest, tail)),
+    }
+}
+
+/// Environment variable holding the number of `CS01_CONFIG_KEY_<n>`/`CS01_CONFIG_VALUE_<n>` pairs.
+pub const CS01_CONFIG_COUNT_ENV: &str = "CS01_CONFIG_COUNT";
+
+/// Settings injected through `CS01_CONFIG_COUNT=<n>` with `CS01_CONFIG_KEY_<i>` and
+/// `CS01_CONFIG_VALUE_<i>` for `i` in `0..n`, like git's `GIT_CONFIG_COUNT`.
+/// They take precedence over every config file.
+pub fn env_config_entries() -> Result<Vec<ConfigEntry>> {
+    parse_env_config(|name| std::env::var_os(name))
+}
+
+fn parse_env_config(var: impl Fn(&str) -> Option<OsString>) -> Result<Vec<ConfigEntry>> {
+    let string_var = |name: &str| -> Result<Option<String>> {
+        var(name)
+            .map(|value| {
+                value
+                    .into_string()
+                    .map_err(|_| anyhow::anyhow!("{} is not valid UTF-8", name))
+            })
+            .transpose()
+    };
+
+    let count = match string_var(CS01_CONFIG_COUNT_ENV)? {
+        None => return Ok(Vec::new()),
+        Some(count) if count.is_empty() => return Ok(Vec::new()),
+        Some(count) => count.parse::<usize>().map_err(|_| {
+            anyhow::anyhow!("bogus count in {}: '{}'", CS01_CONFIG_COUNT_ENV, count)
+        })?,
+    };
+
+    (0..count)
+        .map(|i| {
+            let key_var = format!("CS01_CONFIG_KEY_{}", i);
+            let value_var = format!("CS01_CONFIG_VALUE_{}", i);
+            let key =
+                string_var(&key_var)?.with_context(|| format!("missing config key {}", key_var))?;
+            let value = string_var(&value_var)?
+                .with_context(|| format!("missing config value {}", value_var))?;
+            let key = ConfigKey::parse(&key)
+                .map_err(|e| anyhow::anyhow!("invalid config key in {}: {}", key_var, e))?;
+
+            Ok(ConfigEntry {
+                section: key.section,
+                subsection: key.subsection,
+                name: key.name,
+                value,
+                origin: ConfigOrigin::CommandLine,
+            })
+        })
+        .collect()
+}
+
+/// Every setting that applies, tagged with its scope, in increasing order of precedence:
+/// the files from `config_files` (with their includes), then the environment overrides.
+///
+/// Note: This is what `Config::load_cascaded` flattens; keeping each entry's scope and
+/// origin lets `config --show-scope`/`--show-origin` explain where a value came from.
+pub fn load_cascaded_entries(repo_dir: Option<&Path>) -> Result<Vec<(ConfigScope, ConfigEntry)>> {
+    let mut entries = Vec::new();
+    for (scope, path) in config_files(repo_dir) {
+        entries.extend(
+            load_entries(&path, repo_dir)?
+                .into_iter()
+                .map(|entry| (scope, entry)),
+        );
+    }
+    entries.extend(
+        env_config_entries()?
+            .into_iter()
+            .map(|entry| (ConfigScope::Command, entry)),
+    );
+    Ok(entries)
+}
+
+/// The user's home directory, from `HOME` or (on Windows) `USERPROFILE`.
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Where a config file sits in the cascade, from lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigScope {
+    /// Machine-wide settings (`/etc/cs01config`).
+    System,
+    /// Per-user settings (`~/.cs01config` or `$XDG_CONFIG_HOME/cs01/config`).
+    Global,
+    /// The repository's own `config`.
+    Local,
+    /// Overrides from the environment (`CS01_CONFIG_COUNT`), above every file.
+    Command,
+}
+
+impl ConfigScope {
+    /// The label shown by `config --list --show-scope`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigScope::System => "system",
+            ConfigScope::Global => "global",
+            ConfigScope::Local => "local",
+            ConfigScope::Command => "command",
+        }
+    }
+}
+
+/// Environment variable overriding the system config file, like `GIT_CONFIG_SYSTEM`.
+pub const CS01_CONFIG_SYSTEM_ENV: &str = "CS01_CONFIG_SYSTEM";
+
+/// The machine-wide config file: `$CS01_CONFIG_SYSTEM`, or `/etc/cs01config`.
+pub fn system_config_path() -> PathBuf {
+    std::env::var_os(CS01_CONFIG_SYSTEM_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/etc/cs01config"))
+}
+
+/// Returns the per-user config files, in increasing order of precedence.
+///
+/// Mirrors Git: `$XDG_CONFIG_HOME/cs01/config` (or `~/.config/cs01/config`) is read first,
+/// then `~/.cs01config`, so the latter wins when both set the same key.
+pub fn user_config_paths() -> Vec<PathBuf> {
+    let home = home_dir();
+
+    let xdg = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+
+    let mut paths = Vec::new();
+    if let Some(xdg) = xdg {
+        paths.push(xdg.join("cs01").join("config"));
+    }
+    if let Some(home) = home {
+        paths.push(home.join(".cs01config"));
+    }
+    paths
+}
+
+/// The ignore file used when `core.excludesFile` isn't set: `$XDG_CONFIG_HOME/cs01/ignore`,
+/// or `~/.config/cs01/ignore`.
+pub fn default_excludes_file() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|h| h.join(".config")))
+        .map(|xdg| xdg.join("cs01").join("ignore"))
+}
+
+/// The per-user file that `config --global` writes to.
+///
+/// Note: Like git, this is `~/.cs01config` unless only the XDG file exists,
+/// so a user who chose the XDG location keeps using it.
+pub fn global_config_write_path() -> Result<PathBuf> {
+    let paths = user_config_paths();
+    let home_file = paths
+        .iter()
+        .find(|p| p.file_name().is_some_and(|n| n == ".cs01config"));
+
+    match (home_file, paths.first()) {
+        (Some(home_file), Some(xdg)) if !home_file.exists() && xdg.is_file() => Ok(xdg.clone()),
+        (Some(home_file), _) => Ok(home_file.clone()),
+        _ => bail!("cannot locate the global config file: HOME is not set"),
+    }
+}
+
+/// Every config file that applies, as `(scope, path)` in increasing order of precedence.
+/// `repo_dir` is the metadata directory, if there is a repository.
+pub fn config_files(repo_dir: Option<&Path>) -> Vec<(ConfigScope, PathBuf)> {
+    let mut files = vec![(ConfigScope::System, system_config_path())];
+    files.extend(
+        user_config_paths()
+            .into_iter()
+            .map(|path| (ConfigScope::Global, path)),
+    );
+    if let Some(repo_dir) = repo_dir {
+        files.push((ConfigScope::Local, repo_dir.join("config")));
+    }
+    files
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,6 +1505,876 @@ mod tests {
         assert!(result.contains("active = true"));
     }
 
+    #[test]
+    fn test_obj_to_str_rejects_invalid_names() {
+        let error = |value: Value| obj_to_str(&value).unwrap_err().to_string();
+
+        assert!(error(json!({ "core foo": { "": { "bare": "false" } } })).contains("'core foo'"));
+        assert!(error(json!({ "core]": { "": {} } })).contains("'core]'"));
+        assert!(error(json!({ "": { "": { "bare": "false" } } })).contains("section name"));
+        assert!(error(json!({ "remote": { "a\nb": { "url": "x" } } })).contains("subsection name"));
+        assert!(error(json!({ "core": { "": { "1bare": "false" } } })).contains("'1bare'"));
+        assert!(error(json!({ "core": { "": { "bare=": "false" } } })).contains("'bare='"));
+        assert!(error(json!({ "core": { "": { "log all": "true" } } })).contains("'log all'"));
+
+        // Hyphens, dotted section names and arbitrary quoted subsections are fine
+        let written = obj_to_str(&json!({
+            "core": { "": { "log-all-ref-updates": "true" } },
+            "branch.legacy": { "": { "x": "1" } },
+            "remote": { "my \"odd\" remote]": { "url": "x" } },
+        }))
+        .unwrap();
+        assert_eq!(
+            str_to_obj(&written).unwrap()["core"][""]["log-all-ref-updates"],
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_str_to_obj_basic() {
+        let content = "[core]\n  bare = false\n# comment\n\n[Init]\n  defaultBranch = trunk\n";
+        let result = str_to_obj(content).unwrap();
+        assert_eq!(result["core"][""]["bare"], "false");
+        assert_eq!(result["init"][""]["defaultbranch"], "trunk");
+    }
+
+    #[test]
+    fn test_str_to_obj_subsection() {
+        let content = "[remote \"Origin\"]\n  url = https://example.com\n";
+        let result = str_to_obj(content).unwrap();
+        assert_eq!(result["remote"]["Origin"]["url"], "https://example.com");
+    }
+
+    #[test]
+    fn test_str_to_obj_invalid_input() {
+        assert!(str_to_obj("bare = true\n").is_err());
+        assert!(str_to_obj("[core\n").is_err());
+        assert!(str_to_obj("[core]\n  bare flag\n").is_err());
+    }
+
+    #[test]
+    fn test_str_to_obj_values() {
+        let content = concat!(
+            "[remote \"my \\\"fork\\\"\"] ; trailing comment\n",
+            "  url = https://example.com/?a=b&c=d\n",
+            "  fetch = +refs/heads/*:refs/remotes/fork/*   # inline comment\n",
+            "[alias]\n",
+            "  greet = \"  hello ; world  \"\n",
+            "  mixed = say \"#1\" now\n",
+            "  escaped = a\\tb\\\\c\n",
+        );
+        let result = str_to_obj(content).unwrap();
+        let fork = &result["remote"]["my \"fork\""];
+        assert_eq!(fork["url"], "https://example.com/?a=b&c=d");
+        assert_eq!(fork["fetch"], "+refs/heads/*:refs/remotes/fork/*");
+        assert_eq!(result["alias"][""]["greet"], "  hello ; world  ");
+        assert_eq!(result["alias"][""]["mixed"], "say #1 now");
+        assert_eq!(result["alias"][""]["escaped"], "a\tb\\c");
+    }
+
+    #[test]
+    fn test_str_to_obj_reports_line_numbers() {
+        let cases = [
+            ("[core]\n  bare = true\n  bad key = 1\n", "line 3"),
+            ("# header\n\n[core\n", "line 3"),
+            ("[core]\n  name = \"open\n", "line 2"),
+            ("[core]\n  path = C:\\x\n", "line 2"),
+            ("bare = true\n", "line 1"),
+        ];
+        for (content, expected) in cases {
+            let err = str_to_obj(content).unwrap_err().to_string();
+            assert!(err.starts_with(expected), "{:?} gave {:?}", content, err);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_values() {
+        let values = [
+            "plain",
+            "with = equals",
+            "  padded  ",
+            "hash # and ; semicolon",
+            "quote \" and backslash \\",
+            "tab\tand\nnewline",
+            "",
+        ];
+        for value in values {
+            let config = json!({ "test": { "sub \"quoted\"": { "key": value } } });
+            let parsed = str_to_obj(&obj_to_str(&config).unwrap()).unwrap();
+            assert_eq!(parsed, config, "value {:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_quoting_is_lossless() {
+        let value = r#"my "quoted" path\with\backslashes # not a comment"#;
+        let config = json!({ "core": { "": { "worktree": value } } });
+        let written = obj_to_str(&config).unwrap();
+        assert_eq!(
+            written,
+            "[core]\n  worktree = \"my \\\"quoted\\\" path\\\\with\\\\backslashes # not a comment\"\n"
+        );
+        assert_eq!(str_to_obj(&written).unwrap(), config);
+
+        let subsection = r#"C:\repos\"odd" name"#;
+        let config = json!({ "remote": { subsection: { "url": "x" } } });
+        let written = obj_to_str(&config).unwrap();
+        assert!(written.starts_with(r#"[remote "C:\\repos\\\"odd\" name"]"#));
+        assert_eq!(str_to_obj(&written).unwrap(), config);
+    }
+
+    #[test]
+    fn test_round_trip_init_configs() {
+        use crate::modules::{
+            files::{FsCaps, TreeNode},
+            hash::ObjectFormat,
+            perms::SharedMode,
+            repo_structure::build_metadata_tree,
+        };
+
+        let shared_modes = [
+            SharedMode::Umask,
+            SharedMode::Group,
+            SharedMode::All,
+            SharedMode::Octal(0o640),
+        ];
+        let caps = [
+            FsCaps::default(),
+            FsCaps {
+                filemode: false,
+                symlinks: false,
+                ignorecase: true,
+            },
+        ];
+
+        for bare in [false, true] {
+            for shared in shared_modes {
+                for format in [ObjectFormat::Sha1, ObjectFormat::Sha256] {
+                    for fs_caps in caps {
+                        let tree = build_metadata_tree(bare, "main", shared, format, fs_caps, true)
+                            .unwrap();
+                        let TreeNode::Directory {
+                            entries: children, ..
+                        } = tree
+                        else {
+                            panic!("metadata tree must be a directory");
+                        };
+                        let Some(TreeNode::File { content, .. }) = children.get("config") else {
+                            panic!("metadata tree must contain a config file");
+                        };
+
+                        let content = std::str::from_utf8(content).unwrap();
+                        let parsed = str_to_obj(content).unwrap();
+                        assert_eq!(&obj_to_str(&parsed).unwrap(), content);
+                        assert_eq!(parsed["core"][""]["bare"], bare.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_valued_keys() {
+        let content = concat!(
+            "[remote \"origin\"]\n",
+            "  fetch = +refs/heads/*:refs/remotes/origin/*\n",
+            "  url = https://example.com\n",
+            "  fetch = +refs/tags/*:refs/tags/*\n",
+            "  Fetch = +refs/notes/*:refs/notes/*\n",
+        );
+        let parsed = str_to_obj(content).unwrap();
+        let fetch = &parsed["remote"]["origin"]["fetch"];
+        assert_eq!(
+            all_values(fetch),
+            vec![
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*",
+                "+refs/notes/*:refs/notes/*"
+            ]
+        );
+        assert_eq!(last_value(fetch), Some("+refs/notes/*:refs/notes/*"));
+        assert_eq!(
+            last_value(&parsed["remote"]["origin"]["url"]),
+            Some("https://example.com")
+        );
+
+        // Repeated values are written back as repeated lines, in order
+        let written = obj_to_str(&parsed).unwrap();
+        assert_eq!(str_to_obj(&written).unwrap(), parsed);
+        assert_eq!(written.matches("fetch = ").count(), 3);
+    }
+
+    const COMMENTED: &str = "\
+# Settings for this clone
+; keep in sync with the wiki
+
+[core]
+\t# do not touch
+\tbare = false
+\tfilemode = true ; probed at init
+
+[remote \"origin\"]
+\turl = https://example.com/a.git
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+
+# trailing notes
+";
+
+    #[test]
+    fn test_config_document_set_changes_one_line() {
+        let mut document = ConfigDocument::parse(COMMENTED).unwrap();
+        document.set("core", "", "bare", "true").unwrap();
+        document
+            .set("remote", "origin", "url", "https://example.com/b.git")
+            .unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            COMMENTED
+                .replace("\tbare = false", "\tbare = true")
+                .replace("a.git", "b.git")
+        );
+    }
+
+    #[test]
+    fn test_config_document_inserts_and_removes() {
+        let mut document = ConfigDocument::parse(COMMENTED).unwrap();
+
+        // New keys go after the section's last setting, new sections at the end
+        document
+            .set("core", "", "logallrefupdates", "true")
+            .unwrap();
+        document
+            .add("remote", "origin", "fetch", "+refs/tags/*:refs/tags/*")
+            .unwrap();
+        document.set("user", "", "name", "A U Thor").unwrap();
+        assert_eq!(
+            document.to_string(),
+            COMMENTED
+                .replace(
+                    "probed at init\n",
+                    "probed at init\n  logallrefupdates = true\n"
+                )
+                .replace(
+                    "origin/*\n",
+                    "origin/*\n  fetch = +refs/tags/*:refs/tags/*\n"
+                )
+                + "[user]\n  name = A U Thor\n"
+        );
+
+        // Removing the last key drops a header with nothing left under it, but not
+        // one that still holds a comment
+        document.unset("user", "", "name").unwrap();
+        document.unset("core", "", "bare").unwrap();
+        document.unset("core", "", "filemode").unwrap();
+        document.unset("core", "", "logallrefupdates").unwrap();
+        let text = document.to_string();
+        assert!(!text.contains("[user]"));
+        assert!(text.contains("[core]\n\t# do not touch\n\n[remote"));
+        assert!(text.ends_with("# trailing notes\n"));
+
+        assert!(document.unset("core", "", "bare").is_err());
+        assert!(document.unset("remote", "origin", "fetch").is_err());
+        assert!(document.set("remote", "origin", "fetch", "x").is_err());
+    }
+
+    #[test]
+    fn test_config_document_rejects_invalid_names() {
+        let mut document = ConfigDocument::parse(COMMENTED).unwrap();
+        let before = document.clone();
+
+        assert!(document.set("core foo", "", "bare", "x").is_err());
+        assert!(document.set("core", "", "9lives", "x").is_err());
+        assert!(document.add("remote", "a\nb", "url", "x").is_err());
+        assert!(
+            document
+                .rename_section(("core", ""), ("co re", ""))
+                .is_err()
+        );
+        assert_eq!(document, before);
+
+        document
+            .set("core", "", "log-all-ref-updates", "true")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_names_are_case_insensitive_except_subsections() {
+        let content = "[CORE]\n\tBare = TRUE\n[Remote \"Origin\"]\n\tURL = upper\n[remote \"origin\"]\n\turl = lower\n";
+        let config = Config::parse(content).unwrap();
+
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get_bool("CORE.BARE").unwrap(), Some(true));
+        assert_eq!(
+            config.get_string("remote.Origin.url").unwrap(),
+            Some("upper")
+        );
+        assert_eq!(
+            config.get_string("REMOTE.origin.Url").unwrap(),
+            Some("lower")
+        );
+        assert_eq!(config.get_string("remote.ORIGIN.url").unwrap(), None);
+
+        // Writes find the existing line however it is spelled, and keep that spelling
+        let mut document = ConfigDocument::parse(content).unwrap();
+        let key = ConfigKey::parse("core.BARE").unwrap();
+        document
+            .set(&key.section, &key.subsection, &key.name, "false")
+            .unwrap();
+        assert_eq!(
+            document.to_string(),
+            content.replace("Bare = TRUE", "Bare = false")
+        );
+    }
+
+    #[test]
+    fn test_config_document_rename_section() {
+        let mut document = ConfigDocument::parse(COMMENTED).unwrap();
+        document
+            .rename_section(("remote", "origin"), ("remote", "upstream"))
+            .unwrap();
+        assert_eq!(
+            document.to_string(),
+            COMMENTED.replace("[remote \"origin\"]", "[remote \"upstream\"]")
+        );
+        assert_eq!(
+            document.get_all("remote", "upstream", "url"),
+            vec!["https://example.com/a.git"]
+        );
+        assert!(
+            document
+                .rename_section(("remote", "origin"), ("remote", "x"))
+                .is_err()
+        );
+
+        // Indentation and a comment after the header survive; keys are untouched
+        let content = "[core]\n\tbare = false\n  [remote \"a\"] # primary\n\turl = x\n[remote \"b\"]\n\turl = y\n";
+        let mut document = ConfigDocument::parse(content).unwrap();
+        document
+            .rename_section(("remote", "a"), ("remote", "we \"quote\""))
+            .unwrap();
+        assert_eq!(
+            document.to_string(),
+            content.replace("[remote \"a\"]", "[remote \"we \\\"quote\\\"\"]")
+        );
+        assert_eq!(document.get_all("remote", "we \"quote\"", "url"), vec!["x"]);
+
+        // Renaming onto an existing section would merge them, so it is refused
+        let before = document.clone();
+        let err = document
+            .rename_section(("remote", "b"), ("core", ""))
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+        assert_eq!(document, before);
+    }
+
+    #[test]
+    fn test_config_document_remove_section() {
+        let mut document = ConfigDocument::parse(COMMENTED).unwrap();
+        document.remove_section("core", "").unwrap();
+        assert_eq!(
+            document.to_string(),
+            "# Settings for this clone\n; keep in sync with the wiki\n\n\
+             [remote \"origin\"]\n\turl = https://example.com/a.git\n\
+             \tfetch = +refs/heads/*:refs/remotes/origin/*\n\n# trailing notes\n"
+        );
+
+        // Everything up to the next header goes, including comments inside the section
+        document.remove_section("remote", "origin").unwrap();
+        assert_eq!(
+            document.to_string(),
+            "# Settings for this clone\n; keep in sync with the wiki\n\n"
+        );
+        assert!(document.remove_section("remote", "origin").is_err());
+
+        assert_eq!(
+            parse_section_name("Remote.my.fork").unwrap(),
+            ("remote".to_string(), "my.fork".to_string())
+        );
+        assert_eq!(
+            parse_section_name("core").unwrap(),
+            ("core".to_string(), String::new())
+        );
+        assert!(parse_section_name("remote.").is_err());
+        assert!(parse_section_name("re mote").is_err());
+    }
+
+    #[test]
+    fn test_parse_config_key() {
+        let key = ConfigKey::parse("Core.Bare").unwrap();
+        assert_eq!(
+            (
+                key.section.as_str(),
+                key.subsection.as_str(),
+                key.name.as_str()
+            ),
+            ("core", "", "bare")
+        );
+
+        let key = ConfigKey::parse("remote.Origin.URL").unwrap();
+        assert_eq!(
+            (
+                key.section.as_str(),
+                key.subsection.as_str(),
+                key.name.as_str()
+            ),
+            ("remote", "Origin", "url")
+        );
+
+        let key = ConfigKey::parse("branch.release/1.0.merge").unwrap();
+        assert_eq!(key.subsection, "release/1.0");
+        assert_eq!(key.to_string(), "branch.release/1.0.merge");
+
+        for bad in [
+            "core",
+            "core.",
+            ".bare",
+            "core..bare",
+            "core.1bare",
+            "co re.bare",
+        ] {
+            assert!(
+                ConfigKey::parse(bad).is_err(),
+                "{:?} should be rejected",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_bool_spellings() {
+        for value in ["true", "TRUE", "yes", "Yes", "on", "ON", "1", "-1", "42"] {
+            assert_eq!(parse_bool(value), Some(true), "{:?}", value);
+        }
+        for value in ["false", "False", "no", "NO", "off", "Off", "0", ""] {
+            assert_eq!(parse_bool(value), Some(false), "{:?}", value);
+        }
+        for value in ["maybe", "y", "tru", "1x"] {
+            assert_eq!(parse_bool(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse_int_suffixes() {
+        assert_eq!(parse_int("512"), Some(512));
+        assert_eq!(parse_int("-3"), Some(-3));
+        assert_eq!(parse_int("8k"), Some(8 * 1024));
+        assert_eq!(parse_int("512m"), Some(512 * 1024 * 1024));
+        assert_eq!(parse_int("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_int("1K"), Some(1024));
+        assert_eq!(parse_int("k"), None);
+        assert_eq!(parse_int("12t"), None);
+        assert_eq!(parse_int("9999999999g"), None);
+    }
+
+    #[test]
+    fn test_bool_spellings_round_trip_canonically() {
+        let spellings = [
+            ("true", true),
+            ("TRUE", true),
+            ("yes", true),
+            ("On", true),
+            ("1", true),
+            ("-1", true),
+            ("2k", true),
+            ("false", false),
+            ("No", false),
+            ("off", false),
+            ("0", false),
+            ("\"\"", false),
+        ];
+
+        for (spelling, expected) in spellings {
+            let config = Config::parse(&format!("[core]\n  flag = {}\n", spelling)).unwrap();
+            assert_eq!(
+                config.get_bool("core.flag").unwrap(),
+                Some(expected),
+                "{}",
+                spelling
+            );
+
+            let stored = config.get_string("core.flag").unwrap().unwrap();
+            let canonical = ConfigType::Bool.canonicalize("core.flag", stored).unwrap();
+            assert_eq!(canonical, expected.to_string(), "{}", spelling);
+            assert_eq!(
+                ConfigType::Bool
+                    .normalize_for_write("core.flag", stored)
+                    .unwrap(),
+                canonical
+            );
+
+            // The writer only ever emits the canonical spelling
+            let written = obj_to_str(&json!({ "core": { "": { "flag": expected } } })).unwrap();
+            assert_eq!(written, format!("[core]\n  flag = {}\n", canonical));
+            let reread = Config::parse(&written).unwrap();
+            assert_eq!(reread.get_bool("core.flag").unwrap(), Some(expected));
+        }
+
+        // A bare key is git's shorthand for true
+        let config = Config::parse("[core]\n  flag\n").unwrap();
+        assert_eq!(config.get_bool("core.flag").unwrap(), Some(true));
+
+        assert!(
+            ConfigType::Bool
+                .canonicalize("core.flag", "maybe")
+                .unwrap_err()
+                .to_string()
+                .contains("bad boolean config value 'maybe' for 'core.flag'")
+        );
+    }
+
+    #[test]
+    fn test_config_type_int_and_path() {
+        assert_eq!(
+            ConfigType::Int.canonicalize("pack.window", "1k").unwrap(),
+            "1024"
+        );
+        assert_eq!(
+            ConfigType::Int.canonicalize("pack.window", " 7 ").unwrap(),
+            "7"
+        );
+        assert!(ConfigType::Int.canonicalize("pack.window", "lots").is_err());
+        assert_eq!(
+            ConfigType::Int
+                .normalize_for_write("pack.window", "2m")
+                .unwrap(),
+            "2097152"
+        );
+
+        assert_eq!(
+            ConfigType::Path
+                .canonicalize("core.hooks", "/etc/hooks")
+                .unwrap(),
+            "/etc/hooks"
+        );
+        assert_eq!(
+            ConfigType::Path
+                .normalize_for_write("core.hooks", "~/hooks")
+                .unwrap(),
+            "~/hooks"
+        );
+        assert!(ConfigType::parse("float").is_err());
+    }
+
+    #[test]
+    fn test_config_typed_accessors() {
+        let config = Config::parse(concat!(
+            "[core]\n",
+            "  bare\n",
+            "  filemode = off\n",
+            "  packedGitLimit = 512m\n",
+            "  excludesFile = ~/.cs01ignore\n",
+            "  worktree = /srv/work\n",
+            "  broken = sometimes\n",
+        ))
+        .unwrap();
+
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get_bool("core.filemode").unwrap(), Some(false));
+        assert_eq!(config.get_bool("core.missing").unwrap(), None);
+        assert_eq!(
+            config.get_int("core.packedgitlimit").unwrap(),
+            Some(512 * 1024 * 1024)
+        );
+        assert_eq!(
+            config.get_string("core.worktree").unwrap(),
+            Some("/srv/work")
+        );
+        assert_eq!(
+            config.get_path("core.worktree").unwrap(),
+            Some(PathBuf::from("/srv/work"))
+        );
+        if let Some(home) = home_dir() {
+            assert_eq!(
+                config.get_path("core.excludesfile").unwrap(),
+                Some(home.join(".cs01ignore"))
+            );
+        }
+
+        let err = config.get_bool("core.broken").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "bad boolean config value 'sometimes' for 'core.broken'"
+        );
+        let err = config.get_int("core.filemode").unwrap_err().to_string();
+        assert_eq!(err, "bad numeric config value 'off' for 'core.filemode'");
+    }
+
+    #[test]
+    fn test_include_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo/.CS01");
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::create_dir_all(dir.path().join("shared")).unwrap();
+
+        // repo/.CS01/config -> ../../shared/team.config -> base.config (relative to team.config)
+        std::fs::write(
+            repo.join("config"),
+            "[user]\n  name = Before\n[include]\n  path = ../../shared/team.config\n  path = missing.config\n[user]\n  email = me@example.com\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("shared/team.config"),
+            "[include]\n  path = base.config\n[user]\n  name = Team\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("shared/base.config"),
+            "[core]\n  autocrlf = input\n[user]\n  name = Base\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&repo.join("config"), None).unwrap();
+        // Included values land where the include is, so the later `Team` wins over both
+        assert_eq!(config.get_string("user.name").unwrap(), Some("Team"));
+        assert_eq!(config.get_string("core.autocrlf").unwrap(), Some("input"));
+        assert_eq!(
+            config.get_string("user.email").unwrap(),
+            Some("me@example.com")
+        );
+
+        let names: Vec<_> = load_entries(&repo.join("config"), None)
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.name == "name")
+            .map(|e| e.value)
+            .collect();
+        assert_eq!(names, vec!["Before", "Base", "Team"]);
+
+        // An absolute include that doesn't exist is an error
+        let absolute = dir.path().join("nowhere.config");
+        std::fs::write(
+            dir.path().join("abs.config"),
+            format!("[include]\n  path = {}\n", absolute.display()),
+        )
+        .unwrap();
+        let err = load_entries(&dir.path().join("abs.config"), None).unwrap_err();
+        assert!(err.to_string().contains("not found"), "{}", err);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let vfs = crate::modules::vfs::MemFs::new();
+        let dir = Path::new("/etc");
+        vfs.create_dir(dir).unwrap();
+        vfs.write(&dir.join("a.config"), b"[include]\n  path = b.config\n")
+            .unwrap();
+        vfs.write(&dir.join("b.config"), b"[include]\n  path = a.config\n")
+            .unwrap();
+
+        let err = load_entries_in(&vfs, &dir.join("a.config"), None).unwrap_err();
+        assert!(err.to_string().contains("maximum include depth"), "{}", err);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let matches = |pattern: &str, text: &str| glob_match(pattern.as_bytes(), text.as_bytes());
+
+        assert!(matches("/home/me/work/**", "/home/me/work/proj/.CS01"));
+        assert!(!matches("/home/me/work/**", "/home/me/personal/proj/.CS01"));
+        assert!(matches("**/proj/.CS01", "/home/me/work/proj/.CS01"));
+        assert!(matches("**/.CS01", ".CS01"));
+        assert!(matches("/home/*/work/*/.CS01", "/home/me/work/proj/.CS01"));
+        assert!(!matches("/home/*/.CS01", "/home/me/work/.CS01"));
+        assert!(matches("/srv/repo?.cs01", "/srv/repo1.cs01"));
+        assert!(!matches("/srv/repo?", "/srv/repo/"));
+    }
+
+    #[test]
+    fn test_include_if_cs01dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy().replace('\\', "/");
+        std::fs::write(
+            dir.path().join("work.config"),
+            "[user]\n  email = me@work.example\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("config"),
+            format!(
+                "[user]\n  email = me@home.example\n\
+                 [includeIf \"cs01dir:{root}/Work/\"]\n  path = work.config\n\
+                 [includeIf \"cs01dir/i:{root}/Other/\"]\n  path = work.config\n\
+                 [includeIf \"onbranch:main\"]\n  path = work.config\n"
+            ),
+        )
+        .unwrap();
+        let email = |repo_dir: Option<&Path>| {
+            Config::load(&dir.path().join("config"), repo_dir)
+                .unwrap()
+                .get_string("user.email")
+                .unwrap()
+                .map(str::to_string)
+        };
+
+        let work = dir.path().join("Work/proj/.CS01");
+        let other = dir.path().join("other/proj/.CS01");
+        let personal = dir.path().join("personal/proj/.CS01");
+        assert_eq!(email(Some(&work)).as_deref(), Some("me@work.example"));
+        // `cs01dir/i` ignores case, plain `cs01dir` doesn't
+        assert_eq!(email(Some(&other)).as_deref(), Some("me@work.example"));
+        assert_eq!(
+            email(Some(&dir.path().join("work/proj/.CS01"))).as_deref(),
+            Some("me@home.example")
+        );
+        assert_eq!(email(Some(&personal)).as_deref(), Some("me@home.example"));
+        // Outside a repository no condition holds
+        assert_eq!(email(None).as_deref(), Some("me@home.example"));
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| {
+            vars.iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| OsString::from(v))
+        }
+    }
+
+    #[test]
+    fn test_env_config_entries() {
+        assert!(parse_env_config(env(&[])).unwrap().is_empty());
+        assert!(
+            parse_env_config(env(&[("CS01_CONFIG_COUNT", "")]))
+                .unwrap()
+                .is_empty()
+        );
+
+        let entries = parse_env_config(env(&[
+            ("CS01_CONFIG_COUNT", "2"),
+            ("CS01_CONFIG_KEY_0", "user.name"),
+            ("CS01_CONFIG_VALUE_0", "CI Bot"),
+            ("CS01_CONFIG_KEY_1", "remote.origin.URL"),
+            ("CS01_CONFIG_VALUE_1", ""),
+        ]))
+        .unwrap();
+        let keys: Vec<_> = entries
+            .iter()
+            .map(|e| (e.key_name(), e.value.as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("user.name".to_string(), "CI Bot"),
+                ("remote.origin.url".to_string(), "")
+            ]
+        );
+        assert_eq!(entries[0].origin, ConfigOrigin::CommandLine);
+
+        let error = |vars: &[(&str, &str)]| parse_env_config(env(vars)).unwrap_err().to_string();
+        assert!(error(&[("CS01_CONFIG_COUNT", "two")]).contains("CS01_CONFIG_COUNT"));
+        assert!(error(&[("CS01_CONFIG_COUNT", "-1")]).contains("CS01_CONFIG_COUNT"));
+        assert_eq!(
+            error(&[
+                ("CS01_CONFIG_COUNT", "1"),
+                ("CS01_CONFIG_KEY_0", "user.name")
+            ]),
+            "missing config value CS01_CONFIG_VALUE_0"
+        );
+        assert_eq!(
+            error(&[("CS01_CONFIG_COUNT", "1"), ("CS01_CONFIG_VALUE_0", "x")]),
+            "missing config key CS01_CONFIG_KEY_0"
+        );
+        assert!(
+            error(&[
+                ("CS01_CONFIG_COUNT", "1"),
+                ("CS01_CONFIG_KEY_0", "nosection"),
+                ("CS01_CONFIG_VALUE_0", "x"),
+            ])
+            .contains("CS01_CONFIG_KEY_0")
+        );
+    }
+
+    #[test]
+    fn test_author_identity() {
+        let config =
+            Config::parse("[user]\n  name = A U Thor\n  email = author@example.com\n").unwrap();
+        let author = |config: &Config, vars| config.identity_with_env(Role::Author, env(vars));
+        assert_eq!(
+            author(&config, &[]).unwrap().to_string(),
+            "A U Thor <author@example.com>"
+        );
+        assert_eq!(
+            author(&config, &[("CS01_AUTHOR_NAME", "CI Bot")])
+                .unwrap()
+                .to_string(),
+            "CI Bot <author@example.com>"
+        );
+
+        let err = author(&Config::default(), &[("CS01_AUTHOR_NAME", "CI Bot")]).unwrap_err();
+        assert!(err.to_string().contains("user.email"), "{}", err);
+        assert!(
+            author(
+                &Config::default(),
+                &[
+                    ("CS01_AUTHOR_NAME", "CI Bot"),
+                    ("CS01_AUTHOR_EMAIL", "ci@example.com"),
+                ]
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_committer_identity_and_dates() {
+        let config =
+            Config::parse("[user]\n  name = A U Thor\n  email = author@example.com\n").unwrap();
+        let vars = [
+            ("CS01_AUTHOR_DATE", "1700000000 +0530"),
+            ("CS01_COMMITTER_NAME", "C O Mitter"),
+            ("CS01_COMMITTER_DATE", "1700000100 -0700"),
+        ];
+
+        let author = config.identity_with_env(Role::Author, env(&vars)).unwrap();
+        assert_eq!(author.to_string(), "A U Thor <author@example.com>");
+        assert_eq!(author.when.to_string(), "1700000000 +0530");
+
+        // The committer's overrides are separate from the author's
+        let committer = config
+            .identity_with_env(Role::Committer, env(&vars))
+            .unwrap();
+        assert_eq!(committer.to_string(), "C O Mitter <author@example.com>");
+        assert_eq!(committer.when.to_string(), "1700000100 -0700");
+
+        let err = config
+            .identity_with_env(Role::Author, env(&[("CS01_AUTHOR_DATE", "yesterday")]))
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("CS01_AUTHOR_DATE"),
+            "{:#}",
+            err
+        );
+
+        // Without committer variables or config, the author's name and email stand in,
+        // but not the author's date
+        let author_only = [
+            ("CS01_AUTHOR_NAME", "CI Bot"),
+            ("CS01_AUTHOR_EMAIL", "ci@example.com"),
+            ("CS01_AUTHOR_DATE", "1700000000 +0530"),
+        ];
+        let committer = Config::default()
+            .identity_with_env(Role::Committer, env(&author_only))
+            .unwrap();
+        assert_eq!(committer.to_string(), "CI Bot <ci@example.com>");
+        assert_ne!(committer.when.to_string(), "1700000000 +0530");
+        let committer = config
+            .identity_with_env(Role::Committer, env(&author_only))
+            .unwrap();
+        assert_eq!(committer.to_string(), "A U Thor <author@example.com>");
+
+        let err = Config::default()
+            .identity_with_env(Role::Committer, env(&[]))
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("committer identity unknown"),
+            "{}",
+            err
+        );
+    }
+
     #[test]
     fn test_obj_to_str_invalid_input() {
         // Not an object
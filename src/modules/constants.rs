@@ -0,0 +1,55 @@
+use std::ffi::OsStr;
+
+/// The metadata directory of a repository with a working tree. New repositories always
+/// get exactly this spelling.
+pub const METADATA_DIR: &str = ".CS01";
+
+/// git's metadata directory, which a working tree may hold beside ours or which marks
+/// an embedded git repository.
+pub const GIT_DIR: &str = ".git";
+
+/// The file in a working tree listing untracked paths to leave alone, like `.gitignore`.
+pub const IGNORE_FILE: &str = ".cs01ignore";
+
+/// Whether `name` is `METADATA_DIR` in any casing (`.cs01`, `.Cs01`, ...).
+///
+/// Note: Tools on case-insensitive filesystems may create the directory in lowercase,
+/// and a case-sensitive checkout of such a tree keeps that spelling.
+pub fn is_metadata_dir_name(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.eq_ignore_ascii_case(METADATA_DIR))
+}
+
+/// Whether `name` is a repository's metadata directory, ours or git's, in any casing;
+/// walks of the working tree never enter one.
+pub fn is_repository_dir_name(name: &OsStr) -> bool {
+    is_metadata_dir_name(name)
+        || name
+            .to_str()
+            .is_some_and(|name| name.eq_ignore_ascii_case(GIT_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_metadata_dir_name() {
+        for name in [".CS01", ".cs01", ".Cs01"] {
+            assert!(is_metadata_dir_name(name.as_ref()), "{}", name);
+        }
+        for name in ["CS01", ".CS01x", ".git", "bare.cs01", ""] {
+            assert!(!is_metadata_dir_name(name.as_ref()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_is_repository_dir_name() {
+        for name in [".CS01", ".cs01", ".git", ".GIT"] {
+            assert!(is_repository_dir_name(name.as_ref()), "{}", name);
+        }
+        for name in ["git", ".gitignore", ".git2", ".cs01ignore", ""] {
+            assert!(!is_repository_dir_name(name.as_ref()), "{}", name);
+        }
+    }
+}
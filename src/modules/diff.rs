@@ -0,0 +1,804 @@
+use anyhow::Result;
+use std::fmt;
+
+use crate::modules::{config::Config, hash::ObjectId, objects::tree::EntryMode};
+
+/// One step of the edit script that turns one sequence into another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// `old[old]` and `new[new]` are the same line.
+    Equal { old: usize, new: usize },
+    /// `old[old]` is removed.
+    Delete { old: usize },
+    /// `new[new]` is added.
+    Insert { new: usize },
+}
+
+/// Most lines a file's diff may add and remove together before it's reported as too
+/// large instead (`diff.maxChangedLines`, and `merge.maxChangedLines` for each side
+/// of a merge).
+///
+/// Note: The search keeps a row of positions for every changed line, so its memory
+/// grows with the square of their number; at this many, that's up to 200 MB.
+pub const MAX_CHANGED_LINES: usize = 5000;
+
+/// The `diff.maxChangedLines` or `merge.maxChangedLines` setting named by `key`, or
+/// `MAX_CHANGED_LINES`.
+pub fn max_changed_lines(config: &Config, key: &str) -> Result<usize> {
+    match config.get_int(key)? {
+        Some(value) => usize::try_from(value)
+            .map_err(|_| anyhow::anyhow!("bad config value {} for '{}'", value, key)),
+        None => Ok(MAX_CHANGED_LINES),
+    }
+}
+
+/// A shortest edit script from `old` to `new`, in order, found with Myers' O(ND)
+/// algorithm. Among scripts of the same length, deletions come before insertions.
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    diff_lines_within(old, new, usize::MAX).expect("no limit on the changes")
+}
+
+/// `diff_lines`, or `None` if the script would add and remove more than `max_changes`
+/// lines, found out without searching further than that.
+pub fn diff_lines_within<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    max_changes: usize,
+) -> Option<Vec<Edit>> {
+    // Note: The common ends are split off first; they're often most of a file, and
+    // the search's memory grows with the lines in between.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut edits: Vec<Edit> = (0..prefix)
+        .map(|i| Edit::Equal { old: i, new: i })
+        .collect();
+    let middle = myers(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        max_changes,
+    )?;
+    edits.extend(middle.into_iter().map(|edit| match edit {
+        Edit::Equal { old, new } => Edit::Equal {
+            old: old + prefix,
+            new: new + prefix,
+        },
+        Edit::Delete { old } => Edit::Delete { old: old + prefix },
+        Edit::Insert { new } => Edit::Insert { new: new + prefix },
+    }));
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    edits.extend((0..suffix).map(|i| Edit::Equal {
+        old: old_end + i,
+        new: new_end + i,
+    }));
+    Some(edits)
+}
+
+/// The greedy forward search of Myers' algorithm, keeping the furthest point of every
+/// diagonal reached after each step so the path can be walked back; `None` once more
+/// than `max_changes` steps would be needed.
+fn myers<T: PartialEq>(old: &[T], new: &[T], max_changes: usize) -> Option<Vec<Edit>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let at = |k: isize| (k + offset) as usize;
+    let mut furthest = vec![0isize; 2 * max as usize + 3];
+    // Before step `d`, the diagonals `-d..=d` of `furthest`: all the walk back reads.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        if d as usize > max_changes {
+            return None;
+        }
+        trace.push(furthest[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && furthest[at(k - 1)] < furthest[at(k + 1)]);
+            let mut x = if down {
+                furthest[at(k + 1)]
+            } else {
+                furthest[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| (k + d) as usize;
+        let k = x - y;
+        let down = k == -d || (k != d && furthest[at(k - 1)] < furthest[at(k + 1)]);
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let previous_x = match d {
+            0 => 0,
+            _ => furthest[at(previous_k)],
+        };
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if d > 0 {
+            if down {
+                edits.push(Edit::Insert {
+                    new: previous_y as usize,
+                });
+            } else {
+                edits.push(Edit::Delete {
+                    old: previous_x as usize,
+                });
+            }
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+/// The lines of `data`, each with its `\n` (the last one may have none).
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Whether `data` looks binary, as git guesses: a NUL byte in its first 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// How a line of a hunk relates the two sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Context,
+    Removed,
+    Added,
+}
+
+impl LineKind {
+    /// The character that starts the line in a unified diff.
+    pub fn marker(&self) -> char {
+        match self {
+            LineKind::Context => ' ',
+            LineKind::Removed => '-',
+            LineKind::Added => '+',
+        }
+    }
+}
+
+/// One line of a hunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunkLine {
+    pub kind: LineKind,
+    /// The line without its `\n`.
+    pub text: String,
+    /// The line ends its file without a `\n`, which a unified diff notes after it.
+    pub missing_newline: bool,
+}
+
+/// A run of changes with the unchanged lines around them, as in a unified diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The first line of the hunk in the old file, from 1; for an empty range, the
+    /// line before it.
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+impl Hunk {
+    /// The `@@ -old +new @@` line; a length of 1 isn't written, as in git.
+    pub fn header(&self) -> String {
+        let range = |start: usize, len: usize| match len {
+            1 => start.to_string(),
+            _ => format!("{},{}", start, len),
+        };
+        format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len),
+            range(self.new_start, self.new_len)
+        )
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.header())?;
+        for line in &self.lines {
+            writeln!(f, "{}{}", line.kind.marker(), line.text)?;
+            if line.missing_newline {
+                writeln!(f, "\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `diff --check` found problems; the command exits with a distinct status, as git's does.
+#[derive(Debug)]
+pub struct WhitespaceErrorsFound {
+    pub count: usize,
+}
+
+impl fmt::Display for WhitespaceErrorsFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.count {
+            1 => write!(f, "1 line with whitespace errors"),
+            count => write!(f, "{} lines with whitespace errors", count),
+        }
+    }
+}
+
+impl std::error::Error for WhitespaceErrorsFound {}
+
+/// A whitespace problem in an added line, as `diff --check` reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhitespaceError {
+    /// Spaces, tabs or a carriage return at the end of the line.
+    TrailingWhitespace,
+    /// A space followed by a tab in the line's indent.
+    SpaceBeforeTab,
+    /// A `<<<<<<<`, `|||||||`, `=======` or `>>>>>>>` line left over from a conflict.
+    ConflictMarker,
+}
+
+impl fmt::Display for WhitespaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WhitespaceError::TrailingWhitespace => "trailing whitespace",
+            WhitespaceError::SpaceBeforeTab => "space before tab in indent",
+            WhitespaceError::ConflictMarker => "leftover conflict marker",
+        })
+    }
+}
+
+/// The length of the markers merge writes around conflicting lines.
+const CONFLICT_MARKER_LEN: usize = 7;
+
+/// Whether `line` is a conflict marker: seven of the same marker character, then
+/// nothing or (except for `=======`) a space and a label.
+fn is_conflict_marker(line: &str) -> bool {
+    let Some(first) = line.chars().next().filter(|c| "<|=>".contains(*c)) else {
+        return false;
+    };
+    let Some(rest) = line
+        .strip_prefix(&first.to_string().repeat(CONFLICT_MARKER_LEN))
+        .map(|rest| rest.trim_end_matches('\r'))
+    else {
+        return false;
+    };
+    rest.is_empty() || (first != '=' && rest.starts_with(' '))
+}
+
+impl WhitespaceError {
+    /// The problems in `line`, in the order git names them.
+    pub fn find(line: &str) -> Vec<WhitespaceError> {
+        let mut errors = Vec::new();
+        if line.ends_with([' ', '\t', '\r']) {
+            errors.push(WhitespaceError::TrailingWhitespace);
+        }
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        if indent.contains(" \t") {
+            errors.push(WhitespaceError::SpaceBeforeTab);
+        }
+        if is_conflict_marker(line) {
+            errors.push(WhitespaceError::ConflictMarker);
+        }
+        errors
+    }
+}
+
+impl Hunk {
+    /// The added lines with whitespace problems, each with its line number in the new
+    /// file and the line itself.
+    pub fn whitespace_errors(&self) -> Vec<(usize, &HunkLine, Vec<WhitespaceError>)> {
+        let mut number = self.new_start;
+        let mut found = Vec::new();
+        for line in &self.lines {
+            match line.kind {
+                LineKind::Removed => continue,
+                LineKind::Context => {}
+                LineKind::Added => {
+                    let errors = WhitespaceError::find(&line.text);
+                    if !errors.is_empty() {
+                        found.push((number, line, errors));
+                    }
+                }
+            }
+            number += 1;
+        }
+        found
+    }
+}
+
+/// The hunks of a line diff from `old` to `new`, each change with up to `context`
+/// unchanged lines around it; changes closer than that share a hunk.
+pub fn hunks(old: &[u8], new: &[u8], context: usize) -> Vec<Hunk> {
+    hunks_within(old, new, context, usize::MAX).expect("no limit on the changes")
+}
+
+/// `hunks`, or `None` if they'd add and remove more than `max_changes` lines.
+pub fn hunks_within(
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+    max_changes: usize,
+) -> Option<Vec<Hunk>> {
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    let edits = diff_lines_within(&old_lines, &new_lines, max_changes)?;
+
+    // Where each edit starts in both files, to number the hunks' lines.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_at, mut new_at) = (0, 0);
+    for edit in &edits {
+        positions.push((old_at, new_at));
+        match edit {
+            Edit::Equal { .. } => (old_at, new_at) = (old_at + 1, new_at + 1),
+            Edit::Delete { .. } => old_at += 1,
+            Edit::Insert { .. } => new_at += 1,
+        }
+    }
+    positions.push((old_at, new_at));
+
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (i, edit) in edits.iter().enumerate() {
+        if matches!(edit, Edit::Equal { .. }) {
+            continue;
+        }
+        let (start, end) = (
+            i.saturating_sub(context),
+            (i + context + 1).min(edits.len()),
+        );
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let line = |kind, text: &[u8], last: bool| {
+        let missing_newline = last && !text.ends_with(b"\n");
+        let text = text.strip_suffix(b"\n").unwrap_or(text);
+        HunkLine {
+            kind,
+            text: String::from_utf8_lossy(text).into_owned(),
+            missing_newline,
+        }
+    };
+    let hunks = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let ((old_from, new_from), (old_to, new_to)) = (positions[start], positions[end]);
+            let lines = edits[start..end]
+                .iter()
+                .map(|edit| match *edit {
+                    Edit::Equal { old, .. } => line(
+                        LineKind::Context,
+                        old_lines[old],
+                        old + 1 == old_lines.len(),
+                    ),
+                    Edit::Delete { old } => line(
+                        LineKind::Removed,
+                        old_lines[old],
+                        old + 1 == old_lines.len(),
+                    ),
+                    Edit::Insert { new } => {
+                        line(LineKind::Added, new_lines[new], new + 1 == new_lines.len())
+                    }
+                })
+                .collect();
+            let first_line = |from: usize, to: usize| if to > from { from + 1 } else { from };
+            Hunk {
+                old_start: first_line(old_from, old_to),
+                old_len: old_to - old_from,
+                new_start: first_line(new_from, new_to),
+                new_len: new_to - new_from,
+                lines,
+            }
+        })
+        .collect();
+    Some(hunks)
+}
+
+/// One side of a file's change: its mode, blob id and content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileVersion {
+    pub mode: EntryMode,
+    pub oid: ObjectId,
+    pub data: Vec<u8>,
+}
+
+/// What a patch shows besides its header lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchBody {
+    /// The content is the same (only the mode changed, or the file is empty).
+    Empty,
+    /// Line hunks, after `--- a/path` and `+++ b/path` lines.
+    Text {
+        old_name: String,
+        new_name: String,
+        hunks: Vec<Hunk>,
+    },
+    /// Either side is binary; the line saying so.
+    Binary(String),
+    /// The change has more lines than the limit allows; the line saying so.
+    TooLarge(String),
+}
+
+/// The git-style patch for one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePatch {
+    pub path: String,
+    /// `diff --git`, then mode and `index` lines.
+    pub header: Vec<String>,
+    pub body: PatchBody,
+}
+
+impl fmt::Display for FilePatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.header {
+            writeln!(f, "{}", line)?;
+        }
+        match &self.body {
+            PatchBody::Empty => Ok(()),
+            PatchBody::Text {
+                old_name,
+                new_name,
+                hunks,
+            } => {
+                writeln!(f, "--- {}", old_name)?;
+                writeln!(f, "+++ {}", new_name)?;
+                hunks.iter().try_for_each(|hunk| write!(f, "{}", hunk))
+            }
+            PatchBody::Binary(line) | PatchBody::TooLarge(line) => writeln!(f, "{}", line),
+        }
+    }
+}
+
+/// The patches that take `path` from `old` to `new` (`None` when it doesn't exist on
+/// that side), with `context` lines around changes; none if they're the same. A
+/// change adding and removing more than `max_changes` lines gets no hunks, only a
+/// line saying it's too large to diff.
+///
+/// Note: As in git, a change of file type (e.g. a file becoming a symlink) is shown
+/// as the old file's deletion followed by the new one's creation.
+pub fn file_patches(
+    path: &str,
+    old: Option<&FileVersion>,
+    new: Option<&FileVersion>,
+    context: usize,
+    max_changes: usize,
+) -> Vec<FilePatch> {
+    match (old, new) {
+        (Some(old), Some(new)) if file_type(old.mode) != file_type(new.mode) => {
+            let mut patches = file_patches(path, Some(old), None, context, max_changes);
+            patches.extend(file_patches(path, None, Some(new), context, max_changes));
+            patches
+        }
+        (Some(old), Some(new)) if old.mode == new.mode && old.oid == new.oid => Vec::new(),
+        (None, None) => Vec::new(),
+        _ => vec![file_patch(path, old, new, context, max_changes)],
+    }
+}
+
+/// Files, symlinks and submodules can't be diffed into each other.
+fn file_type(mode: EntryMode) -> u8 {
+    match mode {
+        EntryMode::File | EntryMode::Executable => 0,
+        EntryMode::Symlink => 1,
+        EntryMode::Tree => 2,
+        EntryMode::Commit => 3,
+    }
+}
+
+fn file_patch(
+    path: &str,
+    old: Option<&FileVersion>,
+    new: Option<&FileVersion>,
+    context: usize,
+    max_changes: usize,
+) -> FilePatch {
+    let mode = |version: &FileVersion| format!("{:06o}", version.mode.bits());
+    let abbrev = |version: Option<&FileVersion>| match version {
+        Some(version) => version.oid.short(7),
+        None => "0".repeat(7),
+    };
+
+    let mut header = vec![format!("diff --git a/{} b/{}", path, path)];
+    match (old, new) {
+        (None, Some(new)) => header.push(format!("new file mode {}", mode(new))),
+        (Some(old), None) => header.push(format!("deleted file mode {}", mode(old))),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.push(format!("old mode {}", mode(old)));
+            header.push(format!("new mode {}", mode(new)));
+        }
+        _ => {}
+    }
+    let same_content = old.map(|v| v.oid) == new.map(|v| v.oid);
+    if !same_content {
+        let mut index = format!("index {}..{}", abbrev(old), abbrev(new));
+        if let (Some(old), Some(new)) = (old, new)
+            && old.mode == new.mode
+        {
+            index.push_str(&format!(" {}", mode(old)));
+        }
+        header.push(index);
+    }
+
+    let old_data = old.map_or(&[][..], |v| &v.data);
+    let new_data = new.map_or(&[][..], |v| &v.data);
+    let old_name = old.map_or("/dev/null".to_string(), |_| format!("a/{}", path));
+    let new_name = new.map_or("/dev/null".to_string(), |_| format!("b/{}", path));
+    let body = if same_content || (old_data.is_empty() && new_data.is_empty()) {
+        PatchBody::Empty
+    } else if is_binary(old_data) || is_binary(new_data) {
+        PatchBody::Binary(format!("Binary files {} and {} differ", old_name, new_name))
+    } else {
+        match hunks_within(old_data, new_data, context, max_changes) {
+            Some(hunks) => PatchBody::Text {
+                old_name,
+                new_name,
+                hunks,
+            },
+            None => PatchBody::TooLarge(format!(
+                "Files {} and {} are too large to diff",
+                old_name, new_name
+            )),
+        }
+    };
+    FilePatch {
+        path: path.to_string(),
+        header,
+        body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::hash::{ObjectKind, hash_object};
+
+    /// Applies `edits` to `old`, checking they're consistent with `new`.
+    fn apply<'a>(old: &[&'a str], new: &[&'a str], edits: &[Edit]) -> Vec<&'a str> {
+        let mut result = Vec::new();
+        for edit in edits {
+            match *edit {
+                Edit::Equal { old: i, new: j } => {
+                    assert_eq!(old[i], new[j]);
+                    result.push(old[i]);
+                }
+                Edit::Delete { .. } => {}
+                Edit::Insert { new: j } => result.push(new[j]),
+            }
+        }
+        result
+    }
+
+    fn changes(edits: &[Edit]) -> usize {
+        edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Equal { .. }))
+            .count()
+    }
+
+    #[test]
+    fn test_diff_lines_finds_shortest_scripts() {
+        // The example of Myers' paper, and a few edge cases
+        let cases: &[(&str, &str, usize)] = &[
+            ("ABCABBA", "CBABAC", 5),
+            ("", "", 0),
+            ("", "ABC", 3),
+            ("ABC", "", 3),
+            ("ABC", "ABC", 0),
+            ("ABCD", "AXCD", 2),
+            ("ABCDEF", "ABDEFC", 2),
+            ("XAAAY", "AAAA", 3),
+        ];
+        for (old, new, expected) in cases {
+            let old: Vec<&str> = old.split_terminator("").skip(1).collect();
+            let new: Vec<&str> = new.split_terminator("").skip(1).collect();
+            let edits = diff_lines(&old, &new);
+            assert_eq!(apply(&old, &new, &edits), new, "{:?} -> {:?}", old, new);
+            assert_eq!(changes(&edits), *expected, "{:?} -> {:?}", old, new);
+        }
+
+        // Deletions come first in a replaced run
+        let edits = diff_lines(&["a", "b", "c"], &["a", "x", "c"]);
+        assert_eq!(
+            edits,
+            [
+                Edit::Equal { old: 0, new: 0 },
+                Edit::Delete { old: 1 },
+                Edit::Insert { new: 1 },
+                Edit::Equal { old: 2, new: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_lines_within_a_limit() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "x", "c", "y"];
+        assert_eq!(
+            diff_lines_within(&old, &new, 4),
+            Some(diff_lines(&old, &new))
+        );
+        assert_eq!(diff_lines_within(&old, &new, 3), None);
+        assert_eq!(diff_lines_within(&old, &old, 0).unwrap().len(), 4);
+
+        // Entirely different inputs stop at the limit rather than searching them all
+        let old: Vec<usize> = (0..200_000).collect();
+        let new: Vec<usize> = (200_000..400_000).collect();
+        let started = std::time::Instant::now();
+        assert_eq!(diff_lines_within(&old, &new, 1000), None);
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_hunks_with_context() {
+        let old: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new = old
+            .replace("line 3\n", "line three\n")
+            .replace("line 6\n", "")
+            .replace("line 18\n", "line 18\nextra\n");
+
+        let rendered: String = hunks(old.as_bytes(), new.as_bytes(), 3)
+            .iter()
+            .map(|hunk| hunk.to_string())
+            .collect();
+        assert_eq!(
+            rendered,
+            "@@ -1,9 +1,8 @@\n line 1\n line 2\n-line 3\n+line three\n line 4\n line 5\n\
+             -line 6\n line 7\n line 8\n line 9\n\
+             @@ -16,5 +15,6 @@\n line 16\n line 17\n line 18\n+extra\n line 19\n line 20\n"
+        );
+
+        // Changes two lines apart still share a hunk with one line of context, but
+        // not with none
+        assert_eq!(hunks(old.as_bytes(), new.as_bytes(), 1).len(), 2);
+        let hunks = hunks(old.as_bytes(), new.as_bytes(), 0);
+        assert_eq!(hunks[1].header(), "@@ -6 +5,0 @@");
+        assert_eq!(hunks[2].header(), "@@ -18,0 +18 @@");
+    }
+
+    #[test]
+    fn test_hunks_edge_cases() {
+        let hunk = &hunks(b"", b"a\nb\n", 3)[0];
+        assert_eq!(hunk.to_string(), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        let hunk = &hunks(b"a\n", b"", 3)[0];
+        assert_eq!(hunk.to_string(), "@@ -1 +0,0 @@\n-a\n");
+        let hunk = &hunks(b"a\nb", b"a\nb\n", 3)[0];
+        assert_eq!(
+            hunk.to_string(),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+        assert!(hunks(b"same\n", b"same\n", 3).is_empty());
+    }
+
+    #[test]
+    fn test_whitespace_errors() {
+        use WhitespaceError::*;
+        assert_eq!(WhitespaceError::find("clean"), []);
+        assert_eq!(WhitespaceError::find("x \t"), [TrailingWhitespace]);
+        assert_eq!(WhitespaceError::find("x\r"), [TrailingWhitespace]);
+        assert_eq!(WhitespaceError::find(" \tx"), [SpaceBeforeTab]);
+        assert_eq!(WhitespaceError::find("x \ty"), []);
+        assert_eq!(
+            WhitespaceError::find("  \t "),
+            [TrailingWhitespace, SpaceBeforeTab]
+        );
+
+        // Conflict markers, with or without a label
+        assert_eq!(WhitespaceError::find("<<<<<<< HEAD"), [ConflictMarker]);
+        assert_eq!(WhitespaceError::find("|||||||"), [ConflictMarker]);
+        assert_eq!(WhitespaceError::find("======="), [ConflictMarker]);
+        assert_eq!(WhitespaceError::find(">>>>>>> topic"), [ConflictMarker]);
+        assert_eq!(
+            WhitespaceError::find("=======\r"),
+            [TrailingWhitespace, ConflictMarker]
+        );
+        assert_eq!(WhitespaceError::find("======= x"), []);
+        assert_eq!(WhitespaceError::find("<<<<<<<<"), []);
+        assert_eq!(WhitespaceError::find("<<<<<<"), []);
+        assert_eq!(WhitespaceError::find("<<<<<<<x"), []);
+
+        // Only added lines count, numbered in the new file
+        let old = b"a \nb\nc\n";
+        let new = b"a \nb \nc\nd\t\n";
+        let hunk = &hunks(old, new, 3)[0];
+        let found: Vec<_> = hunk
+            .whitespace_errors()
+            .into_iter()
+            .map(|(number, line, errors)| (number, line.text.as_str(), errors))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (2, "b ", vec![TrailingWhitespace]),
+                (4, "d\t", vec![TrailingWhitespace]),
+            ]
+        );
+    }
+
+    fn version(mode: EntryMode, data: &[u8]) -> FileVersion {
+        FileVersion {
+            mode,
+            oid: hash_object(ObjectKind::Blob, data),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_file_patches() {
+        let old = version(EntryMode::File, b"a\n");
+        let new = version(EntryMode::File, b"b\n");
+        let patches = file_patches("f.txt", Some(&old), Some(&new), 3, MAX_CHANGED_LINES);
+        assert_eq!(
+            patches[0].to_string(),
+            format!(
+                "diff --git a/f.txt b/f.txt\nindex {}..{} 100644\n--- a/f.txt\n+++ b/f.txt\n\
+                 @@ -1 +1 @@\n-a\n+b\n",
+                old.oid.short(7),
+                new.oid.short(7)
+            )
+        );
+
+        let patch = &file_patches("f.txt", None, Some(&new), 3, MAX_CHANGED_LINES)[0];
+        assert_eq!(patch.header[1], "new file mode 100644");
+        assert_eq!(
+            patch.header[2],
+            format!("index 0000000..{}", new.oid.short(7))
+        );
+        assert!(patch.to_string().contains("--- /dev/null\n+++ b/f.txt\n"));
+
+        // A mode change alone has no content lines
+        let executable = version(EntryMode::Executable, b"a\n");
+        let patch = &file_patches("f.txt", Some(&old), Some(&executable), 3, MAX_CHANGED_LINES)[0];
+        assert_eq!(
+            patch.to_string(),
+            "diff --git a/f.txt b/f.txt\nold mode 100644\nnew mode 100755\n"
+        );
+
+        // A type change is a deletion and an addition
+        let link = version(EntryMode::Symlink, b"target");
+        let patches = file_patches("f.txt", Some(&old), Some(&link), 3, MAX_CHANGED_LINES);
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].header[1], "deleted file mode 100644");
+        assert_eq!(patches[1].header[1], "new file mode 120000");
+
+        let binary = version(EntryMode::File, b"\0\x01");
+        let patch = &file_patches("bin", Some(&old), Some(&binary), 3, MAX_CHANGED_LINES)[0];
+        assert_eq!(
+            patch.body,
+            PatchBody::Binary("Binary files a/bin and b/bin differ".to_string())
+        );
+        assert!(file_patches("f.txt", Some(&old), Some(&old), 3, MAX_CHANGED_LINES).is_empty());
+
+        // Past the limit, a line in place of the hunks
+        let longer = version(EntryMode::File, b"b\nc\n");
+        let patch = &file_patches("f.txt", Some(&old), Some(&longer), 3, 2)[0];
+        assert_eq!(
+            patch.body,
+            PatchBody::TooLarge("Files a/f.txt and b/f.txt are too large to diff".to_string())
+        );
+        let patch = &file_patches("f.txt", Some(&old), Some(&longer), 3, 3)[0];
+        assert!(matches!(patch.body, PatchBody::Text { .. }));
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{Context, Result, bail};
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+use crate::modules::config::Config;
+use crate::repo::Repository;
+
+/// Environment variable naming the editor, ahead of `VISUAL` and `EDITOR`, like `GIT_EDITOR`.
+pub const CS01_EDITOR_ENV: &str = "CS01_EDITOR";
+
+/// Used when neither the environment nor `core.editor` names an editor.
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Picks the editor command: `CS01_EDITOR`, `VISUAL`, `EDITOR`, then `core.editor`.
+pub fn editor_command(config: &Config) -> Result<String> {
+    resolve_editor(config, |name| std::env::var_os(name))
+}
+
+fn resolve_editor(config: &Config, var: impl Fn(&str) -> Option<OsString>) -> Result<String> {
+    for name in [CS01_EDITOR_ENV, "VISUAL", "EDITOR"] {
+        if let Some(editor) = var(name).filter(|value| !value.is_empty()) {
+            return editor
+                .into_string()
+                .map_err(|_| anyhow::anyhow!("{} is not valid UTF-8", name));
+        }
+    }
+
+    Ok(config
+        .get_string("core.editor")?
+        .unwrap_or(DEFAULT_EDITOR)
+        .to_string())
+}
+
+/// Opens `path` in the user's editor and waits for it to exit.
+///
+/// Note: Like git, the editor string is run by the shell, so it may carry
+/// arguments (e.g. `code --wait`). A non-zero exit is an error, and callers
+/// should then discard whatever the editor left in `path`.
+pub fn launch(path: &Path) -> Result<()> {
+    let config = match Repository::discover(Path::new("."))? {
+        Some(repo) => repo.config()?,
+        None => Config::load_cascaded(None)?,
+    };
+    let editor = editor_command(&config)?;
+
+    let status = shell_command(&editor, path)
+        .status()
+        .with_context(|| format!("unable to start editor '{}'", editor))?;
+    if !status.success() {
+        bail!("there was a problem with the editor '{}'", editor);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell_command(editor: &str, path: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(path);
+    command
+}
+
+#[cfg(not(unix))]
+fn shell_command(editor: &str, path: &Path) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(editor).arg(path);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_editor_order() {
+        let config = Config::parse("[core]\n  editor = nano\n").unwrap();
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| OsString::from(v))
+            }
+        };
+
+        assert_eq!(resolve_editor(&config, env(&[])).unwrap(), "nano");
+        assert_eq!(
+            resolve_editor(&Config::default(), env(&[])).unwrap(),
+            DEFAULT_EDITOR
+        );
+        assert_eq!(
+            resolve_editor(&config, env(&[("EDITOR", "ed"), ("VISUAL", "vim")])).unwrap(),
+            "vim"
+        );
+        assert_eq!(
+            resolve_editor(
+                &config,
+                env(&[("EDITOR", "ed"), ("CS01_EDITOR", "code --wait")])
+            )
+            .unwrap(),
+            "code --wait"
+        );
+        // An empty variable counts as unset
+        assert_eq!(
+            resolve_editor(&config, env(&[("CS01_EDITOR", ""), ("EDITOR", "ed")])).unwrap(),
+            "ed"
+        );
+    }
+}
@@ -1,175 +1,2802 @@
-use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::fs;
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
 
+use crate::modules::{
+    config::{Config, parse_bool},
+    constants::{METADATA_DIR, is_metadata_dir_name, is_repository_dir_name},
+    output::Output,
+    vfs::{RealFs, Vfs},
+};
+use crate::repo::NotARepository;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TreeNode {
-    File(String),
-    Directory(HashMap<String, TreeNode>),
+    /// A regular file, written byte for byte. `mode` overrides `WriteOptions::file_perms` when set.
+    File { content: Vec<u8>, mode: Option<u32> },
+    /// A directory of named entries. `mode` overrides `WriteOptions::dir_perms` when set.
+    Directory {
+        entries: HashMap<String, TreeNode>,
+        mode: Option<u32>,
+    },
+    /// A symbolic link pointing at the given target, stored as written (usually relative).
+    Symlink(PathBuf),
+}
+
+impl TreeNode {
+    /// A regular file whose permissions are decided by `WriteOptions`.
+    pub fn file(content: impl Into<Vec<u8>>) -> Self {
+        TreeNode::File {
+            content: content.into(),
+            mode: None,
+        }
+    }
+
+    /// A text file, for the common case of config files, refs and templates.
+    pub fn text(content: impl Into<String>) -> Self {
+        TreeNode::file(content.into())
+    }
+
+    /// A file with explicit permission bits, e.g. `0o755` for executables.
+    pub fn file_with_mode(content: impl Into<Vec<u8>>, mode: u32) -> Self {
+        TreeNode::File {
+            content: content.into(),
+            mode: Some(mode),
+        }
+    }
+
+    /// A directory whose permissions are decided by `WriteOptions`.
+    pub fn dir(entries: HashMap<String, TreeNode>) -> Self {
+        TreeNode::Directory {
+            entries,
+            mode: None,
+        }
+    }
+
+    /// A directory with explicit permission bits, e.g. `0o700` for private data.
+    pub fn dir_with_mode(entries: HashMap<String, TreeNode>, mode: u32) -> Self {
+        TreeNode::Directory {
+            entries,
+            mode: Some(mode),
+        }
+    }
+
+    /// The tree as pretty-printed JSON, with entries in sorted order (see `TreeNodeRepr`).
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads a tree written by `to_json`, rejecting names `check_tree_name` would refuse.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// The serialized form of a `TreeNode`, tagged by `type`:
+/// `{"type":"file","content":"..."}`, `{"type":"dir","entries":{...}}`
+/// or `{"type":"symlink","target":"..."}`.
+///
+/// Note: File content that isn't UTF-8 goes in `base64` instead of `content`, and
+/// `mode` (on files and directories) is an octal string like `"755"`. Entries are
+/// sorted, so equal trees always serialize identically.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum TreeNodeRepr {
+    File {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        base64: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<String>,
+    },
+    Dir {
+        entries: BTreeMap<String, TreeNodeRepr>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<String>,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+impl TreeNodeRepr {
+    fn from_node(node: &TreeNode) -> Result<Self> {
+        Ok(match node {
+            TreeNode::File { content, mode } => {
+                let (text, base64) = match std::str::from_utf8(content) {
+                    Ok(text) => (Some(text.to_string()), None),
+                    Err(_) => (None, Some(BASE64.encode(content))),
+                };
+                TreeNodeRepr::File {
+                    content: text,
+                    base64,
+                    mode: mode.map(|mode| format!("{:o}", mode)),
+                }
+            }
+            TreeNode::Directory { entries, mode } => TreeNodeRepr::Dir {
+                entries: entries
+                    .iter()
+                    .map(|(name, child)| Ok((name.clone(), TreeNodeRepr::from_node(child)?)))
+                    .collect::<Result<_>>()?,
+                mode: mode.map(|mode| format!("{:o}", mode)),
+            },
+            TreeNode::Symlink(target) => TreeNodeRepr::Symlink {
+                target: target
+                    .to_str()
+                    .with_context(|| format!("symlink target {:?} is not valid UTF-8", target))?
+                    .to_string(),
+            },
+        })
+    }
+
+    /// Converts back to a `TreeNode`; `path` locates this node in error messages.
+    fn into_node(self, path: &Path) -> Result<TreeNode> {
+        Ok(match self {
+            TreeNodeRepr::File {
+                content,
+                base64,
+                mode,
+            } => {
+                let content = match (content, base64) {
+                    (Some(text), None) => text.into_bytes(),
+                    (None, Some(encoded)) => BASE64
+                        .decode(encoded)
+                        .with_context(|| format!("invalid base64 content for {:?}", path))?,
+                    (None, None) => Vec::new(),
+                    (Some(_), Some(_)) => {
+                        bail!("{:?} has both content and base64", path)
+                    }
+                };
+                let mode = parse_mode(mode, path)?;
+                TreeNode::File { content, mode }
+            }
+            TreeNodeRepr::Dir { entries, mode } => {
+                let mut children = HashMap::new();
+                for (name, child) in entries {
+                    check_tree_name(&name, path)?;
+                    let child = child.into_node(&path.join(&name))?;
+                    children.insert(name, child);
+                }
+                TreeNode::Directory {
+                    entries: children,
+                    mode: parse_mode(mode, path)?,
+                }
+            }
+            TreeNodeRepr::Symlink { target } => TreeNode::Symlink(PathBuf::from(target)),
+        })
+    }
+}
+
+/// Parses an optional octal `mode` string of the node at `path`.
+fn parse_mode(mode: Option<String>, path: &Path) -> Result<Option<u32>> {
+    mode.map(|mode| {
+        u32::from_str_radix(&mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .with_context(|| format!("invalid mode {:?} for {:?}", mode, path))
+    })
+    .transpose()
+}
+
+impl Serialize for TreeNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        TreeNodeRepr::from_node(self)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TreeNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        TreeNodeRepr::deserialize(deserializer)?
+            .into_node(Path::new("."))
+            .map_err(serde::de::Error::custom)
+    }
 }
 
+/// Environment variable naming the metadata directory explicitly, like `GIT_DIR`.
+pub const CS01_DIR_ENV: &str = "CS01_DIR";
+
+/// Environment variable naming the working tree when `CS01_DIR` is set, like `GIT_WORK_TREE`.
+pub const CS01_WORK_TREE_ENV: &str = "CS01_WORK_TREE";
+
+/// Environment variable listing directories repository discovery never climbs into,
+/// like `GIT_CEILING_DIRECTORIES`.
+pub const CS01_CEILING_DIRECTORIES_ENV: &str = "CS01_CEILING_DIRECTORIES";
+
+/// Environment variable letting repository discovery cross mount points,
+/// like `GIT_DISCOVERY_ACROSS_FILESYSTEM`.
+pub const CS01_DISCOVERY_ACROSS_FILESYSTEM_ENV: &str = "CS01_DISCOVERY_ACROSS_FILESYSTEM";
+
 /// Helper to check if `cwd` is within a CS01 repo.
 pub fn in_repo(cwd: Option<&Path>) -> bool {
-    cs01_path(None, cwd).is_some()
+    matches!(discover(cwd), Ok(Some(_)))
+}
+
+/// A repository found by `discover`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredRepo {
+    /// The metadata directory: `<work tree>/.CS01`, the target of a `.CS01` pointer file,
+    /// `CS01_DIR`, or the repository itself when bare.
+    pub repo_dir: PathBuf,
+    /// The working tree root; `None` for bare repositories.
+    pub work_tree: Option<PathBuf>,
 }
 
-/// Locates the root of the CS01 repository.
+impl DiscoveredRepo {
+    /// A repository rooted at `root`; it is bare when `root` is the metadata directory.
+    fn new(root: PathBuf, repo_dir: PathBuf) -> Self {
+        let work_tree = (root != repo_dir).then_some(root);
+        DiscoveredRepo {
+            repo_dir,
+            work_tree,
+        }
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.work_tree.is_none()
+    }
+
+    /// The top of the repository: the working tree, or the metadata directory when bare.
+    pub fn root(&self) -> &Path {
+        self.work_tree.as_deref().unwrap_or(&self.repo_dir)
+    }
+
+    /// `relative` resolved against `root()`.
+    pub fn path_in_repo(&self, relative: &str) -> PathBuf {
+        self.root().join(relative)
+    }
+}
+
+/// Locates the root of the CS01 repository, with `relative_path` joined onto it.
+#[deprecated(note = "use `discover` and `DiscoveredRepo::path_in_repo`")]
+pub fn cs01_path(relative_path: Option<&str>, start_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+    Ok(discover(start_dir)?.map(|found| found.path_in_repo(relative_path.unwrap_or(""))))
+}
+
+/// Finds the repository containing `start_dir` (the current directory by default).
 ///
-/// Critical: This function traverses UPDWARDS from `start_dir`.
+/// If `CS01_DIR` is set, it is used as-is and no search happens; an invalid
+/// `CS01_DIR` is an error rather than a reason to fall back to searching.
+///
+/// Critical: Otherwise this function traverses UPDWARDS from `start_dir`, stopping
+/// below any directory listed in `CS01_CEILING_DIRECTORIES` and at the edge of the
+/// filesystem `start_dir` is on (see `discovery_across_filesystems`).
 /// It identifies the root by looking for:
-/// 1. `.CS01` directory (Standard)
-/// 2. `config` file containing `[core]` section (Bare)
-pub fn cs01_path(relative_path: Option<&str>, start_dir: Option<&Path>) -> Option<PathBuf> {
+/// 1. `.CS01` directory (Standard), in any casing
+/// 2. `.CS01` file reading `cs01dir: <path>` (metadata kept elsewhere, e.g. a linked worktree)
+/// 3. `config` file containing `[core]` section, next to `HEAD` and `objects/` or `refs/` (Bare)
+pub fn discover(start_dir: Option<&Path>) -> Result<Option<DiscoveredRepo>> {
+    discover_in(&RealFs, start_dir)
+}
+
+/// Like `discover`, but looking at `vfs` instead of the real filesystem.
+///
+/// Note: Ceilings and mount points are still resolved on the real filesystem.
+pub fn discover_in(vfs: &dyn Vfs, start_dir: Option<&Path>) -> Result<Option<DiscoveredRepo>> {
+    if let Some(root) = repo_root_from_env(
+        vfs,
+        std::env::var_os(CS01_DIR_ENV),
+        std::env::var_os(CS01_WORK_TREE_ENV),
+    )? {
+        let repo_dir = repo_dir_from_env().unwrap_or_else(|| root.clone());
+        return Ok(Some(DiscoveredRepo::new(root, repo_dir)));
+    }
+
     let start_dir = start_dir
         .map(|p: &Path| p.to_path_buf())
         .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
 
-    let relative_path = relative_path.unwrap_or("");
-    let mut current_dir = start_dir.clone();
+    let ceilings = ceiling_dirs(std::env::var_os(CS01_CEILING_DIRECTORIES_ENV));
+    find_repo_root(vfs, &start_dir, &ceilings, discovery_across_filesystems)
+}
+
+/// Searches upwards from `start_dir` for a repository root without entering `ceilings`.
+/// `may_cross` is asked once, at the first mount point, whether to keep going.
+fn find_repo_root(
+    vfs: &dyn Vfs,
+    start_dir: &Path,
+    ceilings: &[PathBuf],
+    may_cross: impl FnOnce() -> bool,
+) -> Result<Option<DiscoveredRepo>> {
+    // A `.CS01` pointer file ends the search whether or not it is valid.
+    let mut pointer = None;
+
+    let mut metadata_dir = None;
+
+    let Some(root) = find_upwards(start_dir, ceilings, may_cross, |current_dir| {
+        if let Some(potential_cs01) = find_metadata_dir(vfs, current_dir) {
+            if vfs.is_file(&potential_cs01) {
+                pointer = Some(cs01_file_target(vfs, &potential_cs01));
+                return true;
+            }
+            if vfs.is_dir(&potential_cs01) {
+                metadata_dir = Some(potential_cs01);
+                return true;
+            }
+        }
+        looks_like_bare_repo(vfs, current_dir)
+    }) else {
+        return Ok(None);
+    };
+
+    let repo_dir = match (pointer, metadata_dir) {
+        (Some(target), _) => target?,
+        (None, Some(metadata_dir)) => metadata_dir,
+        (None, None) => root.clone(),
+    };
+    Ok(Some(DiscoveredRepo::new(root, repo_dir)))
+}
+
+/// The `.CS01` entry in `dir`, in whatever casing it was created with.
+///
+/// Note: The canonical spelling is tried first, so on a case-insensitive filesystem
+/// this never needs to list the directory.
+pub fn find_metadata_dir(vfs: &dyn Vfs, dir: &Path) -> Option<PathBuf> {
+    let canonical = dir.join(METADATA_DIR);
+    if vfs.symlink_metadata(&canonical).is_ok() {
+        return Some(canonical);
+    }
+    vfs.read_dir(dir)
+        .ok()?
+        .into_iter()
+        .find(|name| is_metadata_dir_name(name))
+        .map(|name| dir.join(name))
+}
+
+/// Whether the working tree directory `dir` is the top of another repository: it holds
+/// a `.CS01` or `.git` (a directory, or a file pointing elsewhere) of its own.
+///
+/// Note: Such a directory is one opaque entry to `add`, `status` and `ls-files`; its
+/// files belong to that repository.
+pub fn is_embedded_repository(dir: &Path) -> bool {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .any(|entry| is_repository_dir_name(&entry.file_name()))
+}
+
+/// Whether `dir` is a bare repository: a `config` starting with `[core]`, a `HEAD`,
+/// and `objects/` or `refs/`.
+///
+/// Note: The config alone isn't enough; plenty of projects ship an unrelated INI file
+/// named `config`, and treating them as repositories hijacks discovery below them.
+fn looks_like_bare_repo(vfs: &dyn Vfs, dir: &Path) -> bool {
+    vfs.is_file(&dir.join("HEAD"))
+        && (vfs.is_dir(&dir.join("objects")) || vfs.is_dir(&dir.join("refs")))
+        && vfs
+            .read_to_string(&dir.join("config"))
+            .is_ok_and(|content| content.trim().starts_with("[core]"))
+}
+
+/// Resolves a `.CS01` pointer file (`cs01dir: <path>`) to the metadata directory it names.
+///
+/// Note: A relative path is relative to the directory holding the file, as with git's `.git` files.
+pub fn read_cs01_file(path: &Path) -> Result<PathBuf> {
+    cs01_file_target(&RealFs, path)
+}
+
+fn cs01_file_target(vfs: &dyn Vfs, path: &Path) -> Result<PathBuf> {
+    let content = vfs
+        .read_to_string(path)
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    let Some(target) = content
+        .trim_end_matches(['\n', '\r'])
+        .strip_prefix("cs01dir: ")
+        .filter(|target| !target.is_empty())
+    else {
+        bail!("invalid cs01 file: {}", path.display());
+    };
+
+    let target = path.parent().unwrap_or(Path::new("")).join(target);
+    if !looks_like_repo_dir_in(vfs, &target) {
+        bail!(
+            "invalid cs01 file: {} points to '{}', which is not a cs01 repository",
+            path.display(),
+            target.display()
+        );
+    }
+    Ok(fs::canonicalize(&target).unwrap_or(target))
+}
+
+/// Whether discovery may continue past a mount point: `CS01_DISCOVERY_ACROSS_FILESYSTEM`,
+/// else `discovery.acrossFileSystems` from the global or system config.
+///
+/// Note: Only consulted when a boundary is actually reached. A config that fails to load
+/// keeps discovery on its own filesystem rather than failing it, so e.g.
+/// `cs01 config --global --edit` can still repair a broken file.
+fn discovery_across_filesystems() -> bool {
+    if let Some(value) = std::env::var_os(CS01_DISCOVERY_ACROSS_FILESYSTEM_ENV) {
+        return value.to_str().and_then(parse_bool).unwrap_or(false);
+    }
+    Config::load_cascaded(None)
+        .and_then(|config| config.get_bool("discovery.acrossFileSystems"))
+        .ok()
+        .flatten()
+        .unwrap_or(false)
+}
+
+/// Parses the value of `CS01_CEILING_DIRECTORIES` (`:`-separated, `;` on Windows).
+///
+/// Note: As in git, relative entries are ignored, and entries are resolved through
+/// symlinks up to the first empty entry; later ones are used as written, which avoids
+/// touching slow network mounts just to read the list.
+fn ceiling_dirs(value: Option<OsString>) -> Vec<PathBuf> {
+    let Some(value) = value else {
+        return Vec::new();
+    };
+
+    let mut canonicalize = true;
+    let mut ceilings = Vec::new();
+    for entry in std::env::split_paths(&value) {
+        if entry.as_os_str().is_empty() {
+            canonicalize = false;
+        } else if !entry.is_absolute() {
+            continue;
+        } else if !canonicalize {
+            ceilings.push(entry);
+        } else if let Ok(real) = fs::canonicalize(&entry) {
+            ceilings.push(real);
+        }
+    }
+    ceilings
+}
+
+/// Locates the metadata directory of the repository containing `start_dir`.
+#[deprecated(note = "use `discover` and `DiscoveredRepo::repo_dir`")]
+pub fn find_repo_dir(start_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+    Ok(discover(start_dir)?.map(|found| found.repo_dir))
+}
+
+/// Returns the metadata directory named by `CS01_DIR`, if set and non-empty.
+pub fn repo_dir_from_env() -> Option<PathBuf> {
+    std::env::var_os(CS01_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Checks whether `dir` looks like a metadata directory (it has a HEAD or a config).
+pub fn looks_like_repo_dir(dir: &Path) -> bool {
+    looks_like_repo_dir_in(&RealFs, dir)
+}
+
+fn looks_like_repo_dir_in(vfs: &dyn Vfs, dir: &Path) -> bool {
+    vfs.is_file(&dir.join("HEAD")) || vfs.is_file(&dir.join("config"))
+}
+
+/// Resolves the repository root from the values of `CS01_DIR` and `CS01_WORK_TREE`.
+///
+/// The root is `CS01_WORK_TREE` when given; otherwise the parent of a directory named
+/// `.CS01`, or the metadata directory itself (a bare repository).
+fn repo_root_from_env(
+    vfs: &dyn Vfs,
+    cs01_dir: Option<OsString>,
+    work_tree: Option<OsString>,
+) -> Result<Option<PathBuf>> {
+    let Some(dir) = cs01_dir.filter(|dir| !dir.is_empty()).map(PathBuf::from) else {
+        return Ok(None);
+    };
+
+    if !looks_like_repo_dir_in(vfs, &dir) {
+        return Err(NotARepository {
+            path: dir,
+            searched: false,
+        }
+        .into());
+    }
+
+    if let Some(work_tree) = work_tree.filter(|wt| !wt.is_empty()) {
+        return Ok(Some(PathBuf::from(work_tree)));
+    }
+
+    match dir.parent() {
+        Some(parent) if dir.file_name().is_some_and(is_metadata_dir_name) => {
+            Ok(Some(parent.to_path_buf()))
+        }
+        _ => Ok(Some(dir)),
+    }
+}
+
+/// Another version control system found around a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignVcs {
+    /// Human-readable name, e.g. "Git".
+    pub name: &'static str,
+    /// The directory containing the VCS metadata (e.g. the parent of `.git`).
+    pub root: PathBuf,
+}
+
+/// Metadata entries of other version control systems, with their display names.
+const FOREIGN_VCS_MARKERS: &[(&str, &str)] = &[(".git", "Git")];
+
+/// Finds another VCS's checkout at `start_dir` or any of its ancestors.
+///
+/// Note: `.git` may be a directory or a file (worktrees and submodules use a
+/// `gitdir:` pointer file), so any entry with that name counts.
+pub fn find_foreign_vcs(start_dir: &Path) -> Option<ForeignVcs> {
+    let mut found = None;
+
+    find_upwards(
+        start_dir,
+        &[],
+        || true,
+        |current_dir| {
+            for (marker, name) in FOREIGN_VCS_MARKERS {
+                if current_dir.join(marker).exists() {
+                    found = Some(*name);
+                    return true;
+                }
+            }
+            false
+        },
+    )
+    .map(|root| ForeignVcs {
+        name: found.unwrap(),
+        root,
+    })
+}
+
+/// Walks from `start_dir` up to the filesystem root, returning the first directory
+/// for which `is_match` returns true.
+///
+/// Note: The walk never moves up into one of `ceilings`, but `start_dir` itself is
+/// always checked, even when it is a ceiling. Moving onto another filesystem
+/// only happens if `may_cross` allows it.
+fn find_upwards(
+    start_dir: &Path,
+    ceilings: &[PathBuf],
+    may_cross: impl FnOnce() -> bool,
+    mut is_match: impl FnMut(&Path) -> bool,
+) -> Option<PathBuf> {
+    let mut current_dir = start_dir.to_path_buf();
+    let mut may_cross = Some(may_cross);
+    let mut filesystem = filesystem_id(&current_dir);
+
+    loop {
+        if is_match(&current_dir) {
+            return Some(current_dir);
+        }
+
+        if !current_dir.pop() || is_ceiling(&current_dir, ceilings) {
+            break;
+        }
+
+        // Once crossing is allowed there is nothing left to compare.
+        if may_cross.is_some() {
+            let parent_filesystem = filesystem_id(&current_dir);
+            if filesystem.is_some()
+                && parent_filesystem.is_some()
+                && parent_filesystem != filesystem
+                && !may_cross.take().is_some_and(|may_cross| may_cross())
+            {
+                break;
+            }
+            filesystem = parent_filesystem;
+        }
+    }
+
+    None
+}
+
+/// Identifies the filesystem `dir` is on: its device on Unix, its volume on Windows.
+#[cfg(unix)]
+fn filesystem_id(dir: &Path) -> Option<OsString> {
+    use std::os::unix::fs::MetadataExt;
+    let device = fs::metadata(dir).ok()?.dev();
+    Some(device.to_string().into())
+}
+
+#[cfg(windows)]
+fn filesystem_id(dir: &Path) -> Option<OsString> {
+    let dir = fs::canonicalize(dir).ok()?;
+    match dir.components().next()? {
+        std::path::Component::Prefix(prefix) => Some(prefix.as_os_str().to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn filesystem_id(_dir: &Path) -> Option<OsString> {
+    None
+}
+
+fn is_ceiling(dir: &Path, ceilings: &[PathBuf]) -> bool {
+    if ceilings.is_empty() {
+        return false;
+    }
+    if ceilings.iter().any(|ceiling| ceiling == dir) {
+        return true;
+    }
+    // The walk may be going through a symlinked path while the ceilings are resolved.
+    fs::canonicalize(dir).is_ok_and(|real| ceilings.contains(&real))
+}
+
+pub struct WriteOptions<'a> {
+    /// Permissions for newly created directories. `None` leaves them to the umask.
+    pub dir_perms: Option<u32>,
+    /// Permissions for newly written files. `None` leaves them to the umask.
+    pub file_perms: Option<u32>,
+    pub overwrite: bool,
+    pub dry_run: bool,
+    /// Write each file to a synced temporary sibling and move it into place, so a crash
+    /// never leaves a truncated file behind. On by default.
+    pub atomic: bool,
+    /// Whether `TreeNode::Symlink` becomes a real link (`core.symlinks`). When false, a
+    /// plain file holding the target path is written instead, as git does.
+    pub symlinks: bool,
+    /// Called after each file or symlink is written or skipped; never during a dry run.
+    pub progress: Option<&'a dyn Fn(Progress)>,
+    /// Whether the first failure stops the write, or every failure is collected.
+    pub on_error: ErrorMode,
+    /// Where the tree is written; the real filesystem by default.
+    pub vfs: &'a dyn Vfs,
+    /// How many threads write files and symlinks. With more than one, every directory is
+    /// created first and the files are shared out between workers; progress reports then
+    /// arrive in completion order. Dry runs always plan on one thread. Defaults to 1.
+    pub parallelism: usize,
+}
+
+/// What `write_files_from_tree` does when writing a node fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorMode {
+    /// Stop at the first failure and return it.
+    #[default]
+    Abort,
+    /// Keep writing the rest of the tree, then fail with a `WriteReport` listing every failure.
+    /// A directory that can't be created counts as one failure; its contents are not attempted.
+    Collect,
+}
+
+/// Every path that failed in an `ErrorMode::Collect` write, with its cause, in tree order.
+#[derive(Debug)]
+pub struct WriteReport {
+    pub failures: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl std::fmt::Display for WriteReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to write {} path(s):", self.failures.len())?;
+        for (path, error) in &self.failures {
+            write!(f, "\n  {}: {:#}", path.display(), error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WriteReport {}
+
+/// How far a tree write has got, reported through `WriteOptions::progress`.
+///
+/// Note: Symlinks count as files, with their target's length as their size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+impl Progress {
+    /// A fresh count for writing `tree`.
+    fn for_tree(tree: &TreeNode) -> Self {
+        let mut progress = Progress::default();
+        progress.add_totals(tree);
+        progress
+    }
+
+    fn add_totals(&mut self, tree: &TreeNode) {
+        match tree {
+            TreeNode::Directory {
+                entries: children, ..
+            } => children.values().for_each(|child| self.add_totals(child)),
+            node => {
+                self.files_total += 1;
+                self.bytes_total += node_size(node);
+            }
+        }
+    }
+}
+
+/// The bytes a file or symlink node puts on disk, for progress reports.
+fn node_size(node: &TreeNode) -> u64 {
+    match node {
+        TreeNode::File { content, .. } => content.len() as u64,
+        TreeNode::Symlink(target) => link_text(target).len() as u64,
+        TreeNode::Directory { .. } => 0,
+    }
+}
+
+impl Default for WriteOptions<'_> {
+    fn default() -> Self {
+        Self {
+            dir_perms: Some(0o755),
+            file_perms: None,
+            overwrite: true,
+            dry_run: false,
+            atomic: true,
+            symlinks: FsCaps::default().symlinks,
+            progress: None,
+            on_error: ErrorMode::Abort,
+            vfs: &RealFs,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Sets the permission bits of `path`.
+///
+/// Note: Only meaningful on Unix. On other platforms this is a no-op, since
+/// their permission model has no equivalent of mode bits.
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions on {:?}", path))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Marks `path` as hidden, like git does for `.git` on Windows.
+///
+/// Note: Only meaningful on Windows. Elsewhere dot-files are hidden by convention,
+/// so this is a no-op.
+#[cfg(windows)]
+pub fn set_hidden(path: &Path) -> Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_HIDDEN, GetFileAttributesW, INVALID_FILE_ATTRIBUTES, SetFileAttributesW,
+    };
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+    // SAFETY: `wide` is a NUL-terminated UTF-16 string that outlives both calls.
+    let ok = unsafe {
+        let attributes = GetFileAttributesW(wide.as_ptr());
+        attributes != INVALID_FILE_ATTRIBUTES
+            && SetFileAttributesW(wide.as_ptr(), attributes | FILE_ATTRIBUTE_HIDDEN) != 0
+    };
+
+    if ok {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error()).with_context(|| format!("Failed to hide {:?}", path))
+    }
+}
+
+#[cfg(not(windows))]
+pub fn set_hidden(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// What the filesystem holding a repository supports, recorded in `core` at init.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsCaps {
+    /// The executable bit survives a round trip (`core.filemode`).
+    pub filemode: bool,
+    /// Symbolic links can be created (`core.symlinks`).
+    pub symlinks: bool,
+    /// File names differing only in case refer to the same file (`core.ignorecase`).
+    pub ignorecase: bool,
+}
+
+impl Default for FsCaps {
+    /// What the platform usually supports, used when the filesystem cannot be probed.
+    fn default() -> Self {
+        Self {
+            filemode: cfg!(unix),
+            symlinks: cfg!(unix),
+            ignorecase: cfg!(any(windows, target_os = "macos")),
+        }
+    }
+}
+
+/// Probes the filesystem at `path` (or its nearest existing ancestor) with a scratch file.
+///
+/// Note: Like git, this tests the actual filesystem rather than trusting the OS, since a
+/// FAT32 stick or a network share mounted on Linux has no mode bits either. Every scratch
+/// entry is removed again; if nothing can be written, the platform defaults are returned.
+pub fn probe_fs_capabilities(path: &Path) -> FsCaps {
+    let Some(dir) = path.ancestors().find(|p| p.is_dir()) else {
+        return FsCaps::default();
+    };
+
+    let name = format!(".cs01-probe-{}", std::process::id());
+    let scratch = dir.join(&name);
+    if fs::write(&scratch, "").is_err() {
+        return FsCaps::default();
+    }
+
+    let link = dir.join(format!("{}-link", name));
+    let caps = FsCaps {
+        filemode: probe_filemode(&scratch),
+        symlinks: probe_symlink(&scratch, &link),
+        ignorecase: dir.join(name.to_uppercase()).exists(),
+    };
+
+    let _ = fs::remove_file(&link);
+    let _ = fs::remove_file(&scratch);
+    caps
+}
+
+#[cfg(unix)]
+fn probe_filemode(scratch: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let has_exec = |p: &Path| {
+        fs::metadata(p)
+            .map(|m| m.permissions().mode() & 0o100 != 0)
+            .unwrap_or(false)
+    };
+
+    set_mode(scratch, 0o755).is_ok()
+        && has_exec(scratch)
+        && set_mode(scratch, 0o644).is_ok()
+        && !has_exec(scratch)
+}
+
+#[cfg(not(unix))]
+fn probe_filemode(_scratch: &Path) -> bool {
+    false
+}
+
+fn probe_symlink(target: &Path, link: &Path) -> bool {
+    #[cfg(unix)]
+    let created = std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    let created = std::os::windows::fs::symlink_file(target, link);
+    #[cfg(not(any(unix, windows)))]
+    let created: std::io::Result<()> = Err(std::io::ErrorKind::Unsupported.into());
+
+    created.is_ok() && link.is_symlink()
+}
+
+/// Creates `path` and any missing parents, applying `mode` (if any) to every directory it
+/// creates.
+///
+/// Note: `fs::create_dir_all` leaves permissions to the umask, so intermediate
+/// directories would otherwise miss `dir_perms`. Existing directories are left untouched.
+/// Every directory actually created is pushed onto `created`, parents first.
+fn create_dirs(
+    vfs: &dyn Vfs,
+    path: &Path,
+    mode: Option<u32>,
+    created: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if path.as_os_str().is_empty() || vfs.is_dir(path) {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        create_dirs(vfs, parent, mode, created)?;
+    }
+
+    match vfs.create_dir(path) {
+        Ok(()) => {
+            created.push(path.to_path_buf());
+            match mode {
+                Some(mode) => vfs.set_mode(path, mode),
+                None => Ok(()),
+            }
+        }
+        // Lost a race with another writer; the directory is there, which is all we need.
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && vfs.is_dir(path) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to create dir {:?}", path)),
+    }
+}
+
+/// Windows device names, which can't be used as file names there even with an extension.
+const RESERVED_DEVICE_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks that `name`, a key in a `TreeNode::Directory` under `parent`, names exactly one
+/// entry inside `parent`.
+///
+/// Critical: Trees can come from templates and other untrusted input. A key like `..`,
+/// `/etc/passwd` or `hooks/../../x` would otherwise make `Path::join` write outside the prefix.
+pub fn check_tree_name(name: &str, parent: &Path) -> Result<()> {
+    let reject = |why: &str| -> Result<()> {
+        bail!(
+            "refusing to write tree entry {:?} in {:?}: {}",
+            name,
+            parent,
+            why
+        )
+    };
+
+    if name.is_empty() {
+        return reject("empty name");
+    }
+    if name == "." || name == ".." {
+        return reject("relative path component");
+    }
+    if name.contains(['/', '\\']) {
+        return reject("contains a path separator");
+    }
+    if name.contains('\0') {
+        return reject("contains a NUL byte");
+    }
+    // Note: Catches what's left of absolute paths, e.g. a `C:` drive prefix on Windows.
+    let mut components = Path::new(name).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(std::path::Component::Normal(_)), None)
+    ) {
+        return reject("not a plain file name");
+    }
+    if cfg!(windows) && is_reserved_device_name(name) {
+        return reject("reserved device name on Windows");
+    }
+    Ok(())
+}
+
+/// Whether `name` is a Windows device name such as `CON` or `lpt1.txt`.
+fn is_reserved_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    RESERVED_DEVICE_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Whether anything, even a dangling symlink, is at `path`.
+///
+/// Note: `Path::exists` follows symlinks, so a link to a missing target would look absent
+/// and a later write would go through it.
+fn path_is_taken(vfs: &dyn Vfs, path: &Path) -> bool {
+    vfs.symlink_metadata(path).is_ok()
+}
+
+/// The bytes of a symlink target, for writing it as a plain file.
+#[cfg(unix)]
+pub(crate) fn link_text(target: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    target.as_os_str().as_bytes().to_vec()
+}
+
+/// The bytes of a symlink target, for writing it as a plain file.
+///
+/// Note: git stores link targets with forward slashes on every platform.
+#[cfg(not(unix))]
+pub(crate) fn link_text(target: &Path) -> Vec<u8> {
+    target.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// The symlink target stored as `text`; the inverse of `link_text`.
+#[cfg(unix)]
+pub(crate) fn link_target(text: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(text))
+}
+
+/// The symlink target stored as `text`; the inverse of `link_text`.
+#[cfg(not(unix))]
+pub(crate) fn link_target(text: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(text).into_owned())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Note: Windows distinguishes links to files from links to directories, so the
+/// kind is chosen by what the target currently is; a missing target gets a file link.
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Creates the symlink `link` -> `target`, replacing whatever file or link is there.
+///
+/// Note: The link is made under a temporary sibling name and renamed into place, so
+/// `link` is never missing in between. An existing directory is never replaced.
+pub(crate) fn replace_with_symlink(target: &Path, link: &Path) -> Result<()> {
+    if fs::symlink_metadata(link).is_ok_and(|metadata| metadata.is_dir()) {
+        bail!("Refusing to replace directory {:?} with a symlink", link);
+    }
+
+    let mut temp_name = link
+        .file_name()
+        .with_context(|| format!("Cannot create symlink at {:?}", link))?
+        .to_os_string();
+    temp_name.push(format!(".tmp-{}", std::process::id()));
+    let temp = link.with_file_name(temp_name);
+
+    let _ = fs::remove_file(&temp);
+    create_symlink(target, &temp)
+        .with_context(|| format!("Failed to create symlink {:?} -> {:?}", link, target))?;
+    fs::rename(&temp, link).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        anyhow::Error::new(e).context(format!("Failed to move symlink into place at {:?}", link))
+    })
+}
+
+/// A sibling of `path` for temporary content, e.g. `.HEAD.tmp.1234-0` next to `HEAD`.
+///
+/// Note: The pid and a per-process counter make the name unique among concurrent writers.
+fn temp_sibling(path: &Path) -> Result<PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let name = path
+        .file_name()
+        .with_context(|| format!("Cannot write {:?}", path))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(
+        ".tmp.{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    Ok(path.with_file_name(temp_name))
+}
+
+/// How long a temporary file may sit untouched before it counts as abandoned.
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Whether `name` is one of the temporary names writes use: `.HEAD.tmp.1234-0` from
+/// `temp_sibling`, or `link.tmp-1234` from the symlink and directory swaps.
+fn is_temp_name(name: &str) -> bool {
+    let Some(at) = name.rfind(".tmp.").or_else(|| name.rfind(".tmp-")) else {
+        return false;
+    };
+    let suffix = &name[at + ".tmp.".len()..];
+    !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit() || b == b'-')
+}
+
+/// Removes the temporary files under `dir` that interrupted writes left behind, once
+/// they are older than `max_age`; returns the paths removed.
+///
+/// Note: A write in progress keeps its file young, so only files no process has
+/// touched for `max_age` are taken; symlinks are never followed.
+pub fn remove_stale_temp_files(dir: &Path, max_age: Duration) -> Result<Vec<PathBuf>> {
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {:?}", dir))?;
+            let path = entry.path();
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| format!("Failed to read {:?}", path))?;
+            if metadata.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let stale = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > max_age);
+            if stale && entry.file_name().to_str().is_some_and(is_temp_name) {
+                fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+                removed.push(path);
+            }
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+/// Creates `path` holding `content` unless something is already there; returns whether it did.
+///
+/// Note: The content is written to a temporary sibling and synced first, then hard-linked
+/// into place. Linking never replaces an existing file, so this keeps the no-overwrite
+/// guarantee even against a concurrent writer, and a crash leaves either no file or the
+/// complete one. Filesystems without hard links fall back to a rename after an existence check.
+pub(crate) fn write_new_file_atomic(path: &Path, content: &[u8]) -> Result<bool> {
+    let temp = temp_sibling(path)?;
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .with_context(|| format!("Failed to create {:?}", temp))?;
+        file.write_all(content)
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Failed to write {:?}", temp))?;
+        drop(file);
+
+        match fs::hard_link(&temp, path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(_) if fs::symlink_metadata(path).is_ok() => Ok(false),
+            Err(_) => fs::rename(&temp, path)
+                .map(|()| true)
+                .with_context(|| format!("Failed to move {:?} into place at {:?}", temp, path)),
+        }
+    })();
+
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+/// One step of writing a tree: performed by `write_files_from_tree`, or only planned
+/// when `WriteOptions::dry_run` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedOp {
+    /// A directory that doesn't exist yet, with any missing parents.
+    CreateDir(PathBuf),
+    /// A file written (or replaced) with `bytes` bytes of content.
+    WriteFile { path: PathBuf, bytes: usize },
+    /// A symlink created (or replacing what was there).
+    CreateSymlink { path: PathBuf, target: PathBuf },
+    /// A file removed outright, e.g. an unborn branch's ref when HEAD is retargeted.
+    RemoveFile(PathBuf),
+    /// A node left alone, and why.
+    Skip { path: PathBuf, reason: &'static str },
+}
+
+impl PlannedOp {
+    /// The path this step acts on.
+    pub fn path(&self) -> &Path {
+        match self {
+            PlannedOp::CreateDir(path) | PlannedOp::RemoveFile(path) => path,
+            PlannedOp::WriteFile { path, .. }
+            | PlannedOp::CreateSymlink { path, .. }
+            | PlannedOp::Skip { path, .. } => path,
+        }
+    }
+}
+
+impl std::fmt::Display for PlannedOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlannedOp::CreateDir(path) => write!(f, "create dir {}", path.display()),
+            PlannedOp::WriteFile { path, bytes } => {
+                write!(f, "write {} ({} bytes)", path.display(), bytes)
+            }
+            PlannedOp::CreateSymlink { path, target } => {
+                write!(f, "symlink {} -> {}", path.display(), target.display())
+            }
+            PlannedOp::RemoveFile(path) => write!(f, "remove {}", path.display()),
+            PlannedOp::Skip { path, reason } => {
+                write!(f, "skip {} ({})", path.display(), reason)
+            }
+        }
+    }
+}
+
+/// Writes a `TreeNode` structure to disk, returning what it did in tree order.
+///
+/// Note: Recursively handles directory creation.
+/// If `options.overwrite` is false, it preserves existing files and symlinks.
+/// With `options.dry_run`, nothing is touched and the result is the plan.
+/// With `ErrorMode::Collect`, a failure is a `WriteReport` naming every failed path.
+pub fn write_files_from_tree(
+    tree: &TreeNode,
+    prefix: &Path,
+    options: &WriteOptions,
+) -> Result<Vec<PlannedOp>> {
+    let mut record = Record::new(tree);
+    write_tree(tree, prefix, options, &mut record)?;
+    record.finish()?;
+    Ok(record.ops)
+}
+
+/// Like `write_files_from_tree`, but all-or-nothing: if any write fails, every
+/// file and directory created by this call is removed again before the error is returned.
+///
+/// Note: Pre-existing paths are never touched by the rollback, which makes this
+/// safe for re-initializing a repository in place.
+pub fn write_files_transaction(
+    tree: &TreeNode,
+    prefix: &Path,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut record = Record::new(tree);
+    let result = write_tree(tree, prefix, options, &mut record).and_then(|()| record.finish());
+
+    if result.is_err() {
+        // Children were recorded after their parents, so undo in reverse.
+        // Cleanup is best effort; the original error is what the caller needs to see.
+        let vfs = options.vfs;
+        for path in record.created.iter().rev() {
+            if vfs
+                .symlink_metadata(path)
+                .is_ok_and(|metadata| metadata.is_dir())
+            {
+                let _ = vfs.remove_dir(path);
+            } else {
+                let _ = vfs.remove_file(path);
+            }
+        }
+    }
+
+    result
+}
+
+/// Writes `tree` into a fresh directory at `target` by staging it in a sibling
+/// temporary directory (`<target>.tmp-<pid>`) and renaming it into place.
+///
+/// Note: The rename is atomic on the same filesystem, so `target` either appears
+/// complete or not at all. On failure the staging directory is removed.
+pub fn write_tree_staged(tree: &TreeNode, target: &Path, options: &WriteOptions) -> Result<()> {
+    let vfs = options.vfs;
+    if vfs.exists(target) {
+        bail!("Refusing to stage over existing path {:?}", target);
+    }
+
+    let mut staging_name = target
+        .file_name()
+        .with_context(|| format!("Cannot stage into {:?}", target))?
+        .to_os_string();
+    staging_name.push(format!(".tmp-{}", std::process::id()));
+    let staging = target.with_file_name(staging_name);
+
+    let result = write_files_from_tree(tree, &staging, options).and_then(|_| {
+        vfs.rename(&staging, target)
+            .with_context(|| format!("Failed to move {:?} into place at {:?}", staging, target))
+    });
+
+    if result.is_err() && vfs.exists(&staging) {
+        let _ = vfs.remove_dir_all(&staging);
+    }
+
+    result
+}
+
+/// What a tree write has done so far.
+struct Record<'t> {
+    /// Every path created, parents first, for rolling back a failed transaction.
+    created: Vec<PathBuf>,
+    /// The steps taken (or planned, in a dry run).
+    ops: Vec<PlannedOp>,
+    progress: Progress,
+    /// Failures set aside in `ErrorMode::Collect`.
+    failures: Vec<(PathBuf, anyhow::Error)>,
+    /// Files and symlinks set aside for the worker threads, when writing in parallel.
+    deferred: Option<Vec<(PathBuf, &'t TreeNode)>>,
+    /// Directories created with their own `mode`, parents first.
+    dir_modes: Vec<(PathBuf, u32)>,
+}
+
+impl<'t> Record<'t> {
+    fn new(tree: &TreeNode) -> Self {
+        Record {
+            created: Vec::new(),
+            ops: Vec::new(),
+            progress: Progress::for_tree(tree),
+            failures: Vec::new(),
+            deferred: None,
+            dir_modes: Vec::new(),
+        }
+    }
+
+    /// Turns collected failures into the write's error.
+    fn finish(&mut self) -> Result<()> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        let failures = std::mem::take(&mut self.failures);
+        Err(WriteReport { failures }.into())
+    }
+
+    /// Gives each directory in `dir_modes` its own mode, children before parents.
+    ///
+    /// Note: Only once everything is written, so a mode without write permission
+    /// (e.g. `0o555`) doesn't lock out the directory's own entries.
+    fn apply_dir_modes(&mut self, options: &WriteOptions) -> Result<()> {
+        for (path, mode) in self.dir_modes.drain(..).rev() {
+            options.vfs.set_mode(&path, mode)?;
+        }
+        Ok(())
+    }
+
+    /// Counts one file or symlink as done and reports it.
+    fn file_done(&mut self, node: &TreeNode, options: &WriteOptions) {
+        self.progress.files_done += 1;
+        self.progress.bytes_done += node_size(node);
+        if let Some(progress) = options.progress
+            && !options.dry_run
+        {
+            progress(self.progress);
+        }
+    }
+}
+
+/// Writes `tree` on one thread, or on `options.parallelism` threads once its directories exist.
+fn write_tree<'t>(
+    tree: &'t TreeNode,
+    prefix: &Path,
+    options: &WriteOptions,
+    record: &mut Record<'t>,
+) -> Result<()> {
+    if options.parallelism <= 1 || options.dry_run {
+        write_tree_recorded(tree, prefix, options, record)?;
+    } else {
+        // Note: Every directory is created up front, so workers never race to create a shared parent.
+        record.deferred = Some(Vec::new());
+        let result = write_tree_recorded(tree, prefix, options, record);
+        let jobs = record.deferred.take().unwrap_or_default();
+        result?;
+        write_leaves_parallel(&jobs, options, record)?;
+    }
+    record.apply_dir_modes(options)
+}
+
+/// Writes the files and symlinks in `jobs` across `options.parallelism` scoped threads.
+///
+/// Note: `options.progress` needn't be thread-safe, so workers send their results back
+/// and this thread does all the recording and reporting.
+fn write_leaves_parallel(
+    jobs: &[(PathBuf, &TreeNode)],
+    options: &WriteOptions,
+    record: &mut Record,
+) -> Result<()> {
+    let WriteOptions {
+        dir_perms,
+        file_perms,
+        overwrite,
+        atomic,
+        symlinks,
+        on_error,
+        vfs,
+        ..
+    } = *options;
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    let mut outcomes: Vec<(usize, Result<PlannedOp>)> = Vec::with_capacity(jobs.len());
+
+    std::thread::scope(|scope| {
+        for _ in 0..options.parallelism.min(jobs.len()) {
+            let sender = sender.clone();
+            let (next, stop) = (&next, &stop);
+            scope.spawn(move || {
+                let options = WriteOptions {
+                    dir_perms,
+                    file_perms,
+                    overwrite,
+                    dry_run: false,
+                    atomic,
+                    symlinks,
+                    progress: None,
+                    on_error,
+                    vfs,
+                    parallelism: 1,
+                };
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((path, node)) = jobs.get(index) else {
+                        break;
+                    };
+                    let mut created = Vec::new();
+                    let result = write_leaf(node, path, &options, &mut created);
+                    if sender.send((index, created, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (index, created, result) in receiver {
+            record.created.extend(created);
+            match &result {
+                Ok(_) => record.file_done(jobs[index].1, options),
+                Err(_) if on_error == ErrorMode::Abort => stop.store(true, Ordering::Relaxed),
+                Err(_) => {}
+            }
+            outcomes.push((index, result));
+        }
+    });
+
+    // Note: Put back in tree order, so the result doesn't depend on which worker finished first.
+    outcomes.sort_by_key(|(index, _)| *index);
+    for (index, result) in outcomes {
+        match result {
+            Ok(op) => record.ops.push(op),
+            Err(e) if on_error == ErrorMode::Collect => {
+                record.failures.push((jobs[index].0.clone(), e))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    record.ops.sort_by(|a, b| a.path().cmp(b.path()));
+    record.failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(())
+}
+
+fn write_tree_recorded<'t>(
+    tree: &'t TreeNode,
+    prefix: &Path,
+    options: &WriteOptions,
+    record: &mut Record<'t>,
+) -> Result<()> {
+    match write_node(tree, prefix, options, record) {
+        Err(e) if options.on_error == ErrorMode::Collect => {
+            record.failures.push((prefix.to_path_buf(), e));
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn write_node<'t>(
+    tree: &'t TreeNode,
+    prefix: &Path,
+    options: &WriteOptions,
+    record: &mut Record<'t>,
+) -> Result<()> {
+    let TreeNode::Directory {
+        entries: children,
+        mode,
+    } = tree
+    else {
+        if let Some(deferred) = &mut record.deferred {
+            deferred.push((prefix.to_path_buf(), tree));
+            return Ok(());
+        }
+        let op = write_leaf(tree, prefix, options, &mut record.created)?;
+        record.ops.push(op);
+        record.file_done(tree, options);
+        return Ok(());
+    };
+
+    let vfs = options.vfs;
+    if !vfs.exists(prefix) {
+        if !options.dry_run {
+            create_dirs(vfs, prefix, options.dir_perms, &mut record.created)?;
+            if let Some(mode) = mode {
+                record.dir_modes.push((prefix.to_path_buf(), *mode));
+            }
+        }
+        record.ops.push(PlannedOp::CreateDir(prefix.to_path_buf()));
+    }
+
+    // Note: Sorted, so plans list the same operations in the same order every time.
+    let mut names: Vec<_> = children.keys().collect();
+    names.sort();
+    for name in names {
+        match check_tree_name(name, prefix) {
+            Ok(()) => write_tree_recorded(&children[name], &prefix.join(name), options, record)?,
+            Err(e) if options.on_error == ErrorMode::Collect => {
+                record.failures.push((prefix.to_path_buf(), e));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Writes (or plans) the file or symlink `tree` at `prefix`, adding every path it creates
+/// to `created`.
+fn write_leaf(
+    tree: &TreeNode,
+    prefix: &Path,
+    options: &WriteOptions,
+    created: &mut Vec<PathBuf>,
+) -> Result<PlannedOp> {
+    let vfs = options.vfs;
+    let skip = || PlannedOp::Skip {
+        path: prefix.to_path_buf(),
+        reason: "already exists",
+    };
+
+    match tree {
+        TreeNode::File { content, mode } => {
+            if !options.overwrite && path_is_taken(vfs, prefix) {
+                return Ok(skip());
+            }
+            if !options.dry_run {
+                if let Some(parent) = prefix.parent() {
+                    create_dirs(vfs, parent, options.dir_perms, created)?;
+                }
+                let existed = path_is_taken(vfs, prefix);
+                if options.overwrite && options.atomic {
+                    // Note: Readers never see a half-written file (see `Vfs::replace`).
+                    vfs.replace(prefix, content)?;
+                } else if options.atomic {
+                    if !vfs.write_new(prefix, content)? {
+                        // Another writer got there first; like any existing file, it is kept.
+                        return Ok(skip());
+                    }
+                } else {
+                    vfs.write(prefix, content)
+                        .with_context(|| format!("Failed to write {:?}", prefix))?;
+                }
+                if !existed {
+                    created.push(prefix.to_path_buf());
+                }
+                if let Some(mode) = mode.or(options.file_perms) {
+                    vfs.set_mode(prefix, mode)?;
+                }
+            }
+            Ok(PlannedOp::WriteFile {
+                path: prefix.to_path_buf(),
+                bytes: content.len(),
+            })
+        }
+        TreeNode::Symlink(target) if !options.symlinks => {
+            write_leaf(&TreeNode::file(link_text(target)), prefix, options, created)
+        }
+        TreeNode::Symlink(target) => {
+            let existed = path_is_taken(vfs, prefix);
+            if existed && !options.overwrite {
+                return Ok(skip());
+            }
+            if !options.dry_run {
+                if let Some(parent) = prefix.parent() {
+                    create_dirs(vfs, parent, options.dir_perms, created)?;
+                }
+                vfs.replace_symlink(target, prefix)?;
+                if !existed {
+                    created.push(prefix.to_path_buf());
+                }
+            }
+            Ok(PlannedOp::CreateSymlink {
+                path: prefix.to_path_buf(),
+                target: target.clone(),
+            })
+        }
+        TreeNode::Directory { .. } => unreachable!("directories are written by write_node"),
+    }
+}
+
+/// Decides whether `read_tree_from_dir` leaves out a path; see `ReadOptions::exclude`.
+pub type ExcludeFn<'a> = dyn Fn(&Path, bool) -> bool + 'a;
+
+/// Options for `read_tree_from_dir`.
+#[derive(Default)]
+pub struct ReadOptions<'a> {
+    /// Read the contents of `.CS01` directories too. Off by default, so snapshotting a
+    /// working tree never picks up the repository's own metadata.
+    pub include_metadata_dir: bool,
+    /// Leave out entries that can't be read (printing a warning) instead of failing.
+    pub skip_unreadable: bool,
+    /// Called with each entry's path relative to the root and whether it's a directory;
+    /// returning true leaves it out.
+    pub exclude: Option<&'a ExcludeFn<'a>>,
+}
+
+/// Builds a `TreeNode` from what is on disk at `path`, the reverse of `write_files_from_tree`.
+///
+/// Note: Symlinks are recorded, never followed. On Unix every file records its permission
+/// bits as `mode`; elsewhere `mode` is `None`. Directories never record one. File names
+/// must be valid UTF-8.
+pub fn read_tree_from_dir(path: &Path, options: &ReadOptions) -> Result<TreeNode> {
+    match read_node(path, Path::new(""), options)? {
+        Some(tree) => Ok(tree),
+        None => bail!("Failed to read {:?}", path),
+    }
+}
+
+/// Reads the entry at `path` (`relative` to the root); `None` when it was skipped.
+fn read_node(path: &Path, relative: &Path, options: &ReadOptions) -> Result<Option<TreeNode>> {
+    let skip_or_fail = |error: anyhow::Error| {
+        if options.skip_unreadable {
+            Output::default().warn(&format!("warning: skipping {:#}", error));
+            Ok(None)
+        } else {
+            Err(error)
+        }
+    };
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return skip_or_fail(anyhow::Error::new(e).context(format!("{:?}", path))),
+    };
+
+    if metadata.is_symlink() {
+        return match fs::read_link(path) {
+            Ok(target) => Ok(Some(TreeNode::Symlink(target))),
+            Err(e) => skip_or_fail(anyhow::Error::new(e).context(format!("{:?}", path))),
+        };
+    }
+
+    if metadata.is_file() {
+        return match fs::read(path) {
+            Ok(content) => Ok(Some(TreeNode::File {
+                content,
+                mode: file_mode(&metadata),
+            })),
+            Err(e) => skip_or_fail(anyhow::Error::new(e).context(format!("{:?}", path))),
+        };
+    }
+
+    if !metadata.is_dir() {
+        return skip_or_fail(anyhow::anyhow!("{:?}: unsupported file type", path));
+    }
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => return skip_or_fail(anyhow::Error::new(e).context(format!("{:?}", path))),
+    };
+
+    let mut children = HashMap::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read dir {:?}", path))?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            anyhow::anyhow!("{:?}: file name is not valid UTF-8", path.join(name))
+        })?;
+        let child = entry.path();
+        let child_relative = relative.join(&name);
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+
+        if options
+            .exclude
+            .is_some_and(|exclude| exclude(&child_relative, is_dir))
+        {
+            continue;
+        }
+        if !options.include_metadata_dir && is_metadata_dir_name(name.as_ref()) && child.is_dir() {
+            continue;
+        }
+
+        if let Some(node) = read_node(&child, &child_relative, options)? {
+            children.insert(name, node);
+        }
+    }
+
+    Ok(Some(TreeNode::dir(children)))
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::vfs::{FileKind, MemFs};
+    use std::cell::Cell;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_files_from_tree() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work");
+        vfs.create_dir(root).unwrap();
+
+        let mut children = HashMap::new();
+        children.insert("file.txt".to_string(), TreeNode::text("hello"));
+        let tree = TreeNode::dir(children);
+
+        let opts = WriteOptions {
+            overwrite: true,
+            vfs: &vfs,
+            ..Default::default()
+        };
+
+        write_files_from_tree(&tree, root, &opts).unwrap();
+
+        let file_path = root.join("file.txt");
+        assert!(vfs.is_file(&file_path));
+        assert_eq!(vfs.read_to_string(&file_path).unwrap(), "hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_files_from_tree_symlinks() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("existing"), "keep\n").unwrap();
+        std::os::unix::fs::symlink("nowhere", root.join("dangling")).unwrap();
+
+        let tree = |target: &str| {
+            let mut children = HashMap::new();
+            for name in ["link", "existing", "dangling"] {
+                children.insert(name.to_string(), TreeNode::Symlink(PathBuf::from(target)));
+            }
+            TreeNode::dir(children)
+        };
+
+        // Without overwrite, nothing that is already there is replaced or written through
+        let opts = WriteOptions {
+            overwrite: false,
+            ..Default::default()
+        };
+        write_files_from_tree(&tree("target.txt"), root, &opts).unwrap();
+        assert_eq!(
+            fs::read_link(root.join("link")).unwrap(),
+            Path::new("target.txt")
+        );
+        assert_eq!(fs::read_to_string(root.join("existing")).unwrap(), "keep\n");
+        assert_eq!(
+            fs::read_link(root.join("dangling")).unwrap(),
+            Path::new("nowhere")
+        );
+        assert!(!root.join("nowhere").exists());
+
+        // A file node never writes through a dangling link either
+        let mut children = HashMap::new();
+        children.insert("dangling".to_string(), TreeNode::text("data"));
+        write_files_from_tree(&TreeNode::dir(children), root, &opts).unwrap();
+        assert!(!root.join("nowhere").exists());
+
+        // With overwrite, files and links are replaced by the new link
+        write_files_from_tree(&tree("other.txt"), root, &WriteOptions::default()).unwrap();
+        for name in ["link", "existing", "dangling"] {
+            assert_eq!(
+                fs::read_link(root.join(name)).unwrap(),
+                Path::new("other.txt")
+            );
+        }
+
+        // Dry runs create nothing
+        let opts = WriteOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        write_files_from_tree(&TreeNode::Symlink("x".into()), &root.join("dry"), &opts).unwrap();
+        assert!(!path_is_taken(&RealFs, &root.join("dry")));
+
+        // Without symlink support the target is written as a plain file
+        let opts = WriteOptions {
+            symlinks: false,
+            ..Default::default()
+        };
+        write_files_from_tree(&TreeNode::Symlink("a/b".into()), &root.join("plain"), &opts)
+            .unwrap();
+        assert!(
+            !fs::symlink_metadata(root.join("plain"))
+                .unwrap()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(root.join("plain")).unwrap(), "a/b");
+
+        // A directory is never replaced
+        fs::create_dir(root.join("subdir")).unwrap();
+        assert!(
+            write_files_from_tree(
+                &TreeNode::Symlink("x".into()),
+                &root.join("subdir"),
+                &WriteOptions::default()
+            )
+            .is_err()
+        );
+        assert!(root.join("subdir").is_dir());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_tree_from_dir_round_trip() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("work");
+
+        let mut nested = HashMap::new();
+        nested.insert(
+            "data.bin".to_string(),
+            TreeNode::file_with_mode(vec![0u8, 0xff, 0xfe, b'\n'], 0o600),
+        );
+        nested.insert("empty".to_string(), TreeNode::dir(HashMap::new()));
+        let mut children = HashMap::new();
+        children.insert(
+            "README".to_string(),
+            TreeNode::file_with_mode("hello\n", 0o644),
+        );
+        children.insert(
+            "run.sh".to_string(),
+            TreeNode::file_with_mode("#!/bin/sh\n", 0o755),
+        );
+        children.insert(
+            "link".to_string(),
+            TreeNode::Symlink(PathBuf::from("src/data.bin")),
+        );
+        children.insert(
+            "dangling".to_string(),
+            TreeNode::Symlink(PathBuf::from("missing")),
+        );
+        children.insert("src".to_string(), TreeNode::dir(nested));
+        let tree = TreeNode::dir(children);
+
+        write_files_from_tree(&tree, &root, &WriteOptions::default()).unwrap();
+        let read = read_tree_from_dir(&root, &ReadOptions::default()).unwrap();
+        assert_eq!(read, tree);
+
+        // Writing what was read reproduces the same tree elsewhere
+        let copy = dir.path().join("copy");
+        write_files_from_tree(&read, &copy, &WriteOptions::default()).unwrap();
+        assert_eq!(
+            read_tree_from_dir(&copy, &ReadOptions::default()).unwrap(),
+            tree
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_tree_from_dir_skips_metadata_and_excluded() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".CS01/refs")).unwrap();
+        fs::write(root.join(".CS01/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join("target/debug/app"), "").unwrap();
+        fs::write(root.join("kept.txt"), "kept").unwrap();
+
+        let exclude = |path: &Path, is_dir: bool| is_dir && path == Path::new("target/debug");
+        let options = ReadOptions {
+            exclude: Some(&exclude),
+            ..Default::default()
+        };
+        let TreeNode::Directory {
+            entries: children, ..
+        } = read_tree_from_dir(root, &options).unwrap()
+        else {
+            panic!("root must be a directory");
+        };
+        let mut names: Vec<_> = children.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["kept.txt", "target"]);
+        assert_eq!(children["target"], TreeNode::dir(HashMap::new()));
+
+        let options = ReadOptions {
+            include_metadata_dir: true,
+            ..Default::default()
+        };
+        let TreeNode::Directory {
+            entries: children, ..
+        } = read_tree_from_dir(root, &options).unwrap()
+        else {
+            panic!("root must be a directory");
+        };
+        assert!(children.contains_key(".CS01"));
+
+        // Unreadable entries fail the read unless asked to skip them.
+        // Note: root can read anything, so there is nothing to check when running as root.
+        let secret = root.join("secret");
+        fs::write(&secret, "x").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read(&secret).is_err() {
+            assert!(read_tree_from_dir(root, &ReadOptions::default()).is_err());
+            let options = ReadOptions {
+                skip_unreadable: true,
+                ..Default::default()
+            };
+            let TreeNode::Directory {
+                entries: children, ..
+            } = read_tree_from_dir(root, &options).unwrap()
+            else {
+                panic!("root must be a directory");
+            };
+            assert!(!children.contains_key("secret"));
+            assert!(children.contains_key("kept.txt"));
+        }
+    }
+
+    /// Names in `dir` that look like leftover temporary or lock files.
+    fn leftovers(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.contains(".tmp.") || name.ends_with(".lock"))
+            .collect()
+    }
+
+    #[test]
+    fn test_remove_stale_temp_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("objects/ab")).unwrap();
+        let day_old = SystemTime::now() - STALE_TEMP_AGE - Duration::from_secs(60);
+        let age = |path: &str, modified: SystemTime| {
+            fs::write(root.join(path), "x").unwrap();
+            fs::File::options()
+                .write(true)
+                .open(root.join(path))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        age(".HEAD.tmp.1234-0", day_old);
+        age("objects/ab/.cdef.tmp.99-12", day_old);
+        age("link.tmp-1234", day_old);
+        // Still being written, or not a temporary file at all
+        age(".config.tmp.1234-1", SystemTime::now());
+        age("notes.tmp.txt", day_old);
+        age("HEAD.lock", day_old);
+        age("objects/ab/cdef", day_old);
+
+        let removed = remove_stale_temp_files(root, STALE_TEMP_AGE).unwrap();
+        assert_eq!(
+            removed,
+            [
+                root.join(".HEAD.tmp.1234-0"),
+                root.join("link.tmp-1234"),
+                root.join("objects/ab/.cdef.tmp.99-12"),
+            ]
+        );
+        for kept in [
+            ".config.tmp.1234-1",
+            "notes.tmp.txt",
+            "HEAD.lock",
+            "objects/ab/cdef",
+        ] {
+            assert!(root.join(kept).exists(), "{}", kept);
+        }
+        assert!(
+            remove_stale_temp_files(&root.join("missing"), STALE_TEMP_AGE)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_write_files_from_tree_atomic() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("config"), "old\n").unwrap();
 
-    loop {
-        let potential_config = current_dir.join("config");
-        let potential_cs01 = current_dir.join(".CS01");
+        let mut children = HashMap::new();
+        children.insert("HEAD".to_string(), TreeNode::text("ref: refs/heads/main\n"));
+        children.insert("config".to_string(), TreeNode::text("new\n"));
+        let tree = TreeNode::dir(children);
 
-        if potential_config.is_file()
-            && let Ok(content) = fs::read_to_string(&potential_config)
-            && content.trim().starts_with("[core]")
-        {
-            return Some(current_dir.join(relative_path));
+        for (overwrite, atomic) in [(false, true), (true, true), (false, false)] {
+            let opts = WriteOptions {
+                overwrite,
+                atomic,
+                ..Default::default()
+            };
+            write_files_from_tree(&tree, root, &opts).unwrap();
+            assert_eq!(
+                fs::read_to_string(root.join("HEAD")).unwrap(),
+                "ref: refs/heads/main\n"
+            );
+            let expected = if overwrite { "new\n" } else { "old\n" };
+            assert_eq!(fs::read_to_string(root.join("config")).unwrap(), expected);
+            assert!(leftovers(root).is_empty(), "{:?}", leftovers(root));
+            fs::remove_file(root.join("HEAD")).unwrap();
+            fs::write(root.join("config"), "old\n").unwrap();
         }
 
-        if potential_cs01.exists() && potential_cs01.is_dir() {
-            return Some(current_dir.join(relative_path));
+        // A file that appears after the existence check is still never replaced
+        fs::write(root.join("raced"), "first\n").unwrap();
+        assert!(!write_new_file_atomic(&root.join("raced"), b"second\n").unwrap());
+        assert_eq!(fs::read_to_string(root.join("raced")).unwrap(), "first\n");
+        assert!(write_new_file_atomic(&root.join("fresh"), b"data").unwrap());
+        assert_eq!(fs::read(root.join("fresh")).unwrap(), b"data");
+        assert!(leftovers(root).is_empty(), "{:?}", leftovers(root));
+
+        // A failed write leaves no temporary or lock file behind
+        fs::create_dir(root.join("busy")).unwrap();
+        fs::write(root.join("busy/file"), "x").unwrap();
+        let mut children = HashMap::new();
+        children.insert("busy".to_string(), TreeNode::text("replacement"));
+        assert!(
+            write_files_from_tree(&TreeNode::dir(children), root, &WriteOptions::default())
+                .is_err()
+        );
+        assert!(root.join("busy/file").is_file());
+        assert!(leftovers(root).is_empty(), "{:?}", leftovers(root));
+    }
+
+    #[test]
+    fn test_write_files_from_tree_reports_progress() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work");
+        vfs.create_dir(root).unwrap();
+
+        // 100 files of 1..=100 bytes, spread over ten directories
+        let mut children = HashMap::new();
+        for d in 0..10 {
+            let files = (0..10)
+                .map(|f| {
+                    let size = d * 10 + f + 1;
+                    (format!("file{}", f), TreeNode::file(vec![b'x'; size]))
+                })
+                .collect();
+            children.insert(format!("dir{}", d), TreeNode::dir(files));
         }
+        let tree = TreeNode::dir(children);
+        vfs.create_dir(&root.join("dir3")).unwrap();
+        vfs.write(&root.join("dir3/file0"), b"kept").unwrap();
 
-        if !current_dir.pop() {
-            break;
+        let reports = std::cell::RefCell::new(Vec::new());
+        let record = |progress: Progress| reports.borrow_mut().push(progress);
+
+        // A dry run reports nothing
+        let opts = WriteOptions {
+            dry_run: true,
+            progress: Some(&record),
+            vfs: &vfs,
+            ..Default::default()
+        };
+        write_files_from_tree(&tree, root, &opts).unwrap();
+        assert!(reports.borrow().is_empty());
+
+        // Every file counts once, including the one that is skipped
+        let opts = WriteOptions {
+            overwrite: false,
+            progress: Some(&record),
+            vfs: &vfs,
+            ..Default::default()
+        };
+        write_files_from_tree(&tree, root, &opts).unwrap();
+        let reports = reports.into_inner();
+        assert_eq!(reports.len(), 100);
+        for (index, progress) in reports.iter().enumerate() {
+            assert_eq!(progress.files_done, index + 1);
+            assert_eq!(progress.files_total, 100);
+            assert_eq!(progress.bytes_total, 5050);
         }
+        assert_eq!(reports.last().unwrap().bytes_done, 5050);
+        assert!(
+            reports
+                .windows(2)
+                .all(|w| w[0].bytes_done < w[1].bytes_done)
+        );
     }
 
-    None
-}
+    #[test]
+    fn test_write_files_from_tree_rejects_unsafe_names() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work/repo");
+        vfs.create_dir_all(root).unwrap();
 
-pub struct WriteOptions {
-    pub dir_perms: u32,
-    pub overwrite: bool,
-    pub dry_run: bool,
-}
+        let escapes = [
+            "",
+            ".",
+            "..",
+            "../escaped",
+            "sub/../../escaped",
+            "/tmp/escaped",
+            "..\\escaped",
+            "bad\0name",
+        ];
+        for name in escapes {
+            // Nested, so the error has to name the parent too
+            let mut hooks = HashMap::new();
+            hooks.insert(name.to_string(), TreeNode::text("pwned"));
+            let mut children = HashMap::new();
+            children.insert("hooks".to_string(), TreeNode::dir(hooks));
+            let tree = TreeNode::dir(children);
 
-impl Default for WriteOptions {
-    fn default() -> Self {
-        Self {
-            dir_perms: 0o755,
-            overwrite: true,
-            dry_run: false,
+            for dry_run in [true, false] {
+                let opts = WriteOptions {
+                    dry_run,
+                    vfs: &vfs,
+                    ..Default::default()
+                };
+                let err = write_files_from_tree(&tree, root, &opts)
+                    .unwrap_err()
+                    .to_string();
+                assert!(err.contains(&format!("{:?}", name)), "{}", err);
+                assert!(
+                    err.contains(&format!("{:?}", root.join("hooks"))),
+                    "{}",
+                    err
+                );
+            }
         }
+
+        // Nothing landed next to the prefix
+        assert_eq!(vfs.read_dir(Path::new("/")).unwrap(), ["work"]);
+        assert_eq!(vfs.read_dir(Path::new("/work")).unwrap(), ["repo"]);
+        assert_eq!(vfs.read_dir(root).unwrap(), ["hooks"]);
+        assert!(vfs.read_dir(&root.join("hooks")).unwrap().is_empty());
+
+        // Dots inside a name are fine
+        assert!(check_tree_name("..hidden", root).is_ok());
+        assert!(check_tree_name("packed-refs.lock", root).is_ok());
     }
-}
 
-/// Writes a `TreeNode` structure to disk.
-///
-/// Note: Recursively handles directory creation.
-/// If `options.overwrite` is false, it preserves existing files.
-pub fn write_files_from_tree(tree: &TreeNode, prefix: &Path, options: &WriteOptions) -> Result<()> {
-    if options.dry_run {
-        println!("[DRY-RUN] Processing at {:?}", prefix);
+    #[test]
+    fn test_reserved_device_names() {
+        for name in ["CON", "con", "Nul.txt", "lpt1", "COM9.tar.gz", "aux "] {
+            assert!(is_reserved_device_name(name), "{}", name);
+        }
+        for name in ["CONFIG", "console", "COM10", "LPT", "hooks", "config"] {
+            assert!(!is_reserved_device_name(name), "{}", name);
+        }
+        assert_eq!(
+            check_tree_name("con", Path::new("repo")).is_err(),
+            cfg!(windows)
+        );
     }
 
-    match tree {
-        TreeNode::File(content) => {
-            if !options.overwrite && prefix.exists() {
-                return Ok(());
-            }
-            if options.dry_run {
-                println!(
-                    "[DRY-RUN] Write file {:?} ({} bytes)",
-                    prefix,
-                    content.len()
-                );
-            } else {
-                if let Some(parent) = prefix.parent() {
-                    fs::create_dir_all(parent)?;
+    /// A tree whose `a_blocked/` the tests make unwritable, next to a healthy `b_ok/`.
+    fn tree_with_failing_dir() -> TreeNode {
+        let mut blocked = HashMap::new();
+        blocked.insert("x".to_string(), TreeNode::text("x"));
+        blocked.insert("y".to_string(), TreeNode::text("y"));
+        let mut ok = HashMap::new();
+        ok.insert("z".to_string(), TreeNode::text("z"));
+        let mut children = HashMap::new();
+        children.insert("a_blocked".to_string(), TreeNode::dir(blocked));
+        children.insert("b_ok".to_string(), TreeNode::dir(ok));
+        TreeNode::dir(children)
+    }
+
+    #[test]
+    fn test_write_files_from_tree_error_modes() {
+        // A file where a directory should be
+        for on_error in [ErrorMode::Abort, ErrorMode::Collect] {
+            let vfs = MemFs::new();
+            let root = Path::new("/work");
+            vfs.create_dir(root).unwrap();
+            vfs.write(&root.join("a_blocked"), b"in the way").unwrap();
+            let opts = WriteOptions {
+                on_error,
+                vfs: &vfs,
+                ..Default::default()
+            };
+
+            let err = write_files_from_tree(&tree_with_failing_dir(), root, &opts).unwrap_err();
+            match on_error {
+                ErrorMode::Abort => {
+                    assert!(err.downcast_ref::<WriteReport>().is_none());
+                    assert!(!vfs.exists(&root.join("b_ok/z")));
+                }
+                ErrorMode::Collect => {
+                    let report = err.downcast_ref::<WriteReport>().unwrap();
+                    let failed: Vec<_> = report.failures.iter().map(|(p, _)| p.clone()).collect();
+                    assert_eq!(failed, [root.join("a_blocked/x"), root.join("a_blocked/y")]);
+                    assert!(err.to_string().contains("failed to write 2 path(s)"));
+                    assert_eq!(vfs.read(&root.join("b_ok/z")).unwrap(), b"z");
                 }
-                fs::write(prefix, content)
-                    .with_context(|| format!("Failed to write {:?}", prefix))?;
             }
         }
-        TreeNode::Directory(children) => {
-            if !prefix.exists() {
-                if options.dry_run {
-                    println!("[DRY-RUN] Create dir {:?}", prefix);
-                } else {
-                    fs::create_dir_all(prefix)
-                        .with_context(|| format!("Failed to create dir {:?}", prefix))?;
-                }
+    }
+
+    #[test]
+    fn test_write_files_from_tree_in_parallel() {
+        let mut children = HashMap::new();
+        for d in 0..8 {
+            let mut files: HashMap<_, _> = (0..25)
+                .map(|f| (format!("file{}", f), TreeNode::text(format!("{}/{}", d, f))))
+                .collect();
+            files.insert("link".to_string(), TreeNode::Symlink("file0".into()));
+            children.insert(format!("dir{}", d), TreeNode::dir(files));
+        }
+        let tree = TreeNode::dir(children);
+
+        let vfs = MemFs::new();
+        let write = |root: &Path, parallelism: usize| {
+            vfs.create_dir_all(&root.join("dir5")).unwrap();
+            vfs.write(&root.join("dir5/file3"), b"kept").unwrap();
+            let reports = std::cell::RefCell::new(Vec::new());
+            let record = |progress: Progress| reports.borrow_mut().push(progress);
+            let opts = WriteOptions {
+                overwrite: false,
+                progress: Some(&record),
+                vfs: &vfs,
+                parallelism,
+                ..Default::default()
+            };
+            let ops: Vec<String> = write_files_from_tree(&tree, root, &opts)
+                .unwrap()
+                .iter()
+                .map(|op| op.to_string().replace(&root.display().to_string(), ""))
+                .collect();
+            (ops, reports.into_inner())
+        };
+
+        // Same result as a serial write; only the order of the reports may differ
+        let (serial_ops, _) = write(Path::new("/serial"), 1);
+        let (ops, reports) = write(Path::new("/parallel"), 4);
+        assert_eq!(ops, serial_ops);
+        assert_eq!(reports.len(), 208);
+        for (index, progress) in reports.iter().enumerate() {
+            assert_eq!(progress.files_done, index + 1);
+            assert_eq!(progress.files_total, 208);
+        }
+        let last = reports.last().unwrap();
+        assert_eq!(last.bytes_done, last.bytes_total);
+        assert_eq!(
+            vfs.read(Path::new("/parallel/dir5/file3")).unwrap(),
+            b"kept"
+        );
+        assert_eq!(
+            vfs.read(Path::new("/parallel/dir7/file24")).unwrap(),
+            b"7/24"
+        );
+        assert!(
+            vfs.symlink_metadata(Path::new("/parallel/dir0/link"))
+                .unwrap()
+                .kind
+                == FileKind::Symlink
+        );
+
+        // Failures are reported as a serial write would report them
+        for on_error in [ErrorMode::Abort, ErrorMode::Collect] {
+            let root = Path::new("/errors");
+            vfs.remove_dir_all(root).ok();
+            vfs.create_dir(root).unwrap();
+            vfs.write(&root.join("a_blocked"), b"in the way").unwrap();
+            let opts = WriteOptions {
+                on_error,
+                vfs: &vfs,
+                parallelism: 4,
+                ..Default::default()
+            };
+
+            let err = write_files_from_tree(&tree_with_failing_dir(), root, &opts).unwrap_err();
+            let report = err.downcast_ref::<WriteReport>();
+            assert_eq!(report.is_some(), on_error == ErrorMode::Collect);
+            if let Some(report) = report {
+                let failed: Vec<_> = report.failures.iter().map(|(p, _)| p.clone()).collect();
+                assert_eq!(failed, [root.join("a_blocked/x"), root.join("a_blocked/y")]);
+                assert_eq!(vfs.read(&root.join("b_ok/z")).unwrap(), b"z");
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_files_from_tree_error_modes_read_only_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        for on_error in [ErrorMode::Abort, ErrorMode::Collect] {
+            let dir = tempdir().unwrap();
+            let root = dir.path();
+            fs::create_dir(root.join("a_blocked")).unwrap();
+            fs::set_permissions(root.join("a_blocked"), fs::Permissions::from_mode(0o555)).unwrap();
+            // Note: root ignores permission bits, so there is nothing to check then.
+            if fs::write(root.join("a_blocked/probe"), "").is_ok() {
+                return;
             }
 
-            for (name, node) in children {
-                write_files_from_tree(node, &prefix.join(name), options)?;
+            let opts = WriteOptions {
+                on_error,
+                ..Default::default()
+            };
+            let result = write_files_from_tree(&tree_with_failing_dir(), root, &opts);
+            fs::set_permissions(root.join("a_blocked"), fs::Permissions::from_mode(0o755)).unwrap();
+
+            let err = result.unwrap_err();
+            let report = err.downcast_ref::<WriteReport>();
+            assert_eq!(report.is_some(), on_error == ErrorMode::Collect);
+            if let Some(report) = report {
+                assert_eq!(report.failures.len(), 2);
             }
+            assert_eq!(root.join("b_ok/z").exists(), on_error == ErrorMode::Collect);
         }
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_tree_node_json_round_trip() {
+        let mut hooks = HashMap::new();
+        hooks.insert(
+            "pre-commit".to_string(),
+            TreeNode::file_with_mode("#!/bin/sh\n", 0o755),
+        );
+        let mut children = HashMap::new();
+        children.insert("HEAD".to_string(), TreeNode::text("ref: refs/heads/main\n"));
+        children.insert("pack".to_string(), TreeNode::file(vec![0x80, 0x00, 0xff]));
+        children.insert("hooks".to_string(), TreeNode::dir(hooks));
+        children.insert(
+            "empty".to_string(),
+            TreeNode::dir_with_mode(HashMap::new(), 0o700),
+        );
+        children.insert("link".to_string(), TreeNode::Symlink("HEAD".into()));
+        let tree = TreeNode::dir(children);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+        let json = tree.to_json().unwrap();
+        assert_eq!(TreeNode::from_json(&json).unwrap(), tree);
+        // Stable regardless of hash order, and readable where the content is text
+        assert_eq!(tree.to_json().unwrap(), json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let entries = &value["entries"];
+        assert_eq!(
+            entries.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["HEAD", "empty", "hooks", "link", "pack"]
+        );
+        assert_eq!(entries["HEAD"]["content"], "ref: refs/heads/main\n");
+        assert_eq!(entries["pack"]["base64"], "gAD/");
+        assert_eq!(entries["hooks"]["entries"]["pre-commit"]["mode"], "755");
+        assert_eq!(entries["empty"]["mode"], "700");
+        assert!(entries["hooks"].get("mode").is_none());
+        assert_eq!(entries["link"]["type"], "symlink");
+    }
 
     #[test]
-    fn test_write_files_from_tree() {
+    fn test_tree_node_from_json_rejects_bad_input() {
+        let cases = [
+            (
+                r#"{"type":"dir","entries":{"..":{"type":"file"}}}"#,
+                "\"..\"",
+            ),
+            (
+                r#"{"type":"dir","entries":{"a":{"type":"dir","entries":{"/etc":{"type":"file"}}}}}"#,
+                "\"./a\"",
+            ),
+            (
+                r#"{"type":"file","base64":"not base64!"}"#,
+                "invalid base64",
+            ),
+            (r#"{"type":"file","content":"x","base64":"eA=="}"#, "both"),
+            (r#"{"type":"file","mode":"999"}"#, "invalid mode"),
+            (
+                r#"{"type":"dir","entries":{},"mode":"17777"}"#,
+                "invalid mode",
+            ),
+            (r#"{"type":"fifo"}"#, "unknown variant"),
+        ];
+        for (json, expected) in cases {
+            let err = TreeNode::from_json(json).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", json, err);
+        }
+    }
+
+    #[test]
+    fn test_write_files_from_tree_binary_content() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work");
+        vfs.create_dir(root).unwrap();
+        // Not valid UTF-8: a lone continuation byte, an overlong NUL and 0xff
+        let content = vec![b'P', b'A', b'C', b'K', 0x80, 0xc0, 0x80, 0x00, 0xff, b'\n'];
+        assert!(String::from_utf8(content.clone()).is_err());
+
+        for overwrite in [false, true] {
+            let path = root.join(format!("pack-{}.pack", overwrite));
+            let mut children = HashMap::new();
+            children.insert(
+                path.file_name().unwrap().to_str().unwrap().to_string(),
+                TreeNode::file(content.clone()),
+            );
+            let opts = WriteOptions {
+                overwrite,
+                vfs: &vfs,
+                ..Default::default()
+            };
+
+            write_files_from_tree(&TreeNode::dir(children), root, &opts).unwrap();
+            assert_eq!(vfs.read(&path).unwrap(), content);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_files_from_tree_applies_perms() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("repo");
+
+        let mut children = HashMap::new();
+        children.insert("file.txt".to_string(), TreeNode::text("hello"));
+        let tree = TreeNode::dir(children);
+
+        let opts = WriteOptions {
+            dir_perms: Some(0o2770),
+            file_perms: Some(0o660),
+            ..Default::default()
+        };
+
+        write_files_from_tree(&tree, &root, &opts).unwrap();
+
+        let dir_mode = fs::metadata(&root).unwrap().permissions().mode();
+        assert_eq!(dir_mode & 0o7777, 0o2770);
+        let file_mode = fs::metadata(root.join("file.txt"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(file_mode & 0o777, 0o660);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_files_from_tree_default_dir_perms_on_every_level() {
+        use std::os::unix::fs::PermissionsExt;
+
         let dir = tempdir().unwrap();
         let root = dir.path();
 
+        // A file whose parent directories don't exist yet
+        let mut nested = HashMap::new();
+        nested.insert("file.txt".to_string(), TreeNode::text("hello"));
+        let tree = TreeNode::dir(nested);
+
+        write_files_from_tree(&tree, &root.join("a/b/c"), &WriteOptions::default()).unwrap();
+
+        // Explicit permissions make the result independent of the process umask.
+        for sub in ["a", "a/b", "a/b/c"] {
+            let mode = fs::metadata(root.join(sub)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, 0o755, "unexpected mode for {}", sub);
+        }
+
+        // Pre-existing directories are not touched
+        fs::set_permissions(root.join("a"), fs::Permissions::from_mode(0o700)).unwrap();
+        write_files_from_tree(&tree, &root.join("a/d"), &WriteOptions::default()).unwrap();
+        let mode = fs::metadata(root.join("a")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o700);
+    }
+
+    #[test]
+    fn test_write_files_from_tree_node_mode_overrides_options() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work");
+        vfs.create_dir(root).unwrap();
+
         let mut children = HashMap::new();
-        children.insert("file.txt".to_string(), TreeNode::File("hello".to_string()));
-        let tree = TreeNode::Directory(children);
+        children.insert(
+            "script.sh".to_string(),
+            TreeNode::file_with_mode("#!/bin/sh\n", 0o755),
+        );
+        let tree = TreeNode::dir(children);
 
         let opts = WriteOptions {
-            overwrite: true,
+            file_perms: Some(0o644),
+            vfs: &vfs,
             ..Default::default()
         };
 
         write_files_from_tree(&tree, root, &opts).unwrap();
 
-        let file_path = root.join("file.txt");
-        assert!(file_path.exists());
-        assert_eq!(fs::read_to_string(file_path).unwrap(), "hello");
+        let mode = vfs.metadata(&root.join("script.sh")).unwrap().mode;
+        assert_eq!(mode, Some(0o755));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_cs01_path_no_repo() {
+    fn test_write_files_from_tree_directory_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
         let dir = tempdir().unwrap();
-        let root = dir.path();
-        assert!(cs01_path(None, Some(root)).is_none());
+
+        let mut locked = HashMap::new();
+        locked.insert("notes.txt".to_string(), TreeNode::text("kept\n"));
+        let mut private = HashMap::new();
+        private.insert("key".to_string(), TreeNode::text("secret\n"));
+        // Read-only, yet its own entries still get written
+        private.insert("locked".to_string(), TreeNode::dir_with_mode(locked, 0o555));
+        let mut children = HashMap::new();
+        children.insert(
+            "private".to_string(),
+            TreeNode::dir_with_mode(private, 0o700),
+        );
+        children.insert(
+            "shared".to_string(),
+            TreeNode::dir_with_mode(HashMap::new(), 0o2775),
+        );
+        children.insert("plain".to_string(), TreeNode::dir(HashMap::new()));
+        let tree = TreeNode::dir(children);
+
+        for parallelism in [1, 4] {
+            let root = dir.path().join(format!("work{}", parallelism));
+            let opts = WriteOptions {
+                dir_perms: Some(0o750),
+                parallelism,
+                ..Default::default()
+            };
+            write_files_from_tree(&tree, &root, &opts).unwrap();
+
+            let mode =
+                |path: &str| fs::metadata(root.join(path)).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(mode("private"), 0o700);
+            assert_eq!(mode("private/locked"), 0o555);
+            assert_eq!(mode("shared"), 0o2775);
+            // Unset falls back to `dir_perms`
+            assert_eq!(mode("plain"), 0o750);
+            assert_eq!(mode(""), 0o750);
+            assert_eq!(
+                fs::read_to_string(root.join("private/locked/notes.txt")).unwrap(),
+                "kept\n"
+            );
+            fs::set_permissions(
+                root.join("private/locked"),
+                fs::Permissions::from_mode(0o755),
+            )
+            .unwrap();
+        }
     }
 
     #[test]
-    fn test_write_files_from_tree_dry_run() {
+    fn test_discover_no_repo() {
         let dir = tempdir().unwrap();
         let root = dir.path();
+        assert!(discover(Some(root)).unwrap().is_none());
+        assert!(!in_repo(Some(root)));
+    }
+
+    #[test]
+    fn test_write_files_from_tree_dry_run() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work");
+        vfs.create_dir(root).unwrap();
 
         let mut children = HashMap::new();
-        children.insert("file.txt".to_string(), TreeNode::File("hello".to_string()));
-        let tree = TreeNode::Directory(children);
+        children.insert("file.txt".to_string(), TreeNode::text("hello"));
+        let tree = TreeNode::dir(children);
 
         let opts = WriteOptions {
             dry_run: true,
+            vfs: &vfs,
             ..Default::default()
         };
 
-        // Execution should succeed
-        write_files_from_tree(&tree, root, &opts).unwrap();
+        // The plan is returned, and nothing is created
+        let plan = write_files_from_tree(&tree, &root.join("new"), &opts).unwrap();
+        assert_eq!(
+            plan,
+            [
+                PlannedOp::CreateDir(root.join("new")),
+                PlannedOp::WriteFile {
+                    path: root.join("new/file.txt"),
+                    bytes: 5,
+                },
+            ]
+        );
+        assert!(!vfs.exists(&root.join("new")));
 
-        // But no file should be created
-        let file_path = root.join("file.txt");
-        assert!(!file_path.exists());
+        vfs.write(&root.join("file.txt"), b"old").unwrap();
+        let opts = WriteOptions {
+            dry_run: true,
+            overwrite: false,
+            vfs: &vfs,
+            ..Default::default()
+        };
+        let plan = write_files_from_tree(&tree, root, &opts).unwrap();
+        assert_eq!(
+            plan,
+            [PlannedOp::Skip {
+                path: root.join("file.txt"),
+                reason: "already exists",
+            }]
+        );
+        assert_eq!(vfs.read(&root.join("file.txt")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_repo_root_from_env() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+
+        // Unset or empty means "search as usual"
+        assert!(repo_root_from_env(&RealFs, None, None).unwrap().is_none());
+        assert!(
+            repo_root_from_env(&RealFs, Some("".into()), None)
+                .unwrap()
+                .is_none()
+        );
+
+        // A directory that is not a repository is an error, not a fallback
+        let err = repo_root_from_env(&RealFs, Some(root.as_os_str().into()), None).unwrap_err();
+        assert!(err.to_string().contains("not a CS01 repository"));
+
+        // A standard metadata dir resolves to its parent
+        let cs01_dir = root.join(".CS01");
+        fs::create_dir(&cs01_dir).unwrap();
+        fs::write(cs01_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        let found = repo_root_from_env(&RealFs, Some(cs01_dir.as_os_str().into()), None).unwrap();
+        assert_eq!(found.as_deref(), Some(root));
+
+        // ...unless an explicit work tree is given
+        let work_tree = root.join("elsewhere");
+        let found = repo_root_from_env(
+            &RealFs,
+            Some(cs01_dir.as_os_str().into()),
+            Some(work_tree.as_os_str().into()),
+        )
+        .unwrap();
+        assert_eq!(found, Some(work_tree));
+
+        // Any other name is treated as a bare repository
+        let bare_dir = root.join("bare.cs01");
+        fs::create_dir(&bare_dir).unwrap();
+        fs::write(bare_dir.join("config"), "[core]\n").unwrap();
+        let found = repo_root_from_env(&RealFs, Some(bare_dir.as_os_str().into()), None).unwrap();
+        assert_eq!(found, Some(bare_dir));
+    }
+
+    fn found_root(found: Result<Option<DiscoveredRepo>>) -> Option<PathBuf> {
+        found.unwrap().map(|found| found.root().to_path_buf())
+    }
+
+    #[test]
+    fn test_find_repo_root_stops_at_ceiling() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let repo = root.join("projects/repo");
+        let nested = repo.join("src/deep");
+        fs::create_dir_all(repo.join(".CS01")).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+
+        // A ceiling above the repository root doesn't get in the way
+        let ceilings = ceiling_dirs(Some(root.join("projects").into_os_string()));
+        assert_eq!(ceilings, [root.join("projects")]);
+        assert_eq!(
+            found_root(find_repo_root(&RealFs, &nested, &ceilings, || false)),
+            Some(repo.clone())
+        );
+
+        // At or below the root, the root is never reached from further down
+        for ceiling in [&repo, &repo.join("src")] {
+            let ceilings = ceiling_dirs(Some(ceiling.as_os_str().into()));
+            assert_eq!(
+                found_root(find_repo_root(&RealFs, &nested, &ceilings, || false)),
+                None
+            );
+        }
+        // ...but the starting directory itself is still checked
+        let ceilings = ceiling_dirs(Some(repo.as_os_str().into()));
+        assert_eq!(
+            found_root(find_repo_root(&RealFs, &repo, &ceilings, || false)),
+            Some(repo.clone())
+        );
+    }
+
+    #[test]
+    fn test_find_repo_root_ignores_decoy_config() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work");
+        let decoy = b"[core]\n  editor = vim\n";
+
+        // A project shipping its own `config` is not a bare repository
+        let project = root.join("project");
+        vfs.create_dir_all(&project.join("src")).unwrap();
+        vfs.write(&project.join("config"), decoy).unwrap();
+        assert_eq!(
+            found_root(find_repo_root(&vfs, &project.join("src"), &[], || false)),
+            None
+        );
+
+        // ...nor does it hide the repository around it
+        vfs.create_dir(&project.join(".CS01")).unwrap();
+        let vendored = project.join("vendor/lib");
+        vfs.create_dir_all(&vendored.join("deep")).unwrap();
+        vfs.write(&vendored.join("config"), decoy).unwrap();
+        let found = find_repo_root(&vfs, &vendored.join("deep"), &[], || false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.work_tree, Some(project.clone()));
+        assert_eq!(found.repo_dir, project.join(".CS01"));
+
+        // With both a `.CS01` directory and a bare-looking layout, `.CS01` wins
+        vfs.write(&project.join("HEAD"), b"ref: refs/heads/main\n")
+            .unwrap();
+        vfs.create_dir(&project.join("refs")).unwrap();
+        let found = find_repo_root(&vfs, &project, &[], || false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.repo_dir, project.join(".CS01"));
+
+        // A genuine bare repository is still found
+        let bare = root.join("bare.cs01");
+        vfs.create_dir_all(&bare.join("refs/heads")).unwrap();
+        vfs.create_dir(&bare.join("objects")).unwrap();
+        vfs.write(&bare.join("HEAD"), b"ref: refs/heads/main\n")
+            .unwrap();
+        vfs.write(&bare.join("config"), b"[core]\n  bare = true\n")
+            .unwrap();
+        let found = find_repo_root(&vfs, &bare.join("refs/heads"), &[], || false)
+            .unwrap()
+            .unwrap();
+        assert!(found.is_bare());
+        assert_eq!(found.repo_dir, bare);
+        assert_eq!(found.path_in_repo("HEAD"), bare.join("HEAD"));
     }
 
     #[test]
-    fn test_cs01_path_deep_resolution() {
+    fn test_find_repo_root_within_one_filesystem() {
+        // Everything under the temp dir is on one filesystem, so crossing never comes up
+        let dir = tempdir().unwrap();
+        let repo = dir.path().canonicalize().unwrap();
+        let nested = repo.join("a/b/c");
+        fs::create_dir_all(repo.join(".CS01")).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+
+        let never_asked = || panic!("asked to cross a filesystem boundary");
+        assert_eq!(
+            found_root(find_repo_root(&RealFs, &nested, &[], never_asked)),
+            Some(repo)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_find_upwards_stops_at_mount_point() {
+        // /proc is its own mount on Linux; skip where it isn't (e.g. some containers)
+        let start = Path::new("/proc/sys");
+        let (Some(proc_fs), Some(root_fs)) = (filesystem_id(start), filesystem_id(Path::new("/")))
+        else {
+            return;
+        };
+        if proc_fs == root_fs {
+            return;
+        }
+
+        let visit = |may_cross: bool| {
+            let mut visited = Vec::new();
+            find_upwards(
+                start,
+                &[],
+                || may_cross,
+                |dir| {
+                    visited.push(dir.to_path_buf());
+                    false
+                },
+            );
+            visited
+        };
+        assert_eq!(visit(false), [Path::new("/proc/sys"), Path::new("/proc")]);
+        assert_eq!(
+            visit(true).last().map(PathBuf::as_path),
+            Some(Path::new("/"))
+        );
+    }
+
+    #[test]
+    fn test_ceiling_dirs_parsing() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let missing = root.join("missing");
+
+        assert!(ceiling_dirs(None).is_empty());
+        // Relative entries are ignored, and so are unresolvable ones before an empty entry
+        let value = std::env::join_paths([
+            Path::new("relative"),
+            &missing,
+            &root,
+            Path::new(""),
+            &missing,
+        ])
+        .unwrap();
+        assert_eq!(ceiling_dirs(Some(value)), [root.clone(), missing]);
+
+        #[cfg(unix)]
+        {
+            // Entries are resolved through symlinks, until the empty entry
+            let link = root.join("link");
+            std::os::unix::fs::symlink(&root, &link).unwrap();
+            let value = std::env::join_paths([&link, Path::new(""), &link]).unwrap();
+            assert_eq!(ceiling_dirs(Some(value)), [root.clone(), link]);
+        }
+    }
+
+    #[test]
+    fn test_find_foreign_vcs() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let deep_path = root.join("a/b");
+        fs::create_dir_all(&deep_path).unwrap();
+
+        assert!(find_foreign_vcs(&deep_path).is_none());
+
+        // `.git` as a pointer file, like in a git worktree
+        fs::write(root.join(".git"), "gitdir: /elsewhere\n").unwrap();
+
+        let found = find_foreign_vcs(&deep_path).unwrap();
+        assert_eq!(found.name, "Git");
+        assert_eq!(found.root, root);
+    }
+
+    #[test]
+    fn test_discover_deep_resolution() {
         let dir = tempdir().unwrap();
         let root = dir.path();
 
@@ -182,11 +2809,102 @@ mod tests {
         fs::create_dir_all(&deep_path).unwrap();
 
         // Check if resolving from deep path finds the root
-        let found = cs01_path(None, Some(&deep_path));
-        assert!(found.is_some());
-        assert_eq!(
-            found.unwrap().canonicalize().unwrap(),
-            root.canonicalize().unwrap()
+        let found = discover(Some(&deep_path)).unwrap().unwrap();
+        assert_eq!(found.work_tree, Some(root.to_path_buf()));
+        assert_eq!(found.repo_dir, cs01_dir);
+        assert!(!found.is_bare());
+        assert_eq!(found.path_in_repo("a/b"), root.join("a/b"));
+        assert!(in_repo(Some(&deep_path)));
+
+        #[allow(deprecated)]
+        let wrapped = cs01_path(Some("a/b"), Some(&deep_path)).unwrap();
+        assert_eq!(wrapped, Some(root.join("a/b")));
+    }
+
+    /// A tree whose `hooks/` entry cannot be written: a name containing NUL is rejected,
+    /// which fails the same way on every platform and even when running as root.
+    ///
+    /// Note: Children are written in sorted order, so `HEAD` and `description` come
+    /// before `hooks`.
+    fn tree_failing_midway() -> TreeNode {
+        let mut hooks = HashMap::new();
+        hooks.insert("bad\0name".to_string(), TreeNode::text("x"));
+        let mut children = HashMap::new();
+        children.insert("HEAD".to_string(), TreeNode::text("ref: refs/heads/main\n"));
+        children.insert("description".to_string(), TreeNode::text("unnamed\n"));
+        children.insert("hooks".to_string(), TreeNode::dir(hooks));
+        TreeNode::dir(children)
+    }
+
+    #[test]
+    fn test_write_files_transaction_rolls_back() {
+        let vfs = MemFs::new();
+        let root = Path::new("/work/repo");
+        vfs.create_dir_all(root).unwrap();
+        vfs.write(&root.join("description"), b"kept").unwrap();
+
+        // Note: Progress is reported after each file, so this sees what the write did
+        // before it failed.
+        let head_written = Cell::new(false);
+        let check_head = |_: Progress| {
+            head_written.set(head_written.get() || vfs.is_file(&root.join("HEAD")));
+        };
+        let opts = WriteOptions {
+            overwrite: false,
+            progress: Some(&check_head),
+            vfs: &vfs,
+            ..Default::default()
+        };
+        assert!(write_files_transaction(&tree_failing_midway(), root, &opts).is_err());
+        assert!(
+            head_written.get(),
+            "the write failed before creating anything"
+        );
+
+        // Only the pre-existing file survives; everything created by the call is gone.
+        assert_eq!(vfs.read_dir(root).unwrap(), ["description"]);
+        assert_eq!(vfs.read(&root.join("description")).unwrap(), b"kept");
+    }
+
+    #[test]
+    fn test_write_tree_staged() {
+        let vfs = MemFs::new();
+        let dir = Path::new("/work");
+        vfs.create_dir(dir).unwrap();
+        let target = dir.join(".CS01");
+        let opts = WriteOptions {
+            vfs: &vfs,
+            ..Default::default()
+        };
+
+        assert!(write_tree_staged(&tree_failing_midway(), &target, &opts).is_err());
+        assert!(!vfs.exists(&target));
+        assert!(
+            vfs.read_dir(dir).unwrap().is_empty(),
+            "staging dir left behind"
         );
+
+        let mut children = HashMap::new();
+        children.insert("HEAD".to_string(), TreeNode::text("ref: refs/heads/main\n"));
+        write_tree_staged(&TreeNode::dir(children), &target, &opts).unwrap();
+        assert!(vfs.is_file(&target.join("HEAD")));
+        assert_eq!(vfs.read_dir(dir).unwrap(), [".CS01"]);
+
+        // Never stages over something that is already there
+        let children = HashMap::new();
+        assert!(write_tree_staged(&TreeNode::dir(children), &target, &opts).is_err());
+    }
+
+    #[test]
+    fn test_probe_fs_capabilities_cleans_up() {
+        let dir = tempdir().unwrap();
+
+        // Probing a path that does not exist yet falls back to its parent
+        let caps = probe_fs_capabilities(&dir.path().join("not/yet/created"));
+        assert_eq!(caps, probe_fs_capabilities(dir.path()));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        #[cfg(unix)]
+        assert!(caps.filemode && caps.symlinks);
     }
 }
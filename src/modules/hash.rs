@@ -0,0 +1,321 @@
+use anyhow::{Result, bail};
+use sha1::{Digest, Sha1};
+
+/// The hash function a repository uses to name its objects.
+///
+/// Recorded at init time as `extensions.objectformat` (only for SHA-256, like git),
+/// and consumed by object storage code to know how long object ids are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// Parses a format name as accepted by `--object-format` and `extensions.objectformat`.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => bail!("unknown object format '{}' (expected sha1 or sha256)", name),
+        }
+    }
+
+    /// The canonical name, as written to config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// Length of a raw digest in bytes.
+    pub fn digest_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    /// Length of a digest rendered as lowercase hex.
+    pub fn hex_len(&self) -> usize {
+        self.digest_len() * 2
+    }
+
+    /// The all-zero id, used by git to mean "no object" (e.g. in reflogs).
+    pub fn null_hex(&self) -> String {
+        "0".repeat(self.hex_len())
+    }
+
+    /// Checks whether `s` is a full-length hex object id for this format.
+    pub fn is_hex_id(&self, s: &str) -> bool {
+        s.len() == self.hex_len() && s.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+impl std::fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The type of a stored object, named in its header as git does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    /// Parses a type name as it appears in an object header (`blob`, `tree`, ...).
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            _ => bail!("unknown object type '{}'", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The name of an object: the digest of its header and content.
+///
+/// Note: One variant per `ObjectFormat`; only SHA-1 is stored so far, and a SHA-256
+/// variant slots in beside it without changing how ids are parsed or printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ObjectId {
+    Sha1([u8; 20]),
+}
+
+impl ObjectId {
+    /// Parses a full-length hex id, in either case; the length decides the format.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("invalid object id '{}': not a hex string", hex);
+        }
+        match hex.len() {
+            40 => {
+                let mut bytes = [0; 20];
+                for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                    *byte = (hex_digit(pair[0]) << 4) | hex_digit(pair[1]);
+                }
+                Ok(ObjectId::Sha1(bytes))
+            }
+            64 => bail!(
+                "invalid object id '{}': sha256 objects are not supported yet",
+                hex
+            ),
+            len => bail!("invalid object id '{}': {} hex digits", hex, len),
+        }
+    }
+
+    /// An id from its raw digest, as stored in tree objects; the length decides the format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match <[u8; 20]>::try_from(bytes) {
+            Ok(bytes) => Ok(ObjectId::Sha1(bytes)),
+            Err(_) => bail!("invalid object id: {} bytes", bytes.len()),
+        }
+    }
+
+    /// The raw digest.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ObjectId::Sha1(bytes) => bytes,
+        }
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        match self {
+            ObjectId::Sha1(_) => ObjectFormat::Sha1,
+        }
+    }
+
+    /// The id as lowercase hex.
+    pub fn to_hex(&self) -> String {
+        self.as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// The first `len` hex digits, as shown in abbreviated ids; the whole id if `len` is longer.
+    pub fn short(&self, len: usize) -> String {
+        let mut hex = self.to_hex();
+        hex.truncate(len);
+        hex
+    }
+}
+
+impl std::fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl std::str::FromStr for ObjectId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_hex(s)
+    }
+}
+
+/// The value of an ASCII hex digit; the caller has checked that it is one.
+fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+/// Hashes `data` as an object of type `kind`, over git's `"<type> <len>\0<data>"` header,
+/// so identical content gets the same id here as in git.
+pub fn hash_object(kind: ObjectKind, data: &[u8]) -> ObjectId {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{} {}\0", kind, data.len()));
+    hasher.update(data);
+    ObjectId::Sha1(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_object_format() {
+        assert_eq!(ObjectFormat::parse("sha1").unwrap(), ObjectFormat::Sha1);
+        assert_eq!(ObjectFormat::parse("SHA256").unwrap(), ObjectFormat::Sha256);
+        assert!(ObjectFormat::parse("md5").is_err());
+    }
+
+    #[test]
+    fn test_hex_helpers() {
+        assert_eq!(ObjectFormat::Sha1.hex_len(), 40);
+        assert_eq!(ObjectFormat::Sha256.hex_len(), 64);
+        assert!(ObjectFormat::Sha1.is_hex_id("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"));
+        assert!(!ObjectFormat::Sha256.is_hex_id("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"));
+        assert!(!ObjectFormat::Sha1.is_hex_id(&"g".repeat(40)));
+        assert!(ObjectFormat::Sha256.is_hex_id(&ObjectFormat::Sha256.null_hex()));
+    }
+
+    #[test]
+    fn test_hash_object_matches_git() {
+        // Ids from `git hash-object -t <type>` for the same content
+        let cases: [(ObjectKind, &[u8], &str); 7] = [
+            (
+                ObjectKind::Blob,
+                b"",
+                "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391",
+            ),
+            (
+                ObjectKind::Blob,
+                b"hello\n",
+                "ce013625030ba8dba906f756967f9e9ca394464a",
+            ),
+            (
+                ObjectKind::Blob,
+                b"\x00\x01\xff",
+                "494b1410a95b9ef0a980c33411fbf7d564472741",
+            ),
+            (
+                ObjectKind::Tree,
+                b"",
+                "4b825dc642cb6eb9a060e54bf8d69288fbee4904",
+            ),
+            (
+                ObjectKind::Commit,
+                b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                  author A U Thor <author@example.com> 1112911993 -0700\n\
+                  committer C O Mitter <committer@example.com> 1112911993 -0700\n\
+                  \n\
+                  Initial\n",
+                "612f2ac2877ef512e73485a5478cdbe49d24b991",
+            ),
+            (
+                ObjectKind::Tag,
+                b"object e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n\
+                  type blob\n\
+                  tag v1\n\
+                  tagger A U Thor <author@example.com> 1112911993 -0700\n\
+                  \n\
+                  v1\n",
+                "c109971085d8aa065513fd554c9a07fc6f4dae39",
+            ),
+            // Same bytes, different type, different id
+            (
+                ObjectKind::Tree,
+                b"hello\n",
+                "149e5b19a5281f340f976d2ba38d4f02d8a6e967",
+            ),
+        ];
+        for (kind, data, expected) in cases {
+            let id = hash_object(kind, data);
+            assert_eq!(id.to_hex(), expected, "{} {:?}", kind, data);
+            assert_eq!(id.format(), ObjectFormat::Sha1);
+        }
+    }
+
+    #[test]
+    fn test_object_id_hex() {
+        let hex = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let id = ObjectId::from_hex(hex).unwrap();
+        assert_eq!(id, hash_object(ObjectKind::Blob, b""));
+        assert_eq!(id.as_bytes()[..2], [0xe6, 0x9d]);
+        assert_eq!(ObjectId::from_bytes(id.as_bytes()).unwrap(), id);
+        assert!(ObjectId::from_bytes(&id.as_bytes()[1..]).is_err());
+        assert_eq!(id.to_string(), hex);
+        assert_eq!(ObjectId::from_hex(&hex.to_uppercase()).unwrap(), id);
+        assert_eq!(hex.parse::<ObjectId>().unwrap(), id);
+        assert_eq!(id.short(7), "e69de29");
+        assert_eq!(id.short(100), hex);
+
+        for bad in [
+            "",
+            "e69de29",
+            &"g".repeat(40),
+            &"0".repeat(64),
+            "é".repeat(20).as_str(),
+        ] {
+            assert!(ObjectId::from_hex(bad).is_err(), "{:?}", bad);
+        }
+
+        // Ordered bytewise, like sorted hex
+        let low = ObjectId::from_hex(&ObjectFormat::Sha1.null_hex()).unwrap();
+        let high = ObjectId::from_hex(&"f".repeat(40)).unwrap();
+        assert!(low < id && id < high);
+    }
+
+    #[test]
+    fn test_object_kind_names() {
+        for kind in [
+            ObjectKind::Blob,
+            ObjectKind::Tree,
+            ObjectKind::Commit,
+            ObjectKind::Tag,
+        ] {
+            assert_eq!(ObjectKind::parse(kind.name()).unwrap(), kind);
+        }
+        assert!(ObjectKind::parse("Blob").is_err());
+    }
+}
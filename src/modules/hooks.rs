@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::modules::{files::CS01_DIR_ENV, output::Output};
+use crate::repo::Repository;
+
+/// What `run_hook` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookOutcome {
+    /// The hook ran and exited successfully.
+    Ran,
+    /// There's no hook by that name, or it isn't executable.
+    Missing,
+    /// The hook exists but couldn't be run here (no `sh` on Windows).
+    Skipped,
+}
+
+/// A hook that exited unsuccessfully, which stops the operation that ran it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookFailed {
+    pub name: String,
+    /// The exit status; `None` when the hook was killed by a signal.
+    pub status: Option<i32>,
+}
+
+impl std::fmt::Display for HookFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => write!(
+                f,
+                "the '{}' hook failed (exit status {})",
+                self.name, status
+            ),
+            None => write!(f, "the '{}' hook was terminated by a signal", self.name),
+        }
+    }
+}
+
+impl std::error::Error for HookFailed {}
+
+/// The directory hooks are looked up in: `core.hooksPath` (relative to the top of the
+/// working tree), or `hooks/` in the metadata directory.
+pub fn hooks_dir(repo: &Repository) -> Result<PathBuf> {
+    let Some(path) = repo.config()?.get_path("core.hookspath")? else {
+        return Ok(repo.repo_dir().join("hooks"));
+    };
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    Ok(repo.work_tree().unwrap_or(repo.repo_dir()).join(path))
+}
+
+/// Runs the hook `name` with `args`, feeding it `stdin` if given.
+///
+/// The hook runs from the top of the working tree (the metadata directory for a bare
+/// repository) with `CS01_DIR` set, and its output goes to stderr so it can't mix
+/// with ours. A missing or non-executable hook is skipped, as in git.
+///
+/// Note: A non-zero exit is a `HookFailed` error. On Windows the hook is run through
+/// `sh`, and skipped with a warning when there's no `sh` to run it.
+pub fn run_hook(
+    repo: &Repository,
+    name: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+) -> Result<HookOutcome> {
+    let path = hooks_dir(repo)?.join(name);
+    if !is_executable(&path) {
+        return Ok(HookOutcome::Missing);
+    }
+
+    let repo_dir = std::path::absolute(repo.repo_dir())
+        .with_context(|| format!("Failed to resolve {:?}", repo.repo_dir()))?;
+    let mut command = hook_command(&path);
+    command
+        .args(args)
+        .current_dir(repo.work_tree().unwrap_or(repo.repo_dir()))
+        .env(CS01_DIR_ENV, &repo_dir)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::from(std::io::stderr()));
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) if cfg!(not(unix)) && e.kind() == std::io::ErrorKind::NotFound => {
+            Output::default().warn(&format!(
+                "hint: the '{}' hook was ignored because sh is not available",
+                name
+            ));
+            return Ok(HookOutcome::Skipped);
+        }
+        Err(e) => return Err(e).with_context(|| format!("cannot run hook {:?}", path)),
+    };
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // Note: A hook may exit without reading its input; that isn't a failure.
+        match pipe.write_all(input) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                return Err(e).with_context(|| format!("Failed to write to hook {:?}", path));
+            }
+            _ => {}
+        }
+    }
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for hook {:?}", path))?;
+    if !status.success() {
+        return Err(HookFailed {
+            name: name.to_string(),
+            status: status.code(),
+        }
+        .into());
+    }
+    Ok(HookOutcome::Ran)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+#[cfg(unix)]
+fn hook_command(path: &Path) -> Command {
+    Command::new(path)
+}
+
+#[cfg(not(unix))]
+fn hook_command(path: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command.arg(path);
+    command
+}
@@ -0,0 +1,610 @@
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::modules::{config::default_excludes_file, constants::IGNORE_FILE};
+use crate::repo::Repository;
+
+/// One line of an ignore file, with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The file the pattern was read from.
+    pub source: PathBuf,
+    /// Its line number in that file, from 1.
+    pub line: usize,
+    /// The pattern as written, without trailing spaces.
+    pub text: String,
+    /// Whether it re-includes what it matches (`!pattern`).
+    pub negated: bool,
+    /// The glob itself, without `!`, a leading `/` or a trailing `/`.
+    glob: String,
+    /// The directory of the `.cs01ignore` it's in, from the top of the working tree;
+    /// empty for the top and for repository-wide files.
+    base: String,
+    /// Only directories match (`pattern/`).
+    dir_only: bool,
+    /// Matched against the whole path below `base` rather than just the last
+    /// component, since the pattern has a `/` other than a trailing one.
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Reads `line` as git does; `None` for blank lines and `#` comments.
+    ///
+    /// Note: Trailing spaces are dropped unless escaped with `\`, and `\#` or `\!` at
+    /// the start stand for a literal `#` or `!`.
+    fn parse(source: &Path, line_number: usize, line: &str, base: &str) -> Option<Self> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let mut text = line.trim_end_matches(' ');
+        if text.len() < line.len() && text.ends_with('\\') && !text.ends_with("\\\\") {
+            text = &line[..text.len() + 1];
+        }
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+
+        let (negated, mut glob) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        if glob.starts_with("\\#") || glob.starts_with("\\!") {
+            glob = &glob[1..];
+        }
+        let dir_only = glob.ends_with('/');
+        let glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            source: source.to_path_buf(),
+            line: line_number,
+            text: text.to_string(),
+            negated,
+            glob: glob.to_string(),
+            base: base.to_string(),
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Whether this pattern matches `path` (from the top of the working tree).
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(rest) => rest,
+                None => return false,
+            }
+        };
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Decides which untracked paths of a working tree are ignored, following gitignore rules.
+///
+/// Patterns come from the `.cs01ignore` file of each directory, then from
+/// repository-wide files (`info/exclude`, then `core.excludesFile`). A deeper
+/// `.cs01ignore` takes precedence over the ones above it and over the repository-wide
+/// files, and within a file the last matching line wins, so `!pattern` can re-include
+/// what an earlier line ignored. Nothing inside an ignored directory can be re-included.
+///
+/// Note: `.cs01ignore` files are read as their directories are first asked about; an
+/// unreadable one counts as empty, as git only warns about it.
+#[derive(Debug)]
+pub struct IgnoreMatcher {
+    work_tree: PathBuf,
+    /// Repository-wide patterns, in increasing order of precedence.
+    global: Vec<Pattern>,
+    /// Whether `.cs01ignore` files are read at all.
+    directory_files: bool,
+    /// The patterns of each directory's `.cs01ignore`, by directory, once read.
+    directories: RefCell<HashMap<String, Rc<Vec<Pattern>>>>,
+}
+
+impl IgnoreMatcher {
+    /// A matcher for `work_tree` that only knows its `.cs01ignore` files so far.
+    pub fn new(work_tree: &Path) -> Self {
+        IgnoreMatcher {
+            work_tree: work_tree.to_path_buf(),
+            global: Vec::new(),
+            directory_files: true,
+            directories: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// A matcher for `work_tree` that leaves its `.cs01ignore` files alone, so only
+    /// files added with `add_file` count.
+    pub fn without_directory_files(work_tree: &Path) -> Self {
+        IgnoreMatcher {
+            directory_files: false,
+            ..IgnoreMatcher::new(work_tree)
+        }
+    }
+
+    /// The rules for the working tree of `repo`: its `.cs01ignore` files, its
+    /// `info/exclude` and the user's `core.excludesFile` (by default
+    /// `~/.config/cs01/ignore`).
+    pub fn for_repo(repo: &Repository) -> Result<Self> {
+        let work_tree = repo
+            .work_tree()
+            .context("this operation must be run in a work tree")?;
+        let mut matcher = IgnoreMatcher::new(work_tree);
+        let excludes_file = match repo.config()?.get_path("core.excludesfile")? {
+            Some(path) => Some(path),
+            None => default_excludes_file(),
+        };
+        if let Some(path) = excludes_file {
+            matcher.add_file(&path)?;
+        }
+        matcher.add_file(&repo.repo_dir().join("info/exclude"))?;
+        Ok(matcher)
+    }
+
+    /// Adds the patterns in `path` as repository-wide rules, taking precedence over
+    /// files added before; a missing file adds nothing.
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        // Note: Repository-wide rules are checked from the end, so they go in front.
+        let mut patterns = read_patterns(path, "")?;
+        patterns.append(&mut self.global);
+        self.global = patterns;
+        Ok(())
+    }
+
+    /// Whether `path` (`/`-separated, from the top of the working tree) is ignored;
+    /// `is_dir` says whether it's a directory, for `pattern/` rules.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.matching_pattern(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    /// The pattern that decides whether `path` is ignored: one that ignores a directory
+    /// above it, or else the one of highest precedence that matches it, which may be a
+    /// negation. `None` when no pattern matches.
+    pub fn matching_pattern(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        let parents = path.match_indices('/').map(|(i, _)| &path[..i]);
+        for parent in parents {
+            if let Some(pattern) = self.last_match(parent, true)
+                && !pattern.negated
+            {
+                return Some(pattern);
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// The matching pattern of highest precedence for `path` alone.
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        let parents = path.match_indices('/').map(|(i, _)| &path[..i]);
+        let dirs: Vec<&str> = match self.directory_files {
+            true => [""].into_iter().chain(parents).collect(),
+            false => Vec::new(),
+        };
+        for dir in dirs.into_iter().rev() {
+            let patterns = self.directory_patterns(dir);
+            if let Some(pattern) = patterns.iter().rev().find(|p| p.matches(path, is_dir)) {
+                return Some(pattern.clone());
+            }
+        }
+        self.global
+            .iter()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .cloned()
+    }
+
+    /// The patterns of the `.cs01ignore` in `dir`, read on first use.
+    fn directory_patterns(&self, dir: &str) -> Rc<Vec<Pattern>> {
+        if let Some(patterns) = self.directories.borrow().get(dir) {
+            return patterns.clone();
+        }
+        let path = self.work_tree.join(dir).join(IGNORE_FILE);
+        let patterns = Rc::new(read_patterns(&path, dir).unwrap_or_default());
+        self.directories
+            .borrow_mut()
+            .insert(dir.to_string(), patterns.clone());
+        patterns
+    }
+}
+
+/// The patterns in the ignore file at `path`, for paths under `base`; none when the
+/// file doesn't exist.
+fn read_patterns(path: &Path, base: &str) -> Result<Vec<Pattern>> {
+    let text = match fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    };
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(path, i + 1, line, base))
+        .collect())
+}
+
+/// Whether `text` matches the glob `pattern`, as git's wildmatch does for paths: `*`
+/// and `?` never match `/`, `[...]` is a character class, `\` escapes the next
+/// character, and `**` between slashes (or at either end) matches any number of
+/// directories.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    glob(pattern, text, true)
+}
+
+/// Whether `text` matches the glob `pattern` as a whole string, as git matches
+/// reference names: like [`wildmatch`], except that `*`, `?` and `[...]` match `/` too.
+pub fn wildmatch_name(pattern: &[u8], text: &[u8]) -> bool {
+    glob(pattern, text, false)
+}
+
+/// [`wildmatch`], or with `pathname` off, [`wildmatch_name`].
+fn glob(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let start = p;
+                while p < pattern.len() && pattern[p] == b'*' {
+                    p += 1;
+                }
+                let whole_components = pathname
+                    && p - start >= 2
+                    && (start == 0 || pattern[start - 1] == b'/')
+                    && (p == pattern.len() || pattern[p] == b'/');
+                if whole_components {
+                    if p == pattern.len() {
+                        return true;
+                    }
+                    // `**/` matches no directory at all, or any run of them.
+                    let rest = &pattern[p + 1..];
+                    return glob(rest, &text[t..], pathname)
+                        || (t..text.len())
+                            .any(|i| text[i] == b'/' && glob(rest, &text[i + 1..], pathname));
+                }
+                let rest = &pattern[p..];
+                for i in t..=text.len() {
+                    if glob(rest, &text[i..], pathname) {
+                        return true;
+                    }
+                    if pathname && i < text.len() && text[i] == b'/' {
+                        break;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' if t < text.len() && !(pathname && text[t] == b'/') => {
+                match match_class(pattern, p, text[t]) {
+                    Some((true, next)) => {
+                        p = next;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'[' => return false,
+            c => {
+                let (literal, width) = match (c, pattern.get(p + 1)) {
+                    (b'\\', Some(next)) => (*next, 2),
+                    _ => (c, 1),
+                };
+                if t >= text.len() || text[t] != literal {
+                    return false;
+                }
+                p += width;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Matches `c` against the class starting at `pattern[start]` (a `[`); returns whether
+/// it matched and where the pattern continues, or `None` if the class isn't closed.
+fn match_class(pattern: &[u8], start: usize, c: u8) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(i)?;
+        if low == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if low == b'[' && pattern.get(i + 1) == Some(&b':') {
+            let rest = &pattern[i + 2..];
+            let end = rest.windows(2).position(|w| w == b":]")?;
+            matched |= in_named_class(&rest[..end], c);
+            i += end + 4;
+            continue;
+        }
+        if low == b'\\' {
+            i += 1;
+            low = *pattern.get(i)?;
+        }
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&b| b != b']') {
+            let mut high = pattern[i + 2];
+            i += 2;
+            if high == b'\\' {
+                i += 1;
+                high = *pattern.get(i)?;
+            }
+            matched |= low <= c && c <= high;
+        } else {
+            matched |= low == c;
+        }
+        i += 1;
+    }
+}
+
+/// Whether `c` is in the POSIX class `name`, as in `[[:digit:]]`.
+fn in_named_class(name: &[u8], c: u8) -> bool {
+    match name {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase(),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace(),
+        b"upper" => c.is_ascii_uppercase(),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_wildmatch() {
+        let cases = [
+            ("foo", "foo", true),
+            ("foo", "foobar", false),
+            ("*.o", "main.o", true),
+            ("*.o", "dir/main.o", false),
+            ("?.txt", "a.txt", true),
+            ("?.txt", "ab.txt", false),
+            ("a?b", "a/b", false),
+            ("doc/*.txt", "doc/notes.txt", true),
+            ("doc/*.txt", "doc/server/arch.txt", false),
+            ("**/foo", "foo", true),
+            ("**/foo", "a/b/foo", true),
+            ("**/foo/bar", "x/foo/bar", true),
+            ("abc/**", "abc/x/y", true),
+            ("abc/**", "abc", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "a/xb", false),
+            ("a**b", "a/b", false),
+            ("a**b", "axxb", true),
+            ("[abc].rs", "b.rs", true),
+            ("[!abc].rs", "b.rs", false),
+            ("[a-c]x", "cx", true),
+            ("[a-c]x", "dx", false),
+            ("[]]", "]", true),
+            ("[[:digit:]]*", "7up", true),
+            ("[[:digit:]]*", "up", false),
+            ("\\*", "*", true),
+            ("\\*", "x", false),
+            ("[unclosed", "[unclosed", true),
+            ("*", "", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{} against {}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_wildmatch_name() {
+        let cases = [
+            ("refs/remotes/*", "refs/remotes/origin/main", true),
+            ("refs/tags/v?", "refs/tags/v1", true),
+            ("refs/*/main", "refs/remotes/origin/main", true),
+            ("refs/[ht]*", "refs/heads/main", true),
+            ("refs/heads/*", "refs/tags/v1", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch_name(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{} against {}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_pattern_parse() {
+        let source = Path::new(".cs01ignore");
+        let parse = |line| Pattern::parse(source, 1, line, "");
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("# comment"), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(parse("/"), None);
+
+        let pattern = parse("!/build/  ").unwrap();
+        assert_eq!(pattern.text, "!/build/");
+        assert!(pattern.negated && pattern.dir_only && pattern.anchored);
+        assert_eq!(pattern.glob, "build");
+        let pattern = parse("build/").unwrap();
+        assert!(pattern.dir_only && !pattern.anchored);
+        assert_eq!(parse("\\#file").unwrap().glob, "#file");
+        assert!(!parse("\\!file").unwrap().negated);
+        assert_eq!(parse("trailing\\ ").unwrap().glob, "trailing\\ ");
+        assert!(parse("doc/frotz").unwrap().anchored);
+    }
+
+    /// The examples of the gitignore documentation, as `(pattern lines, path, is_dir,
+    /// ignored)` against a top-level ignore file.
+    #[test]
+    fn test_ignore_rules_from_the_git_docs() {
+        let cases: &[(&[&str], &str, bool, bool)] = &[
+            // A pattern without a slash matches at any level
+            (&["frotz"], "frotz", false, true),
+            (&["frotz"], "a/frotz", false, true),
+            (&["frotz"], "a/frotz", true, true),
+            // A trailing slash only matches directories (and so what is in them)
+            (&["frotz/"], "frotz", false, false),
+            (&["frotz/"], "frotz", true, true),
+            (&["frotz/"], "a/frotz/file", false, true),
+            // A slash at the start or in the middle anchors the pattern
+            (&["doc/frotz/"], "doc/frotz", true, true),
+            (&["doc/frotz/"], "a/doc/frotz", true, false),
+            (&["/frotz"], "frotz", false, true),
+            (&["/frotz"], "a/frotz", false, false),
+            (&["doc/*.html"], "doc/index.html", false, true),
+            (&["doc/*.html"], "doc/sub/index.html", false, false),
+            (&["**/foo"], "deep/down/foo", false, true),
+            (&["abc/**"], "abc/def/ghi", false, true),
+            (&["a/**/b"], "a/x/y/b", false, true),
+            // The last matching line wins, so a negation re-includes
+            (&["*.log", "!keep.log"], "keep.log", false, false),
+            (&["*.log", "!keep.log"], "other.log", false, true),
+            (&["!keep.log", "*.log"], "keep.log", false, true),
+            // Nothing inside an excluded directory can be re-included
+            (
+                &["build/", "!build/keep.txt"],
+                "build/keep.txt",
+                false,
+                true,
+            ),
+            // ... but the directory's contents can be excluded and some re-included
+            (
+                &["/*", "!/foo", "/foo/*", "!/foo/bar"],
+                "foo/bar",
+                true,
+                false,
+            ),
+            (
+                &["/*", "!/foo", "/foo/*", "!/foo/bar"],
+                "foo/baz",
+                false,
+                true,
+            ),
+            (
+                &["/*", "!/foo", "/foo/*", "!/foo/bar"],
+                "other",
+                false,
+                true,
+            ),
+            // Escapes
+            (&["\\!important"], "!important", false, true),
+            (&["\\#hash"], "#hash", false, true),
+        ];
+
+        for (lines, path, is_dir, expected) in cases {
+            let dir = tempdir().unwrap();
+            fs::write(dir.path().join(IGNORE_FILE), lines.join("\n")).unwrap();
+            let matcher = IgnoreMatcher::new(dir.path());
+            assert_eq!(
+                matcher.is_ignored(path, *is_dir),
+                *expected,
+                "{:?} with {:?}",
+                path,
+                lines
+            );
+        }
+    }
+
+    #[test]
+    fn test_ignore_precedence_between_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::write(root.join("global"), "*.tmp\n*.bak\n*.swp\n").unwrap();
+        fs::write(root.join("exclude"), "!*.bak\n*.o\n").unwrap();
+        fs::write(root.join(IGNORE_FILE), "!*.swp\n*.a\n").unwrap();
+        fs::write(
+            root.join("sub").join(IGNORE_FILE),
+            "!*.o\n!*.a\n/only-here\n",
+        )
+        .unwrap();
+        fs::write(root.join("sub/deeper").join(IGNORE_FILE), "*.a\n").unwrap();
+
+        let mut matcher = IgnoreMatcher::new(root);
+        matcher.add_file(&root.join("global")).unwrap();
+        matcher.add_file(&root.join("exclude")).unwrap();
+        matcher.add_file(&root.join("missing")).unwrap();
+
+        let cases = [
+            ("x.tmp", true),
+            // info/exclude overrides the global file
+            ("x.bak", false),
+            // .cs01ignore overrides both
+            ("x.swp", false),
+            ("x.o", true),
+            ("x.a", true),
+            // A deeper .cs01ignore overrides the ones above, only under its directory
+            ("sub/x.o", false),
+            ("sub/x.a", false),
+            ("sub/deeper/x.a", true),
+            ("sub/only-here", true),
+            ("only-here", false),
+            ("sub/deeper/only-here", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(matcher.is_ignored(path, false), expected, "{}", path);
+        }
+
+        // The deciding pattern, with where it came from
+        let pattern = matcher.matching_pattern("sub/x.a", false).unwrap();
+        assert!(pattern.negated);
+        assert_eq!(pattern.source, root.join("sub").join(IGNORE_FILE));
+        assert_eq!((pattern.line, pattern.text.as_str()), (2, "!*.a"));
+        let pattern = matcher.matching_pattern("x.bak", false).unwrap();
+        assert_eq!((pattern.source, pattern.line), (root.join("exclude"), 1));
+        assert_eq!(matcher.matching_pattern("README", false), None);
+    }
+}
@@ -0,0 +1,1171 @@
+use anyhow::{Context, Result, bail};
+use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::modules::{
+    constants::is_repository_dir_name,
+    files::link_text,
+    hash::{ObjectId, ObjectKind, hash_object},
+    lockfile::Lockfile,
+    objects::tree::{EntryMode, Tree, TreeEntry},
+    odb::Odb,
+};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+/// The version new indexes are written in: git's default, without extended flags.
+const DEFAULT_VERSION: u32 = 2;
+/// The first version with extended flags, which entries that have any need.
+const EXTENDED_VERSION: u32 = 3;
+/// The version with prefix-compressed paths and no entry padding.
+const COMPRESSED_VERSION: u32 = 4;
+/// Bytes of an entry before its path: ten 32-bit stat fields, the id and the flags.
+const ENTRY_FIXED_LEN: usize = 62;
+const CHECKSUM_LEN: usize = 20;
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE: u16 = 0x3000;
+/// The low 12 bits of the flags hold the path length, or all ones if it doesn't fit.
+const NAME_MASK: u16 = 0x0fff;
+
+const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+/// A file timestamp as the index stores it, truncated to 32-bit seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexTime {
+    pub seconds: u32,
+    pub nanoseconds: u32,
+}
+
+impl IndexTime {
+    /// `time` as the index records it; times before 1970 are recorded as zero.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        IndexTime {
+            seconds: since_epoch.as_secs() as u32,
+            nanoseconds: since_epoch.subsec_nanos(),
+        }
+    }
+}
+
+/// One staged file: its content id and mode, and the stat data it had when staged.
+///
+/// Note: The stat fields only let later commands skip rehashing unchanged files; a
+/// zeroed entry is valid and just looks modified until it's refreshed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: IndexTime,
+    pub mtime: IndexTime,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: EntryMode,
+    pub uid: u32,
+    pub gid: u32,
+    /// The file size, truncated to 32 bits as in git.
+    pub size: u32,
+    pub oid: ObjectId,
+    /// The assume-valid bit and the merge stage (bits 12-13); the path length bits
+    /// are filled in on write.
+    pub flags: u16,
+    /// The skip-worktree (0x4000) and intent-to-add (0x2000) bits, stored from index
+    /// version 3 on; kept as read so writing the index back doesn't lose them.
+    pub extended_flags: u16,
+    /// The path from the top of the working tree, `/`-separated.
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// A stage 0 entry with no stat data.
+    pub fn new(path: impl Into<String>, mode: EntryMode, oid: ObjectId) -> Self {
+        IndexEntry {
+            ctime: IndexTime::default(),
+            mtime: IndexTime::default(),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            oid,
+            flags: 0,
+            extended_flags: 0,
+            path: path.into(),
+        }
+    }
+
+    /// The merge stage: 0 for a normal entry, 1-3 for the base, ours and theirs
+    /// versions of a conflicted path.
+    pub fn stage(&self) -> u8 {
+        ((self.flags & FLAG_STAGE) >> 12) as u8
+    }
+
+    /// The same entry at merge stage `stage` (0-3).
+    pub fn with_stage(mut self, stage: u8) -> Self {
+        self.flags = (self.flags & !FLAG_STAGE) | ((stage as u16) << 12 & FLAG_STAGE);
+        self
+    }
+
+    pub fn assume_valid(&self) -> bool {
+        self.flags & FLAG_ASSUME_VALID != 0
+    }
+
+    /// Records the stat data of the file `metadata` describes (from `symlink_metadata`).
+    #[cfg(unix)]
+    pub fn set_stat(&mut self, metadata: &Metadata) {
+        use std::os::unix::fs::MetadataExt;
+
+        self.ctime = IndexTime {
+            seconds: metadata.ctime() as u32,
+            nanoseconds: metadata.ctime_nsec() as u32,
+        };
+        self.mtime = IndexTime {
+            seconds: metadata.mtime() as u32,
+            nanoseconds: metadata.mtime_nsec() as u32,
+        };
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.len() as u32;
+    }
+
+    /// Note: Only times and size are available here, as in git for Windows.
+    #[cfg(not(unix))]
+    pub fn set_stat(&mut self, metadata: &Metadata) {
+        let time = |time: std::io::Result<SystemTime>| {
+            time.map(IndexTime::from_system_time).unwrap_or_default()
+        };
+        self.ctime = time(metadata.created());
+        self.mtime = time(metadata.modified());
+        self.size = metadata.len() as u32;
+    }
+
+    /// Whether the file `metadata` describes still has the stat data recorded here, so
+    /// its content can be assumed unchanged without hashing it again.
+    pub fn stat_matches(&self, metadata: &Metadata) -> bool {
+        let mut current = self.clone();
+        current.set_stat(metadata);
+        current == *self
+    }
+
+    /// Whether the file may have changed within the same timestamp tick the index was
+    /// written in (`index_mtime`), so matching stat data proves nothing.
+    ///
+    /// Note: This is git's "racy clean" problem; such entries must be compared by content.
+    pub fn is_racy(&self, index_mtime: IndexTime) -> bool {
+        self.mtime >= index_mtime
+    }
+
+    /// Index order: by path bytes, then by stage.
+    fn index_cmp(&self, path: &str, stage: u8) -> Ordering {
+        self.path
+            .as_bytes()
+            .cmp(path.as_bytes())
+            .then(self.stage().cmp(&stage))
+    }
+}
+
+/// The staging area (`<repo>/index`): the files the next commit will record.
+///
+/// Stored in git's layout, so git can read it and the other way round:
+/// - A 12-byte header: `DIRC`, the version (2, 3 or 4) and the number of entries,
+///   all big-endian 32-bit values.
+/// - The entries, sorted by path bytes and then stage. Each is ctime and mtime
+///   (seconds and nanoseconds), dev, ino, mode, uid, gid and size as 32-bit values,
+///   the 20-byte object id, 16 bits of flags (assume-valid, extended, 2 bits of
+///   stage, 12 bits of path length), from version 3 another 16 bits of extended
+///   flags if the extended bit is set, then the path, NUL-padded to a multiple of 8
+///   bytes with at least one NUL. Version 4 instead stores how many bytes to drop
+///   from the end of the previous path as a varint, then the rest of the path with
+///   one NUL, unpadded.
+/// - Optional extensions (4-byte signature, 32-bit size, data). Those git marks
+///   optional (signature starting with `A`-`Z`, like the `TREE` cache) are skipped on
+///   read and not written back; any other is refused.
+/// - A SHA-1 of everything before it.
+///
+/// Note: An index is written back in the version it was read in, or version 3 when
+/// a version 2 index gains extended flags. New index files take the version from
+/// `index.version` (see `Repository::read_index`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    version: u32,
+    entries: Vec<IndexEntry>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            version: DEFAULT_VERSION,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl Index {
+    /// An empty index to be written in `version` (2, 3 or 4).
+    pub fn with_version(version: u32) -> Result<Self> {
+        if !(DEFAULT_VERSION..=COMPRESSED_VERSION).contains(&version) {
+            bail!("unsupported index version {}", version);
+        }
+        Ok(Index {
+            version,
+            entries: Vec::new(),
+        })
+    }
+
+    /// The version the index was read in or created for.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Reads the index at `path`; a missing file is an empty index, as in a new repository.
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Index::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        Index::parse(&data).with_context(|| format!("corrupt index {:?}", path))
+    }
+
+    /// Replaces the index at `path`, taking `<path>.lock` for the duration.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        self.write_locked(Lockfile::acquire(path)?)
+    }
+
+    /// Writes through a lock the caller already holds, then commits it.
+    ///
+    /// Note: Read-modify-write cycles acquire the lock first, then `read`, change and
+    /// call this, so no concurrent update is lost.
+    pub fn write_locked(&self, mut lock: Lockfile) -> Result<()> {
+        lock.write(&self.serialize())?;
+        lock.commit()
+    }
+
+    /// All entries, in index order.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The stage 0 entry for `path`.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    /// Stages `entry`, replacing the entry with the same path and stage.
+    ///
+    /// A stage 0 entry also resolves the path: it replaces the path's conflict stages,
+    /// and entries that would clash with it in a tree (a file `a` when adding `a/b`,
+    /// or anything under `a/` when adding the file `a`) are removed, as git does.
+    pub fn add_entry(&mut self, entry: IndexEntry) -> Result<()> {
+        check_index_path(&entry.path)?;
+        if entry.mode == EntryMode::Tree {
+            bail!("cannot stage a directory entry {:?}", entry.path);
+        }
+
+        if entry.stage() == 0 {
+            let path = entry.path.as_str();
+            self.entries.retain(|other| {
+                other.path != path && !is_inside(&other.path, path) && !is_inside(path, &other.path)
+            });
+        }
+        match self.position(&entry.path, entry.stage()) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+        Ok(())
+    }
+
+    /// Unstages every stage of `path`; returns whether anything was removed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        self.entries.len() != before
+    }
+
+    /// Stores the staged files as tree objects, one per directory, and returns the id of
+    /// the root tree; an empty index gives the empty tree.
+    ///
+    /// Note: The blobs are expected to be stored already, as `add` leaves them. Unmerged
+    /// paths can't be written, as in git.
+    pub fn write_tree(&self, odb: &Odb) -> Result<ObjectId> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage() != 0) {
+            bail!("'{}' is unmerged; resolve the conflict first", entry.path);
+        }
+        let files: Vec<(&str, &IndexEntry)> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry))
+            .collect();
+        write_subtree(odb, &files)
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| entry.index_cmp(path, stage))
+    }
+
+    /// The index file's bytes, checksum included.
+    pub fn serialize(&self) -> Vec<u8> {
+        let extended = self.entries.iter().any(|entry| entry.extended_flags != 0);
+        let version = match self.version {
+            DEFAULT_VERSION if extended => EXTENDED_VERSION,
+            version => version,
+        };
+        let mut data = Vec::new();
+        data.extend_from_slice(SIGNATURE);
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        let mut previous = "";
+        for entry in &self.entries {
+            let start = data.len();
+            for value in [
+                entry.ctime.seconds,
+                entry.ctime.nanoseconds,
+                entry.mtime.seconds,
+                entry.mtime.nanoseconds,
+                entry.dev,
+                entry.ino,
+                entry.mode.bits(),
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            data.extend_from_slice(entry.oid.as_bytes());
+            let name_len = entry.path.len().min(NAME_MASK as usize) as u16;
+            let mut flags = (entry.flags & !(FLAG_EXTENDED | NAME_MASK)) | name_len;
+            if entry.extended_flags != 0 {
+                flags |= FLAG_EXTENDED;
+            }
+            data.extend_from_slice(&flags.to_be_bytes());
+            if entry.extended_flags != 0 {
+                data.extend_from_slice(&entry.extended_flags.to_be_bytes());
+            }
+            if version == COMPRESSED_VERSION {
+                let shared = common_prefix_len(previous, &entry.path);
+                write_varint(&mut data, previous.len() - shared);
+                data.extend_from_slice(&entry.path.as_bytes()[shared..]);
+                data.push(0);
+                previous = &entry.path;
+            } else {
+                data.extend_from_slice(entry.path.as_bytes());
+                let padded = (data.len() - start + 8) & !7;
+                data.resize(start + padded, 0);
+            }
+        }
+
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    /// Parses index file bytes, checking the checksum, the header and the entry order.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 12 + CHECKSUM_LEN {
+            bail!("file is too short ({} bytes)", data.len());
+        }
+        let (body, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+        // Note: git with `index.skipHash` writes a zero checksum instead of a real one.
+        if checksum.iter().any(|&b| b != 0) && Sha1::digest(body).as_slice() != checksum {
+            bail!("checksum mismatch");
+        }
+
+        let mut reader = Reader { data: body, pos: 0 };
+        if reader.take(4)? != SIGNATURE {
+            bail!("bad signature (not an index file)");
+        }
+        let version = reader.u32()?;
+        if !(DEFAULT_VERSION..=COMPRESSED_VERSION).contains(&version) {
+            bail!("unsupported index version {}", version);
+        }
+        let count = reader.u32()?;
+
+        let mut entries: Vec<IndexEntry> = Vec::new();
+        for _ in 0..count {
+            let previous = entries.last().map(|entry| entry.path.as_str());
+            let entry = read_entry(&mut reader, version, previous)?;
+            if let Some(last) = entries.last()
+                && last.index_cmp(&entry.path, entry.stage()) != Ordering::Less
+            {
+                bail!("entries out of order at {:?}", entry.path);
+            }
+            entries.push(entry);
+        }
+
+        while reader.pos < body.len() {
+            let signature = reader.take(4)?;
+            let size = reader.u32()? as usize;
+            if !signature[0].is_ascii_uppercase() {
+                bail!(
+                    "unsupported index extension {:?}",
+                    String::from_utf8_lossy(signature)
+                );
+            }
+            reader.take(size)?;
+        }
+        Ok(Index { version, entries })
+    }
+}
+
+/// Writes the tree holding `files`, given by path below it, and every tree beneath.
+///
+/// Note: Index order keeps each directory's files together: `a/b` may come after
+/// `a.txt` but never after `a0`.
+fn write_subtree(odb: &Odb, files: &[(&str, &IndexEntry)]) -> Result<ObjectId> {
+    let mut entries = Vec::new();
+    let mut rest = files;
+    while let Some(&(path, entry)) = rest.first() {
+        let Some((dir, _)) = path.split_once('/') else {
+            entries.push(TreeEntry {
+                mode: entry.mode,
+                name: path.to_string(),
+                id: entry.oid,
+            });
+            rest = &rest[1..];
+            continue;
+        };
+        let count = rest
+            .iter()
+            .take_while(|(path, _)| is_inside(path, dir))
+            .count();
+        let children: Vec<(&str, &IndexEntry)> = rest[..count]
+            .iter()
+            .map(|(path, entry)| (&path[dir.len() + 1..], *entry))
+            .collect();
+        entries.push(TreeEntry {
+            mode: EntryMode::Tree,
+            name: dir.to_string(),
+            id: write_subtree(odb, &children)?,
+        });
+        rest = &rest[count..];
+    }
+    odb.write(ObjectKind::Tree, &Tree::new(entries)?.serialize())
+}
+
+/// The mode a working tree file described by `metadata` is staged with.
+///
+/// Note: With `core.filemode` off (`filemode`) the executable bit on disk means
+/// nothing, so a file keeps the mode of its `staged` entry.
+pub fn working_mode(metadata: &Metadata, filemode: bool, staged: Option<&IndexEntry>) -> EntryMode {
+    if metadata.file_type().is_symlink() {
+        EntryMode::Symlink
+    } else if !filemode {
+        match staged {
+            Some(entry) if entry.mode == EntryMode::Executable => EntryMode::Executable,
+            _ => EntryMode::File,
+        }
+    } else if is_executable(metadata) {
+        EntryMode::Executable
+    } else {
+        EntryMode::File
+    }
+}
+
+/// What a working tree file is staged as: its bytes, or a symlink's target.
+pub fn working_content(path: &Path, mode: EntryMode) -> Result<Vec<u8>> {
+    let content = if mode == EntryMode::Symlink {
+        fs::read_link(path).map(|target| link_text(&target))
+    } else {
+        fs::read(path)
+    };
+    content.with_context(|| format!("Failed to read {:?}", path))
+}
+
+/// Whether the working tree file of `entry` still holds what is staged; a missing
+/// file has nothing to lose.
+pub fn is_unmodified(
+    work_tree: &Path,
+    entry: &IndexEntry,
+    filemode: bool,
+    index_mtime: Option<IndexTime>,
+) -> Result<bool> {
+    let full = work_tree.join(&entry.path);
+    let metadata = match fs::symlink_metadata(&full) {
+        Ok(metadata) => metadata,
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            return Ok(true);
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", full)),
+    };
+    // Note: Submodules aren't looked into; their directory is all there is to see.
+    if entry.mode == EntryMode::Commit || metadata.is_dir() {
+        return Ok(entry.mode == EntryMode::Commit && metadata.is_dir());
+    }
+    let mode = working_mode(&metadata, filemode, Some(entry));
+    if mode != entry.mode {
+        return Ok(false);
+    }
+    if entry.stat_matches(&metadata)
+        && index_mtime.is_some_and(|index_mtime| !entry.is_racy(index_mtime))
+    {
+        return Ok(true);
+    }
+    Ok(hash_object(ObjectKind::Blob, &working_content(&full, mode)?) == entry.oid)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// When the index at `path` was last written, for `IndexEntry::is_racy`; `None` when
+/// there is no index yet.
+pub fn index_mtime(path: &Path) -> Option<IndexTime> {
+    let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+    modified.ok().map(IndexTime::from_system_time)
+}
+
+/// A bounds-checked view of the bytes being parsed.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let Some(bytes) = self.data.get(self.pos..self.pos.saturating_add(len)) else {
+            bail!("unexpected end of file at byte {}", self.pos);
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// A varint as git's index version 4 stores it: 7 bits per byte, most significant
+    /// first, each continuation adding one so no value has two encodings.
+    fn varint(&mut self) -> Result<usize> {
+        let start = self.pos;
+        let mut byte = self.take(1)?[0];
+        let mut value = (byte & 0x7f) as usize;
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = value
+                .checked_add(1)
+                .and_then(|value| value.checked_mul(0x80))
+                .with_context(|| format!("varint at byte {} is too large", start))?
+                + (byte & 0x7f) as usize;
+        }
+        Ok(value)
+    }
+
+    /// The bytes up to the next NUL, which is consumed too.
+    fn until_nul(&mut self) -> Result<&'a [u8]> {
+        let rest = &self.data[self.pos..];
+        let Some(len) = rest.iter().position(|&b| b == 0) else {
+            bail!("unexpected end of file at byte {}", self.data.len());
+        };
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+}
+
+/// Appends `value` as `Reader::varint` reads it.
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+/// How many bytes `a` and `b` start with in common, ending on a character boundary.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    while !b.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+/// Reads the entry at the reader's position from an index of `version`; `previous`
+/// is the path of the entry before it, which version 4 paths are relative to.
+fn read_entry(reader: &mut Reader, version: u32, previous: Option<&str>) -> Result<IndexEntry> {
+    let start = reader.pos;
+    let mut stat = [0; 10];
+    for value in &mut stat {
+        *value = reader.u32()?;
+    }
+    let oid = ObjectId::from_bytes(reader.take(CHECKSUM_LEN)?)?;
+    let flags = reader.u16()?;
+    let mut extended_flags = 0;
+    if flags & FLAG_EXTENDED != 0 {
+        if version < EXTENDED_VERSION {
+            bail!(
+                "entry at byte {} has extended flags in a version {} index",
+                start,
+                version
+            );
+        }
+        extended_flags = reader.u16()?;
+        if extended_flags & !(EXTENDED_SKIP_WORKTREE | EXTENDED_INTENT_TO_ADD) != 0 {
+            bail!(
+                "entry at byte {} has unknown extended flags {:#06x}",
+                start,
+                extended_flags
+            );
+        }
+    }
+
+    let path = if version == COMPRESSED_VERSION {
+        let previous = previous.unwrap_or("");
+        let drop = reader.varint()?;
+        let Some(kept) = previous.len().checked_sub(drop) else {
+            bail!(
+                "entry at byte {} drops {} bytes of a {}-byte path",
+                start,
+                drop,
+                previous.len()
+            );
+        };
+        let mut path = previous.as_bytes()[..kept].to_vec();
+        path.extend_from_slice(reader.until_nul()?);
+        String::from_utf8(path)
+            .with_context(|| format!("entry at byte {} has a non-UTF-8 path", start))?
+    } else {
+        // Paths too long for the flags are found by their terminating NUL.
+        let name_len = match flags & NAME_MASK {
+            NAME_MASK => reader.data[reader.pos..]
+                .iter()
+                .position(|&b| b == 0)
+                .with_context(|| format!("entry at byte {} has no end", start))?,
+            len => len as usize,
+        };
+        let path = String::from_utf8(reader.take(name_len)?.to_vec())
+            .with_context(|| format!("entry at byte {} has a non-UTF-8 path", start))?;
+        let fixed_len = match flags & FLAG_EXTENDED {
+            0 => ENTRY_FIXED_LEN,
+            _ => ENTRY_FIXED_LEN + 2,
+        };
+        let padded = (fixed_len + name_len + 8) & !7;
+        let padding = reader.take(start + padded - reader.pos)?;
+        if padding.iter().any(|&b| b != 0) {
+            bail!("entry {:?} is not NUL-terminated", path);
+        }
+        path
+    };
+    check_index_path(&path).with_context(|| format!("entry at byte {}", start))?;
+
+    let mode = EntryMode::from_bits(stat[6])
+        .ok()
+        .filter(|mode| *mode != EntryMode::Tree)
+        .with_context(|| format!("entry {:?} has invalid mode {:o}", path, stat[6]))?;
+    Ok(IndexEntry {
+        ctime: IndexTime {
+            seconds: stat[0],
+            nanoseconds: stat[1],
+        },
+        mtime: IndexTime {
+            seconds: stat[2],
+            nanoseconds: stat[3],
+        },
+        dev: stat[4],
+        ino: stat[5],
+        mode,
+        uid: stat[7],
+        gid: stat[8],
+        size: stat[9],
+        oid,
+        flags: flags & !(FLAG_EXTENDED | NAME_MASK),
+        extended_flags,
+        path,
+    })
+}
+
+/// Checks that `path` could be checked out: relative, `/`-separated, and with no
+/// empty, `.`, `..` or metadata directory components (ours or git's).
+fn check_index_path(path: &str) -> Result<()> {
+    let valid = !path.is_empty()
+        && !path.contains('\0')
+        && path.split('/').all(|part| {
+            !part.is_empty()
+                && part != "."
+                && part != ".."
+                && !is_repository_dir_name(part.as_ref())
+        });
+    if !valid {
+        bail!("invalid path {:?} in index", path);
+    }
+    Ok(())
+}
+
+/// Whether `path` lies under the directory `dir`.
+fn is_inside(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::hash::hash_object;
+    use tempfile::tempdir;
+
+    fn entry(path: &str, content: &str) -> IndexEntry {
+        let mut entry = IndexEntry::new(
+            path,
+            EntryMode::File,
+            hash_object(ObjectKind::Blob, content.as_bytes()),
+        );
+        entry.size = content.len() as u32;
+        entry
+    }
+
+    fn paths(index: &Index) -> Vec<(&str, u8)> {
+        index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.stage()))
+            .collect()
+    }
+
+    /// `body` followed by its checksum, as a valid index file would end.
+    fn with_checksum(body: &[u8]) -> Vec<u8> {
+        [body, Sha1::digest(body).as_slice()].concat()
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("index");
+        assert!(Index::read(&path).unwrap().is_empty());
+
+        let mut index = Index::default();
+        let mut script = entry("bin/run.sh", "#!/bin/sh\n");
+        script.mode = EntryMode::Executable;
+        script.mtime = IndexTime {
+            seconds: 1_700_000_000,
+            nanoseconds: 123_456_789,
+        };
+        script.ino = 42;
+        script.flags = FLAG_ASSUME_VALID;
+        index.add_entry(script).unwrap();
+        let mut link = entry("link", "target");
+        link.mode = EntryMode::Symlink;
+        index.add_entry(link).unwrap();
+        // Padding differs with the path length; cover every remainder mod 8
+        for len in 1..=8 {
+            index.add_entry(entry(&"p".repeat(len), "")).unwrap();
+        }
+        // Too long to fit the flags' length bits
+        index
+            .add_entry(entry(&format!("deep/{}", "x".repeat(5000)), "long"))
+            .unwrap();
+        let mut theirs = entry("conflict", "theirs");
+        theirs.flags = 3 << 12;
+        index.add_entry(theirs).unwrap();
+
+        index.write(&path).unwrap();
+        assert!(!dir.path().join("index.lock").exists());
+        let read = Index::read(&path).unwrap();
+        assert_eq!(read, index);
+        assert_eq!(read.len(), 12);
+        assert!(read.get("bin/run.sh").unwrap().assume_valid());
+        assert!(read.get("conflict").is_none());
+        assert_eq!(read.entries()[1].stage(), 3);
+
+        // The same entries, prefix-compressed
+        let compressed = Index {
+            version: COMPRESSED_VERSION,
+            ..index.clone()
+        };
+        let written = compressed.serialize();
+        assert!(written.len() < index.serialize().len());
+        assert_eq!(Index::parse(&written).unwrap(), compressed);
+
+        // Only 2, 3 and 4 can be asked for
+        assert_eq!(Index::with_version(4).unwrap().version(), 4);
+        assert!(Index::with_version(1).is_err());
+        assert!(Index::with_version(5).is_err());
+
+        // Entries are 8-byte aligned
+        for len in 1..=8 {
+            let mut single = Index::default();
+            single.add_entry(entry(&"p".repeat(len), "")).unwrap();
+            let entry_len = single.serialize().len() - 12 - CHECKSUM_LEN;
+            assert_eq!(entry_len % 8, 0);
+            assert!(entry_len > ENTRY_FIXED_LEN + len);
+        }
+
+        // Writing is refused while someone else holds the lock
+        let lock = Lockfile::acquire(&path).unwrap();
+        assert!(Index::default().write(&path).is_err());
+        drop(lock);
+        assert_eq!(Index::read(&path).unwrap(), index);
+    }
+
+    #[test]
+    fn test_index_add_remove_get() {
+        let mut index = Index::default();
+        for path in ["b", "a/z", "a-b", "a/b", "c"] {
+            index.add_entry(entry(path, path)).unwrap();
+        }
+        // Sorted by bytes: '-' < '/'
+        assert_eq!(
+            paths(&index),
+            [("a-b", 0), ("a/b", 0), ("a/z", 0), ("b", 0), ("c", 0)]
+        );
+
+        index.add_entry(entry("b", "changed")).unwrap();
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.get("b").unwrap().size, 7);
+        assert!(index.get("a").is_none());
+
+        // A file replaces the directory of the same name, and the other way round
+        index.add_entry(entry("a", "file")).unwrap();
+        assert_eq!(paths(&index), [("a", 0), ("a-b", 0), ("b", 0), ("c", 0)]);
+        index.add_entry(entry("b/c/d", "nested")).unwrap();
+        assert_eq!(
+            paths(&index),
+            [("a", 0), ("a-b", 0), ("b/c/d", 0), ("c", 0)]
+        );
+
+        // Conflict stages sit side by side, and stage 0 resolves them
+        for stage in [3, 1, 2] {
+            let mut conflicted = entry("c", &format!("stage {}", stage));
+            conflicted.flags = (stage as u16) << 12;
+            index.add_entry(conflicted).unwrap();
+        }
+        assert_eq!(paths(&index)[3..], [("c", 0), ("c", 1), ("c", 2), ("c", 3)]);
+        index.add_entry(entry("c", "resolved")).unwrap();
+        assert_eq!(paths(&index)[3..], [("c", 0)]);
+
+        assert!(index.remove("a-b"));
+        assert!(!index.remove("a-b"));
+        assert!(!index.remove("b"));
+        assert_eq!(paths(&index), [("a", 0), ("b/c/d", 0), ("c", 0)]);
+
+        for bad in [
+            "",
+            "/a",
+            "a/",
+            "a//b",
+            "./a",
+            "a/../b",
+            ".CS01/config",
+            "x/.cs01",
+            ".git/config",
+            "sub/.GIT/HEAD",
+        ] {
+            assert!(index.add_entry(entry(bad, "")).is_err(), "{:?}", bad);
+        }
+        let tree = IndexEntry::new("dir", EntryMode::Tree, hash_object(ObjectKind::Tree, b""));
+        assert!(index.add_entry(tree).is_err());
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn test_index_write_tree() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+        let mut index = Index::default();
+        assert_eq!(
+            index.write_tree(&odb).unwrap().to_hex(),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+
+        // `a.txt` sorts between the files of `a` and those of `a-b`, `a0` after them
+        for path in ["a-b/x", "a.txt", "a/b/c", "a/d", "a0"] {
+            index.add_entry(entry(path, "x\n")).unwrap();
+        }
+        let root = index.write_tree(&odb).unwrap();
+        let files = Tree::flatten(&odb, &root).unwrap();
+        let paths: Vec<&str> = files.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["a-b/x", "a.txt", "a/b/c", "a/d", "a0"]);
+
+        // Conflicts must be resolved first
+        let mut theirs = entry("a/d", "theirs\n");
+        theirs.flags = 3 << 12;
+        index.add_entry(theirs).unwrap();
+        let err = index.write_tree(&odb).unwrap_err();
+        assert!(err.to_string().contains("'a/d' is unmerged"), "{}", err);
+    }
+
+    #[test]
+    fn test_index_reads_git_index() {
+        // Written by `git add` and `git commit` (so it has a TREE extension), using
+        // `index.version = 2`
+        let fixture_path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/git_index_v2");
+        let fixture = fs::read(&fixture_path).unwrap();
+        let index = Index::read(&fixture_path).unwrap();
+
+        // As `git ls-files -s --debug` lists it
+        let expected = [
+            (
+                EntryMode::File,
+                "ce013625030ba8dba906f756967f9e9ca394464a",
+                "README.md",
+                6,
+            ),
+            (
+                EntryMode::File,
+                "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391",
+                "empty",
+                0,
+            ),
+            (
+                EntryMode::Symlink,
+                "42061c01a1c70097d1e4579f29a5adf40abdec95",
+                "link",
+                9,
+            ),
+            (
+                EntryMode::Executable,
+                "4163036efa65bd4a469e752267498f01ea36a55c",
+                "run.sh",
+                18,
+            ),
+            (
+                EntryMode::File,
+                "7a788f2457bfeefa9ed2754cb8f08f7bc761ae34",
+                "src/lib/mod.rs",
+                11,
+            ),
+            (
+                EntryMode::File,
+                "f328e4d9d04c31d0d70d16d21a07d1613be9d577",
+                "src/main.rs",
+                13,
+            ),
+        ];
+        assert_eq!(index.len(), expected.len());
+        for (entry, (mode, oid, path, size)) in index.entries().iter().zip(expected) {
+            assert_eq!(entry.mode, mode);
+            assert_eq!(entry.oid.to_hex(), oid);
+            assert_eq!(entry.path, path);
+            assert_eq!(entry.size, size);
+            assert_eq!(entry.stage(), 0);
+        }
+        let readme = index.get("README.md").unwrap();
+        assert_eq!(readme.mtime.seconds, 1792165560);
+        assert_eq!(readme.mtime.nanoseconds, 548667504);
+        assert_eq!(readme.ino, 1228894);
+
+        // Written back byte for byte, up to the extension we drop
+        let written = index.serialize();
+        assert!(fixture.starts_with(&written[..written.len() - CHECKSUM_LEN]));
+        assert_eq!(Index::parse(&written).unwrap(), index);
+    }
+
+    #[test]
+    fn test_index_reads_git_index_v3_and_v4() {
+        // Written by `git add`, with `git add -N notes.txt` (intent-to-add) and
+        // `git update-index --skip-worktree src/lib/mod.rs`, then
+        // `git update-index --index-version 4` (and 3)
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let expected = [
+            ("README.md", "ce013625030ba8dba906f756967f9e9ca394464a", 0),
+            (
+                "notes.txt",
+                "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391",
+                EXTENDED_INTENT_TO_ADD,
+            ),
+            (
+                "src/lib/mod.rs",
+                "d9ba5e9063abe5a7e6c827a6966808fc031ac11d",
+                EXTENDED_SKIP_WORKTREE,
+            ),
+            ("src/main.rs", "f328e4d9d04c31d0d70d16d21a07d1613be9d577", 0),
+        ];
+        let mut read = Vec::new();
+        for name in ["git_index_v3", "git_index_v4"] {
+            let fixture = fs::read(fixtures.join(name)).unwrap();
+            let index = Index::parse(&fixture).unwrap();
+            let found: Vec<_> = index
+                .entries()
+                .iter()
+                .map(|entry| {
+                    let oid = entry.oid.to_hex();
+                    (entry.path.as_str(), oid, entry.extended_flags)
+                })
+                .collect();
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(path, oid, flags)| (*path, oid.to_string(), *flags))
+                .collect();
+            assert_eq!(found, expected, "{}", name);
+            assert_eq!(index.get("README.md").unwrap().size, 6);
+
+            // Written back byte for byte, in the version it was read in
+            assert_eq!(index.serialize(), fixture, "{}", name);
+            read.push(index.entries().to_vec());
+        }
+        assert_eq!(read[0], read[1]);
+
+        // A version 2 index that gains extended flags becomes version 3
+        let mut index = Index::default();
+        let mut skipped = entry("a", "a");
+        skipped.extended_flags = EXTENDED_SKIP_WORKTREE;
+        index.add_entry(skipped).unwrap();
+        let written = index.serialize();
+        assert_eq!(written[4..8], EXTENDED_VERSION.to_be_bytes());
+        let parsed = Index::parse(&written).unwrap();
+        assert_eq!(parsed.entries(), index.entries());
+    }
+
+    #[test]
+    fn test_index_v4_is_smaller_on_a_large_tree() {
+        // 50,000 paths shaped like a large source tree: deep, with long shared prefixes
+        let mut paths: Vec<String> = (0..50_000)
+            .map(|i| {
+                format!(
+                    "src/components/module_{:03}/submodule_{:02}/file_{:05}.rs",
+                    i / 500,
+                    i / 50 % 10,
+                    i
+                )
+            })
+            .collect();
+        paths.sort();
+        let template = entry("x", "");
+        let v2 = Index {
+            version: DEFAULT_VERSION,
+            entries: paths
+                .into_iter()
+                .map(|path| IndexEntry {
+                    path,
+                    ..template.clone()
+                })
+                .collect(),
+        };
+        let v4 = Index {
+            version: COMPRESSED_VERSION,
+            ..v2.clone()
+        };
+
+        let (v2_data, v4_data) = (v2.serialize(), v4.serialize());
+        assert_eq!(Index::parse(&v4_data).unwrap(), v4);
+        // Each v2 entry spends 59 bytes on its path and padding; v4 needs only the
+        // few bytes that differ from the path before it
+        assert!(
+            v4_data.len() * 10 < v2_data.len() * 7,
+            "v2 {} bytes, v4 {} bytes",
+            v2_data.len(),
+            v4_data.len()
+        );
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 0x407f, 0x4080, 1 << 40] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            let mut reader = Reader {
+                data: &data,
+                pos: 0,
+            };
+            assert_eq!(reader.varint().unwrap(), value);
+            assert_eq!(reader.pos, data.len());
+        }
+        // git's encoding: 0x80 is 0x80 0x00, not 0x81 0x00
+        let mut data = Vec::new();
+        write_varint(&mut data, 0x80);
+        assert_eq!(data, [0x80, 0x00]);
+    }
+
+    #[test]
+    fn test_index_rejects_corrupt_files() {
+        let mut index = Index::default();
+        index.add_entry(entry("a", "a")).unwrap();
+        index.add_entry(entry("b", "b")).unwrap();
+        let valid = index.serialize();
+        let body = &valid[..valid.len() - CHECKSUM_LEN];
+        let first_entry = 12;
+        let second_entry = first_entry + 64;
+
+        let mut flipped = valid.clone();
+        flipped[20] ^= 1;
+        let mut bad_signature = body.to_vec();
+        bad_signature[..4].copy_from_slice(b"DIRX");
+        let mut version_5 = body.to_vec();
+        version_5[7] = 5;
+        let mut too_many = body.to_vec();
+        too_many[11] = 3;
+        let mut unsorted = body.to_vec();
+        unsorted[second_entry + ENTRY_FIXED_LEN] = b'0';
+        let mut bad_mode = body.to_vec();
+        bad_mode[first_entry + 24..first_entry + 28].copy_from_slice(&0o100664u32.to_be_bytes());
+        let mut extended = body.to_vec();
+        extended[first_entry + 60] |= 0x40;
+        let mut bad_path = body.to_vec();
+        bad_path[first_entry + ENTRY_FIXED_LEN] = b'.';
+        let mut optional_extension = body.to_vec();
+        optional_extension.extend_from_slice(b"ZZZZ\0\0\0\x02ok");
+        let mut required_extension = body.to_vec();
+        required_extension.extend_from_slice(b"link\0\0\0\0");
+        let mut short_extension = body.to_vec();
+        short_extension.extend_from_slice(b"ZZZZ\0\0\0\x09ok");
+
+        let cases: [(Vec<u8>, &str); 11] = [
+            (valid[..30].to_vec(), "too short"),
+            (flipped, "checksum mismatch"),
+            (with_checksum(&bad_signature), "bad signature"),
+            (with_checksum(&version_5), "unsupported index version 5"),
+            (with_checksum(&too_many), "unexpected end of file"),
+            (with_checksum(&unsorted), "out of order"),
+            (with_checksum(&bad_mode), "invalid mode 100664"),
+            (
+                with_checksum(&extended),
+                "extended flags in a version 2 index",
+            ),
+            (with_checksum(&bad_path), "invalid path \".\""),
+            (
+                with_checksum(&required_extension),
+                "unsupported index extension \"link\"",
+            ),
+            (with_checksum(&short_extension), "unexpected end of file"),
+        ];
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("index");
+        for (data, expected) in cases {
+            fs::write(&path, &data).unwrap();
+            let err = format!("{:#}", Index::read(&path).unwrap_err());
+            assert!(err.contains("corrupt index"), "{}", err);
+            assert!(err.contains(expected), "{}", err);
+        }
+
+        // Unknown optional extensions are skipped, and so is a zero checksum
+        let with_extension = Index::parse(&with_checksum(&optional_extension)).unwrap();
+        assert_eq!(with_extension, index);
+        let zero_checksum = [body, &[0; CHECKSUM_LEN]].concat();
+        assert_eq!(Index::parse(&zero_checksum).unwrap(), index);
+    }
+}
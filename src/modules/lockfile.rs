@@ -0,0 +1,183 @@
+use anyhow::{Context, Result, bail};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Exclusive write access to a file, following git's lockfile protocol.
+///
+/// Acquiring creates `<file>.lock` with `O_EXCL`, so only one writer can hold it.
+/// New content is written to the lock file and `commit` renames it over the original,
+/// so readers see either the old or the new file, never a partial one.
+/// Dropping an uncommitted lock deletes the lock file and leaves the original untouched.
+///
+/// Note: A writer that read the file before acquiring the lock may still lose an update;
+/// read-modify-write cycles must read after `acquire`.
+#[derive(Debug)]
+pub struct Lockfile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+    committed: bool,
+}
+
+impl Lockfile {
+    /// Takes the lock for `path` by creating `<path>.lock`; fails if it already exists.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut lock_name = path
+            .file_name()
+            .with_context(|| format!("Cannot lock {:?}", path))?
+            .to_os_string();
+        lock_name.push(".lock");
+        let lock_path = path.with_file_name(lock_name);
+
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => bail!(
+                "Unable to create '{}': File exists.\n\n\
+                 Another cs01 process seems to be running. If not, a cs01 process may have \
+                 crashed earlier; remove '{}' manually and try again.",
+                lock_path.display(),
+                lock_path.display()
+            ),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {:?}", lock_path));
+            }
+        };
+
+        Ok(Lockfile {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+            committed: false,
+        })
+    }
+
+    /// The `<file>.lock` path held by this lock.
+    pub fn lock_path(&self) -> &Path {
+        &self.lock_path
+    }
+
+    /// Appends `content` to the pending new version of the file.
+    pub fn write(&mut self, content: &[u8]) -> Result<()> {
+        let file = self.file.as_mut().context("lock already committed")?;
+        file.write_all(content)
+            .with_context(|| format!("Failed to write {:?}", self.lock_path))
+    }
+
+    /// Replaces the file with what was written and releases the lock.
+    ///
+    /// Note: An existing file keeps its permission bits, so e.g. a shared
+    /// repository's group-writable config stays group-writable.
+    pub fn commit(mut self) -> Result<()> {
+        // Synced so a crash after the rename can't leave a truncated file in place,
+        // and closed before the rename, which Windows requires.
+        let file = self.file.take().context("lock already committed")?;
+        file.sync_all()
+            .with_context(|| format!("Failed to sync {:?}", self.lock_path))?;
+        drop(file);
+
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            fs::set_permissions(&self.lock_path, metadata.permissions())
+                .with_context(|| format!("Failed to set permissions on {:?}", self.lock_path))?;
+        }
+
+        fs::rename(&self.lock_path, &self.path).with_context(|| {
+            format!(
+                "Failed to move {:?} into place at {:?}",
+                self.lock_path, self.path
+            )
+        })?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Lockfile {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.file = None;
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lockfile_commit_and_drop() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "old\n").unwrap();
+
+        let mut lock = Lockfile::acquire(&path).unwrap();
+        assert!(lock.lock_path().exists());
+        // A second writer is turned away and told which file to remove
+        let err = Lockfile::acquire(&path).unwrap_err().to_string();
+        assert!(
+            err.contains(&lock.lock_path().display().to_string()),
+            "{}",
+            err
+        );
+
+        lock.write(b"new\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        lock.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(!dir.path().join("config.lock").exists());
+
+        // Dropping without commit leaves the original alone
+        let mut lock = Lockfile::acquire(&path).unwrap();
+        lock.write(b"abandoned\n").unwrap();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(!dir.path().join("config.lock").exists());
+    }
+
+    #[test]
+    fn test_lockfile_concurrent_writers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "initial\n").unwrap();
+
+        let outcomes: Vec<String> = ["a", "b"]
+            .iter()
+            .map(|name| format!("{}\n", name.repeat(64 * 1024)))
+            .collect();
+
+        std::thread::scope(|scope| {
+            for content in &outcomes {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        // Each writer retries until it gets its turn.
+                        let mut lock = loop {
+                            if let Ok(lock) = Lockfile::acquire(path) {
+                                break lock;
+                            }
+                            std::thread::yield_now();
+                        };
+                        // Written in pieces, so an unlocked writer would interleave.
+                        for chunk in content.as_bytes().chunks(4096) {
+                            lock.write(chunk).unwrap();
+                        }
+                        lock.commit().unwrap();
+                    }
+                });
+            }
+        });
+
+        let result = fs::read_to_string(&path).unwrap();
+        assert!(
+            outcomes.contains(&result),
+            "file holds a mix of both writers"
+        );
+        assert!(!dir.path().join("config.lock").exists());
+    }
+}
@@ -0,0 +1,525 @@
+use anyhow::{Result, bail};
+use std::collections::BTreeSet;
+
+use crate::modules::{
+    checkout::Files,
+    diff::{Edit, diff_lines_within, is_binary, split_lines},
+    hash::{ObjectId, ObjectKind},
+    objects::tree::EntryMode,
+    odb::Odb,
+    output::Output,
+};
+
+/// How a path is recorded on one side of a merge.
+pub type Version = (EntryMode, ObjectId);
+
+/// The result of merging two changed versions of a text against their base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedText {
+    pub data: Vec<u8>,
+    /// How many conflict blocks `data` holds.
+    pub conflicts: usize,
+    /// Whether a side changed too many lines to merge line by line, so the sides were
+    /// merged as a whole: one conflict block unless only one side changed.
+    pub too_large: bool,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line, as diff3 does.
+///
+/// Stretches only one side changed take that side's lines; where both changed the same
+/// stretch differently, both versions go in between `<<<<<<< ours`, `=======` and
+/// `>>>>>>> theirs` markers, named by `labels`. Lines both sides agree on at the start
+/// or end of such a stretch are kept outside the markers, as git does. A side that
+/// adds and removes more than `max_changes` lines makes the whole file one stretch.
+pub fn merge_text(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: (&str, &str),
+    max_changes: usize,
+) -> MergedText {
+    let (base, ours, theirs) = (split_lines(base), split_lines(ours), split_lines(theirs));
+    let mut merged = MergedText {
+        data: Vec::new(),
+        conflicts: 0,
+        too_large: false,
+    };
+    let (Some(ours_at), Some(theirs_at)) = (
+        matching_lines(&base, &ours, max_changes),
+        matching_lines(&base, &theirs, max_changes),
+    ) else {
+        merged.too_large = true;
+        merge_chunk(&mut merged, &base, &ours, &theirs, labels);
+        return merged;
+    };
+
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // A base line both sides kept where they are now.
+        if i < base.len() && ours_at[i] == Some(j) && theirs_at[i] == Some(k) {
+            merged.data.extend_from_slice(base[i]);
+            (i, j, k) = (i + 1, j + 1, k + 1);
+            continue;
+        }
+        // Otherwise everything up to the next line both kept changed on some side.
+        let next = (i..base.len()).find_map(|o| Some((o, ours_at[o]?, theirs_at[o]?)));
+        let (o, next_j, next_k) = next.unwrap_or((base.len(), ours.len(), theirs.len()));
+        if (o, next_j, next_k) == (i, j, k) {
+            break;
+        }
+        merge_chunk(
+            &mut merged,
+            &base[i..o],
+            &ours[j..next_j],
+            &theirs[k..next_k],
+            labels,
+        );
+        (i, j, k) = (o, next_j, next_k);
+    }
+    merged
+}
+
+/// For each line of `base`, the line of `side` it was kept as, if any; `None` if `side`
+/// adds and removes more than `max_changes` lines.
+fn matching_lines(
+    base: &[&[u8]],
+    side: &[&[u8]],
+    max_changes: usize,
+) -> Option<Vec<Option<usize>>> {
+    let mut matching = vec![None; base.len()];
+    for edit in diff_lines_within(base, side, max_changes)? {
+        if let Edit::Equal { old, new } = edit {
+            matching[old] = Some(new);
+        }
+    }
+    Some(matching)
+}
+
+/// Adds the merge of a stretch that changed on one side or both to `merged`.
+fn merge_chunk(
+    merged: &mut MergedText,
+    base: &[&[u8]],
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    labels: (&str, &str),
+) {
+    let taken = if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    };
+    if let Some(lines) = taken {
+        lines
+            .iter()
+            .for_each(|line| merged.data.extend_from_slice(line));
+        return;
+    }
+
+    let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+    let suffix = ours[prefix..]
+        .iter()
+        .rev()
+        .zip(theirs[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let data = &mut merged.data;
+    ours[..prefix]
+        .iter()
+        .for_each(|line| data.extend_from_slice(line));
+    data.extend_from_slice(format!("<<<<<<< {}\n", labels.0).as_bytes());
+    push_lines(data, &ours[prefix..ours.len() - suffix]);
+    data.extend_from_slice(b"=======\n");
+    push_lines(data, &theirs[prefix..theirs.len() - suffix]);
+    data.extend_from_slice(format!(">>>>>>> {}\n", labels.1).as_bytes());
+    ours[ours.len() - suffix..]
+        .iter()
+        .for_each(|line| data.extend_from_slice(line));
+    merged.conflicts += 1;
+}
+
+/// Adds `lines` to `data`, ending the last one with a newline so a marker can follow.
+fn push_lines(data: &mut Vec<u8>, lines: &[&[u8]]) {
+    lines.iter().for_each(|line| data.extend_from_slice(line));
+    if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        data.push(b'\n');
+    }
+}
+
+/// Why a path couldn't be merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides changed the file in ways that clash.
+    Content,
+    /// Both sides added different files at the same path.
+    AddAdd,
+    /// One side deleted the file and the other changed it; `deleted_by_us` says which.
+    ModifyDelete { deleted_by_us: bool },
+}
+
+/// A path a merge left for the user to resolve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: String,
+    pub kind: ConflictKind,
+    /// The common ancestor's version, and ours and theirs: index stages 1, 2 and 3.
+    pub base: Option<Version>,
+    pub ours: Option<Version>,
+    pub theirs: Option<Version>,
+}
+
+/// The result of merging two trees against their base.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeMerge {
+    /// The merged files. A conflicted path holds what goes in the working tree: the file
+    /// with conflict markers, or the version that one side changed and the other deleted.
+    pub files: Files,
+    pub conflicts: Vec<Conflict>,
+    /// The paths whose content was merged line by line, sorted.
+    pub auto_merged: Vec<String>,
+}
+
+/// Merges the files of `ours` and `theirs` against those of their common ancestor,
+/// `base`, path by path.
+///
+/// A path only one side changed takes that side's version. Files both sides changed
+/// are merged with `merge_text` (an added file's base is empty), and the merged blob,
+/// markers and all, is stored in `odb`. Binary files, symlinks and submodules changed
+/// on both sides, and files one side changed and the other deleted, are conflicts
+/// that keep our version, or the one still there. A file a side changed in more than
+/// `max_changes` lines is merged as a whole, with a warning.
+///
+/// Note: Renames aren't detected, and a file on one side where the other has a
+/// directory stops the merge.
+pub fn merge_trees(
+    odb: &Odb,
+    base: &Files,
+    ours: &Files,
+    theirs: &Files,
+    labels: (&str, &str),
+    max_changes: usize,
+) -> Result<TreeMerge> {
+    let mut merge = TreeMerge::default();
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    for path in paths {
+        let (old, our, their) = (
+            base.get(path).copied(),
+            ours.get(path).copied(),
+            theirs.get(path).copied(),
+        );
+        let resolved = if our == their || their == old {
+            our
+        } else if our == old {
+            their
+        } else {
+            merge_path(
+                odb,
+                &mut merge,
+                path,
+                (old, our, their),
+                labels,
+                max_changes,
+            )?
+        };
+        if let Some(version) = resolved {
+            merge.files.insert(path.clone(), version);
+        }
+    }
+
+    for path in merge.files.keys() {
+        for (i, _) in path.match_indices('/') {
+            if merge.files.contains_key(&path[..i]) {
+                bail!(
+                    "cannot merge '{}': it is a file on one side and a directory on the other",
+                    &path[..i]
+                );
+            }
+        }
+    }
+    Ok(merge)
+}
+
+/// Merges a path both sides changed, recording a conflict in `merge` if they clash, and
+/// returns the version to keep.
+fn merge_path(
+    odb: &Odb,
+    merge: &mut TreeMerge,
+    path: &str,
+    (base, ours, theirs): (Option<Version>, Option<Version>, Option<Version>),
+    labels: (&str, &str),
+    max_changes: usize,
+) -> Result<Option<Version>> {
+    let mut conflict = |kind| {
+        merge.conflicts.push(Conflict {
+            path: path.to_string(),
+            kind,
+            base,
+            ours,
+            theirs,
+        })
+    };
+    let (Some(our), Some(their)) = (ours, theirs) else {
+        conflict(ConflictKind::ModifyDelete {
+            deleted_by_us: ours.is_none(),
+        });
+        return Ok(ours.or(theirs));
+    };
+    let kind = match base {
+        Some(_) => ConflictKind::Content,
+        None => ConflictKind::AddAdd,
+    };
+
+    let is_file = |mode| matches!(mode, EntryMode::File | EntryMode::Executable);
+    let base_mode = base.map(|(mode, _)| mode);
+    let mode = if our.0 == their.0 || base_mode == Some(their.0) {
+        Some(our.0)
+    } else if base_mode == Some(our.0) {
+        Some(their.0)
+    } else {
+        None
+    };
+    if !is_file(our.0) || !is_file(their.0) {
+        conflict(kind);
+        return Ok(Some(our));
+    }
+
+    let read = |version: Option<Version>| -> Result<Vec<u8>> {
+        match version {
+            Some((_, oid)) => Ok(odb.read(&oid)?.1),
+            None => Ok(Vec::new()),
+        }
+    };
+    let (old, new_ours, new_theirs) = (read(base)?, read(Some(our))?, read(Some(their))?);
+    if [&old, &new_ours, &new_theirs]
+        .iter()
+        .any(|data| is_binary(data))
+    {
+        conflict(kind);
+        return Ok(Some(our));
+    }
+    merge.auto_merged.push(path.to_string());
+    let merged = merge_text(&old, &new_ours, &new_theirs, labels, max_changes);
+    if merged.too_large {
+        Output::default().warn(&format!(
+            "warning: too many changed lines to merge {} line by line; raise merge.maxChangedLines to merge it that way",
+            path
+        ));
+    }
+    let oid = odb.write(ObjectKind::Blob, &merged.data)?;
+    if merged.conflicts > 0 || mode.is_none() {
+        conflict(kind);
+    }
+    Ok(Some((mode.unwrap_or(our.0), oid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::diff::MAX_CHANGED_LINES;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let merged = merge_text(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            ("HEAD", "topic"),
+            MAX_CHANGED_LINES,
+        );
+        (String::from_utf8(merged.data).unwrap(), merged.conflicts)
+    }
+
+    #[test]
+    fn test_merge_text() {
+        let base = "a\nb\nc\nd\ne\n";
+        // Changes to different lines both go in
+        assert_eq!(
+            merge(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n"),
+            ("A\nb\nc\nd\nE\n".to_string(), 0)
+        );
+        // Insertions and deletions, and the same change made on both sides
+        assert_eq!(
+            merge(base, "a\nnew\nb\nc\nd\n", "a\nb\nd\ne\n"),
+            ("a\nnew\nb\nd\n".to_string(), 0)
+        );
+        assert_eq!(
+            merge(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n"),
+            ("a\nB\nc\nd\ne\n".to_string(), 0)
+        );
+
+        // Clashing changes, with the lines both agree on outside the markers
+        assert_eq!(
+            merge(base, "a\nx\nsame\nc\nd\ne\n", "a\ny\nsame\nc\nd\ne\n"),
+            (
+                "a\n<<<<<<< HEAD\nx\n=======\ny\n>>>>>>> topic\nsame\nc\nd\ne\n".to_string(),
+                1
+            )
+        );
+        // A missing final newline doesn't run into a marker
+        assert_eq!(
+            merge("a\nb", "a\nours", "a\ntheirs"),
+            (
+                "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\n".to_string(),
+                1
+            )
+        );
+        // Files added on both sides merge against an empty base
+        assert_eq!(
+            merge("", "one\n", "two\n"),
+            (
+                "<<<<<<< HEAD\none\n=======\ntwo\n>>>>>>> topic\n".to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn test_merge_text_past_the_limit() {
+        // Changes to different lines, but too many to merge them line by line
+        let base = "a\nb\nc\nd\ne\n";
+        let merged = merge_text(
+            base.as_bytes(),
+            b"A\nb\nc\nd\ne\n",
+            b"a\nb\nc\nd\nE\n",
+            ("HEAD", "topic"),
+            1,
+        );
+        assert!(merged.too_large);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            String::from_utf8(merged.data).unwrap(),
+            "<<<<<<< HEAD\nA\nb\nc\nd\ne\n=======\na\nb\nc\nd\nE\n>>>>>>> topic\n"
+        );
+
+        // A side that didn't change still gives way
+        let merged = merge_text(
+            base.as_bytes(),
+            base.as_bytes(),
+            b"x\n",
+            ("HEAD", "topic"),
+            1,
+        );
+        assert_eq!((merged.data, merged.conflicts), (b"x\n".to_vec(), 0));
+
+        // Huge, entirely rewritten files finish quickly as one conflict
+        let lines = |tag: &str| -> Vec<u8> {
+            (0..200_000)
+                .flat_map(|i| format!("{} {}\n", tag, i).into_bytes())
+                .collect()
+        };
+        let started = std::time::Instant::now();
+        let merged = merge_text(
+            &lines("base"),
+            &lines("ours"),
+            &lines("theirs"),
+            ("HEAD", "topic"),
+            MAX_CHANGED_LINES,
+        );
+        assert!(merged.too_large);
+        assert_eq!(merged.conflicts, 1);
+        assert!(started.elapsed() < std::time::Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_merge_trees() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+        let blob = |content: &str| odb.write(ObjectKind::Blob, content.as_bytes()).unwrap();
+        let files = |list: &[(&str, EntryMode, ObjectId)]| -> Files {
+            list.iter()
+                .map(|(path, mode, oid)| (path.to_string(), (*mode, *oid)))
+                .collect()
+        };
+        let (file, exe) = (EntryMode::File, EntryMode::Executable);
+
+        let base = files(&[
+            ("kept.txt", file, blob("kept\n")),
+            ("lines.txt", file, blob("1\n2\n3\n")),
+            ("gone.txt", file, blob("gone\n")),
+            ("script", file, blob("run\n")),
+        ]);
+        let ours = files(&[
+            ("kept.txt", file, blob("kept\n")),
+            ("lines.txt", file, blob("one\n2\n3\n")),
+            ("gone.txt", file, blob("changed\n")),
+            ("script", exe, blob("run\n")),
+            ("new.txt", file, blob("ours\n")),
+        ]);
+        let theirs = files(&[
+            ("kept.txt", file, blob("kept\n")),
+            ("lines.txt", file, blob("1\n2\nthree\n")),
+            ("script", file, blob("run fast\n")),
+            ("new.txt", file, blob("theirs\n")),
+            ("added.txt", file, blob("added\n")),
+        ]);
+        let merge = merge_trees(
+            &odb,
+            &base,
+            &ours,
+            &theirs,
+            ("HEAD", "topic"),
+            MAX_CHANGED_LINES,
+        )
+        .unwrap();
+
+        assert_eq!(
+            merge.files,
+            files(&[
+                ("added.txt", file, blob("added\n")),
+                ("gone.txt", file, blob("changed\n")),
+                ("kept.txt", file, blob("kept\n")),
+                ("lines.txt", file, blob("one\n2\nthree\n")),
+                (
+                    "new.txt",
+                    file,
+                    blob("<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\n")
+                ),
+                ("script", exe, blob("run fast\n")),
+            ])
+        );
+        assert_eq!(merge.auto_merged, ["lines.txt", "new.txt", "script"]);
+        let conflicts: Vec<(&str, ConflictKind)> = merge
+            .conflicts
+            .iter()
+            .map(|conflict| (conflict.path.as_str(), conflict.kind))
+            .collect();
+        assert_eq!(
+            conflicts,
+            [
+                (
+                    "gone.txt",
+                    ConflictKind::ModifyDelete {
+                        deleted_by_us: false
+                    }
+                ),
+                ("new.txt", ConflictKind::AddAdd),
+            ]
+        );
+        assert_eq!(merge.conflicts[1].base, None);
+        assert_eq!(merge.conflicts[1].ours, ours.get("new.txt").copied());
+        assert_eq!(merge.conflicts[1].theirs, theirs.get("new.txt").copied());
+
+        // A file changed on one side, where the other has put a directory
+        let base = files(&[("path", file, blob("file\n"))]);
+        let ours = files(&[("path", file, blob("changed\n"))]);
+        let theirs = files(&[("path/inside", file, blob("file\n"))]);
+        assert!(
+            merge_trees(
+                &odb,
+                &base,
+                &ours,
+                &theirs,
+                ("HEAD", "topic"),
+                MAX_CHANGED_LINES,
+            )
+            .is_err()
+        );
+    }
+}
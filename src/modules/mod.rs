@@ -1,3 +1,23 @@
+pub mod checkout;
+pub mod color;
 pub mod config;
+pub mod constants;
+pub mod diff;
+pub mod editor;
 pub mod files;
+pub mod hash;
+pub mod hooks;
+pub mod ignore;
+pub mod index;
+pub mod lockfile;
+pub mod merge;
+pub mod objects;
+pub mod odb;
+pub mod output;
+pub mod perms;
+pub mod refs;
 pub mod repo_structure;
+pub mod revision;
+pub mod revwalk;
+pub mod tree;
+pub mod vfs;
@@ -0,0 +1,418 @@
+use anyhow::{Context, Result, bail};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::modules::{
+    config::Identity,
+    hash::{ObjectId, ObjectKind},
+    odb::Odb,
+};
+
+/// When something happened, as git records it: seconds since the Unix epoch, plus the
+/// UTC offset of whoever did it, so the local time can be shown as they saw it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub seconds: i64,
+    /// Minutes east of UTC, e.g. 330 for `+0530`.
+    pub offset_minutes: i32,
+}
+
+impl Timestamp {
+    /// The current time.
+    ///
+    /// Note: Finding the local UTC offset needs a timezone database, so this is recorded
+    /// as UTC (`+0000`); `CS01_AUTHOR_DATE` and `CS01_COMMITTER_DATE` can give another.
+    pub fn now() -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        Timestamp {
+            seconds,
+            offset_minutes: 0,
+        }
+    }
+
+    /// Parses git's raw date format, `<seconds> <+hhmm|-hhmm>`.
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = || format!("invalid date {:?} (expected e.g. 1700000000 +0530)", text);
+        let (seconds, offset) = text.trim().split_once(' ').with_context(invalid)?;
+        let seconds = seconds.parse().ok().with_context(invalid)?;
+
+        let (sign, digits) = match offset.as_bytes().first() {
+            Some(b'+') => (1, &offset[1..]),
+            Some(b'-') => (-1, &offset[1..]),
+            _ => bail!(invalid()),
+        };
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            bail!(invalid());
+        }
+        let hours: i32 = digits[..2].parse()?;
+        let minutes: i32 = digits[2..].parse()?;
+        if minutes >= 60 {
+            bail!(invalid());
+        }
+        Ok(Timestamp {
+            seconds,
+            offset_minutes: sign * (hours * 60 + minutes),
+        })
+    }
+
+    /// The UTC offset as git writes it, e.g. `+0530`.
+    pub fn offset(&self) -> String {
+        let sign = if self.offset_minutes < 0 { '-' } else { '+' };
+        let offset = self.offset_minutes.unsigned_abs();
+        format!("{}{:02}{:02}", sign, offset / 60, offset % 60)
+    }
+
+    /// The time as `log` shows it, in git's default format and the recorded offset,
+    /// e.g. `Wed Nov 15 03:43:20 2023 +0530`.
+    pub fn to_log_date(&self) -> String {
+        const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+
+        let local = self.seconds + i64::from(self.offset_minutes) * 60;
+        let days = local.div_euclid(86_400);
+        let time = local.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            WEEKDAYS[days.rem_euclid(7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60,
+            year,
+            self.offset()
+        )
+    }
+}
+
+/// The proleptic Gregorian date `days` after 1970-01-01, as (year, month, day).
+///
+/// Note: Howard Hinnant's `civil_from_days`, which counts in 400-year eras starting
+/// on March 1st so leap days fall at the end of each year.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.seconds, self.offset())
+    }
+}
+
+/// A commit: a snapshot (`tree`), the commits it follows, who made it and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: ObjectId,
+    /// None for a root commit, two or more for a merge.
+    pub parents: Vec<ObjectId>,
+    pub author: Identity,
+    pub committer: Identity,
+    /// Headers this type doesn't interpret (`encoding`, `gpgsig`, ...), in order, kept so
+    /// a parsed commit serializes back to the same bytes. Multi-line values use `\n`.
+    pub extra_headers: Vec<(String, String)>,
+    /// Everything after the blank line that ends the headers, byte for byte.
+    pub message: String,
+}
+
+impl Commit {
+    /// The content of the commit object, in git's canonical layout.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut text = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            text.push_str(&format!("parent {}\n", parent));
+        }
+        text.push_str(&format!("author {}\n", signature(&self.author)));
+        text.push_str(&format!("committer {}\n", signature(&self.committer)));
+        for (name, value) in &self.extra_headers {
+            // Note: Continuation lines start with a space, as in git.
+            text.push_str(&format!("{} {}\n", name, value.replace('\n', "\n ")));
+        }
+        text.push('\n');
+        text.push_str(&self.message);
+        text.into_bytes()
+    }
+
+    /// Reads the commit object `id`, failing if it is some other kind of object.
+    pub fn read(odb: &Odb, id: &ObjectId) -> Result<Self> {
+        let (kind, data) = odb.read(id)?;
+        if kind != ObjectKind::Commit {
+            bail!("object {} is a {}, not a commit", id, kind);
+        }
+        Commit::parse(&data).with_context(|| format!("corrupt commit {}", id))
+    }
+
+    /// The first line of the message, as `log --oneline` shows it.
+    pub fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+
+    /// Reads a commit object's content.
+    ///
+    /// Note: `tree`, `author` and `committer` are required; any number of parents is fine,
+    /// and the message is kept as-is, with or without a final newline.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(data).context("corrupt commit: not valid UTF-8")?;
+        let (headers, message) = match text.split_once("\n\n") {
+            Some((headers, message)) => (headers, message),
+            None => (text.strip_suffix('\n').unwrap_or(text), ""),
+        };
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers: Vec<(String, String)> = Vec::new();
+        for line in headers.lines() {
+            if let Some(continuation) = line.strip_prefix(' ') {
+                let Some((_, value)) = extra_headers.last_mut() else {
+                    bail!("corrupt commit: continuation line without a header");
+                };
+                value.push('\n');
+                value.push_str(continuation);
+                continue;
+            }
+
+            let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+            let context = || format!("corrupt commit: bad {} header", name);
+            match name {
+                "tree" if tree.is_none() => {
+                    tree = Some(ObjectId::from_hex(value).with_context(context)?)
+                }
+                "parent" => parents.push(ObjectId::from_hex(value).with_context(context)?),
+                "author" if author.is_none() => {
+                    author = Some(parse_signature(value).with_context(context)?)
+                }
+                "committer" if committer.is_none() => {
+                    committer = Some(parse_signature(value).with_context(context)?)
+                }
+                "tree" | "author" | "committer" => {
+                    bail!("corrupt commit: duplicate {} header", name)
+                }
+                _ => extra_headers.push((name.to_string(), value.to_string())),
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.context("corrupt commit: missing tree header")?,
+            parents,
+            author: author.context("corrupt commit: missing author header")?,
+            committer: committer.context("corrupt commit: missing committer header")?,
+            extra_headers,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// An identity as it appears in a commit header: `Name <email> <seconds> <offset>`.
+fn signature(identity: &Identity) -> String {
+    format!("{} {}", identity, identity.when)
+}
+
+fn parse_signature(value: &str) -> Result<Identity> {
+    // Note: Split at the last `>`, since names and dates can't contain one but could
+    // otherwise be confused with each other.
+    let (who, when) = value
+        .rsplit_once('>')
+        .context("missing '>' after the email")?;
+    let (name, email) = who
+        .split_once('<')
+        .context("missing '<' before the email")?;
+    Ok(Identity {
+        name: name.trim_end().to_string(),
+        email: email.to_string(),
+        when: Timestamp::parse(when)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::hash::{ObjectKind, hash_object};
+
+    const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    fn id(hex: &str) -> ObjectId {
+        ObjectId::from_hex(hex).unwrap()
+    }
+
+    fn identity(name: &str, email: &str, when: &str) -> Identity {
+        Identity {
+            name: name.to_string(),
+            email: email.to_string(),
+            when: Timestamp::parse(when).unwrap(),
+        }
+    }
+
+    fn commit(parents: &[&str], when: &str, message: &str) -> Commit {
+        let author = identity("A U Thor", "author@example.com", when);
+        Commit {
+            tree: id(EMPTY_TREE),
+            parents: parents.iter().map(|hex| id(hex)).collect(),
+            author: author.clone(),
+            committer: author,
+            extra_headers: Vec::new(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_commit_matches_git() {
+        let mut initial = commit(&[], "1700000000 +0530", "Initial commit\n");
+        initial.committer = identity("C O Mitter", "committer@example.com", "1700000100 -0700");
+        let merge = commit(
+            &[
+                "612f2ac2877ef512e73485a5478cdbe49d24b991",
+                "c109971085d8aa065513fd554c9a07fc6f4dae39",
+            ],
+            "1700000000 +0000",
+            "Merge branch 'topic'\n",
+        );
+        let paragraphs = commit(
+            &["612f2ac2877ef512e73485a5478cdbe49d24b991"],
+            "1700000000 -0230",
+            "Subject line\n\nFirst paragraph\nwraps here.\n\n\nSecond paragraph after two blank lines.\n",
+        );
+        let no_newline = commit(&[], "1700000000 +0000", "no newline");
+
+        // Ids from `git hash-object -t commit` for the same text
+        for (commit, expected) in [
+            (&initial, "cb4d31274e8ce79e004048dd0e39acbe2b7f7dfc"),
+            (&merge, "45efe43614d4a54548dacf44c0844b59b1a4d04b"),
+            (&paragraphs, "b884e5619fd8d9b7abaaa107da7bbab5df988c00"),
+            (&no_newline, "62675039fd70698e89f968c137ad7b2346ea8441"),
+        ] {
+            let data = commit.serialize();
+            assert_eq!(hash_object(ObjectKind::Commit, &data).to_hex(), expected);
+            assert_eq!(&Commit::parse(&data).unwrap(), commit);
+        }
+
+        assert!(
+            String::from_utf8(merge.serialize())
+                .unwrap()
+                .starts_with(&format!(
+                    "tree {}\nparent 612f2ac2877ef512e73485a5478cdbe49d24b991\n\
+             parent c109971085d8aa065513fd554c9a07fc6f4dae39\n\
+             author A U Thor <author@example.com> 1700000000 +0000\n",
+                    EMPTY_TREE
+                ))
+        );
+    }
+
+    #[test]
+    fn test_commit_keeps_unknown_headers() {
+        let text = format!(
+            "tree {}\nauthor A <a@example.com> 1 +0000\ncommitter A <a@example.com> 1 +0000\n\
+             encoding ISO-8859-1\ngpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+             \nSigned\n",
+            EMPTY_TREE
+        );
+        let commit = Commit::parse(text.as_bytes()).unwrap();
+        assert_eq!(commit.extra_headers.len(), 2);
+        assert_eq!(
+            commit.extra_headers[1].1,
+            "-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----"
+        );
+        assert_eq!(commit.serialize(), text.as_bytes());
+
+        // A commit without a message at all
+        let bare = format!(
+            "tree {}\nauthor A <a@example.com> 1 +0000\ncommitter A <a@example.com> 1 +0000\n",
+            EMPTY_TREE
+        );
+        assert_eq!(Commit::parse(bare.as_bytes()).unwrap().message, "");
+    }
+
+    #[test]
+    fn test_commit_parse_rejects_malformed_input() {
+        let who = "author A <a@example.com> 1 +0000\ncommitter A <a@example.com> 1 +0000\n";
+        for (text, expected) in [
+            (format!("{}\nmsg\n", who), "missing tree"),
+            (format!("tree {}\n\nmsg\n", EMPTY_TREE), "missing author"),
+            (format!("tree abc\n{}\nmsg\n", who), "bad tree header"),
+            (
+                format!("tree {t}\ntree {t}\n{}\nmsg\n", who, t = EMPTY_TREE),
+                "duplicate tree",
+            ),
+            (
+                format!(
+                    "tree {}\nauthor A a@example.com 1 +0000\ncommitter A <a@example.com> 1 +0000\n\n",
+                    EMPTY_TREE
+                ),
+                "bad author header",
+            ),
+            (
+                format!(
+                    "tree {}\nauthor A <a@example.com> yesterday\ncommitter A <a@example.com> 1 +0000\n\n",
+                    EMPTY_TREE
+                ),
+                "bad author header",
+            ),
+            (
+                format!(" orphan\ntree {}\n{}\n", EMPTY_TREE, who),
+                "continuation",
+            ),
+        ] {
+            let err = format!("{:#}", Commit::parse(text.as_bytes()).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        for (text, offset) in [
+            ("1700000000 +0530", 330),
+            ("1700000000 -0700", -420),
+            ("0 +0000", 0),
+            ("1700000000 -0030", -30),
+        ] {
+            let when = Timestamp::parse(text).unwrap();
+            assert_eq!(when.offset_minutes, offset);
+            assert_eq!(when.to_string(), text);
+        }
+        for bad in [
+            "",
+            "1700000000",
+            "1700000000 0530",
+            "1700000000 +530",
+            "x +0000",
+            "1 +0060",
+        ] {
+            assert!(Timestamp::parse(bad).is_err(), "{:?}", bad);
+        }
+        assert!(Timestamp::now().seconds > 1_700_000_000);
+    }
+
+    #[test]
+    fn test_timestamp_log_date() {
+        // As `git log` shows them, leap days and dates before 1970 included
+        for (text, expected) in [
+            ("1700000000 +0530", "Wed Nov 15 03:43:20 2023 +0530"),
+            ("0 +0000", "Thu Jan 1 00:00:00 1970 +0000"),
+            ("-1 +0000", "Wed Dec 31 23:59:59 1969 +0000"),
+            ("951782400 -0100", "Mon Feb 28 23:00:00 2000 -0100"),
+            ("1709210096 +1400", "Fri Mar 1 02:34:56 2024 +1400"),
+            ("4102444800 -1230", "Thu Dec 31 11:30:00 2099 -1230"),
+        ] {
+            let when = Timestamp::parse(text).unwrap();
+            assert_eq!(when.to_log_date(), expected, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{Context, Result, bail};
+
+use crate::modules::{
+    hash::{ObjectId, ObjectKind},
+    odb::Odb,
+};
+
+/// Most tags a chain of annotated tags is followed through before giving up.
+pub const MAX_TAG_DEPTH: usize = 32;
+
+/// The object a tag points at: its `object <id>` header line.
+pub fn tagged_object(data: &[u8]) -> Result<ObjectId> {
+    let text = String::from_utf8_lossy(data);
+    let Some(hex) = text
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("object "))
+    else {
+        bail!("missing object line");
+    };
+    ObjectId::from_hex(hex)
+}
+
+/// The first object that isn't an annotated tag on the way from `id`, which is `id`
+/// itself unless it's a tag.
+pub fn peel(odb: &Odb, id: &ObjectId) -> Result<ObjectId> {
+    let mut peeled = *id;
+    for _ in 0..MAX_TAG_DEPTH {
+        let (kind, data) = odb.read(&peeled)?;
+        if kind != ObjectKind::Tag {
+            return Ok(peeled);
+        }
+        peeled = tagged_object(&data).with_context(|| format!("corrupt tag {}", peeled))?;
+    }
+    bail!("too many levels of tags while peeling {}", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tag(odb: &Odb, target: &ObjectId, kind: &str, name: &str) -> ObjectId {
+        let data = format!(
+            "object {}\ntype {}\ntag {}\ntagger C O Mitter <committer@example.com> 1700000100 -0700\n\nRelease\n",
+            target, kind, name
+        );
+        odb.write(ObjectKind::Tag, data.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_peel_follows_tags_of_tags() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+        let blob = odb.write(ObjectKind::Blob, b"hello\n").unwrap();
+        let inner = tag(&odb, &blob, "blob", "v1");
+        let outer = tag(&odb, &inner, "tag", "v1-signed");
+
+        assert_eq!(peel(&odb, &blob).unwrap(), blob);
+        assert_eq!(peel(&odb, &inner).unwrap(), blob);
+        assert_eq!(peel(&odb, &outer).unwrap(), blob);
+
+        let broken = odb.write(ObjectKind::Tag, b"type blob\n").unwrap();
+        let err = peel(&odb, &broken).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("missing object line"),
+            "{:#}",
+            err
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use anyhow::{Context, Result, bail};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use crate::modules::{
+    constants::is_repository_dir_name,
+    files::{TreeNode, check_tree_name, link_text},
+    hash::{ObjectId, ObjectKind},
+    odb::Odb,
+};
+
+/// The kind of a tree entry, as recorded in its mode. Only the modes git writes are valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryMode {
+    /// A regular file (`100644`).
+    File,
+    /// A file with the executable bit set (`100755`).
+    Executable,
+    /// A symlink, whose blob holds the target path (`120000`).
+    Symlink,
+    /// A subdirectory (`40000`).
+    Tree,
+    /// A submodule's commit (`160000`).
+    Commit,
+}
+
+impl EntryMode {
+    pub fn from_bits(bits: u32) -> Result<Self> {
+        match bits {
+            0o100644 => Ok(EntryMode::File),
+            0o100755 => Ok(EntryMode::Executable),
+            0o120000 => Ok(EntryMode::Symlink),
+            0o40000 => Ok(EntryMode::Tree),
+            0o160000 => Ok(EntryMode::Commit),
+            _ => bail!("invalid tree entry mode {:o}", bits),
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            EntryMode::File => 0o100644,
+            EntryMode::Executable => 0o100755,
+            EntryMode::Symlink => 0o120000,
+            EntryMode::Tree => 0o40000,
+            EntryMode::Commit => 0o160000,
+        }
+    }
+
+    /// The type of the object the entry points at.
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            EntryMode::File | EntryMode::Executable | EntryMode::Symlink => ObjectKind::Blob,
+            EntryMode::Tree => ObjectKind::Tree,
+            EntryMode::Commit => ObjectKind::Commit,
+        }
+    }
+}
+
+/// One name in a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: EntryMode,
+    pub name: String,
+    pub id: ObjectId,
+}
+
+impl TreeEntry {
+    /// Orders entries as git does: a subtree sorts as if its name ended in `/`,
+    /// so `lib.txt` comes before the directory `lib`, which comes before `lib0`.
+    fn git_cmp(&self, other: &TreeEntry) -> Ordering {
+        let key = |entry: &TreeEntry| {
+            let suffix: &[u8] = if entry.mode == EntryMode::Tree {
+                b"/"
+            } else {
+                b""
+            };
+            [entry.name.as_bytes(), suffix].concat()
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+/// Shown as `cat-file -p` prints it: `<mode> <type> <id>\t<name>`.
+impl std::fmt::Display for TreeEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:06o} {} {}\t{}",
+            self.mode.bits(),
+            self.mode.kind(),
+            self.id,
+            self.name
+        )
+    }
+}
+
+/// A directory listing stored as an object: entries in git's order, names unique.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    /// A tree of `entries`, sorted into git's order.
+    ///
+    /// Critical: Names are checked like tree entries written to disk, since a tree may
+    /// be checked out later; `..`, paths and the metadata directory are refused.
+    pub fn new(mut entries: Vec<TreeEntry>) -> Result<Self> {
+        // Note: A file and a directory of the same name don't sort next to each other.
+        let mut names = HashSet::new();
+        for entry in &entries {
+            check_entry_name(&entry.name)?;
+            if !names.insert(entry.name.as_str()) {
+                bail!("duplicate tree entry {:?}", entry.name);
+            }
+        }
+        entries.sort_by(TreeEntry::git_cmp);
+        Ok(Tree { entries })
+    }
+
+    pub fn entries(&self) -> &[TreeEntry] {
+        &self.entries
+    }
+
+    /// The content of the tree object: `<octal mode> <name>\0<raw id>` per entry.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for entry in &self.entries {
+            data.extend_from_slice(format!("{:o} {}\0", entry.mode.bits(), entry.name).as_bytes());
+            data.extend_from_slice(entry.id.as_bytes());
+        }
+        data
+    }
+
+    /// Reads a tree object's content, refusing anything git wouldn't have written:
+    /// unknown or zero-padded modes, unsafe names, duplicates and entries out of order.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut entries: Vec<TreeEntry> = Vec::new();
+        let mut names = HashSet::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let offset = data.len() - rest.len();
+            let entry = parse_entry(&mut rest)
+                .with_context(|| format!("corrupt tree: bad entry at byte {}", offset))?;
+            if let Some(previous) = entries.last()
+                && previous.git_cmp(&entry) != Ordering::Less
+            {
+                bail!(
+                    "corrupt tree: entry {:?} out of order after {:?}",
+                    entry.name,
+                    previous.name
+                );
+            }
+            if !names.insert(entry.name.clone()) {
+                bail!("corrupt tree: duplicate entry {:?}", entry.name);
+            }
+            entries.push(entry);
+        }
+        Ok(Tree { entries })
+    }
+
+    /// Reads the tree object `id`, failing if it is some other kind of object.
+    pub fn read(odb: &Odb, id: &ObjectId) -> Result<Self> {
+        let (kind, data) = odb.read(id)?;
+        if kind != ObjectKind::Tree {
+            bail!("object {} is a {}, not a tree", id, kind);
+        }
+        Tree::parse(&data).with_context(|| format!("corrupt tree {}", id))
+    }
+
+    /// Every non-tree entry under the tree `id`, by `/`-separated path, with subtrees
+    /// expanded; the layout of the index.
+    pub fn flatten(odb: &Odb, id: &ObjectId) -> Result<BTreeMap<String, (EntryMode, ObjectId)>> {
+        let mut files = BTreeMap::new();
+        flatten_into(odb, id, "", &mut files)?;
+        Ok(files)
+    }
+
+    /// Stores `node` in `odb` as objects, bottom-up: a blob per file and symlink, a tree
+    /// per directory. Returns the id of the root tree.
+    ///
+    /// Note: Git can't record an empty directory, so empty directories (and those holding
+    /// only empty directories) are left out, except for an empty root.
+    pub fn from_tree_node(node: &TreeNode, odb: &Odb) -> Result<ObjectId> {
+        let TreeNode::Directory { .. } = node else {
+            bail!("the root of a tree must be a directory");
+        };
+        match write_node(node, odb)? {
+            Some((_, id)) => Ok(id),
+            None => odb.write(ObjectKind::Tree, &Tree::default().serialize()),
+        }
+    }
+}
+
+fn flatten_into(
+    odb: &Odb,
+    id: &ObjectId,
+    prefix: &str,
+    files: &mut BTreeMap<String, (EntryMode, ObjectId)>,
+) -> Result<()> {
+    for entry in Tree::read(odb, id)?.entries {
+        let path = format!("{}{}", prefix, entry.name);
+        if entry.mode == EntryMode::Tree {
+            flatten_into(odb, &entry.id, &format!("{}/", path), files)?;
+        } else {
+            files.insert(path, (entry.mode, entry.id));
+        }
+    }
+    Ok(())
+}
+
+/// Stores `node` and returns the mode and id its parent records, or `None` for an empty directory.
+fn write_node(node: &TreeNode, odb: &Odb) -> Result<Option<(EntryMode, ObjectId)>> {
+    match node {
+        TreeNode::File { content, mode } => {
+            let entry_mode = match mode {
+                Some(mode) if mode & 0o111 != 0 => EntryMode::Executable,
+                _ => EntryMode::File,
+            };
+            Ok(Some((entry_mode, odb.write(ObjectKind::Blob, content)?)))
+        }
+        TreeNode::Symlink(target) => Ok(Some((
+            EntryMode::Symlink,
+            odb.write(ObjectKind::Blob, &link_text(target))?,
+        ))),
+        TreeNode::Directory {
+            entries: children, ..
+        } => {
+            let mut entries = Vec::new();
+            for (name, child) in children {
+                check_entry_name(name)?;
+                if let Some((mode, id)) = write_node(child, odb)? {
+                    entries.push(TreeEntry {
+                        mode,
+                        name: name.clone(),
+                        id,
+                    });
+                }
+            }
+            if entries.is_empty() {
+                return Ok(None);
+            }
+            let tree = Tree::new(entries)?;
+            Ok(Some((
+                EntryMode::Tree,
+                odb.write(ObjectKind::Tree, &tree.serialize())?,
+            )))
+        }
+    }
+}
+
+/// Reads one entry off the front of `rest`.
+fn parse_entry(rest: &mut &[u8]) -> Result<TreeEntry> {
+    let space = rest
+        .iter()
+        .position(|&b| b == b' ')
+        .context("missing mode")?;
+    let mode_text = std::str::from_utf8(&rest[..space]).context("invalid mode")?;
+    // Note: Git never zero-pads modes, so `040000` means the tree was not written by git.
+    if mode_text.starts_with('0') {
+        bail!("zero-padded mode {}", mode_text);
+    }
+    let mode = u32::from_str_radix(mode_text, 8)
+        .ok()
+        .with_context(|| format!("invalid mode {:?}", mode_text))?;
+    let mode = EntryMode::from_bits(mode)?;
+    *rest = &rest[space + 1..];
+
+    let nul = rest
+        .iter()
+        .position(|&b| b == 0)
+        .context("unterminated name")?;
+    let name = std::str::from_utf8(&rest[..nul])
+        .context("name is not valid UTF-8")?
+        .to_string();
+    check_entry_name(&name)?;
+    *rest = &rest[nul + 1..];
+
+    // Note: Only SHA-1 ids are stored so far.
+    if rest.len() < 20 {
+        bail!("truncated id for {:?}", name);
+    }
+    let id = ObjectId::from_bytes(&rest[..20])?;
+    *rest = &rest[20..];
+
+    Ok(TreeEntry { mode, name, id })
+}
+
+/// Checks that `name` can safely be a tree entry.
+fn check_entry_name(name: &str) -> Result<()> {
+    check_tree_name(name, Path::new("."))?;
+    if is_repository_dir_name(name.as_ref()) {
+        bail!(
+            "refusing tree entry {:?}: reserved for repository metadata",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::index::{Index, IndexEntry};
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn id(hex: &str) -> ObjectId {
+        ObjectId::from_hex(hex).unwrap()
+    }
+
+    fn entry(mode: EntryMode, name: &str, hex: &str) -> TreeEntry {
+        TreeEntry {
+            mode,
+            name: name.to_string(),
+            id: id(hex),
+        }
+    }
+
+    /// README, bin/run.sh (executable), lib/a.txt, lib.txt, lib-x and a symlink `link -> README`.
+    fn fixture() -> TreeNode {
+        let mut bin = HashMap::new();
+        bin.insert(
+            "run.sh".to_string(),
+            TreeNode::file_with_mode("#!/bin/sh\necho run\n", 0o755),
+        );
+        let mut lib = HashMap::new();
+        lib.insert("a.txt".to_string(), TreeNode::text("a\n"));
+        let mut root = HashMap::new();
+        root.insert("README".to_string(), TreeNode::text("hello\n"));
+        root.insert("bin".to_string(), TreeNode::dir(bin));
+        root.insert("lib".to_string(), TreeNode::dir(lib));
+        root.insert("lib.txt".to_string(), TreeNode::text("top\n"));
+        root.insert("lib-x".to_string(), TreeNode::text("dash\n"));
+        root.insert("link".to_string(), TreeNode::Symlink("README".into()));
+        root.insert("empty".to_string(), TreeNode::dir(HashMap::new()));
+        TreeNode::dir(root)
+    }
+
+    #[test]
+    fn test_tree_from_tree_node_matches_git() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+
+        // `git add -A && git write-tree` for the same files
+        let root = Tree::from_tree_node(&fixture(), &odb).unwrap();
+        assert_eq!(root.to_hex(), "30c74386b0ea8a1fa271d5b58dded257a6d5e2d9");
+
+        let (kind, data) = odb.read(&root).unwrap();
+        assert_eq!(kind, ObjectKind::Tree);
+        let tree = Tree::parse(&data).unwrap();
+        assert_eq!(tree.serialize(), data);
+        let listing: Vec<String> = tree.entries().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            listing,
+            [
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tREADME",
+                "040000 tree ab9886a4a27110546a3771b2bfc93760bb25f679\tbin",
+                "100644 blob a2544f7ec3007899167de1fef481a5a0fd63fa41\tlib-x",
+                "100644 blob bf1a1fdefa3c7f4b0180a75a951e9574662a8bc8\tlib.txt",
+                "040000 tree 08585692ce06452da6f82ae66b90d98b55536fca\tlib",
+                "120000 blob 100b93820ade4c16225673b4ca62bb3ade63c313\tlink",
+            ]
+        );
+        // Every object the root refers to is stored too
+        assert!(tree.entries().iter().all(|entry| odb.contains(&entry.id)));
+
+        // Flattened, files are listed by path in index order
+        let files = Tree::flatten(&odb, &root).unwrap();
+        let paths: Vec<&str> = files.keys().map(String::as_str).collect();
+        assert_eq!(
+            paths,
+            [
+                "README",
+                "bin/run.sh",
+                "lib-x",
+                "lib.txt",
+                "lib/a.txt",
+                "link"
+            ]
+        );
+        assert_eq!(files["bin/run.sh"].0, EntryMode::Executable);
+        assert_eq!(
+            files["lib/a.txt"].1,
+            id("78981922613b2afb6025042ff6bd878ac1994e85")
+        );
+
+        // An index of the same files writes the same trees
+        let mut index = Index::default();
+        for (path, (mode, id)) in &files {
+            index.add_entry(IndexEntry::new(path, *mode, *id)).unwrap();
+        }
+        assert_eq!(index.write_tree(&odb).unwrap(), root);
+        assert!(Tree::read(&odb, &files["README"].1).is_err());
+
+        let empty = Tree::from_tree_node(&TreeNode::dir(HashMap::new()), &odb).unwrap();
+        assert_eq!(empty.to_hex(), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+        assert!(Tree::from_tree_node(&TreeNode::text("x"), &odb).is_err());
+    }
+
+    #[test]
+    fn test_tree_new_sorts_and_validates() {
+        let blob = "ce013625030ba8dba906f756967f9e9ca394464a";
+        let tree = Tree::new(vec![
+            entry(EntryMode::Tree, "lib", blob),
+            entry(EntryMode::File, "lib0", blob),
+            entry(EntryMode::File, "lib.txt", blob),
+        ])
+        .unwrap();
+        let names: Vec<_> = tree.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["lib.txt", "lib", "lib0"]);
+        assert_eq!(Tree::parse(&tree.serialize()).unwrap(), tree);
+
+        for name in ["", ".", "..", "a/b", ".CS01", ".cs01", ".git", ".Git"] {
+            assert!(
+                Tree::new(vec![entry(EntryMode::File, name, blob)]).is_err(),
+                "{:?}",
+                name
+            );
+        }
+        let err = Tree::new(vec![
+            entry(EntryMode::File, "same", blob),
+            entry(EntryMode::File, "same-ish", blob),
+            entry(EntryMode::Tree, "same", blob),
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("duplicate"), "{}", err);
+    }
+
+    #[test]
+    fn test_tree_parse_rejects_malformed_input() {
+        let raw = id("ce013625030ba8dba906f756967f9e9ca394464a");
+        let with_id = |prefix: &[u8]| [prefix, raw.as_bytes()].concat();
+
+        let cases: [(Vec<u8>, &str); 9] = [
+            (with_id(b"100644 a\0")[..25].to_vec(), "truncated id"),
+            (b"100644 a".to_vec(), "unterminated name"),
+            (b"no-space-here".to_vec(), "missing mode"),
+            (with_id(b"100664 a\0"), "invalid tree entry mode"),
+            (with_id(b"040000 a\0"), "zero-padded"),
+            (with_id(b"100644 ..\0"), "relative path component"),
+            (
+                [with_id(b"100644 b\0"), with_id(b"100644 a\0")].concat(),
+                "out of order",
+            ),
+            (
+                [with_id(b"100644 a\0"), with_id(b"100644 a\0")].concat(),
+                "out of order",
+            ),
+            (
+                [with_id(b"100644 a\0"), with_id(b"40000 a\0")].concat(),
+                "duplicate entry",
+            ),
+        ];
+        for (data, expected) in cases {
+            let err = format!("{:#}", Tree::parse(&data).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+        }
+        assert_eq!(Tree::parse(b"").unwrap(), Tree::default());
+    }
+}
@@ -0,0 +1,397 @@
+use anyhow::{Context, Result, bail};
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::modules::{
+    config::Config,
+    files::{set_mode, write_new_file_atomic},
+    hash::{ObjectFormat, ObjectId, ObjectKind, hash_object},
+};
+
+/// Names a directory to store objects in instead of the repository's `objects/`, like
+/// git's `GIT_OBJECT_DIRECTORY`.
+pub const CS01_OBJECT_DIRECTORY_ENV: &str = "CS01_OBJECT_DIRECTORY";
+
+/// The object database of a repository: loose objects stored zlib-compressed under
+/// `objects/`, each in a file named after its id (`objects/ab/cdef...`), as git does.
+///
+/// Note: With `CS01_OBJECT_DIRECTORY` set, objects are written there instead, and the
+/// repository's own `objects/` is only read from (an alternate, in git's terms).
+#[derive(Debug, Clone)]
+pub struct Odb {
+    objects_dir: PathBuf,
+    /// Directories searched for objects after `objects_dir`, never written to.
+    alternates: Vec<PathBuf>,
+}
+
+impl Odb {
+    /// Opens the object database of the repository whose metadata directory is `repo_dir`,
+    /// in the directory `CS01_OBJECT_DIRECTORY` names if it is set.
+    pub fn open(repo_dir: &Path) -> Result<Self> {
+        let object_directory = std::env::var_os(CS01_OBJECT_DIRECTORY_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        Self::open_in(repo_dir, object_directory.as_deref())
+    }
+
+    /// Opens the object database of `repo_dir`, storing new objects in `object_directory`
+    /// when given.
+    ///
+    /// Note: Objects are only stored as SHA-1 so far; a repository whose config asks for
+    /// another `extensions.objectformat` is refused rather than read with the wrong hash.
+    pub fn open_in(repo_dir: &Path, object_directory: Option<&Path>) -> Result<Self> {
+        let own_dir = repo_dir.join("objects");
+        let (objects_dir, alternates) = match object_directory {
+            Some(dir) => {
+                let dir = std::path::absolute(dir)
+                    .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+                let alternates = if own_dir.is_dir() && !same_dir(&own_dir, &dir) {
+                    vec![own_dir]
+                } else {
+                    Vec::new()
+                };
+                (dir, alternates)
+            }
+            None => (own_dir, Vec::new()),
+        };
+        if !objects_dir.is_dir() {
+            bail!("no object database at {:?}", objects_dir);
+        }
+        let config = Config::load(&repo_dir.join("config"), Some(repo_dir))?;
+        if let Some(name) = config.get_string("extensions.objectformat")? {
+            let format = ObjectFormat::parse(name)?;
+            if format != ObjectFormat::Sha1 {
+                bail!(
+                    "object format '{}' is not supported yet; only sha1 repositories can be read",
+                    format.name()
+                );
+            }
+        }
+        Ok(Odb {
+            objects_dir,
+            alternates,
+        })
+    }
+
+    /// The directory new objects are written to.
+    pub fn objects_dir(&self) -> &Path {
+        &self.objects_dir
+    }
+
+    /// Where the loose object `id` is (or would be) written.
+    pub fn object_path(&self, id: &ObjectId) -> PathBuf {
+        loose_path(&self.objects_dir, id)
+    }
+
+    pub fn contains(&self, id: &ObjectId) -> bool {
+        self.find(id).is_some()
+    }
+
+    /// Where the object `id` is stored: the object directory, or else an alternate.
+    fn find(&self, id: &ObjectId) -> Option<PathBuf> {
+        std::iter::once(&self.objects_dir)
+            .chain(&self.alternates)
+            .map(|dir| loose_path(dir, id))
+            .find(|path| path.is_file())
+    }
+
+    /// Every stored object whose hex id starts with `prefix` (lowercase hex, at least
+    /// two digits), sorted.
+    pub fn find_by_prefix(&self, prefix: &str) -> Result<Vec<ObjectId>> {
+        if prefix.len() < 2
+            || !prefix
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        {
+            bail!("invalid object id prefix {:?}", prefix);
+        }
+        let (fan_out, rest) = prefix.split_at(2);
+        let mut found = Vec::new();
+        for objects_dir in std::iter::once(&self.objects_dir).chain(&self.alternates) {
+            let dir = objects_dir.join(fan_out);
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+            };
+            for entry in entries {
+                let entry = entry.with_context(|| format!("Failed to read {:?}", dir))?;
+                let name = entry.file_name();
+                // Note: Temporary files from interrupted writes don't parse as ids and are skipped.
+                if let Some(name) = name.to_str()
+                    && name.starts_with(rest)
+                    && let Ok(id) = ObjectId::from_hex(&format!("{}{}", fan_out, name))
+                {
+                    found.push(id);
+                }
+            }
+        }
+        found.sort_by_key(|id| id.to_hex());
+        found.dedup();
+        Ok(found)
+    }
+
+    /// Stores `data` as an object of type `kind` and returns its id.
+    ///
+    /// Note: Objects are immutable, so an object that is already stored is left alone
+    /// and counts as success. New objects are written to a temporary file and moved
+    /// into place, so a crash never leaves a truncated object behind.
+    pub fn write(&self, kind: ObjectKind, data: &[u8]) -> Result<ObjectId> {
+        let id = hash_object(kind, data);
+        if self.contains(&id) {
+            return Ok(id);
+        }
+        let path = self.object_path(&id);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(format!("{} {}\0", kind, data.len()).as_bytes())
+            .and_then(|()| encoder.write_all(data))
+            .with_context(|| format!("Failed to compress object {}", id))?;
+        let compressed = encoder
+            .finish()
+            .with_context(|| format!("Failed to compress object {}", id))?;
+
+        if let Some(fan_out) = path.parent() {
+            fs::create_dir_all(fan_out)
+                .with_context(|| format!("Failed to create directory {:?}", fan_out))?;
+        }
+        if write_new_file_atomic(&path, &compressed)? {
+            // Like git, loose objects are read-only; nothing should ever change one.
+            set_mode(&path, 0o444)?;
+        }
+        Ok(id)
+    }
+
+    /// Reads the object `id`, checking that its header is well-formed and its length right.
+    pub fn read(&self, id: &ObjectId) -> Result<(ObjectKind, Vec<u8>)> {
+        let path = self.find(id).unwrap_or_else(|| self.object_path(id));
+        let compressed = match fs::read(&path) {
+            Ok(compressed) => compressed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                bail!("object {} not found at {:?}", id, path)
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+
+        let mut raw = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut raw)
+            .with_context(|| format!("corrupt object {:?}: cannot decompress", path))?;
+
+        parse_object(&raw).with_context(|| format!("corrupt object {:?}", path))
+    }
+}
+
+/// Where the loose object `id` is stored under `objects_dir`.
+fn loose_path(objects_dir: &Path, id: &ObjectId) -> PathBuf {
+    let hex = id.to_hex();
+    let (fan_out, rest) = hex.split_at(2);
+    objects_dir.join(fan_out).join(rest)
+}
+
+/// Whether `a` and `b` are the same directory, however they are spelled.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Splits a decompressed object into its type and payload.
+fn parse_object(raw: &[u8]) -> Result<(ObjectKind, Vec<u8>)> {
+    let Some(nul) = raw.iter().position(|&b| b == 0) else {
+        bail!("header is not terminated");
+    };
+    let header = std::str::from_utf8(&raw[..nul]).context("header is not valid UTF-8")?;
+    let Some((kind, len)) = header.split_once(' ') else {
+        bail!("malformed header {:?}", header);
+    };
+    let kind = ObjectKind::parse(kind)?;
+    // Note: Only plain decimal, as git writes it; `+8` and `08` would parse but aren't canonical.
+    let canonical =
+        len.bytes().all(|b| b.is_ascii_digit()) && (len == "0" || !len.starts_with('0'));
+    let len: usize = match len.parse() {
+        Ok(len) if canonical => len,
+        _ => bail!("malformed header {:?}", header),
+    };
+
+    let data = &raw[nul + 1..];
+    if data.len() != len {
+        bail!(
+            "header says {} bytes but the object holds {}",
+            len,
+            data.len()
+        );
+    }
+    Ok((kind, data.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn empty_odb(dir: &Path) -> Odb {
+        fs::create_dir_all(dir.join("objects")).unwrap();
+        Odb::open(dir).unwrap()
+    }
+
+    #[test]
+    fn test_odb_round_trip() {
+        let dir = tempdir().unwrap();
+        let odb = empty_odb(dir.path());
+
+        let cases: [(ObjectKind, &[u8]); 4] = [
+            (ObjectKind::Blob, b""),
+            (ObjectKind::Blob, b"hello\n"),
+            (ObjectKind::Blob, &[0, 1, 0xff, 0]),
+            (ObjectKind::Tree, b""),
+        ];
+        for (kind, data) in cases {
+            let id = odb.write(kind, data).unwrap();
+            assert_eq!(id, hash_object(kind, data));
+            assert!(odb.contains(&id));
+            assert_eq!(odb.read(&id).unwrap(), (kind, data.to_vec()));
+        }
+
+        // Stored where git stores it, so git can read it too
+        let hello = ObjectId::from_hex("ce013625030ba8dba906f756967f9e9ca394464a").unwrap();
+        assert_eq!(
+            odb.object_path(&hello),
+            dir.path()
+                .join("objects/ce/013625030ba8dba906f756967f9e9ca394464a")
+        );
+        assert!(odb.contains(&hello));
+
+        let large = vec![b'x'; 1 << 20];
+        let id = odb.write(ObjectKind::Blob, &large).unwrap();
+        assert!(fs::metadata(odb.object_path(&id)).unwrap().len() < 10_000);
+        assert_eq!(odb.read(&id).unwrap().1, large);
+
+        assert!(Odb::open(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_odb_object_directory_override() {
+        let dir = tempdir().unwrap();
+        let old = empty_odb(dir.path())
+            .write(ObjectKind::Blob, b"old\n")
+            .unwrap();
+        let elsewhere = dir.path().join("elsewhere");
+        fs::create_dir(&elsewhere).unwrap();
+
+        let odb = Odb::open_in(dir.path(), Some(&elsewhere)).unwrap();
+        assert_eq!(odb.objects_dir(), elsewhere);
+
+        // New objects land in the override
+        let new = odb.write(ObjectKind::Blob, b"new\n").unwrap();
+        assert!(odb.object_path(&new).starts_with(&elsewhere));
+        assert!(odb.object_path(&new).is_file());
+        assert!(!dir.path().join("objects").join(&new.to_hex()[..2]).exists());
+
+        // Objects already in the repository are still found there, and not copied
+        assert!(odb.contains(&old));
+        assert_eq!(
+            odb.read(&old).unwrap(),
+            (ObjectKind::Blob, b"old\n".to_vec())
+        );
+        assert_eq!(odb.find_by_prefix(&old.to_hex()[..6]).unwrap(), vec![old]);
+        assert_eq!(odb.write(ObjectKind::Blob, b"old\n").unwrap(), old);
+        assert!(!odb.object_path(&old).exists());
+
+        // The repository's own directory named again is no alternate of itself
+        let same = Odb::open_in(dir.path(), Some(&dir.path().join("objects"))).unwrap();
+        assert!(same.alternates.is_empty());
+        assert!(Odb::open_in(dir.path(), Some(&dir.path().join("missing"))).is_err());
+    }
+
+    #[test]
+    fn test_odb_open_checks_object_format() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("objects")).unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(&config, "[extensions]\n\tobjectformat = sha1\n").unwrap();
+        assert!(Odb::open(dir.path()).is_ok());
+        fs::write(&config, "[extensions]\n\tobjectformat = sha256\n").unwrap();
+        let err = Odb::open(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("sha256"), "{}", err);
+        fs::write(&config, "[extensions]\n\tobjectformat = md5\n").unwrap();
+        assert!(Odb::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_odb_find_by_prefix() {
+        let dir = tempdir().unwrap();
+        let odb = empty_odb(dir.path());
+        let empty_tree = odb.write(ObjectKind::Tree, b"").unwrap();
+        // f0acb599... and f0acbc2e...: the same first five digits
+        let first = odb.write(ObjectKind::Blob, b"1071").unwrap();
+        let second = odb.write(ObjectKind::Blob, b"1251").unwrap();
+
+        assert_eq!(odb.find_by_prefix("4b825d").unwrap(), vec![empty_tree]);
+        assert_eq!(odb.find_by_prefix("f0acb").unwrap(), vec![first, second]);
+        assert_eq!(odb.find_by_prefix("f0acbc").unwrap(), vec![second]);
+        assert_eq!(odb.find_by_prefix(&first.to_hex()).unwrap(), vec![first]);
+        assert!(odb.find_by_prefix("ffff").unwrap().is_empty());
+        assert!(odb.find_by_prefix("4B82").is_err());
+        assert!(odb.find_by_prefix("4").is_err());
+    }
+
+    #[test]
+    fn test_odb_write_is_idempotent() {
+        let dir = tempdir().unwrap();
+        let odb = empty_odb(dir.path());
+        let id = odb.write(ObjectKind::Blob, b"same\n").unwrap();
+        let path = odb.object_path(&id);
+
+        // Swap the stored bytes for a marker: a second write must not replace them
+        set_mode(&path, 0o644).unwrap();
+        fs::write(&path, b"marker").unwrap();
+
+        assert_eq!(odb.write(ObjectKind::Blob, b"same\n").unwrap(), id);
+        assert_eq!(fs::read(&path).unwrap(), b"marker");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_odb_read_rejects_corrupt_objects() {
+        let dir = tempdir().unwrap();
+        let odb = empty_odb(dir.path());
+        let id = hash_object(ObjectKind::Blob, b"content\n");
+        let path = odb.object_path(&id);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let compress = |raw: &[u8]| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(raw).unwrap();
+            encoder.finish().unwrap()
+        };
+        let valid = compress(b"blob 8\0content\n");
+
+        let cases: [(Vec<u8>, &str); 8] = [
+            (valid[..valid.len() / 2].to_vec(), "cannot decompress"),
+            (b"not zlib at all".to_vec(), "cannot decompress"),
+            (compress(b"blob 8"), "not terminated"),
+            (compress(b"blob 20\0content\n"), "header says 20 bytes"),
+            (compress(b"blob 3\0content\n"), "header says 3 bytes"),
+            (compress(b"blob\0content\n"), "malformed header"),
+            (compress(b"blob +8\0content\n"), "malformed header"),
+            (compress(b"bolb 8\0content\n"), "unknown object type"),
+        ];
+        for (stored, expected) in cases {
+            fs::write(&path, &stored).unwrap();
+            let err = format!("{:#}", odb.read(&id).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+            assert!(err.contains(&format!("{:?}", path)), "{}", err);
+        }
+
+        fs::remove_file(&path).unwrap();
+        let err = odb.read(&id).unwrap_err().to_string();
+        assert!(err.contains("not found"), "{}", err);
+        assert!(err.contains(&id.to_hex()), "{}", err);
+    }
+}
@@ -0,0 +1,106 @@
+use colored::*;
+use std::cell::Cell;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// Routes user-facing messages so every command treats `--quiet` the same way.
+///
+/// - Success messages go to stdout and are suppressed in quiet mode.
+/// - Warnings go to stderr and are always shown, like git's `-q`.
+/// - Hints (advice on what to do next) go to stderr, except in quiet mode.
+/// - In JSON mode, stdout carries only the command's JSON result; human-readable
+///   success messages and hints are dropped, and warnings become
+///   `{"warning": ...}` lines on stderr, like errors.
+/// - A command's own listing (e.g. `diff`'s patches or `log`'s commits) is its result, not
+///   a message, so it goes to stdout as is; in JSON mode that result is what `json` prints.
+/// - Errors are not handled here; they propagate to `main` which prints them to stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Output {
+    pub quiet: bool,
+    pub json: bool,
+}
+
+impl Output {
+    pub fn new(quiet: bool) -> Self {
+        Self { quiet, json: false }
+    }
+
+    /// Switches to machine-readable output.
+    pub fn with_json(self, json: bool) -> Self {
+        Self { json, ..self }
+    }
+
+    /// Prints a success message in green.
+    pub fn success(&self, message: &str) {
+        if !self.quiet && !self.json {
+            println!("{}", message.green());
+        }
+    }
+
+    /// Prints a command's result as a single line of JSON on stdout.
+    pub fn json(&self, value: &serde_json::Value) {
+        println!("{}", value);
+    }
+
+    /// Prints a warning in yellow to stderr, even in quiet mode.
+    pub fn warn(&self, message: &str) {
+        if self.json {
+            let message = message.strip_prefix("warning: ").unwrap_or(message);
+            eprintln!("{}", serde_json::json!({ "warning": message }));
+        } else {
+            eprintln!("{}", message.yellow());
+        }
+    }
+
+    /// Prints advice to stderr, unless in quiet or JSON mode.
+    pub fn hint(&self, message: &str) {
+        if !self.quiet && !self.json {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// How long an operation runs before its progress line appears, so quick ones stay silent.
+const PROGRESS_DELAY: Duration = Duration::from_secs(1);
+
+/// A carriage-return progress line on stdout, like git's `Updating files: 40% (4/10)`.
+///
+/// Note: Nothing is shown in quiet or JSON mode, or when stdout isn't a terminal, so
+/// piped output never contains partial lines.
+pub struct ProgressLine {
+    title: String,
+    enabled: bool,
+    start: Instant,
+    shown: Cell<bool>,
+}
+
+impl ProgressLine {
+    pub fn new(title: &str, out: &Output) -> Self {
+        Self {
+            title: title.to_string(),
+            enabled: !out.quiet && !out.json && std::io::stdout().is_terminal(),
+            start: Instant::now(),
+            shown: Cell::new(false),
+        }
+    }
+
+    /// Redraws the line with `done` of `total` items, ending it once everything is done.
+    pub fn update(&self, done: usize, total: usize) {
+        if !self.enabled || (!self.shown.get() && self.start.elapsed() < PROGRESS_DELAY) {
+            return;
+        }
+        self.shown.set(true);
+
+        let percent = (done * 100).checked_div(total).unwrap_or(100);
+        let mut stdout = std::io::stdout();
+        let _ = write!(
+            stdout,
+            "\r{}: {}% ({}/{})",
+            self.title, percent, done, total
+        );
+        if done >= total {
+            let _ = writeln!(stdout, ", done.");
+        }
+        let _ = stdout.flush();
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{Result, bail};
+
+/// How a repository is shared between users, mirroring git's `--shared` option.
+///
+/// The mode decides which permissions are applied to the metadata directories and files,
+/// and which value is recorded as `core.sharedRepository` in the generated config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedMode {
+    /// Use the permissions reported by the process umask (the default).
+    Umask,
+    /// Make the repository group-writable (and group-sticky on directories).
+    Group,
+    /// Like `Group`, but additionally readable by all users.
+    All,
+    /// Explicit octal permission bits for files, e.g. `0660`.
+    Octal(u32),
+}
+
+impl SharedMode {
+    /// Parses the value given to `--shared`.
+    ///
+    /// Accepts the same spellings as git: `umask`/`false`, `group`/`true`,
+    /// `all`/`world`/`everybody`, or an octal number such as `0640`.
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "umask" | "false" => Ok(SharedMode::Umask),
+            "group" | "true" => Ok(SharedMode::Group),
+            "all" | "world" | "everybody" => Ok(SharedMode::All),
+            _ => {
+                let octal = value.strip_prefix('0').unwrap_or(value);
+                let mode = u32::from_str_radix(octal, 8)
+                    .map_err(|_| anyhow::anyhow!("invalid value for --shared: '{}'", value))?;
+
+                // Note: The owner must always be able to read and write, otherwise
+                // the repository becomes unusable for the person creating it.
+                if mode > 0o777 || mode & 0o600 != 0o600 {
+                    bail!(
+                        "invalid value for --shared: '{}' (must grant the owner read and write)",
+                        value
+                    );
+                }
+
+                Ok(SharedMode::Octal(mode))
+            }
+        }
+    }
+
+    /// Permission bits applied to directories created by init, or `None` to leave them
+    /// to the umask.
+    pub fn dir_perms(&self) -> Option<u32> {
+        match self {
+            SharedMode::Umask => None,
+            SharedMode::Group => Some(0o2770),
+            SharedMode::All => Some(0o2775),
+            // Directories get the execute bit wherever the read bit is set,
+            // so that members who can read files can also traverse into them.
+            SharedMode::Octal(mode) => Some(0o2000 | mode | ((mode & 0o444) >> 2)),
+        }
+    }
+
+    /// Permission bits applied to files created by init, or `None` to leave them to the umask.
+    pub fn file_perms(&self) -> Option<u32> {
+        match self {
+            SharedMode::Umask => None,
+            SharedMode::Group => Some(0o660),
+            SharedMode::All => Some(0o664),
+            SharedMode::Octal(mode) => Some(*mode),
+        }
+    }
+
+    /// The permissions of a file that comes with its own `mode` (an executable hook, a
+    /// template file), widened to this sharing mode.
+    ///
+    /// Note: As in git, group and other only get the access the owner has: no write bits
+    /// for a read-only file, and execute bits wherever they can read an executable one.
+    /// The named modes add to `mode`; an octal mode replaces its permission bits.
+    pub fn adjust_mode(&self, mode: u32) -> u32 {
+        let Some(shared) = self.file_perms() else {
+            return mode;
+        };
+        let mut tweak = shared;
+        if mode & 0o200 == 0 {
+            tweak &= !0o222;
+        }
+        if mode & 0o100 != 0 {
+            tweak |= (tweak & 0o444) >> 2;
+        }
+        match self {
+            SharedMode::Octal(_) => (mode & !0o777) | tweak,
+            _ => mode | tweak,
+        }
+    }
+
+    /// The value recorded as `core.sharedrepository`, or `None` when nothing should be written.
+    pub fn config_value(&self) -> Option<String> {
+        match self {
+            SharedMode::Umask => None,
+            SharedMode::Group => Some("1".to_string()),
+            SharedMode::All => Some("2".to_string()),
+            SharedMode::Octal(mode) => Some(format!("0{:o}", mode)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_named_modes() {
+        assert_eq!(SharedMode::parse("umask").unwrap(), SharedMode::Umask);
+        assert_eq!(SharedMode::parse("false").unwrap(), SharedMode::Umask);
+        assert_eq!(SharedMode::parse("group").unwrap(), SharedMode::Group);
+        assert_eq!(SharedMode::parse("true").unwrap(), SharedMode::Group);
+        assert_eq!(SharedMode::parse("all").unwrap(), SharedMode::All);
+        assert_eq!(SharedMode::parse("everybody").unwrap(), SharedMode::All);
+    }
+
+    #[test]
+    fn test_parse_octal() {
+        let mode = SharedMode::parse("0640").unwrap();
+        assert_eq!(mode, SharedMode::Octal(0o640));
+        assert_eq!(mode.file_perms(), Some(0o640));
+        assert_eq!(mode.dir_perms(), Some(0o2750));
+        assert_eq!(mode.config_value().unwrap(), "0640");
+    }
+
+    #[test]
+    fn test_adjust_mode() {
+        assert_eq!(SharedMode::Umask.dir_perms(), None);
+        assert_eq!(SharedMode::Umask.adjust_mode(0o755), 0o755);
+        assert_eq!(SharedMode::Group.adjust_mode(0o755), 0o775);
+        assert_eq!(SharedMode::Group.adjust_mode(0o644), 0o664);
+        assert_eq!(SharedMode::All.adjust_mode(0o755), 0o775);
+        assert_eq!(SharedMode::Group.adjust_mode(0o444), 0o444);
+        assert_eq!(SharedMode::Octal(0o640).adjust_mode(0o755), 0o750);
+        assert_eq!(SharedMode::Octal(0o640).adjust_mode(0o644), 0o640);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(SharedMode::parse("sometimes").is_err());
+        assert!(SharedMode::parse("0999").is_err());
+        // Owner without write access
+        assert!(SharedMode::parse("0440").is_err());
+    }
+}
@@ -0,0 +1,1502 @@
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::modules::{hash::ObjectId, lockfile::Lockfile};
+
+/// Checks that `name` is a valid reference name, following `git check-ref-format`.
+///
+/// The name is validated as given (e.g. a branch name like `feature/x`), so
+/// one-level names are allowed. Rules:
+/// - Not empty, not the single character `@`.
+/// - No component starts with `.` or ends with `.lock`; no empty components
+///   (leading, trailing, or consecutive `/`).
+/// - No `..`, `@{`, or trailing `.`.
+/// - No control characters, spaces, or any of `~ ^ : ? * [ \`.
+pub fn validate_ref_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("reference name cannot be empty");
+    }
+
+    if name == "@" {
+        bail!("reference name cannot be '@'");
+    }
+
+    if name.contains("..") {
+        bail!("reference name cannot contain '..'");
+    }
+
+    if name.contains("@{") {
+        bail!("reference name cannot contain '@{{'");
+    }
+
+    if name.ends_with('.') {
+        bail!("reference name cannot end with '.'");
+    }
+
+    if let Some(c) = name.chars().find(|c| {
+        c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+    }) {
+        bail!("reference name cannot contain {:?}", c);
+    }
+
+    for component in name.split('/') {
+        if component.is_empty() {
+            bail!("reference name cannot contain empty path components");
+        }
+        if component.starts_with('.') {
+            bail!("reference name components cannot start with '.'");
+        }
+        if component.ends_with(".lock") {
+            bail!("reference name components cannot end with '.lock'");
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the branch `HEAD` points at, e.g. `main` for `ref: refs/heads/main`.
+///
+/// Returns `None` when `HEAD` is missing or detached (contains an object id).
+pub fn head_branch(repo_dir: &Path) -> Result<Option<String>> {
+    let head_path = repo_dir.join("HEAD");
+    if !head_path.is_file() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&head_path)
+        .with_context(|| format!("Failed to read {}", head_path.display()))?;
+
+    Ok(content
+        .trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(|branch| branch.to_string()))
+}
+
+/// Checks whether `refs/heads/<branch>` has no commit yet.
+///
+/// Note: Older versions of init wrote a bootstrap file containing `ref: refs/heads/<branch>`
+/// rather than leaving the branch missing, so a branch holding that text is unborn too.
+pub fn is_unborn(repo_dir: &Path, branch: &str) -> Result<bool> {
+    let ref_path = repo_dir.join("refs/heads").join(branch);
+    if !ref_path.is_file() {
+        return Ok(packed_ref(repo_dir, &format!("refs/heads/{}", branch))?.is_none());
+    }
+
+    let content = fs::read_to_string(&ref_path)
+        .with_context(|| format!("Failed to read {}", ref_path.display()))?;
+
+    Ok(content.trim().starts_with("ref:"))
+}
+
+/// Whether `refs/heads/<branch>` is the bootstrap file older versions of init wrote:
+/// `ref: refs/heads/<branch>`, a symbolic ref to itself that no lookup can resolve.
+pub fn is_bootstrap_ref(repo_dir: &Path, branch: &str) -> bool {
+    let ref_path = repo_dir.join("refs/heads").join(branch);
+    fs::read_to_string(ref_path)
+        .is_ok_and(|content| content.trim() == format!("ref: refs/heads/{}", branch))
+}
+
+/// Maximum number of symbolic refs followed before giving up, matching git.
+pub const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The raw content of a single reference file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefValue {
+    /// `ref: <target>` pointing at another reference.
+    Symbolic(String),
+    /// A hex object id.
+    Direct(String),
+}
+
+/// The end point of following a reference through any symbolic indirections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedRef {
+    /// The last reference in the chain, i.e. the one that holds (or would hold) the object id.
+    pub name: String,
+    /// The object id, or `None` when the chain ends at a reference that doesn't exist yet
+    /// (an unborn branch, or any other dangling symbolic ref).
+    pub oid: Option<String>,
+    /// Every reference visited, starting with the one that was asked for.
+    pub chain: Vec<String>,
+}
+
+/// What HEAD currently refers to, described honestly for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadState {
+    /// HEAD points at `refs/heads/<name>`.
+    Branch(String),
+    /// HEAD points at a reference outside `refs/heads/` (e.g. set by another tool).
+    OtherRef(String),
+    /// HEAD contains an object id directly.
+    Detached(String),
+}
+
+/// Whether `name` is HEAD or a pseudo-ref like `ORIG_HEAD` or `MERGE_HEAD`: capital
+/// letters and underscores ending in `HEAD`, kept at the top of the repository directory.
+pub fn is_pseudo_ref(name: &str) -> bool {
+    name.ends_with("HEAD") && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+}
+
+/// Checks that `name` may be read from or written to the repository directory.
+///
+/// Only `HEAD`, other pseudo-refs and names under `refs/` qualify; this keeps a
+/// malicious symbolic ref like `ref: ../../etc/passwd` from escaping the repository.
+fn check_ref_path(name: &str) -> Result<()> {
+    if is_pseudo_ref(name) {
+        return Ok(());
+    }
+    if !name.starts_with("refs/") {
+        bail!(
+            "invalid reference '{}': must be HEAD or start with refs/",
+            name
+        );
+    }
+    validate_ref_name(name).with_context(|| format!("invalid reference '{}'", name))
+}
+
+/// Reads a single reference without following symbolic refs (git's `--no-recurse`).
+///
+/// A loose reference file wins; without one, `packed-refs` is consulted. Returns `None`
+/// when the reference is in neither.
+pub fn read_ref(repo_dir: &Path, name: &str) -> Result<Option<RefValue>> {
+    check_ref_path(name)?;
+
+    let ref_path = repo_dir.join(name);
+    if !ref_path.is_file() {
+        return Ok(packed_ref(repo_dir, name)?.map(|packed| RefValue::Direct(packed.oid)));
+    }
+
+    let content = fs::read_to_string(&ref_path)
+        .with_context(|| format!("Failed to read {}", ref_path.display()))?;
+    let content = content.trim();
+
+    if let Some(target) = content.strip_prefix("ref:") {
+        let target = target.trim();
+        check_ref_path(target).with_context(|| format!("bad symbolic ref {}", name))?;
+        return Ok(Some(RefValue::Symbolic(target.to_string())));
+    }
+
+    if content.is_empty() || !content.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("reference {} is broken: '{}'", name, content);
+    }
+
+    Ok(Some(RefValue::Direct(content.to_lowercase())))
+}
+
+/// Follows `name` through symbolic refs until reaching an object id or a missing reference.
+///
+/// Fails with "too many levels of symbolic refs" after `MAX_SYMREF_DEPTH` hops,
+/// which also catches cycles.
+pub fn resolve_ref(repo_dir: &Path, name: &str) -> Result<ResolvedRef> {
+    let mut current = name.to_string();
+    let mut chain = vec![current.clone()];
+
+    loop {
+        match read_ref(repo_dir, &current)? {
+            None => {
+                return Ok(ResolvedRef {
+                    name: current,
+                    oid: None,
+                    chain,
+                });
+            }
+            Some(RefValue::Direct(oid)) => {
+                return Ok(ResolvedRef {
+                    name: current,
+                    oid: Some(oid),
+                    chain,
+                });
+            }
+            Some(RefValue::Symbolic(target)) => {
+                if chain.len() > MAX_SYMREF_DEPTH {
+                    bail!(
+                        "too many levels of symbolic refs while resolving {}: {}",
+                        name,
+                        chain.join(" -> ")
+                    );
+                }
+                chain.push(target.clone());
+                current = target;
+            }
+        }
+    }
+}
+
+/// Describes HEAD, or returns `None` when HEAD is missing.
+pub fn head_state(repo_dir: &Path) -> Result<Option<HeadState>> {
+    Ok(read_ref(repo_dir, "HEAD")?.map(|value| match value {
+        RefValue::Direct(oid) => HeadState::Detached(oid),
+        RefValue::Symbolic(target) => match target.strip_prefix("refs/heads/") {
+            Some(branch) => HeadState::Branch(branch.to_string()),
+            None => HeadState::OtherRef(target),
+        },
+    }))
+}
+
+/// Every reference under `prefix` (e.g. `refs/heads/`) with the id it resolves to,
+/// sorted by name, whether loose or packed.
+///
+/// Note: Lock files and other names that aren't valid references are skipped, as are
+/// symbolic refs to references that don't exist.
+pub fn list_refs(repo_dir: &Path, prefix: &str) -> Result<Vec<(String, String)>> {
+    let dir = prefix.trim_end_matches('/');
+    let mut loose = Vec::new();
+    collect_ref_names(repo_dir, dir, &mut loose)?;
+    let packed = read_packed_refs(repo_dir)?
+        .into_iter()
+        .map(|packed| packed.name)
+        .filter(|name| {
+            name.strip_prefix(dir)
+                .is_some_and(|rest| rest.starts_with('/'))
+        });
+    let names: BTreeSet<String> = loose.into_iter().chain(packed).collect();
+
+    let mut refs = Vec::new();
+    for name in names {
+        if check_ref_path(&name).is_err() {
+            continue;
+        }
+        if let Some(oid) = resolve_ref(repo_dir, &name)?.oid {
+            refs.push((name, oid));
+        }
+    }
+    Ok(refs)
+}
+
+fn collect_ref_names(repo_dir: &Path, dir: &str, names: &mut Vec<String>) -> Result<()> {
+    let path = repo_dir.join(dir);
+    let entries = match fs::read_dir(&path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", path.display()))?;
+        let Some(name) = entry
+            .file_name()
+            .to_str()
+            .map(|name| format!("{}/{}", dir, name))
+        else {
+            continue;
+        };
+        if entry.file_type()?.is_dir() {
+            collect_ref_names(repo_dir, &name, names)?;
+        } else {
+            names.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// What a reference must hold for `update_ref` or `delete_ref` to go ahead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedOld {
+    /// Whatever it holds, including nothing.
+    Any,
+    /// It must not exist yet, so an update only ever creates it.
+    Missing,
+    /// It must hold this object id.
+    Oid(ObjectId),
+}
+
+/// A compare-and-swap update refused because the reference changed underneath it.
+#[derive(Debug)]
+pub struct RefMismatch {
+    pub name: String,
+    pub expected: ExpectedOld,
+    /// What the reference actually holds; `None` when it doesn't exist.
+    pub actual: Option<String>,
+}
+
+impl std::fmt::Display for RefMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot lock ref '{}': ", self.name)?;
+        match (&self.expected, &self.actual) {
+            (ExpectedOld::Oid(expected), Some(actual)) => {
+                write!(f, "is at {} but expected {}", actual, expected)
+            }
+            (ExpectedOld::Oid(expected), None) => {
+                write!(f, "expected {} but it does not exist", expected)
+            }
+            (_, Some(actual)) => write!(f, "reference already exists at {}", actual),
+            (_, None) => write!(f, "reference changed unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for RefMismatch {}
+
+/// Points `name` at `new_oid` if it currently holds what `expected` says, returning the
+/// id it held before (`None` when it was created).
+///
+/// Symbolic refs are followed, so updating `HEAD` on a branch moves the branch, and an
+/// unborn branch is created. A mismatch fails with a `RefMismatch`.
+///
+/// Note: The check happens after taking `<ref>.lock` and the new value is moved into
+/// place before the lock is released, so two writers can never both succeed against
+/// the same old value.
+pub fn update_ref(
+    repo_dir: &Path,
+    name: &str,
+    new_oid: &ObjectId,
+    expected: &ExpectedOld,
+) -> Result<Option<String>> {
+    let target = resolve_ref(repo_dir, name)?.name;
+    let mut lock = lock_ref(repo_dir, &target)?;
+    let old = checked_old_value(repo_dir, &target, expected)?;
+
+    lock.write(format!("{}\n", new_oid).as_bytes())?;
+    lock.commit()?;
+    Ok(old)
+}
+
+/// Removes `name` (or the reference it points at) if it holds what `expected` says,
+/// returning the id it held; `None` when there was nothing to delete.
+///
+/// The reference is removed from `packed-refs` too.
+///
+/// Note: Directories emptied by the removal are pruned, but never `refs/heads` and
+/// its siblings, which init creates.
+pub fn delete_ref(repo_dir: &Path, name: &str, expected: &ExpectedOld) -> Result<Option<String>> {
+    let target = resolve_ref(repo_dir, name)?.name;
+    let lock = lock_ref(repo_dir, &target)?;
+    let old = checked_old_value(repo_dir, &target, expected)?;
+
+    // Note: The packed value goes first, so it can't show through once the loose file
+    // is gone, and a failure leaves the reference as it was.
+    if old.is_some() {
+        remove_packed_ref(repo_dir, &target)?;
+    }
+    let ref_path = repo_dir.join(&target);
+    if ref_path.is_file() {
+        fs::remove_file(&ref_path)
+            .with_context(|| format!("Failed to remove {}", ref_path.display()))?;
+    }
+    drop(lock);
+
+    prune_ref_dirs(repo_dir, &ref_path);
+    Ok(old)
+}
+
+/// Removes the symbolic ref `name` itself, not the reference it points at, returning
+/// its target; fails if `name` isn't symbolic.
+///
+/// Note: Symbolic refs are never packed, so only the loose file has to go.
+pub fn delete_symbolic_ref(repo_dir: &Path, name: &str) -> Result<String> {
+    let lock = lock_ref(repo_dir, name)?;
+    let Some(RefValue::Symbolic(target)) = read_ref(repo_dir, name)? else {
+        bail!("cannot delete {}: not a symbolic ref", name);
+    };
+    let ref_path = repo_dir.join(name);
+    fs::remove_file(&ref_path)
+        .with_context(|| format!("Failed to remove {}", ref_path.display()))?;
+    drop(lock);
+
+    prune_ref_dirs(repo_dir, &ref_path);
+    Ok(target)
+}
+
+/// Removes the directories a removed reference at `ref_path` leaves empty, but never
+/// `refs/heads` and its siblings, which init creates.
+fn prune_ref_dirs(repo_dir: &Path, ref_path: &Path) {
+    let keep = repo_dir.join("refs");
+    let mut dir = ref_path.parent();
+    while let Some(parent) = dir {
+        if !parent.starts_with(&keep) || parent == keep || parent.parent() == Some(&keep) {
+            break;
+        }
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+/// The file references are packed into, at the top of the repository directory.
+pub const PACKED_REFS: &str = "packed-refs";
+
+/// The first line of the `packed-refs` that `pack_refs` writes: every annotated tag in it
+/// has its peeled line, and the references are sorted.
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted ";
+
+/// One reference stored in `packed-refs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedRef {
+    pub name: String,
+    pub oid: String,
+    /// For an annotated tag, the object it finally points at (its `^<oid>` line).
+    pub peeled: Option<String>,
+}
+
+/// Reads `packed-refs`, sorted by name; a missing file packs nothing.
+pub fn read_packed_refs(repo_dir: &Path) -> Result<Vec<PackedRef>> {
+    let path = repo_dir.join(PACKED_REFS);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    parse_packed_refs(&content).with_context(|| format!("corrupt {}", path.display()))
+}
+
+/// The packed entry for `name`, if there is one.
+fn packed_ref(repo_dir: &Path, name: &str) -> Result<Option<PackedRef>> {
+    Ok(read_packed_refs(repo_dir)?
+        .into_iter()
+        .find(|packed| packed.name == name))
+}
+
+/// Parses `packed-refs`: `<oid> <name>` lines, each optionally followed by a `^<oid>`
+/// line with the peeled object, and `#` lines such as the header.
+fn parse_packed_refs(content: &str) -> Result<Vec<PackedRef>> {
+    let packed_oid = |text: &str| -> Result<String> { Ok(ObjectId::from_hex(text)?.to_hex()) };
+
+    let mut refs: Vec<PackedRef> = Vec::new();
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            let Some(last) = refs.last_mut() else {
+                bail!("peeled line before any reference: '{}'", line);
+            };
+            last.peeled = Some(packed_oid(peeled)?);
+            continue;
+        }
+        let Some((oid, name)) = line.split_once(' ') else {
+            bail!("unexpected line '{}'", line);
+        };
+        if !name.starts_with("refs/") {
+            bail!("invalid packed reference '{}'", name);
+        }
+        check_ref_path(name)?;
+        refs.push(PackedRef {
+            name: name.to_string(),
+            oid: packed_oid(oid)?,
+            peeled: None,
+        });
+    }
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(refs)
+}
+
+/// `refs` in the `packed-refs` format, which `refs` must already be sorted for.
+fn serialize_packed_refs<'a>(refs: impl IntoIterator<Item = &'a PackedRef>) -> String {
+    let mut content = format!("{}\n", PACKED_REFS_HEADER);
+    for packed in refs {
+        content.push_str(&format!("{} {}\n", packed.oid, packed.name));
+        if let Some(peeled) = &packed.peeled {
+            content.push_str(&format!("^{}\n", peeled));
+        }
+    }
+    content
+}
+
+/// Drops `name` from `packed-refs`, if it's there.
+///
+/// Note: The lock is taken even when there is nothing to remove, so a delete fails
+/// rather than being undone by a `pack_refs` that is writing the file right now.
+fn remove_packed_ref(repo_dir: &Path, name: &str) -> Result<()> {
+    let mut lock = Lockfile::acquire(&repo_dir.join(PACKED_REFS))
+        .with_context(|| format!("cannot delete ref '{}': packed-refs is locked", name))?;
+    let mut refs = read_packed_refs(repo_dir)?;
+    let before = refs.len();
+    refs.retain(|packed| packed.name != name);
+    if refs.len() == before {
+        return Ok(());
+    }
+    lock.write(serialize_packed_refs(&refs).as_bytes())?;
+    lock.commit()
+}
+
+/// Moves the loose references into `packed-refs`, returning the names packed: every
+/// reference with `all`, otherwise only tags. `peel` gives the object an annotated tag
+/// finally points at, and `None` for any other object.
+///
+/// Symbolic refs stay loose. References packed before are kept.
+///
+/// Note: `packed-refs` is written first, then each loose file is removed under its
+/// ref's lock, and only if it still holds what was packed: a reference updated in the
+/// meantime stays loose, and so keeps its newer value.
+pub fn pack_refs(
+    repo_dir: &Path,
+    all: bool,
+    peel: impl Fn(&str) -> Result<Option<String>>,
+) -> Result<Vec<String>> {
+    let mut lock =
+        Lockfile::acquire(&repo_dir.join(PACKED_REFS)).context("cannot lock packed-refs")?;
+    let mut packed: BTreeMap<String, PackedRef> = read_packed_refs(repo_dir)?
+        .into_iter()
+        .map(|packed| (packed.name.clone(), packed))
+        .collect();
+
+    let mut names = Vec::new();
+    collect_ref_names(repo_dir, "refs", &mut names)?;
+    names.sort();
+    let mut loose = Vec::new();
+    for name in names {
+        if check_ref_path(&name).is_err() || !(all || name.starts_with("refs/tags/")) {
+            continue;
+        }
+        let Some(RefValue::Direct(oid)) = read_ref(repo_dir, &name)? else {
+            continue;
+        };
+        let peeled = peel(&oid)?;
+        packed.insert(
+            name.clone(),
+            PackedRef {
+                name: name.clone(),
+                oid: oid.clone(),
+                peeled,
+            },
+        );
+        loose.push((name, oid));
+    }
+    lock.write(serialize_packed_refs(packed.values()).as_bytes())?;
+    lock.commit()?;
+
+    for (name, oid) in &loose {
+        // A reference being updated right now is left to its writer.
+        let Ok(ref_lock) = lock_ref(repo_dir, name) else {
+            continue;
+        };
+        let ref_path = repo_dir.join(name);
+        if ref_path.is_file() && read_ref(repo_dir, name)? == Some(RefValue::Direct(oid.clone())) {
+            fs::remove_file(&ref_path)
+                .with_context(|| format!("Failed to remove {}", ref_path.display()))?;
+        }
+        drop(ref_lock);
+        prune_ref_dirs(repo_dir, &ref_path);
+    }
+    Ok(loose.into_iter().map(|(name, _)| name).collect())
+}
+
+/// A symbolic ref refused because its target is not under `refs/`.
+#[derive(Debug)]
+pub struct OutsideRefs {
+    pub name: String,
+    pub target: String,
+}
+
+impl std::fmt::Display for OutsideRefs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to point {} outside refs/: {}",
+            self.name, self.target
+        )
+    }
+}
+
+impl std::error::Error for OutsideRefs {}
+
+/// Makes `name` (usually `HEAD`) a symbolic ref pointing at `target`.
+///
+/// Only names under `refs/` are valid targets, so HEAD can't be pointed at arbitrary
+/// files (that fails with `OutsideRefs`); the target doesn't have to exist (a new,
+/// unborn branch).
+pub fn write_symbolic_ref(repo_dir: &Path, name: &str, target: &str) -> Result<()> {
+    check_symbolic_target(name, target)?;
+    let mut lock = lock_ref(repo_dir, name)?;
+    lock.write(format!("ref: {}\n", target).as_bytes())?;
+    lock.commit()
+}
+
+/// Makes `name` (usually `HEAD`) hold `oid` itself, replacing a symbolic ref rather than
+/// following it: HEAD becomes detached and the branch it was on is left alone.
+pub fn write_detached_ref(repo_dir: &Path, name: &str, oid: &ObjectId) -> Result<()> {
+    let mut lock = lock_ref(repo_dir, name)?;
+    lock.write(format!("{}\n", oid).as_bytes())?;
+    lock.commit()
+}
+
+/// Checks that `name` may point at `target`, as `write_symbolic_ref` does before writing.
+pub fn check_symbolic_target(name: &str, target: &str) -> Result<()> {
+    if !target.starts_with("refs/") {
+        return Err(OutsideRefs {
+            name: name.to_string(),
+            target: target.to_string(),
+        }
+        .into());
+    }
+    check_ref_path(target)
+}
+
+/// Takes `<ref>.lock` for `name`, creating the directories a new reference needs.
+fn lock_ref(repo_dir: &Path, name: &str) -> Result<Lockfile> {
+    check_ref_path(name)?;
+    let ref_path = repo_dir.join(name);
+    if let Some(parent) = ref_path.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("cannot lock ref '{}': failed to create {:?}", name, parent)
+        })?;
+    }
+    Lockfile::acquire(&ref_path).with_context(|| format!("cannot lock ref '{}'", name))
+}
+
+/// Reads the object id `name` holds, failing with a `RefMismatch` unless it is `expected`.
+fn checked_old_value(
+    repo_dir: &Path,
+    name: &str,
+    expected: &ExpectedOld,
+) -> Result<Option<String>> {
+    let actual = match read_ref(repo_dir, name)? {
+        Some(RefValue::Direct(oid)) => Some(oid),
+        Some(RefValue::Symbolic(target)) => {
+            bail!(
+                "cannot lock ref '{}': it is a symbolic ref to {}",
+                name,
+                target
+            )
+        }
+        None => None,
+    };
+
+    let matches = match expected {
+        ExpectedOld::Any => true,
+        ExpectedOld::Missing => actual.is_none(),
+        ExpectedOld::Oid(oid) => actual.as_deref() == Some(oid.to_hex().as_str()),
+    };
+    if !matches {
+        return Err(RefMismatch {
+            name: name.to_string(),
+            expected: expected.clone(),
+            actual,
+        }
+        .into());
+    }
+    Ok(actual)
+}
+
+/// One line of a reflog (`logs/HEAD`, `logs/refs/heads/<branch>`, ...).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// The previous object id; the null id when the ref was just created.
+    pub old_oid: String,
+    pub new_oid: String,
+    /// `Name <email>` of whoever made the update.
+    pub identity: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The committer's UTC offset, e.g. `+0200`.
+    pub tz: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Formats the entry the way git writes it: `old new identity timestamp tz\tmessage`.
+    ///
+    /// Note: Each entry must stay on one line, so newlines in the message become spaces.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {} {}\t{}\n",
+            self.old_oid,
+            self.new_oid,
+            self.identity,
+            self.timestamp,
+            self.tz,
+            self.message.trim().replace('\n', " ")
+        )
+    }
+}
+
+impl ReflogEntry {
+    /// Parses a reflog line as `to_line` writes it, without its newline.
+    pub fn parse(line: &str) -> Result<Self> {
+        let malformed = || anyhow::anyhow!("malformed reflog line '{}'", line);
+        let (fields, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old_oid, rest) = fields.split_once(' ').ok_or_else(malformed)?;
+        let (new_oid, rest) = rest.split_once(' ').ok_or_else(malformed)?;
+        let mut rest = rest.rsplitn(3, ' ');
+        let (Some(tz), Some(timestamp), Some(identity)) = (rest.next(), rest.next(), rest.next())
+        else {
+            return Err(malformed());
+        };
+        for oid in [old_oid, new_oid] {
+            ObjectId::from_hex(oid).map_err(|_| malformed())?;
+        }
+        Ok(ReflogEntry {
+            old_oid: old_oid.to_lowercase(),
+            new_oid: new_oid.to_lowercase(),
+            identity: identity.to_string(),
+            timestamp: timestamp.parse().map_err(|_| malformed())?,
+            tz: tz.to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+/// The reflog of `name`, oldest entry first; empty when there is none.
+pub fn read_reflog(repo_dir: &Path, name: &str) -> Result<Vec<ReflogEntry>> {
+    check_ref_path(name)?;
+    let log_path = repo_dir.join("logs").join(name);
+    let content = match fs::read_to_string(&log_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", log_path)),
+    };
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| ReflogEntry::parse(line).with_context(|| format!("corrupt {:?}", log_path)))
+        .collect()
+}
+
+/// Where, under `logs/`, the reflogs of deleted references are kept; no reference's
+/// log can live there, since they are all `HEAD`-like or under `refs/`.
+pub const DELETED_LOGS_DIR: &str = "deleted";
+
+/// Moves the reflog of the deleted reference `name` to `logs/deleted/<name>`, after
+/// whatever earlier references of that name left there, so its history isn't lost.
+pub fn set_aside_reflog(repo_dir: &Path, name: &str) -> Result<()> {
+    check_ref_path(name)?;
+    let logs = repo_dir.join("logs");
+    let log_path = logs.join(name);
+    let content = match fs::read(&log_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", log_path)),
+    };
+
+    let aside = logs.join(DELETED_LOGS_DIR).join(name);
+    if let Some(parent) = aside.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create dir {:?}", parent))?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&aside)
+        .and_then(|mut file| file.write_all(&content))
+        .with_context(|| format!("Failed to append to {:?}", aside))?;
+    fs::remove_file(&log_path).with_context(|| format!("Failed to remove {:?}", log_path))?;
+
+    // Note: Emptied directories go, as they do for the reference itself.
+    let mut dir = log_path.parent();
+    while let Some(parent) = dir {
+        if parent == logs || parent == logs.join("refs") || fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+/// Whether updates to `name` get a reflog entry: always once it has a reflog, and
+/// with `core.logallrefupdates` also for HEAD and branches, like git.
+pub fn should_log_ref(repo_dir: &Path, name: &str, log_all_ref_updates: bool) -> bool {
+    let logged_by_default = name == "HEAD"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+    (log_all_ref_updates && logged_by_default) || repo_dir.join("logs").join(name).is_file()
+}
+
+/// Appends `entry` to the reflog of `name` (`HEAD` or a name under `refs/`),
+/// creating `logs/` and any missing parent directories on the way.
+pub fn append_reflog(repo_dir: &Path, name: &str, entry: &ReflogEntry) -> Result<()> {
+    check_ref_path(name)?;
+
+    let log_path = repo_dir.join("logs").join(name);
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create dir {:?}", parent))?;
+    }
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .and_then(|mut file| file.write_all(entry.to_line().as_bytes()))
+        .with_context(|| format!("Failed to append to {:?}", log_path))
+}
+
+/// Replaces the reflog of `name` with `entries`, oldest first, as dropping a stash
+/// entry does.
+///
+/// Note: The log is rewritten under its own lock, so a reader never sees half of it.
+pub fn write_reflog(repo_dir: &Path, name: &str, entries: &[ReflogEntry]) -> Result<()> {
+    check_ref_path(name)?;
+    let log_path = repo_dir.join("logs").join(name);
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create dir {:?}", parent))?;
+    }
+    let mut lock = Lockfile::acquire(&log_path)?;
+    let content: String = entries.iter().map(ReflogEntry::to_line).collect();
+    lock.write(content.as_bytes())?;
+    lock.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_head_branch_and_unborn() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+
+        assert_eq!(head_branch(repo).unwrap(), None);
+
+        fs::write(repo.join("HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(head_branch(repo).unwrap().as_deref(), Some("feature/x"));
+        assert!(is_unborn(repo, "feature/x").unwrap());
+
+        fs::create_dir_all(repo.join("refs/heads/feature")).unwrap();
+        fs::write(
+            repo.join("refs/heads/feature/x"),
+            "ref: refs/heads/feature/x",
+        )
+        .unwrap();
+        assert!(is_unborn(repo, "feature/x").unwrap());
+
+        fs::write(
+            repo.join("refs/heads/feature/x"),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n",
+        )
+        .unwrap();
+        assert!(!is_unborn(repo, "feature/x").unwrap());
+
+        fs::write(
+            repo.join("HEAD"),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n",
+        )
+        .unwrap();
+        assert_eq!(head_branch(repo).unwrap(), None);
+    }
+
+    #[test]
+    fn test_validate_ref_name_valid() {
+        for name in [
+            "main",
+            "master",
+            "feature/x",
+            "feature/nested/deep",
+            "release-1.0",
+            "user_name",
+            "v1.2.3",
+            "a@b",
+            "UPPER",
+            "x.lockfile",
+        ] {
+            assert!(
+                validate_ref_name(name).is_ok(),
+                "expected '{}' to be valid",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_ref_name_invalid() {
+        for name in [
+            "",
+            "@",
+            "foo..bar",
+            "with space",
+            "tilde~1",
+            "caret^",
+            "colon:name",
+            "question?",
+            "star*",
+            "bracket[",
+            "back\\slash",
+            "trailing/",
+            "/leading",
+            "double//slash",
+            "ends.",
+            "has@{brace",
+            ".hidden",
+            "dir/.hidden",
+            "branch.lock",
+            "dir/branch.lock/x",
+            "ctrl\u{7}char",
+            "tab\tchar",
+            "del\u{7f}",
+        ] {
+            assert!(
+                validate_ref_name(name).is_err(),
+                "expected '{:?}' to be invalid",
+                name
+            );
+        }
+    }
+
+    const OID: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    fn write_refs(repo: &Path, refs: &[(&str, &str)]) {
+        for (name, content) in refs {
+            let path = repo.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("{}\n", content)).unwrap();
+        }
+    }
+
+    /// (refs on disk, ref to resolve, expected final name, expected oid, expected chain length)
+    type ResolveCase<'a> = (
+        &'a [(&'a str, &'a str)],
+        &'a str,
+        &'a str,
+        Option<&'a str>,
+        usize,
+    );
+
+    #[test]
+    fn test_resolve_ref_chains() {
+        let cases: &[ResolveCase] = &[
+            // Depth 1: HEAD -> branch
+            (
+                &[("HEAD", "ref: refs/heads/main"), ("refs/heads/main", OID)],
+                "HEAD",
+                "refs/heads/main",
+                Some(OID),
+                2,
+            ),
+            // Depth 2: HEAD -> symref -> branch
+            (
+                &[
+                    ("HEAD", "ref: refs/custom/alias"),
+                    ("refs/custom/alias", "ref: refs/heads/main"),
+                    ("refs/heads/main", OID),
+                ],
+                "HEAD",
+                "refs/heads/main",
+                Some(OID),
+                3,
+            ),
+            // Depth 3
+            (
+                &[
+                    ("HEAD", "ref: refs/a"),
+                    ("refs/a", "ref: refs/b"),
+                    ("refs/b", "ref: refs/heads/main"),
+                    ("refs/heads/main", OID),
+                ],
+                "HEAD",
+                "refs/heads/main",
+                Some(OID),
+                4,
+            ),
+            // Dangling: unborn branch
+            (
+                &[("HEAD", "ref: refs/heads/unborn")],
+                "HEAD",
+                "refs/heads/unborn",
+                None,
+                2,
+            ),
+            // Dangling: HEAD outside refs/heads
+            (
+                &[("HEAD", "ref: refs/custom/x")],
+                "HEAD",
+                "refs/custom/x",
+                None,
+                2,
+            ),
+            // Dangling at the end of a longer chain
+            (
+                &[
+                    ("HEAD", "ref: refs/custom/alias"),
+                    ("refs/custom/alias", "ref: refs/heads/gone"),
+                ],
+                "HEAD",
+                "refs/heads/gone",
+                None,
+                3,
+            ),
+            // Direct ref, no indirection
+            (
+                &[("refs/tags/v1", OID)],
+                "refs/tags/v1",
+                "refs/tags/v1",
+                Some(OID),
+                1,
+            ),
+        ];
+
+        for (refs, name, expected_name, expected_oid, chain_len) in cases {
+            let dir = tempdir().unwrap();
+            write_refs(dir.path(), refs);
+
+            let resolved = resolve_ref(dir.path(), name).unwrap();
+            assert_eq!(resolved.name, *expected_name, "refs: {:?}", refs);
+            assert_eq!(resolved.oid.as_deref(), *expected_oid, "refs: {:?}", refs);
+            assert_eq!(resolved.chain.len(), *chain_len, "refs: {:?}", refs);
+        }
+    }
+
+    #[test]
+    fn test_resolve_ref_errors() {
+        let cases: &[&[(&str, &str)]] = &[
+            // Cycle
+            &[
+                ("HEAD", "ref: refs/a"),
+                ("refs/a", "ref: refs/b"),
+                ("refs/b", "ref: refs/a"),
+            ],
+            // Too deep
+            &[
+                ("HEAD", "ref: refs/1"),
+                ("refs/1", "ref: refs/2"),
+                ("refs/2", "ref: refs/3"),
+                ("refs/3", "ref: refs/4"),
+                ("refs/4", "ref: refs/5"),
+                ("refs/5", "ref: refs/6"),
+                ("refs/6", OID),
+            ],
+            // Escaping the repository
+            &[("HEAD", "ref: ../outside")],
+            // Garbage content
+            &[("HEAD", "not a ref")],
+        ];
+
+        for refs in cases {
+            let dir = tempdir().unwrap();
+            write_refs(dir.path(), refs);
+            assert!(resolve_ref(dir.path(), "HEAD").is_err(), "refs: {:?}", refs);
+        }
+    }
+
+    #[test]
+    fn test_pseudo_refs() {
+        for name in ["HEAD", "ORIG_HEAD", "MERGE_HEAD"] {
+            assert!(is_pseudo_ref(name), "{}", name);
+        }
+        for name in [
+            "config",
+            "head",
+            "ORIG_HEAD/x",
+            "../HEAD",
+            "refs/HEAD",
+            "HEADS",
+        ] {
+            assert!(!is_pseudo_ref(name), "{}", name);
+        }
+
+        let dir = tempdir().unwrap();
+        write_refs(dir.path(), &[("ORIG_HEAD", OID), ("config", OID)]);
+        let resolved = resolve_ref(dir.path(), "ORIG_HEAD").unwrap();
+        assert_eq!(resolved.oid.as_deref(), Some(OID));
+        assert!(read_ref(dir.path(), "config").is_err());
+    }
+
+    #[test]
+    fn test_head_state() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+
+        assert_eq!(head_state(repo).unwrap(), None);
+
+        write_refs(repo, &[("HEAD", "ref: refs/heads/main")]);
+        assert_eq!(
+            head_state(repo).unwrap(),
+            Some(HeadState::Branch("main".to_string()))
+        );
+
+        write_refs(repo, &[("HEAD", "ref: refs/custom/x")]);
+        assert_eq!(
+            head_state(repo).unwrap(),
+            Some(HeadState::OtherRef("refs/custom/x".to_string()))
+        );
+
+        write_refs(repo, &[("HEAD", OID)]);
+        assert_eq!(
+            head_state(repo).unwrap(),
+            Some(HeadState::Detached(OID.to_string()))
+        );
+    }
+
+    fn oid(hex: &str) -> ObjectId {
+        ObjectId::from_hex(hex).unwrap()
+    }
+
+    const OTHER: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+
+    #[test]
+    fn test_update_ref_through_unborn_head() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        fs::create_dir_all(repo.join("refs/heads")).unwrap();
+        write_symbolic_ref(repo, "HEAD", "refs/heads/main").unwrap();
+
+        // HEAD dangles onto an unborn branch until the first update creates it
+        let resolved = resolve_ref(repo, "HEAD").unwrap();
+        assert_eq!(
+            (resolved.name.as_str(), resolved.oid),
+            ("refs/heads/main", None)
+        );
+        assert!(is_unborn(repo, "main").unwrap());
+
+        let old = update_ref(repo, "HEAD", &oid(OID), &ExpectedOld::Missing).unwrap();
+        assert_eq!(old, None);
+        assert_eq!(
+            fs::read_to_string(repo.join("refs/heads/main")).unwrap(),
+            format!("{}\n", OID)
+        );
+        assert_eq!(
+            read_ref(repo, "HEAD").unwrap(),
+            Some(RefValue::Symbolic("refs/heads/main".to_string()))
+        );
+        assert!(!is_unborn(repo, "main").unwrap());
+
+        let old = update_ref(
+            repo,
+            "refs/heads/main",
+            &oid(OTHER),
+            &ExpectedOld::Oid(oid(OID)),
+        )
+        .unwrap();
+        assert_eq!(old.as_deref(), Some(OID));
+        assert_eq!(
+            resolve_ref(repo, "HEAD").unwrap().oid.as_deref(),
+            Some(OTHER)
+        );
+
+        // Nested names get their directories; no lock file is left behind
+        update_ref(repo, "refs/heads/feature/x", &oid(OID), &ExpectedOld::Any).unwrap();
+        assert_eq!(
+            fs::read_dir(repo.join("refs/heads/feature"))
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_update_ref_compare_and_swap() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        update_ref(repo, "refs/heads/main", &oid(OID), &ExpectedOld::Any).unwrap();
+
+        let mismatches = [
+            (ExpectedOld::Oid(oid(OTHER)), "is at"),
+            (ExpectedOld::Missing, "already exists"),
+        ];
+        for (expected, message) in mismatches {
+            let err = update_ref(repo, "refs/heads/main", &oid(OTHER), &expected).unwrap_err();
+            let mismatch = err.downcast_ref::<RefMismatch>().expect("a RefMismatch");
+            assert_eq!(mismatch.actual.as_deref(), Some(OID));
+            assert!(err.to_string().contains(message), "{}", err);
+            assert_eq!(
+                resolve_ref(repo, "refs/heads/main").unwrap().oid.as_deref(),
+                Some(OID)
+            );
+        }
+        let err = update_ref(
+            repo,
+            "refs/heads/new",
+            &oid(OID),
+            &ExpectedOld::Oid(oid(OID)),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+        assert!(!repo.join("refs/heads/new").exists());
+
+        // A held lock turns other writers away
+        let lock = Lockfile::acquire(&repo.join("refs/heads/main")).unwrap();
+        assert!(update_ref(repo, "refs/heads/main", &oid(OTHER), &ExpectedOld::Any).is_err());
+        drop(lock);
+
+        // Racing writers expecting the same old value: exactly one wins
+        let outcomes: Vec<bool> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        update_ref(
+                            repo,
+                            "refs/heads/main",
+                            &oid(OTHER),
+                            &ExpectedOld::Oid(oid(OID)),
+                        )
+                        .is_ok()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(outcomes.iter().filter(|&&ok| ok).count(), 1);
+        assert_eq!(
+            resolve_ref(repo, "refs/heads/main").unwrap().oid.as_deref(),
+            Some(OTHER)
+        );
+    }
+
+    #[test]
+    fn test_delete_ref() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        fs::create_dir_all(repo.join("refs/heads")).unwrap();
+        update_ref(repo, "refs/heads/feature/x", &oid(OID), &ExpectedOld::Any).unwrap();
+
+        let err =
+            delete_ref(repo, "refs/heads/feature/x", &ExpectedOld::Oid(oid(OTHER))).unwrap_err();
+        assert!(err.downcast_ref::<RefMismatch>().is_some());
+        assert!(repo.join("refs/heads/feature/x").exists());
+
+        let old = delete_ref(repo, "refs/heads/feature/x", &ExpectedOld::Oid(oid(OID))).unwrap();
+        assert_eq!(old.as_deref(), Some(OID));
+        // Emptied directories go, but refs/heads stays
+        assert!(!repo.join("refs/heads/feature").exists());
+        assert!(repo.join("refs/heads").is_dir());
+        assert_eq!(
+            delete_ref(repo, "refs/heads/feature/x", &ExpectedOld::Any).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_delete_symbolic_ref() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        fs::create_dir_all(repo.join("refs/heads")).unwrap();
+        update_ref(repo, "refs/heads/main", &oid(OID), &ExpectedOld::Any).unwrap();
+        write_symbolic_ref(repo, "refs/remotes/origin/HEAD", "refs/heads/main").unwrap();
+
+        // A direct reference is refused, not followed
+        let err = delete_symbolic_ref(repo, "refs/heads/main").unwrap_err();
+        assert!(err.to_string().contains("not a symbolic ref"), "{}", err);
+
+        let target = delete_symbolic_ref(repo, "refs/remotes/origin/HEAD").unwrap();
+        assert_eq!(target, "refs/heads/main");
+        assert!(!repo.join("refs/remotes/origin").exists());
+        assert_eq!(
+            resolve_ref(repo, "refs/heads/main").unwrap().oid.as_deref(),
+            Some(OID)
+        );
+        assert!(delete_symbolic_ref(repo, "refs/remotes/origin/HEAD").is_err());
+    }
+
+    #[test]
+    fn test_list_refs() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        assert!(list_refs(repo, "refs/heads/").unwrap().is_empty());
+
+        write_refs(
+            repo,
+            &[
+                ("refs/heads/main", OID),
+                ("refs/heads/feature/x", OTHER),
+                ("refs/heads/alias", "ref: refs/heads/main"),
+                ("refs/heads/dangling", "ref: refs/heads/nope"),
+                ("refs/heads/main.lock", OTHER),
+                ("refs/tags/v1", OID),
+            ],
+        );
+        let refs = list_refs(repo, "refs/heads/").unwrap();
+        assert_eq!(
+            refs,
+            [
+                ("refs/heads/alias".to_string(), OID.to_string()),
+                ("refs/heads/feature/x".to_string(), OTHER.to_string()),
+                ("refs/heads/main".to_string(), OID.to_string()),
+            ]
+        );
+        assert_eq!(list_refs(repo, "refs/").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_packed_refs() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        fs::write(
+            repo.join(PACKED_REFS),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n\
+                 {OID} refs/heads/packed\n\
+                 {OTHER} refs/heads/main\n\
+                 {OTHER} refs/tags/v1\n\
+                 ^{OID}\n"
+            ),
+        )
+        .unwrap();
+        write_refs(
+            repo,
+            &[("refs/heads/main", OID), ("HEAD", "ref: refs/heads/packed")],
+        );
+
+        let packed = read_packed_refs(repo).unwrap();
+        assert_eq!(packed.len(), 3);
+        assert_eq!(packed[2].name, "refs/tags/v1");
+        assert_eq!(packed[2].peeled.as_deref(), Some(OID));
+
+        // A packed-only ref resolves, and a loose one wins over its packed value
+        assert_eq!(resolve_ref(repo, "HEAD").unwrap().oid.as_deref(), Some(OID));
+        assert_eq!(
+            read_ref(repo, "refs/heads/main").unwrap(),
+            Some(RefValue::Direct(OID.to_string()))
+        );
+        assert!(!is_unborn(repo, "packed").unwrap());
+        assert_eq!(
+            list_refs(repo, "refs/heads").unwrap(),
+            [
+                ("refs/heads/main".to_string(), OID.to_string()),
+                ("refs/heads/packed".to_string(), OID.to_string()),
+            ]
+        );
+
+        // Deleting takes the ref out of packed-refs too, so nothing shows through
+        let old = delete_ref(repo, "refs/heads/main", &ExpectedOld::Oid(oid(OID))).unwrap();
+        assert_eq!(old.as_deref(), Some(OID));
+        assert_eq!(read_ref(repo, "refs/heads/main").unwrap(), None);
+        let old = delete_ref(repo, "refs/heads/packed", &ExpectedOld::Any).unwrap();
+        assert_eq!(old.as_deref(), Some(OID));
+        assert_eq!(read_ref(repo, "refs/heads/packed").unwrap(), None);
+        assert_eq!(read_packed_refs(repo).unwrap().len(), 1);
+
+        fs::write(repo.join(PACKED_REFS), "not a ref line\n").unwrap();
+        assert!(read_ref(repo, "refs/heads/gone").is_err());
+    }
+
+    #[test]
+    fn test_pack_refs() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        write_refs(
+            repo,
+            &[
+                ("refs/heads/main", OID),
+                ("refs/heads/feature/x", OTHER),
+                ("refs/heads/alias", "ref: refs/heads/main"),
+                ("refs/tags/v1", OTHER),
+            ],
+        );
+        let peel = |oid: &str| Ok((oid == OTHER).then(|| OID.to_string()));
+
+        // Without `all` only tags are packed
+        assert_eq!(pack_refs(repo, false, peel).unwrap(), ["refs/tags/v1"]);
+        assert!(!repo.join("refs/tags/v1").exists());
+        assert!(repo.join("refs/tags").is_dir());
+        assert_eq!(
+            read_ref(repo, "refs/tags/v1").unwrap(),
+            Some(RefValue::Direct(OTHER.to_string()))
+        );
+
+        let packed = pack_refs(repo, true, peel).unwrap();
+        assert_eq!(packed, ["refs/heads/feature/x", "refs/heads/main"]);
+        assert!(!repo.join("refs/heads/feature").exists());
+        assert!(repo.join("refs/heads/alias").is_file());
+        assert_eq!(
+            fs::read_to_string(repo.join(PACKED_REFS)).unwrap(),
+            format!(
+                "{}\n{OTHER} refs/heads/feature/x\n^{OID}\n{OID} refs/heads/main\n\
+                 {OTHER} refs/tags/v1\n^{OID}\n",
+                PACKED_REFS_HEADER
+            )
+        );
+        assert_eq!(list_refs(repo, "refs/").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_reflog_round_trip() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        assert!(read_reflog(repo, "refs/heads/main").unwrap().is_empty());
+
+        let entry = ReflogEntry {
+            old_oid: OID.to_string(),
+            new_oid: OTHER.to_string(),
+            identity: "C O Mitter <committer@example.com>".to_string(),
+            timestamp: 1700000100,
+            tz: "-0700".to_string(),
+            message: "commit: Second".to_string(),
+        };
+        let created = ReflogEntry {
+            old_oid: "0".repeat(40),
+            message: String::new(),
+            ..entry.clone()
+        };
+        append_reflog(repo, "refs/heads/main", &created).unwrap();
+        append_reflog(repo, "refs/heads/main", &entry).unwrap();
+        assert_eq!(
+            read_reflog(repo, "refs/heads/main").unwrap(),
+            [created.clone(), entry.clone()]
+        );
+        assert!(ReflogEntry::parse("not a reflog line").is_err());
+
+        write_reflog(repo, "refs/heads/main", std::slice::from_ref(&entry)).unwrap();
+        assert_eq!(
+            read_reflog(repo, "refs/heads/main").unwrap(),
+            std::slice::from_ref(&entry)
+        );
+        write_reflog(repo, "refs/heads/main", &[created.clone(), entry.clone()]).unwrap();
+
+        // A deleted ref's log is kept aside, after any earlier one
+        set_aside_reflog(repo, "refs/heads/main").unwrap();
+        append_reflog(repo, "refs/heads/main", &created).unwrap();
+        set_aside_reflog(repo, "refs/heads/main").unwrap();
+        assert!(!repo.join("logs/refs/heads/main").exists());
+        assert!(repo.join("logs/refs").is_dir());
+        let aside = fs::read_to_string(repo.join("logs/deleted/refs/heads/main")).unwrap();
+        assert_eq!(
+            aside,
+            [created.to_line(), entry.to_line(), created.to_line()].concat()
+        );
+    }
+
+    #[test]
+    fn test_write_symbolic_ref() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        write_symbolic_ref(repo, "HEAD", "refs/heads/topic").unwrap();
+        assert_eq!(
+            head_state(repo).unwrap(),
+            Some(HeadState::Branch("topic".to_string()))
+        );
+
+        for target in ["HEAD", "heads/main", "refs/heads/bad..name", "../outside"] {
+            assert!(
+                write_symbolic_ref(repo, "HEAD", target).is_err(),
+                "{}",
+                target
+            );
+        }
+        let err = write_symbolic_ref(repo, "HEAD", "tags/v1").unwrap_err();
+        assert!(err.to_string().contains("outside refs/"), "{}", err);
+        assert_eq!(
+            fs::read_to_string(repo.join("HEAD")).unwrap(),
+            "ref: refs/heads/topic\n"
+        );
+
+        // The bootstrap file older versions of init wrote is recognized
+        fs::create_dir_all(repo.join("refs/heads")).unwrap();
+        fs::write(repo.join("refs/heads/topic"), "ref: refs/heads/topic").unwrap();
+        assert!(is_bootstrap_ref(repo, "topic"));
+        fs::write(repo.join("refs/heads/topic"), format!("{}\n", OID)).unwrap();
+        assert!(!is_bootstrap_ref(repo, "topic"));
+    }
+
+    #[test]
+    fn test_append_reflog() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+
+        let mut entry = ReflogEntry {
+            old_oid: "0".repeat(40),
+            new_oid: OID.to_string(),
+            identity: "A U Thor <author@example.com>".to_string(),
+            timestamp: 1700000000,
+            tz: "+0100".to_string(),
+            message: "commit (initial): first\n".to_string(),
+        };
+        append_reflog(repo, "refs/heads/main", &entry).unwrap();
+
+        entry.old_oid = OID.to_string();
+        entry.message = "commit: second\nwith body".to_string();
+        append_reflog(repo, "refs/heads/main", &entry).unwrap();
+
+        let log = fs::read_to_string(repo.join("logs/refs/heads/main")).unwrap();
+        assert_eq!(
+            log,
+            format!(
+                "{zero} {OID} A U Thor <author@example.com> 1700000000 +0100\tcommit (initial): first\n\
+                 {OID} {OID} A U Thor <author@example.com> 1700000000 +0100\tcommit: second with body\n",
+                zero = "0".repeat(40)
+            )
+        );
+
+        assert!(append_reflog(repo, "../escape", &entry).is_err());
+    }
+}
@@ -3,96 +3,253 @@ use std::collections::HashMap;
 use anyhow::Result;
 use serde_json::json;
 
-use crate::modules::{config::obj_to_str, files::TreeNode};
+use crate::modules::{
+    config::obj_to_str,
+    constants::METADATA_DIR,
+    files::{FsCaps, TreeNode},
+    hash::ObjectFormat,
+    perms::SharedMode,
+};
+
+macro_rules! hook_sample {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("../../templates/hooks/", $name)),
+        )
+    };
+}
+
+/// Sample hook scripts shipped in every new repository, embedded at compile time.
+const SAMPLE_HOOKS: &[(&str, &str)] = &[
+    hook_sample!("applypatch-msg.sample"),
+    hook_sample!("commit-msg.sample"),
+    hook_sample!("fsmonitor-watchman.sample"),
+    hook_sample!("post-update.sample"),
+    hook_sample!("pre-applypatch.sample"),
+    hook_sample!("pre-commit.sample"),
+    hook_sample!("pre-merge-commit.sample"),
+    hook_sample!("prepare-commit-msg.sample"),
+    hook_sample!("pre-push.sample"),
+    hook_sample!("pre-rebase.sample"),
+    hook_sample!("pre-receive.sample"),
+    hook_sample!("push-to-checkout.sample"),
+    hook_sample!("sendemail-validate.sample"),
+    hook_sample!("update.sample"),
+];
 
 /// Generates the directory structure for a new CS01 repository.
 ///
 /// Returns a `TreeNode` representing the entire file hierarchy.
 /// If `bare` is true, returns the structure directly (config, HEAD, etc. at top level).
 /// If `bare` is false, wraps the structure in a `.CS01` directory.
-pub fn build_repo_tree(bare: bool, initial_branch: &str) -> Result<TreeNode> {
-    let branch_ref = format!("ref: refs/heads/{}", initial_branch);
+pub fn build_repo_tree(
+    bare: bool,
+    initial_branch: &str,
+    shared: SharedMode,
+    object_format: ObjectFormat,
+    fs_caps: FsCaps,
+    hide_dot_files: bool,
+) -> Result<TreeNode> {
+    let internal_structure = build_metadata_tree(
+        bare,
+        initial_branch,
+        shared,
+        object_format,
+        fs_caps,
+        hide_dot_files,
+    )?;
 
-    let config_json = json!({
+    if bare {
+        Ok(internal_structure)
+    } else {
+        let mut root = HashMap::new();
+        root.insert(METADATA_DIR.to_string(), internal_structure);
+        Ok(TreeNode::dir(root))
+    }
+}
+
+/// Generates the contents of the metadata directory (config, HEAD, refs, etc.),
+/// without the `.CS01` wrapper.
+///
+/// `shared` is recorded as `core.sharedrepository` unless it is the umask default.
+/// A non-SHA-1 `object_format` is recorded as `extensions.objectformat`, which requires
+/// `core.repositoryformatversion = 1` so older readers refuse the repository.
+/// `fs_caps` decides `core.filemode`; like git, `core.symlinks` and `core.ignorecase`
+/// are only written when they differ from the usual defaults (`true` and `false`).
+/// `hide_dot_files` is recorded as `core.hidedotfiles` on Windows, the only platform
+/// where it has an effect.
+pub fn build_metadata_tree(
+    bare: bool,
+    initial_branch: &str,
+    shared: SharedMode,
+    object_format: ObjectFormat,
+    fs_caps: FsCaps,
+    hide_dot_files: bool,
+) -> Result<TreeNode> {
+    // Note: Keys are written in insertion order, which follows git's own layout
+    // (`repositoryformatversion` first), so the files look familiar side by side.
+    let mut config_json = json!({
         "core": {
             "": {
-                "bare": bare,
                 "repositoryformatversion": 0,
-                "filemode": true,
-                "logallrefupdates": true
+                "filemode": fs_caps.filemode,
+                "bare": bare
             }
         }
     });
 
+    // Like git, reflogs are only kept by default where someone works on the branches;
+    // a bare repository is updated by pushes, which have no local history to recover.
+    let log_all_ref_updates = !bare;
+    if log_all_ref_updates {
+        config_json["core"][""]["logallrefupdates"] = json!(true);
+    }
+
+    if !fs_caps.symlinks {
+        config_json["core"][""]["symlinks"] = json!(false);
+    }
+    if fs_caps.ignorecase {
+        config_json["core"][""]["ignorecase"] = json!(true);
+    }
+
+    if cfg!(windows) {
+        config_json["core"][""]["hidedotfiles"] = json!(hide_dot_files);
+    }
+
+    if let Some(value) = shared.config_value() {
+        config_json["core"][""]["sharedrepository"] = json!(value);
+    }
+
+    if object_format != ObjectFormat::Sha1 {
+        config_json["core"][""]["repositoryformatversion"] = json!(1);
+        config_json["extensions"] = json!({
+            "": {
+                "objectformat": object_format.name()
+            }
+        });
+    }
+
     let config_content = obj_to_str(&config_json)?;
 
     let mut internal_structure = HashMap::new();
 
     internal_structure.insert(
         "HEAD".to_string(),
-        TreeNode::File(format!("{}\n", branch_ref)),
+        TreeNode::text(format!("ref: refs/heads/{}\n", initial_branch)),
     );
 
-    internal_structure.insert("config".to_string(), TreeNode::File(config_content));
+    internal_structure.insert("config".to_string(), TreeNode::text(config_content));
 
     internal_structure.insert(
         "description".to_string(),
-        TreeNode::File(
+        TreeNode::text(
             "Unnamed repository; edit this file 'description' to name the repository.\n"
                 .to_string(),
         ),
     );
 
     let mut hooks = HashMap::new();
-    let sample_hooks = vec![
-        "applypatch-msg.sample",
-        "commit-msg.sample",
-        "fsmonitor-watchman.sample",
-        "post-update.sample",
-        "pre-applypatch.sample",
-        "pre-commit.sample",
-        "pre-merge-commit.sample",
-        "prepare-commit-msg.sample",
-        "pre-push.sample",
-        "pre-rebase.sample",
-        "pre-receive.sample",
-        "push-to-checkout.sample",
-        "sendemail-validate.sample",
-        "update.sample",
-    ];
-    for hook in sample_hooks {
-        hooks.insert(hook.to_string(), TreeNode::File("".to_string()));
+    for (name, content) in SAMPLE_HOOKS {
+        hooks.insert(name.to_string(), TreeNode::file_with_mode(*content, 0o755));
     }
-    internal_structure.insert("hooks".to_string(), TreeNode::Directory(hooks));
+    internal_structure.insert("hooks".to_string(), TreeNode::dir(hooks));
 
     let mut info = HashMap::new();
     info.insert(
         "exclude".to_string(),
-        TreeNode::File(
-            "# cs01 ls-files --others --exclude-from=.cs01/info/exclude\n# Lines that start with '#' are comments.\n# For a project mostly in C, the following would be a good set of\n# exclude patterns (uncomment them if you want to use them):\n# *.[oa]\n# *~\n".to_string(),
-        ),
+        TreeNode::text(format!(
+            "# cs01 ls-files --others --exclude-from={METADATA_DIR}/info/exclude\n# Lines that start with '#' are comments.\n# For a project mostly in C, the following would be a good set of\n# exclude patterns (uncomment them if you want to use them):\n# *.[oa]\n# *~\n"
+        )),
     );
-    internal_structure.insert("info".to_string(), TreeNode::Directory(info));
+    internal_structure.insert("info".to_string(), TreeNode::dir(info));
 
     let mut objects = HashMap::new();
-    objects.insert("info".to_string(), TreeNode::Directory(HashMap::new()));
-    objects.insert("pack".to_string(), TreeNode::Directory(HashMap::new()));
-    internal_structure.insert("objects".to_string(), TreeNode::Directory(objects));
-
-    let mut heads = HashMap::new();
-    heads.insert(initial_branch.to_string(), TreeNode::File(branch_ref));
+    objects.insert("info".to_string(), TreeNode::dir(HashMap::new()));
+    objects.insert("pack".to_string(), TreeNode::dir(HashMap::new()));
+    internal_structure.insert("objects".to_string(), TreeNode::dir(objects));
 
+    // Note: Like git, HEAD points at a branch that doesn't exist yet (an unborn branch);
+    // its ref file is written by the first commit.
     let mut refs = HashMap::new();
-    refs.insert("heads".to_string(), TreeNode::Directory(heads));
-    refs.insert("tags".to_string(), TreeNode::Directory(HashMap::new()));
+    refs.insert("heads".to_string(), TreeNode::dir(HashMap::new()));
+    refs.insert("tags".to_string(), TreeNode::dir(HashMap::new()));
 
-    internal_structure.insert("refs".to_string(), TreeNode::Directory(refs));
+    internal_structure.insert("refs".to_string(), TreeNode::dir(refs));
 
-    if bare {
-        Ok(TreeNode::Directory(internal_structure))
-    } else {
-        let mut root = HashMap::new();
-        root.insert(".CS01".to_string(), TreeNode::Directory(internal_structure));
-        Ok(TreeNode::Directory(root))
+    // Note: Reflogs are appended to, never created on demand by readers, so the
+    // hierarchy exists from the start. `logs/HEAD` stays empty until the first commit.
+    if log_all_ref_updates {
+        let mut log_refs = HashMap::new();
+        log_refs.insert("heads".to_string(), TreeNode::dir(HashMap::new()));
+
+        let mut logs = HashMap::new();
+        logs.insert("HEAD".to_string(), TreeNode::text(""));
+        logs.insert("refs".to_string(), TreeNode::dir(log_refs));
+        internal_structure.insert("logs".to_string(), TreeNode::dir(logs));
+    }
+
+    Ok(TreeNode::dir(internal_structure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_of(tree: &TreeNode) -> &str {
+        let TreeNode::Directory {
+            entries: children, ..
+        } = tree
+        else {
+            panic!("metadata tree must be a directory");
+        };
+        match children.get("config") {
+            Some(TreeNode::File { content, .. }) => std::str::from_utf8(content).unwrap(),
+            _ => panic!("metadata tree must contain a config file"),
+        }
+    }
+
+    // Note: On Windows the config also records core.hidedotfiles.
+    #[cfg(not(windows))]
+    #[test]
+    fn test_config_snapshot() {
+        let caps = FsCaps {
+            filemode: true,
+            symlinks: true,
+            ignorecase: false,
+        };
+
+        let tree = build_metadata_tree(
+            false,
+            "main",
+            SharedMode::Umask,
+            ObjectFormat::Sha1,
+            caps,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            config_of(&tree),
+            "[core]\n  repositoryformatversion = 0\n  filemode = true\n  bare = false\n  logallrefupdates = true\n"
+        );
+
+        let caps = FsCaps {
+            filemode: false,
+            symlinks: false,
+            ignorecase: true,
+        };
+        let tree = build_metadata_tree(
+            true,
+            "main",
+            SharedMode::Group,
+            ObjectFormat::Sha256,
+            caps,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            config_of(&tree),
+            "[core]\n  repositoryformatversion = 1\n  filemode = false\n  bare = true\n  symlinks = false\n  ignorecase = true\n  sharedrepository = 1\n[extensions]\n  objectformat = sha256\n"
+        );
     }
 }
@@ -0,0 +1,425 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::modules::{
+    hash::{ObjectId, ObjectKind},
+    objects::commit::Commit,
+    odb::Odb,
+    refs::{is_pseudo_ref, read_ref, read_reflog, resolve_ref},
+};
+
+/// The fewest hex digits accepted as an abbreviated object id, as in git.
+pub const MIN_ABBREV: usize = 4;
+
+/// Where a short name like `main` is looked for, in order, as git does.
+///
+/// Note: Tags come before branches, so a tag and a branch of the same name resolve
+/// to the tag; name branches and tags differently, or spell out `refs/heads/<name>`.
+const REF_SEARCH_ORDER: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// A revision that names no object, or more than one.
+#[derive(Debug)]
+pub enum BadRevision {
+    Unknown(String),
+    /// An abbreviated id matching several objects, listed with their types.
+    Ambiguous {
+        spec: String,
+        candidates: Vec<(ObjectId, ObjectKind)>,
+    },
+}
+
+impl std::fmt::Display for BadRevision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BadRevision::Unknown(spec) => write!(f, "unknown revision '{}'", spec),
+            BadRevision::Ambiguous { spec, candidates } => {
+                write!(
+                    f,
+                    "short object ID {} is ambiguous; the candidates are:",
+                    spec
+                )?;
+                for (id, kind) in candidates {
+                    write!(f, "\n  {} {}", id.short(10), kind)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BadRevision {}
+
+/// Resolves a revision like `main`, `HEAD~2`, `@^2` or `1a2b3c` to an object id.
+///
+/// A revision is a base followed by any number of suffixes:
+/// - The base is `@` (HEAD), a full or abbreviated (at least 4 digits) hex id, or a
+///   reference name, looked up in `REF_SEARCH_ORDER`.
+/// - `~N` follows first parents N times (`~` alone is `~1`).
+/// - `^N` picks the Nth parent (`^` alone is `^1`); `^0` is the commit itself.
+///
+/// Note: As in git, a full-length id resolves even when the object isn't stored, and a
+/// reference wins over an abbreviated id spelled the same. Names that match nothing
+/// fail with `BadRevision`.
+pub fn resolve_revision(repo_dir: &Path, odb: &Odb, spec: &str) -> Result<ObjectId> {
+    let base_end = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (base, mut suffixes) = spec.split_at(base_end);
+    let unknown = || BadRevision::Unknown(spec.to_string());
+
+    let mut id = resolve_base(repo_dir, odb, base, spec)?;
+    while let Some(op) = suffixes.chars().next() {
+        let rest = &suffixes[op.len_utf8()..];
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let count: usize = match &rest[..digits_end] {
+            "" => 1,
+            digits => digits.parse().map_err(|_| unknown())?,
+        };
+        suffixes = &rest[digits_end..];
+
+        id = match (op, count) {
+            ('~', _) => {
+                for _ in 0..count {
+                    id = *parents(odb, &id, spec)?.first().ok_or_else(unknown)?;
+                }
+                id
+            }
+            ('^', 0) => {
+                parents(odb, &id, spec)?;
+                id
+            }
+            ('^', _) => *parents(odb, &id, spec)?
+                .get(count - 1)
+                .ok_or_else(unknown)?,
+            _ => return Err(unknown().into()),
+        };
+    }
+    Ok(id)
+}
+
+/// Resolves the part of a revision before any `~`/`^` suffix.
+fn resolve_base(repo_dir: &Path, odb: &Odb, base: &str, spec: &str) -> Result<ObjectId> {
+    let unknown = || BadRevision::Unknown(spec.to_string());
+    let base = if base == "@" { "HEAD" } else { base };
+    if base.is_empty() {
+        return Err(unknown().into());
+    }
+
+    let is_hex = base.bytes().all(|b| b.is_ascii_hexdigit());
+    if is_hex && let Ok(id) = ObjectId::from_hex(base) {
+        return Ok(id);
+    }
+
+    if let Some((name, selector)) = base.split_once("@{")
+        && let Some(selector) = selector.strip_suffix('}')
+    {
+        return resolve_reflog_entry(repo_dir, name, selector, spec);
+    }
+
+    if let Some(name) = full_ref_name(repo_dir, base) {
+        // A symbolic ref to an unborn branch names nothing yet.
+        let resolved = resolve_ref(repo_dir, &name)?;
+        let oid = resolved.oid.ok_or_else(unknown)?;
+        return ObjectId::from_hex(&oid)
+            .with_context(|| format!("reference {} is broken", resolved.name));
+    }
+
+    if is_hex && base.len() >= MIN_ABBREV {
+        let prefix = base.to_ascii_lowercase();
+        let found = odb.find_by_prefix(&prefix)?;
+        match found.as_slice() {
+            [id] => return Ok(*id),
+            [] => {}
+            _ => {
+                let candidates = found
+                    .iter()
+                    .map(|id| Ok((*id, odb.read(id)?.0)))
+                    .collect::<Result<Vec<_>>>()?;
+                return Err(BadRevision::Ambiguous {
+                    spec: base.to_string(),
+                    candidates,
+                }
+                .into());
+            }
+        }
+    }
+    Err(unknown().into())
+}
+
+/// The full name of the reference a name like `main` stands for, found by trying
+/// `REF_SEARCH_ORDER`; `None` when no such reference exists.
+pub fn full_ref_name(repo_dir: &Path, name: &str) -> Option<String> {
+    REF_SEARCH_ORDER
+        .iter()
+        .map(|pattern| pattern.replace("{}", name))
+        .find(|candidate| {
+            // Note: Only pseudo-refs like HEAD and valid names under refs/ are
+            // references; anything else in the search order (`main` itself,
+            // `refs/heads/a..b`) is simply not there.
+            (is_pseudo_ref(candidate) || candidate.starts_with("refs/"))
+                && matches!(read_ref(repo_dir, candidate), Ok(Some(_)))
+        })
+}
+
+/// Resolves `name@{n}`: the value `name` had `n` moves ago, read from its reflog. An
+/// empty `name` stands for the branch HEAD is on.
+///
+/// Note: Only counts are understood, not dates like `main@{yesterday}`.
+fn resolve_reflog_entry(
+    repo_dir: &Path,
+    name: &str,
+    selector: &str,
+    spec: &str,
+) -> Result<ObjectId> {
+    let unknown = || BadRevision::Unknown(spec.to_string());
+    let Ok(n) = selector.parse::<usize>() else {
+        return Err(unknown().into());
+    };
+    let full = match name {
+        "" => resolve_ref(repo_dir, "HEAD")?.name,
+        "@" => "HEAD".to_string(),
+        _ => full_ref_name(repo_dir, name).ok_or_else(unknown)?,
+    };
+    let entries = read_reflog(repo_dir, &full)?;
+    match entries.iter().rev().nth(n) {
+        Some(entry) => ObjectId::from_hex(&entry.new_oid)
+            .with_context(|| format!("the reflog of {} is broken", full)),
+        None if entries.is_empty() => Err(unknown().into()),
+        None => Err(unknown())
+            .with_context(|| format!("log for '{}' only has {} entries", name, entries.len())),
+    }
+}
+
+/// The parents of the commit `id`; fails when `id` isn't a stored commit.
+fn parents(odb: &Odb, id: &ObjectId, spec: &str) -> Result<Vec<ObjectId>> {
+    if !odb.contains(id) {
+        return Err(BadRevision::Unknown(spec.to_string()).into());
+    }
+    let (kind, data) = odb.read(id)?;
+    if kind != ObjectKind::Commit {
+        return Err(BadRevision::Unknown(spec.to_string()))
+            .with_context(|| format!("{} is a {}, not a commit", id, kind));
+    }
+    Ok(Commit::parse(&data)?.parents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::Identity;
+    use crate::modules::objects::commit::Timestamp;
+    use crate::modules::refs::{
+        ExpectedOld, ReflogEntry, append_reflog, update_ref, write_symbolic_ref,
+    };
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn commit(odb: &Odb, parents: &[ObjectId], message: &str) -> ObjectId {
+        let tree = odb.write(ObjectKind::Tree, b"").unwrap();
+        let identity = Identity {
+            name: "A U Thor".to_string(),
+            email: "author@example.com".to_string(),
+            when: Timestamp::parse("1700000000 +0000").unwrap(),
+        };
+        let commit = Commit {
+            tree,
+            parents: parents.to_vec(),
+            author: identity.clone(),
+            committer: identity,
+            extra_headers: Vec::new(),
+            message: format!("{}\n", message),
+        };
+        odb.write(ObjectKind::Commit, &commit.serialize()).unwrap()
+    }
+
+    /// Two lines of history joined by a merge:
+    /// `root <- a <- b <- merge` on main, with `side` (from root) as the merge's second parent.
+    struct History {
+        root: ObjectId,
+        a: ObjectId,
+        b: ObjectId,
+        side: ObjectId,
+        merge: ObjectId,
+    }
+
+    fn history(repo: &Path) -> (Odb, History) {
+        fs::create_dir_all(repo.join("objects")).unwrap();
+        let odb = Odb::open(repo).unwrap();
+        let root = commit(&odb, &[], "root");
+        let a = commit(&odb, &[root], "a");
+        let b = commit(&odb, &[a], "b");
+        let side = commit(&odb, &[root], "side");
+        let merge = commit(&odb, &[b, side], "merge");
+
+        write_symbolic_ref(repo, "HEAD", "refs/heads/main").unwrap();
+        update_ref(repo, "refs/heads/main", &merge, &ExpectedOld::Any).unwrap();
+        update_ref(repo, "refs/heads/side", &side, &ExpectedOld::Any).unwrap();
+        update_ref(repo, "refs/tags/v1", &a, &ExpectedOld::Any).unwrap();
+        update_ref(repo, "refs/remotes/origin/main", &b, &ExpectedOld::Any).unwrap();
+        let history = History {
+            root,
+            a,
+            b,
+            side,
+            merge,
+        };
+        (odb, history)
+    }
+
+    #[test]
+    fn test_resolve_revision() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        let (odb, h) = history(repo);
+
+        let cases = [
+            ("HEAD", h.merge),
+            ("@", h.merge),
+            ("main", h.merge),
+            ("heads/main", h.merge),
+            ("refs/heads/main", h.merge),
+            ("side", h.side),
+            ("v1", h.a),
+            ("origin/main", h.b),
+            ("HEAD~", h.b),
+            ("HEAD~1", h.b),
+            ("HEAD~2", h.a),
+            ("@~3", h.root),
+            ("HEAD^", h.b),
+            ("HEAD^2", h.side),
+            ("HEAD^0", h.merge),
+            ("HEAD^2~1", h.root),
+            ("main~1^1~1", h.root),
+            ("main~1^1", h.a),
+            ("HEAD^^^", h.root),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(
+                resolve_revision(repo, &odb, spec).unwrap(),
+                expected,
+                "{}",
+                spec
+            );
+        }
+
+        // Full and abbreviated ids, in either case
+        let hex = h.side.to_hex();
+        for spec in [
+            hex.clone(),
+            hex[..7].to_string(),
+            hex[..7].to_uppercase(),
+            format!("{}~1", &hex[..8]),
+        ] {
+            let expected = if spec.contains('~') { h.root } else { h.side };
+            assert_eq!(
+                resolve_revision(repo, &odb, &spec).unwrap(),
+                expected,
+                "{}",
+                spec
+            );
+        }
+        // A full id needn't be stored
+        let missing = "0123456789012345678901234567890123456789";
+        assert_eq!(
+            resolve_revision(repo, &odb, missing).unwrap().to_hex(),
+            missing
+        );
+    }
+
+    #[test]
+    fn test_resolve_reflog_entries() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        let (odb, h) = history(repo);
+        let moves = [(None, h.root), (Some(h.root), h.b), (Some(h.b), h.merge)];
+        for (old, new) in moves {
+            let entry = ReflogEntry {
+                old_oid: old.map_or("0".repeat(40), |id| id.to_hex()),
+                new_oid: new.to_hex(),
+                identity: "A U Thor <author@example.com>".to_string(),
+                timestamp: 1700000000,
+                tz: "+0000".to_string(),
+                message: "moved".to_string(),
+            };
+            append_reflog(repo, "refs/heads/main", &entry).unwrap();
+        }
+
+        let cases = [
+            ("main@{0}", h.merge),
+            ("main@{1}", h.b),
+            ("main@{2}", h.root),
+            ("main@{1}~1", h.a),
+            ("refs/heads/main@{2}", h.root),
+            ("@{1}", h.b),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(
+                resolve_revision(repo, &odb, spec).unwrap(),
+                expected,
+                "{}",
+                spec
+            );
+        }
+
+        let err = resolve_revision(repo, &odb, "main@{3}").unwrap_err();
+        assert_eq!(err.to_string(), "log for 'main' only has 3 entries");
+        for spec in ["main@{yesterday}", "side@{0}", "nope@{0}", "HEAD@{0}"] {
+            let err = resolve_revision(repo, &odb, spec).unwrap_err();
+            assert!(err.downcast_ref::<BadRevision>().is_some(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_resolve_revision_failures() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        let (odb, _) = history(repo);
+
+        for spec in [
+            "",
+            "nope",
+            "HEAD~4",
+            "HEAD^3",
+            "side^2",
+            "HEAD~x",
+            "HEAD~1x",
+            "HEAD~é",
+            "abc",
+            "0123456",
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904~1",
+            "refs/heads/../../HEAD",
+        ] {
+            let err = resolve_revision(repo, &odb, spec).unwrap_err();
+            assert!(
+                err.downcast_ref::<BadRevision>().is_some(),
+                "{}: {:#}",
+                spec,
+                err
+            );
+        }
+
+        // An unborn HEAD doesn't resolve
+        write_symbolic_ref(repo, "HEAD", "refs/heads/unborn").unwrap();
+        let err = resolve_revision(repo, &odb, "HEAD").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BadRevision>(),
+            Some(BadRevision::Unknown(_))
+        ));
+
+        // f0acb599... and f0acbc2e... share their first five digits
+        odb.write(ObjectKind::Blob, b"1071").unwrap();
+        odb.write(ObjectKind::Blob, b"1251").unwrap();
+        let err = resolve_revision(repo, &odb, "f0acb").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("f0acb59936 blob"), "{}", message);
+        assert!(message.contains("f0acbc2e3f blob"), "{}", message);
+        assert!(resolve_revision(repo, &odb, "f0acbc").is_ok());
+    }
+}
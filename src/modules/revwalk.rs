@@ -0,0 +1,293 @@
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use crate::modules::{hash::ObjectId, objects::commit::Commit, odb::Odb};
+
+/// A commit waiting to be shown, ordered by committer date.
+struct Pending {
+    time: i64,
+    /// When it was queued; among commits of the same date the first queued comes first.
+    sequence: u64,
+    id: ObjectId,
+    commit: Commit,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Note: `BinaryHeap` pops the greatest, so a newer date or an earlier sequence wins.
+        self.time
+            .cmp(&other.time)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+/// Walks history from one or more commits to their ancestors, newest first, as
+/// `git log` does: the commit with the latest committer date among those reached so
+/// far comes next.
+///
+/// Note: Each commit is yielded once, however many paths lead to it, so the shared
+/// history below a merge isn't repeated.
+pub struct RevWalk<'a> {
+    odb: &'a Odb,
+    queue: BinaryHeap<Pending>,
+    seen: HashSet<ObjectId>,
+    sequence: u64,
+    /// A parent that couldn't be read, reported after the commit naming it.
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(odb: &'a Odb) -> Self {
+        RevWalk {
+            odb,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            sequence: 0,
+            error: None,
+        }
+    }
+
+    /// Starts the walk (also) from `id`, which must be a stored commit.
+    pub fn push(&mut self, id: ObjectId) -> Result<()> {
+        if !self.seen.insert(id) {
+            return Ok(());
+        }
+        let commit = Commit::read(self.odb, &id)?;
+        self.queue.push(Pending {
+            time: commit.committer.when.seconds,
+            sequence: self.sequence,
+            id,
+            commit,
+        });
+        self.sequence += 1;
+        Ok(())
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<(ObjectId, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        let pending = self.queue.pop()?;
+        for parent in &pending.commit.parents {
+            if let Err(e) = self.push(*parent) {
+                // Note: A missing parent ends the walk; what lies beyond it is unknown.
+                self.queue.clear();
+                self.error = Some(e);
+                break;
+            }
+        }
+        Some(Ok((pending.id, pending.commit)))
+    }
+}
+
+/// Whether the commit `ancestor` is `descendant` or reachable from it through parents.
+pub fn is_ancestor(odb: &Odb, ancestor: &ObjectId, descendant: &ObjectId) -> Result<bool> {
+    let mut walk = RevWalk::new(odb);
+    walk.push(*descendant)?;
+    for item in walk {
+        if item?.0 == *ancestor {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The best common ancestor of the commits `one` and `other`: one reachable from both
+/// that no other common ancestor descends from. `None` when their histories are
+/// unrelated.
+///
+/// Note: Criss-cross histories can have several such commits; the one with the latest
+/// committer date is picked, where git would merge them into a virtual base.
+pub fn merge_base(odb: &Odb, one: &ObjectId, other: &ObjectId) -> Result<Option<ObjectId>> {
+    let mut walk = RevWalk::new(odb);
+    walk.push(*one)?;
+    let reachable = walk
+        .map(|item| item.map(|(id, _)| id))
+        .collect::<Result<HashSet<_>>>()?;
+
+    let mut walk = RevWalk::new(odb);
+    walk.push(*other)?;
+    let mut common = Vec::new();
+    for item in walk {
+        let (id, commit) = item?;
+        if reachable.contains(&id) {
+            common.push((id, commit));
+        }
+    }
+
+    // Every ancestor of a common ancestor is one too, so the best are those that no
+    // other common ancestor's parents lead to.
+    let mut below = RevWalk::new(odb);
+    for (_, commit) in &common {
+        for parent in &commit.parents {
+            below.push(*parent)?;
+        }
+    }
+    let below = below
+        .map(|item| item.map(|(id, _)| id))
+        .collect::<Result<HashSet<_>>>()?;
+    Ok(common
+        .into_iter()
+        .map(|(id, _)| id)
+        .find(|id| !below.contains(id)))
+}
+
+/// How many commits `ours` has that `theirs` lacks, and the other way round, as
+/// `status` and `switch` report for a branch and its upstream.
+pub fn ahead_behind(odb: &Odb, ours: &ObjectId, theirs: &ObjectId) -> Result<(usize, usize)> {
+    let reachable = |id: &ObjectId| -> Result<HashSet<ObjectId>> {
+        let mut walk = RevWalk::new(odb);
+        walk.push(*id)?;
+        walk.map(|item| item.map(|(id, _)| id)).collect()
+    };
+    let ours = reachable(ours)?;
+    let theirs = reachable(theirs)?;
+    Ok((
+        ours.difference(&theirs).count(),
+        theirs.difference(&ours).count(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::Identity;
+    use crate::modules::hash::ObjectKind;
+    use crate::modules::objects::commit::Timestamp;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn commit(odb: &Odb, parents: &[ObjectId], seconds: i64, message: &str) -> ObjectId {
+        let tree = odb.write(ObjectKind::Tree, b"").unwrap();
+        let identity = Identity {
+            name: "A U Thor".to_string(),
+            email: "author@example.com".to_string(),
+            when: Timestamp {
+                seconds,
+                offset_minutes: 0,
+            },
+        };
+        let commit = Commit {
+            tree,
+            parents: parents.to_vec(),
+            author: identity.clone(),
+            committer: identity,
+            extra_headers: Vec::new(),
+            message: format!("{}\n", message),
+        };
+        odb.write(ObjectKind::Commit, &commit.serialize()).unwrap()
+    }
+
+    fn messages(walk: RevWalk) -> Vec<String> {
+        walk.map(|item| item.unwrap().1.summary().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_revwalk_orders_by_date_and_visits_once() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+
+        // root <- a <- c <- merge, and root <- b <- merge, with b dated between a and c
+        let root = commit(&odb, &[], 100, "root");
+        let a = commit(&odb, &[root], 200, "a");
+        let b = commit(&odb, &[root], 250, "b");
+        let c = commit(&odb, &[a], 300, "c");
+        let merge = commit(&odb, &[c, b], 400, "merge");
+
+        let mut walk = RevWalk::new(&odb);
+        walk.push(merge).unwrap();
+        assert_eq!(messages(walk), ["merge", "c", "b", "a", "root"]);
+        assert!(is_ancestor(&odb, &b, &merge).unwrap());
+        assert!(is_ancestor(&odb, &root, &c).unwrap());
+        assert!(is_ancestor(&odb, &c, &c).unwrap());
+        assert!(!is_ancestor(&odb, &b, &c).unwrap());
+        assert!(!is_ancestor(&odb, &merge, &root).unwrap());
+
+        // Several starting points, one of them reachable from the other
+        let mut walk = RevWalk::new(&odb);
+        walk.push(a).unwrap();
+        walk.push(b).unwrap();
+        walk.push(root).unwrap();
+        assert_eq!(messages(walk), ["b", "a", "root"]);
+
+        // Commits of the same date come in the order they were reached
+        let first = commit(&odb, &[root], 500, "first parent");
+        let second = commit(&odb, &[root], 500, "second parent");
+        let tie = commit(&odb, &[first, second], 600, "tie");
+        let mut walk = RevWalk::new(&odb);
+        walk.push(tie).unwrap();
+        assert_eq!(
+            messages(walk),
+            ["tie", "first parent", "second parent", "root"]
+        );
+    }
+
+    #[test]
+    fn test_merge_base() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+
+        // root <- a <- c <- merge <- d, and root <- b <- merge, b <- e
+        let root = commit(&odb, &[], 100, "root");
+        let a = commit(&odb, &[root], 200, "a");
+        let b = commit(&odb, &[root], 250, "b");
+        let c = commit(&odb, &[a], 300, "c");
+        let merge = commit(&odb, &[c, b], 400, "merge");
+        let d = commit(&odb, &[merge], 500, "d");
+        let e = commit(&odb, &[b], 150, "e, dated before its parent");
+
+        assert_eq!(merge_base(&odb, &c, &b).unwrap(), Some(root));
+        assert_eq!(merge_base(&odb, &d, &e).unwrap(), Some(b));
+        assert_eq!(merge_base(&odb, &e, &d).unwrap(), Some(b));
+        assert_eq!(merge_base(&odb, &a, &d).unwrap(), Some(a));
+        assert_eq!(merge_base(&odb, &d, &d).unwrap(), Some(d));
+
+        let unrelated = commit(&odb, &[], 600, "unrelated");
+        assert_eq!(merge_base(&odb, &d, &unrelated).unwrap(), None);
+
+        assert_eq!(ahead_behind(&odb, &d, &d).unwrap(), (0, 0));
+        assert_eq!(ahead_behind(&odb, &d, &a).unwrap(), (4, 0));
+        assert_eq!(ahead_behind(&odb, &a, &d).unwrap(), (0, 4));
+        assert_eq!(ahead_behind(&odb, &c, &e).unwrap(), (2, 2));
+        assert_eq!(ahead_behind(&odb, &unrelated, &root).unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn test_revwalk_fails_on_missing_or_wrong_objects() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("objects")).unwrap();
+        let odb = Odb::open(dir.path()).unwrap();
+        let blob = odb.write(ObjectKind::Blob, b"not a commit").unwrap();
+        assert!(RevWalk::new(&odb).push(blob).is_err());
+
+        let missing = ObjectId::from_hex("0123456789012345678901234567890123456789").unwrap();
+        let orphan = commit(&odb, &[missing], 100, "orphan");
+        let mut walk = RevWalk::new(&odb);
+        walk.push(orphan).unwrap();
+        assert_eq!(walk.next().unwrap().unwrap().0, orphan);
+        assert!(walk.next().unwrap().is_err());
+        assert!(walk.next().is_none());
+    }
+}
@@ -0,0 +1,470 @@
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+
+use crate::modules::files::TreeNode;
+
+/// One difference between two trees, at a path relative to their roots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeDelta {
+    /// Only in the new tree.
+    Added(PathBuf),
+    /// Only in the old tree.
+    Removed(PathBuf),
+    /// A file whose bytes differ, or a symlink whose target differs.
+    Modified(PathBuf),
+    /// A file with the same bytes but different permission bits.
+    ModeChanged {
+        path: PathBuf,
+        old: Option<u32>,
+        new: Option<u32>,
+    },
+    /// The same path holds a different kind of node (e.g. a file became a symlink).
+    TypeChanged(PathBuf),
+}
+
+impl TreeDelta {
+    /// The path this delta is about.
+    pub fn path(&self) -> &Path {
+        match self {
+            TreeDelta::Added(path)
+            | TreeDelta::Removed(path)
+            | TreeDelta::Modified(path)
+            | TreeDelta::ModeChanged { path, .. }
+            | TreeDelta::TypeChanged(path) => path,
+        }
+    }
+}
+
+/// Lists what changed from `old` to `new`, sorted by path so the output is stable.
+///
+/// Note: Added and removed directories are reported file by file, like `git status`;
+/// only an empty directory is reported as itself. `mode: None` and `Some(_)` count as
+/// different modes, since `None` defers to whatever `WriteOptions` says.
+pub fn diff_trees(old: &TreeNode, new: &TreeNode) -> Vec<TreeDelta> {
+    let mut deltas = Vec::new();
+    diff_node(Path::new(""), old, new, &mut deltas);
+    deltas.sort_by(|a, b| a.path().cmp(b.path()));
+    deltas
+}
+
+fn diff_node(path: &Path, old: &TreeNode, new: &TreeNode, deltas: &mut Vec<TreeDelta>) {
+    match (old, new) {
+        (
+            TreeNode::Directory {
+                entries: old_children,
+                ..
+            },
+            TreeNode::Directory {
+                entries: new_children,
+                ..
+            },
+        ) => {
+            for (name, old_child) in old_children {
+                let child_path = path.join(name);
+                match new_children.get(name) {
+                    Some(new_child) => diff_node(&child_path, old_child, new_child, deltas),
+                    None => leaves(&child_path, old_child, &mut |leaf| {
+                        deltas.push(TreeDelta::Removed(leaf))
+                    }),
+                }
+            }
+            for (name, new_child) in new_children {
+                if !old_children.contains_key(name) {
+                    leaves(&path.join(name), new_child, &mut |leaf| {
+                        deltas.push(TreeDelta::Added(leaf))
+                    });
+                }
+            }
+        }
+        (
+            TreeNode::File {
+                content: old_content,
+                mode: old_mode,
+            },
+            TreeNode::File {
+                content: new_content,
+                mode: new_mode,
+            },
+        ) => {
+            if old_content != new_content {
+                deltas.push(TreeDelta::Modified(path.to_path_buf()));
+            } else if old_mode != new_mode {
+                deltas.push(TreeDelta::ModeChanged {
+                    path: path.to_path_buf(),
+                    old: *old_mode,
+                    new: *new_mode,
+                });
+            }
+        }
+        (TreeNode::Symlink(old_target), TreeNode::Symlink(new_target)) => {
+            if old_target != new_target {
+                deltas.push(TreeDelta::Modified(path.to_path_buf()));
+            }
+        }
+        _ => deltas.push(TreeDelta::TypeChanged(path.to_path_buf())),
+    }
+}
+
+/// Calls `found` with every file, symlink and empty directory under `node`.
+fn leaves(path: &Path, node: &TreeNode, found: &mut impl FnMut(PathBuf)) {
+    match node {
+        TreeNode::Directory {
+            entries: children, ..
+        } if !children.is_empty() => {
+            for (name, child) in children {
+                leaves(&path.join(name), child, found);
+            }
+        }
+        _ => found(path.to_path_buf()),
+    }
+}
+
+/// Formats a delta as a short-status line, e.g. ` M src/main.rs`.
+///
+/// Note: As in `git status --short`, a mode change shows as `M` and a type change as `T`.
+pub fn render_delta(delta: &TreeDelta) -> String {
+    let code = match delta {
+        TreeDelta::Added(_) => 'A',
+        TreeDelta::Removed(_) => 'D',
+        TreeDelta::Modified(_) | TreeDelta::ModeChanged { .. } => 'M',
+        TreeDelta::TypeChanged(_) => 'T',
+    };
+    format!(" {} {}", code, delta.path().display())
+}
+
+/// What `merge_trees` does when both trees define the same path as a file or symlink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// The overlay's node replaces the base's.
+    OverlayWins,
+    /// The base's node is kept.
+    BaseWins,
+    /// Fail, naming the path; identical nodes are not a conflict.
+    ErrorOnConflict,
+}
+
+/// Lays `overlay` on top of `base`, merging directories recursively.
+///
+/// Note: A file or symlink in one tree where the other has a directory can't be resolved
+/// by any policy, so it is always an error naming the full path.
+pub fn merge_trees(base: TreeNode, overlay: TreeNode, policy: MergePolicy) -> Result<TreeNode> {
+    merge_node(Path::new(""), base, overlay, policy)
+}
+
+fn merge_node(
+    path: &Path,
+    base: TreeNode,
+    overlay: TreeNode,
+    policy: MergePolicy,
+) -> Result<TreeNode> {
+    let shown = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+
+    match (base, overlay) {
+        (
+            TreeNode::Directory {
+                entries: mut children,
+                mode: base_mode,
+            },
+            TreeNode::Directory {
+                entries: overlay_children,
+                mode: overlay_mode,
+            },
+        ) => {
+            for (name, overlay_child) in overlay_children {
+                let merged = match children.remove(&name) {
+                    Some(base_child) => {
+                        merge_node(&path.join(&name), base_child, overlay_child, policy)?
+                    }
+                    None => overlay_child,
+                };
+                children.insert(name, merged);
+            }
+            // Note: Modes never conflict; the side the policy favours wins if both set one.
+            let mode = match policy {
+                MergePolicy::BaseWins => base_mode.or(overlay_mode),
+                _ => overlay_mode.or(base_mode),
+            };
+            Ok(TreeNode::Directory {
+                entries: children,
+                mode,
+            })
+        }
+        (TreeNode::Directory { .. }, _) | (_, TreeNode::Directory { .. }) => bail!(
+            "cannot merge trees: '{}' is a directory in one and not in the other",
+            shown.display()
+        ),
+        (base, overlay) => match policy {
+            MergePolicy::OverlayWins => Ok(overlay),
+            MergePolicy::BaseWins => Ok(base),
+            MergePolicy::ErrorOnConflict if base == overlay => Ok(base),
+            MergePolicy::ErrorOnConflict => bail!(
+                "cannot merge trees: '{}' is defined differently in both",
+                shown.display()
+            ),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn dir(children: Vec<(&str, TreeNode)>) -> TreeNode {
+        TreeNode::dir(
+            children
+                .into_iter()
+                .map(|(name, node)| (name.to_string(), node))
+                .collect(),
+        )
+    }
+
+    /// Finds the node at `path`, if any.
+    fn node_at<'a>(tree: &'a TreeNode, path: &Path) -> Option<&'a TreeNode> {
+        path.iter().try_fold(tree, |node, name| match node {
+            TreeNode::Directory {
+                entries: children, ..
+            } => children.get(name.to_str().unwrap()),
+            _ => None,
+        })
+    }
+
+    /// Puts `node` at `path` (or removes what is there for `None`), creating parent directories.
+    fn replace_at(tree: &mut TreeNode, path: &Path, node: Option<TreeNode>) {
+        let mut names: Vec<_> = path.iter().map(|n| n.to_str().unwrap()).collect();
+        let Some(last) = names.pop() else {
+            *tree = node.unwrap();
+            return;
+        };
+        let mut current = tree;
+        for name in names {
+            let TreeNode::Directory {
+                entries: children, ..
+            } = current
+            else {
+                panic!("parent of {:?} is not a directory", path);
+            };
+            current = children
+                .entry(name.to_string())
+                .or_insert_with(|| TreeNode::dir(HashMap::new()));
+        }
+        let TreeNode::Directory {
+            entries: children, ..
+        } = current
+        else {
+            panic!("parent of {:?} is not a directory", path);
+        };
+        match node {
+            Some(node) => children.insert(last.to_string(), node),
+            None => children.remove(last),
+        };
+    }
+
+    /// Applies `deltas` to `old` by copying each changed path over from `new`.
+    ///
+    /// Note: Removals are reported per file, so a directory emptied by them is
+    /// dropped too when `new` doesn't have it.
+    fn apply(old: &TreeNode, new: &TreeNode, deltas: &[TreeDelta]) -> TreeNode {
+        let mut result = old.clone();
+        for delta in deltas {
+            let path = delta.path();
+            replace_at(&mut result, path, node_at(new, path).cloned());
+
+            for parent in path.ancestors().skip(1) {
+                let emptied = matches!(node_at(&result, parent),
+                    Some(TreeNode::Directory { entries: children, .. }) if children.is_empty());
+                if parent.as_os_str().is_empty() || !emptied || node_at(new, parent).is_some() {
+                    break;
+                }
+                replace_at(&mut result, parent, None);
+            }
+        }
+        result
+    }
+
+    /// A small deterministic pseudo-random tree, so the properties are checked on many shapes.
+    fn generated_tree(seed: u64) -> TreeNode {
+        fn next(state: &mut u64) -> u32 {
+            *state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (*state >> 33) as u32
+        }
+        fn build(state: &mut u64, depth: u32) -> TreeNode {
+            let mut children = HashMap::new();
+            for _ in 0..next(state) % 4 {
+                let name = format!("n{}", next(state) % 5);
+                let node = match next(state) % 4 {
+                    0 if depth < 3 => build(state, depth + 1),
+                    1 => TreeNode::Symlink(PathBuf::from(format!("t{}", next(state) % 2))),
+                    2 => TreeNode::file_with_mode(
+                        vec![(next(state) % 2) as u8],
+                        if next(state).is_multiple_of(2) {
+                            0o644
+                        } else {
+                            0o755
+                        },
+                    ),
+                    _ => TreeNode::file(vec![(next(state) % 3) as u8]),
+                };
+                children.insert(name, node);
+            }
+            TreeNode::dir(children)
+        }
+        let mut state = seed;
+        build(&mut state, 0)
+    }
+
+    #[test]
+    fn test_diff_trees_reports_each_kind() {
+        let old = dir(vec![
+            ("same", TreeNode::text("x")),
+            ("edited", TreeNode::text("before")),
+            ("chmod", TreeNode::file_with_mode("#!/bin/sh\n", 0o644)),
+            (
+                "gone",
+                dir(vec![("a", TreeNode::text("1")), ("b", TreeNode::text("2"))]),
+            ),
+            ("kind", TreeNode::text("target")),
+            ("link", TreeNode::Symlink("one".into())),
+        ]);
+        let new = dir(vec![
+            ("same", TreeNode::text("x")),
+            ("edited", TreeNode::text("after")),
+            ("chmod", TreeNode::file_with_mode("#!/bin/sh\n", 0o755)),
+            ("kind", TreeNode::Symlink("target".into())),
+            ("link", TreeNode::Symlink("two".into())),
+            ("new", dir(vec![("empty", dir(vec![]))])),
+        ]);
+
+        let deltas = diff_trees(&old, &new);
+        let rendered: Vec<_> = deltas.iter().map(render_delta).collect();
+        assert_eq!(
+            rendered,
+            [
+                " M chmod",
+                " M edited",
+                " D gone/a",
+                " D gone/b",
+                " T kind",
+                " M link",
+                " A new/empty",
+            ]
+        );
+        assert_eq!(
+            deltas[0],
+            TreeDelta::ModeChanged {
+                path: "chmod".into(),
+                old: Some(0o644),
+                new: Some(0o755),
+            }
+        );
+    }
+
+    #[test]
+    fn test_merge_trees_policies() {
+        let base = || {
+            dir(vec![
+                ("description", TreeNode::text("base\n")),
+                ("same", TreeNode::text("x")),
+                ("hooks", dir(vec![("a.sample", TreeNode::text("a"))])),
+            ])
+        };
+        let overlay = || {
+            dir(vec![
+                ("description", TreeNode::text("overlay\n")),
+                ("same", TreeNode::text("x")),
+                (
+                    "hooks",
+                    dir(vec![("b", TreeNode::Symlink("b.sample".into()))]),
+                ),
+            ])
+        };
+        let merged_hooks = dir(vec![
+            ("a.sample", TreeNode::text("a")),
+            ("b", TreeNode::Symlink("b.sample".into())),
+        ]);
+
+        let merged = merge_trees(base(), overlay(), MergePolicy::OverlayWins).unwrap();
+        assert_eq!(
+            merged,
+            dir(vec![
+                ("description", TreeNode::text("overlay\n")),
+                ("same", TreeNode::text("x")),
+                ("hooks", merged_hooks.clone()),
+            ])
+        );
+
+        let merged = merge_trees(base(), overlay(), MergePolicy::BaseWins).unwrap();
+        assert_eq!(
+            merged,
+            dir(vec![
+                ("description", TreeNode::text("base\n")),
+                ("same", TreeNode::text("x")),
+                ("hooks", merged_hooks),
+            ])
+        );
+
+        let err = merge_trees(base(), overlay(), MergePolicy::ErrorOnConflict).unwrap_err();
+        assert!(err.to_string().contains("'description'"), "{}", err);
+        // Identical files and disjoint additions are not conflicts
+        let mut without_description = overlay();
+        if let TreeNode::Directory {
+            entries: children, ..
+        } = &mut without_description
+        {
+            children.remove("description");
+        }
+        assert!(merge_trees(base(), without_description, MergePolicy::ErrorOnConflict).is_ok());
+    }
+
+    #[test]
+    fn test_merge_trees_file_directory_collision() {
+        let base = dir(vec![(
+            "info",
+            dir(vec![("exclude", dir(vec![("x", TreeNode::text(""))]))]),
+        )]);
+        let overlay = dir(vec![(
+            "info",
+            dir(vec![("exclude", TreeNode::text("*.o\n"))]),
+        )]);
+
+        for policy in [
+            MergePolicy::OverlayWins,
+            MergePolicy::BaseWins,
+            MergePolicy::ErrorOnConflict,
+        ] {
+            let err = merge_trees(base.clone(), overlay.clone(), policy).unwrap_err();
+            let path = Path::new("info").join("exclude");
+            assert!(
+                err.to_string().contains(&format!("'{}'", path.display())),
+                "{}",
+                err
+            );
+            let err = merge_trees(overlay.clone(), base.clone(), policy).unwrap_err();
+            assert!(err.to_string().contains("is a directory"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_diff_trees_properties() {
+        for seed in 0..200 {
+            let old = generated_tree(seed);
+            let new = generated_tree(seed + 1000);
+
+            assert!(diff_trees(&old, &old).is_empty(), "seed {}", seed);
+
+            let deltas = diff_trees(&old, &new);
+            let mut sorted = deltas.clone();
+            sorted.sort_by(|a, b| a.path().cmp(b.path()));
+            assert_eq!(deltas, sorted, "seed {}", seed);
+
+            // Copying every reported path over from `new` reconciles the trees
+            assert_eq!(apply(&old, &new, &deltas), new, "seed {}", seed);
+        }
+    }
+}
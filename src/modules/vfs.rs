@@ -0,0 +1,597 @@
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::modules::{
+    files::{replace_with_symlink, set_mode, write_new_file_atomic},
+    lockfile::Lockfile,
+};
+
+/// The kind of entry at a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// What `Vfs::metadata` reports about a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsMetadata {
+    pub kind: FileKind,
+    /// Content length for files; 0 otherwise.
+    pub len: u64,
+    /// Permission bits, where the filesystem has them.
+    pub mode: Option<u32>,
+}
+
+impl VfsMetadata {
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+}
+
+/// The filesystem operations tree writes, discovery and the config reader depend on.
+///
+/// `RealFs` goes to disk; `MemFs` keeps everything in memory, so tests using it need
+/// no temporary directories and can't interfere with each other.
+pub trait Vfs: Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    /// Creates or truncates `path`.
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()>;
+    /// Creates one directory; its parent must exist.
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Follows symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
+    /// Describes a symlink itself rather than its target.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
+    /// Creates `link` pointing at `target`.
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Removes an empty directory.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// The names in a directory, sorted.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>>;
+    fn set_mode(&self, path: &Path, mode: u32) -> Result<()>;
+
+    /// Creates `path` holding `content` unless something is already there, in which
+    /// case it returns false. Readers never see a partly written file.
+    fn write_new(&self, path: &Path, content: &[u8]) -> Result<bool>;
+    /// Replaces `path` with `content`; readers see the old or the new file, never a mix.
+    fn replace(&self, path: &Path, content: &[u8]) -> Result<()>;
+    /// Points `link` at `target`, replacing a file or symlink already there.
+    fn replace_symlink(&self, target: &Path, link: &Path) -> Result<()>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| metadata.is_file())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| metadata.is_dir())
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.utf8_error()))
+    }
+}
+
+/// The real filesystem, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        fs::write(path, content)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        fs::metadata(path).map(|metadata| real_metadata(&metadata))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        fs::symlink_metadata(path).map(|metadata| real_metadata(&metadata))
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn symlink(&self, _target: &Path, _link: &Path) -> io::Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let mut names = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> Result<()> {
+        set_mode(path, mode)
+    }
+
+    fn write_new(&self, path: &Path, content: &[u8]) -> Result<bool> {
+        write_new_file_atomic(path, content)
+    }
+
+    // Note: Goes through a lock, so a concurrent writer (e.g. `cs01 config`) can't
+    // interleave with this one.
+    fn replace(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let mut lock = Lockfile::acquire(path)?;
+        lock.write(content)?;
+        lock.commit()
+    }
+
+    fn replace_symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        replace_with_symlink(target, link)
+    }
+}
+
+fn real_metadata(metadata: &fs::Metadata) -> VfsMetadata {
+    let kind = if metadata.is_symlink() {
+        FileKind::Symlink
+    } else if metadata.is_dir() {
+        FileKind::Dir
+    } else {
+        FileKind::File
+    };
+
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+
+    VfsMetadata {
+        kind,
+        len: if kind == FileKind::File {
+            metadata.len()
+        } else {
+            0
+        },
+        mode,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MemEntry {
+    File { content: Vec<u8>, mode: u32 },
+    Dir { mode: u32 },
+    Symlink(PathBuf),
+}
+
+/// An in-memory filesystem for tests.
+///
+/// Note: Paths are taken literally; `..` is not resolved and only the last component
+/// of a path may be a symlink. The root (`/`, or an empty path) always exists.
+#[derive(Debug, Default)]
+pub struct MemFs {
+    entries: Mutex<BTreeMap<PathBuf, MemEntry>>,
+}
+
+/// Permission bits `MemFs` gives new entries, as a `022` umask would.
+const MEM_FILE_MODE: u32 = 0o644;
+const MEM_DIR_MODE: u32 = 0o755;
+
+/// Guards against symlink cycles in `MemFs`, like the kernel's `ELOOP` limit.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+impl MemFs {
+    pub fn new() -> Self {
+        MemFs::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, MemEntry>> {
+        // A panicking test thread mustn't take every other user of the fs down with it.
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn is_root(path: &Path) -> bool {
+    path.parent().is_none() || path.as_os_str().is_empty()
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("{:?} not found", path))
+}
+
+/// Fails unless `path`'s parent is an existing directory.
+fn check_parent(entries: &BTreeMap<PathBuf, MemEntry>, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !is_root(parent) => match entries.get(parent) {
+            Some(MemEntry::Dir { .. }) => Ok(()),
+            Some(_) => Err(io::Error::new(
+                ErrorKind::NotADirectory,
+                format!("{:?} is not a directory", parent),
+            )),
+            None => Err(not_found(parent)),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// The entry at `path`, following a final symlink when `follow` is set.
+fn lookup(
+    entries: &BTreeMap<PathBuf, MemEntry>,
+    path: &Path,
+    follow: bool,
+) -> io::Result<(PathBuf, MemEntry)> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        if is_root(&path) {
+            return Ok((path, MemEntry::Dir { mode: MEM_DIR_MODE }));
+        }
+        match entries.get(&path) {
+            Some(MemEntry::Symlink(target)) if follow => {
+                path = path.parent().unwrap_or(Path::new("")).join(target);
+            }
+            Some(entry) => return Ok((path, entry.clone())),
+            None => return Err(not_found(&path)),
+        }
+    }
+    Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("too many levels of symbolic links at {:?}", path),
+    ))
+}
+
+fn mem_metadata(entry: &MemEntry) -> VfsMetadata {
+    match entry {
+        MemEntry::File { content, mode } => VfsMetadata {
+            kind: FileKind::File,
+            len: content.len() as u64,
+            mode: Some(*mode),
+        },
+        MemEntry::Dir { mode } => VfsMetadata {
+            kind: FileKind::Dir,
+            len: 0,
+            mode: Some(*mode),
+        },
+        MemEntry::Symlink(_) => VfsMetadata {
+            kind: FileKind::Symlink,
+            len: 0,
+            mode: None,
+        },
+    }
+}
+
+impl Vfs for MemFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match lookup(&self.entries(), path, true)? {
+            (_, MemEntry::File { content, .. }) => Ok(content),
+            _ => Err(io::Error::new(
+                ErrorKind::IsADirectory,
+                format!("{:?} is a directory", path),
+            )),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        let mut entries = self.entries();
+        check_parent(&entries, path)?;
+        let (path, mode) = match lookup(&entries, path, true) {
+            Ok((_, MemEntry::Dir { .. })) => {
+                return Err(io::Error::new(
+                    ErrorKind::IsADirectory,
+                    format!("{:?} is a directory", path),
+                ));
+            }
+            Ok((resolved, MemEntry::File { mode, .. })) => (resolved, mode),
+            _ => (path.to_path_buf(), MEM_FILE_MODE),
+        };
+        entries.insert(
+            path,
+            MemEntry::File {
+                content: content.to_vec(),
+                mode,
+            },
+        );
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        if is_root(path) || entries.contains_key(path) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{:?} already exists", path),
+            ));
+        }
+        check_parent(&entries, path)?;
+        entries.insert(path.to_path_buf(), MemEntry::Dir { mode: MEM_DIR_MODE });
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        if is_root(path) || self.is_dir(path) {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.create_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        let entry = entries.get(from).cloned().ok_or_else(|| not_found(from))?;
+        check_parent(&entries, to)?;
+        match (&entry, entries.get(to)) {
+            (_, None) => {}
+            (MemEntry::Dir { .. }, Some(MemEntry::Dir { .. }))
+                if entries.keys().any(|path| path.parent() == Some(to)) =>
+            {
+                return Err(io::Error::new(
+                    ErrorKind::DirectoryNotEmpty,
+                    format!("{:?} is not empty", to),
+                ));
+            }
+            (MemEntry::Dir { .. }, Some(MemEntry::Dir { .. })) => {}
+            (MemEntry::Dir { .. }, Some(_)) | (_, Some(MemEntry::Dir { .. })) => {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("cannot replace {:?} with {:?}", to, from),
+                ));
+            }
+            (_, Some(_)) => {}
+        }
+
+        // Moves everything below `from` along with it.
+        let moved: Vec<PathBuf> = entries
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let entry = entries.remove(&path).unwrap();
+            let relative = path.strip_prefix(from).unwrap();
+            let new_path = if relative.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(relative)
+            };
+            entries.insert(new_path, entry);
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        lookup(&self.entries(), path, true).map(|(_, entry)| mem_metadata(&entry))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        lookup(&self.entries(), path, false).map(|(_, entry)| mem_metadata(&entry))
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        if is_root(link) || entries.contains_key(link) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{:?} already exists", link),
+            ));
+        }
+        check_parent(&entries, link)?;
+        entries.insert(link.to_path_buf(), MemEntry::Symlink(target.to_path_buf()));
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        match entries.get(path) {
+            Some(MemEntry::Dir { .. }) => Err(io::Error::new(
+                ErrorKind::IsADirectory,
+                format!("{:?} is a directory", path),
+            )),
+            Some(_) => {
+                entries.remove(path);
+                Ok(())
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        match entries.get(path) {
+            Some(MemEntry::Dir { .. })
+                if entries.keys().any(|child| child.parent() == Some(path)) =>
+            {
+                Err(io::Error::new(
+                    ErrorKind::DirectoryNotEmpty,
+                    format!("{:?} is not empty", path),
+                ))
+            }
+            Some(MemEntry::Dir { .. }) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(_) => Err(io::Error::new(
+                ErrorKind::NotADirectory,
+                format!("{:?} is not a directory", path),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        if !matches!(entries.get(path), Some(MemEntry::Dir { .. })) {
+            return Err(not_found(path));
+        }
+        entries.retain(|entry, _| !entry.starts_with(path));
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let entries = self.entries();
+        let (dir, entry) = lookup(&entries, path, true)?;
+        if !matches!(entry, MemEntry::Dir { .. }) {
+            return Err(io::Error::new(
+                ErrorKind::NotADirectory,
+                format!("{:?} is not a directory", path),
+            ));
+        }
+        // Keys are sorted, so the names come out sorted too.
+        Ok(entries
+            .keys()
+            .filter(|child| child.parent() == Some(&dir))
+            .filter_map(|child| child.file_name().map(OsString::from))
+            .collect())
+    }
+
+    fn set_mode(&self, path: &Path, new_mode: u32) -> Result<()> {
+        let mut entries = self.entries();
+        let (resolved, _) = lookup(&entries, path, true)
+            .with_context(|| format!("Failed to set permissions on {:?}", path))?;
+        match entries.get_mut(&resolved) {
+            Some(MemEntry::File { mode, .. } | MemEntry::Dir { mode }) => *mode = new_mode,
+            _ => bail!("Failed to set permissions on {:?}", path),
+        }
+        Ok(())
+    }
+
+    fn write_new(&self, path: &Path, content: &[u8]) -> Result<bool> {
+        if self.symlink_metadata(path).is_ok() {
+            return Ok(false);
+        }
+        self.write(path, content)
+            .with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(true)
+    }
+
+    fn replace(&self, path: &Path, content: &[u8]) -> Result<()> {
+        self.write(path, content)
+            .with_context(|| format!("Failed to write {:?}", path))
+    }
+
+    fn replace_symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        match self.symlink_metadata(link) {
+            Ok(metadata) if metadata.is_dir() => {
+                bail!("Refusing to replace directory {:?} with a symlink", link)
+            }
+            Ok(_) => self.remove_file(link)?,
+            Err(_) => {}
+        }
+        self.symlink(target, link)
+            .with_context(|| format!("Failed to create symlink {:?} -> {:?}", link, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mem_fs_basics() {
+        let fs = MemFs::new();
+        let root = Path::new("/repo");
+
+        assert!(
+            fs.write(&root.join("HEAD"), b"x").is_err(),
+            "parent missing"
+        );
+        fs.create_dir_all(&root.join("refs/heads")).unwrap();
+        fs.write(&root.join("HEAD"), b"ref: refs/heads/main\n")
+            .unwrap();
+        assert_eq!(
+            fs.read(&root.join("HEAD")).unwrap(),
+            b"ref: refs/heads/main\n"
+        );
+        assert!(fs.is_file(&root.join("HEAD")));
+        assert!(fs.is_dir(&root.join("refs")));
+        assert_eq!(fs.read_dir(root).unwrap(), ["HEAD", "refs"]);
+
+        // Directories must be empty to go, and can't be written over
+        assert_eq!(
+            fs.remove_dir(&root.join("refs")).unwrap_err().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        assert!(fs.write(&root.join("refs"), b"x").is_err());
+        assert!(fs.create_dir(&root.join("HEAD/x")).is_err());
+
+        // Symlinks resolve relative to their directory
+        fs.symlink(Path::new("HEAD"), &root.join("link")).unwrap();
+        assert_eq!(
+            fs.read(&root.join("link")).unwrap(),
+            b"ref: refs/heads/main\n"
+        );
+        assert_eq!(
+            fs.symlink_metadata(&root.join("link")).unwrap().kind,
+            FileKind::Symlink
+        );
+        fs.symlink(Path::new("loop"), &root.join("loop")).unwrap();
+        assert!(fs.metadata(&root.join("loop")).is_err());
+
+        // Renaming a directory takes its contents along
+        fs.rename(&root.join("refs"), &root.join("moved")).unwrap();
+        assert!(fs.is_dir(&root.join("moved/heads")));
+        assert!(!fs.exists(&root.join("refs")));
+
+        fs.set_mode(&root.join("HEAD"), 0o600).unwrap();
+        assert_eq!(fs.metadata(&root.join("HEAD")).unwrap().mode, Some(0o600));
+
+        assert!(!fs.write_new(&root.join("HEAD"), b"new").unwrap());
+        assert!(fs.write_new(&root.join("ORIG_HEAD"), b"new").unwrap());
+
+        fs.remove_dir_all(root).unwrap();
+        assert!(fs.read_dir(Path::new("/")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,454 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Component, Path, PathBuf};
+
+use crate::commands::init;
+pub use crate::commands::init::{InitOptions, InitOutcome};
+use crate::modules::{
+    config::Config,
+    constants::{METADATA_DIR, is_metadata_dir_name},
+    files::{discover, looks_like_repo_dir},
+    hash::ObjectId,
+    index::Index,
+    odb::Odb,
+    output::Output,
+    refs::{HeadState, head_state, resolve_ref},
+    revision::resolve_revision,
+};
+
+/// A command that needs a repository was run outside one, or was pointed at a directory
+/// that isn't one (`Repository::open`, `CS01_DIR`).
+///
+/// Every command but those the CLI lists as working outside a repository needs one, and
+/// fails with this error (exit status 4).
+#[derive(Debug)]
+pub struct NotARepository {
+    /// The directory that was expected to be a repository, or where the search for one
+    /// started.
+    pub path: PathBuf,
+    /// Whether the parents of `path` were searched too.
+    pub searched: bool,
+}
+
+impl NotARepository {
+    /// No repository contains the current directory.
+    pub fn in_current_dir() -> Self {
+        let cwd = Path::new(".");
+        NotARepository {
+            path: std::path::absolute(cwd).unwrap_or_else(|_| cwd.to_path_buf()),
+            searched: true,
+        }
+    }
+}
+
+impl std::fmt::Display for NotARepository {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.searched {
+            write!(
+                f,
+                "not a CS01 repository (or any of the parent directories): {} \
+                 (searched from '{}')",
+                METADATA_DIR,
+                self.path.display()
+            )
+        } else {
+            write!(f, "not a CS01 repository: '{}'", self.path.display())
+        }
+    }
+}
+
+impl std::error::Error for NotARepository {}
+
+/// A CS01 repository: where its metadata and working tree live, and access to its state.
+///
+/// This is the entry point for using CS01 as a library, and the CLI goes through it too,
+/// so both always agree on discovery rules (`CS01_DIR`, `CS01_WORK_TREE`, bare repositories).
+///
+/// ```no_run
+/// use cs_01::repo::Repository;
+///
+/// let repo = Repository::discover(std::path::Path::new("."))?.expect("not in a repository");
+/// println!("metadata in {}", repo.repo_dir().display());
+/// if let Some(branch) = repo.config()?.get_string("init.defaultbranch")? {
+///     println!("default branch: {}", branch);
+/// }
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repository {
+    repo_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+}
+
+impl Repository {
+    /// Finds the repository containing `start` (or named by `CS01_DIR`), searching upwards.
+    ///
+    /// Returns `None` when `start` isn't inside a repository.
+    pub fn discover(start: &Path) -> Result<Option<Self>> {
+        let start = std::path::absolute(start)
+            .with_context(|| format!("Failed to resolve {}", start.display()))?;
+        Ok(discover(Some(&start))?.map(|found| Repository {
+            repo_dir: found.repo_dir,
+            work_tree: found.work_tree,
+        }))
+    }
+
+    /// The repository containing the current directory (or named by `CS01_DIR`),
+    /// failing with `NotARepository` when there is none.
+    pub fn current() -> Result<Self> {
+        Self::discover(Path::new("."))?.ok_or_else(|| NotARepository::in_current_dir().into())
+    }
+
+    /// Opens the repository whose metadata directory is `repo_dir`, without searching.
+    ///
+    /// A directory named `.CS01` belongs to the working tree around it; anything else is bare.
+    pub fn open(repo_dir: &Path) -> Result<Self> {
+        if !looks_like_repo_dir(repo_dir) {
+            return Err(NotARepository {
+                path: repo_dir.to_path_buf(),
+                searched: false,
+            }
+            .into());
+        }
+
+        let work_tree = match repo_dir.parent() {
+            Some(parent) if repo_dir.file_name().is_some_and(is_metadata_dir_name) => {
+                Some(parent.to_path_buf())
+            }
+            _ => None,
+        };
+        Ok(Repository {
+            repo_dir: repo_dir.to_path_buf(),
+            work_tree,
+        })
+    }
+
+    /// Creates a repository at `path`, or repairs the one already there.
+    ///
+    /// Warnings (e.g. about nesting with `force`) are printed to stderr.
+    pub fn init(path: &Path, options: InitOptions) -> Result<Self> {
+        Ok(Self::init_with_output(path, &options, &Output::default())?.0)
+    }
+
+    /// Like `init`, but routes warnings through `out` and also reports what init did.
+    ///
+    /// Note: With `options.dry_run` nothing is created; the plan is in `InitOutcome::plan`
+    /// and the returned `Repository` describes where it would be.
+    pub fn init_with_output(
+        path: &Path,
+        options: &InitOptions,
+        out: &Output,
+    ) -> Result<(Self, InitOutcome)> {
+        let outcome = init::init(path, options, out)?;
+        let repo = Repository {
+            repo_dir: outcome.repo_dir.clone(),
+            work_tree: outcome.work_tree.clone(),
+        };
+        Ok((repo, outcome))
+    }
+
+    /// The metadata directory (`.CS01`, or the repository itself when bare).
+    pub fn repo_dir(&self) -> &Path {
+        &self.repo_dir
+    }
+
+    /// The working tree root; `None` for bare repositories.
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.work_tree.is_none()
+    }
+
+    /// The repository's own config file (`<repo_dir>/config`).
+    pub fn config_path(&self) -> PathBuf {
+        self.repo_dir.join("config")
+    }
+
+    /// The effective config: system, global, this repository's file, then environment overrides.
+    pub fn config(&self) -> Result<Config> {
+        Config::load_cascaded(Some(&self.repo_dir))
+    }
+
+    /// Where `path` (relative to `cwd`, or absolute) is in the working tree: a
+    /// `/`-separated path from its top, empty for the top itself.
+    ///
+    /// Note: `.` and `..` are resolved by name first, as git does with pathspecs, so
+    /// `cs01 add .` in `src/` means `src`; symlinked directories above the working tree
+    /// (like `/tmp` on macOS) are resolved only when that isn't enough.
+    pub fn work_tree_path(&self, cwd: &Path, path: &Path) -> Result<String> {
+        let work_tree = self
+            .work_tree()
+            .context("this operation must be run in a work tree")?;
+        let lexical = |dir: &Path| -> Result<PathBuf> {
+            let dir = std::path::absolute(dir)
+                .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+            Ok(normalize_path(&dir))
+        };
+
+        let mut relative = lexical(&cwd.join(path))?
+            .strip_prefix(lexical(work_tree)?)
+            .map(Path::to_path_buf);
+        if relative.is_err()
+            && let (Ok(cwd), Ok(top)) = (cwd.canonicalize(), work_tree.canonicalize())
+        {
+            relative = normalize_path(&cwd.join(path))
+                .strip_prefix(top)
+                .map(Path::to_path_buf);
+        }
+        let Ok(relative) = relative else {
+            bail!(
+                "'{}' is outside repository at '{}'",
+                path.display(),
+                work_tree.display()
+            );
+        };
+
+        let parts = relative
+            .iter()
+            .map(|part| {
+                part.to_str()
+                    .with_context(|| format!("path {:?} is not valid UTF-8", path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(parts.join("/"))
+    }
+
+    /// The staging area's file (`<repo_dir>/index`); see `Index`.
+    pub fn index_path(&self) -> PathBuf {
+        self.repo_dir.join("index")
+    }
+
+    /// Reads the index; while there is none yet, an empty one that will be written in
+    /// the version `index.version` asks for, as git creates new index files.
+    pub fn read_index(&self) -> Result<Index> {
+        let path = self.index_path();
+        if path.exists() {
+            return Index::read(&path);
+        }
+        match self.config()?.get_int("index.version")? {
+            None => Ok(Index::default()),
+            Some(version) => u32::try_from(version)
+                .ok()
+                .and_then(|version| Index::with_version(version).ok())
+                .with_context(|| format!("bad config value {} for 'index.version'", version)),
+        }
+    }
+
+    /// The repository's object database (`<repo_dir>/objects`).
+    pub fn odb(&self) -> Result<Odb> {
+        Odb::open(&self.repo_dir)
+    }
+
+    /// What HEAD points at, or `None` when HEAD is missing.
+    pub fn head_ref(&self) -> Result<Option<HeadState>> {
+        head_state(&self.repo_dir)
+    }
+
+    /// The commit HEAD points at, or `None` while its branch is unborn.
+    pub fn head_commit(&self) -> Result<Option<ObjectId>> {
+        let resolved = resolve_ref(&self.repo_dir, "HEAD")?;
+        resolved
+            .oid
+            .map(|oid| {
+                ObjectId::from_hex(&oid)
+                    .with_context(|| format!("reference {} is broken", resolved.name))
+            })
+            .transpose()
+    }
+
+    /// The object a revision like `main~2` or `1a2b3c` names; see `resolve_revision`.
+    pub fn resolve_revision(&self, spec: &str) -> Result<ObjectId> {
+        resolve_revision(&self.repo_dir, &self.odb()?, spec)
+    }
+}
+
+/// `path` with `.` components dropped and each `..` removing the component before it.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_discover_from_nested_dir() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let created = Repository::init(&root, InitOptions::default()).unwrap();
+
+        let nested = root.join("src/deeply/nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        let repo = Repository::discover(&nested).unwrap().unwrap();
+
+        assert_eq!(repo, created);
+        assert_eq!(repo.repo_dir(), root.join(".CS01"));
+        assert_eq!(repo.work_tree(), Some(root.as_path()));
+        assert!(!repo.is_bare());
+        assert!(matches!(
+            repo.head_ref().unwrap(),
+            Some(HeadState::Branch(_))
+        ));
+        assert_eq!(
+            repo.config().unwrap().get_bool("core.bare").unwrap(),
+            Some(false)
+        );
+        assert_eq!(Repository::open(repo.repo_dir()).unwrap(), repo);
+    }
+
+    #[test]
+    fn test_work_tree_path() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap().join("work");
+        let repo = Repository::init(&root, InitOptions::default()).unwrap();
+        let src = root.join("src");
+        std::fs::create_dir_all(&src).unwrap();
+
+        let cases = [
+            (&root, ".", ""),
+            (&root, "src/main.rs", "src/main.rs"),
+            (&src, ".", "src"),
+            (&src, "./lib/../main.rs", "src/main.rs"),
+            (&src, "..", ""),
+            (&src, "../README.md", "README.md"),
+        ];
+        for (cwd, path, expected) in cases {
+            assert_eq!(
+                repo.work_tree_path(cwd, Path::new(path)).unwrap(),
+                expected,
+                "{}",
+                path
+            );
+        }
+        assert_eq!(repo.work_tree_path(&src, &root.join("a/b")).unwrap(), "a/b");
+
+        let err = repo.work_tree_path(&src, Path::new("../..")).unwrap_err();
+        assert!(err.to_string().contains("outside repository"), "{}", err);
+        // Reached through a symlink to the working tree
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&root, &link).unwrap();
+            assert_eq!(
+                repo.work_tree_path(&link.join("src"), Path::new("x"))
+                    .unwrap(),
+                "src/x"
+            );
+        }
+    }
+
+    #[test]
+    fn test_discover_bare_repo() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap().join("project.cs01");
+        let options = InitOptions {
+            bare: true,
+            initial_branch: Some("trunk".to_string()),
+            ..InitOptions::default()
+        };
+        Repository::init(&root, options).unwrap();
+
+        let repo = Repository::discover(&root.join("refs/heads"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(repo.repo_dir(), root);
+        assert_eq!(repo.work_tree(), None);
+        assert!(repo.is_bare());
+        assert_eq!(
+            repo.head_ref().unwrap(),
+            Some(HeadState::Branch("trunk".to_string()))
+        );
+        assert_eq!(
+            repo.config().unwrap().get_bool("core.bare").unwrap(),
+            Some(true)
+        );
+        assert_eq!(Repository::open(&root).unwrap(), repo);
+    }
+
+    #[test]
+    fn test_discover_through_cs01_file() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let meta = root.join("meta");
+        Repository::init(
+            &meta,
+            InitOptions {
+                bare: true,
+                ..InitOptions::default()
+            },
+        )
+        .unwrap();
+
+        // The working tree's `.CS01` is a file pointing at the sibling metadata directory
+        let work = root.join("work");
+        std::fs::create_dir_all(work.join("src")).unwrap();
+        std::fs::write(work.join(".CS01"), "cs01dir: ../meta\n").unwrap();
+
+        let repo = Repository::discover(&work.join("src")).unwrap().unwrap();
+        assert_eq!(repo.repo_dir(), meta);
+        assert_eq!(repo.work_tree(), Some(work.as_path()));
+        assert!(repo.head_ref().unwrap().is_some());
+
+        // A pointer that leads nowhere is an error, not a reason to keep searching
+        for content in [
+            "",
+            "gitdir: ../meta\n",
+            "cs01dir: \n",
+            "cs01dir: ../missing\n",
+        ] {
+            std::fs::write(work.join(".CS01"), content).unwrap();
+            let err = Repository::discover(&work).unwrap_err().to_string();
+            assert!(
+                err.starts_with("invalid cs01 file"),
+                "{:?}: {}",
+                content,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_discover_lowercase_metadata_dir() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let lower = root.join("lower");
+        Repository::init(&lower, InitOptions::default()).unwrap();
+        std::fs::rename(lower.join(".CS01"), lower.join(".cs01")).unwrap();
+        // Note: On a case-insensitive filesystem both spellings are the same entry.
+        if lower.join(".CS01").exists() {
+            return;
+        }
+
+        std::fs::create_dir(lower.join("src")).unwrap();
+        let repo = Repository::discover(&lower.join("src")).unwrap().unwrap();
+        assert_eq!(repo.repo_dir(), lower.join(".cs01"));
+        assert_eq!(repo.work_tree(), Some(lower.as_path()));
+        assert_eq!(Repository::open(&lower.join(".cs01")).unwrap(), repo);
+
+        // Init won't add a second, differently cased metadata directory
+        let err = Repository::init(&lower, InitOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("already contains"), "{}", err);
+        assert!(!lower.join(".CS01").exists());
+    }
+
+    #[test]
+    fn test_discover_outside_repo() {
+        let dir = tempdir().unwrap();
+        assert_eq!(Repository::discover(dir.path()).unwrap(), None);
+        assert!(Repository::open(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! What the integration tests share: running cs01 cut off from the developer's own
+//! environment, and shortcuts for the steps most tests start with.
+
+// Note: Each test file is its own crate and uses only some of these.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// A cs01 invocation in `dir`, with a fixed author and committer so commit ids are
+/// reproducible.
+///
+/// Note: The global and system config files point somewhere empty and the editor always
+/// fails, so the developer's own setup can't leak into the results; callers override
+/// any of it with `env`.
+pub fn cs01_command(dir: &Path, args: &[&str]) -> Command {
+    let manifest_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let nowhere = dir.join("no-such-config-dir");
+
+    let mut command = Command::new("cargo");
+    command
+        .args([
+            "run",
+            "--quiet",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+            "--",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .env_remove("CS01_DIR")
+        .env_remove("CS01_WORK_TREE")
+        .env_remove("CS01_OBJECT_DIRECTORY")
+        .env_remove("CS01_CONFIG_COUNT")
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("VISUAL")
+        .env_remove("EDITOR")
+        .env("HOME", &nowhere)
+        .env("CS01_CONFIG_SYSTEM", nowhere.join("system"))
+        .env("CS01_EDITOR", "false")
+        .env("CS01_AUTHOR_NAME", "A U Thor")
+        .env("CS01_AUTHOR_EMAIL", "author@example.com")
+        .env("CS01_AUTHOR_DATE", "1700000000 +0530")
+        .env("CS01_COMMITTER_NAME", "C O Mitter")
+        .env("CS01_COMMITTER_EMAIL", "committer@example.com")
+        .env("CS01_COMMITTER_DATE", "1700000100 -0700");
+    command
+}
+
+pub fn cs01(dir: &Path, args: &[&str]) -> Output {
+    cs01_command(dir, args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+/// Like `cs01`, feeding it `stdin`.
+pub fn cs01_with_stdin(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = cs01_command(dir, args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute command");
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+/// Runs cs01 and returns its output, which must be a success.
+pub fn run(root: &Path, args: &[&str]) -> String {
+    let output = cs01(root, args);
+    assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+    stdout(&output)
+}
+
+pub fn rev_parse(root: &Path, revision: &str) -> String {
+    run(root, &["rev-parse", revision]).trim_end().to_string()
+}
+
+pub fn read(root: &Path, path: &str) -> String {
+    fs::read_to_string(root.join(path)).unwrap()
+}
+
+/// Stages everything and commits it, returning the new commit's id.
+pub fn commit_all(root: &Path, message: &str) -> String {
+    run(root, &["add", "-A"]);
+    run(root, &["commit", "-m", message]);
+    rev_parse(root, "HEAD")
+}
+
+/// Writes `files` and commits everything, returning the new commit's id.
+pub fn commit(root: &Path, files: &[(&str, &str)], message: &str) -> String {
+    for (path, content) in files {
+        fs::write(root.join(path), content).unwrap();
+    }
+    commit_all(root, message)
+}
+
+/// A repository with two commits, `first` and its child `second`, made with
+/// `commit-tree`; no ref points at either.
+pub fn unreferenced_commits(root: &Path) -> (String, String) {
+    run(root, &["init", "-q"]);
+    let tree = run(root, &["write-tree"]).trim_end().to_string();
+    let first = run(root, &["commit-tree", &tree, "-m", "first"]);
+    let first = first.trim_end().to_string();
+    let second = run(root, &["commit-tree", &tree, "-p", &first, "-m", "second"]);
+    (first, second.trim_end().to_string())
+}
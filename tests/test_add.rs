@@ -0,0 +1,342 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stderr, stdout};
+
+// Ids from `git ls-files --stage` after `git add` of the same files
+const README: &str = "100644 ce013625030ba8dba906f756967f9e9ca394464a 0\tREADME.md";
+const LINK: &str = "120000 42061c01a1c70097d1e4579f29a5adf40abdec95 0\tlink";
+const RUN: &str = "100755 4163036efa65bd4a469e752267498f01ea36a55c 0\trun.sh";
+const LIB: &str = "100644 7a788f2457bfeefa9ed2754cb8f08f7bc761ae34 0\tsrc/lib/mod.rs";
+const MAIN: &str = "100644 f328e4d9d04c31d0d70d16d21a07d1613be9d577 0\tsrc/main.rs";
+
+/// The `<mode> <id> <stage>\t<path>` lines of `debug-index`, without the stat data.
+fn staged(root: &Path) -> Vec<String> {
+    let output = cs01(root, &["debug-index"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .map(str::to_string)
+        .collect()
+}
+
+/// A repository holding the files the expected ids above were computed from.
+fn work_tree(dir: &Path) -> std::path::PathBuf {
+    let root = dir.join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::create_dir_all(root.join("src/lib")).unwrap();
+    fs::write(root.join("README.md"), "hello\n").unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("src/lib/mod.rs"), "pub mod x;\n").unwrap();
+    fs::write(root.join("run.sh"), "#!/bin/sh\necho hi\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(root.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("README.md", root.join("link")).unwrap();
+    }
+    root
+}
+
+#[cfg(unix)]
+#[test]
+fn test_add_stages_files_like_git() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(staged(&root).is_empty());
+
+    let output = cs01(&root, &["add", "."]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(staged(&root), [README, LINK, RUN, LIB, MAIN]);
+
+    // The blobs are stored, and the stat data recorded
+    let output = cs01(&root, &["cat-file", "-p", "4163036efa65"]);
+    assert_eq!(stdout(&output), "#!/bin/sh\necho hi\n");
+    let output = cs01(&root, &["--json", "debug-index"]);
+    let entries: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(entries[0]["path"], "README.md");
+    assert_eq!(entries[0]["size"], 6);
+    assert_ne!(entries[0]["mtime"][0], 0);
+    assert_ne!(entries[0]["ino"], 0);
+    assert!(!root.join(".CS01/index.lock").exists());
+
+    // Nothing was staged from the metadata directory
+    let output = cs01(&root, &["debug-index"]);
+    assert!(!stdout(&output).contains(".CS01"));
+    let output = cs01(&root, &["add", ".CS01/config"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("metadata directory"));
+}
+
+#[test]
+fn test_add_creates_the_index_in_the_configured_version() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(
+        cs01(&root, &["config", "index.version", "4"])
+            .status
+            .success()
+    );
+
+    let output = cs01(&root, &["add", "."]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let index = fs::read(root.join(".CS01/index")).unwrap();
+    assert_eq!(&index[..8], b"DIRC\0\0\0\x04");
+    assert_eq!(staged(&root), [README, LINK, RUN, LIB, MAIN]);
+
+    // Git reads it too, where it's installed
+    if let Ok(output) = std::process::Command::new("git")
+        .args(["--git-dir=.CS01", "--work-tree=.", "ls-files", "--stage"])
+        .current_dir(&root)
+        .output()
+    {
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(
+            stdout(&output).lines().collect::<Vec<_>>(),
+            [README, LINK, RUN, LIB, MAIN]
+        );
+    }
+
+    // Later writes keep the version, even after the setting changes
+    assert!(
+        cs01(&root, &["config", "index.version", "2"])
+            .status
+            .success()
+    );
+    fs::write(root.join("README.md"), "changed\n").unwrap();
+    assert!(cs01(&root, &["add", "README.md"]).status.success());
+    assert_eq!(fs::read(root.join(".CS01/index")).unwrap()[7], 4);
+
+    // Only versions git can read are accepted
+    fs::remove_file(root.join(".CS01/index")).unwrap();
+    assert!(
+        cs01(&root, &["config", "index.version", "5"])
+            .status
+            .success()
+    );
+    let output = cs01(&root, &["add", "."]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("index.version"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_add_paths_are_relative_to_the_current_directory() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    let src = root.join("src");
+
+    assert!(cs01(&src, &["add", "."]).status.success());
+    assert_eq!(staged(&root), [LIB, MAIN]);
+
+    let output = cs01(
+        &src,
+        &["--json", "add", "--verbose", "../README.md", "main.rs"],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["added"], serde_json::json!(["README.md"]));
+    assert_eq!(staged(&root), [README, LIB, MAIN]);
+
+    // Outside the working tree, missing files and no paths at all are refused, and
+    // nothing is staged when one path is bad
+    fs::write(root.join("new.txt"), "new\n").unwrap();
+    for (args, expected) in [
+        (&["add", "../.."][..], "outside repository"),
+        (
+            &["add", "../new.txt", "nope.rs"],
+            "pathspec 'nope.rs' did not match",
+        ),
+        (&["add"], "Nothing specified"),
+    ] {
+        let output = cs01(&src, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+    }
+    assert_eq!(staged(&root).len(), 3);
+}
+
+#[test]
+fn test_add_respects_ignore_rules() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    fs::write(root.join("build.log"), "noise\n").unwrap();
+    fs::create_dir(root.join("target")).unwrap();
+    fs::write(root.join("target/out.bin"), "binary\n").unwrap();
+    let mut exclude = fs::read_to_string(root.join(".CS01/info/exclude")).unwrap();
+    exclude.push_str("build.log\ntarget\n");
+    fs::write(root.join(".CS01/info/exclude"), exclude).unwrap();
+
+    // Ignored files found while recursing are skipped quietly
+    assert!(cs01(&root, &["add", "."]).status.success());
+    let paths = staged(&root).join("\n");
+    assert!(!paths.contains("build.log") && !paths.contains("target"));
+
+    // Named explicitly, they are refused, but the other paths are still staged
+    fs::write(root.join("notes.txt"), "notes\n").unwrap();
+    let output = cs01(&root, &["add", "build.log", "notes.txt"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("ignored"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("build.log"));
+    assert!(stderr(&output).contains("-f"));
+    assert!(staged(&root).join("\n").contains("\tnotes.txt"));
+
+    let output = cs01(&root, &["add", "-f", "build.log", "target/out.bin"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let paths = staged(&root).join("\n");
+    assert!(paths.contains("\tbuild.log") && paths.contains("\ttarget/out.bin"));
+
+    // Once tracked, changes to them are staged like any other
+    fs::write(root.join("target/out.bin"), "rebuilt\n").unwrap();
+    let output = cs01(&root, &["add", "-v", "."]);
+    assert_eq!(stdout(&output), "add 'target/out.bin'\n");
+}
+
+#[test]
+fn test_add_stages_deletions_with_update_or_all() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    fs::remove_file(root.join("run.sh")).unwrap();
+    assert!(cs01(&root, &["add", "README.md", "src"]).status.success());
+
+    fs::remove_file(root.join("src/main.rs")).unwrap();
+    fs::write(root.join("README.md"), "changed\n").unwrap();
+    fs::write(root.join("new.txt"), "new\n").unwrap();
+
+    // A plain add leaves deleted files staged
+    assert!(cs01(&root, &["add", "src"]).status.success());
+    assert!(staged(&root).contains(&MAIN.to_string()));
+
+    // -u stages changes and deletions of tracked files, but adds nothing new
+    let output = cs01(&root, &["add", "-u", "-v"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "add 'README.md'\nremove 'src/main.rs'\n");
+    let paths = staged(&root).join("\n");
+    assert!(!paths.contains("new.txt") && !paths.contains("main.rs"));
+
+    // -A adds new files too, and a deleted directory can be named
+    fs::remove_dir_all(root.join("src")).unwrap();
+    let output = cs01(&root, &["add", "-A", "-v", "src", "new.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "add 'new.txt'\nremove 'src/lib/mod.rs'\n");
+    let paths: Vec<String> = staged(&root)
+        .iter()
+        .map(|line| line.split('\t').nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(paths, ["README.md", "new.txt"]);
+    assert!(!cs01(&root, &["add", "-u", "-A"]).status.success());
+}
+
+#[test]
+fn test_readding_unchanged_files_keeps_the_index() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    let index = root.join(".CS01/index");
+    assert!(cs01(&root, &["add", "."]).status.success());
+    let before = stdout(&cs01(&root, &["debug-index"]));
+    let bytes = fs::read(&index).unwrap();
+    #[cfg(unix)]
+    let inode = {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(&index).unwrap().ino()
+    };
+
+    let output = cs01(&root, &["add", "-v", "."]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stdout(&cs01(&root, &["debug-index"])), before);
+    assert_eq!(fs::read(&index).unwrap(), bytes);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(fs::metadata(&index).unwrap().ino(), inode);
+    }
+
+    // A change of the same size is still noticed
+    fs::write(root.join("README.md"), "HELLO\n").unwrap();
+    let output = cs01(&root, &["add", "-v", "."]);
+    assert_eq!(stdout(&output), "add 'README.md'\n");
+    assert_ne!(stdout(&cs01(&root, &["debug-index"])), before);
+}
+
+#[test]
+fn test_add_leaves_embedded_repositories_alone() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join("README.md"), "hello\n").unwrap();
+    // git's metadata beside ours, a git clone and a cs01 repository in subdirectories
+    for path in [
+        ".git/config",
+        "sub/.git/HEAD",
+        "sub/file.txt",
+        "deep/nested/.CS01/HEAD",
+        "deep/nested/secret.txt",
+    ] {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "embedded only\n").unwrap();
+    }
+
+    let output = cs01(&root, &["add", "."]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let err = stderr(&output);
+    assert!(
+        err.starts_with(
+            "warning: adding embedded repository: deep/nested\n\
+             warning: adding embedded repository: sub\nhint: "
+        ),
+        "{}",
+        err
+    );
+    assert_eq!(staged(&root), [README]);
+
+    // Nothing from them reached our object store
+    let output = cs01(&root, &["hash-object", "sub/file.txt"]);
+    let blob = stdout(&output).trim_end().to_string();
+    assert!(!cs01(&root, &["cat-file", "-e", &blob]).status.success());
+
+    // Naming them, or what's inside, stages nothing either
+    let output = cs01(&root, &["add", "sub"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("adding embedded repository: sub\n"));
+    // With --json, the warning is JSON too and the hint is left out
+    let output = cs01(&root, &["--json", "add", "sub"]);
+    assert!(output.status.success());
+    assert_eq!(
+        stderr(&output),
+        "{\"warning\":\"adding embedded repository: sub\"}\n"
+    );
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["embedded"], serde_json::json!(["sub"]));
+    let output = cs01(&root, &["add", "sub/file.txt"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("is inside the embedded repository 'sub'"),
+        "{}",
+        stderr(&output)
+    );
+    let output = cs01(&root, &["add", ".git/config"]);
+    assert!(stderr(&output).contains("metadata directory"));
+    assert_eq!(staged(&root), [README]);
+
+    // An ignored one isn't warned about
+    fs::write(root.join(".cs01ignore"), "sub/\ndeep/\n").unwrap();
+    let output = cs01(&root, &["add", "."]);
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+
+    let output = cs01(&root, &["commit", "-m", "Only ours"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = cs01(&root, &["ls-files"]);
+    assert_eq!(stdout(&output), ".cs01ignore\nREADME.md\n");
+}
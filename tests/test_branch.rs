@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, rev_parse, stderr, stdout};
+
+/// Writes `name` and commits it; returns the new commit's id.
+fn commit_file(root: &Path, name: &str) -> String {
+    fs::write(root.join(name), format!("{}\n", name)).unwrap();
+    assert!(cs01(root, &["add", name]).status.success());
+    let output = cs01(root, &["commit", "-m", name]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    rev_parse(root, "HEAD")
+}
+
+fn init(dir: &Path) -> std::path::PathBuf {
+    let root = dir.join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    root
+}
+
+#[test]
+fn test_branch_create_and_list() {
+    let dir = tempdir().unwrap();
+    let root = init(dir.path());
+
+    // Before the first commit there is nothing to list or branch from
+    let output = cs01(&root, &["branch"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+    let output = cs01(&root, &["branch", "topic"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("not a valid object name: 'main'"),
+        "{}",
+        stderr(&output)
+    );
+
+    let first = commit_file(&root, "a.txt");
+    let output = cs01(&root, &["branch", "topic"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    let second = commit_file(&root, "b.txt");
+
+    // From an abbreviated id, or any other revision
+    assert!(
+        cs01(&root, &["branch", "old", &first[..7]])
+            .status
+            .success()
+    );
+    assert!(
+        cs01(&root, &["branch", "nested/prev", "main~1"])
+            .status
+            .success()
+    );
+    assert_eq!(rev_parse(&root, "topic"), first);
+    assert_eq!(rev_parse(&root, "old"), first);
+    assert_eq!(rev_parse(&root, "nested/prev"), first);
+    let log = fs::read_to_string(root.join(".CS01/logs/refs/heads/old")).unwrap();
+    assert!(log.ends_with(&format!("\tbranch: Created from {}\n", &first[..7])));
+
+    // The current branch is marked; no colors without a terminal
+    let output = cs01(&root, &["branch"]);
+    assert_eq!(stdout(&output), "* main\n  nested/prev\n  old\n  topic\n");
+    let output = cs01(&root, &["--json", "branch"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value[0]["name"], "main");
+    assert_eq!(value[0]["id"], second.as_str());
+    assert_eq!(value[0]["current"], true);
+    assert_eq!(value[3]["current"], false);
+
+    // Existing branches, bad names and bad start points are refused
+    for (args, expected) in [
+        (&["branch", "topic"][..], "already exists"),
+        (&["branch", "a..b"], "not a valid branch name"),
+        (&["branch", "HEAD"], "not a valid branch name"),
+        (&["branch", "x", "nope"], "unknown revision"),
+        (&["branch", "x", "HEAD^{tree}"], "unknown revision"),
+    ] {
+        let output = cs01(&root, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+    }
+    let tree = stdout(&cs01(&root, &["write-tree"])).trim_end().to_string();
+    let output = cs01(&root, &["branch", "x", &tree]);
+    assert!(stderr(&output).contains("not a valid branch point"));
+    assert_eq!(rev_parse(&root, "topic"), first);
+
+    // A detached HEAD is listed first
+    fs::write(root.join(".CS01/HEAD"), format!("{}\n", first)).unwrap();
+    let output = cs01(&root, &["branch"]);
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "* (HEAD detached at {})\n  main\n  nested/prev\n  old\n  topic\n",
+            &first[..7]
+        )
+    );
+}
+
+#[test]
+fn test_branch_delete() {
+    let dir = tempdir().unwrap();
+    let root = init(dir.path());
+    let first = commit_file(&root, "a.txt");
+    assert!(cs01(&root, &["branch", "merged"]).status.success());
+    commit_file(&root, "b.txt");
+
+    // A commit on a branch of its own, not reachable from HEAD
+    let tree = stdout(&cs01(&root, &["write-tree"])).trim_end().to_string();
+    let output = cs01(&root, &["commit-tree", &tree, "-p", &first, "-m", "side"]);
+    let side = stdout(&output).trim_end().to_string();
+    assert!(cs01(&root, &["branch", "side", &side]).status.success());
+
+    let output = cs01(&root, &["branch", "-d", "merged"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!("Deleted branch merged (was {}).\n", &first[..7])
+    );
+    assert!(!root.join(".CS01/refs/heads/merged").exists());
+    assert!(!root.join(".CS01/logs/refs/heads/merged").exists());
+
+    let output = cs01(&root, &["branch", "-d", "side"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("not fully merged"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(stderr(&output).contains("cs01 branch -D side"));
+    assert_eq!(rev_parse(&root, "side"), side);
+    let output = cs01(&root, &["--json", "branch", "-D", "side"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["deleted"], "side");
+    assert_eq!(value["id"], side.as_str());
+
+    // The current branch and missing ones can't be deleted
+    for (args, expected) in [
+        (&["branch", "-D", "main"][..], "used by the current HEAD"),
+        (&["branch", "-d", "nope"], "branch 'nope' not found"),
+        (&["branch", "-d"], "branch name required"),
+    ] {
+        let output = cs01(&root, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+    }
+    assert_eq!(stdout(&cs01(&root, &["branch"])), "* main\n");
+}
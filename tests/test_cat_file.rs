@@ -0,0 +1,100 @@
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_with_stdin, stdout};
+
+/// Stores `content` as an object of type `kind` and returns its id.
+fn write_object(dir: &Path, kind: &str, content: &[u8]) -> String {
+    let output = cs01_with_stdin(dir, &["hash-object", "-w", "--stdin", "-t", kind], content);
+    assert!(output.status.success());
+    stdout(&output).trim_end().to_string()
+}
+
+#[test]
+fn test_cat_file_blob() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+
+    // No trailing newline, and not UTF-8: printed back byte for byte
+    let content = b"line one\nline two \xff";
+    let id = write_object(root, "blob", content);
+
+    let output = cs01(root, &["cat-file", "-p", &id]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, content);
+    assert_eq!(stdout(&cs01(root, &["cat-file", "-t", &id])), "blob\n");
+    assert_eq!(stdout(&cs01(root, &["cat-file", "-s", &id])), "19\n");
+
+    let output = cs01(root, &["--json", "cat-file", "-p", &id]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["type"], "blob");
+    assert_eq!(value["size"], 19);
+    assert_eq!(value["base64"], "bGluZSBvbmUKbGluZSB0d28g/w==");
+
+    // -e answers with the exit status alone
+    let output = cs01(root, &["cat-file", "-e", &id]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let missing = "0123456789012345678901234567890123456789";
+    let output = cs01(root, &["cat-file", "-e", missing]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    for args in [
+        &["cat-file", "-p", missing][..],
+        &["cat-file", "-t", "abc123"],
+        &["cat-file", &id],
+        &["cat-file", "-p", "-t", &id],
+    ] {
+        let output = cs01(root, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_cat_file_tree_and_commit() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+
+    let blob = write_object(root, "blob", b"hello\n");
+    let empty_tree = write_object(root, "tree", b"");
+    let raw_id = |hex: &str| -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    };
+    let mut tree = b"100644 hello.txt\0".to_vec();
+    tree.extend(raw_id(&blob));
+    tree.extend(b"40000 sub\0");
+    tree.extend(raw_id(&empty_tree));
+    let tree = write_object(root, "tree", &tree);
+
+    let output = cs01(root, &["cat-file", "-p", &tree]);
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "100644 blob {}\thello.txt\n040000 tree {}\tsub\n",
+            blob, empty_tree
+        )
+    );
+    assert_eq!(stdout(&cs01(root, &["cat-file", "-t", &tree])), "tree\n");
+
+    let output = cs01(root, &["--json", "cat-file", "-p", &tree]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["entries"][1]["name"], "sub");
+    assert_eq!(value["entries"][1]["mode"], "040000");
+
+    let commit = format!(
+        "tree {}\nauthor A U Thor <author@example.com> 1112911993 -0700\n\
+         committer A U Thor <author@example.com> 1112911993 -0700\n\nInitial\n",
+        tree
+    );
+    let id = write_object(root, "commit", commit.as_bytes());
+    assert_eq!(stdout(&cs01(root, &["cat-file", "-p", &id])), commit);
+    assert_eq!(stdout(&cs01(root, &["cat-file", "-t", &id])), "commit\n");
+}
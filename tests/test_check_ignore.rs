@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_with_stdin, stdout};
+
+/// A repository whose rules ignore `*.log` in `info/exclude`, re-include `keep.log`
+/// at the top, and ignore it again under `sub/`, where `sub/trace.log` comes back.
+fn negation_chain(dir: &Path) -> std::path::PathBuf {
+    let root = dir.join("work");
+    fs::create_dir_all(root.join("sub/build")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join(".CS01/info/exclude"), "# logs\n*.log\n").unwrap();
+    fs::write(root.join(".cs01ignore"), "!keep.log\nbuild/\n").unwrap();
+    fs::write(root.join("sub/.cs01ignore"), "keep.log\n!trace.log\n").unwrap();
+    root
+}
+
+#[test]
+fn test_check_ignore_reports_the_deciding_rule() {
+    let dir = tempdir().unwrap();
+    let root = negation_chain(dir.path());
+
+    let output = cs01(
+        &root,
+        &[
+            "check-ignore",
+            "a.log",
+            "keep.log",
+            "sub/keep.log",
+            "sub/trace.log",
+            "x.txt",
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "a.log\nsub/keep.log\n");
+
+    // -v shows which file and line matched, negations included
+    let output = cs01(
+        &root,
+        &[
+            "check-ignore",
+            "-v",
+            "a.log",
+            "keep.log",
+            "sub/keep.log",
+            "sub/trace.log",
+            "sub/build/out.o",
+            "x.txt",
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        ".CS01/info/exclude:2:*.log\ta.log\n\
+         .cs01ignore:1:!keep.log\tkeep.log\n\
+         sub/.cs01ignore:1:keep.log\tsub/keep.log\n\
+         sub/.cs01ignore:2:!trace.log\tsub/trace.log\n\
+         .cs01ignore:2:build/\tsub/build/out.o\n"
+    );
+
+    // Paths are relative to the current directory
+    let output = cs01(&root.join("sub"), &["check-ignore", "-v", "keep.log"]);
+    assert_eq!(stdout(&output), "sub/.cs01ignore:1:keep.log\tkeep.log\n");
+
+    let output = cs01(&root, &["--json", "check-ignore", "-v", "keep.log"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(
+        value["matches"],
+        serde_json::json!([{
+            "path": "keep.log",
+            "ignored": false,
+            "source": ".cs01ignore",
+            "line": 1,
+            "pattern": "!keep.log",
+        }])
+    );
+}
+
+#[test]
+fn test_check_ignore_exit_status_and_stdin() {
+    let dir = tempdir().unwrap();
+    let root = negation_chain(dir.path());
+
+    // Nothing ignored exits 1 without a message; with -v a negation counts as a match
+    let output = cs01(&root, &["check-ignore", "keep.log", "x.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+    assert!(output.stderr.is_empty());
+    let output = cs01(&root, &["check-ignore", "-v", "keep.log"]);
+    assert!(output.status.success());
+
+    let output = cs01_with_stdin(
+        &root,
+        &["check-ignore", "--stdin"],
+        b"x.txt\nb.log\nsub/keep.log\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "b.log\nsub/keep.log\n");
+
+    // Tracked files are never reported
+    fs::write(root.join("tracked.log"), "x\n").unwrap();
+    assert!(cs01(&root, &["add", "-f", "tracked.log"]).status.success());
+    let output = cs01(&root, &["check-ignore", "tracked.log"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = cs01(&root, &["check-ignore"]);
+    assert!(!output.status.success());
+}
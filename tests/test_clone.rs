@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{commit, cs01, read, rev_parse, run, stderr};
+
+/// A repository `a` with two commits on `main` and a `side` branch at the first.
+fn source_repo(root: &Path) -> (std::path::PathBuf, Vec<String>) {
+    let a = root.join("a");
+    fs::create_dir(&a).unwrap();
+    run(&a, &["init", "-q"]);
+    fs::create_dir(a.join("src")).unwrap();
+    let first = commit(
+        &a,
+        &[("README", "hello\n"), ("src/lib.rs", "fn a() {}\n")],
+        "First",
+    );
+    run(&a, &["branch", "side"]);
+    let second = commit(&a, &[("src/lib.rs", "fn b() {}\n")], "Second");
+    (a, vec![second, first])
+}
+
+#[test]
+fn test_clone_copies_history_and_checks_out_head() {
+    let dir = tempdir().unwrap();
+    let (a, ids) = source_repo(dir.path());
+
+    let output = cs01(dir.path(), &["clone", "a", "b"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Cloning into 'b'..."));
+    let b = dir.path().join("b");
+    assert_eq!(read(&b, "README"), "hello\n");
+    assert_eq!(read(&b, "src/lib.rs"), "fn b() {}\n");
+    assert_eq!(run(&b, &["log"]), run(&a, &["log"]));
+    assert_eq!(rev_parse(&b, "HEAD"), ids[0]);
+    assert!(run(&b, &["status"]).contains("nothing to commit, working tree clean"));
+
+    // The source's branches are remote-tracking branches of `origin`.
+    assert_eq!(run(&b, &["symbolic-ref", "HEAD"]), "refs/heads/main\n");
+    assert_eq!(rev_parse(&b, "refs/remotes/origin/main"), ids[0]);
+    assert_eq!(rev_parse(&b, "refs/remotes/origin/side"), ids[1]);
+    assert_eq!(
+        cs01(&b, &["rev-parse", "refs/heads/side"]).status.code(),
+        Some(128)
+    );
+    let url = run(&b, &["config", "remote.origin.url"]);
+    assert_eq!(Path::new(url.trim_end()), std::path::absolute(&a).unwrap());
+    assert_eq!(
+        run(&b, &["config", "remote.origin.fetch"]),
+        "+refs/heads/*:refs/remotes/origin/*\n"
+    );
+    assert_eq!(
+        run(&b, &["config", "branch.main.merge"]),
+        "refs/heads/main\n"
+    );
+
+    // Without a destination, the source's directory name is used; it's taken.
+    let output = cs01(dir.path(), &["clone", "a"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("destination path 'a' already exists"));
+}
+
+#[test]
+fn test_clone_of_empty_repository_leaves_head_unborn() {
+    let dir = tempdir().unwrap();
+    run(
+        dir.path(),
+        &["init", "-q", "--initial-branch", "trunk", "empty"],
+    );
+
+    let output = cs01(dir.path(), &["clone", "empty", "copy"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("You appear to have cloned an empty repository."));
+    let copy = dir.path().join("copy");
+    assert_eq!(run(&copy, &["symbolic-ref", "HEAD"]), "refs/heads/trunk\n");
+    assert!(!cs01(&copy, &["rev-parse", "HEAD"]).status.success());
+
+    // Committing in the clone starts the branch.
+    commit(&copy, &[("file", "content\n")], "Initial");
+    assert!(run(&copy, &["log"]).contains("Initial"));
+}
+
+#[test]
+fn test_bare_clone_and_copied_objects() {
+    let dir = tempdir().unwrap();
+    let (_, ids) = source_repo(dir.path());
+
+    run(dir.path(), &["clone", "--bare", "a", "bare"]);
+    let bare = dir.path().join("bare");
+    assert!(bare.join("HEAD").is_file());
+    assert!(!bare.join("README").exists());
+    assert_eq!(rev_parse(&bare, "refs/heads/main"), ids[0]);
+    assert_eq!(rev_parse(&bare, "refs/heads/side"), ids[1]);
+    assert_eq!(run(&bare, &["config", "core.bare"]), "true\n");
+
+    run(dir.path(), &["clone", "--no-hardlinks", "a", "copy"]);
+    let copy = dir.path().join("copy");
+    assert_eq!(read(&copy, "src/lib.rs"), "fn b() {}\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let object = |root: &Path| {
+            let hex = &ids[0];
+            root.join("objects").join(&hex[..2]).join(&hex[2..])
+        };
+        let linked = fs::metadata(object(&bare)).unwrap();
+        assert!(linked.nlink() > 1);
+        let copied = fs::metadata(object(&copy.join(".CS01"))).unwrap();
+        assert_eq!(copied.nlink(), 1);
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_command, stderr, stdout};
+
+// `git write-tree` after staging README.md and src/main.rs, then after changing README.md
+const FIRST_TREE: &str = "2e0a79de35fd067bd5e03bf70c7821ef0f11d6cb";
+const SECOND_TREE: &str = "e62798e1df137fe518f769bf57d84d1c7b77fe31";
+// `git commit-tree FIRST_TREE -m "Initial import"` with the identity below
+const FIRST_COMMIT: &str = "1e7e9242c3c4d6d7122ab983c082893fe0632c09";
+
+/// Runs cs01 with `editor` as the editor.
+fn cs01_with_editor(dir: &Path, args: &[&str], editor: &str) -> Output {
+    cs01_command(dir, args)
+        .env("CS01_EDITOR", editor)
+        .output()
+        .expect("Failed to execute command")
+}
+
+fn cat_file(root: &Path, object: &str) -> String {
+    let output = cs01(root, &["cat-file", "-p", object]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+}
+
+fn work_tree(dir: &Path) -> std::path::PathBuf {
+    let root = dir.join("work");
+    fs::create_dir_all(root.join("src")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join("README.md"), "hello\n").unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    root
+}
+
+#[test]
+fn test_commit_records_the_index_and_moves_the_branch() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+
+    // Nothing staged yet
+    let output = cs01(&root, &["commit", "-m", "Empty"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("nothing to commit"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!root.join(".CS01/refs/heads/main").exists());
+
+    assert!(cs01(&root, &["add", "."]).status.success());
+    let output = cs01(&root, &["commit", "-m", "Initial import"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "[main (root-commit) {}] Initial import\n",
+            &FIRST_COMMIT[..7]
+        )
+    );
+    assert_eq!(
+        fs::read_to_string(root.join(".CS01/refs/heads/main")).unwrap(),
+        format!("{}\n", FIRST_COMMIT)
+    );
+    assert_eq!(
+        cat_file(&root, "HEAD"),
+        format!(
+            "tree {}\n\
+             author A U Thor <author@example.com> 1700000000 +0530\n\
+             committer C O Mitter <committer@example.com> 1700000100 -0700\n\
+             \nInitial import\n",
+            FIRST_TREE
+        )
+    );
+
+    // The next commit follows the first, and only staged changes are recorded
+    fs::write(root.join("README.md"), "hello\nmore\n").unwrap();
+    assert!(cs01(&root, &["add", "README.md"]).status.success());
+    fs::write(root.join("src/main.rs"), "unstaged\n").unwrap();
+    let output = cs01(&root, &["--json", "commit", "-m", "Second", "-m", "Body."]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["branch"], "main");
+    assert_eq!(value["root"], false);
+    assert_eq!(value["summary"], "Second");
+    let second = value["id"].as_str().unwrap().to_string();
+    let text = cat_file(&root, &second);
+    assert!(
+        text.starts_with(&format!("tree {}\nparent {}\n", SECOND_TREE, FIRST_COMMIT)),
+        "{}",
+        text
+    );
+    assert!(text.ends_with("\n\nSecond\n\nBody.\n"), "{}", text);
+
+    // Both moves are in the branch's reflog and HEAD's
+    for log in [".CS01/logs/HEAD", ".CS01/logs/refs/heads/main"] {
+        let lines: Vec<String> = fs::read_to_string(root.join(log))
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(lines.len(), 2, "{}", log);
+        assert!(lines[0].starts_with(&format!("{} {}", "0".repeat(40), FIRST_COMMIT)));
+        assert!(lines[0].ends_with("\tcommit (initial): Initial import"));
+        assert!(lines[1].starts_with(&format!("{} {}", FIRST_COMMIT, second)));
+        assert!(lines[1].ends_with("\tcommit: Second"));
+    }
+
+    // An unchanged tree needs --allow-empty
+    let output = cs01(&root, &["commit", "-m", "Again"]);
+    assert!(!output.status.success());
+    let output = cs01(&root, &["commit", "--allow-empty", "-m", "Again"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = cat_file(&root, "HEAD");
+    assert!(text.starts_with(&format!("tree {}\nparent {}\n", SECOND_TREE, second)));
+}
+
+#[test]
+fn test_commit_allow_empty_message() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(cs01(&root, &["add", "README.md"]).status.success());
+
+    // Whitespace cleans up to nothing, which aborts...
+    let output = cs01(&root, &["commit", "-m", "  \n"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("empty commit message"));
+    assert!(!root.join(".CS01/refs/heads/main").exists());
+
+    // ...unless it's allowed: the commit ends with the blank line after the headers
+    let output = cs01(&root, &["commit", "--allow-empty-message", "-m", "  \n"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = cat_file(&root, "HEAD");
+    assert!(text.ends_with(" -0700\n\n"), "{:?}", text);
+}
+
+#[test]
+fn test_commit_with_only_the_author_set() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(cs01(&root, &["add", "README.md"]).status.success());
+
+    // The author's name and email stand in for the committer's, as in git
+    let output = cs01_command(&root, &["commit", "-m", "By the author"])
+        .env_remove("CS01_COMMITTER_NAME")
+        .env_remove("CS01_COMMITTER_EMAIL")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = cat_file(&root, "HEAD");
+    assert!(
+        text.contains("\ncommitter A U Thor <author@example.com> 1700000100 -0700\n"),
+        "{}",
+        text
+    );
+
+    // Without the author's either, there's no one to commit as
+    let output = cs01_command(&root, &["commit", "--allow-empty", "-m", "Nobody"])
+        .env_remove("CS01_COMMITTER_NAME")
+        .env_remove("CS01_COMMITTER_EMAIL")
+        .env_remove("CS01_AUTHOR_NAME")
+        .env_remove("CS01_AUTHOR_EMAIL")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("identity unknown"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_commit_message_from_the_editor() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(cs01(&root, &["add", "README.md"]).status.success());
+
+    // The editor saves what it was given, then writes the message
+    let script = dir.path().join("editor.sh");
+    fs::write(
+        &script,
+        "cp \"$1\" \"$(dirname \"$1\")/../../template.txt\"\n\
+         printf 'From the editor  \\n\\n\\n# dropped\\nBody\\n' > \"$1\"\n",
+    )
+    .unwrap();
+    let editor = format!("sh {}", script.display());
+    let output = cs01_with_editor(&root, &["commit"], &editor);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).ends_with("] From the editor\n"));
+    assert!(cat_file(&root, "HEAD").ends_with("\n\nFrom the editor\n\nBody\n"));
+
+    let template = fs::read_to_string(dir.path().join("template.txt")).unwrap();
+    assert!(template.starts_with("\n# Please enter the commit message"));
+    assert!(template.contains("# On branch main\n#\n# Initial commit\n"));
+    assert!(template.contains("# Changes to be committed:\n#\tnew file:   README.md\n"));
+    assert!(template.contains("# Untracked files:\n#\tsrc/\n"));
+
+    // An empty message, or a failing editor, aborts and leaves the branch alone
+    let head = fs::read_to_string(root.join(".CS01/refs/heads/main")).unwrap();
+    assert!(cs01(&root, &["add", "src"]).status.success());
+    let output = cs01_with_editor(&root, &["commit"], "true");
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("empty commit message"),
+        "{}",
+        stderr(&output)
+    );
+    let output = cs01_with_editor(&root, &["commit"], "false");
+    assert!(!output.status.success());
+    let output = cs01(&root, &["commit", "-m", "  \n"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        fs::read_to_string(root.join(".CS01/refs/heads/main")).unwrap(),
+        head
+    );
+    assert!(!root.join(".CS01/index.lock").exists());
+}
+
+#[cfg(unix)]
+fn install_hook(root: &Path, name: &str, script: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    let path = root.join(".CS01/hooks").join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_commit_runs_the_pre_commit_hook() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(cs01(&root, &["add", "."]).status.success());
+
+    // The hook runs from the top of the working tree and sees the repository
+    install_hook(
+        &root,
+        "pre-commit",
+        "pwd > ../pwd.txt\necho \"$CS01_DIR\" > ../dir.txt\necho 'checks failed'\nexit 3\n",
+    );
+    let output = cs01(&root.join("src"), &["commit", "-m", "Initial import"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("checks failed")
+            && stderr(&output).contains("the 'pre-commit' hook failed (exit status 3)"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(stdout(&output), "");
+    assert!(!root.join(".CS01/refs/heads/main").exists());
+    assert!(!root.join(".CS01/index.lock").exists());
+    let root = fs::canonicalize(&root).unwrap();
+    let pwd = fs::read_to_string(dir.path().join("pwd.txt")).unwrap();
+    assert_eq!(fs::canonicalize(pwd.trim_end()).unwrap(), root);
+    let cs01_dir = fs::read_to_string(dir.path().join("dir.txt")).unwrap();
+    assert_eq!(
+        fs::canonicalize(cs01_dir.trim_end()).unwrap(),
+        root.join(".CS01")
+    );
+
+    // --no-verify skips it, and a hook that isn't executable is skipped too
+    let output = cs01(&root, &["commit", "-n", "-m", "Initial import"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(cat_file(&root, "HEAD").starts_with(&format!("tree {}\n", FIRST_TREE)));
+    let hook = root.join(".CS01/hooks/pre-commit");
+    let mode = std::os::unix::fs::PermissionsExt::from_mode(0o644);
+    fs::set_permissions(&hook, mode).unwrap();
+    let output = cs01(&root, &["commit", "--allow-empty", "-m", "Again"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_commit_msg_hook_gets_the_message_file() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+    assert!(cs01(&root, &["add", "."]).status.success());
+
+    install_hook(
+        &root,
+        "commit-msg",
+        "case \"$1\" in */COMMIT_EDITMSG) ;; *) exit 1 ;; esac\n\
+         printf '\\nSigned-off-by: C O Mitter\\n' >> \"$1\"\n",
+    );
+    let output = cs01(&root, &["commit", "-m", "Initial import"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(cat_file(&root, "HEAD").ends_with("\n\nInitial import\n\nSigned-off-by: C O Mitter\n"));
+
+    // A failing commit-msg hook aborts the commit
+    install_hook(&root, "commit-msg", "grep -q '^JIRA-' \"$1\"\n");
+    let head = fs::read_to_string(root.join(".CS01/refs/heads/main")).unwrap();
+    let output = cs01(&root, &["commit", "--allow-empty", "-m", "No ticket"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("the 'commit-msg' hook failed (exit status 1)"));
+    assert_eq!(
+        fs::read_to_string(root.join(".CS01/refs/heads/main")).unwrap(),
+        head
+    );
+    let output = cs01(&root, &["commit", "--allow-empty", "-m", "JIRA-1 Fix"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
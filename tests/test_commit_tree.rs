@@ -0,0 +1,105 @@
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_with_stdin, stdout};
+
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+#[test]
+fn test_commit_tree_matches_git() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let refs = || std::fs::read_to_string(root.join(".CS01/refs/heads/main")).ok();
+    let refs_before = refs();
+    let output = cs01(root, &["hash-object", "-w", "-t", "tree", "--stdin"]);
+    assert_eq!(stdout(&output), format!("{}\n", EMPTY_TREE));
+
+    // Ids from `git commit-tree` with the same identities and dates
+    let output = cs01(root, &["commit-tree", EMPTY_TREE, "-m", "Initial"]);
+    assert!(output.status.success());
+    let first = "8354a7ec20ee8270f089367860819e49c5fe25ba";
+    assert_eq!(stdout(&output), format!("{}\n", first));
+
+    let args = [
+        "commit-tree",
+        EMPTY_TREE,
+        "-p",
+        first,
+        "-m",
+        "Second",
+        "-m",
+        "Body text.",
+    ];
+    let second = "853d1afbdfd38d7ac97e7db4b24c7221d80a6dcf";
+    assert_eq!(stdout(&cs01(root, &args)), format!("{}\n", second));
+    let output = cs01(root, &["cat-file", "-p", second]);
+    assert!(stdout(&output).ends_with("\n\nSecond\n\nBody text.\n"));
+
+    // A merge, with the message from standard input
+    let output = cs01_with_stdin(
+        root,
+        &["commit-tree", EMPTY_TREE, "-p", first, "-p", second],
+        b"From stdin\n\nwith blank lines\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "b385dbbdb43c7d781e12917e7741be3e553da6cd\n"
+    );
+
+    // No ref was touched
+    assert_eq!(refs(), refs_before);
+}
+
+#[test]
+fn test_commit_tree_rejects_bad_objects() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    cs01(root, &["hash-object", "-w", "-t", "tree", "--stdin"]);
+    let blob = stdout(&cs01_with_stdin(
+        root,
+        &["hash-object", "-w", "--stdin"],
+        b"hello\n",
+    ));
+    let blob = blob.trim_end();
+    let missing = "0123456789012345678901234567890123456789";
+    let objects_before = std::fs::read_dir(root.join(".CS01/objects"))
+        .unwrap()
+        .count();
+
+    for (args, expected) in [
+        (
+            &["commit-tree", "abc", "-m", "x"][..],
+            "unknown revision 'abc'",
+        ),
+        (
+            &["commit-tree", missing, "-m", "x"],
+            "Not a valid object name",
+        ),
+        (&["commit-tree", blob, "-m", "x"], "is a blob, not a tree"),
+        (
+            &["commit-tree", EMPTY_TREE, "-p", EMPTY_TREE, "-m", "x"],
+            "is a tree, not a commit",
+        ),
+        (
+            &["commit-tree", EMPTY_TREE, "-p", missing, "-m", "x"],
+            "Not a valid object name",
+        ),
+    ] {
+        let output = cs01(root, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(expected),
+            "{:?}",
+            args
+        );
+    }
+    // Nothing was written by the failed attempts
+    assert_eq!(
+        std::fs::read_dir(root.join(".CS01/objects"))
+            .unwrap()
+            .count(),
+        objects_before
+    );
+}
@@ -0,0 +1,731 @@
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_command, stdout};
+
+fn cs01_with_configs(dir: &Path, home: &Path, system: &Path, args: &[&str]) -> Output {
+    cs01_command(dir, args)
+        .env("HOME", home)
+        .env("CS01_CONFIG_SYSTEM", system)
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn test_config_get_set_unset() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+
+    // Values written by init can be read back, also from a subdirectory
+    let output = cs01(root, &["config", "core.bare"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "false\n");
+
+    let nested = root.join("src/deep");
+    std::fs::create_dir_all(&nested).unwrap();
+    assert_eq!(stdout(&cs01(&nested, &["config", "Core.Bare"])), "false\n");
+
+    // Setting a key in a missing section creates it; dots belong to the subsection
+    let url = "https://example.com/repo.git?a=b";
+    assert!(
+        cs01(root, &["config", "remote.origin.url", url])
+            .status
+            .success()
+    );
+    assert!(
+        cs01(
+            root,
+            &["config", "branch.release/1.0.merge", "refs/heads/x"]
+        )
+        .status
+        .success()
+    );
+    let config = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+    assert!(config.contains("[remote \"origin\"]"));
+    assert!(config.contains("[branch \"release/1.0\"]"));
+    assert_eq!(
+        stdout(&cs01(root, &["config", "remote.origin.url"])),
+        format!("{}\n", url)
+    );
+
+    let listing = stdout(&cs01(root, &["config", "--list"]));
+    assert!(listing.contains("core.bare=false\n"));
+    assert!(listing.contains(&format!("remote.origin.url={}\n", url)));
+    assert!(listing.contains("branch.release/1.0.merge=refs/heads/x\n"));
+
+    // Unsetting the last key in a section removes the header
+    assert!(
+        cs01(root, &["config", "--unset", "remote.origin.url"])
+            .status
+            .success()
+    );
+    let config = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+    assert!(!config.contains("remote"));
+    assert!(config.contains("[core]"));
+
+    // A missing key exits non-zero without output; unsetting it is an error
+    let output = cs01(root, &["config", "remote.origin.url"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let output = cs01(root, &["config", "--unset", "remote.origin.url"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("key not found"));
+
+    // Keys without a section are rejected
+    assert!(!cs01(root, &["config", "bare", "true"]).status.success());
+}
+
+#[test]
+fn test_config_outside_repository() {
+    let dir = tempdir().unwrap();
+
+    // Reads fall back to the global and system files, where nothing is set
+    let output = cs01(dir.path(), &["config", "core.bare"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    // Writes default to the repository, so they need one
+    let output = cs01(dir.path(), &["config", "core.bare", "true"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a CS01 repository"));
+}
+
+#[test]
+fn test_config_json() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+
+    let output = cs01(root, &["--json", "config", "core.bare"]);
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["key"], "core.bare");
+    assert_eq!(value["value"], "false");
+
+    let output = cs01(root, &["config", "--list", "--json"]);
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["core.repositoryformatversion"], "0");
+}
+
+#[test]
+fn test_config_multi_valued() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+
+    let refspecs = [
+        "+refs/heads/*:refs/remotes/origin/*",
+        "+refs/tags/*:refs/tags/*",
+        "+refs/notes/*:refs/notes/*",
+    ];
+    for refspec in refspecs {
+        let output = cs01(root, &["config", "--add", "remote.origin.fetch", refspec]);
+        assert!(output.status.success());
+    }
+
+    let config = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+    assert_eq!(config.matches("fetch = ").count(), 3);
+
+    // --get-all keeps the order; a plain get sees the last value
+    let expected: String = refspecs.iter().map(|r| format!("{}\n", r)).collect();
+    assert_eq!(
+        stdout(&cs01(root, &["config", "--get-all", "remote.origin.fetch"])),
+        expected
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["config", "remote.origin.fetch"])),
+        format!("{}\n", refspecs[2])
+    );
+    let listing = stdout(&cs01(root, &["config", "--list"]));
+    assert_eq!(listing.matches("remote.origin.fetch=").count(), 3);
+
+    // Neither set nor unset silently collapse several values
+    let output = cs01(root, &["config", "remote.origin.fetch", "x"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("multiple values"));
+    assert!(
+        !cs01(root, &["config", "--unset", "remote.origin.fetch"])
+            .status
+            .success()
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["config", "--get-all", "remote.origin.fetch"])),
+        expected
+    );
+
+    let output = cs01(root, &["--json", "config", "--list"]);
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["remote.origin.fetch"], serde_json::json!(refspecs));
+}
+
+#[test]
+fn test_config_edits_touch_only_their_line() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let config_path = root.join(".CS01/config");
+
+    assert!(
+        cs01(root, &["config", "user.name", "A U Thor"])
+            .status
+            .success()
+    );
+    assert!(
+        cs01(root, &["config", "init.defaultBranch", "main"])
+            .status
+            .success()
+    );
+    let before = std::fs::read_to_string(&config_path).unwrap();
+
+    // Changing a value keeps every other line where it was
+    assert!(
+        cs01(root, &["config", "core.bare", "true"])
+            .status
+            .success()
+    );
+    let after = std::fs::read_to_string(&config_path).unwrap();
+    let changed: Vec<_> = before
+        .lines()
+        .zip(after.lines())
+        .filter(|(a, b)| a != b)
+        .collect();
+    assert_eq!(changed, vec![("  bare = false", "  bare = true")]);
+    assert_eq!(before.lines().count(), after.lines().count());
+
+    // New sections are appended and removing one leaves the rest in order
+    assert!(
+        cs01(root, &["config", "--unset", "user.name"])
+            .status
+            .success()
+    );
+    let after = std::fs::read_to_string(&config_path).unwrap();
+    assert_eq!(
+        after,
+        before
+            .replace("  bare = false", "  bare = true")
+            .replace("[user]\n  name = A U Thor\n", "")
+    );
+}
+
+#[test]
+fn test_config_scopes() {
+    let dir = tempdir().unwrap();
+    let home = dir.path().join("home");
+    let system = dir.path().join("etc/cs01config");
+    let repo = dir.path().join("repo");
+    std::fs::create_dir_all(&home).unwrap();
+    std::fs::create_dir_all(&repo).unwrap();
+    let run = |cwd: &Path, args: &[&str]| cs01_with_configs(cwd, &home, &system, args);
+
+    // --global and --system create their files on first write, even outside a repository
+    assert!(
+        run(dir.path(), &["config", "--system", "user.name", "System"])
+            .status
+            .success()
+    );
+    assert!(
+        run(
+            dir.path(),
+            &["config", "--system", "remote.origin.fetch", "sys"]
+        )
+        .status
+        .success()
+    );
+    assert!(
+        run(dir.path(), &["config", "--global", "user.name", "Global"])
+            .status
+            .success()
+    );
+    assert!(
+        run(
+            dir.path(),
+            &["config", "--global", "init.defaultBranch", "trunk"]
+        )
+        .status
+        .success()
+    );
+    assert!(system.is_file());
+    assert!(home.join(".cs01config").is_file());
+    assert_eq!(
+        stdout(&run(dir.path(), &["config", "user.name"])),
+        "Global\n"
+    );
+
+    // init picks up init.defaultBranch from the global file
+    assert!(run(&repo, &["init", "-q"]).status.success());
+    let head = std::fs::read_to_string(repo.join(".CS01/HEAD")).unwrap();
+    assert_eq!(head.trim(), "ref: refs/heads/trunk");
+
+    // The repository overrides single values and extends multi-valued ones
+    assert!(
+        run(&repo, &["config", "user.name", "Local"])
+            .status
+            .success()
+    );
+    assert!(
+        run(&repo, &["config", "--add", "remote.origin.fetch", "local"])
+            .status
+            .success()
+    );
+    assert_eq!(stdout(&run(&repo, &["config", "user.name"])), "Local\n");
+    assert_eq!(
+        stdout(&run(&repo, &["config", "--global", "user.name"])),
+        "Global\n"
+    );
+    assert_eq!(
+        stdout(&run(&repo, &["config", "--get-all", "remote.origin.fetch"])),
+        "sys\nlocal\n"
+    );
+
+    let listing = stdout(&run(&repo, &["config", "--list", "--show-scope"]));
+    let lines: Vec<_> = listing
+        .lines()
+        .filter(|l| l.contains("user.name"))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "system\tuser.name=System",
+            "global\tuser.name=Global",
+            "local\tuser.name=Local"
+        ]
+    );
+
+    // Unsetting in the repository reveals the global value again
+    assert!(
+        run(&repo, &["config", "--unset", "user.name"])
+            .status
+            .success()
+    );
+    assert_eq!(stdout(&run(&repo, &["config", "user.name"])), "Global\n");
+
+    // --local requires a repository
+    let output = run(dir.path(), &["config", "--local", "user.name"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_config_include_is_not_inlined() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    std::fs::write(root.join("shared.config"), "[user]\n  name = Shared\n").unwrap();
+
+    assert!(
+        cs01(root, &["config", "include.path", "../shared.config"])
+            .status
+            .success()
+    );
+    assert_eq!(stdout(&cs01(root, &["config", "user.name"])), "Shared\n");
+    assert!(stdout(&cs01(root, &["config", "--list"])).contains("user.name=Shared\n"));
+
+    // Writing a key only touches the repository's own file
+    assert!(
+        cs01(root, &["config", "core.bare", "false"])
+            .status
+            .success()
+    );
+    let config = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+    assert!(!config.contains("Shared"));
+    assert!(config.contains("[include]\n  path = ../shared.config\n"));
+}
+
+#[test]
+fn test_config_include_if_cs01dir() {
+    let dir = tempdir().unwrap();
+    let home = dir.path().join("home");
+    let work = home.join("work/proj");
+    let personal = home.join("personal/proj");
+    for repo in [&work, &personal] {
+        std::fs::create_dir_all(repo).unwrap();
+        assert!(cs01(repo, &["init", "-q"]).status.success());
+    }
+    std::fs::write(
+        home.join(".cs01config"),
+        "[user]\n  email = me@home.example\n[includeIf \"cs01dir:~/work/\"]\n  path = .cs01config-work\n",
+    )
+    .unwrap();
+    std::fs::write(
+        home.join(".cs01config-work"),
+        "[user]\n  email = me@work.example\n",
+    )
+    .unwrap();
+
+    let system = dir.path().join("no-system-config");
+    let email = |repo: &Path| {
+        stdout(&cs01_with_configs(
+            repo,
+            &home,
+            &system,
+            &["config", "user.email"],
+        ))
+    };
+    assert_eq!(email(&work), "me@work.example\n");
+    assert_eq!(email(&personal), "me@home.example\n");
+    assert_eq!(email(dir.path()), "me@home.example\n");
+}
+
+#[test]
+fn test_config_refuses_to_write_while_locked() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let lock = root.join(".CS01/config.lock");
+    std::fs::write(&lock, "").unwrap();
+
+    let output = cs01(root, &["config", "user.name", "A U Thor"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("config.lock"), "{}", stderr);
+    // The lock belongs to someone else, so it is left in place
+    assert!(lock.exists());
+
+    std::fs::remove_file(&lock).unwrap();
+    assert!(
+        cs01(root, &["config", "user.name", "A U Thor"])
+            .status
+            .success()
+    );
+    assert!(!lock.exists());
+}
+
+#[test]
+fn test_config_env_overrides() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    assert!(
+        cs01(root, &["config", "user.name", "Local Name"])
+            .status
+            .success()
+    );
+
+    let with_env = |vars: &[(&str, &str)], args: &[&str]| {
+        cs01_command(root, args)
+            .envs(vars.iter().copied())
+            .output()
+            .expect("Failed to execute command")
+    };
+    let overrides = [
+        ("CS01_CONFIG_COUNT", "1"),
+        ("CS01_CONFIG_KEY_0", "user.name"),
+        ("CS01_CONFIG_VALUE_0", "CI Bot"),
+    ];
+
+    // The environment wins over the repository's own config, which stays untouched
+    assert_eq!(
+        stdout(&with_env(&overrides, &["config", "user.name"])),
+        "CI Bot\n"
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["config", "user.name"])),
+        "Local Name\n"
+    );
+
+    let listed = stdout(&with_env(
+        &overrides,
+        &["config", "--list", "--show-scope", "--show-origin"],
+    ));
+    let config_path = Path::new(".CS01").join("config");
+    assert!(
+        listed.contains("command\tcommand line:\tuser.name=CI Bot\n"),
+        "{}",
+        listed
+    );
+    assert!(listed.contains("local\tfile:"), "{}", listed);
+    assert!(
+        listed.contains(&format!(
+            "{}\tuser.name=Local Name\n",
+            config_path.display()
+        )),
+        "{}",
+        listed
+    );
+
+    let output = with_env(
+        &[
+            ("CS01_CONFIG_COUNT", "1"),
+            ("CS01_CONFIG_KEY_0", "user.name"),
+        ],
+        &["config", "user.name"],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("CS01_CONFIG_VALUE_0"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_edit() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let config_path = root.join(".CS01/config");
+    let original = std::fs::read_to_string(&config_path).unwrap();
+
+    let edit_with = |editor: &str| {
+        cs01_command(root, &["config", "--edit"])
+            .env("CS01_EDITOR", editor)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // A failing editor leaves the file alone, even if it already wrote to it
+    let output = edit_with("echo '[user] name = Half' >> \"$1\"; false");
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read_to_string(&config_path).unwrap(), original);
+
+    // A syntax error is reported with its line number instead of being saved
+    let output = edit_with("echo 'not a valid line' >>");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let broken_line = original.lines().count() + 1;
+    assert!(
+        stderr.contains(&format!("line {}", broken_line)),
+        "{}",
+        stderr
+    );
+    assert_eq!(std::fs::read_to_string(&config_path).unwrap(), original);
+
+    let output = edit_with("printf '[user]\\n  name = Edited\\n' >>");
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(stdout(&cs01(root, &["config", "user.name"])), "Edited\n");
+    assert!(!root.join(".CS01/config.lock").exists());
+}
+
+#[test]
+fn test_config_rejects_invalid_names() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let original = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+
+    for key in ["core.1bare", "core.bare=", "co re.bare", "remote.a\nb.url"] {
+        let output = cs01(root, &["config", key, "x"]);
+        assert!(!output.status.success(), "{:?} was accepted", key);
+    }
+    assert_eq!(
+        std::fs::read_to_string(root.join(".CS01/config")).unwrap(),
+        original
+    );
+
+    assert!(
+        cs01(root, &["config", "core.log-all-ref-updates", "true"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_config_names_are_case_insensitive() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir(root.join(".CS01")).unwrap();
+    std::fs::write(
+        root.join(".CS01/config"),
+        "[CORE]\n\tBare = TRUE\n[remote \"Origin\"]\n\tURL = https://example.com\n",
+    )
+    .unwrap();
+
+    assert_eq!(stdout(&cs01(root, &["config", "core.BARE"])), "TRUE\n");
+    assert_eq!(
+        stdout(&cs01(root, &["config", "Remote.Origin.url"])),
+        "https://example.com\n"
+    );
+    // Subsection names are case-sensitive
+    assert!(
+        !cs01(root, &["config", "remote.origin.url"])
+            .status
+            .success()
+    );
+
+    assert!(
+        cs01(root, &["config", "core.bare", "false"])
+            .status
+            .success()
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join(".CS01/config")).unwrap(),
+        "[CORE]\n\tBare = false\n[remote \"Origin\"]\n\tURL = https://example.com\n"
+    );
+}
+
+#[test]
+fn test_config_show_origin_and_scope() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let home = root.join("home");
+    let system = root.join("system-config");
+    std::fs::create_dir(&home).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    let run = |args: &[&str]| cs01_with_configs(&root, &home, &system, args);
+
+    assert!(
+        run(&["config", "--global", "user.name", "Global Name"])
+            .status
+            .success()
+    );
+    assert!(run(&["config", "user.name", "Local Name"]).status.success());
+
+    let global_file = home.join(".cs01config");
+    let local_file = root.join(".CS01").join("config");
+    assert_eq!(
+        stdout(&run(&["config", "--show-origin", "--get-all", "user.name"])),
+        format!(
+            "file:{}\tGlobal Name\nfile:{}\tLocal Name\n",
+            global_file.display(),
+            local_file.display()
+        )
+    );
+    assert_eq!(
+        stdout(&run(&[
+            "config",
+            "--show-scope",
+            "--show-origin",
+            "user.name"
+        ])),
+        format!("local\tfile:{}\tLocal Name\n", local_file.display())
+    );
+    assert!(
+        stdout(&run(&["config", "--list", "--show-scope"]))
+            .contains("global\tuser.name=Global Name\n")
+    );
+}
+
+#[test]
+fn test_config_type_canonicalizes() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    std::fs::write(
+        root.join(".CS01/config"),
+        "[core]\n  bare = no\n  flag\n[pack]\n  window = 1k\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        stdout(&cs01(root, &["config", "--type=bool", "core.bare"])),
+        "false\n"
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["config", "--type=bool", "core.flag"])),
+        "true\n"
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["config", "--type=int", "pack.window"])),
+        "1024\n"
+    );
+    // Without --type the value is printed as written
+    assert_eq!(stdout(&cs01(root, &["config", "pack.window"])), "1k\n");
+
+    let home = root.join("home");
+    let output = cs01_with_configs(
+        root,
+        &home,
+        &home.join("system"),
+        &["config", "--type=path", "core.hookspath"],
+    );
+    assert!(!output.status.success());
+    assert!(
+        cs01(root, &["config", "core.hookspath", "~/hooks"])
+            .status
+            .success()
+    );
+    assert_eq!(
+        stdout(&cs01_with_configs(
+            root,
+            &home,
+            &home.join("system"),
+            &["config", "--type=path", "core.hookspath"],
+        )),
+        format!("{}\n", home.join("hooks").display())
+    );
+
+    // Writes store the canonical form, and reject values of the wrong type
+    assert!(
+        cs01(root, &["config", "--type=bool", "core.bare", "Yes"])
+            .status
+            .success()
+    );
+    assert!(
+        !cs01(root, &["config", "--type=int", "pack.window", "many"])
+            .status
+            .success()
+    );
+    let config = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+    assert!(config.contains("  bare = true\n"), "{}", config);
+    assert!(config.contains("  window = 1k\n"), "{}", config);
+}
+
+#[test]
+fn test_config_rename_and_remove_section() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let config_path = root.join(".CS01/config");
+    let original = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(
+        &config_path,
+        format!(
+            "{}[remote \"origin\"] # where we push\n\turl = https://example.com/a.git\n[remote \"fork\"]\n\turl = https://example.com/b.git\n",
+            original
+        ),
+    )
+    .unwrap();
+
+    let output = cs01(
+        root,
+        &[
+            "config",
+            "--rename-section",
+            "remote.origin",
+            "remote.upstream",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let renamed = std::fs::read_to_string(&config_path).unwrap();
+    assert!(
+        renamed
+            .contains("[remote \"upstream\"] # where we push\n\turl = https://example.com/a.git\n"),
+        "{}",
+        renamed
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["config", "remote.upstream.url"])),
+        "https://example.com/a.git\n"
+    );
+
+    // A rename onto an existing section, or of a missing one, fails and changes nothing
+    for args in [
+        [
+            "config",
+            "--rename-section",
+            "remote.upstream",
+            "remote.fork",
+        ],
+        [
+            "config",
+            "--rename-section",
+            "remote.origin",
+            "remote.other",
+        ],
+    ] {
+        let output = cs01(root, &args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), renamed);
+    }
+
+    assert!(
+        cs01(root, &["config", "--remove-section", "remote.fork"])
+            .status
+            .success()
+    );
+    let removed = std::fs::read_to_string(&config_path).unwrap();
+    assert!(!removed.contains("fork"), "{}", removed);
+    assert!(removed.contains("[core]"), "{}", removed);
+    assert!(
+        !cs01(root, &["config", "--remove-section", "remote.fork"])
+            .status
+            .success()
+    );
+}
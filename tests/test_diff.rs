@@ -0,0 +1,295 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+mod common;
+use common::{commit_all, cs01, stderr, stdout};
+
+fn diff(root: &Path, args: &[&str]) -> String {
+    let output = cs01(root, &[&["diff"], args].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+}
+
+/// The abbreviated id git would give `content` as a blob.
+fn blob(root: &Path, content: &str) -> String {
+    fs::write(root.join(".blob"), content).unwrap();
+    let output = cs01(root, &["hash-object", ".blob"]);
+    fs::remove_file(root.join(".blob")).unwrap();
+    stdout(&output)[..7].to_string()
+}
+
+const POEM: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+/// A repository whose first commit holds a poem, a file to delete and a script.
+fn repository(dir: &Path) -> PathBuf {
+    let root = dir.join("work");
+    fs::create_dir_all(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join("poem.txt"), POEM).unwrap();
+    fs::write(root.join("gone.txt"), "bye\n").unwrap();
+    fs::write(root.join("run.sh"), "echo hi\n").unwrap();
+    commit_all(&root, "first");
+    root
+}
+
+#[test]
+fn test_diff_work_tree_against_index() {
+    let dir = tempdir().unwrap();
+    let root = repository(dir.path());
+    assert_eq!(diff(&root, &[]), "");
+
+    fs::write(root.join("poem.txt"), POEM.replace("two\n", "2\n")).unwrap();
+    fs::remove_file(root.join("gone.txt")).unwrap();
+    fs::write(root.join("untracked.txt"), "not shown\n").unwrap();
+    assert_eq!(
+        diff(&root, &[]),
+        format!(
+            "diff --git a/gone.txt b/gone.txt\n\
+             deleted file mode 100644\n\
+             index {}..0000000\n\
+             --- a/gone.txt\n\
+             +++ /dev/null\n\
+             @@ -1 +0,0 @@\n\
+             -bye\n\
+             diff --git a/poem.txt b/poem.txt\n\
+             index {}..{} 100644\n\
+             --- a/poem.txt\n\
+             +++ b/poem.txt\n\
+             @@ -1,5 +1,5 @@\n one\n-two\n+2\n three\n four\n five\n",
+            blob(&root, "bye\n"),
+            blob(&root, POEM),
+            blob(&root, &POEM.replace("two\n", "2\n")),
+        )
+    );
+
+    // -U sets the context; staged changes are no longer shown
+    assert!(diff(&root, &["-U1"]).contains("@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"));
+    assert!(cs01(&root, &["add", "poem.txt"]).status.success());
+    assert!(!diff(&root, &[]).contains("poem.txt"));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(root.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(
+            diff(&root, &[])
+                .contains("diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n")
+        );
+    }
+}
+
+#[test]
+fn test_diff_staged_against_head() {
+    let dir = tempdir().unwrap();
+    let root = repository(dir.path());
+
+    fs::write(root.join("new.txt"), "fresh\n").unwrap();
+    fs::write(root.join("poem.txt"), POEM.replace("nine\n", "")).unwrap();
+    assert!(
+        cs01(&root, &["add", "new.txt", "poem.txt"])
+            .status
+            .success()
+    );
+    // Unstaged changes don't show
+    fs::write(root.join("poem.txt"), "rewritten\n").unwrap();
+
+    assert_eq!(
+        diff(&root, &["--staged"]),
+        format!(
+            "diff --git a/new.txt b/new.txt\n\
+             new file mode 100644\n\
+             index 0000000..{}\n\
+             --- /dev/null\n\
+             +++ b/new.txt\n\
+             @@ -0,0 +1 @@\n\
+             +fresh\n\
+             diff --git a/poem.txt b/poem.txt\n\
+             index {}..{} 100644\n\
+             --- a/poem.txt\n\
+             +++ b/poem.txt\n\
+             @@ -6,5 +6,4 @@\n six\n seven\n eight\n-nine\n ten\n",
+            blob(&root, "fresh\n"),
+            blob(&root, POEM),
+            blob(&root, &POEM.replace("nine\n", "")),
+        )
+    );
+    assert_eq!(diff(&root, &["--cached"]), diff(&root, &["--staged"]));
+
+    let output = cs01(&root, &["--json", "diff", "--staged"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["files"][0]["path"], "new.txt");
+    assert_eq!(
+        value["files"][0]["hunks"][0]["lines"],
+        serde_json::json!(["+fresh"])
+    );
+}
+
+#[test]
+fn test_diff_between_commits() {
+    let dir = tempdir().unwrap();
+    let root = repository(dir.path());
+
+    fs::write(root.join("poem.txt"), format!("{}eleven", POEM)).unwrap();
+    fs::write(root.join("image.bin"), b"\x89PNG\0\x01\x02").unwrap();
+    fs::remove_file(root.join("gone.txt")).unwrap();
+    commit_all(&root, "second");
+
+    let output = diff(&root, &["HEAD~1", "HEAD"]);
+    assert!(
+        output.contains(
+            "diff --git a/image.bin b/image.bin\n\
+             new file mode 100644\n"
+        ),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("Binary files /dev/null and b/image.bin differ\n"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains(
+            "@@ -8,3 +8,4 @@\n eight\n nine\n ten\n+eleven\n\\ No newline at end of file\n"
+        ),
+        "{}",
+        output
+    );
+    assert!(output.contains("deleted file mode 100644\n"), "{}", output);
+
+    // The other way round, and against the working tree
+    let output = diff(&root, &["HEAD", "HEAD~1"]);
+    assert!(output.contains("+++ b/gone.txt\n"), "{}", output);
+    assert!(output.contains("-eleven\n"), "{}", output);
+    let output = diff(&root, &["HEAD~1"]);
+    assert!(output.contains("+eleven\n"), "{}", output);
+
+    let output = cs01(&root, &["diff", "HEAD", "nope"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_diff_check() {
+    let dir = tempdir().unwrap();
+    let root = repository(dir.path());
+
+    // Clean changes pass
+    fs::write(root.join("poem.txt"), format!("{}eleven\n", POEM)).unwrap();
+    let output = cs01(&root, &["diff", "--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+
+    // Only added lines are checked, numbered in the new file
+    fs::write(root.join("poem.txt"), POEM.replace("two\n", "two  \n")).unwrap();
+    fs::write(root.join("run.sh"), "echo hi\n \techo there\n").unwrap();
+    let output = cs01(&root, &["diff", "--check"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "poem.txt:2: trailing whitespace.\n+two  \n\
+         run.sh:2: space before tab in indent.\n+ \techo there\n"
+    );
+
+    // Between commits too
+    commit_all(&root, "sloppy");
+    let output = cs01(&root, &["diff", "--check", "HEAD~1", "HEAD"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(cs01(&root, &["diff", "--check"]).status.success());
+
+    // Leftover conflict markers
+    fs::write(
+        root.join("poem.txt"),
+        POEM.replace("two\n", "<<<<<<< HEAD\ntwo\n=======\n2\n>>>>>>> topic\n"),
+    )
+    .unwrap();
+    let output = cs01(&root, &["diff", "--check"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stdout(&output),
+        "poem.txt:2: leftover conflict marker.\n+<<<<<<< HEAD\n\
+         poem.txt:4: leftover conflict marker.\n+=======\n\
+         poem.txt:6: leftover conflict marker.\n+>>>>>>> topic\n"
+    );
+    assert!(stderr(&output).contains("3 lines with whitespace errors"));
+}
+
+/// `count` numbered lines, each starting with `tag`.
+fn numbered_lines(tag: &str, count: usize) -> String {
+    (0..count).map(|i| format!("{} {}\n", tag, i)).collect()
+}
+
+#[test]
+fn test_diff_of_huge_files_degrades_with_a_notice() {
+    let dir = tempdir().unwrap();
+    let root = repository(dir.path());
+    fs::write(root.join("big.txt"), numbered_lines("old", 300_000)).unwrap();
+    commit_all(&root, "big");
+
+    // A small change to a huge file is still diffed line by line
+    let small = numbered_lines("old", 300_000).replace("old 150000\n", "new 150000\n");
+    fs::write(root.join("big.txt"), small).unwrap();
+    let started = std::time::Instant::now();
+    let output = diff(&root, &[]);
+    assert!(
+        output.contains("@@ -149998,7 +149998,7 @@\n old 149997\n"),
+        "{}",
+        output
+    );
+
+    // Rewriting all of it is too much: a line in place of the hunks, and a warning
+    fs::write(root.join("big.txt"), numbered_lines("new", 300_000)).unwrap();
+    let output = cs01(&root, &["diff"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).ends_with(" 100644\nFiles a/big.txt and b/big.txt are too large to diff\n"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(
+        stderr(&output),
+        "warning: too many changed lines to diff big.txt; raise diff.maxChangedLines to see them\n"
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(60));
+
+    let output = cs01(&root, &["--json", "diff"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["files"][0]["too_large"], true);
+    assert_eq!(value["files"][0]["hunks"], serde_json::json!([]));
+    // The warning goes to stderr as JSON too, with --check as well
+    let output = cs01(&root, &["--json", "diff", "--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value, serde_json::json!({ "errors": [] }));
+    let value: serde_json::Value = serde_json::from_str(&stderr(&output)).unwrap();
+    assert_eq!(
+        value["warning"],
+        "too many changed lines to diff big.txt; raise diff.maxChangedLines to see them"
+    );
+
+    // The limit is configurable
+    fs::write(
+        root.join("big.txt"),
+        numbered_lines("old", 300_000) + "more\n",
+    )
+    .unwrap();
+    assert!(
+        cs01(&root, &["config", "diff.maxChangedLines", "0"])
+            .status
+            .success()
+    );
+    assert!(diff(&root, &[]).contains("too large to diff"));
+    assert!(
+        cs01(&root, &["config", "diff.maxChangedLines", "--", "-1"])
+            .status
+            .success()
+    );
+    let output = cs01(&root, &["diff"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("bad config value -1 for 'diff.maxChangedLines'"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,124 @@
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_command, cs01_with_stdin, stderr, stdout};
+
+// Ids from `git hash-object` for the same content
+const HELLO_BLOB: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+#[test]
+fn test_hash_object_without_repository() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("hello.txt"), "hello\n").unwrap();
+    std::fs::write(root.join("empty"), "").unwrap();
+
+    // Pure hashing works anywhere and writes nothing
+    let output = cs01(root, &["hash-object", "hello.txt", "empty"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), format!("{}\n{}\n", HELLO_BLOB, EMPTY_BLOB));
+
+    let output = cs01(root, &["hash-object", "--stdin", "-t", "tree"]);
+    assert_eq!(stdout(&output), format!("{}\n", EMPTY_TREE));
+
+    // Standard input comes before the files
+    let output = cs01_with_stdin(root, &["hash-object", "--stdin", "empty"], b"hello\n");
+    assert_eq!(stdout(&output), format!("{}\n{}\n", HELLO_BLOB, EMPTY_BLOB));
+
+    let output = cs01(root, &["--json", "hash-object", "hello.txt"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["type"], "blob");
+    assert_eq!(value["ids"], serde_json::json!([HELLO_BLOB]));
+
+    // Storing needs a repository
+    let output = cs01(root, &["hash-object", "-w", "hello.txt"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a CS01 repository"));
+
+    for args in [
+        &["hash-object"][..],
+        &["hash-object", "-t", "blob2", "hello.txt"],
+        &["hash-object", "missing.txt"],
+    ] {
+        assert!(!cs01(root, args).status.success(), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_hash_object_write() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    let nested = root.join("src/deep");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(nested.join("hello.txt"), "hello\n").unwrap();
+
+    let object = root.join(".CS01/objects/ce/013625030ba8dba906f756967f9e9ca394464a");
+    let output = cs01(&nested, &["hash-object", "hello.txt"]);
+    assert_eq!(stdout(&output), format!("{}\n", HELLO_BLOB));
+    assert!(!object.exists());
+
+    // Found from a subdirectory, and writing again is harmless
+    for _ in 0..2 {
+        let output = cs01(&nested, &["hash-object", "-w", "hello.txt"]);
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), format!("{}\n", HELLO_BLOB));
+        assert!(object.is_file());
+    }
+
+    let output = cs01(&nested, &["hash-object", "-w", "--stdin", "-t", "tree"]);
+    assert_eq!(stdout(&output), format!("{}\n", EMPTY_TREE));
+    assert!(
+        root.join(".CS01/objects/4b/825dc642cb6eb9a060e54bf8d69288fbee4904")
+            .is_file()
+    );
+}
+
+#[test]
+fn test_hash_object_write_to_object_directory() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    let elsewhere = dir.path().join("objects");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::create_dir(&elsewhere).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    std::fs::write(root.join("hello.txt"), "hello\n").unwrap();
+    std::fs::write(root.join("empty.txt"), "").unwrap();
+    let output = cs01(&root, &["hash-object", "-w", "hello.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let with_override = |args: &[&str]| {
+        cs01_command(&root, args)
+            .env("CS01_OBJECT_DIRECTORY", &elsewhere)
+            .output()
+            .unwrap()
+    };
+
+    // New objects land in the override...
+    let output = with_override(&["hash-object", "-w", "empty.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), format!("{}\n", EMPTY_BLOB));
+    assert!(
+        elsewhere
+            .join(&EMPTY_BLOB[..2])
+            .join(&EMPTY_BLOB[2..])
+            .is_file()
+    );
+    assert!(!root.join(".CS01/objects").join(&EMPTY_BLOB[..2]).exists());
+
+    // ...while those already in the repository are still read from it
+    let output = with_override(&["cat-file", "-p", HELLO_BLOB]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "hello\n");
+    let output = with_override(&["cat-file", "-t", &EMPTY_BLOB[..7]]);
+    assert_eq!(stdout(&output), "blob\n");
+
+    // Without it, the new object isn't there
+    assert!(
+        !cs01(&root, &["cat-file", "-t", EMPTY_BLOB])
+            .status
+            .success()
+    );
+}
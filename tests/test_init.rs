@@ -1,3 +1,6 @@
+// Note: The original tests pass `&[...]` to `Command::args`; kept as written.
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use std::process::Command;
 use tempfile::tempdir;
 
@@ -57,12 +60,25 @@ fn test_init_command() {
     let hooks_dir = cs01_dir.join("hooks");
     assert!(hooks_dir.exists());
     assert!(hooks_dir.join("pre-commit.sample").exists());
+    let pre_commit = std::fs::read_to_string(hooks_dir.join("pre-commit.sample")).unwrap();
+    assert!(pre_commit.starts_with("#!/bin/sh"));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(hooks_dir.join("pre-commit.sample"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111, "hook samples should be executable");
+    }
 
     // Check info/exclude
     let info_exclude = cs01_dir.join("info/exclude");
     assert!(info_exclude.exists());
     let exclude_content = std::fs::read_to_string(info_exclude).unwrap();
-    assert!(exclude_content.contains("# cs01 ls-files --others --exclude-from=.cs01/info/exclude"));
+    assert!(exclude_content.contains("# cs01 ls-files --others --exclude-from=.CS01/info/exclude"));
 
     // Check objects subdirs
     assert!(cs01_dir.join("objects/info").exists());
@@ -114,6 +130,12 @@ fn test_init_command_with_path() {
     assert!(config_content.contains("bare = false"));
     assert!(config_content.contains("filemode = true"));
     assert!(config_content.contains("logallrefupdates = true"));
+
+    // The reflog hierarchy is ready for the first ref update
+    let head_log = cs01_dir.join("logs/HEAD");
+    assert!(head_log.is_file());
+    assert_eq!(std::fs::read_to_string(head_log).unwrap(), "");
+    assert!(cs01_dir.join("logs/refs/heads").is_dir());
 }
 
 #[test]
@@ -198,6 +220,10 @@ fn test_init_bare_command() {
     assert!(config_file.exists());
     let config_content = std::fs::read_to_string(config_file).unwrap();
     assert!(config_content.contains("bare = true"));
+
+    // Like git, bare repositories don't keep reflogs by default
+    assert!(!config_content.contains("logallrefupdates"));
+    assert!(!root.join("logs").exists());
 }
 
 #[test]
@@ -274,3 +300,863 @@ fn test_init_absolute_path() {
     assert!(output.status.success());
     assert!(target_abs_path.join(".CS01").exists());
 }
+
+#[test]
+fn test_init_bare_shared_group() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+            "--",
+            "init",
+            "--bare",
+            "--shared=group",
+        ])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "Command failed:\nStdout: {}\nStderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let config_content = std::fs::read_to_string(root.join("config")).unwrap();
+    assert!(config_content.contains("sharedrepository = 1"));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let refs_mode = std::fs::metadata(root.join("refs"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(refs_mode & 0o7777, 0o2770);
+        let head_mode = std::fs::metadata(root.join("HEAD"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(head_mode & 0o777, 0o660);
+        let hook_mode = std::fs::metadata(root.join("hooks/pre-commit.sample"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(hook_mode & 0o777, 0o775);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_init_respects_restrictive_umask() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::process::CommandExt;
+
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let mut command = Command::new("cargo");
+    command
+        .args([
+            "run",
+            "--quiet",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+            "--",
+            "init",
+        ])
+        .current_dir(root);
+    // SAFETY: umask is async-signal-safe and touches no memory.
+    unsafe {
+        command.pre_exec(|| {
+            libc::umask(0o077);
+            Ok(())
+        });
+    }
+    let output = command.output().expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "Command failed:\nStdout: {}\nStderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    for dir in [".CS01", ".CS01/refs", ".CS01/refs/heads", ".CS01/objects"] {
+        let mode = std::fs::metadata(root.join(dir))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o700, "{dir}");
+    }
+}
+
+#[test]
+fn test_init_quiet() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+            "--",
+            "init",
+            "-q",
+        ])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert!(
+        output.stdout.is_empty(),
+        "Expected no output, got: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    // The repository must still be created
+    assert!(root.join(".CS01/HEAD").exists());
+    assert!(root.join(".CS01/config").exists());
+}
+
+#[test]
+fn test_init_json_output() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--quiet",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+                "--json",
+            ])
+            .args(extra)
+            .current_dir(root)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run_init(&["--initial-branch=trunk"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains('\x1b'), "JSON output must not be colored");
+    let value: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert_eq!(value["action"], "initialized");
+    assert_eq!(value["bare"], false);
+    assert_eq!(value["initial_branch"], "trunk");
+    let canonical_root = root.canonicalize().unwrap();
+    assert_eq!(value["path"], canonical_root.to_str().unwrap());
+    assert_eq!(
+        value["repo_dir"],
+        canonical_root.join(".CS01").to_str().unwrap()
+    );
+
+    let output = run_init(&[]);
+    assert!(output.status.success());
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["action"], "reinitialized");
+
+    // Errors are reported as JSON on stderr
+    let output = run_init(&["--initial-branch=bad..name"]);
+    assert!(!output.status.success());
+    let value: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert!(value["error"].as_str().unwrap().contains("bad..name"));
+}
+
+#[test]
+fn test_init_default_branch_precedence() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let home = root.join("home");
+    std::fs::create_dir(&home).unwrap();
+    std::fs::write(
+        home.join(".cs01config"),
+        "[init]\n  defaultBranch = trunk\n",
+    )
+    .unwrap();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |name: &str, env_branch: Option<&str>, extra: &[&str]| {
+        let mut cmd = Command::new("cargo");
+        cmd.args([
+            "run",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+            "--",
+            "init",
+            name,
+        ])
+        .args(extra)
+        .current_dir(root)
+        .env("HOME", &home)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("CS01_INITIAL_BRANCH");
+        if let Some(branch) = env_branch {
+            cmd.env("CS01_INITIAL_BRANCH", branch);
+        }
+        let output = cmd.output().expect("Failed to execute command");
+        assert!(
+            output.status.success(),
+            "Command failed:\nStderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        std::fs::read_to_string(root.join(name).join(".CS01/HEAD")).unwrap()
+    };
+
+    // 1. User config beats the built-in default
+    let head = run_init("from-config", None, &[]);
+    assert_eq!(head.trim(), "ref: refs/heads/trunk");
+    // The branch is unborn: HEAD names it, but it has no ref file until the first commit
+    assert!(!root.join("from-config/.CS01/refs/heads/trunk").exists());
+    assert!(root.join("from-config/.CS01/refs/heads").is_dir());
+
+    // 2. Environment variable beats user config
+    let head = run_init("from-env", Some("develop"), &[]);
+    assert_eq!(head.trim(), "ref: refs/heads/develop");
+
+    // 3. CLI flag beats everything
+    let head = run_init(
+        "from-flag",
+        Some("develop"),
+        &["--initial-branch", "release"],
+    );
+    assert_eq!(head.trim(), "ref: refs/heads/release");
+}
+
+#[test]
+fn test_init_invalid_branch_name() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+            "--",
+            "init",
+            "--initial-branch",
+            "foo..bar",
+        ])
+        .current_dir(root)
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid initial branch name: 'foo..bar'"));
+
+    // Nothing should have been written
+    assert!(!root.join(".CS01").exists());
+}
+
+#[test]
+fn test_init_object_format() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+            ])
+            .args(extra)
+            .current_dir(root)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // 1. Unknown formats are rejected
+    let output = run_init(&["--object-format", "md5"]);
+    assert!(!output.status.success());
+    assert!(!root.join(".CS01").exists());
+
+    // 2. SHA-256 is recorded in config
+    let output = run_init(&["--object-format", "sha256"]);
+    assert!(output.status.success());
+    let config_content = std::fs::read_to_string(root.join(".CS01/config")).unwrap();
+    assert!(config_content.contains("objectformat = sha256"));
+    assert!(config_content.contains("repositoryformatversion = 1"));
+
+    // 3. Re-init with a conflicting format is refused
+    let output = run_init(&["--object-format", "sha1"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("different hash"));
+
+    // 4. Re-init without a format (or with the same one) is fine
+    assert!(run_init(&[]).status.success());
+    assert!(run_init(&["--object-format", "sha256"]).status.success());
+}
+
+#[test]
+fn test_reinit_retargets_unborn_head() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+            ])
+            .args(extra)
+            .current_dir(root)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // 1. Fresh init on main, then re-init on trunk
+    assert!(run_init(&["--initial-branch", "main"]).status.success());
+    let output = run_init(&["--initial-branch", "trunk"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Reinitialized"));
+
+    let cs01_dir = root.join(".CS01");
+    let head = std::fs::read_to_string(cs01_dir.join("HEAD")).unwrap();
+    assert_eq!(head.trim(), "ref: refs/heads/trunk");
+    assert!(!cs01_dir.join("refs/heads/trunk").exists());
+    assert!(!cs01_dir.join("refs/heads/main").exists());
+
+    // A bootstrap ref left by an older version is removed along the way
+    std::fs::write(cs01_dir.join("refs/heads/trunk"), "ref: refs/heads/trunk").unwrap();
+    assert!(run_init(&[]).status.success());
+    assert!(!cs01_dir.join("refs/heads/trunk").exists());
+
+    // 2. Once the branch has a commit, HEAD must not move
+    std::fs::write(
+        cs01_dir.join("refs/heads/trunk"),
+        "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n",
+    )
+    .unwrap();
+    let output = run_init(&["--initial-branch", "other"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ignored --initial-branch=other"));
+
+    let head = std::fs::read_to_string(cs01_dir.join("HEAD")).unwrap();
+    assert_eq!(head.trim(), "ref: refs/heads/trunk");
+}
+
+#[test]
+fn test_init_detects_git_repository() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir(root.join(".git")).unwrap();
+    let sub_dir = root.join("sub");
+    std::fs::create_dir(&sub_dir).unwrap();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |cwd: &std::path::Path, extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+            ])
+            .args(extra)
+            .current_dir(cwd)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // 1. Ancestor case is refused without --force
+    let output = run_init(&sub_dir, &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+    assert!(!sub_dir.join(".CS01").exists());
+
+    // 2. ...and allowed with it
+    let output = run_init(&sub_dir, &["--force"]);
+    assert!(output.status.success());
+    assert!(sub_dir.join(".CS01").exists());
+
+    // 3. Same-directory case only warns
+    let output = run_init(root, &[]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already a Git repository"));
+    assert!(root.join(".CS01").exists());
+}
+
+#[test]
+fn test_init_nested_repo_force_and_exclude() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |cwd: &std::path::Path, extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+            ])
+            .args(extra)
+            .current_dir(cwd)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    assert!(run_init(root, &[]).status.success());
+
+    // 1. The refusal suggests --force, and --force succeeds with a warning
+    let forced_dir = root.join("vendor/forced");
+    std::fs::create_dir_all(&forced_dir).unwrap();
+
+    let output = run_init(&forced_dir, &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+
+    let output = run_init(&forced_dir, &["--force"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("nested repository"));
+    let inner = forced_dir.join(".CS01");
+    assert!(inner.join("HEAD").exists());
+    assert!(inner.join("config").exists());
+    assert!(inner.join("refs/heads").exists());
+
+    // 2. A directory excluded by the outer repo needs no --force
+    let excluded_dir = root.join("third_party/lib");
+    std::fs::create_dir_all(&excluded_dir).unwrap();
+    let exclude_file = root.join(".CS01/info/exclude");
+    let mut exclude = std::fs::read_to_string(&exclude_file).unwrap();
+    exclude.push_str("/third_party/\n");
+    std::fs::write(&exclude_file, exclude).unwrap();
+
+    let output = run_init(&excluded_dir, &[]);
+    assert!(
+        output.status.success(),
+        "Stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(excluded_dir.join(".CS01").exists());
+}
+
+#[test]
+fn test_init_returns_outcome() {
+    use cs_01::commands::init::{InitOptions, init};
+    use cs_01::modules::output::Output;
+
+    let dir = tempdir().unwrap();
+    let target = dir.path().join("lib-repo");
+    let quiet = Output::new(true);
+    let options = InitOptions {
+        initial_branch: Some("trunk".to_string()),
+        ..InitOptions::default()
+    };
+
+    let outcome = init(&target, &options, &quiet).unwrap();
+
+    let target = target.canonicalize().unwrap();
+    assert!(!outcome.reinitialized);
+    assert!(!outcome.bare);
+    assert_eq!(outcome.initial_branch, "trunk");
+    assert_eq!(outcome.work_tree.as_deref(), Some(target.as_path()));
+    assert_eq!(outcome.repo_dir, target.join(".CS01"));
+
+    let outcome = init(&target, &options, &quiet).unwrap();
+    assert!(outcome.reinitialized);
+
+    let bare_target = dir.path().join("bare-repo");
+    let bare = InitOptions {
+        bare: true,
+        ..InitOptions::default()
+    };
+    let outcome = init(&bare_target, &bare, &quiet).unwrap();
+    assert!(outcome.bare);
+    assert_eq!(outcome.work_tree, None);
+    assert_eq!(outcome.repo_dir, bare_target.canonicalize().unwrap());
+}
+
+#[test]
+fn test_init_bare_reinit_detection() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = |cwd: &std::path::Path, extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+            ])
+            .args(extra)
+            .current_dir(cwd)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // 1. A stray file named HEAD does not make a directory a bare repository
+    let stray = root.join("stray");
+    std::fs::create_dir(&stray).unwrap();
+    std::fs::write(stray.join("HEAD"), "not a ref\n").unwrap();
+    let output = run_init(&stray, &["--bare"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Initialized empty bare"));
+
+    // 2. A bare repository whose HEAD was deleted is still recognized, and HEAD is restored
+    let bare = root.join("bare");
+    std::fs::create_dir(&bare).unwrap();
+    assert!(run_init(&bare, &["--bare"]).status.success());
+    std::fs::remove_file(bare.join("HEAD")).unwrap();
+    let output = run_init(&bare, &["--bare"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Reinitialized existing bare"));
+    assert!(bare.join("HEAD").exists());
+
+    // 3. A directory holding a standard repository refuses --bare
+    let standard = root.join("standard");
+    std::fs::create_dir(&standard).unwrap();
+    assert!(run_init(&standard, &[]).status.success());
+    let output = run_init(&standard, &["--bare"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("standard repository"));
+    assert!(!standard.join("HEAD").exists());
+}
+
+#[test]
+fn test_init_honors_cs01_dir() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let meta_dir = root.join("meta");
+    let work_dir = root.join("work");
+    std::fs::create_dir(&work_dir).unwrap();
+
+    // Get the path to the current project's Cargo.toml
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let manifest_path = std::path::Path::new(manifest_dir).join("Cargo.toml");
+
+    let run_init = || {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--manifest-path",
+                manifest_path.to_str().unwrap(),
+                "--",
+                "init",
+            ])
+            .current_dir(&work_dir)
+            .env("CS01_DIR", &meta_dir)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run_init();
+    assert!(
+        output.status.success(),
+        "Stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Metadata lands in CS01_DIR, not in the working tree
+    assert!(meta_dir.join("HEAD").exists());
+    assert!(meta_dir.join("refs/heads").exists());
+    let config_content = std::fs::read_to_string(meta_dir.join("config")).unwrap();
+    assert!(config_content.contains("bare = false"));
+    assert!(!work_dir.join(".CS01").exists());
+
+    let output = run_init();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Reinitialized"));
+}
+
+#[test]
+fn test_init_records_fs_capabilities() {
+    use cs_01::commands::init::{InitOptions, init};
+    use cs_01::modules::{config::str_to_obj, files::probe_fs_capabilities, output::Output};
+
+    let dir = tempdir().unwrap();
+    let target = dir.path().join("probed");
+    let expected = probe_fs_capabilities(dir.path());
+
+    init(&target, &InitOptions::default(), &Output::new(true)).unwrap();
+
+    let config = std::fs::read_to_string(target.join(".CS01/config")).unwrap();
+    let core = &str_to_obj(&config).unwrap()["core"][""];
+    assert_eq!(core["filemode"], expected.filemode.to_string());
+    assert_eq!(
+        core.get("symlinks")
+            .and_then(|v| v.as_str())
+            .unwrap_or("true"),
+        expected.symlinks.to_string()
+    );
+    assert_eq!(
+        core.get("ignorecase")
+            .and_then(|v| v.as_str())
+            .unwrap_or("false"),
+        expected.ignorecase.to_string()
+    );
+
+    // The probe's scratch files must not end up in the new working tree
+    let mut entries: Vec<_> = std::fs::read_dir(&target)
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, vec![".CS01"]);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_init_hides_metadata_dir() {
+    use cs_01::commands::init::init;
+    use cs_01::modules::{output::Output, perms::SharedMode};
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    let dir = tempdir().unwrap();
+    init(
+        false,
+        None,
+        dir.path().to_str().unwrap(),
+        SharedMode::Umask,
+        None,
+        false,
+        &Output::new(true),
+    )
+    .unwrap();
+
+    let attributes = std::fs::metadata(dir.path().join(".CS01"))
+        .unwrap()
+        .file_attributes();
+    assert_ne!(attributes & FILE_ATTRIBUTE_HIDDEN, 0);
+
+    let config = std::fs::read_to_string(dir.path().join(".CS01/config")).unwrap();
+    assert!(config.contains("hidedotfiles = true"));
+}
+
+#[test]
+fn test_init_with_template() {
+    use cs_01::commands::init::{InitOptions, init};
+    use cs_01::modules::output::Output;
+
+    let dir = tempdir().unwrap();
+    let template = dir.path().join("template");
+    std::fs::create_dir_all(template.join("hooks")).unwrap();
+    std::fs::create_dir_all(template.join("info")).unwrap();
+    std::fs::write(template.join("description"), "Team project\n").unwrap();
+    std::fs::write(template.join("hooks/pre-commit"), "#!/bin/sh\nexit 0\n").unwrap();
+    std::fs::write(template.join("info/exclude"), "*.o\n").unwrap();
+    std::fs::write(template.join("HEAD"), "ref: refs/heads/template\n").unwrap();
+    std::fs::write(template.join("config"), "[core]\n\tbare = true\n").unwrap();
+
+    let target = dir.path().join("repo");
+    let options = InitOptions {
+        template: Some(template.clone()),
+        ..InitOptions::default()
+    };
+    init(&target, &options, &Output::new(true)).unwrap();
+
+    let cs01_dir = target.join(".CS01");
+    let read = |name: &str| std::fs::read_to_string(cs01_dir.join(name)).unwrap();
+    assert_eq!(read("description"), "Team project\n");
+    assert_eq!(read("hooks/pre-commit"), "#!/bin/sh\nexit 0\n");
+    assert_eq!(read("info/exclude"), "*.o\n");
+    // Built-in files the template doesn't replace are still there
+    assert!(cs01_dir.join("hooks/pre-commit.sample").is_file());
+    // HEAD and config always come from init itself
+    assert_eq!(read("HEAD"), "ref: refs/heads/main\n");
+    assert!(read("config").contains("bare = false"));
+
+    // Re-init keeps what is already there, as without a template
+    std::fs::write(cs01_dir.join("description"), "edited\n").unwrap();
+    init(&target, &options, &Output::new(true)).unwrap();
+    assert_eq!(read("description"), "edited\n");
+
+    // A missing template directory is not an error
+    let options = InitOptions {
+        template: Some(dir.path().join("missing")),
+        ..InitOptions::default()
+    };
+    init(&dir.path().join("other"), &options, &Output::new(true)).unwrap();
+
+    // A template file where init needs a directory is
+    let clashing = dir.path().join("clashing");
+    std::fs::create_dir(&clashing).unwrap();
+    std::fs::write(clashing.join("refs"), "").unwrap();
+    let options = InitOptions {
+        template: Some(clashing),
+        ..InitOptions::default()
+    };
+    let err = init(&dir.path().join("third"), &options, &Output::new(true)).unwrap_err();
+    assert!(format!("{:#}", err).contains("'refs'"), "{:#}", err);
+    assert!(!dir.path().join("third/.CS01").exists());
+}
+
+#[test]
+fn test_init_dry_run_plan() {
+    use cs_01::commands::init::{InitOptions, init};
+    use cs_01::modules::files::PlannedOp;
+    use cs_01::modules::output::Output;
+
+    let dir = tempdir().unwrap();
+    let target = dir.path().join("project");
+    let options = InitOptions {
+        dry_run: true,
+        ..InitOptions::default()
+    };
+
+    let outcome = init(&target, &options, &Output::new(true)).unwrap();
+    assert!(!target.exists(), "a dry run must not touch the disk");
+    assert!(!outcome.reinitialized);
+
+    let plan = outcome.plan.unwrap();
+    let cs01_dir = target.join(".CS01");
+    assert_eq!(plan[0], PlannedOp::CreateDir(cs01_dir.clone()));
+    assert!(plan.contains(&PlannedOp::WriteFile {
+        path: cs01_dir.join("HEAD"),
+        bytes: "ref: refs/heads/main\n".len(),
+    }));
+    assert!(plan.iter().any(|op| matches!(op,
+        PlannedOp::WriteFile { path, .. } if *path == cs01_dir.join("config"))));
+    for refs in ["refs", "refs/heads", "refs/tags"] {
+        assert!(
+            plan.contains(&PlannedOp::CreateDir(cs01_dir.join(refs))),
+            "{}",
+            refs
+        );
+    }
+
+    // Planned against an existing repository, everything already there is skipped
+    init(&target, &InitOptions::default(), &Output::new(true)).unwrap();
+    let outcome = init(&target, &options, &Output::new(true)).unwrap();
+    assert!(outcome.reinitialized);
+    let plan = outcome.plan.unwrap();
+    assert!(plan.contains(&PlannedOp::Skip {
+        path: cs01_dir.join("HEAD"),
+        reason: "already exists",
+    }));
+    assert!(
+        plan.iter().all(|op| matches!(op, PlannedOp::Skip { .. })),
+        "{:?}",
+        plan
+    );
+
+    // The CLI renders the plan instead of acting on it
+    let manifest_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let output = Command::new("cargo")
+        .args(["run", "--manifest-path", manifest_path.to_str().unwrap()])
+        .args(["--", "--json", "init", "--dry-run", "fresh"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["dry_run"], true);
+    assert!(
+        result["plan"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|op| op["op"] == "write_file" && op["path"].as_str().unwrap().ends_with("HEAD"))
+    );
+    assert!(!dir.path().join("fresh").exists());
+}
+
+// Note: On Windows the config also records core.hidedotfiles.
+#[cfg(not(windows))]
+#[test]
+fn test_repo_tree_snapshot() {
+    use cs_01::modules::{
+        files::FsCaps, hash::ObjectFormat, perms::SharedMode, repo_structure::build_repo_tree,
+    };
+
+    let caps = FsCaps {
+        filemode: true,
+        symlinks: true,
+        ignorecase: false,
+    };
+    let tree = build_repo_tree(
+        false,
+        "main",
+        SharedMode::Umask,
+        ObjectFormat::Sha1,
+        caps,
+        true,
+    )
+    .unwrap();
+    let json = tree.to_json().unwrap() + "\n";
+
+    // Regenerate after an intended change with CS01_UPDATE_SNAPSHOTS=1 and review the diff.
+    let snapshot =
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/repo_tree.json");
+    if std::env::var_os("CS01_UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(snapshot.parent().unwrap()).unwrap();
+        std::fs::write(&snapshot, &json).unwrap();
+    }
+    let expected = std::fs::read_to_string(&snapshot).unwrap();
+    assert!(
+        json == expected,
+        "repository structure changed; rerun with CS01_UPDATE_SNAPSHOTS=1 if intended"
+    );
+    assert_eq!(
+        cs_01::modules::files::TreeNode::from_json(&expected).unwrap(),
+        tree
+    );
+}
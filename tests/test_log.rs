@@ -0,0 +1,327 @@
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01_command, cs01_with_stdin, stderr, stdout};
+
+// The history below, as git stores it: First <- Second <- Third <- Merge side, and
+// First <- Side <- Merge side
+const FIRST: &str = "5b6cbaecf59f8dc1fb2dd22f57a04289ad6cf740";
+const SECOND: &str = "8a3875072e219aced921e02f788526692726e44c";
+const THIRD: &str = "e5601776562a1f1bbe8578bd12483a09fa781eb3";
+const SIDE: &str = "7e9ecafbdcbed493c9662b10bf9a9c5ae2fee61c";
+const MERGE: &str = "0e6f7a4bba661086e924e523602d9ce6023d3fb6";
+
+/// Runs cs01 as A U Thor and C O Mitter, both at `seconds` (in different timezones).
+fn cs01_at(dir: &Path, args: &[&str], seconds: u32) -> Output {
+    cs01_command(dir, args)
+        .env("CS01_AUTHOR_DATE", format!("{} +0530", seconds))
+        .env("CS01_COMMITTER_DATE", format!("{} -0700", seconds))
+        .output()
+        .expect("Failed to execute command")
+}
+
+fn cs01(dir: &Path, args: &[&str]) -> Output {
+    cs01_at(dir, args, 1_700_000_000)
+}
+
+/// Three commits on main and a side commit from the first, merged; the side commit is
+/// dated between the second and third.
+fn repo_with_history(dir: &Path) -> std::path::PathBuf {
+    let root = dir.join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    let tree = stdout(&cs01(&root, &["write-tree"])).trim_end().to_string();
+
+    let commit = |seconds: u32, args: &[&str]| {
+        let output = cs01_at(
+            &root,
+            &[&["commit-tree", tree.as_str()], args].concat(),
+            seconds,
+        );
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output).trim_end().to_string()
+    };
+    let first = commit(1_700_000_000, &["-m", "First"]);
+    let second = commit(
+        1_700_000_100,
+        &["-p", &first, "-m", "Second", "-m", "With a body."],
+    );
+    let third = commit(1_700_000_200, &["-p", &second, "-m", "Third"]);
+    let side = commit(1_700_000_150, &["-p", &first, "-m", "Side"]);
+    let merge = commit(
+        1_700_000_300,
+        &["-p", &third, "-p", &side, "-m", "Merge side"],
+    );
+    assert_eq!([first, second, third, side], [FIRST, SECOND, THIRD, SIDE]);
+    assert_eq!(merge, MERGE);
+    assert!(cs01(&root, &["update-ref", "HEAD", MERGE]).status.success());
+    root
+}
+
+/// `repo_with_history` with every kind of reference: the current branch and another,
+/// lightweight and annotated tags, remote branches, the stash and a notes reference.
+fn repo_with_references(dir: &Path) -> std::path::PathBuf {
+    let root = repo_with_history(dir);
+    let tag = format!(
+        "object {}\ntype commit\ntag v2\ntagger C O Mitter <committer@example.com> 1700000100 -0700\n\nVersion 2\n",
+        SECOND
+    );
+    let output = cs01_with_stdin(
+        &root,
+        &["hash-object", "-t", "tag", "-w", "--stdin"],
+        tag.as_bytes(),
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let tag = stdout(&output).trim_end().to_string();
+    for (name, id) in [
+        ("refs/heads/side", SIDE),
+        ("refs/tags/v1", SIDE),
+        ("refs/tags/v2", tag.as_str()),
+        ("refs/remotes/origin/main", THIRD),
+        ("refs/remotes/origin/HEAD", THIRD),
+        ("refs/stash", FIRST),
+        ("refs/notes/commits", FIRST),
+    ] {
+        assert!(cs01(&root, &["update-ref", name, id]).status.success());
+    }
+    root
+}
+
+/// The `commit <id>` lines of `log`.
+fn ids(output: &Output) -> Vec<String> {
+    stdout(output)
+        .lines()
+        .filter_map(|line| line.strip_prefix("commit "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_log_matches_git() {
+    let dir = tempdir().unwrap();
+    let root = repo_with_history(dir.path());
+
+    // `git log` of the same history; the shared first commit is shown once
+    let expected = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/git_log_merge.txt"),
+    )
+    .unwrap();
+    let output = cs01(&root, &["log"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), expected);
+
+    let output = cs01(&root, &["log", "-n", "2"]);
+    assert_eq!(ids(&output), [MERGE, THIRD]);
+    let output = cs01(&root, &["log", "--max-count", "0"]);
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn test_log_from_a_revision() {
+    let dir = tempdir().unwrap();
+    let root = repo_with_history(dir.path());
+
+    let output = cs01(&root, &["log", &SIDE[..7]]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(ids(&output), [SIDE, FIRST]);
+    let output = cs01(&root, &["log", "main~1"]);
+    assert_eq!(ids(&output), [THIRD, SECOND, FIRST]);
+
+    let output = cs01(&root, &["--json", "log", "-n", "1", "HEAD^2"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value[0]["id"], SIDE);
+    assert_eq!(value[0]["parents"], serde_json::json!([FIRST]));
+    assert_eq!(value[0]["author"]["name"], "A U Thor");
+    assert_eq!(value[0]["committer"]["date"], "1700000150 -0700");
+    assert_eq!(value[0]["message"], "Side\n");
+    assert_eq!(value.as_array().unwrap().len(), 1);
+
+    // Unknown revisions and objects other than commits are refused
+    let output = cs01(&root, &["log", "nope"]);
+    assert_eq!(output.status.code(), Some(128));
+    let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    let output = cs01(&root, &["log", tree]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("not a commit"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_log_on_an_unborn_branch() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+
+    let output = cs01(&root, &["log"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("your current branch 'main' does not have any commits yet"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_log_decorate() {
+    let dir = tempdir().unwrap();
+    let root = repo_with_history(dir.path());
+    for (name, id) in [
+        ("refs/heads/side", SIDE),
+        ("refs/tags/v1", SIDE),
+        ("refs/remotes/origin/main", THIRD),
+    ] {
+        assert!(cs01(&root, &["update-ref", name, id]).status.success());
+    }
+
+    // The current branch only next to HEAD, then the rest in git's order
+    let output = cs01(&root, &["log", "--decorate"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = stdout(&output);
+    let names: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix("commit "))
+        .map(|line| &line[40..])
+        .collect();
+    assert_eq!(
+        names,
+        [
+            " (HEAD -> main)",
+            " (origin/main)",
+            " (tag: v1, side)",
+            "",
+            ""
+        ]
+    );
+    assert_eq!(ids(&cs01(&root, &["log"])).len(), 5);
+
+    // A detached HEAD stands alone
+    let output = cs01(&root, &["switch", "--detach", "side"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = cs01(&root, &["log", "--decorate", "-n", "1"]);
+    assert!(
+        stdout(&output).starts_with(&format!("commit {} (HEAD, tag: v1, side)\n", SIDE)),
+        "{}",
+        stdout(&output)
+    );
+
+    let output = cs01(&root, &["--json", "log", "--decorate", "main"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value[0]["decorations"], serde_json::json!(["main"]));
+    assert_eq!(value[1]["decorations"], serde_json::json!(["origin/main"]));
+}
+
+#[test]
+fn test_log_decorate_matches_git() {
+    let dir = tempdir().unwrap();
+    let root = repo_with_references(dir.path());
+
+    // `git log` of the same repository, each run as a `$ log <args>` line followed by
+    // its output; a `format:` run has no final newline, which the fixture adds
+    let fixture = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/git_log_decorate.txt"),
+    )
+    .unwrap();
+    let mut runs = 0;
+    for run in fixture.split("$ ").skip(1) {
+        let (command, expected) = run.split_once('\n').unwrap();
+        let args: Vec<&str> = command.split(' ').collect();
+        let output = cs01(&root, &args);
+        assert!(output.status.success(), "{}: {}", command, stderr(&output));
+        let mut actual = stdout(&output);
+        if !actual.ends_with('\n') {
+            actual.push('\n');
+        }
+        assert_eq!(actual, expected, "{}", command);
+        runs += 1;
+    }
+    assert_eq!(runs, 11);
+}
+
+#[test]
+fn test_log_decorate_colors_and_config() {
+    let dir = tempdir().unwrap();
+    let root = repo_with_references(dir.path());
+    let colored = |args: &[&str]| {
+        let output = cs01_command(&root, args)
+            .env("CLICOLOR_FORCE", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output)
+    };
+
+    // Each kind of reference in its own color, the stash in magenta, notes plain
+    let output = colored(&[
+        "log",
+        "--oneline",
+        "--decorate",
+        "--decorate-refs-exclude=x",
+    ]);
+    for painted in [
+        "\x1b[1;36mHEAD -> \x1b[0m\x1b[1;32mmain\x1b[0m",
+        "\x1b[1;31morigin/main\x1b[0m",
+        "\x1b[1;33mtag: v1\x1b[0m",
+        "\x1b[1;35mrefs/stash\x1b[0m",
+        "\x1b[33m, \x1b[0mrefs/notes/commits\x1b[33m)",
+    ] {
+        assert!(output.contains(painted), "{:?} in {:?}", painted, output);
+    }
+
+    // color.decorate.<slot> overrides a color; a bad one is an error
+    assert!(
+        cs01(&root, &["config", "color.decorate.branch", "blue ul"])
+            .status
+            .success()
+    );
+    let output = colored(&["log", "--oneline", "--decorate", "-n", "1"]);
+    assert!(output.contains("\x1b[4;34mmain\x1b[0m"), "{:?}", output);
+    assert!(
+        cs01(&root, &["config", "color.decorate.branch", "purple"])
+            .status
+            .success()
+    );
+    let output = cs01(&root, &["log", "--decorate"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("bad config value purple for 'color.decorate.branch'"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        cs01(&root, &["config", "--unset", "color.decorate.branch"])
+            .status
+            .success()
+    );
+
+    // log.decorate applies unless overridden
+    assert!(
+        cs01(&root, &["config", "log.decorate", "full"])
+            .status
+            .success()
+    );
+    let output = cs01(&root, &["log", "--oneline", "-n", "1"]);
+    assert_eq!(
+        stdout(&output),
+        "0e6f7a4 (HEAD -> refs/heads/main) Merge side\n"
+    );
+    let output = cs01(&root, &["log", "--oneline", "--no-decorate", "-n", "1"]);
+    assert_eq!(stdout(&output), "0e6f7a4 Merge side\n");
+    let output = cs01(&root, &["--json", "log", "-n", "1", "v1"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(
+        value[0]["decorations"],
+        serde_json::json!(["tag: refs/tags/v1", "refs/heads/side"])
+    );
+
+    let output = cs01(&root, &["log", "--decorate=sometimes"]);
+    assert!(!output.status.success());
+    let output = cs01(&root, &["log", "--pretty=fancy"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stdout};
+
+fn ls_files(dir: &Path, args: &[&str]) -> String {
+    let output = cs01(dir, &[&["ls-files"], args].concat());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout(&output)
+}
+
+/// A repository with staged files, one of them since modified and one deleted, and
+/// untracked files, some of them ignored.
+fn work_tree(dir: &Path) -> PathBuf {
+    let root = dir.join("work");
+    fs::create_dir_all(root.join("src/deep")).unwrap();
+    fs::create_dir_all(root.join("target")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join("b.txt"), "b\n").unwrap();
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("src/lib.rs"), "\n").unwrap();
+    assert!(cs01(&root, &["add", "."]).status.success());
+
+    fs::write(root.join("a.txt"), "changed\n").unwrap();
+    fs::remove_file(root.join("src/lib.rs")).unwrap();
+    fs::write(root.join("src/deep/new.rs"), "\n").unwrap();
+    fs::write(root.join("notes.md"), "\n").unwrap();
+    fs::write(root.join("debug.log"), "\n").unwrap();
+    fs::write(root.join("target/app"), "\n").unwrap();
+    fs::write(root.join(".cs01ignore"), "*.log\n").unwrap();
+    let mut exclude = fs::read_to_string(root.join(".CS01/info/exclude")).unwrap();
+    exclude.push_str("target/\n");
+    fs::write(root.join(".CS01/info/exclude"), exclude).unwrap();
+    root
+}
+
+#[test]
+fn test_ls_files_lists_the_index() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+
+    assert_eq!(
+        ls_files(&root, &[]),
+        "a.txt\nb.txt\nsrc/lib.rs\nsrc/main.rs\n"
+    );
+    // A subdirectory lists only its files, relative to it unless --full-name is given
+    assert_eq!(
+        ls_files(&root.join("src"), &["--cached"]),
+        "lib.rs\nmain.rs\n"
+    );
+    assert_eq!(
+        ls_files(&root.join("src"), &["--full-name", "-d"]),
+        "src/lib.rs\n"
+    );
+    assert_eq!(ls_files(&root.join("src/deep"), &[]), "");
+
+    let staged = ls_files(&root, &["--stage"]);
+    let lines: Vec<&str> = staged.lines().collect();
+    assert_eq!(
+        lines[1],
+        "100644 61780798228d17af2d34fce4cfbdf35556832472 0\tb.txt"
+    );
+    assert_eq!(lines.len(), 4);
+
+    assert_eq!(ls_files(&root, &["--modified"]), "a.txt\nsrc/lib.rs\n");
+    assert_eq!(ls_files(&root, &["-d"]), "src/lib.rs\n");
+    assert_eq!(ls_files(&root, &["-z", "-m"]), "a.txt\0src/lib.rs\0");
+
+    // Restaging clears the modification
+    assert!(cs01(&root, &["add", "a.txt"]).status.success());
+    assert_eq!(ls_files(&root, &["-m"]), "src/lib.rs\n");
+
+    let output = cs01(&root, &["--json", "ls-files", "-d"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["files"][0]["path"], "src/lib.rs");
+    assert_eq!(value["files"][0]["listing"], "deleted");
+    assert_eq!(value["files"][0]["stage"], 0);
+}
+
+#[test]
+fn test_ls_files_lists_untracked_files() {
+    let dir = tempdir().unwrap();
+    let root = work_tree(dir.path());
+
+    // Without exclusions, ignored files are listed too
+    assert_eq!(
+        ls_files(&root, &["--others"]),
+        ".cs01ignore\ndebug.log\nnotes.md\nsrc/deep/new.rs\ntarget/app\n"
+    );
+    assert_eq!(
+        ls_files(&root.join("src"), &["-o", "--exclude-standard"]),
+        "deep/new.rs\n"
+    );
+    assert_eq!(
+        ls_files(
+            &root.join("src"),
+            &["-o", "--exclude-standard", "--full-name"]
+        ),
+        "src/deep/new.rs\n"
+    );
+    // What init's info/exclude suggests: only that file's patterns count
+    assert_eq!(
+        ls_files(&root, &["--others", "--exclude-from=.CS01/info/exclude"]),
+        ".cs01ignore\ndebug.log\nnotes.md\nsrc/deep/new.rs\n"
+    );
+
+    // Untracked files come before the index entries
+    assert_eq!(
+        ls_files(&root, &["-o", "-c", "--exclude-standard", "-z"]),
+        ".cs01ignore\0notes.md\0src/deep/new.rs\0a.txt\0b.txt\0src/lib.rs\0src/main.rs\0"
+    );
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{commit, cs01, read, rev_parse, run, stderr, stdout};
+
+/// A repository whose `main` and `topic` branches both start from a commit of
+/// `lines.txt`, holding the lines 1 to 5.
+fn forked_repo(root: &Path) -> String {
+    run(root, &["init", "-q"]);
+    let base = commit(root, &[("lines.txt", "1\n2\n3\n4\n5\n")], "Base");
+    run(root, &["branch", "topic"]);
+    base
+}
+
+#[test]
+fn test_merge_fast_forwards() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let base = forked_repo(root);
+    run(root, &["switch", "topic"]);
+    let tip = commit(root, &[("new.txt", "new\n")], "Add new.txt");
+    run(root, &["switch", "main"]);
+
+    assert_eq!(
+        run(root, &["merge", "topic"]),
+        format!("Updating {}..{}\nFast-forward\n", &base[..7], &tip[..7])
+    );
+    assert_eq!(rev_parse(root, "main"), tip);
+    assert_eq!(rev_parse(root, "ORIG_HEAD"), base);
+    assert_eq!(read(root, "new.txt"), "new\n");
+    assert!(run(root, &["reflog"]).starts_with(&format!(
+        "{} HEAD@{{0}}: merge topic: Fast-forward\n",
+        &tip[..7]
+    )));
+    assert_eq!(run(root, &["merge", "topic"]), "Already up to date.\n");
+
+    // --no-ff records a merge commit anyway, with the same tree
+    run(root, &["reset", "--hard", &base]);
+    let output = run(root, &["merge", "--no-ff", "topic"]);
+    assert!(
+        output.ends_with("Merge made by a three-way merge.\n"),
+        "{}",
+        output
+    );
+    assert_eq!(rev_parse(root, "HEAD^1"), base);
+    assert_eq!(rev_parse(root, "HEAD^2"), tip);
+    assert_eq!(
+        run(root, &["log", "-n", "1"]).lines().last(),
+        Some("    Merge branch 'topic'")
+    );
+    assert_eq!(read(root, "new.txt"), "new\n");
+}
+
+#[test]
+fn test_merge_combines_changes_to_both_branches() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let base = forked_repo(root);
+    let ours = commit(root, &[("lines.txt", "1\n2\n3\n4\nfive\n")], "Change 5");
+    run(root, &["switch", "topic"]);
+    let theirs = commit(
+        root,
+        &[("lines.txt", "one\n2\n3\n4\n5\n"), ("new.txt", "new\n")],
+        "Change 1",
+    );
+    run(root, &["switch", "main"]);
+
+    let output = cs01(root, &["merge", "--ff-only", "topic"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Not possible to fast-forward, aborting."));
+
+    assert_eq!(
+        run(root, &["merge", "topic"]),
+        "Auto-merging lines.txt\nMerge made by a three-way merge.\n"
+    );
+    assert_eq!(read(root, "lines.txt"), "one\n2\n3\n4\nfive\n");
+    assert_eq!(read(root, "new.txt"), "new\n");
+    assert_eq!(rev_parse(root, "HEAD^1"), ours);
+    assert_eq!(rev_parse(root, "HEAD^2"), theirs);
+    assert_eq!(rev_parse(root, "ORIG_HEAD"), ours);
+    assert_eq!(rev_parse(root, "HEAD~2"), base);
+    assert!(
+        run(root, &["status"]).ends_with("nothing to commit, working tree clean\n"),
+        "the merge result is committed"
+    );
+}
+
+#[test]
+fn test_conflicting_merge_is_resolved_and_committed() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    forked_repo(root);
+    let ours = commit(root, &[("lines.txt", "1\n2\nours\n4\n5\n")], "Ours");
+    run(root, &["switch", "topic"]);
+    let theirs = commit(
+        root,
+        &[("lines.txt", "1\n2\ntheirs\n4\n5\n"), ("new.txt", "new\n")],
+        "Theirs",
+    );
+    run(root, &["switch", "main"]);
+
+    let output = cs01(root, &["merge", "topic"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "Auto-merging lines.txt\nCONFLICT (content): Merge conflict in lines.txt\n"
+    );
+    assert!(
+        stderr(&output)
+            .contains("Automatic merge failed; fix conflicts and then commit the result.")
+    );
+    assert_eq!(
+        read(root, "lines.txt"),
+        "1\n2\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\n4\n5\n"
+    );
+    assert_eq!(read(root, "new.txt"), "new\n");
+    assert_eq!(rev_parse(root, "HEAD"), ours);
+    assert_eq!(rev_parse(root, "MERGE_HEAD"), theirs);
+    assert_eq!(
+        read(root, ".CS01/MERGE_MSG"),
+        "Merge branch 'topic'\n\n# Conflicts:\n#\tlines.txt\n"
+    );
+    let stages: Vec<String> = run(root, &["debug-index"])
+        .lines()
+        .filter(|line| line.contains("lines.txt"))
+        .map(String::from)
+        .collect();
+    assert_eq!(stages.len(), 3, "{:?}", stages);
+
+    let status = run(root, &["status"]);
+    assert!(status.contains("You have unmerged paths."), "{}", status);
+    assert!(status.contains("\tnew file:   new.txt\n"), "{}", status);
+    assert!(
+        status.contains("\tboth modified:   lines.txt\n"),
+        "{}",
+        status
+    );
+
+    let output = cs01(root, &["merge", "topic"]);
+    assert!(stderr(&output).contains("MERGE_HEAD exists"));
+    let output = cs01(root, &["commit", "-m", "Too soon"]);
+    assert!(stderr(&output).contains("'lines.txt' is unmerged"));
+
+    fs::write(root.join("lines.txt"), "1\n2\nboth\n4\n5\n").unwrap();
+    run(root, &["add", "lines.txt"]);
+    let status = run(root, &["status"]);
+    assert!(
+        status.contains("All conflicts fixed but you are still merging."),
+        "{}",
+        status
+    );
+    run(root, &["commit", "-m", "Merge topic"]);
+
+    assert_eq!(rev_parse(root, "HEAD^1"), ours);
+    assert_eq!(rev_parse(root, "HEAD^2"), theirs);
+    assert!(!root.join(".CS01/MERGE_HEAD").exists());
+    assert!(!root.join(".CS01/MERGE_MSG").exists());
+    assert!(
+        run(root, &["reflog"])
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(" HEAD@{0}: commit (merge): Merge topic")
+    );
+    assert!(run(root, &["status"]).ends_with("nothing to commit, working tree clean\n"));
+}
+
+#[test]
+fn test_merge_refuses_to_overwrite_local_changes() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    forked_repo(root);
+    commit(root, &[("other.txt", "other\n")], "Add other.txt");
+    run(root, &["switch", "topic"]);
+    commit(root, &[("lines.txt", "1\n2\n3\n4\nfive\n")], "Change 5");
+    run(root, &["switch", "main"]);
+
+    fs::write(root.join("lines.txt"), "local\n").unwrap();
+    let output = cs01(root, &["merge", "topic"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains(
+        "Your local changes to the following files would be overwritten by merge:\n\tlines.txt\n\
+         Please commit your changes or stash them before you merge."
+    ));
+    assert_eq!(read(root, "lines.txt"), "local\n");
+    assert!(!root.join(".CS01/ORIG_HEAD").exists());
+}
+
+#[test]
+fn test_merge_of_huge_rewrites_degrades_to_one_conflict() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let lines =
+        |tag: &str| -> String { (0..100_000).map(|i| format!("{} {}\n", tag, i)).collect() };
+    run(root, &["init", "-q"]);
+    commit(root, &[("big.txt", &lines("base"))], "Base");
+    run(root, &["branch", "topic"]);
+    commit(root, &[("big.txt", &lines("ours"))], "Rewrite on main");
+    run(root, &["switch", "topic"]);
+    commit(root, &[("big.txt", &lines("theirs"))], "Rewrite on topic");
+    run(root, &["switch", "main"]);
+
+    let started = std::time::Instant::now();
+    let output = cs01(root, &["merge", "topic"]);
+    assert!(started.elapsed() < std::time::Duration::from_secs(60));
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).starts_with(
+            "warning: too many changed lines to merge big.txt line by line; raise merge.maxChangedLines to merge it that way\nError: Automatic merge failed"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        read(root, "big.txt"),
+        format!(
+            "<<<<<<< HEAD\n{}=======\n{}>>>>>>> topic\n",
+            lines("ours"),
+            lines("theirs")
+        )
+    );
+    assert!(stdout(&output).contains("CONFLICT (content): Merge conflict in big.txt"));
+}
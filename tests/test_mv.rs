@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stderr, stdout};
+
+/// The status of the working tree: staged changes as `change path`, then whether the
+/// working tree matches the index.
+fn status(root: &Path) -> (Vec<String>, bool) {
+    let output = cs01(root, &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let staged = value["staged"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            format!(
+                "{} {}",
+                c["change"].as_str().unwrap(),
+                c["path"].as_str().unwrap()
+            )
+        })
+        .collect();
+    let clean =
+        value["unstaged"] == serde_json::json!([]) && value["untracked"] == serde_json::json!([]);
+    (staged, clean)
+}
+
+/// A working tree with README.md, docs/guide.md and src/{lib.rs,util/mod.rs} committed.
+fn committed_tree(dir: &Path) -> PathBuf {
+    let root = dir.join("work");
+    fs::create_dir_all(root.join("src/util")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    for path in [
+        "README.md",
+        "docs/guide.md",
+        "src/lib.rs",
+        "src/util/mod.rs",
+    ] {
+        fs::write(root.join(path), format!("{}\n", path)).unwrap();
+    }
+    assert!(cs01(&root, &["add", "."]).status.success());
+    let output = cs01(&root, &["commit", "-m", "Initial"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    root
+}
+
+#[test]
+fn test_mv_renames_files_and_directories() {
+    let dir = tempdir().unwrap();
+    let root = committed_tree(dir.path());
+
+    // Across directories, from a subdirectory
+    let output = cs01(
+        &root.join("docs"),
+        &["mv", "-v", "guide.md", "../src/guide.md"],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "Renaming docs/guide.md to src/guide.md\n");
+    assert!(!root.join("docs/guide.md").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("src/guide.md")).unwrap(),
+        "docs/guide.md\n"
+    );
+    assert_eq!(
+        status(&root),
+        (
+            vec![
+                "deleted docs/guide.md".to_string(),
+                "added src/guide.md".to_string()
+            ],
+            true
+        )
+    );
+
+    // Into an existing directory, several at once
+    fs::create_dir(root.join("misc")).unwrap();
+    let output = cs01(&root, &["--json", "mv", "README.md", "src/util", "misc"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["renamed"][1]["from"], "src/util");
+    assert_eq!(value["renamed"][1]["to"], "misc/util");
+    assert!(root.join("misc/util/mod.rs").is_file());
+    let (staged, clean) = status(&root);
+    assert!(clean);
+    assert!(staged.contains(&"added misc/README.md".to_string()));
+    assert!(staged.contains(&"added misc/util/mod.rs".to_string()));
+    assert!(staged.contains(&"deleted src/util/mod.rs".to_string()));
+
+    // A directory renamed outright
+    let output = cs01(&root, &["mv", "misc", "other"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(root.join("other/util/mod.rs").is_file());
+    assert!(status(&root).1);
+}
+
+#[test]
+fn test_mv_refuses_bad_moves() {
+    let dir = tempdir().unwrap();
+    let root = committed_tree(dir.path());
+    fs::write(root.join("untracked.txt"), "new\n").unwrap();
+
+    for (args, expected) in [
+        (
+            &["mv", "nope", "x"][..],
+            "bad source, source=nope, destination=x",
+        ),
+        (&["mv", "untracked.txt", "x"], "not under version control"),
+        (&["mv", "README.md", "src/lib.rs"], "destination exists"),
+        (&["mv", "README.md", "untracked.txt"], "destination exists"),
+        (
+            &["mv", "README.md", "no/dir/x"],
+            "destination directory does not exist",
+        ),
+        (
+            &["mv", "src", "src/inner"],
+            "can not move directory into itself",
+        ),
+        (
+            &["mv", "README.md", "docs/guide.md", "x"],
+            "is not a directory",
+        ),
+        (&["mv", "README.md"], "required"),
+    ] {
+        let output = cs01(&root, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+    }
+    assert_eq!(status(&root), (Vec::new(), false));
+
+    // -f replaces a tracked destination file
+    let output = cs01(&root, &["mv", "-f", "README.md", "src/lib.rs"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+        "README.md\n"
+    );
+    let (staged, _) = status(&root);
+    assert_eq!(staged, ["deleted README.md", "modified src/lib.rs"]);
+}
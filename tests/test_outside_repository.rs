@@ -0,0 +1,121 @@
+use std::fs;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stderr, stdout};
+
+const NOT_A_REPOSITORY: &str =
+    "fatal: not a CS01 repository (or any of the parent directories): .CS01";
+const HINT: &str = "hint: use \"cs01 init\" to create one";
+
+#[test]
+fn test_help_lists_the_commands_that_work_outside_a_repository() {
+    let dir = tempdir().unwrap();
+
+    let output = cs01(dir.path(), &["--help"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(
+        "Outside a repository, only these commands work: init, clone, \
+         hash-object (without -w), config (reads outside the repository scope), help."
+    ));
+
+    let output = cs01(dir.path(), &["--version"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("CS01 "));
+    assert!(cs01(dir.path(), &["help", "log"]).status.success());
+}
+
+#[test]
+fn test_listed_commands_work_outside_a_repository() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("hello.txt"), "hello\n").unwrap();
+
+    let output = cs01(root, &["hash-object", "hello.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // An unset key, not a missing repository
+    let output = cs01(root, &["config", "core.bare"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let output = cs01(root, &["init", "-q", "source"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = cs01(root, &["clone", "source", "copy"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn test_other_commands_fail_outside_a_repository() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("hello.txt"), "hello\n").unwrap();
+
+    let start = std::path::absolute(root).unwrap();
+    for args in [
+        &["cat-file", "-p", "HEAD"][..],
+        &["write-tree"],
+        &["commit-tree", "HEAD^{tree}", "-m", "x"],
+        &["update-ref", "refs/heads/x", "HEAD"],
+        &["symbolic-ref", "HEAD"],
+        &["pack-refs"],
+        &["rev-parse", "HEAD"],
+        &["add", "hello.txt"],
+        &["status"],
+        &["diff"],
+        &["ls-files"],
+        &["check-ignore", "hello.txt"],
+        &["commit", "-m", "x"],
+        &["branch"],
+        &["rm", "hello.txt"],
+        &["mv", "hello.txt", "bye.txt"],
+        &["switch", "main"],
+        &["reset"],
+        &["merge", "main"],
+        &["log"],
+        &["reflog"],
+        &["stash"],
+        // Only the listed exceptions of the listed commands need one
+        &["hash-object", "-w", "hello.txt"],
+        &["config", "--local", "core.bare"],
+    ] {
+        let output = cs01(root, args);
+        assert_eq!(
+            output.status.code(),
+            Some(4),
+            "{:?}: {}",
+            args,
+            stderr(&output)
+        );
+        assert_eq!(stdout(&output), "", "{:?}", args);
+        assert_eq!(
+            stderr(&output),
+            format!(
+                "{} (searched from '{}')\n{}\n",
+                NOT_A_REPOSITORY,
+                start.display(),
+                HINT
+            ),
+            "{:?}",
+            args
+        );
+    }
+    // Nothing was created along the way
+    assert_eq!(fs::read_dir(root).unwrap().count(), 1);
+}
+
+#[test]
+fn test_init_hint_only_where_there_is_something_to_track() {
+    let dir = tempdir().unwrap();
+
+    let output = cs01(dir.path(), &["status"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).starts_with(NOT_A_REPOSITORY));
+    assert!(!stderr(&output).contains("hint:"));
+
+    let output = cs01(dir.path(), &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stderr(&output)).unwrap();
+    assert!(
+        value["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("not a CS01 repository")
+    );
+}
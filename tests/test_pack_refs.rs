@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stderr};
+
+/// Runs cs01 and returns its output without the final newline; it must be a success.
+fn run(root: &Path, args: &[&str]) -> String {
+    common::run(root, args).trim_end().to_string()
+}
+
+/// A repository with commits `first` and `second` on `main`, a branch `topic` at
+/// `first`, and an annotated tag `v1` for `first`.
+fn repo_with_refs(root: &Path) -> (String, String, String) {
+    run(root, &["init", "-q"]);
+    let tree = run(root, &["write-tree"]);
+    let first = run(root, &["commit-tree", &tree, "-m", "first"]);
+    let second = run(root, &["commit-tree", &tree, "-p", &first, "-m", "second"]);
+    run(root, &["update-ref", "refs/heads/main", &second]);
+    run(root, &["update-ref", "refs/heads/topic", &first]);
+
+    let tag = format!(
+        "object {}\ntype commit\ntag v1\ntagger C O Mitter <committer@example.com> 1700000100 -0700\n\nv1\n",
+        first
+    );
+    fs::write(root.join("tag.txt"), tag).unwrap();
+    let tag = run(root, &["hash-object", "-t", "tag", "-w", "tag.txt"]);
+    fs::write(root.join(".CS01/refs/tags/v1"), format!("{}\n", tag)).unwrap();
+    (first, second, tag)
+}
+
+#[test]
+fn test_pack_refs_moves_loose_refs() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, second, tag) = repo_with_refs(root);
+
+    // By default only tags are packed, with their peeled commit
+    assert_eq!(run(root, &["pack-refs"]), "");
+    assert!(!root.join(".CS01/refs/tags/v1").exists());
+    assert!(root.join(".CS01/refs/heads/main").exists());
+    assert_eq!(
+        run(root, &["--json", "pack-refs", "--all"]),
+        r#"{"packed":["refs/heads/main","refs/heads/topic"]}"#
+    );
+    assert_eq!(
+        fs::read_to_string(root.join(".CS01/packed-refs")).unwrap(),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted \n\
+             {second} refs/heads/main\n\
+             {first} refs/heads/topic\n\
+             {tag} refs/tags/v1\n\
+             ^{first}\n"
+        )
+    );
+    assert!(!root.join(".CS01/refs/heads/main").exists());
+    assert!(root.join(".CS01/refs/heads").is_dir());
+
+    // Packed refs resolve and list as before
+    assert_eq!(
+        run(root, &["rev-parse", "main", "topic", "v1"]),
+        format!("{second}\n{first}\n{tag}")
+    );
+    assert_eq!(
+        run(root, &["log", "-n", "1"]).lines().next().unwrap(),
+        format!("commit {second}")
+    );
+    assert_eq!(run(root, &["branch"]), "* main\n  topic");
+
+    // A loose value wins over the packed one
+    run(root, &["update-ref", "refs/heads/main", &first, &second]);
+    assert_eq!(run(root, &["rev-parse", "main"]), first);
+
+    // Deleting a packed-only ref takes it out of packed-refs
+    run(root, &["update-ref", "-d", "refs/heads/topic"]);
+    let output = cs01(root, &["rev-parse", "--verify", "topic"]);
+    assert!(!output.status.success());
+    let packed = fs::read_to_string(root.join(".CS01/packed-refs")).unwrap();
+    assert!(!packed.contains("refs/heads/topic"), "{}", packed);
+
+    // So does deleting one that is both loose and packed
+    run(root, &["update-ref", "-d", "refs/heads/main"]);
+    assert!(
+        !cs01(root, &["rev-parse", "--verify", "main"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_reads_packed_refs_written_by_git() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, second, tag) = repo_with_refs(root);
+    for name in ["heads/main", "heads/topic", "tags/v1"] {
+        fs::remove_file(root.join(".CS01/refs").join(name)).unwrap();
+    }
+    // As `git pack-refs --all` writes it
+    fs::write(
+        root.join(".CS01/packed-refs"),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted \n\
+             {second} refs/heads/main\n\
+             {first} refs/heads/topic\n\
+             {tag} refs/tags/v1\n\
+             ^{first}\n"
+        ),
+    )
+    .unwrap();
+
+    assert_eq!(run(root, &["rev-parse", "HEAD~1"]), first);
+    assert_eq!(run(root, &["rev-parse", "v1"]), tag);
+    let output = cs01(root, &["branch", "-d", "topic"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(run(root, &["branch"]), "* main");
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, run, stderr, stdout};
+
+/// Commits `file.txt` holding `content`, returning the new commit's id.
+fn commit(root: &Path, content: &str, message: &str) -> String {
+    fs::write(root.join("file.txt"), content).unwrap();
+    run(root, &["add", "file.txt"]);
+    run(root, &["commit", "-m", message]);
+    run(root, &["rev-parse", "HEAD"]).trim_end().to_string()
+}
+
+#[test]
+fn test_reflog_after_commits_and_a_reset() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    let first = commit(root, "1\n", "First");
+    let second = commit(root, "2\n", "Second");
+    let third = commit(root, "3\n", "Third");
+    run(root, &["reset", "--hard", "HEAD~2"]);
+
+    let expected = format!(
+        "{} HEAD@{{0}}: reset: moving to HEAD~2\n\
+         {} HEAD@{{1}}: commit: Third\n\
+         {} HEAD@{{2}}: commit: Second\n\
+         {} HEAD@{{3}}: commit (initial): First\n",
+        &first[..7],
+        &third[..7],
+        &second[..7],
+        &first[..7],
+    );
+    assert_eq!(run(root, &["reflog"]), expected);
+    assert_eq!(
+        run(root, &["reflog", "main"]),
+        expected.replace("HEAD@", "main@")
+    );
+
+    // Entries resolve as revisions, with suffixes on top
+    assert_eq!(
+        run(
+            root,
+            &["rev-parse", "HEAD@{1}", "main@{2}", "@{1}~1", "HEAD@{0}"]
+        ),
+        format!("{third}\n{second}\n{second}\n{first}\n")
+    );
+    run(root, &["reset", "--hard", "HEAD@{1}"]);
+    assert_eq!(fs::read_to_string(root.join("file.txt")).unwrap(), "3\n");
+
+    let output = cs01(root, &["rev-parse", "main@{9}"]);
+    assert_eq!(output.status.code(), Some(128));
+    assert!(stderr(&output).contains("log for 'main' only has 5 entries"));
+
+    let output = cs01(root, &["--json", "reflog"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["entries"][1]["selector"], "HEAD@{1}");
+    assert_eq!(value["entries"][1]["old"], third);
+    assert_eq!(value["entries"][1]["new"], first);
+    assert_eq!(value["entries"][4]["old"], "0".repeat(40));
+}
+
+#[test]
+fn test_deleted_branch_keeps_its_reflog() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    commit(root, "1\n", "First");
+    run(root, &["branch", "topic"]);
+    assert_eq!(run(root, &["reflog", "topic"]).lines().count(), 1);
+
+    run(root, &["branch", "-d", "topic"]);
+    assert!(!root.join(".CS01/logs/refs/heads/topic").exists());
+    let aside = fs::read_to_string(root.join(".CS01/logs/deleted/refs/heads/topic")).unwrap();
+    assert!(
+        aside.ends_with("\tbranch: Created from HEAD\n"),
+        "{}",
+        aside
+    );
+    let output = cs01(root, &["reflog", "topic"]);
+    assert_eq!(output.status.code(), Some(128));
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{commit_all, cs01, read, rev_parse, stderr, stdout};
+
+/// The staged, unstaged and untracked lists of `status --json`.
+fn status(root: &Path) -> serde_json::Value {
+    let output = cs01(root, &["--json", "status"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    serde_json::json!([value["staged"], value["unstaged"], value["untracked"]])
+}
+
+/// A repository with two commits: the second changes `a.txt`, deletes `gone.txt` and
+/// adds `dir/new.txt`.
+fn two_commits(dir: &Path) -> (std::path::PathBuf, String, String) {
+    let root = dir.join("work");
+    fs::create_dir_all(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join("a.txt"), "one\n").unwrap();
+    fs::write(root.join("gone.txt"), "gone\n").unwrap();
+    let first = commit_all(&root, "First");
+    fs::write(root.join("a.txt"), "two\n").unwrap();
+    fs::remove_file(root.join("gone.txt")).unwrap();
+    fs::create_dir(root.join("dir")).unwrap();
+    fs::write(root.join("dir/new.txt"), "new\n").unwrap();
+    let second = commit_all(&root, "Second");
+    (root, first, second)
+}
+
+#[test]
+fn test_reset_back_and_forth_between_two_commits() {
+    let dir = tempdir().unwrap();
+    let (root, first, second) = two_commits(dir.path());
+    let clean = serde_json::json!([[], [], []]);
+
+    // --soft moves only the branch: the second commit's changes are left staged
+    let output = cs01(&root, &["reset", "--soft", "HEAD~1"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(rev_parse(&root, "main"), first);
+    assert_eq!(rev_parse(&root, "ORIG_HEAD"), second);
+    assert_eq!(
+        status(&root),
+        serde_json::json!([
+            [
+                { "path": "a.txt", "change": "modified" },
+                { "path": "dir/new.txt", "change": "added" },
+                { "path": "gone.txt", "change": "deleted" },
+            ],
+            [],
+            [],
+        ])
+    );
+
+    // Back to the second commit, then --mixed unstages them too
+    assert!(cs01(&root, &["reset", "--soft", &second]).status.success());
+    assert_eq!(status(&root), clean);
+    let output = cs01(&root, &["reset", &first]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "Unstaged changes after reset:\nM\ta.txt\nD\tgone.txt\n"
+    );
+    assert_eq!(rev_parse(&root, "HEAD"), first);
+    assert_eq!(
+        status(&root),
+        serde_json::json!([
+            [],
+            [
+                { "path": "a.txt", "change": "modified" },
+                { "path": "gone.txt", "change": "deleted" },
+            ],
+            ["dir/"],
+        ])
+    );
+    assert_eq!(read(&root, "a.txt"), "two\n");
+
+    // --hard to the second commit needs nothing but the index; back to the first
+    // rewrites the working tree, dropping local changes
+    let output = cs01(&root, &["reset", "--hard", "ORIG_HEAD"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!("HEAD is now at {} Second\n", &second[..7])
+    );
+    assert_eq!(status(&root), clean);
+    fs::write(root.join("a.txt"), "local\n").unwrap();
+    let output = cs01(&root, &["reset", "--hard", &first]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "Updated working tree files:\n\ta.txt\n\tdir/new.txt\n\tgone.txt\nHEAD is now at {} First\n",
+            &first[..7]
+        )
+    );
+    assert_eq!(read(&root, "a.txt"), "one\n");
+    assert_eq!(read(&root, "gone.txt"), "gone\n");
+    assert!(!root.join("dir").exists());
+    assert_eq!(status(&root), clean);
+
+    let output = cs01(&root, &["reset", "--hard", &second]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(read(&root, "dir/new.txt"), "new\n");
+    assert!(!root.join("gone.txt").exists());
+    assert_eq!(status(&root), clean);
+
+    // Every move is in the reflogs
+    let log = read(&root, ".CS01/logs/refs/heads/main");
+    let moves: Vec<&str> = log
+        .lines()
+        .filter_map(|line| line.split_once('\t').map(|(_, message)| message))
+        .collect();
+    assert_eq!(
+        moves,
+        [
+            "commit (initial): First".to_string(),
+            "commit: Second".to_string(),
+            "reset: moving to HEAD~1".to_string(),
+            format!("reset: moving to {}", second),
+            format!("reset: moving to {}", first),
+            "reset: moving to ORIG_HEAD".to_string(),
+            format!("reset: moving to {}", first),
+            format!("reset: moving to {}", second),
+        ]
+    );
+    assert_eq!(read(&root, ".CS01/logs/HEAD"), log);
+}
+
+#[test]
+fn test_reset_paths_unstages_files() {
+    let dir = tempdir().unwrap();
+    let (root, _, second) = two_commits(dir.path());
+    fs::write(root.join("a.txt"), "three\n").unwrap();
+    fs::write(root.join("dir/new.txt"), "changed\n").unwrap();
+    fs::write(root.join("added.txt"), "added\n").unwrap();
+    assert!(cs01(&root, &["add", "-A"]).status.success());
+
+    // A path that isn't a commit is a path; the commit may be named before `--`
+    let output = cs01(&root.join("dir"), &["reset", "new.txt", "../added.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "Unstaged changes after reset:\nM\tdir/new.txt\n"
+    );
+    assert_eq!(
+        status(&root),
+        serde_json::json!([
+            [{ "path": "a.txt", "change": "modified" }],
+            [{ "path": "dir/new.txt", "change": "modified" }],
+            ["added.txt"],
+        ])
+    );
+    let output = cs01(&root, &["--json", "reset", "HEAD", "--", "a.txt", "dir"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "{\"reset\":[\"a.txt\"]}\n");
+    assert_eq!(read(&root, "a.txt"), "three\n");
+    assert_eq!(rev_parse(&root, "HEAD"), second);
+    assert!(!root.join(".CS01/ORIG_HEAD").exists());
+
+    let output = cs01(&root, &["reset", "--hard", "HEAD", "--", "a.txt"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Cannot do hard reset with paths."));
+}
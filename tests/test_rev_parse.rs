@@ -0,0 +1,184 @@
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, cs01_with_stdin, stdout, unreferenced_commits};
+
+#[test]
+fn test_rev_parse_revisions() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, second) = unreferenced_commits(root);
+
+    // Nothing to resolve on the unborn branch yet
+    let output = cs01(root, &["rev-parse", "HEAD"]);
+    assert_eq!(output.status.code(), Some(128));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown revision 'HEAD'"));
+
+    assert!(
+        cs01(root, &["update-ref", "HEAD", &second])
+            .status
+            .success()
+    );
+    assert!(
+        cs01(root, &["update-ref", "refs/tags/v1", &first])
+            .status
+            .success()
+    );
+
+    let output = cs01(
+        root,
+        &[
+            "rev-parse",
+            "HEAD",
+            "@",
+            "main~1",
+            "HEAD^",
+            "v1",
+            &second[..7],
+            "main^0",
+        ],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        stdout(&output),
+        format!("{second}\n{second}\n{first}\n{first}\n{first}\n{second}\n{second}\n")
+    );
+
+    // Interleaved with the repository facts, in the same order
+    let output = cs01(root, &["rev-parse", "HEAD", "--is-bare-repository", "v1"]);
+    assert_eq!(stdout(&output), format!("{second}\nfalse\n{first}\n"));
+
+    let output = cs01(root, &["--json", "rev-parse", "main~1"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["ids"], serde_json::json!([first]));
+
+    // One bad revision fails the whole command, printing nothing
+    for revision in ["main~2", "HEAD^2", "nope", "abcd"] {
+        let output = cs01(root, &["rev-parse", "HEAD", revision]);
+        assert_eq!(output.status.code(), Some(128), "{}", revision);
+        assert!(output.stdout.is_empty(), "{}", revision);
+    }
+}
+
+#[test]
+fn test_rev_parse_ambiguous_abbreviation() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    // f0acb599... and f0acbc2e... share their first five digits
+    for content in [&b"1071"[..], b"1251"] {
+        assert!(
+            cs01_with_stdin(root, &["hash-object", "-w", "--stdin"], content)
+                .status
+                .success()
+        );
+    }
+
+    let output = cs01(root, &["rev-parse", "f0acb"]);
+    assert_eq!(output.status.code(), Some(128));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("short object ID f0acb is ambiguous"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("f0acb59936 blob"), "{}", stderr);
+    assert!(stderr.contains("f0acbc2e3f blob"), "{}", stderr);
+
+    let output = cs01(root, &["rev-parse", "f0acbc"]);
+    assert_eq!(
+        stdout(&output),
+        "f0acbc2e3f4e89593f20f0cb958cb3c05ed4deaf\n"
+    );
+}
+
+#[test]
+fn test_rev_parse_introspection() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let work = root.join("work");
+    std::fs::create_dir_all(work.join("src")).unwrap();
+    assert!(cs01(&work, &["init", "-q"]).status.success());
+
+    let args = [
+        "rev-parse",
+        "--cs01-dir",
+        "--show-toplevel",
+        "--is-bare-repository",
+        "--is-inside-work-tree",
+    ];
+    let output = cs01(&work.join("src"), &args);
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "{}\n{}\nfalse\ntrue\n",
+            work.join(".CS01").display(),
+            work.display()
+        )
+    );
+
+    // Where the current directory is, and the way back up
+    std::fs::create_dir_all(work.join("src/deep")).unwrap();
+    let args = ["rev-parse", "--show-prefix", "--show-cdup"];
+    assert_eq!(stdout(&cs01(&work, &args)), "\n\n");
+    assert_eq!(stdout(&cs01(&work.join("src"), &args)), "src/\n../\n");
+    assert_eq!(
+        stdout(&cs01(&work.join("src/deep"), &args)),
+        "src/deep/\n../../\n"
+    );
+
+    // Answers come in the order they were asked for, revisions included
+    let args = [
+        "rev-parse",
+        "--show-prefix",
+        "--show-cdup",
+        "--show-toplevel",
+        "--is-bare-repository",
+    ];
+    assert_eq!(
+        stdout(&cs01(&work.join("src"), &args)),
+        format!("src/\n../\n{}\nfalse\n", work.display())
+    );
+    let args = [
+        "rev-parse",
+        "--show-cdup",
+        "--show-toplevel",
+        "--show-prefix",
+    ];
+    assert_eq!(
+        stdout(&cs01(&work.join("src"), &args)),
+        format!("../\n{}\nsrc/\n", work.display())
+    );
+    let output = cs01(
+        &work.join("src/deep"),
+        &["--json", "rev-parse", "--show-prefix"],
+    );
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["prefix"], "src/deep/");
+
+    // The metadata directory isn't part of the working tree
+    let output = cs01(&work.join(".CS01"), &["rev-parse", "--is-inside-work-tree"]);
+    assert_eq!(stdout(&output), "false\n");
+
+    let bare = root.join("bare.cs01");
+    assert!(
+        cs01(&root, &["init", "-q", "--bare", "bare.cs01"])
+            .status
+            .success()
+    );
+    let output = cs01(
+        &bare,
+        &["rev-parse", "--is-bare-repository", "--is-inside-work-tree"],
+    );
+    assert_eq!(stdout(&output), "true\nfalse\n");
+    let output = cs01(&bare, &["rev-parse", "--show-toplevel"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be run in a work tree"));
+
+    let output = cs01(&root, &["rev-parse", "--is-bare-repository"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stderr, stdout};
+
+/// The paths `status` reports as staged, with their change.
+fn staged(root: &Path) -> Vec<(String, String)> {
+    let output = cs01(root, &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    value["staged"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            (
+                c["path"].as_str().unwrap().into(),
+                c["change"].as_str().unwrap().into(),
+            )
+        })
+        .collect()
+}
+
+/// A working tree with a.txt, b.txt and src/{lib.rs,main.rs} committed.
+fn committed_tree(dir: &Path) -> PathBuf {
+    let root = dir.join("work");
+    fs::create_dir_all(root.join("src")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    for path in ["a.txt", "b.txt", "src/lib.rs", "src/main.rs"] {
+        fs::write(root.join(path), format!("{}\n", path)).unwrap();
+    }
+    assert!(cs01(&root, &["add", "."]).status.success());
+    let output = cs01(&root, &["commit", "-m", "Initial"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    root
+}
+
+#[test]
+fn test_rm_removes_from_index_and_disk() {
+    let dir = tempdir().unwrap();
+    let root = committed_tree(dir.path());
+
+    let output = cs01(&root, &["rm", "a.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "rm 'a.txt'\n");
+    assert!(!root.join("a.txt").exists());
+    assert_eq!(staged(&root), [("a.txt".into(), "deleted".into())]);
+
+    // --cached keeps the file, which is now untracked
+    let output = cs01(&root, &["rm", "--cached", "b.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "b.txt\n");
+    let output = cs01(&root, &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["untracked"], serde_json::json!(["b.txt"]));
+
+    // Directories need -r, and paths are taken from the current directory
+    let output = cs01(&root.join("src"), &["rm", "."]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("not removing '.' recursively without -r"),
+        "{}",
+        stderr(&output)
+    );
+    let output = cs01(&root.join("src"), &["--json", "rm", "-r", "."]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(
+        value["removed"],
+        serde_json::json!(["src/lib.rs", "src/main.rs"])
+    );
+    assert!(!root.join("src").exists());
+
+    let output = cs01(&root, &["rm", "nope.txt"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("pathspec 'nope.txt' did not match any files"));
+    assert!(!root.join(".CS01/index.lock").exists());
+}
+
+#[test]
+fn test_rm_refuses_to_lose_changes() {
+    let dir = tempdir().unwrap();
+    let root = committed_tree(dir.path());
+
+    // An unstaged modification
+    fs::write(root.join("a.txt"), "changed\n").unwrap();
+    let output = cs01(&root, &["rm", "a.txt"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains(
+            "the following file has local modifications:\n    a.txt\n\
+             (use --cached to keep the file, or -f to force removal)"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "changed\n");
+    assert_eq!(staged(&root), []);
+
+    // A staged one, and one different from both HEAD and the file
+    fs::write(root.join("b.txt"), "staged\n").unwrap();
+    assert!(cs01(&root, &["add", "b.txt"]).status.success());
+    let output = cs01(&root, &["rm", "b.txt"]);
+    assert!(
+        stderr(&output)
+            .contains("the following file has changes staged in the index:\n    b.txt\n")
+    );
+    fs::write(root.join("b.txt"), "staged, then changed\n").unwrap();
+    let output = cs01(&root, &["rm", "--cached", "a.txt", "b.txt"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains(
+            "the following file has staged content different from both the file and the HEAD:\n    b.txt\n"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!stderr(&output).contains("a.txt"));
+
+    // Nothing was removed; -f removes anyway
+    assert_eq!(staged(&root), [("b.txt".into(), "modified".into())]);
+    let output = cs01(&root, &["rm", "-f", "-q", "a.txt", "b.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert!(!root.join("a.txt").exists() && !root.join("b.txt").exists());
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use tempfile::tempdir;
+
+mod common;
+use common::{commit, cs01, read, rev_parse, run, stderr, stdout};
+
+#[test]
+fn test_stash_push_then_pop_onto_a_new_commit() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    let base = commit(root, &[("a.txt", "1\n2\n3\n")], "Base");
+
+    fs::write(root.join("a.txt"), "1\n2\nthree\n").unwrap();
+    fs::write(root.join("b.txt"), "new\n").unwrap();
+    run(root, &["add", "b.txt"]);
+    fs::write(root.join("untracked.txt"), "untracked\n").unwrap();
+    assert_eq!(
+        run(root, &["stash", "push", "-m", "work"]),
+        "Saved working directory and index state On main: work\n"
+    );
+    assert_eq!(read(root, "a.txt"), "1\n2\n3\n");
+    assert!(!root.join("b.txt").exists());
+    assert_eq!(read(root, "untracked.txt"), "untracked\n");
+    assert_eq!(run(root, &["stash", "list"]), "stash@{0}: On main: work\n");
+
+    // The entry is a commit of the working tree on top of HEAD and the index
+    let stash = rev_parse(root, "stash@{0}");
+    assert_eq!(rev_parse(root, "refs/stash"), stash);
+    assert_eq!(rev_parse(root, "stash^1"), base);
+    let index_commit = run(root, &["cat-file", "-p", "stash^2"]);
+    assert!(index_commit.ends_with(&format!("\n\nindex on main: {} Base\n", &base[..7])));
+
+    commit(root, &[("a.txt", "one\n2\n3\n")], "Change 1");
+    assert_eq!(
+        run(root, &["stash", "pop"]),
+        format!("Auto-merging a.txt\nDropped refs/stash@{{0}} ({})\n", stash)
+    );
+    assert_eq!(read(root, "a.txt"), "one\n2\nthree\n");
+    assert_eq!(read(root, "b.txt"), "new\n");
+    let status = run(root, &["status"]);
+    assert!(
+        status.contains("Changes to be committed:\n\tnew file:   b.txt\n"),
+        "{}",
+        status
+    );
+    assert!(status.contains("\tmodified:   a.txt\n"), "{}", status);
+    assert_eq!(run(root, &["stash", "list"]), "");
+    assert!(!root.join(".CS01/refs/stash").exists());
+    assert!(!root.join(".CS01/logs/refs/stash").exists());
+}
+
+#[test]
+fn test_stash_with_nothing_to_save() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    commit(root, &[("a.txt", "a\n")], "Base");
+    fs::write(root.join("untracked.txt"), "untracked\n").unwrap();
+
+    assert_eq!(run(root, &["stash"]), "No local changes to save\n");
+    assert!(!root.join(".CS01/refs/stash").exists());
+    let output = cs01(root, &["stash", "pop"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("No stash entries found."));
+}
+
+#[test]
+fn test_conflicting_pop_keeps_the_entry() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    commit(root, &[("a.txt", "1\n2\n3\n")], "Base");
+    fs::write(root.join("a.txt"), "1\n2\nstashed\n").unwrap();
+    run(root, &["stash"]);
+    commit(root, &[("a.txt", "1\n2\ncommitted\n")], "Change 3");
+
+    let output = cs01(root, &["stash", "pop"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "Auto-merging a.txt\nCONFLICT (content): Merge conflict in a.txt\n"
+    );
+    assert!(stderr(&output).contains("The stash entry is kept in case you need it again."));
+    assert_eq!(
+        read(root, "a.txt"),
+        "1\n2\n<<<<<<< Updated upstream\ncommitted\n=======\nstashed\n>>>>>>> Stashed changes\n"
+    );
+    assert_eq!(run(root, &["stash", "list"]).lines().count(), 1);
+    assert!(run(root, &["status"]).contains("\tboth modified:   a.txt\n"));
+}
+
+#[test]
+fn test_stash_stack_and_untracked_files() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    let base = commit(root, &[("a.txt", "a\n")], "Base");
+
+    fs::write(root.join("a.txt"), "first\n").unwrap();
+    run(root, &["stash"]);
+    fs::write(root.join("untracked.txt"), "untracked\n").unwrap();
+    run(root, &["stash", "push", "-u"]);
+    assert!(!root.join("untracked.txt").exists());
+    assert_eq!(read(root, "a.txt"), "a\n");
+    let wip = format!("WIP on main: {} Base", &base[..7]);
+    assert_eq!(
+        run(root, &["stash", "list"]),
+        format!("stash@{{0}}: {}\nstash@{{1}}: {}\n", wip, wip)
+    );
+
+    // Untracked files come back untracked, and an older entry can go first
+    run(root, &["stash", "apply"]);
+    assert_eq!(read(root, "untracked.txt"), "untracked\n");
+    assert!(run(root, &["status"]).contains("Untracked files:"));
+    let output = cs01(root, &["stash", "apply"]);
+    assert!(stderr(&output).contains("untracked.txt already exists, no checkout"));
+    fs::remove_file(root.join("untracked.txt")).unwrap();
+
+    let newest = rev_parse(root, "stash@{0}");
+    run(root, &["stash", "pop", "stash@{1}"]);
+    assert_eq!(read(root, "a.txt"), "first\n");
+    assert_eq!(rev_parse(root, "refs/stash"), newest);
+    run(root, &["stash", "drop"]);
+    assert_eq!(run(root, &["stash", "list"]), "");
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::Stdio;
+use tempfile::tempdir;
+
+mod common;
+use common::{commit, cs01, cs01_command, run, stdout};
+
+fn status(dir: &Path) -> String {
+    let output = cs01(dir, &["status"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout(&output)
+}
+
+/// Commits the working tree (which the tests keep equal to the index) with plumbing.
+fn commit_work_tree(root: &Path) {
+    let tree = stdout(&cs01(root, &["write-tree"])).trim_end().to_string();
+    let commit = stdout(&cs01(root, &["commit-tree", &tree, "-m", "snapshot"]));
+    assert!(
+        cs01(root, &["update-ref", "HEAD", commit.trim_end()])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_status_before_the_first_commit() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+
+    assert_eq!(
+        status(&root),
+        "No commits yet on main\n\n\
+         nothing to commit (create/copy files and use \"cs01 add\" to track)\n"
+    );
+
+    fs::create_dir_all(root.join("src/deep")).unwrap();
+    fs::create_dir_all(root.join("empty/inner")).unwrap();
+    fs::create_dir_all(root.join("logs")).unwrap();
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("src/deep/x.rs"), "x\n").unwrap();
+    fs::write(root.join("build.log"), "noise\n").unwrap();
+    fs::write(root.join("logs/today"), "noise\n").unwrap();
+    fs::write(root.join(".cs01ignore"), "build.log\n").unwrap();
+    let mut exclude = fs::read_to_string(root.join(".CS01/info/exclude")).unwrap();
+    exclude.push_str("logs\n");
+    fs::write(root.join(".CS01/info/exclude"), exclude).unwrap();
+
+    // Untracked directories collapse; ignored and empty ones, and .CS01, are left out.
+    // Without a terminal there are no colors.
+    assert_eq!(
+        status(&root),
+        "No commits yet on main\n\n\
+         Untracked files:\n  (use \"cs01 add <file>...\" to include in what will be committed)\n\
+         \t.cs01ignore\n\ta.txt\n\tsrc/\n\n\
+         nothing added to commit but untracked files present (use \"cs01 add\" to track)\n"
+    );
+
+    assert!(
+        cs01(&root, &["add", "src/main.rs", "a.txt"])
+            .status
+            .success()
+    );
+    fs::write(root.join("a.txt"), "changed\n").unwrap();
+    assert_eq!(
+        status(&root),
+        "No commits yet on main\n\n\
+         Changes to be committed:\n\
+         \tnew file:   a.txt\n\tnew file:   src/main.rs\n\n\
+         Changes not staged for commit:\n  (use \"cs01 add <file>...\" to update what will be committed)\n\
+         \tmodified:   a.txt\n\n\
+         Untracked files:\n  (use \"cs01 add <file>...\" to include in what will be committed)\n\
+         \t.cs01ignore\n\tsrc/deep/\n"
+    );
+
+    // Paths are relative to the current directory
+    let output = status(&root.join("src"));
+    assert!(output.contains("\tnew file:   ../a.txt\n"), "{}", output);
+    assert!(output.contains("\tnew file:   main.rs\n"), "{}", output);
+    assert!(output.contains("\tdeep/\n"), "{}", output);
+
+    let output = cs01(&root.join("src"), &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["branch"], "main");
+    assert_eq!(value["head"], serde_json::Value::Null);
+    assert_eq!(
+        value["staged"],
+        serde_json::json!([
+            { "path": "a.txt", "change": "added" },
+            { "path": "src/main.rs", "change": "added" },
+        ])
+    );
+    assert_eq!(
+        value["unstaged"],
+        serde_json::json!([{ "path": "a.txt", "change": "modified" }])
+    );
+    assert_eq!(
+        value["untracked"],
+        serde_json::json!([".cs01ignore", "src/deep/"])
+    );
+}
+
+#[test]
+fn test_status_layers_ignore_files() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    fs::create_dir_all(root.join("src/gen")).unwrap();
+    fs::create_dir_all(root.join("target/debug")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    for path in [
+        "a.log",
+        "keep.log",
+        "notes.tmp",
+        "src/b.log",
+        "src/gen/out.rs",
+        "src/gen/keep.rs",
+        "target/debug/app",
+    ] {
+        fs::write(root.join(path), "x\n").unwrap();
+    }
+    let excludes = dir.path().join("excludes");
+    fs::write(&excludes, "*.tmp\n").unwrap();
+    let output = cs01(
+        &root,
+        &["config", "core.excludesFile", excludes.to_str().unwrap()],
+    );
+    assert!(output.status.success());
+    let mut exclude = fs::read_to_string(root.join(".CS01/info/exclude")).unwrap();
+    exclude.push_str("*.log\n/target/\n");
+    fs::write(root.join(".CS01/info/exclude"), exclude).unwrap();
+    fs::write(root.join(".cs01ignore"), "!keep.log\n!/target/debug/app\n").unwrap();
+    fs::write(root.join("src/.cs01ignore"), "gen/*\n!gen/keep.rs\n").unwrap();
+
+    // A deeper file overrides the ones above it, but nothing under an ignored
+    // directory comes back.
+    assert!(cs01(&root, &["add", "-A"]).status.success());
+    let output = cs01(&root, &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let staged: Vec<&str> = value["staged"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        staged,
+        [
+            ".cs01ignore",
+            "keep.log",
+            "src/.cs01ignore",
+            "src/gen/keep.rs"
+        ]
+    );
+    assert_eq!(value["untracked"], serde_json::json!([]));
+}
+
+#[test]
+fn test_status_against_head() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    for name in ["keep.txt", "edit.txt", "gone.txt", "unstage.txt"] {
+        fs::write(root.join(name), format!("{}\n", name)).unwrap();
+    }
+    assert!(cs01(&root, &["add", "."]).status.success());
+    commit_work_tree(&root);
+
+    assert_eq!(
+        status(&root),
+        "On branch main\n\nnothing to commit, working tree clean\n"
+    );
+
+    // Staged: a change, a new file and a deletion; unstaged: another change and deletion
+    fs::write(root.join("edit.txt"), "edited\n").unwrap();
+    fs::write(root.join("new.txt"), "new\n").unwrap();
+    fs::remove_file(root.join("gone.txt")).unwrap();
+    assert!(cs01(&root, &["add", "-A"]).status.success());
+    fs::write(root.join("keep.txt"), "same size\n").unwrap();
+    fs::remove_file(root.join("unstage.txt")).unwrap();
+
+    assert_eq!(
+        status(&root),
+        "On branch main\n\n\
+         Changes to be committed:\n\
+         \tmodified:   edit.txt\n\tdeleted:    gone.txt\n\tnew file:   new.txt\n\n\
+         Changes not staged for commit:\n  (use \"cs01 add <file>...\" to update what will be committed)\n\
+         \tmodified:   keep.txt\n\tdeleted:    unstage.txt\n"
+    );
+
+    assert!(cs01(&root, &["add", "-A"]).status.success());
+    commit_work_tree(&root);
+    let output = status(&root);
+    assert!(
+        output.ends_with("nothing to commit, working tree clean\n"),
+        "{}",
+        output
+    );
+
+    // Only unstaged changes
+    fs::write(root.join("new.txt"), "newer\n").unwrap();
+    let output = status(&root);
+    assert!(
+        output.ends_with("\n\nno changes added to commit (use \"cs01 add\")\n"),
+        "{}",
+        output
+    );
+
+    // A detached HEAD is named by its commit
+    let head = stdout(&cs01(&root, &["rev-parse", "HEAD"]));
+    fs::write(root.join(".CS01/HEAD"), &head).unwrap();
+    let output = status(&root);
+    assert!(
+        output.starts_with(&format!("HEAD detached at {}\n", &head[..7])),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn test_status_short_and_porcelain() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    run(root, &["init", "-q"]);
+    fs::create_dir(root.join("src")).unwrap();
+    commit(
+        root,
+        &[("lines.txt", "base\n"), ("src/main.rs", "main\n")],
+        "Base",
+    );
+    run(root, &["branch", "topic"]);
+    commit(
+        root,
+        &[("lines.txt", "ours\n"), ("added.txt", "ours\n")],
+        "Ours",
+    );
+    run(root, &["switch", "topic"]);
+    commit(
+        root,
+        &[("lines.txt", "theirs\n"), ("added.txt", "theirs\n")],
+        "Theirs",
+    );
+    run(root, &["switch", "main"]);
+    assert!(!cs01(root, &["merge", "topic"]).status.success());
+
+    // Staged, then changed again; only changed; untracked
+    fs::write(root.join("staged.txt"), "staged\n").unwrap();
+    run(root, &["add", "staged.txt"]);
+    fs::write(root.join("staged.txt"), "changed\n").unwrap();
+    fs::write(root.join("src/main.rs"), "changed\n").unwrap();
+    fs::write(root.join("src/new.rs"), "new\n").unwrap();
+
+    assert_eq!(
+        run(root, &["status", "--porcelain"]),
+        "AA added.txt\nUU lines.txt\n M src/main.rs\nAM staged.txt\n?? src/new.rs\n"
+    );
+    // Short output is relative to the current directory; porcelain never is
+    assert_eq!(
+        run(&root.join("src"), &["status", "-s"]),
+        "AA ../added.txt\nUU ../lines.txt\n M main.rs\nAM ../staged.txt\n?? new.rs\n"
+    );
+    assert_eq!(
+        run(&root.join("src"), &["status", "--porcelain"]),
+        run(root, &["status", "--short"])
+    );
+}
+
+#[test]
+fn test_status_into_a_closed_pipe() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    assert!(cs01(root, &["init", "-q"]).status.success());
+    // More output than a pipe holds, so cs01 is still writing when the reader goes away
+    for i in 0..10_000 {
+        fs::write(root.join(format!("untracked-{:05}", i)), "").unwrap();
+    }
+
+    let mut child = cs01_command(root, &["status"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first_line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut first_line)
+        .unwrap();
+    assert_eq!(first_line, "No commits yet on main\n");
+
+    // Like `cs01 status | head -1`: no panic and no error message
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(!stderr.contains("Error"), "{}", stderr);
+}
+
+#[test]
+fn test_status_shows_embedded_repositories_as_directories() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    fs::create_dir(&root).unwrap();
+    run(&root, &["init", "-q"]);
+    commit(&root, &[("README.md", "hello\n")], "first");
+    for path in [
+        ".git/config",
+        "sub/.git/HEAD",
+        "sub/file.txt",
+        "deep/nested/.CS01/HEAD",
+        "bare/.git",
+    ] {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "embedded only\n").unwrap();
+    }
+
+    // Each is one entry, never looked into; a directory holding one is listed whole
+    assert_eq!(
+        run(&root, &["status", "--porcelain"]),
+        "?? bare/\n?? deep/\n?? sub/\n"
+    );
+    assert_eq!(
+        run(&root, &["ls-files", "--others"]),
+        "bare/\ndeep/nested/\nsub/\n"
+    );
+
+    // The long format says why add leaves them out
+    let long = run(&root, &["status"]);
+    assert!(
+        long.contains(
+            "\tbare/ (embedded repository; not staged by add)\n\
+             \tdeep/\n\
+             \tsub/ (embedded repository; not staged by add)\n"
+        ),
+        "{}",
+        long
+    );
+    let output = cs01(&root, &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["embedded"], serde_json::json!(["bare/", "sub/"]));
+}
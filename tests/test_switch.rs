@@ -0,0 +1,318 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+mod common;
+use common::{commit_all, cs01, read, rev_parse, stderr, stdout};
+
+/// Checks that the index and working tree hold exactly HEAD's files.
+fn assert_clean(root: &Path) {
+    let output = cs01(root, &["--json", "status"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    for list in ["staged", "unstaged", "untracked"] {
+        assert_eq!(value[list], serde_json::json!([]), "{}: {}", list, value);
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).unwrap().permissions().mode() & 0o111 != 0
+}
+
+/// `main` and `topic` diverge from a first commit: topic modifies, deletes and adds
+/// files, turns a directory into a file and a file into a directory, makes a script
+/// executable and repoints a symlink.
+fn divergent_branches(dir: &Path) -> (PathBuf, String, String) {
+    let root = dir.join("work");
+    fs::create_dir_all(root.join("dir")).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    fs::write(root.join("same.txt"), "same\n").unwrap();
+    fs::write(root.join("gone.txt"), "gone\n").unwrap();
+    fs::write(root.join("dir/file.txt"), "in dir\n").unwrap();
+    fs::write(root.join("swap"), "a file\n").unwrap();
+    fs::write(root.join("run.sh"), "echo hi\n").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("a.txt", root.join("link")).unwrap();
+    let main = commit_all(&root, "main");
+
+    let output = cs01(&root, &["switch", "-c", "topic"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "Switched to a new branch 'topic'\n");
+    fs::write(root.join("a.txt"), "topic\n").unwrap();
+    fs::remove_file(root.join("gone.txt")).unwrap();
+    fs::remove_dir_all(root.join("dir")).unwrap();
+    fs::write(root.join("dir"), "now a file\n").unwrap();
+    fs::remove_file(root.join("swap")).unwrap();
+    fs::create_dir_all(root.join("swap")).unwrap();
+    fs::write(root.join("swap/inner.txt"), "inner\n").unwrap();
+    fs::create_dir_all(root.join("new/deep")).unwrap();
+    fs::write(root.join("new/deep/b.txt"), "b\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(root.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_file(root.join("link")).unwrap();
+        std::os::unix::fs::symlink("dir", root.join("link")).unwrap();
+    }
+    let topic = commit_all(&root, "topic");
+    (root, main, topic)
+}
+
+#[test]
+fn test_switch_between_divergent_branches() {
+    let dir = tempdir().unwrap();
+    let (root, main, topic) = divergent_branches(dir.path());
+    assert_eq!(rev_parse(&root, "main"), main);
+
+    let output = cs01(&root, &["switch", "main"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "Switched to branch 'main'\n");
+    assert_eq!(read(&root, ".CS01/HEAD"), "ref: refs/heads/main\n");
+    assert_eq!(read(&root, "a.txt"), "a\n");
+    assert_eq!(read(&root, "gone.txt"), "gone\n");
+    assert_eq!(read(&root, "dir/file.txt"), "in dir\n");
+    assert_eq!(read(&root, "swap"), "a file\n");
+    assert!(!root.join("new").exists());
+    #[cfg(unix)]
+    {
+        assert!(!is_executable(&root.join("run.sh")));
+        assert_eq!(
+            fs::read_link(root.join("link")).unwrap(),
+            Path::new("a.txt")
+        );
+    }
+    assert_clean(&root);
+
+    // And back again, through the `checkout` alias
+    let output = cs01(&root, &["checkout", "topic"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(read(&root, "a.txt"), "topic\n");
+    assert!(!root.join("gone.txt").exists());
+    assert_eq!(read(&root, "dir"), "now a file\n");
+    assert_eq!(read(&root, "swap/inner.txt"), "inner\n");
+    assert_eq!(read(&root, "new/deep/b.txt"), "b\n");
+    #[cfg(unix)]
+    {
+        assert!(is_executable(&root.join("run.sh")));
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("dir"));
+    }
+    assert_clean(&root);
+    assert_eq!(rev_parse(&root, "HEAD"), topic);
+
+    // Each move is in HEAD's reflog
+    let log = read(&root, ".CS01/logs/HEAD");
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(lines[lines.len() - 2].starts_with(&format!("{} {}", topic, main)));
+    assert!(lines[lines.len() - 2].ends_with("\tcheckout: moving from topic to main"));
+    assert!(lines[lines.len() - 1].ends_with("\tcheckout: moving from main to topic"));
+
+    let output = cs01(&root, &["switch", "topic"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Already on 'topic'\n");
+}
+
+#[test]
+fn test_switch_keeps_local_changes_or_refuses() {
+    let dir = tempdir().unwrap();
+    let (root, main, topic) = divergent_branches(dir.path());
+
+    // A change to a file both branches share carries over, staged or not
+    fs::write(root.join("same.txt"), "local\n").unwrap();
+    fs::write(root.join("untracked.txt"), "mine\n").unwrap();
+    let output = cs01(&root, &["switch", "main"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "M\tsame.txt\nSwitched to branch 'main'\n");
+    assert_eq!(read(&root, "same.txt"), "local\n");
+    assert_eq!(read(&root, "untracked.txt"), "mine\n");
+
+    // Changes to files that differ, and untracked files in the way, are listed
+    fs::write(root.join("a.txt"), "local change\n").unwrap();
+    fs::write(root.join("gone.txt"), "staged change\n").unwrap();
+    assert!(cs01(&root, &["add", "gone.txt"]).status.success());
+    fs::create_dir_all(root.join("new/deep")).unwrap();
+    fs::write(root.join("new/deep/b.txt"), "in the way\n").unwrap();
+    let output = cs01(&root, &["switch", "topic"]);
+    assert!(!output.status.success());
+    let error = stderr(&output);
+    assert!(
+        error.contains(
+            "Your local changes to the following files would be overwritten by checkout:\n\
+             \ta.txt\n\
+             \tgone.txt\n\
+             Please commit your changes or stash them before you switch branches.\n"
+        ),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains(
+            "The following untracked working tree files would be overwritten by checkout:\n\
+             \tnew/deep/b.txt\n\
+             Please move or remove them before you switch branches."
+        ),
+        "{}",
+        error
+    );
+
+    // Nothing moved
+    assert_eq!(rev_parse(&root, "HEAD"), main);
+    assert_eq!(read(&root, ".CS01/HEAD"), "ref: refs/heads/main\n");
+    assert_eq!(read(&root, "a.txt"), "local change\n");
+    assert_eq!(read(&root, "dir/file.txt"), "in dir\n");
+    assert_eq!(read(&root, "new/deep/b.txt"), "in the way\n");
+    assert!(!root.join(".CS01/index.lock").exists());
+
+    // A file in the way of a directory the other branch needs is refused too
+    fs::remove_dir_all(root.join("new")).unwrap();
+    fs::write(root.join("new"), "a file\n").unwrap();
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    fs::write(root.join("gone.txt"), "gone\n").unwrap();
+    assert!(cs01(&root, &["add", "a.txt", "gone.txt"]).status.success());
+    let output = cs01(&root, &["switch", "topic"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("\tnew\n"), "{}", stderr(&output));
+
+    fs::remove_file(root.join("new")).unwrap();
+    let output = cs01(&root, &["switch", "topic"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(rev_parse(&root, "HEAD"), topic);
+    assert_eq!(read(&root, "same.txt"), "local\n");
+}
+
+#[test]
+fn test_switch_create_and_detach() {
+    let dir = tempdir().unwrap();
+    let (root, main, topic) = divergent_branches(dir.path());
+
+    // A new branch from a start point is checked out there
+    let output = cs01(&root, &["switch", "-c", "fix", "main"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(rev_parse(&root, "fix"), main);
+    assert_eq!(read(&root, ".CS01/HEAD"), "ref: refs/heads/fix\n");
+    assert_eq!(read(&root, "a.txt"), "a\n");
+    assert_clean(&root);
+    let output = cs01(&root, &["checkout", "-b", "fix"]);
+    assert!(
+        stderr(&output).contains("a branch named 'fix' already exists"),
+        "{}",
+        stderr(&output)
+    );
+
+    // A detached HEAD holds the raw id, and branches are left alone
+    let output = cs01(&root, &["switch", "--detach", &topic[..7]]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!("HEAD is now at {} topic\n", &topic[..7])
+    );
+    let advice = stderr(&output);
+    assert!(
+        advice.starts_with(&format!("Note: switching to '{}'.\n", &topic[..7])),
+        "{}",
+        advice
+    );
+    assert!(advice.contains("You are in 'detached HEAD' state."));
+    assert_eq!(read(&root, ".CS01/HEAD"), format!("{}\n", topic));
+    assert_eq!(rev_parse(&root, "fix"), main);
+    assert_eq!(read(&root, "a.txt"), "topic\n");
+    assert_clean(&root);
+    let log = read(&root, ".CS01/logs/HEAD");
+    assert!(log.ends_with(&format!("\tcheckout: moving from fix to {}\n", topic)));
+
+    let output = cs01(&root, &["--json", "switch", "main"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["branch"], "main");
+    assert_eq!(value["id"], main.as_str());
+    assert_eq!(value["created"], false);
+
+    // Quiet leaves out the summary and the advice, but not errors
+    fs::write(root.join("a.txt"), "changed\n").unwrap();
+    let output = cs01(&root, &["switch", "-q", "--detach", &topic[..7]]);
+    assert_eq!(stdout(&output), "");
+    assert!(
+        stderr(&output).contains("would be overwritten"),
+        "{}",
+        stderr(&output)
+    );
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    let output = cs01(&root, &["switch", "-q", "--detach", &topic[..7]]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "");
+    assert_eq!(read(&root, ".CS01/HEAD"), format!("{}\n", topic));
+    let output = cs01(&root, &["switch", "--quiet", "main"]);
+    assert_eq!(
+        (stdout(&output), stderr(&output)),
+        (String::new(), String::new())
+    );
+
+    // Commits need --detach, and unknown names are refused
+    for (args, expected) in [
+        (&["switch", &topic[..7]][..], "a branch is expected"),
+        (&["switch", "nope"], "invalid reference: nope"),
+        (&["switch"], "missing branch or commit argument"),
+        (&["switch", "-c", "a..b"], "not a valid branch name"),
+    ] {
+        let output = cs01(&root, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+    }
+    assert_eq!(read(&root, ".CS01/HEAD"), "ref: refs/heads/main\n");
+}
+
+#[test]
+fn test_switch_reports_the_upstream() {
+    let dir = tempdir().unwrap();
+    let (root, _, _) = divergent_branches(dir.path());
+    // topic tracks main in this repository, as `--track` would set it up
+    assert!(
+        cs01(&root, &["config", "branch.topic.remote", "."])
+            .status
+            .success()
+    );
+    assert!(
+        cs01(&root, &["config", "branch.topic.merge", "refs/heads/main"])
+            .status
+            .success()
+    );
+
+    let switch = |args: &[&str]| {
+        let output = cs01(&root, args);
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output)
+    };
+    assert_eq!(switch(&["switch", "main"]), "Switched to branch 'main'\n");
+    assert_eq!(
+        switch(&["switch", "topic"]),
+        "Switched to branch 'topic'\nYour branch is ahead of 'main' by 1 commit.\n"
+    );
+
+    // Two commits on main that topic doesn't have
+    assert!(cs01(&root, &["switch", "main"]).status.success());
+    fs::write(root.join("later.txt"), "later\n").unwrap();
+    commit_all(&root, "on main");
+    fs::remove_file(root.join("later.txt")).unwrap();
+    commit_all(&root, "on main again");
+    assert_eq!(
+        switch(&["switch", "topic"]),
+        "Switched to branch 'topic'\n\
+         Your branch and 'main' have diverged,\n\
+         and have 1 and 2 different commits each, respectively.\n"
+    );
+
+    assert!(
+        cs01(&root, &["config", "branch.topic.merge", "refs/heads/gone"])
+            .status
+            .success()
+    );
+    assert_eq!(switch(&["switch", "main"]), "Switched to branch 'main'\n");
+    assert_eq!(
+        switch(&["switch", "topic"]),
+        "Switched to branch 'topic'\nYour branch is based on 'gone', but the upstream is gone.\n"
+    );
+}
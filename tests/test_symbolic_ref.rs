@@ -0,0 +1,142 @@
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stderr, stdout, unreferenced_commits};
+
+fn read(root: &Path, name: &str) -> String {
+    std::fs::read_to_string(root.join(".CS01").join(name)).unwrap_or_default()
+}
+
+#[test]
+fn test_symbolic_ref_read_and_retarget() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, second) = unreferenced_commits(root);
+    assert!(
+        cs01(root, &["update-ref", "refs/heads/main", &first])
+            .status
+            .success()
+    );
+    assert!(
+        cs01(root, &["update-ref", "refs/heads/topic", &second])
+            .status
+            .success()
+    );
+
+    assert_eq!(
+        stdout(&cs01(root, &["symbolic-ref", "HEAD"])),
+        "refs/heads/main\n"
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["symbolic-ref", "--short", "HEAD"])),
+        "main\n"
+    );
+
+    let output = cs01(
+        root,
+        &["symbolic-ref", "-m", "switch", "HEAD", "refs/heads/topic"],
+    );
+    assert!(output.status.success());
+    assert_eq!(read(root, "HEAD"), "ref: refs/heads/topic\n");
+    assert!(read(root, "logs/HEAD").ends_with(&format!(
+        "{first} {second} C O Mitter <committer@example.com> 1700000100 -0700\tswitch\n"
+    )));
+
+    let output = cs01(root, &["--json", "symbolic-ref", "HEAD"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["target"], "refs/heads/topic");
+
+    // Pointing at a branch that doesn't exist yet is fine: it is unborn
+    assert!(
+        cs01(root, &["symbolic-ref", "HEAD", "refs/heads/new"])
+            .status
+            .success()
+    );
+    assert_eq!(read(root, "HEAD"), "ref: refs/heads/new\n");
+}
+
+#[test]
+fn test_symbolic_ref_refusals() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, _) = unreferenced_commits(root);
+    assert!(
+        cs01(root, &["update-ref", "refs/heads/main", &first])
+            .status
+            .success()
+    );
+
+    // Outside refs/ has its own exit status
+    for target in ["main", "tags/v1", "HEAD"] {
+        let output = cs01(root, &["symbolic-ref", "HEAD", target]);
+        assert_eq!(output.status.code(), Some(3), "{}", target);
+        assert!(
+            String::from_utf8_lossy(&output.stderr)
+                .contains("refusing to point HEAD outside refs/")
+        );
+    }
+    let output = cs01(root, &["symbolic-ref", "HEAD", "refs/heads/bad..name"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(read(root, "HEAD"), "ref: refs/heads/main\n");
+
+    // A direct ref isn't symbolic; -q says so with the exit status alone
+    let output = cs01(root, &["symbolic-ref", "refs/heads/main"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a symbolic ref"));
+    let output = cs01(root, &["symbolic-ref", "-q", "refs/heads/main"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_symbolic_ref_no_recurse_and_delete() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, _) = unreferenced_commits(root);
+    assert!(
+        cs01(root, &["update-ref", "refs/heads/main", &first])
+            .status
+            .success()
+    );
+    // HEAD -> refs/heads/alias -> refs/heads/main
+    assert!(
+        cs01(
+            root,
+            &["symbolic-ref", "refs/heads/alias", "refs/heads/main"]
+        )
+        .status
+        .success()
+    );
+    assert!(
+        cs01(root, &["symbolic-ref", "HEAD", "refs/heads/alias"])
+            .status
+            .success()
+    );
+
+    assert_eq!(
+        stdout(&cs01(root, &["symbolic-ref", "HEAD"])),
+        "refs/heads/main\n"
+    );
+    assert_eq!(
+        stdout(&cs01(root, &["symbolic-ref", "--no-recurse", "HEAD"])),
+        "refs/heads/alias\n"
+    );
+
+    // Deleting removes the symbolic ref itself; what it pointed at stays
+    let output = cs01(root, &["symbolic-ref", "--delete", "refs/heads/alias"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!root.join(".CS01/refs/heads/alias").exists());
+    assert_eq!(read(root, "refs/heads/main"), format!("{}\n", first));
+
+    // Direct references and HEAD can't be deleted this way
+    let output = cs01(root, &["symbolic-ref", "-d", "refs/heads/main"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("not a symbolic ref"));
+    let output = cs01(root, &["symbolic-ref", "-q", "-d", "refs/heads/main"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "");
+    let output = cs01(root, &["symbolic-ref", "-d", "HEAD"]);
+    assert!(!output.status.success());
+    assert_eq!(read(root, "HEAD"), "ref: refs/heads/alias\n");
+}
@@ -0,0 +1,93 @@
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stdout, unreferenced_commits};
+
+fn read(root: &Path, name: &str) -> String {
+    std::fs::read_to_string(root.join(".CS01").join(name)).unwrap_or_default()
+}
+
+#[test]
+fn test_update_ref_moves_branches() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, second) = unreferenced_commits(root);
+
+    // HEAD is on the unborn main, so updating HEAD creates the branch
+    let output = cs01(root, &["update-ref", "-m", "start", "HEAD", &first, ""]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty());
+    assert_eq!(read(root, "refs/heads/main"), format!("{}\n", first));
+    assert_eq!(read(root, "HEAD"), "ref: refs/heads/main\n");
+
+    let output = cs01(
+        root,
+        &["--json", "update-ref", "refs/heads/main", &second, &first],
+    );
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["ref"], "refs/heads/main");
+    assert_eq!(value["old"], first.as_str());
+
+    // Both the branch and HEAD, which is on it, get reflog entries
+    let zero = "0".repeat(40);
+    let expected = format!(
+        "{zero} {first} C O Mitter <committer@example.com> 1700000100 -0700\tstart\n\
+         {first} {second} C O Mitter <committer@example.com> 1700000100 -0700\t\n"
+    );
+    assert_eq!(read(root, "logs/refs/heads/main"), expected);
+    assert_eq!(read(root, "logs/HEAD"), expected);
+
+    let output = cs01(root, &["update-ref", "-d", "refs/heads/main", &second]);
+    assert!(output.status.success());
+    assert!(!root.join(".CS01/refs/heads/main").exists());
+    assert!(!root.join(".CS01/logs/refs/heads/main").exists());
+    // The reflog is set aside, not lost
+    assert_eq!(read(root, "logs/deleted/refs/heads/main"), expected);
+}
+
+#[test]
+fn test_update_ref_rejects_stale_and_bad_values() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    let (first, second) = unreferenced_commits(root);
+    let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    assert!(
+        cs01(root, &["update-ref", "refs/heads/main", &first])
+            .status
+            .success()
+    );
+
+    // A stale old value has its own exit status and changes nothing
+    for args in [
+        &["update-ref", "refs/heads/main", &second, &second][..],
+        &["update-ref", "refs/heads/main", &second, ""],
+        &["update-ref", "-d", "refs/heads/main", &second],
+    ] {
+        let output = cs01(root, args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot lock ref"));
+        assert_eq!(read(root, "refs/heads/main"), format!("{}\n", first));
+    }
+
+    for args in [
+        &["update-ref", "refs/heads/main", tree][..],
+        &[
+            "update-ref",
+            "refs/heads/main",
+            "0123456789012345678901234567890123456789",
+        ],
+        &["update-ref", "main", &second],
+        &["update-ref", "refs/heads/main"],
+        &["update-ref", "-d", "refs/heads/missing"],
+    ] {
+        let output = cs01(root, args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+    }
+    assert_eq!(read(root, "refs/heads/main"), format!("{}\n", first));
+    assert!(!root.join(".CS01/refs/heads/main.lock").exists());
+}
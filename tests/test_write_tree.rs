@@ -0,0 +1,44 @@
+use tempfile::tempdir;
+
+mod common;
+use common::{cs01, stdout};
+
+#[test]
+fn test_write_tree_matches_git() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().join("work");
+    std::fs::create_dir(&root).unwrap();
+    assert!(cs01(&root, &["init", "-q"]).status.success());
+
+    std::fs::write(root.join("hello.txt"), "hello\n").unwrap();
+    std::fs::create_dir_all(root.join("src/empty")).unwrap();
+    std::fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(root.join("build.log"), "noise").unwrap();
+    let mut exclude = std::fs::read_to_string(root.join(".CS01/info/exclude")).unwrap();
+    exclude.push_str("build.log\n");
+    std::fs::write(root.join(".CS01/info/exclude"), exclude).unwrap();
+
+    // Id from `git add -A && git write-tree` on the same files, without build.log
+    let expected = "aae2b3618f4a481bc1bde056dae4b7617edb7e83";
+    let output = cs01(&root, &["write-tree"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), format!("{}\n", expected));
+
+    // The same from a subdirectory, and the objects can be read back
+    let output = cs01(&root.join("src"), &["write-tree"]);
+    assert_eq!(stdout(&output), format!("{}\n", expected));
+    let output = cs01(&root, &["cat-file", "-p", expected]);
+    assert!(stdout(&output).contains("\tsrc\n"));
+    assert!(!stdout(&output).contains("build.log"));
+
+    let output = cs01(&root, &["--json", "write-tree"]);
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["id"], expected);
+
+    let output = cs01(dir.path(), &["write-tree"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("fatal: not a CS01 repository (or any of the parent directories): .CS01")
+    );
+}